@@ -0,0 +1,137 @@
+//
+// benches/dom_xpath.rs
+//
+// amxml: XML processor with XPath.
+// Copyright (C) 2018 KOYAMA Hiro <tac@amris.co.jp>
+//
+// DOM の基本操作と XPath の軸のベンチマーク (cargo bench)。
+// 兄弟・親への移動が子の数によらず一定時間であること、
+// union / intersect / except がノード数に比例する時間であることを、
+// 大きさの異なる文書で比べて確かめる。
+//
+
+#[macro_use]
+extern crate criterion;
+extern crate amxml;
+
+use criterion::{black_box, BenchmarkId, Criterion};
+use amxml::dom::*;
+
+// ---------------------------------------------------------------------
+// 子要素 n 個 (それぞれ属性3個とテキスト) をもつ文書。
+//
+fn wide_xml(n: usize) -> String {
+    let mut xml = String::from("<root>");
+    for i in 0 .. n {
+        xml += &format!(r#"<item id="i{}" kind="k{}" rank="{}">text {}</item>"#,
+                        i, i % 7, i % 100, i);
+    }
+    xml += "</root>";
+    return xml;
+}
+
+// ---------------------------------------------------------------------
+// 深さ n の入れ子の文書。
+//
+fn deep_xml(n: usize) -> String {
+    return format!("{}{}", "<d>".repeat(n), "</d>".repeat(n));
+}
+
+// ---------------------------------------------------------------------
+//
+fn bench_parse(c: &mut Criterion) {
+    let xml = wide_xml(1000);
+    c.bench_function("parse/wide_1000", |b| {
+        b.iter(|| new_document(black_box(&xml)).unwrap())
+    });
+    let doc = new_document(&xml).unwrap();
+    c.bench_function("serialize/wide_1000", |b| {
+        b.iter(|| black_box(&doc).to_string())
+    });
+}
+
+// ---------------------------------------------------------------------
+// 子の数を変えても、1回あたりの時間が変わらないはず。
+//
+fn bench_navigation(c: &mut Criterion) {
+    let mut group = c.benchmark_group("navigation");
+    for &n in [100, 10000].iter() {
+        let doc = new_document(&wide_xml(n)).unwrap();
+        let root = doc.root_element();
+        let middle = root.nth_child(n / 2).unwrap();
+        group.bench_with_input(BenchmarkId::new("parent", n), &middle, |b, node| {
+            b.iter(|| node.parent().unwrap())
+        });
+        group.bench_with_input(BenchmarkId::new("nth_child", n), &root, |b, node| {
+            b.iter(|| node.nth_child(black_box(n / 2)).unwrap())
+        });
+        group.bench_with_input(BenchmarkId::new("next_sibling", n), &middle, |b, node| {
+            b.iter(|| node.next_sibling().unwrap())
+        });
+        group.bench_with_input(BenchmarkId::new("previous_sibling", n), &middle, |b, node| {
+            b.iter(|| node.previous_sibling().unwrap())
+        });
+        group.bench_with_input(BenchmarkId::new("attribute_value", n), &middle, |b, node| {
+            b.iter(|| node.attribute_value(black_box("rank")).unwrap())
+        });
+    }
+    group.finish();
+
+    let doc = new_document(&deep_xml(1000)).unwrap();
+    let leaf = doc.get_first_node("//d[not(*)]").unwrap();
+    c.bench_function("navigation/ancestors_deep_1000", |b| {
+        b.iter(|| leaf.ancestors().len())
+    });
+}
+
+// ---------------------------------------------------------------------
+//
+fn bench_axes(c: &mut Criterion) {
+    let doc = new_document(&wide_xml(1000)).unwrap();
+    let middle = doc.get_first_node("/root/item[500]").unwrap();
+    let cases = [
+        ( "child", "/root/item", &doc ),
+        ( "descendant", "//item", &doc ),
+        ( "attribute", "//item/@kind", &doc ),
+        ( "predicate", "//item[@kind = 'k3']", &doc ),
+        ( "last", "/root/item[last()]", &doc ),
+        ( "following-sibling", "following-sibling::item[1]", &middle ),
+        ( "preceding-sibling", "preceding-sibling::item[1]", &middle ),
+        ( "following", "following::text()[1]", &middle ),
+        ( "parent", "..", &middle ),
+    ];
+    let mut group = c.benchmark_group("xpath");
+    for &(name, xpath, node) in cases.iter() {
+        group.bench_function(name, |b| {
+            b.iter(|| node.eval_xpath(black_box(xpath)).unwrap())
+        });
+    }
+    group.finish();
+}
+
+// ---------------------------------------------------------------------
+// ノード数を10倍にすれば、1回あたりの時間もおよそ10倍になるはず
+// (ノードの組ごとに比べると100倍になる)。
+//
+fn bench_set_operations(c: &mut Criterion) {
+    let cases = [
+        ( "union", "count(/root/item | /root/item[@kind = 'k3'])" ),
+        ( "intersect", "count(/root/item intersect /root/item[@kind = 'k3'])" ),
+        ( "except", "count(/root/item except /root/item[@kind = 'k3'])" ),
+    ];
+    let mut group = c.benchmark_group("set_operations");
+    group.sample_size(10);
+    for &n in [10000, 100000].iter() {
+        let doc = new_document(&wide_xml(n)).unwrap();
+        for &(name, xpath) in cases.iter() {
+            group.bench_with_input(BenchmarkId::new(name, n), &doc, |b, doc| {
+                b.iter(|| doc.eval_xpath(black_box(xpath)).unwrap())
+            });
+        }
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_parse, bench_navigation, bench_axes,
+                bench_set_operations);
+criterion_main!(benches);
@@ -0,0 +1,290 @@
+//
+// binary.rs
+//
+// amxml: XML processor with XPath.
+// Copyright (C) 2018 KOYAMA Hiro <tac@amris.co.jp>
+//
+//!
+//! Binary content embedded in XML: xs:hexBinary and xs:base64Binary.
+//!
+//! The codec functions convert between octets and the lexical forms
+//! of these types. In XPath, the values of these types are created by
+//! casting, like <code>"SGVsbG8=" cast as xs:base64Binary</code>,
+//! and can be cast to each other, or to xs:string.
+//!
+//! # Examples
+//!
+//! ```
+//! use amxml::dom::*;
+//! use amxml::binary::*;
+//! let xml = r#"<sig alg="x"><value>SGVs
+//!     bG8=</value><hash>48656C6C6F</hash></sig>"#;
+//! let doc = new_document(xml).unwrap();
+//! let value = doc.get_first_node("//value").unwrap();
+//! assert_eq!(value.binary_value(BinaryEncoding::Base64).unwrap(), b"Hello");
+//! let hash = doc.get_first_node("//hash").unwrap();
+//! assert_eq!(hash.binary_value(BinaryEncoding::Hex).unwrap(), b"Hello");
+//!
+//! let result = doc.eval_xpath("(//value cast as xs:base64Binary) eq ((//hash cast as xs:hexBinary) cast as xs:base64Binary)").unwrap();
+//! assert_eq!(result.to_string(), "true");
+//! ```
+//!
+
+use std::error::Error;
+#[cfg(feature = "no_std")]
+use std::prelude::*;
+
+use dom::*;
+use xmlerror::*;
+
+// =====================================================================
+/// Encoding of binary content, for NodePtr#binary_value().
+///
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum BinaryEncoding {
+    /// xs:hexBinary: two hexadecimal digits per octet.
+    Hex,
+    /// xs:base64Binary: Base64 (RFC 2045); whitespace is ignored.
+    Base64,
+}
+
+// =====================================================================
+//
+impl NodePtr {
+
+    // =================================================================
+    /// Decodes the string value of the node (typically the text content
+    /// of an element, or the value of an attribute) as binary content
+    /// in the encoding. Leading and trailing whitespace is ignored.
+    ///
+    /// # Examples
+    ///
+    /// See the module document.
+    ///
+    /// # Errors
+    ///
+    /// - When the string value is not valid in the encoding.
+    ///
+    pub fn binary_value(&self, encoding: BinaryEncoding) -> Result<Vec<u8>, Box<Error>> {
+        let mut s = String::new();
+        collect_text(self, &mut s);
+        match encoding {
+            BinaryEncoding::Hex => return decode_hex(s.trim()),
+            BinaryEncoding::Base64 => return decode_base64(&s),
+        }
+    }
+}
+
+// ---------------------------------------------------------------------
+// ノードの文字列値。
+// 深い木でもスタックを使い切らないよう、TreeWalker で子孫をたどる。
+//
+fn collect_text(node: &NodePtr, s: &mut String) {
+    match node.node_type() {
+        NodeType::DocumentRoot | NodeType::Element => {
+            for text in node.walk().node_type(NodeType::Text) {
+                *s += &text.value();
+            }
+        },
+        NodeType::Text | NodeType::Attribute => {
+            *s += &node.value();
+        },
+        _ => {},
+    }
+}
+
+// =====================================================================
+/// Encodes the octets into the canonical lexical form of xs:hexBinary,
+/// with upper case letters.
+///
+/// # Examples
+///
+/// ```
+/// use amxml::binary::*;
+/// assert_eq!(encode_hex(&[0x0f, 0xb7]), "0FB7");
+/// ```
+///
+pub fn encode_hex(octets: &[u8]) -> String {
+    let mut s = String::new();
+    for b in octets.iter() {
+        s += &format!("{:02X}", b);
+    }
+    return s;
+}
+
+// =====================================================================
+/// Decodes the lexical form of xs:hexBinary
+/// (upper or lower case letters are allowed).
+///
+/// # Errors
+///
+/// - When the length is odd, or there is a non-hexadecimal character.
+///
+pub fn decode_hex(s: &str) -> Result<Vec<u8>, Box<Error>> {
+    let digits: Vec<char> = s.chars().collect();
+    if digits.len() % 2 != 0 {
+        return Err(dynamic_error!(
+            "Invalid xs:hexBinary (odd length): {} [err:FORG0001]", s));
+    }
+    let mut octets: Vec<u8> = vec!{};
+    for pair in digits.chunks(2) {
+        match (pair[0].to_digit(16), pair[1].to_digit(16)) {
+            (Some(h), Some(l)) => octets.push((h * 16 + l) as u8),
+            _ => return Err(dynamic_error!(
+                "Invalid xs:hexBinary: {} [err:FORG0001]", s)),
+        }
+    }
+    return Ok(octets);
+}
+
+// ---------------------------------------------------------------------
+//
+const BASE64_CHARS: &[u8] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+// =====================================================================
+/// Encodes the octets into the canonical lexical form of xs:base64Binary
+/// (with padding, and without line breaks).
+///
+/// # Examples
+///
+/// ```
+/// use amxml::binary::*;
+/// assert_eq!(encode_base64(b"Hello"), "SGVsbG8=");
+/// ```
+///
+pub fn encode_base64(octets: &[u8]) -> String {
+    let mut s = String::new();
+    for chunk in octets.chunks(3) {
+        let b0 = chunk[0] as usize;
+        let b1 = if 1 < chunk.len() { chunk[1] as usize } else { 0 };
+        let b2 = if 2 < chunk.len() { chunk[2] as usize } else { 0 };
+        s.push(BASE64_CHARS[b0 >> 2] as char);
+        s.push(BASE64_CHARS[((b0 & 0x03) << 4) | (b1 >> 4)] as char);
+        if 1 < chunk.len() {
+            s.push(BASE64_CHARS[((b1 & 0x0f) << 2) | (b2 >> 6)] as char);
+        } else {
+            s.push('=');
+        }
+        if 2 < chunk.len() {
+            s.push(BASE64_CHARS[b2 & 0x3f] as char);
+        } else {
+            s.push('=');
+        }
+    }
+    return s;
+}
+
+// =====================================================================
+/// Decodes the lexical form of xs:base64Binary.
+/// Whitespace (e.g. line breaks) is ignored.
+///
+/// # Errors
+///
+/// - When there is a character that is not in the Base64 alphabet,
+///   or the padding is incorrect.
+///
+pub fn decode_base64(s: &str) -> Result<Vec<u8>, Box<Error>> {
+    let chars: Vec<char> = s.chars()
+            .filter(|c| ! (*c == ' ' || *c == '\t' || *c == '\n' || *c == '\r'))
+            .collect();
+    if chars.len() % 4 != 0 {
+        return Err(dynamic_error!(
+            "Invalid xs:base64Binary (length): {} [err:FORG0001]", s));
+    }
+
+    let mut octets: Vec<u8> = vec!{};
+    for (i, quad) in chars.chunks(4).enumerate() {
+        let is_last = i == chars.len() / 4 - 1;
+        let mut values: Vec<u32> = vec!{};
+        let mut pad = 0;
+        for ch in quad.iter() {
+            if *ch == '=' {
+                pad += 1;
+                continue;
+            }
+            if 0 < pad {                // '=' の後に文字がある
+                return Err(dynamic_error!(
+                    "Invalid xs:base64Binary (padding): {} [err:FORG0001]", s));
+            }
+            match BASE64_CHARS.iter().position(|b| *b as char == *ch) {
+                Some(v) => values.push(v as u32),
+                None => return Err(dynamic_error!(
+                    "Invalid xs:base64Binary: {} [err:FORG0001]", s)),
+            }
+        }
+        if 2 < pad || (0 < pad && ! is_last) {
+            return Err(dynamic_error!(
+                "Invalid xs:base64Binary (padding): {} [err:FORG0001]", s));
+        }
+        while values.len() < 4 {
+            values.push(0);
+        }
+        let n = (values[0] << 18) | (values[1] << 12) | (values[2] << 6) | values[3];
+        octets.push((n >> 16) as u8);
+        if pad < 2 {
+            octets.push((n >> 8) as u8);
+        }
+        if pad < 1 {
+            octets.push(n as u8);
+        }
+    }
+    return Ok(octets);
+}
+
+// =====================================================================
+//
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // -----------------------------------------------------------------
+    //
+    #[test]
+    fn test_hex() {
+        assert_eq!(encode_hex(&[]), "");
+        assert_eq!(encode_hex(&[0x00, 0xab, 0xff]), "00ABFF");
+        assert_eq!(decode_hex("00abFF").unwrap(), vec!{0x00, 0xab, 0xff});
+        assert_eq!(decode_hex("").unwrap(), vec!{});
+        assert!(decode_hex("ABC").is_err());
+        assert!(decode_hex("GG").is_err());
+    }
+
+    // -----------------------------------------------------------------
+    //
+    #[test]
+    fn test_base64() {
+        let cases = [
+            ( "", "" ),
+            ( "f", "Zg==" ),
+            ( "fo", "Zm8=" ),
+            ( "foo", "Zm9v" ),
+            ( "foob", "Zm9vYg==" ),
+            ( "fooba", "Zm9vYmE=" ),
+            ( "foobar", "Zm9vYmFy" ),
+        ];
+        for (octets, lexical) in cases.iter() {
+            assert_eq!(encode_base64(octets.as_bytes()), *lexical);
+            assert_eq!(decode_base64(lexical).unwrap(), octets.as_bytes());
+        }
+        assert_eq!(decode_base64("Zm9v\n YmFy").unwrap(), b"foobar");
+        for invalid in ["Zm9", "Zm9v!mFy", "Zg=a", "Z===", "Zg==Zm9v"].iter() {
+            assert!(decode_base64(invalid).is_err(), "{}", invalid);
+        }
+    }
+
+    // -----------------------------------------------------------------
+    // 子孫のテキストを連結した文字列値。深い木でもよい。
+    //
+    #[test]
+    fn test_binary_value() {
+        let doc = new_document("<r>0F<!--c--><b>b7</b> </r>").unwrap();
+        assert_eq!(doc.binary_value(BinaryEncoding::Hex).unwrap(), vec!{0x0f, 0xb7});
+
+        let depth = 100000;
+        let xml = format!("{}Zm9v{}", "<a>".repeat(depth), "</a>".repeat(depth));
+        let doc = new_document(&xml).unwrap();
+        assert_eq!(doc.root_element().binary_value(BinaryEncoding::Base64).unwrap(),
+                    b"foo");
+    }
+}
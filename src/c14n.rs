@@ -0,0 +1,380 @@
+//
+// c14n.rs
+//
+// amxml: XML processor with XPath.
+// Copyright (C) 2018 KOYAMA Hiro <tac@amris.co.jp>
+//
+//!
+//! Canonical XML.
+//!
+//! NodePtr#canonicalize() serializes the document, or the subtree
+//! under an element (as a document subset), in the form of
+//! <a href="https://www.w3.org/TR/xml-c14n">Canonical XML Version 1.0</a>,
+//! so that logically equivalent documents give the same octets,
+//! as required for digital signatures (see the module dsig).
+//!
+//! - The XML declaration and DOCTYPE are dropped.
+//! - Empty elements are written as start and end tag pairs.
+//! - Namespace declarations are written first, sorted by prefix,
+//!   then the other attributes, sorted by namespace URI and local name.
+//! - Superfluous namespace declarations are dropped; the subtree
+//!   gets the declarations in scope, and the xml:* attributes
+//!   inherited, from the ancestors.
+//! - Characters are escaped as the Recommendation specifies,
+//!   e.g. '&gt;' is written as is in attribute values.
+//! - Comments are dropped, unless C14nOptions#with_comments(true).
+//!
+//! This processor does not read DTDs, and the default attributes
+//! and entities declared in DTDs are not taken into account.
+//!
+//! # Examples
+//!
+//! ```
+//! use amxml::dom::*;
+//! use amxml::c14n::*;
+//! let xml = r#"<?xml version="1.0"?><!--c--><root xmlns:p="http://p" b='2' a="1&gt;"><p:e/></root>"#;
+//! let doc = new_document(xml).unwrap();
+//! assert_eq!(doc.canonicalize(),
+//!     r#"<root xmlns:p="http://p" a="1>" b="2"><p:e></p:e></root>"#);
+//!
+//! let e = doc.get_first_node("//p:e").unwrap();
+//! assert_eq!(e.canonicalize(), r#"<p:e xmlns:p="http://p"></p:e>"#);
+//!
+//! let options = C14nOptions::new().with_comments(true);
+//! assert!(doc.canonicalize_with_options(&options).starts_with("<!--c-->\n<root "));
+//! ```
+//!
+
+use std::collections::BTreeMap;
+#[cfg(feature = "no_std")]
+use std::prelude::*;
+
+use dom::*;
+use escape::{escape_c14n_attribute, escape_c14n_text};
+
+const XML_NS_URI: &str = "http://www.w3.org/XML/1998/namespace";
+
+// =====================================================================
+/// Options for NodePtr#canonicalize_with_options().
+///
+#[derive(Debug, Clone, Default)]
+pub struct C14nOptions {
+    with_comments: bool,
+    exclude: Vec<NodePtr>,
+}
+
+impl C14nOptions {
+    // -----------------------------------------------------------------
+    /// Returns the default options: without comments, no node excluded.
+    ///
+    pub fn new() -> C14nOptions {
+        return C14nOptions::default();
+    }
+
+    // -----------------------------------------------------------------
+    /// Writes comments (Canonical XML with Comments).
+    ///
+    pub fn with_comments(mut self, with_comments: bool) -> C14nOptions {
+        self.with_comments = with_comments;
+        return self;
+    }
+
+    // -----------------------------------------------------------------
+    /// Excludes the node (and its subtree) from the output,
+    /// as the enveloped signature transform does for the Signature
+    /// element.
+    ///
+    pub fn exclude(mut self, node: &NodePtr) -> C14nOptions {
+        self.exclude.push(node.rc_clone());
+        return self;
+    }
+}
+
+// ---------------------------------------------------------------------
+// 正規化の作業スタックの項目。
+//
+enum C14nWork {
+    Node(NodePtr, BTreeMap<String, String>, BTreeMap<String, String>),
+            // 出力するノードと、親要素までに出力した名前空間宣言、
+            // 親要素で有効な名前空間宣言。
+    EndTag(String),
+}
+
+// =====================================================================
+//
+impl NodePtr {
+
+    // =================================================================
+    /// Returns the Canonical XML (without comments) of the node;
+    /// see the module document of c14n.
+    ///
+    pub fn canonicalize(&self) -> String {
+        return self.canonicalize_with_options(&C14nOptions::new());
+    }
+
+    // =================================================================
+    /// Returns the Canonical XML of the node, with the options.
+    ///
+    pub fn canonicalize_with_options(&self, options: &C14nOptions) -> String {
+        let mut s = String::new();
+        if self.node_type() == NodeType::DocumentRoot {
+            let mut seen_root = false;
+            for ch in self.children().iter() {
+                if options.exclude.contains(ch) {
+                    continue;
+                }
+                match ch.node_type() {
+                    NodeType::Element => {
+                        seen_root = true;
+                        s += &canonicalize_subtree(ch, &BTreeMap::new(), options);
+                    },
+                    NodeType::Comment | NodeType::Instruction => {
+                        let c = canonicalize_subtree(ch, &BTreeMap::new(), options);
+                        if c == "" {
+                            continue;
+                        }
+                        if seen_root {
+                            s += "\n";
+                            s += &c;
+                        } else {
+                            s += &c;
+                            s += "\n";
+                        }
+                    },
+                    _ => {},
+                }
+            }
+            return s;
+        }
+        return canonicalize_subtree(self, &BTreeMap::new(), options);
+    }
+}
+
+// ---------------------------------------------------------------------
+// node を根とする部分木を正規化する。
+// 深い木でもスタックを使い切らないよう、再帰せず作業スタックでたどる。
+//
+fn canonicalize_subtree(node: &NodePtr, rendered: &BTreeMap<String, String>,
+                options: &C14nOptions) -> String {
+    let mut s = String::new();
+    let mut apex = true;
+    let parent_in_scope = match node.parent() {
+        Some(p) => namespaces_in_scope(&p),
+        None => BTreeMap::new(),
+    };
+    let mut stack: Vec<C14nWork> = vec!{
+        C14nWork::Node(node.rc_clone(), rendered.clone(), parent_in_scope)
+    };
+    while let Some(work) = stack.pop() {
+        let (node, rendered, mut in_scope) = match work {
+            C14nWork::Node(node, rendered, in_scope) => (node, rendered, in_scope),
+            C14nWork::EndTag(name) => {
+                s += &format!("</{}>", name);
+                continue;
+            },
+        };
+        if options.exclude.contains(&node) {
+            apex = false;
+            continue;
+        }
+        match node.node_type() {
+            NodeType::Element => {
+                add_namespace_declarations(&node, &mut in_scope);
+                let mut new_rendered = rendered.clone();
+                s += &format!("<{}", node.name());
+                for (prefix, uri) in in_scope.iter() {
+                    let parent_uri = rendered.get(prefix).map(|u| u.as_str()).unwrap_or("");
+                    if uri == parent_uri && (rendered.contains_key(prefix) || prefix == "") {
+                        continue;
+                    }
+                    if prefix == "" {
+                        s += &format!(" xmlns=\"{}\"", escape_c14n_attribute(uri));
+                    } else {
+                        s += &format!(" xmlns:{}=\"{}\"", prefix, escape_c14n_attribute(uri));
+                    }
+                    new_rendered.insert(prefix.clone(), uri.clone());
+                }
+                for &(_, _, ref name, ref value) in sorted_attributes(&node, &in_scope, apex).iter() {
+                    s += &format!(" {}=\"{}\"", name, escape_c14n_attribute(value));
+                }
+                s += ">";
+                stack.push(C14nWork::EndTag(node.name()));
+                let mut children = node.children();
+                children.reverse();
+                for ch in children.into_iter() {
+                    stack.push(C14nWork::Node(ch, new_rendered.clone(), in_scope.clone()));
+                }
+            },
+            NodeType::Text => {
+                s += &escape_c14n_text(&node.value());
+            },
+            NodeType::Comment => {
+                if options.with_comments {
+                    s += &format!("<!--{}-->", node.value());
+                }
+            },
+            NodeType::Instruction => {
+                if node.value() == "" {
+                    s += &format!("<?{}?>", node.name());
+                } else {
+                    s += &format!("<?{} {}?>", node.name(), node.value());
+                }
+            },
+            _ => {},
+        }
+        apex = false;
+    }
+    return s;
+}
+
+// ---------------------------------------------------------------------
+// 要素で有効な名前空間宣言 (接頭辞 → URI)。既定の名前空間は接頭辞 ""。
+// 祖先から順に、近いものが優先するように集める。
+// 祖先をたどるのは部分木の根についてだけで、その子孫は親のものに
+// add_namespace_declarations() で自身の宣言を加えて求める。
+//
+fn namespaces_in_scope(node: &NodePtr) -> BTreeMap<String, String> {
+    let mut elems: Vec<NodePtr> = vec!{node.rc_clone()};
+    let mut curr = node.parent();
+    while let Some(p) = curr {
+        curr = p.parent();
+        elems.push(p);
+    }
+    let mut in_scope: BTreeMap<String, String> = BTreeMap::new();
+    for e in elems.iter().rev() {
+        add_namespace_declarations(e, &mut in_scope);
+    }
+    return in_scope;
+}
+
+// ---------------------------------------------------------------------
+// 要素 node の名前空間宣言を in_scope に加える (上書きする)。
+//
+fn add_namespace_declarations(node: &NodePtr, in_scope: &mut BTreeMap<String, String>) {
+    for at in node.attribute_nodes().iter() {
+        let name = at.name();
+        if name == "xmlns" {
+            in_scope.insert(String::new(), at.value());
+        } else if name.starts_with("xmlns:") {
+            in_scope.insert(String::from(&name["xmlns:".len() ..]), at.value());
+        }
+    }
+}
+
+// ---------------------------------------------------------------------
+// 名前空間宣言以外の属性を、(名前空間URI, 局所名) の順に整列して返す。
+// 部分木の根 (apex) には、祖先の xml:* 属性も継承する。
+//
+fn sorted_attributes(node: &NodePtr, in_scope: &BTreeMap<String, String>,
+                apex: bool) -> Vec<(String, String, String, String)> {
+    let mut attrs: Vec<(String, String, String, String)> = vec!{};
+    let push = |attrs: &mut Vec<(String, String, String, String)>, name: String, value: String| {
+        if name == "xmlns" || name.starts_with("xmlns:") {
+            return;
+        }
+        if attrs.iter().any(|a| a.2 == name) {
+            return;
+        }
+        let (uri, local) = match name.find(':') {
+            Some(pos) => {
+                let prefix = &name[.. pos];
+                let uri = if prefix == "xml" {
+                    String::from(XML_NS_URI)
+                } else {
+                    in_scope.get(prefix).cloned().unwrap_or(String::new())
+                };
+                (uri, String::from(&name[pos + 1 ..]))
+            },
+            None => (String::new(), name.clone()),
+        };
+        attrs.push((uri, local, name, value));
+    };
+    for at in node.attribute_nodes().iter() {
+        push(&mut attrs, at.name(), at.value());
+    }
+    if apex {
+        let mut curr = node.parent();
+        while let Some(p) = curr {
+            for at in p.attribute_nodes().iter() {
+                if at.name().starts_with("xml:") {
+                    push(&mut attrs, at.name(), at.value());
+                }
+            }
+            curr = p.parent();
+        }
+    }
+    attrs.sort_by(|a, b| (&a.0, &a.1).cmp(&(&b.0, &b.1)));
+    return attrs;
+}
+
+// =====================================================================
+//
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // -----------------------------------------------------------------
+    //
+    #[test]
+    fn test_canonicalize() {
+        // Canonical XML 1.0, 3.1 / 3.2 / 3.3 の例に準ずる。
+        let xml = r#"<?xml version="1.0"?>
+<?xml-stylesheet href="doc.xsl"
+   type="text/xsl"   ?>
+<!DOCTYPE doc>
+<doc>Hello, world!<!-- Comment 1 --></doc>
+<?pi-without-data     ?>
+<!-- Comment 2 -->"#;
+        let doc = new_document(xml).unwrap();
+        assert_eq!(doc.canonicalize(), "<?xml-stylesheet href=\"doc.xsl\"\n   type=\"text/xsl\"   ?>\n<doc>Hello, world!</doc>\n<?pi-without-data?>");
+
+        let xml = r#"<doc><e1   /><e2   ></e2><e3   name = "elem3"   id="elem3"   /><e5 a:attr="out" b:attr="sorted" attr2="all" attr="I'm"
+   xmlns:b="http://www.ietf.org"
+   xmlns:a="http://www.w3.org"
+   xmlns="http://example.org"/><e6 xmlns="" xmlns:a="http://www.w3.org"><e7 xmlns="http://www.ietf.org"><e8 xmlns="" xmlns:a="http://www.w3.org"><e9 xmlns="" xmlns:a="http://www.ietf.org"/></e8></e7></e6></doc>"#;
+        let doc = new_document(xml).unwrap();
+        assert_eq!(doc.canonicalize(), concat!(
+            r#"<doc><e1></e1><e2></e2><e3 id="elem3" name="elem3"></e3>"#,
+            r#"<e5 xmlns="http://example.org" xmlns:a="http://www.w3.org" xmlns:b="http://www.ietf.org" attr="I'm" attr2="all" b:attr="sorted" a:attr="out"></e5>"#,
+            r#"<e6 xmlns:a="http://www.w3.org"><e7 xmlns="http://www.ietf.org"><e8 xmlns=""><e9 xmlns:a="http://www.ietf.org"></e9></e8></e7></e6></doc>"#));
+
+        let xml = "<doc a=\"&lt;&gt;&quot;&#x9;&#xA;\">&lt;&gt;\"&#xD;</doc>";
+        let doc = new_document(xml).unwrap();
+        assert_eq!(doc.canonicalize(),
+            "<doc a=\"&lt;>&quot;&#x9;&#xA;\">&lt;&gt;\"&#xD;</doc>");
+    }
+
+    // -----------------------------------------------------------------
+    //
+    #[test]
+    fn test_canonicalize_subset() {
+        let xml = r#"<a xmlns="http://a" xmlns:p="http://p" xml:lang="ja"><b xml:space="preserve"><p:c q="1"/><s/></b></a>"#;
+        let doc = new_document(xml).unwrap();
+        let b = doc.get_first_node("//*[local-name() = 'b']").unwrap();
+        assert_eq!(b.canonicalize(),
+            r#"<b xmlns="http://a" xmlns:p="http://p" xml:lang="ja" xml:space="preserve"><p:c q="1"></p:c><s></s></b>"#);
+
+        let s = doc.get_first_node("//*[local-name() = 's']").unwrap();
+        let options = C14nOptions::new().exclude(&s);
+        assert_eq!(doc.canonicalize_with_options(&options),
+            r#"<a xmlns="http://a" xmlns:p="http://p" xml:lang="ja"><b xml:space="preserve"><p:c q="1"></p:c></b></a>"#);
+    }
+
+    // -----------------------------------------------------------------
+    // 深い木: 名前空間宣言を求めるのに、要素ごとに祖先をたどらない。
+    //
+    #[test]
+    fn test_canonicalize_deep() {
+        let depth = 100000;
+        let xml = format!("<a xmlns=\"http://a\">{}<p:b xmlns:p=\"http://p\"/>{}</a>",
+                        "<a>".repeat(depth), "</a>".repeat(depth));
+        let doc = new_document(&xml).unwrap();
+        let c14n = doc.canonicalize();
+        assert!(c14n.starts_with("<a xmlns=\"http://a\"><a><a>"));
+        assert!(c14n.contains("<a><p:b xmlns:p=\"http://p\"></p:b></a>"));
+
+        let b = doc.get_first_node("//*[local-name() = 'b']").unwrap();
+        assert_eq!(b.canonicalize(),
+            r#"<p:b xmlns="http://a" xmlns:p="http://p"></p:b>"#);
+    }
+}
@@ -0,0 +1,466 @@
+//
+// catalog.rs
+//
+// amxml: XML processor with XPath.
+// Copyright (C) 2018 KOYAMA Hiro <tac@amris.co.jp>
+//
+//!
+//! XML Catalogs.
+//!
+//! <strong>Catalog</strong> maps the identifiers of external resources
+//! (system identifiers, public identifiers and URIs) to other URIs,
+//! typically local mirrors, according to the entries of
+//! <a href="https://www.oasis-open.org/committees/download.php/14809/xml-catalogs.html">OASIS XML Catalogs</a>:
+//!
+//! - <code>system</code>, <code>rewriteSystem</code>: system identifiers.
+//! - <code>public</code>: public identifiers.
+//! - <code>uri</code>, <code>rewriteURI</code>: other URIs,
+//!   e.g. those given to fn:doc(); if none of them match,
+//!   the system entries are tried as well.
+//!
+//! Relative URIs in the catalog are resolved against the base URI of
+//! the entry, i.e. xml:base or the document URI of the catalog
+//! (see NodePtr#base_uri()). Entries in <code>group</code> are
+//! looked up as if they were at the top level.
+//! <code>nextCatalog</code>, <code>delegate*</code> and the
+//! <code>prefer</code> attribute are not supported; the system
+//! identifier is always preferred to the public identifier.
+//!
+//! The catalog only maps the identifiers; fetching the resources is up
+//! to the application, e.g. the handlers registered by
+//! EvalOptions#uri_scheme(), to which fn:doc() passes the URIs mapped
+//! by the catalog set by EvalOptions#catalog().
+//! This processor does not read DTDs; Catalog#resolve_doctype() tells
+//! where the DTD of a document is to be read from.
+//!
+//! # Examples
+//!
+//! ```
+//! use amxml::dom::*;
+//! use amxml::catalog::*;
+//! let catalog = new_catalog(r#"
+//!     <catalog xmlns="urn:oasis:names:tc:entity:xmlns:xml:catalog"
+//!              xml:base="file:///opt/mirror/">
+//!         <public publicId="-//W3C//DTD XHTML 1.0 Strict//EN" uri="dtd/xhtml1-strict.dtd"/>
+//!         <rewriteSystem systemIdStartString="http://example.com/schemas/" rewritePrefix="schemas/"/>
+//!         <uri name="http://example.com/data.xml" uri="data/data.xml"/>
+//!     </catalog>"#).unwrap();
+//!
+//! assert_eq!(catalog.resolve_public("-//W3C//DTD XHTML 1.0 Strict//EN").unwrap(),
+//!     "file:///opt/mirror/dtd/xhtml1-strict.dtd");
+//! assert_eq!(catalog.resolve_system("http://example.com/schemas/a/b.xsd").unwrap(),
+//!     "file:///opt/mirror/schemas/a/b.xsd");
+//! assert_eq!(catalog.resolve_uri("http://example.com/data.xml").unwrap(),
+//!     "file:///opt/mirror/data/data.xml");
+//! assert_eq!(catalog.resolve_uri("http://example.com/other.xml"), None);
+//!
+//! let doc = new_document(r#"<!DOCTYPE html PUBLIC "-//W3C//DTD XHTML 1.0 Strict//EN"
+//!     "http://www.w3.org/TR/xhtml1/DTD/xhtml1-strict.dtd"><html/>"#).unwrap();
+//! assert_eq!(catalog.resolve_doctype(&doc).unwrap(),
+//!     "file:///opt/mirror/dtd/xhtml1-strict.dtd");
+//! ```
+//!
+
+use std::error::Error;
+#[cfg(feature = "no_std")]
+use std::prelude::*;
+
+use dom::*;
+use xmlerror::*;
+
+const CATALOG_NS_URI: &str = "urn:oasis:names:tc:entity:xmlns:xml:catalog";
+
+// ---------------------------------------------------------------------
+// カタログの項目。
+// 値のURIは、項目のベースURIに対して解決したもの。
+//
+#[derive(Debug, Clone, PartialEq)]
+enum CatalogEntry {
+    System{ system_id: String, uri: String },
+    RewriteSystem{ start: String, prefix: String },
+    Public{ public_id: String, uri: String },
+    Uri{ name: String, uri: String },
+    RewriteUri{ start: String, prefix: String },
+}
+
+// =====================================================================
+/// XML Catalog: see the module document.
+///
+#[derive(Debug, Clone, Default)]
+pub struct Catalog {
+    entries: Vec<CatalogEntry>,
+}
+
+// =====================================================================
+/// Parses the XML string as a catalog.
+/// Relative URIs in it are resolved against xml:base only;
+/// use new_catalog_from_document() to give the catalog its URI.
+///
+/// # Errors
+///
+/// - When there is syntax error in XML.
+/// - When the root element is not <code>catalog</code> in the
+///   namespace of XML Catalogs.
+/// - When an entry lacks a required attribute.
+///
+pub fn new_catalog(xml_string: &str) -> Result<Catalog, Box<Error>> {
+    let doc = new_document(xml_string)?;
+    return new_catalog_from_document(&doc);
+}
+
+// =====================================================================
+/// Makes the catalog from the document, e.g. read by
+/// new_document_from_file(), so that relative URIs in the catalog are
+/// resolved against the document URI.
+///
+/// # Examples
+///
+/// ```
+/// use amxml::dom::*;
+/// use amxml::catalog::*;
+/// let options = ParseOptions::new().document_uri("file:///etc/xml/catalog.xml");
+/// let doc = new_document_with_options(r#"
+///     <catalog xmlns="urn:oasis:names:tc:entity:xmlns:xml:catalog">
+///         <group xml:base="mirror/">
+///             <system systemId="http://example.com/a.dtd" uri="a.dtd"/>
+///         </group>
+///     </catalog>"#, &options).unwrap();
+/// let catalog = new_catalog_from_document(&doc).unwrap();
+/// assert_eq!(catalog.resolve_system("http://example.com/a.dtd").unwrap(),
+///     "file:///etc/xml/mirror/a.dtd");
+/// ```
+///
+/// # Errors
+///
+/// - When the root element is not <code>catalog</code> in the
+///   namespace of XML Catalogs.
+/// - When an entry lacks a required attribute.
+///
+pub fn new_catalog_from_document(doc: &NodePtr) -> Result<Catalog, Box<Error>> {
+    let root = doc.root().root_element();
+    if root.local_name() != "catalog" || root.namespace_uri() != CATALOG_NS_URI {
+        return Err(xml_syntax_error!(
+            "Not an XML catalog: root element is {}", root.name()));
+    }
+
+    let mut catalog = Catalog::default();
+    let mut stack: Vec<NodePtr> = root.children();
+    stack.reverse();
+    while let Some(elem) = stack.pop() {
+        if elem.node_type() != NodeType::Element ||
+           elem.namespace_uri() != CATALOG_NS_URI {
+            continue;
+        }
+        let entry = match elem.local_name().as_str() {
+            "group" => {
+                let mut children = elem.children();
+                children.reverse();
+                stack.append(&mut children);
+                continue;
+            },
+            "system" => CatalogEntry::System{
+                system_id: normalize_system_id(&required_attribute(&elem, "systemId")?),
+                uri: entry_uri(&elem, "uri")?,
+            },
+            "rewriteSystem" => CatalogEntry::RewriteSystem{
+                start: normalize_system_id(&required_attribute(&elem, "systemIdStartString")?),
+                prefix: entry_uri(&elem, "rewritePrefix")?,
+            },
+            "public" => CatalogEntry::Public{
+                public_id: normalize_public_id(&required_attribute(&elem, "publicId")?),
+                uri: entry_uri(&elem, "uri")?,
+            },
+            "uri" => CatalogEntry::Uri{
+                name: normalize_system_id(&required_attribute(&elem, "name")?),
+                uri: entry_uri(&elem, "uri")?,
+            },
+            "rewriteURI" => CatalogEntry::RewriteUri{
+                start: normalize_system_id(&required_attribute(&elem, "uriStartString")?),
+                prefix: entry_uri(&elem, "rewritePrefix")?,
+            },
+            _ => continue,
+        };
+        catalog.entries.push(entry);
+    }
+    return Ok(catalog);
+}
+
+// ---------------------------------------------------------------------
+// 項目の必須の属性値。
+//
+fn required_attribute(elem: &NodePtr, name: &str) -> Result<String, Box<Error>> {
+    match elem.attribute_value(name) {
+        Some(value) => return Ok(value),
+        None => return Err(xml_syntax_error!(
+            "XML catalog: {} lacks attribute {}", elem.local_name(), name)),
+    }
+}
+
+// ---------------------------------------------------------------------
+// 項目のURI (またはrewritePrefix) を、項目のベースURIに対して解決する。
+//
+fn entry_uri(elem: &NodePtr, name: &str) -> Result<String, Box<Error>> {
+    let uri = required_attribute(elem, name)?;
+    if let Some(base) = elem.base_uri() {
+        if let Some(resolved) = resolve_uri(&uri, &base) {
+            return Ok(resolved);
+        }
+    }
+    return Ok(uri);
+}
+
+impl Catalog {
+    // -----------------------------------------------------------------
+    /// Returns the empty catalog, that maps nothing.
+    ///
+    pub fn new() -> Catalog {
+        return Catalog::default();
+    }
+
+    // -----------------------------------------------------------------
+    /// Appends the entries of the other catalog, which are looked up
+    /// after the entries of this catalog.
+    ///
+    pub fn append(&mut self, other: &Catalog) {
+        self.entries.extend(other.entries.iter().cloned());
+    }
+
+    // -----------------------------------------------------------------
+    /// Maps the system identifier by the <code>system</code> entries,
+    /// or the <code>rewriteSystem</code> entry with the longest
+    /// matching prefix.
+    ///
+    pub fn resolve_system(&self, system_id: &str) -> Option<String> {
+        let system_id = normalize_system_id(system_id);
+        for entry in self.entries.iter() {
+            if let CatalogEntry::System{system_id: ref id, ref uri} = *entry {
+                if *id == system_id {
+                    return Some(uri.clone());
+                }
+            }
+        }
+        return self.rewrite(&system_id, true);
+    }
+
+    // -----------------------------------------------------------------
+    /// Maps the public identifier by the <code>public</code> entries.
+    /// White space in the identifier is normalized before comparison.
+    ///
+    pub fn resolve_public(&self, public_id: &str) -> Option<String> {
+        let public_id = normalize_public_id(public_id);
+        for entry in self.entries.iter() {
+            if let CatalogEntry::Public{public_id: ref id, ref uri} = *entry {
+                if *id == public_id {
+                    return Some(uri.clone());
+                }
+            }
+        }
+        return None;
+    }
+
+    // -----------------------------------------------------------------
+    /// Maps the external identifier (of DTD, entity, etc.):
+    /// the system identifier first, then the public identifier.
+    ///
+    pub fn resolve_external_id(&self, public_id: Option<&str>,
+                system_id: Option<&str>) -> Option<String> {
+        if let Some(system_id) = system_id {
+            if let Some(uri) = self.resolve_system(system_id) {
+                return Some(uri);
+            }
+        }
+        if let Some(public_id) = public_id {
+            return self.resolve_public(public_id);
+        }
+        return None;
+    }
+
+    // -----------------------------------------------------------------
+    /// Maps the URI by the <code>uri</code> entries, or the
+    /// <code>rewriteURI</code> entry with the longest matching prefix;
+    /// if none matches, by the system entries (see resolve_system()).
+    ///
+    pub fn resolve_uri(&self, uri: &str) -> Option<String> {
+        let normalized = normalize_system_id(uri);
+        for entry in self.entries.iter() {
+            if let CatalogEntry::Uri{ref name, uri: ref mapped} = *entry {
+                if *name == normalized {
+                    return Some(mapped.clone());
+                }
+            }
+        }
+        if let Some(mapped) = self.rewrite(&normalized, false) {
+            return Some(mapped);
+        }
+        return self.resolve_system(uri);
+    }
+
+    // -----------------------------------------------------------------
+    /// Maps the external identifier in the DOCTYPE of the document,
+    /// i.e. tells where the DTD is to be read from.
+    /// Returns None if the document has no DOCTYPE with an external
+    /// identifier, or the catalog doesn't map it.
+    ///
+    pub fn resolve_doctype(&self, doc: &NodePtr) -> Option<String> {
+        for node in doc.root().children().iter() {
+            if node.node_type() == NodeType::Directive &&
+               node.value().starts_with("DOCTYPE") {
+                let (public_id, system_id) = parse_doctype_external_id(&node.value());
+                return self.resolve_external_id(
+                        public_id.as_ref().map(|s| s.as_str()),
+                        system_id.as_ref().map(|s| s.as_str()));
+            }
+        }
+        return None;
+    }
+
+    // -----------------------------------------------------------------
+    // rewriteSystem (is_system) または rewriteURI の項目のうち、
+    // 開始文字列が最も長く一致するもので、先頭部分を置き換える。
+    //
+    fn rewrite(&self, id: &str, is_system: bool) -> Option<String> {
+        let mut best: Option<(&String, &String)> = None;
+        for entry in self.entries.iter() {
+            let (start, prefix) = match (entry, is_system) {
+                (&CatalogEntry::RewriteSystem{ref start, ref prefix}, true) => (start, prefix),
+                (&CatalogEntry::RewriteUri{ref start, ref prefix}, false) => (start, prefix),
+                _ => continue,
+            };
+            if id.starts_with(start.as_str()) {
+                let longer = match best {
+                    Some((best_start, _)) => best_start.len() < start.len(),
+                    None => true,
+                };
+                if longer {
+                    best = Some((start, prefix));
+                }
+            }
+        }
+        return best.map(|(start, prefix)| format!("{}{}", prefix, &id[start.len() ..]));
+    }
+}
+
+// ---------------------------------------------------------------------
+// システム識別子 (URI) の正規化 (XML Catalogs, 6.3)。
+// 空白、制御文字、非ASCII文字と、URIに使えない記号を %XX の形にする。
+//
+fn normalize_system_id(id: &str) -> String {
+    let mut normalized = String::new();
+    for b in id.bytes() {
+        match b {
+            0x00 ..= 0x20 | 0x7F ..= 0xFF |
+            b'"' | b'<' | b'>' | b'\\' | b'^' | b'`' | b'{' | b'|' | b'}' => {
+                normalized += &format!("%{:02X}", b);
+            },
+            _ => normalized.push(b as char),
+        }
+    }
+    return normalized;
+}
+
+// ---------------------------------------------------------------------
+// 公開識別子の正規化 (XML Catalogs, 6.2)。
+// 前後の空白を除去し、連続する空白を1個の空白に置き換える。
+//
+fn normalize_public_id(id: &str) -> String {
+    return id.split(|c| c == ' ' || c == '\t' || c == '\r' || c == '\n')
+            .filter(|t| *t != "")
+            .collect::<Vec<&str>>().join(" ");
+}
+
+// ---------------------------------------------------------------------
+// DOCTYPE宣言 (先頭の「<!」と末尾の「>」を除いたもの) から、
+// 外部識別子 (公開識別子、システム識別子) を取り出す。
+//
+// DOCTYPE Name (SYSTEM SystemLiteral | PUBLIC PubidLiteral SystemLiteral)? ...
+//
+fn parse_doctype_external_id(doctype: &str) -> (Option<String>, Option<String>) {
+    let mut rest = doctype["DOCTYPE".len() ..].trim_start();
+    let name_end = rest.find(|c: char| c.is_whitespace() || c == '[')
+                       .unwrap_or(rest.len());
+    rest = rest[name_end ..].trim_start();
+    let is_public = rest.starts_with("PUBLIC");
+    if ! is_public && ! rest.starts_with("SYSTEM") {
+        return (None, None);
+    }
+    rest = &rest[6 ..];
+    let mut literals: Vec<String> = vec!{};
+    while literals.len() < (if is_public { 2 } else { 1 }) {
+        rest = rest.trim_start();
+        let quote = match rest.chars().next() {
+            Some(c) if c == '"' || c == '\'' => c,
+            _ => break,
+        };
+        match rest[1 ..].find(quote) {
+            Some(end) => {
+                literals.push(String::from(&rest[1 .. end + 1]));
+                rest = &rest[end + 2 ..];
+            },
+            None => break,
+        }
+    }
+    if is_public {
+        return (literals.get(0).cloned(), literals.get(1).cloned());
+    } else {
+        return (None, literals.get(0).cloned());
+    }
+}
+
+// =====================================================================
+//
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // -----------------------------------------------------------------
+    //
+    #[test]
+    fn test_catalog() {
+        let catalog = new_catalog(r#"
+<catalog xmlns="urn:oasis:names:tc:entity:xmlns:xml:catalog" xml:base="file:///m/">
+    <system systemId="http://example.com/a b.dtd" uri="a.dtd"/>
+    <rewriteSystem systemIdStartString="http://example.com/" rewritePrefix="ex/"/>
+    <rewriteSystem systemIdStartString="http://example.com/deep/" rewritePrefix="deep/"/>
+    <public publicId="  -//A//DTD   A//EN " uri="pub/a.dtd"/>
+    <group xml:base="http://mirror.local/">
+        <uri name="http://example.com/doc.xml" uri="doc.xml"/>
+        <rewriteURI uriStartString="urn:x:" rewritePrefix="x/"/>
+    </group>
+    <other xmlns="http://other" uri="ignored"/>
+</catalog>"#).unwrap();
+
+        assert_eq!(catalog.resolve_system("http://example.com/a%20b.dtd").unwrap(), "file:///m/a.dtd");
+        assert_eq!(catalog.resolve_system("http://example.com/a b.dtd").unwrap(), "file:///m/a.dtd");
+        assert_eq!(catalog.resolve_system("http://example.com/b.dtd").unwrap(), "file:///m/ex/b.dtd");
+        assert_eq!(catalog.resolve_system("http://example.com/deep/c.dtd").unwrap(), "file:///m/deep/c.dtd");
+        assert_eq!(catalog.resolve_system("http://other.com/c.dtd"), None);
+
+        assert_eq!(catalog.resolve_public("-//A//DTD A//EN").unwrap(), "file:///m/pub/a.dtd");
+        assert_eq!(catalog.resolve_public("-//B//DTD B//EN"), None);
+        assert_eq!(catalog.resolve_external_id(Some("-//A//DTD A//EN"), Some("http://example.com/z.dtd")).unwrap(),
+                "file:///m/ex/z.dtd");
+        assert_eq!(catalog.resolve_external_id(Some("-//A//DTD A//EN"), Some("z.dtd")).unwrap(),
+                "file:///m/pub/a.dtd");
+
+        assert_eq!(catalog.resolve_uri("http://example.com/doc.xml").unwrap(), "http://mirror.local/doc.xml");
+        assert_eq!(catalog.resolve_uri("urn:x:y").unwrap(), "http://mirror.local/x/y");
+        assert_eq!(catalog.resolve_uri("http://example.com/other.xml").unwrap(), "file:///m/ex/other.xml");
+        assert_eq!(catalog.resolve_uri("urn:z"), None);
+
+        assert!(new_catalog("<catalog/>").is_err());
+        assert!(new_catalog(r#"<catalog xmlns="urn:oasis:names:tc:entity:xmlns:xml:catalog"><system uri="a"/></catalog>"#).is_err());
+    }
+
+    // -----------------------------------------------------------------
+    //
+    #[test]
+    fn test_parse_doctype_external_id() {
+        assert_eq!(parse_doctype_external_id(r#"DOCTYPE a SYSTEM "a.dtd""#),
+            (None, Some(String::from("a.dtd"))));
+        assert_eq!(parse_doctype_external_id(r#"DOCTYPE a PUBLIC '-//A//EN' "a.dtd" [ <!ENTITY e "x"> ]"#),
+            (Some(String::from("-//A//EN")), Some(String::from("a.dtd"))));
+        assert_eq!(parse_doctype_external_id(r#"DOCTYPE a [ <!ENTITY e "x"> ]"#),
+            (None, None));
+        assert_eq!(parse_doctype_external_id("DOCTYPE a"), (None, None));
+    }
+}
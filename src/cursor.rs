@@ -0,0 +1,267 @@
+//
+// cursor.rs
+//
+// amxml: XML processor with XPath.
+// Copyright (C) 2018 KOYAMA Hiro <tac@amris.co.jp>
+//
+//!
+//! Cursor (zipper style) navigation over the DOM tree.
+//!
+//! A <strong>Cursor</strong> points to a node, and moves to its
+//! parent, first / last child, or next / previous sibling.
+//! The cursor remembers the path from the starting node's root,
+//! i.e. the position of each ancestor among its siblings,
+//! so that each move costs O(1), without building the vector of
+//! children nor searching the node in it.
+//!
+//! # Examples
+//!
+//! ```
+//! use amxml::dom::*;
+//! use amxml::cursor::*;
+//! let doc = new_document(r#"<root><a><x/></a><b/><c/></root>"#).unwrap();
+//! let mut cursor = Cursor::new(&doc.root_element());
+//! assert!(cursor.first_child());
+//! assert_eq!(cursor.node().name(), "a");
+//! let mut names = vec!{};
+//! loop {
+//!     names.push(format!("{}@{}", cursor.node().name(), cursor.position()));
+//!     if ! cursor.next_sibling() {
+//!         break;
+//!     }
+//! }
+//! assert_eq!(names, vec!{"a@0", "b@1", "c@2"});
+//!
+//! assert!(cursor.prev_sibling());
+//! assert!(cursor.parent());
+//! assert_eq!(cursor.node().name(), "root");
+//! assert_eq!(cursor.depth(), 1);
+//! ```
+//!
+
+use std::mem;
+use std::usize;
+#[cfg(feature = "no_std")]
+use std::prelude::*;
+
+use dom::*;
+
+// =====================================================================
+/// Cursor pointing to a node in the DOM tree.
+/// See the module document.
+///
+#[derive(Debug, Clone)]
+pub struct Cursor {
+    node: NodePtr,
+    ancestors: Vec<(NodePtr, usize)>,
+            // 根から node に至る各段の (親, 親の子の中での位置)。
+            // 属性ノードの位置は usize::MAX。
+}
+
+impl Cursor {
+    // =================================================================
+    /// Creates the cursor pointing to the node.
+    ///
+    /// This computes the position of the node and its ancestors
+    /// once; the moves afterwards do not.
+    ///
+    pub fn new(node: &NodePtr) -> Cursor {
+        let mut ancestors: Vec<(NodePtr, usize)> = vec!{};
+        let mut curr = node.rc_clone();
+        while let Some(parent) = curr.parent() {
+            let pos = parent.children().iter()
+                        .position(|ch| *ch == curr).unwrap_or(usize::MAX);
+            ancestors.push((parent.rc_clone(), pos));
+            curr = parent;
+        }
+        ancestors.reverse();
+        return Cursor{
+            node: node.rc_clone(),
+            ancestors: ancestors,
+        };
+    }
+
+    // =================================================================
+    /// Returns the node the cursor points to.
+    ///
+    pub fn node(&self) -> &NodePtr {
+        return &self.node;
+    }
+
+    // =================================================================
+    /// Returns the position (0-based) of the node among the children
+    /// of its parent; 0 for the root.
+    /// Returns usize::MAX for the attribute node.
+    ///
+    pub fn position(&self) -> usize {
+        match self.ancestors.last() {
+            Some(&(_, pos)) => return pos,
+            None => return 0,
+        }
+    }
+
+    // =================================================================
+    /// Returns the depth of the node, i.e. the number of its ancestors;
+    /// 0 for the root.
+    ///
+    pub fn depth(&self) -> usize {
+        return self.ancestors.len();
+    }
+
+    // =================================================================
+    /// Moves to the parent.
+    /// Returns false (and does not move) if the node is the root.
+    ///
+    pub fn parent(&mut self) -> bool {
+        match self.ancestors.pop() {
+            Some((parent, _)) => {
+                self.node = parent;
+                return true;
+            },
+            None => return false,
+        }
+    }
+
+    // =================================================================
+    /// Moves to the first child.
+    /// Returns false (and does not move) if there is no child.
+    ///
+    pub fn first_child(&mut self) -> bool {
+        return self.move_to_child(0);
+    }
+
+    // =================================================================
+    /// Moves to the last child.
+    /// Returns false (and does not move) if there is no child.
+    ///
+    pub fn last_child(&mut self) -> bool {
+        let n = self.node.child_count();
+        if n == 0 {
+            return false;
+        }
+        return self.move_to_child(n - 1);
+    }
+
+    // =================================================================
+    /// Moves to the next sibling.
+    /// Returns false (and does not move) if there is no next sibling,
+    /// or the node is the attribute node.
+    ///
+    pub fn next_sibling(&mut self) -> bool {
+        let pos = self.position();
+        if pos == usize::MAX {
+            return false;
+        }
+        return self.move_to_sibling(pos + 1);
+    }
+
+    // =================================================================
+    /// Moves to the previous sibling.
+    /// Returns false (and does not move) if there is no previous sibling,
+    /// or the node is the attribute node.
+    ///
+    pub fn prev_sibling(&mut self) -> bool {
+        let pos = self.position();
+        if pos == 0 || pos == usize::MAX {
+            return false;
+        }
+        return self.move_to_sibling(pos - 1);
+    }
+
+    // -----------------------------------------------------------------
+    //
+    fn move_to_child(&mut self, pos: usize) -> bool {
+        match self.node.nth_child(pos) {
+            Some(child) => {
+                let parent = mem::replace(&mut self.node, child);
+                self.ancestors.push((parent, pos));
+                return true;
+            },
+            None => return false,
+        }
+    }
+
+    // -----------------------------------------------------------------
+    //
+    fn move_to_sibling(&mut self, pos: usize) -> bool {
+        let sibling = match self.ancestors.last() {
+            Some(&(ref parent, _)) => parent.nth_child(pos),
+            None => None,
+        };
+        match sibling {
+            Some(sibling) => {
+                self.node = sibling;
+                if let Some(last) = self.ancestors.last_mut() {
+                    last.1 = pos;
+                }
+                return true;
+            },
+            None => return false,
+        }
+    }
+}
+
+// =====================================================================
+//
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // -----------------------------------------------------------------
+    //
+    #[test]
+    fn test_cursor() {
+        let doc = new_document(r#"<root><a id="1"><x/>t</a><b/><c><y/><z/></c></root>"#).unwrap();
+
+        // 文書順に全ノードをたどる。
+        let mut cursor = Cursor::new(&doc);
+        let mut visited = vec!{};
+        loop {
+            visited.push(format!("{}{}{}", cursor.node().name(),
+                        cursor.depth(), cursor.position()));
+            if cursor.first_child() {
+                continue;
+            }
+            while ! cursor.next_sibling() {
+                if ! cursor.parent() {
+                    break;
+                }
+            }
+            if cursor.depth() == 0 {
+                break;
+            }
+        }
+        assert_eq!(visited.join(" "), "00 root10 a20 x30 31 b21 c22 y30 z31");
+
+        // 途中のノードから始める。
+        let elem_z = doc.get_first_node("//z").unwrap();
+        let mut cursor = Cursor::new(&elem_z);
+        assert_eq!((cursor.depth(), cursor.position()), (3, 1));
+        assert!(! cursor.next_sibling());
+        assert!(cursor.prev_sibling());
+        assert_eq!(cursor.node().name(), "y");
+        assert!(! cursor.prev_sibling());
+        assert!(! cursor.first_child());
+        assert!(cursor.parent());
+        assert!(cursor.prev_sibling());
+        assert_eq!(cursor.node().name(), "b");
+        assert!(cursor.parent());
+        assert!(cursor.last_child());
+        assert_eq!(cursor.node().name(), "c");
+        assert!(cursor.parent());
+        assert!(cursor.parent());
+        assert!(! cursor.parent());
+        assert_eq!(cursor.node().node_type(), NodeType::DocumentRoot);
+
+        // 属性ノードには兄弟がない。
+        let attr = doc.get_first_node("//@id").unwrap();
+        let mut cursor = Cursor::new(&attr);
+        assert_eq!(cursor.position(), usize::MAX);
+        assert!(! cursor.next_sibling());
+        assert!(! cursor.prev_sibling());
+        assert!(cursor.parent());
+        assert_eq!(cursor.node().name(), "a");
+        assert!(cursor.next_sibling());
+        assert_eq!(cursor.node().name(), "b");
+    }
+}
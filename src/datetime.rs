@@ -0,0 +1,681 @@
+//
+// datetime.rs
+//
+// amxml: XML processor with XPath.
+// Copyright (C) 2018 KOYAMA Hiro <tac@amris.co.jp>
+//
+//!
+//! Date and time: xs:dateTime.
+//!
+//! The parser functions read the lexical form of xs:dateTime,
+//! RFC 3339 timestamps (leniently, as found in feeds), and the dates
+//! of HTTP and e-mail headers (as fn:parse-ietf-date does).
+//! In XPath, the values of xs:dateTime are created by casting,
+//! like <code>"2018-10-01T09:00:00Z" cast as xs:dateTime</code>,
+//! by <code>fn:parse-ietf-date()</code>, or by the extension function
+//! <code>am:parse-rfc3339()</code>, and can be compared with each other
+//! or cast to xs:string.
+//!
+//! # Examples
+//!
+//! ```
+//! use amxml::dom::*;
+//! use amxml::datetime::*;
+//! let dt = parse_ietf_date("Wed, 06 Jun 1994 07:29:35 GMT").unwrap();
+//! assert_eq!(dt.to_string(), "1994-06-06T07:29:35Z");
+//! let dt = parse_rfc3339("1994-06-06 16:29:35.5+0900").unwrap();
+//! assert_eq!(dt.to_string(), "1994-06-06T16:29:35.5+09:00");
+//!
+//! let xml = r#"<feed><item date="Mon, 01 Oct 2018 10:00:00 +0200"/><item date="Mon, 01 Oct 2018 09:00:00 GMT"/></feed>"#;
+//! let doc = new_document(xml).unwrap();
+//! let result = doc.eval_xpath("parse-ietf-date(//item[1]/@date) lt parse-ietf-date(//item[2]/@date)").unwrap();
+//! assert_eq!(result.to_string(), "true");
+//! ```
+//!
+
+use std::cmp::Ordering;
+use std::error::Error;
+use std::fmt;
+#[cfg(feature = "no_std")]
+use std::prelude::*;
+
+use xmlerror::*;
+
+// =====================================================================
+/// Value of xs:dateTime.
+///
+#[derive(Debug, PartialEq, Clone)]
+pub struct DateTime {
+    pub year: i64,
+    pub month: u32,
+    pub day: u32,
+    pub hour: u32,
+    pub minute: u32,
+    pub second: u32,
+    pub nanosecond: u32,
+    /// Offset from UTC in minutes; None if the value has no timezone.
+    pub timezone: Option<i32>,
+}
+
+impl fmt::Display for DateTime {
+    // -----------------------------------------------------------------
+    // 正規字句表現。
+    //
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut s = String::new();
+        if self.year < 0 {
+            s += &format!("-{:04}", -self.year);
+        } else {
+            s += &format!("{:04}", self.year);
+        }
+        s += &format!("-{:02}-{:02}T{:02}:{:02}:{:02}",
+                    self.month, self.day, self.hour, self.minute, self.second);
+        if self.nanosecond != 0 {
+            let frac = format!("{:09}", self.nanosecond);
+            s += &format!(".{}", frac.trim_end_matches('0'));
+        }
+        match self.timezone {
+            Some(0) => s += &"Z",
+            Some(tz) => {
+                let sign = if tz < 0 { '-' } else { '+' };
+                s += &format!("{}{:02}:{:02}", sign, tz.abs() / 60, tz.abs() % 60);
+            },
+            None => {},
+        }
+        return write!(f, "{}", s);
+    }
+}
+
+impl DateTime {
+    // =================================================================
+    /// Compares with the other value by the instant in time.
+    /// A value without timezone is taken as UTC
+    /// (the implicit timezone).
+    ///
+    pub fn compare(&self, other: &DateTime) -> Ordering {
+        return self.instant().cmp(&other.instant());
+    }
+
+    // -----------------------------------------------------------------
+    // UTC の 1970-01-01T00:00:00 からの (秒, ナノ秒)。
+    //
+    fn instant(&self) -> (i64, u32) {
+        let days = days_from_civil(self.year, self.month, self.day);
+        let tz = self.timezone.unwrap_or(0) as i64;
+        let secs = days * 86400 + (self.hour as i64) * 3600 +
+                    (self.minute as i64) * 60 + (self.second as i64) - tz * 60;
+        return (secs, self.nanosecond);
+    }
+
+    // -----------------------------------------------------------------
+    // 各欄の値域を検査する。時が24の場合 (24:00:00) は翌日の0時にする。
+    //
+    fn validate(mut self, s: &str) -> Result<DateTime, Box<Error>> {
+        if self.month < 1 || 12 < self.month ||
+           self.day < 1 || days_in_month(self.year, self.month) < self.day ||
+           59 < self.minute || 59 < self.second {
+            return Err(dynamic_error!(
+                "Invalid date/time (out of range): {} [err:FORG0001]", s));
+        }
+        if self.hour == 24 {
+            if self.minute != 0 || self.second != 0 || self.nanosecond != 0 {
+                return Err(dynamic_error!(
+                    "Invalid date/time (out of range): {} [err:FORG0001]", s));
+            }
+            let (year, month, day) = civil_from_days(
+                        days_from_civil(self.year, self.month, self.day) + 1);
+            self.year = year;
+            self.month = month;
+            self.day = day;
+            self.hour = 0;
+        } else if 23 < self.hour {
+            return Err(dynamic_error!(
+                "Invalid date/time (out of range): {} [err:FORG0001]", s));
+        }
+        if let Some(tz) = self.timezone {
+            if 14 * 60 < tz.abs() {
+                return Err(dynamic_error!(
+                    "Invalid date/time (timezone): {} [err:FORG0001]", s));
+            }
+        }
+        return Ok(self);
+    }
+}
+
+// =====================================================================
+/// Parses the lexical form of xs:dateTime,
+/// e.g. <code>2018-10-01T09:00:00.5+09:00</code>.
+/// Leading and trailing whitespace is ignored.
+///
+/// # Errors
+///
+/// - When the string is not a valid lexical form of xs:dateTime.
+///
+pub fn parse_date_time(s: &str) -> Result<DateTime, Box<Error>> {
+    let mut sc = Scanner::new(s.trim());
+    let dt = scan_rfc3339(&mut sc, false);
+    match dt {
+        Some(dt) if sc.at_end() => return dt.validate(s),
+        _ => return Err(dynamic_error!(
+                "Invalid xs:dateTime: {} [err:FORG0001]", s)),
+    }
+}
+
+// =====================================================================
+/// Parses the timestamp of RFC 3339 leniently: besides the form of
+/// xs:dateTime, accepted are a space or a lower case 't' between the
+/// date and the time, a lower case 'z', the time without seconds, and
+/// the timezone offset without colon (e.g. <code>+0900</code>).
+/// A date without time means the midnight.
+///
+/// # Examples
+///
+/// ```
+/// use amxml::datetime::*;
+/// assert_eq!(parse_rfc3339("2018-10-01t09:00z").unwrap().to_string(),
+///     "2018-10-01T09:00:00Z");
+/// assert_eq!(parse_rfc3339("2018-10-01").unwrap().to_string(),
+///     "2018-10-01T00:00:00");
+/// ```
+///
+/// # Errors
+///
+/// - When the string is not a timestamp.
+///
+pub fn parse_rfc3339(s: &str) -> Result<DateTime, Box<Error>> {
+    let mut sc = Scanner::new(s.trim());
+    let dt = scan_rfc3339(&mut sc, true);
+    match dt {
+        Some(dt) if sc.at_end() => return dt.validate(s),
+        _ => return Err(dynamic_error!(
+                "Invalid RFC 3339 date/time: {} [err:FORG0001]", s)),
+    }
+}
+
+// =====================================================================
+/// Parses the date in the formats of HTTP and e-mail headers
+/// (RFC 1123, RFC 850, asctime), as fn:parse-ietf-date does,
+/// e.g. <code>Wed, 06 Jun 1994 07:29:35 GMT</code> or
+/// <code>Wed Jun 06 11:54:45 EST 2013</code>.
+/// A date without timezone is taken as UTC, and a two-digit year
+/// means 19xx.
+///
+/// # Errors
+///
+/// - When the string is not in the formats [err:FORG0010].
+///
+pub fn parse_ietf_date(s: &str) -> Result<DateTime, Box<Error>> {
+    let mut sc = Scanner::new(s);
+    match scan_ietf_date(&mut sc) {
+        Some(dt) if sc.at_end() => {
+            match dt.validate(s) {
+                Ok(dt) => return Ok(dt),
+                Err(_) => {},
+            }
+        },
+        _ => {},
+    }
+    return Err(dynamic_error!("Invalid IETF date: {} [err:FORG0010]", s));
+}
+
+// ---------------------------------------------------------------------
+// 字句解析用の、文字の読み取り位置。
+//
+struct Scanner {
+    chars: Vec<char>,
+    pos: usize,
+}
+
+impl Scanner {
+    fn new(s: &str) -> Scanner {
+        return Scanner{ chars: s.chars().collect(), pos: 0 };
+    }
+
+    fn at_end(&self) -> bool {
+        return self.chars.len() <= self.pos;
+    }
+
+    fn peek(&self) -> Option<char> {
+        return self.chars.get(self.pos).cloned();
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        if self.peek() == Some(c) {
+            self.pos += 1;
+            return true;
+        }
+        return false;
+    }
+
+    // -----------------------------------------------------------------
+    // 空白を読み飛ばし、読み飛ばしたか否かを返す。
+    //
+    fn skip_spaces(&mut self) -> bool {
+        let start = self.pos;
+        while let Some(c) = self.peek() {
+            if c == ' ' || c == '\t' || c == '\r' || c == '\n' {
+                self.pos += 1;
+            } else {
+                break;
+            }
+        }
+        return start < self.pos;
+    }
+
+    // -----------------------------------------------------------------
+    // min 桁以上 max 桁以下の数字を読み、その値を返す。
+    //
+    fn digits(&mut self, min: usize, max: usize) -> Option<(i64, usize)> {
+        let mut value: i64 = 0;
+        let mut n = 0;
+        while n < max {
+            match self.peek().and_then(|c| c.to_digit(10)) {
+                Some(d) => {
+                    value = value * 10 + (d as i64);
+                    self.pos += 1;
+                    n += 1;
+                },
+                None => break,
+            }
+        }
+        if n < min {
+            return None;
+        }
+        return Some((value, n));
+    }
+
+    // -----------------------------------------------------------------
+    // 英字の並びを読む。
+    //
+    fn word(&mut self) -> String {
+        let mut s = String::new();
+        while let Some(c) = self.peek() {
+            if c.is_ascii_alphabetic() {
+                s.push(c);
+                self.pos += 1;
+            } else {
+                break;
+            }
+        }
+        return s;
+    }
+
+    // -----------------------------------------------------------------
+    // 小数点以下の数字をナノ秒として読む (10桁目以降は切り捨てる)。
+    //
+    fn fraction(&mut self) -> Option<u32> {
+        let mut nanos: u32 = 0;
+        let mut n = 0;
+        while let Some(d) = self.peek().and_then(|c| c.to_digit(10)) {
+            if n < 9 {
+                nanos = nanos * 10 + d;
+            }
+            self.pos += 1;
+            n += 1;
+        }
+        if n == 0 {
+            return None;
+        }
+        for _ in n .. 9 {
+            nanos *= 10;
+        }
+        return Some(nanos);
+    }
+}
+
+// ---------------------------------------------------------------------
+// xs:dateTime の字句表現。lenient ならば RFC 3339 の揺れも受け付ける。
+//
+fn scan_rfc3339(sc: &mut Scanner, lenient: bool) -> Option<DateTime> {
+    let negative = ! lenient && sc.eat('-');
+    let (year, _) = sc.digits(4, usize::max_value())?;
+    let year = if negative { -year } else { year };
+    if ! sc.eat('-') { return None; }
+    let (month, _) = sc.digits(2, 2)?;
+    if ! sc.eat('-') { return None; }
+    let (day, _) = sc.digits(2, 2)?;
+    let mut dt = DateTime{
+        year: year, month: month as u32, day: day as u32,
+        hour: 0, minute: 0, second: 0, nanosecond: 0, timezone: None,
+    };
+    if lenient && sc.at_end() {
+        return Some(dt);
+    }
+    if ! (sc.eat('T') || (lenient && (sc.eat('t') || sc.eat(' ')))) {
+        return None;
+    }
+    let (hour, _) = sc.digits(2, 2)?;
+    if ! sc.eat(':') { return None; }
+    let (minute, _) = sc.digits(2, 2)?;
+    dt.hour = hour as u32;
+    dt.minute = minute as u32;
+    if sc.eat(':') {
+        let (second, _) = sc.digits(2, 2)?;
+        dt.second = second as u32;
+        if sc.eat('.') {
+            dt.nanosecond = sc.fraction()?;
+        }
+    } else if ! lenient {
+        return None;
+    }
+    if lenient {
+        sc.skip_spaces();
+    }
+    if sc.eat('Z') || (lenient && sc.eat('z')) {
+        dt.timezone = Some(0);
+    } else if let Some(sign) = sc.peek().filter(|c| *c == '+' || *c == '-') {
+        sc.pos += 1;
+        let (tzh, _) = sc.digits(2, 2)?;
+        let tzm = if sc.eat(':') || lenient {
+            match sc.digits(2, 2) {
+                Some((m, _)) => m,
+                None if lenient => 0,
+                None => return None,
+            }
+        } else {
+            return None;
+        };
+        if 59 < tzm {
+            return None;
+        }
+        let tz = (tzh * 60 + tzm) as i32;
+        dt.timezone = Some(if sign == '-' { -tz } else { tz });
+    }
+    return Some(dt);
+}
+
+// ---------------------------------------------------------------------
+// fn:parse-ietf-date の文法:
+//   input     ::= S? (dayname ","? S)? ((datespec S time) | asctime) S?
+//   datespec  ::= daynum dsep monthname dsep year
+//   asctime   ::= monthname dsep daynum S time S year
+//   dsep      ::= S | (S? "-" S?)
+//   time      ::= hours ":" minutes (":" seconds)? (S? timezone)?
+//   timezone  ::= tzname | tzoffset (S? "(" S? tzname S? ")")?
+//
+fn scan_ietf_date(sc: &mut Scanner) -> Option<DateTime> {
+    sc.skip_spaces();
+    let save = sc.pos;
+    let word = sc.word();
+    if is_dayname(&word) {
+        sc.eat(',');
+        if ! sc.skip_spaces() {
+            return None;
+        }
+    } else {
+        sc.pos = save;
+    }
+
+    let mut dt = DateTime{
+        year: 0, month: 0, day: 0,
+        hour: 0, minute: 0, second: 0, nanosecond: 0, timezone: None,
+    };
+    if sc.peek().map_or(false, |c| c.is_ascii_digit()) {
+        // datespec S time
+        let (day, _) = sc.digits(1, 2)?;
+        scan_dsep(sc)?;
+        dt.month = month_number(&sc.word())?;
+        scan_dsep(sc)?;
+        dt.year = scan_year(sc)?;
+        dt.day = day as u32;
+        if ! sc.skip_spaces() {
+            return None;
+        }
+        scan_ietf_time(sc, &mut dt)?;
+    } else {
+        // asctime
+        dt.month = month_number(&sc.word())?;
+        scan_dsep(sc)?;
+        let (day, _) = sc.digits(1, 2)?;
+        dt.day = day as u32;
+        if ! sc.skip_spaces() {
+            return None;
+        }
+        scan_ietf_time(sc, &mut dt)?;
+        if ! sc.skip_spaces() {
+            return None;
+        }
+        dt.year = scan_year(sc)?;
+    }
+    sc.skip_spaces();
+    if dt.timezone.is_none() {
+        dt.timezone = Some(0);
+    }
+    return Some(dt);
+}
+
+// ---------------------------------------------------------------------
+//
+fn scan_dsep(sc: &mut Scanner) -> Option<()> {
+    let spaces = sc.skip_spaces();
+    if sc.eat('-') {
+        sc.skip_spaces();
+        return Some(());
+    }
+    if spaces {
+        return Some(());
+    }
+    return None;
+}
+
+// ---------------------------------------------------------------------
+// 2桁の年は19xx年とする。
+//
+fn scan_year(sc: &mut Scanner) -> Option<i64> {
+    match sc.digits(2, 4)? {
+        (year, 2) => return Some(1900 + year),
+        (year, 4) => return Some(year),
+        _ => return None,
+    }
+}
+
+// ---------------------------------------------------------------------
+//
+fn scan_ietf_time(sc: &mut Scanner, dt: &mut DateTime) -> Option<()> {
+    let (hour, _) = sc.digits(1, 2)?;
+    if ! sc.eat(':') { return None; }
+    let (minute, _) = sc.digits(2, 2)?;
+    dt.hour = hour as u32;
+    dt.minute = minute as u32;
+    if sc.eat(':') {
+        let (second, _) = sc.digits(2, 2)?;
+        dt.second = second as u32;
+        if sc.eat('.') {
+            dt.nanosecond = sc.fraction()?;
+        }
+    }
+
+    let save = sc.pos;
+    sc.skip_spaces();
+    if let Some(sign) = sc.peek().filter(|c| *c == '+' || *c == '-') {
+        sc.pos += 1;
+        let (tzh, n) = sc.digits(1, 2)?;
+        let tzm = if sc.eat(':') {
+            sc.digits(2, 2)?.0
+        } else if n == 2 {
+            sc.digits(2, 2).map_or(0, |(m, _)| m)
+        } else {
+            0
+        };
+        if 59 < tzm {
+            return None;
+        }
+        let tz = (tzh * 60 + tzm) as i32;
+        dt.timezone = Some(if sign == '-' { -tz } else { tz });
+
+        // 後に続く "(tzname)" は註釈として読み飛ばす。
+        let save = sc.pos;
+        sc.skip_spaces();
+        if sc.eat('(') {
+            sc.skip_spaces();
+            tzname_offset(&sc.word())?;
+            sc.skip_spaces();
+            if ! sc.eat(')') {
+                return None;
+            }
+        } else {
+            sc.pos = save;
+        }
+        return Some(());
+    }
+    let word_start = sc.pos;
+    let word = sc.word();
+    match tzname_offset(&word) {
+        Some(tz) => dt.timezone = Some(tz),
+        None => {
+            sc.pos = if word.is_empty() { save } else { word_start };
+            if ! word.is_empty() {
+                return None;
+            }
+        },
+    }
+    return Some(());
+}
+
+// ---------------------------------------------------------------------
+//
+fn is_dayname(word: &str) -> bool {
+    const DAYNAMES: [&str; 14] = [
+        "mon", "tue", "wed", "thu", "fri", "sat", "sun",
+        "monday", "tuesday", "wednesday", "thursday", "friday",
+        "saturday", "sunday",
+    ];
+    let word = word.to_ascii_lowercase();
+    return DAYNAMES.iter().any(|d| *d == word);
+}
+
+// ---------------------------------------------------------------------
+//
+fn month_number(word: &str) -> Option<u32> {
+    const MONTHNAMES: [&str; 12] = [
+        "jan", "feb", "mar", "apr", "may", "jun",
+        "jul", "aug", "sep", "oct", "nov", "dec",
+    ];
+    let word = word.to_ascii_lowercase();
+    return MONTHNAMES.iter().position(|m| *m == word).map(|i| i as u32 + 1);
+}
+
+// ---------------------------------------------------------------------
+// 時間帯名の UTC からの差 (分)。
+//
+fn tzname_offset(word: &str) -> Option<i32> {
+    match word.to_ascii_uppercase().as_str() {
+        "UT" | "UTC" | "GMT" => return Some(0),
+        "EST" => return Some(-5 * 60),
+        "EDT" => return Some(-4 * 60),
+        "CST" => return Some(-6 * 60),
+        "CDT" => return Some(-5 * 60),
+        "MST" => return Some(-7 * 60),
+        "MDT" => return Some(-6 * 60),
+        "PST" => return Some(-8 * 60),
+        "PDT" => return Some(-7 * 60),
+        _ => return None,
+    }
+}
+
+// ---------------------------------------------------------------------
+//
+fn is_leap_year(year: i64) -> bool {
+    return (year % 4 == 0 && year % 100 != 0) || year % 400 == 0;
+}
+
+fn days_in_month(year: i64, month: u32) -> u32 {
+    match month {
+        2 => return if is_leap_year(year) { 29 } else { 28 },
+        4 | 6 | 9 | 11 => return 30,
+        _ => return 31,
+    }
+}
+
+// ---------------------------------------------------------------------
+// 1970-01-01 からの日数 (先発グレゴリオ暦)。
+//
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = if 0 <= y { y } else { y - 399 } / 400;
+    let yoe = y - era * 400;
+    let m = month as i64;
+    let doy = (153 * (if 2 < m { m - 3 } else { m + 9 }) + 2) / 5 + (day as i64) - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    return era * 146097 + doe - 719468;
+}
+
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719468;
+    let era = if 0 <= z { z } else { z - 146096 } / 146097;
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    return (year, month, day);
+}
+
+// =====================================================================
+//
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // -----------------------------------------------------------------
+    //
+    #[test]
+    fn test_parse_ietf_date() {
+        let cases = [
+            ( "Wed, 06 Jun 1994 07:29:35 GMT", "1994-06-06T07:29:35Z" ),
+            ( "Wed, 6 Jun 94 07:29:35 GMT", "1994-06-06T07:29:35Z" ),
+            ( "Wed Jun 06 11:54:45 EST 2013", "2013-06-06T11:54:45-05:00" ),
+            ( "Sunday, 06-Nov-94 08:49:37 GMT", "1994-11-06T08:49:37Z" ),
+            ( "Wed, 6 Jun 94 07:29:35 +0500", "1994-06-06T07:29:35+05:00" ),
+            ( "  6 jun 1994 7:29 -03:30 (EDT) ", "1994-06-06T07:29:00-03:30" ),
+            ( "Mon, 01 Oct 2018 23:59:59.25", "2018-10-01T23:59:59.25Z" ),
+        ];
+        for (s, expected) in cases.iter() {
+            assert_eq!(parse_ietf_date(s).unwrap().to_string(), *expected, "{}", s);
+        }
+
+        let errors = [
+            "", "Wed, 06 Jun 1994", "Wed 06 Jun 1994 07:29:35 GMT x",
+            "Wed, 31 Jun 1994 07:29:35 GMT", "Wed, 06 Jux 1994 07:29:35 GMT",
+            "Wed, 06 Jun 1994 25:00:00 GMT", "Wed, 06 Jun 994 07:29:35 GMT",
+            "Wed, 06 Jun 1994 07:29:35 JST",
+        ];
+        for s in errors.iter() {
+            assert!(parse_ietf_date(s).is_err(), "{}", s);
+        }
+    }
+
+    // -----------------------------------------------------------------
+    //
+    #[test]
+    fn test_parse_date_time() {
+        assert_eq!(parse_date_time("2018-10-01T09:00:00").unwrap().to_string(),
+                "2018-10-01T09:00:00");
+        assert_eq!(parse_date_time(" -0044-03-15T12:00:00.100+00:00 ").unwrap().to_string(),
+                "-0044-03-15T12:00:00.1Z");
+        assert_eq!(parse_date_time("2018-12-31T24:00:00Z").unwrap().to_string(),
+                "2019-01-01T00:00:00Z");
+        assert!(parse_date_time("2018-10-01 09:00:00").is_err());
+        assert!(parse_date_time("2018-10-01T09:00").is_err());
+        assert!(parse_date_time("2018-02-29T09:00:00").is_err());
+        assert!(parse_date_time("2018-10-01T09:00:00+15:00").is_err());
+
+        assert_eq!(parse_rfc3339("2016-02-29 09:00:00.123456789123-0800").unwrap().to_string(),
+                "2016-02-29T09:00:00.123456789-08:00");
+        assert!(parse_rfc3339("2018/10/01").is_err());
+    }
+
+    // -----------------------------------------------------------------
+    //
+    #[test]
+    fn test_compare() {
+        let a = parse_date_time("2018-10-01T09:00:00+09:00").unwrap();
+        let b = parse_date_time("2018-10-01T00:00:00Z").unwrap();
+        let c = parse_date_time("2018-10-01T00:00:00.5").unwrap();
+        assert_eq!(a.compare(&b), Ordering::Equal);
+        assert_eq!(a.compare(&c), Ordering::Less);
+        assert_eq!(c.compare(&b), Ordering::Greater);
+    }
+}
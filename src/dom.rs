@@ -17,20 +17,59 @@
 //!
 //! This processor does not care Directives &lt;!DOCTYPE ...&gt;,
 //! &lt;!ELEMENT ...&gt;, etc.
+//! A Directive in the prolog is kept as a child of the DocumentRoot
+//! node (NodeType::Directive) only to be serialized again;
+//! XPath does not see it.
 //!
 //! This processor accepts some illegal XML documents, like those
 //! that have more than one root elements.
 //! Sometimes it is convenient to accept such document temporally
 //! in the course of manipurating.
+//! NodePtr#validate_well_formedness() reports such problems,
+//! before serializing the document.
+//!
+//! For HTML-ish documents that are not well-formed XML,
+//! use new_html_document() instead of new_document().
 //!
 
-use std::cell::{Cell, RefCell};
+use std::cell::{Cell, Ref, RefCell, RefMut};
+use std::cmp::Ordering;
+use std::any::Any;
+use std::collections::HashMap;
+use std::collections::hash_map::DefaultHasher;
+use std::convert::TryFrom;
 use std::error::Error;
 use std::fmt;
+#[cfg(not(feature = "no_std"))]
+use std::fs;
+use std::hash::{Hash, Hasher};
+#[cfg(not(feature = "no_std"))]
+use std::io::Write;
+use std::mem;
+#[cfg(not(feature = "no_std"))]
+use std::path::Path;
+#[cfg(feature = "no_std")]
+use std::prelude::*;
+use std::str::FromStr;
 use std::rc::{Rc, Weak};
 use std::usize;
-use sax::{SaxDecoder, XmlToken};
+use project::{PathStep, compile_path, match_path};
+use sax::{SaxDecoder, XmlToken, is_ncname, is_qname, is_xml_char,
+          is_xml11_char, is_xml11_restricted_char};
+#[cfg(not(feature = "no_std"))]
+use sax::decode_xml_bytes;
+use escape::*;
+#[cfg(feature = "html-entities")]
+use html_entities::HTML4_ENTITIES;
 use xmlerror::*;
+use xpath_impl::eval::{match_pattern, match_xpath};
+use xpath_impl::parser::{XNodePtr, compile_xpath};
+#[cfg(feature = "regex")]
+use regex::Regex;
+#[cfg(feature = "serde")]
+pub use from_serde::{from_serde, MappingOptions};
+#[cfg(feature = "serde")]
+pub use to_serde::{to_serde, to_serde_with_options};
 
 // =====================================================================
 /// A node in the XML document tree.
@@ -47,8 +86,71 @@ fn wrap_rc_clone(rc_node: &RcNode) -> NodePtr {
 }
 
 // ---------------------------------------------------------------------
+// ノードの位置 (display_path())、名前、属性、テキストの冒頭を示す。
+// テキストの冒頭は、既定では40文字まで。精度で指定することもできる
+// (例: format!("{:.10?}", node))。
 //
 impl fmt::Debug for NodePtr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let max_chars = f.precision().unwrap_or(DEBUG_PREVIEW_CHARS);
+        let mut s = self.display_path();
+        match self.node_type() {
+            NodeType::DocumentRoot => {
+                s += &" (DocumentRoot)";
+            },
+            NodeType::Element => {
+                s += &format!(" <{}", self.name());
+                let attributes = self.attribute_nodes();
+                for at in attributes.iter().take(DEBUG_ATTRIBUTES) {
+                    s += &format!(r#" {}="{}""#,
+                            at.name(), preview_text(&at.value(), max_chars));
+                }
+                if DEBUG_ATTRIBUTES < attributes.len() {
+                    s += &format!(" ...(+{})", attributes.len() - DEBUG_ATTRIBUTES);
+                }
+                s += &">";
+                let text = self.text_prefix(max_chars + 1);
+                if text != "" {
+                    s += &format!(" {:?}", preview_text(&text, max_chars));
+                }
+            },
+            NodeType::Attribute => {
+                s += &format!(r#" {}="{}""#,
+                        self.name(), preview_text(&self.value(), max_chars));
+            },
+            NodeType::Instruction => {
+                s += &format!(" <?{}", self.name());
+                if self.value() != "" {
+                    s += &format!(" {:?}", preview_text(&self.value(), max_chars));
+                }
+                s += &"?>";
+            },
+            _ => {
+                s += &format!(" {:?}", preview_text(&self.value(), max_chars));
+            },
+        }
+        return write!(f, "NodePtr({})", s);
+    }
+}
+
+// Debug で示すテキストの冒頭の既定の文字数、および属性の最大個数。
+const DEBUG_PREVIEW_CHARS: usize = 40;
+const DEBUG_ATTRIBUTES: usize = 3;
+
+// ---------------------------------------------------------------------
+// 文字列の先頭 max_chars 文字。切り詰めた場合は末尾に「...」を付ける。
+//
+fn preview_text(s: &str, max_chars: usize) -> String {
+    match s.char_indices().nth(max_chars) {
+        Some((pos, _)) => return format!("{}...", &s[.. pos]),
+        None => return String::from(s),
+    }
+}
+
+// ---------------------------------------------------------------------
+// 要素などの文字列化: XPathの評価結果 (Sequence) もこの形で示す。
+//
+impl fmt::Display for NodePtr {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self.node_type() {
             NodeType::DocumentRoot => {
@@ -58,7 +160,7 @@ impl fmt::Debug for NodePtr {
                 let mut str = String::new();
                 str += &"<";
                 str += &self.name();
-                for at in self.attributes().iter() {
+                for at in self.attribute_nodes().iter() {
                     str += &format!(r#" {}="{}""#, at.name(), at.value());
                 }
                 str += &">";
@@ -70,6 +172,16 @@ impl fmt::Debug for NodePtr {
             NodeType::Attribute => {
                 return write!(f, r#"{}="{}""#, self.name(), self.value());
             },
+            NodeType::Comment => {
+                return write!(f, "<!--{}-->", escape_comment(&self.value()));
+            },
+            NodeType::Instruction => {
+                if self.value() == "" {
+                    return write!(f, "<?{}?>", self.name());
+                }
+                return write!(f, "<?{} {}?>", self.name(),
+                        escape_processing_instruction(&self.value()));
+            },
             _ => {
                 return write!(f, "");
             },
@@ -79,38 +191,277 @@ impl fmt::Debug for NodePtr {
 
 // ---------------------------------------------------------------------
 //
-impl fmt::Display for NodePtr {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        return write!(f, "{:?}", self);
+impl PartialEq for NodePtr {
+    fn eq(&self, other: &NodePtr) -> bool {
+        return Rc::ptr_eq(&self.rc_node, &other.rc_node)
     }
 }
 
+impl Eq for NodePtr {
+}
+
 // ---------------------------------------------------------------------
+// 同一のノードか否か (PartialEq) に合わせ、ノードのアドレスで
+// ハッシュ値を求める。
 //
-impl PartialEq for NodePtr {
-    fn eq(&self, other: &NodePtr) -> bool {
-        return Rc::ptr_eq(&self.rc_node, &other.rc_node)
+impl Hash for NodePtr {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.node_key().hash(state);
     }
 }
 
-impl Eq for NodePtr {
+// =====================================================================
+/// How namespace declarations are fixed up when a subtree is moved,
+/// for NodePtr#append_child_with_fixup() and NodePtr#replace_with_fixup().
+///
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum NamespaceFixup {
+    /// Moves the subtree as is; its prefixes may become unbound
+    /// (or bound to other URIs) in the new place.
+    None,
+    /// Declares, on the root element of the moved subtree, the
+    /// namespaces that the subtree uses but inherited from its
+    /// ancestors in the old place, unless the new place already
+    /// binds them to the same URIs.
+    DeclareMissing,
+}
+
+// =====================================================================
+/// Whether the names given to the mutation methods are checked,
+/// for NodePtr#set_attribute_with_check() and
+/// NodePtr#rename_attribute_with_check().
+///
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum NameCheck {
+    /// Rejects the name that is not a QName of Namespaces in XML.
+    Validate,
+    /// Accepts any name; the document may not be re-parsed
+    /// after serialized.
+    Bypass,
+}
+
+// =====================================================================
+/// How the attribute that appears more than once in a start tag is
+/// treated, for ParseOptions#duplicate_attributes().
+///
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum DuplicateAttributes {
+    /// Rejects the document, as the XML spec requires (default).
+    Error,
+    /// Keeps the first one, and ignores the others.
+    FirstWins,
+    /// Keeps the last one, and ignores the others.
+    LastWins,
+}
+
+impl Default for DuplicateAttributes {
+    fn default() -> DuplicateAttributes {
+        return DuplicateAttributes::Error;
+    }
+}
+
+// =====================================================================
+/// How the document type declaration (&lt;!DOCTYPE ...&gt;) is
+/// treated, for ParseOptions#doctype().
+///
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum DoctypePolicy {
+    /// Keeps it as a Directive node in the prolog, and uses the
+    /// attribute types declared in its internal subset (default).
+    Keep,
+    /// Drops it, without using the declarations in it.
+    Ignore,
+    /// Rejects the document that has it, for deployments that must
+    /// refuse DTDs entirely.
+    Reject,
+}
+
+impl Default for DoctypePolicy {
+    fn default() -> DoctypePolicy {
+        return DoctypePolicy::Keep;
+    }
+}
+
+// =====================================================================
+/// Version of XML whose character ranges are enforced,
+/// for ParseOptions#check_chars().
+///
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum XmlVersion {
+    /// XML 1.0 (Fifth Edition): control characters other than
+    /// tab, line feed and carriage return are not allowed at all.
+    Xml10,
+    /// XML 1.1: control characters other than U+0000 are allowed,
+    /// but the restricted ones only as character references.
+    Xml11,
+    /// Follows the version in the XML declaration of the document;
+    /// XML 1.0 if there is no XML declaration.
+    Declared,
 }
 
 // =====================================================================
 /// Type of node in the XML document tree.
 ///
+/// # Examples
+///
+/// ```
+/// use amxml::dom::*;
+/// let xml_string = r#"<!DOCTYPE article><article>DOM</article>"#;
+/// let doc = new_document(&xml_string).unwrap();
+/// assert_eq!(doc.node_type(), NodeType::DocumentRoot);
+/// assert_eq!(doc.root_element().name(), "article");
+/// assert_eq!(doc.children()[0].node_type(), NodeType::Directive);
+/// assert_eq!(doc.to_string(), xml_string);
+/// ```
+///
 #[derive(Debug, PartialEq, Clone)]
 pub enum NodeType {
+    /// The document node, at the top of the tree; its children are
+    /// the root element and the prolog (XML declaration, DOCTYPE,
+    /// comments, processing instructions).
+    /// This is what NodePtr#root() and XPath fn:root() return,
+    /// and what document-node() matches.
     DocumentRoot,
     Element,
     Text,
     Comment,
+    /// The XML declaration; not a node of the XPath data model.
     XMLDecl,
     Instruction,
     Attribute,
+    /// &lt;!DOCTYPE ...&gt; etc. in the prolog;
+    /// not a node of the XPath data model.
     Directive,
 }
 
+// =====================================================================
+/// Mutation of the document, notified to the observers registered
+/// by NodePtr#on_mutation().
+///
+#[derive(Debug, Clone)]
+pub enum MutationEvent {
+    /// The node (tree) is inserted as a child of the parent.
+    NodeInserted { parent: NodePtr, node: NodePtr },
+    /// The node (tree) is removed from the children of the parent.
+    NodeRemoved { parent: NodePtr, node: NodePtr },
+    /// The attribute of the element is added, updated or deleted.
+    /// old_value (new_value) is None if the attribute did not exist
+    /// before (does not exist after) the mutation.
+    AttributeChanged {
+        element: NodePtr,
+        name: String,
+        old_value: Option<String>,
+        new_value: Option<String>,
+    },
+    /// The text content of the element is replaced.
+    TextChanged { element: NodePtr },
+}
+
+// =====================================================================
+/// Which nodes are counted by NodePtr#hierarchical_number(),
+/// as the <code>level</code> attribute of xsl:number.
+///
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum NumberLevel {
+    /// The nearest ancestor-or-self that matches the pattern,
+    /// numbered among its siblings: e.g. "4".
+    Single,
+    /// Each ancestor-or-self that matches the pattern,
+    /// numbered among its siblings: e.g. "2.4.1".
+    Multiple,
+    /// The matching nodes anywhere before the node (including
+    /// ancestors and itself) in document order: e.g. "17".
+    Any,
+}
+
+type MutationObserver = Rc<Fn(&MutationEvent)>;
+
+// ---------------------------------------------------------------------
+// 変更の通知を受ける函数の一覧。
+//
+struct MutationObservers(RefCell<Vec<MutationObserver>>);
+
+impl fmt::Debug for MutationObservers {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        return write!(f, "MutationObservers({})", self.0.borrow().len());
+    }
+}
+
+// ---------------------------------------------------------------------
+// ノードに付けた注釈の表。
+// キーはノードのアドレス。解放されたノードのアドレスが再利用される
+// ことがあるので、Weak が同じノードを指しているかを確かめてから使う。
+//
+struct Annotations(RefCell<HashMap<usize, (Weak<Node>, Vec<(String, Rc<Any>)>)>>);
+
+impl fmt::Debug for Annotations {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        return write!(f, "Annotations({})", self.0.borrow().len());
+    }
+}
+
+// ---------------------------------------------------------------------
+// NodePtr#define_key() で定義した名前付きキー。
+// 索引 (キーの値 → ノード) は必要になったとき作り、
+// 文書が変更されたら捨てる。
+//
+struct NamedKey {
+    name: String,
+    match_xnode: XNodePtr,
+    use_xnode: XNodePtr,
+    index: Option<HashMap<String, Vec<NodePtr>>>,
+}
+
+struct NamedKeys(RefCell<Vec<NamedKey>>);
+
+impl fmt::Debug for NamedKeys {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        return write!(f, "NamedKeys({})", self.0.borrow().len());
+    }
+}
+
+// ---------------------------------------------------------------------
+// NodePtr#build_attr_index() で登録した、属性値 → 要素の索引。
+// 文書が変更されたら索引を捨て、次に引くときに作り直す。
+//
+struct AttrIndex {
+    attr_name: String,
+    index: Option<HashMap<String, Vec<NodePtr>>>,
+}
+
+struct AttrIndexes(RefCell<Vec<AttrIndex>>);
+
+impl fmt::Debug for AttrIndexes {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        return write!(f, "AttrIndexes({})", self.0.borrow().len());
+    }
+}
+
+// ---------------------------------------------------------------------
+// 文書 (木の根) ごとの表。木の根のノードにだけ、必要になったとき作る
+// (他のノードは None のまま)。
+//
+#[derive(Debug)]
+struct DocumentExtras {
+    observers: MutationObservers,
+    annotations: Annotations,
+    keys: NamedKeys,
+    attr_indexes: AttrIndexes,
+    document_uri: RefCell<Option<String>>,
+}
+
+impl DocumentExtras {
+    fn new() -> DocumentExtras {
+        return DocumentExtras {
+            observers: MutationObservers(RefCell::new(vec!{})),
+            annotations: Annotations(RefCell::new(HashMap::new())),
+            keys: NamedKeys(RefCell::new(vec!{})),
+            attr_indexes: AttrIndexes(RefCell::new(vec!{})),
+            document_uri: RefCell::new(None),
+        };
+    }
+}
+
 // =====================================================================
 //
 #[derive(Debug)]
@@ -121,7 +472,101 @@ struct Node {
     value: String,
     parent: Option<RefCell<Weak<Node>>>,
     children: RefCell<Vec<RcNode>>,
+    index: Cell<usize>,         // 親の children における位置 (親の children_indexed が true の場合のみ有効)
+    children_indexed: Cell<bool>,       // 子の index を振ってあるか否か
     attributes: RefCell<Vec<RcNode>>,
+    attribute_cache: RefCell<Option<Rc<Vec<NodePtr>>>>,    // attribute_nodes() の結果
+    extras: RefCell<Option<Box<DocumentExtras>>>,   // 木の根のみ
+    raw: Cell<bool>,            // テキストをエスケープせずに出力するか否か
+}
+
+// ---------------------------------------------------------------------
+// 深い木を (Rcの既定の動作で) 再帰的に解放すると、スタックを使い切る
+// ことがあるので、子孫を作業スタックに移してから1個ずつ解放する。
+// 他から参照されている子孫 (の部分木) は解放しない。
+//
+impl Drop for Node {
+    fn drop(&mut self) {
+        let mut stack: Vec<RcNode> = self.children.borrow_mut().drain(..).collect();
+        while let Some(rc_node) = stack.pop() {
+            if let Ok(node) = Rc::try_unwrap(rc_node) {
+                stack.extend(node.children.borrow_mut().drain(..));
+            }
+        }
+    }
+}
+
+// ---------------------------------------------------------------------
+// 属性の配列を変更する場合は、attributes.borrow_mut() ではなく
+// これを使い、attribute_nodes() のキャッシュを無効にする。
+//
+impl Node {
+    fn attributes_mut<'a>(&'a self) -> RefMut<'a, Vec<RcNode>> {
+        *self.attribute_cache.borrow_mut() = None;
+        return self.attributes.borrow_mut();
+    }
+
+    // -----------------------------------------------------------------
+    // 文書ごとの表 (木の根のノードで呼び出す)。まだなければ None。
+    //
+    fn extras<'a>(&'a self) -> Option<Ref<'a, DocumentExtras>> {
+        let extras = self.extras.borrow();
+        if extras.is_none() {
+            return None;
+        }
+        return Some(Ref::map(extras, |e| &**e.as_ref().unwrap()));
+    }
+
+    // -----------------------------------------------------------------
+    // 文書ごとの表 (木の根のノードで呼び出す)。まだなければ作る。
+    //
+    fn extras_or_new<'a>(&'a self) -> Ref<'a, DocumentExtras> {
+        if self.extras.borrow().is_none() {
+            *self.extras.borrow_mut() = Some(Box::new(DocumentExtras::new()));
+        }
+        return Ref::map(self.extras.borrow(), |e| &**e.as_ref().unwrap());
+    }
+
+    // -----------------------------------------------------------------
+    // 子の配列を変更する場合は、children.borrow_mut() ではなくこれを
+    // 使い、子の index を無効にする (次に child_position() を呼び出した
+    // ときに振り直す)。
+    //
+    fn children_mut<'a>(&'a self) -> RefMut<'a, Vec<RcNode>> {
+        self.children_indexed.set(false);
+        return self.children.borrow_mut();
+    }
+
+    // -----------------------------------------------------------------
+    // 子を末尾に追加する。他の子の index は変わらないので、
+    // 無効にしない。
+    //
+    fn push_child(&self, child: &RcNode) {
+        let mut children = self.children.borrow_mut();
+        child.index.set(children.len());
+        children.push(Rc::clone(child));
+    }
+
+    // -----------------------------------------------------------------
+    // 子 child の位置。子でなければ None。
+    // index が有効ならば O(1) で求まる。ただし、同じノードが複数の
+    // 親の子として共有されている場合など、index が他の親における
+    // 位置を指していることがあるので、確認してから使う。
+    //
+    fn child_position(&self, child: &RcNode) -> Option<usize> {
+        let children = self.children.borrow();
+        if ! self.children_indexed.get() {
+            for (i, ch) in children.iter().enumerate() {
+                ch.index.set(i);
+            }
+            self.children_indexed.set(true);
+        }
+        let i = child.index.get();
+        if i < children.len() && Rc::ptr_eq(&children[i], child) {
+            return Some(i);
+        }
+        return children.iter().position(|ch| Rc::ptr_eq(ch, child));
+    }
 }
 
 // ---------------------------------------------------------------------
@@ -140,7 +585,12 @@ fn make_new_rc_node(node_type: NodeType,
             None => None,
         },
         children: RefCell::new(vec!{}),
+        index: Cell::new(0),
+        children_indexed: Cell::new(false),
         attributes: RefCell::new(vec!{}),
+        attribute_cache: RefCell::new(None),
+        extras: RefCell::new(None),
+        raw: Cell::new(false),
     });
     return node;
 }
@@ -153,30 +603,251 @@ fn make_new_child_rc_node(node_type: NodeType, parent: &mut RcNode,
                 name: &str, value: &str, index_hint: usize) -> RcNode {
 
     let node = make_new_rc_node(node_type, Some(parent), name, value);
-    if index_hint <= parent.children.borrow_mut().len() {
-        parent.children.borrow_mut().insert(index_hint, Rc::clone(&node));
+    if index_hint < parent.children.borrow().len() {
+        parent.children_mut().insert(index_hint, Rc::clone(&node));
     } else {
-        parent.children.borrow_mut().push(Rc::clone(&node));
+        parent.push_child(&node);
     }
 
     return node;
 }
 
+// =====================================================================
+/// Options for new_document_with_options().
+///
+/// The default options accept the same documents as new_document(),
+/// which does not check the characters nor the encoding declaration,
+/// rejects duplicate attributes, and keeps the DOCTYPE.
+///
+#[derive(Debug, Clone, Default)]
+pub struct ParseOptions {
+    check_chars: Option<XmlVersion>,
+    encoding: Option<String>,
+    raw_attribute_values: bool,
+    duplicate_attributes: DuplicateAttributes,
+    doctype: DoctypePolicy,
+    document_uri: Option<String>,
+    entities: Vec<(String, String)>,
+}
+
+impl ParseOptions {
+    // -----------------------------------------------------------------
+    /// Returns the default options.
+    ///
+    pub fn new() -> ParseOptions {
+        return ParseOptions::default();
+    }
+
+    // -----------------------------------------------------------------
+    /// Rejects the document that contains characters not allowed in
+    /// the version of XML, whether written as is or as character
+    /// references, in text, attribute values, etc.
+    /// With XmlVersion::Xml10, the document that declares another
+    /// version is rejected as well.
+    ///
+    pub fn check_chars(mut self, version: XmlVersion) -> ParseOptions {
+        self.check_chars = Some(version);
+        return self;
+    }
+
+    // -----------------------------------------------------------------
+    /// Rejects the document whose encoding declaration, if any,
+    /// does not name the given encoding (compared case-insensitively).
+    /// Since the document is given as a string, that is, already
+    /// decoded, this should be the encoding actually used to decode
+    /// it, e.g. "UTF-8" if it was read by std::fs::read_to_string().
+    ///
+    pub fn encoding(mut self, encoding: &str) -> ParseOptions {
+        self.encoding = Some(String::from(encoding));
+        return self;
+    }
+
+    // -----------------------------------------------------------------
+    /// Keeps the attribute values as written (with references decoded),
+    /// instead of normalizing white space in them as the XML spec
+    /// requires; see the module document of sax.
+    /// This is the fidelity mode, for the documents whose attribute
+    /// values must be kept intact, such as line breaks in them.
+    ///
+    pub fn raw_attribute_values(mut self, raw: bool) -> ParseOptions {
+        self.raw_attribute_values = raw;
+        return self;
+    }
+
+    // -----------------------------------------------------------------
+    /// Sets how duplicate attributes in a start tag are treated.
+    ///
+    pub fn duplicate_attributes(mut self, policy: DuplicateAttributes) -> ParseOptions {
+        self.duplicate_attributes = policy;
+        return self;
+    }
+
+    // -----------------------------------------------------------------
+    /// Sets how the document type declaration is treated.
+    ///
+    pub fn doctype(mut self, policy: DoctypePolicy) -> ParseOptions {
+        self.doctype = policy;
+        return self;
+    }
+
+    // -----------------------------------------------------------------
+    /// Records the URI of the document, which becomes its document URI
+    /// and base URI; see NodePtr#document_uri() and NodePtr#base_uri().
+    ///
+    pub fn document_uri(mut self, uri: &str) -> ParseOptions {
+        self.document_uri = Some(String::from(uri));
+        return self;
+    }
+
+    // -----------------------------------------------------------------
+    /// Registers the named entity besides the five predefined in XML,
+    /// so that <code>&amp;name;</code> in text and attribute values is
+    /// replaced by the value. cf. SaxDecoder#add_entity()
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use amxml::dom::*;
+    /// let options = ParseOptions::new().entity("product", "amxml");
+    /// let doc = new_document_with_options("<p>&product; &amp; &unknown;</p>", &options).unwrap();
+    /// assert_eq!(doc.to_string(), "<p>amxml &amp; &amp;unknown;</p>");
+    /// ```
+    ///
+    pub fn entity(mut self, name: &str, value: &str) -> ParseOptions {
+        self.entities.retain(|e| e.0 != name);
+        self.entities.push((String::from(name), String::from(value)));
+        return self;
+    }
+
+    // -----------------------------------------------------------------
+    /// Registers the character entities of HTML 4, such as
+    /// <code>&amp;nbsp;</code> and <code>&amp;eacute;</code>
+    /// (feature "html-entities"). cf. html_entities
+    ///
+    #[cfg(feature = "html-entities")]
+    pub fn html_entities(mut self) -> ParseOptions {
+        for (name, ch) in HTML4_ENTITIES.iter() {
+            self = self.entity(name, &ch.to_string());
+        }
+        return self;
+    }
+}
+
 // =====================================================================
 /// Parses the XML string and creates the DOM tree and
 /// returns the topmost DocumentRoot node.
 ///
+/// The string can also be parsed by <code>str::parse()</code>
+/// (NodePtr implements FromStr) or <code>NodePtr::try_from()</code>.
+///
 /// # Examples
 ///
-/// See the module document.
+/// See also the module document.
+///
+/// ```
+/// use std::convert::TryFrom;
+/// use amxml::dom::*;
+/// let doc: NodePtr = "<root><a/></root>".parse().unwrap();
+/// assert_eq!(doc.root_element().name(), "root");
+/// let doc = NodePtr::try_from("<root><b/></root>").unwrap();
+/// assert_eq!(doc.to_string(), "<root><b/></root>");
+/// assert!("<root></a>".parse::<NodePtr>().is_err());
+/// ```
 ///
 /// # Errors
 ///
 /// - When there is syntax error, e.g. "&lt;foo&gt;xxx&lt;/bar&gt;".
+///   The error is XmlError with the position (line and column)
+///   where the error was detected: see XmlError#position().
+/// - When an attribute appears more than once in a start tag.
 ///
 pub fn new_document(xml_string: &str) -> Result<NodePtr, Box<Error>> {
+    return new_document_with_options(xml_string, &ParseOptions::new());
+}
+
+// =====================================================================
+/// Parses the XML string as new_document() does, and checks the
+/// conformance to the W3C Recommendations as specified by the options.
+///
+/// # Examples
+///
+/// ```
+/// use amxml::dom::*;
+/// let xml = "<a>\u{1}</a>";
+/// assert!(new_document(xml).is_ok());
+/// let options = ParseOptions::new().check_chars(XmlVersion::Xml10);
+/// assert!(new_document_with_options(xml, &options).is_err());
+/// assert!(new_document_with_options("<a v='&#x1;'/>", &options).is_err());
+///
+/// let options = ParseOptions::new().check_chars(XmlVersion::Declared);
+/// let xml = r#"<?xml version="1.1"?><a>&#x1;</a>"#;
+/// assert!(new_document_with_options(xml, &options).is_ok());
+/// let xml = "<?xml version=\"1.1\"?><a>\u{1}</a>";
+/// assert!(new_document_with_options(xml, &options).is_err());
+///
+/// let options = ParseOptions::new().encoding("UTF-8");
+/// let xml = r#"<?xml version="1.0" encoding="Shift_JIS"?><a/>"#;
+/// assert!(new_document_with_options(xml, &options).is_err());
+/// let xml = r#"<?xml version="1.0" encoding="utf-8"?><a/>"#;
+/// assert!(new_document_with_options(xml, &options).is_ok());
+///
+/// let xml = "<a v='1\n2'/>";
+/// let doc = new_document(xml).unwrap();
+/// assert_eq!(doc.root_element().attribute_value("v").unwrap(), "1 2");
+/// let options = ParseOptions::new().raw_attribute_values(true);
+/// let doc = new_document_with_options(xml, &options).unwrap();
+/// assert_eq!(doc.root_element().attribute_value("v").unwrap(), "1\n2");
+/// assert_eq!(doc.to_string(), "<a v=\"1&#xA;2\"/>");
+///
+/// let xml = r#"<a v="1" v="2"/>"#;
+/// assert!(new_document(xml).is_err());
+/// let options = ParseOptions::new().duplicate_attributes(DuplicateAttributes::LastWins);
+/// let doc = new_document_with_options(xml, &options).unwrap();
+/// assert_eq!(doc.to_string(), r#"<a v="2"/>"#);
+/// let options = ParseOptions::new().duplicate_attributes(DuplicateAttributes::FirstWins);
+/// let doc = new_document_with_options(r#"<a v="1" w="0" v="2"/>"#, &options).unwrap();
+/// assert_eq!(doc.to_string(), r#"<a v="1" w="0"/>"#);
+///
+/// let xml = r#"<!DOCTYPE a [ <!ATTLIST a t NMTOKENS #IMPLIED> ]><a t=" x  y "/>"#;
+/// let options = ParseOptions::new().doctype(DoctypePolicy::Keep);
+/// let doc = new_document_with_options(xml, &options).unwrap();
+/// assert_eq!(doc.to_string(),
+///         r#"<!DOCTYPE a [ <!ATTLIST a t NMTOKENS #IMPLIED> ]><a t="x y"/>"#);
+/// let options = ParseOptions::new().doctype(DoctypePolicy::Ignore);
+/// let doc = new_document_with_options(xml, &options).unwrap();
+/// assert_eq!(doc.root_element().attribute_value("t").unwrap(), " x  y ");
+///
+/// let xml = r#"<!DOCTYPE a [ <!ENTITY e "x"> ]><a/>"#;
+/// let options = ParseOptions::new().doctype(DoctypePolicy::Reject);
+/// assert!(new_document_with_options(xml, &options).is_err());
+/// let options = ParseOptions::new().doctype(DoctypePolicy::Ignore);
+/// let doc = new_document_with_options(xml, &options).unwrap();
+/// assert_eq!(doc.to_string(), "<a/>");
+///
+/// let options = ParseOptions::new().document_uri("http://example.com/a.xml");
+/// let doc = new_document_with_options("<a/>", &options).unwrap();
+/// assert_eq!(doc.document_uri().unwrap(), "http://example.com/a.xml");
+/// ```
+///
+/// # Errors
+///
+/// - When there is syntax error, e.g. "&lt;foo&gt;xxx&lt;/bar&gt;".
+/// - When the document contains a character not allowed in
+///   the version of XML.
+/// - When the encoding declaration does not match the encoding.
+/// - When an attribute appears more than once in a start tag,
+///   unless the options allow it.
+/// - When there is the DOCTYPE, and the options reject it.
+///
+pub fn new_document_with_options(xml_string: &str,
+                options: &ParseOptions) -> Result<NodePtr, Box<Error>> {
 
     let mut dec = SaxDecoder::new(&String::from(xml_string))?;
+    dec.set_normalize_attributes(! options.raw_attribute_values);
+    dec.set_ignore_doctype(options.doctype != DoctypePolicy::Keep);
+    for (name, value) in options.entities.iter() {
+        dec.add_entity(name, value);
+    }
 
     let doc_root = make_new_rc_node(NodeType::DocumentRoot, None, "", "");
     let mut curr_node = Rc::clone(&doc_root);
@@ -191,17 +862,36 @@ pub fn new_document(xml_string: &str) -> Result<NodePtr, Box<Error>> {
                             name.as_str(), "", usize::MAX);
                 curr_node = Rc::clone(&e);
                 for at in attr.iter() {
+                    let dup = curr_node.attributes.borrow().iter()
+                                .position(|a| a.name == at.name());
+                    if let Some(i) = dup {
+                        match options.duplicate_attributes {
+                            DuplicateAttributes::Error => {
+                                let (line, column) = dec.position();
+                                return Err(xml_syntax_error_at(line, column, &format!(
+                                    "Duplicated attribute: {} in {}",
+                                    at.name(), name)));
+                            },
+                            DuplicateAttributes::FirstWins => {
+                                continue;
+                            },
+                            DuplicateAttributes::LastWins => {
+                                curr_node.attributes_mut().remove(i);
+                            },
+                        }
+                    }
                     let attr_node = make_new_rc_node(NodeType::Attribute,
                             Some(&mut curr_node), at.name(), at.value());
-                    curr_node.attributes.borrow_mut().push(
+                    curr_node.attributes_mut().push(
                             Rc::clone(&attr_node));
                 }
             },
             Ok(XmlToken::EndElement{name}) => {
                 if curr_node.name.as_str() != name {
-                    return Err(xml_syntax_error!(
+                    let (line, column) = dec.position();
+                    return Err(xml_syntax_error_at(line, column, &format!(
                         "Element name mismatch: {} and {}",
-                        curr_node.name.as_str(), name));
+                        curr_node.name.as_str(), name)));
                 }
                 curr_node = match curr_node.parent {
                     Some(ref p) => p.borrow().upgrade().unwrap(),
@@ -230,785 +920,5097 @@ pub fn new_document(xml_string: &str) -> Result<NodePtr, Box<Error>> {
                             &mut curr_node,
                             "", comment.as_str(), usize::MAX);
             },
-            Ok(XmlToken::Directive{directive: _directive}) => {},
+            Ok(XmlToken::Directive{directive}) => {
+                if directive.starts_with("<!DOCTYPE") {
+                    match options.doctype {
+                        DoctypePolicy::Keep => {},
+                        DoctypePolicy::Ignore => continue,
+                        DoctypePolicy::Reject => {
+                            let (line, column) = dec.position();
+                            return Err(xml_syntax_error_at(line, column,
+                                "DOCTYPE is not allowed"));
+                        },
+                    }
+                }
+                add_prolog_directive(&mut curr_node, &directive);
+            },
             Err(e) => {
-                return Err(xml_syntax_error!("XML syntax error: {}", e));
+                let (line, column) = dec.position();
+                return Err(xml_syntax_error_at(line, column,
+                    &format!("XML syntax error: {}", e)));
             },
         }
     }
-    return Ok(NodePtr{rc_node: doc_root});
+    let doc = NodePtr{rc_node: doc_root};
+    check_conformance(xml_string, &doc, options)?;
+    if let Some(ref uri) = options.document_uri {
+        doc.set_document_uri(uri);
+    }
+    return Ok(doc);
+}
+
+// =====================================================================
+/// Reads the XML file, parses it as new_document() does, and records
+/// the file URI (<code>file://</code> and the absolute path) as the
+/// document URI.
+///
+/// # Examples
+///
+/// ```
+/// use amxml::dom::*;
+/// let path = std::env::temp_dir().join("amxml_new_document_from_file.xml");
+/// std::fs::write(&path, "<a xml:base='sub/'><b/></a>").unwrap();
+/// let doc = new_document_from_file(&path.to_string_lossy()).unwrap();
+/// assert!(doc.document_uri().unwrap().starts_with("file:///"));
+/// assert!(doc.get_first_node("//b").unwrap().base_uri().unwrap()
+///             .ends_with("/sub/"));
+///
+/// // UTF-16 with BOM; NodePtr::try_from() takes the path as well.
+/// use std::convert::TryFrom;
+/// let utf16: Vec<u8> = [0xFEFF].iter().chain("<a>\u{3042}</a>".encode_utf16().collect::<Vec<u16>>().iter())
+///     .flat_map(|u| vec![(*u & 0xFF) as u8, (*u >> 8) as u8]).collect();
+/// std::fs::write(&path, utf16).unwrap();
+/// let doc = NodePtr::try_from(path.as_path()).unwrap();
+/// assert_eq!(doc.to_string(), "<a>\u{3042}</a>");
+/// std::fs::remove_file(&path).unwrap();
+/// ```
+///
+/// # Errors
+///
+/// - When the file can't be read, or is not encoded in UTF-8 or
+///   UTF-16 (see sax::decode_xml_bytes()).
+/// - When there is syntax error, e.g. "&lt;foo&gt;xxx&lt;/bar&gt;".
+///
+#[cfg(not(feature = "no_std"))]
+pub fn new_document_from_file(path: &str) -> Result<NodePtr, Box<Error>> {
+    return read_document_file(Path::new(path));
 }
 
 // ---------------------------------------------------------------------
+// new_document_from_file() と NodePtr::try_from(&Path) の本体。
+// 符号化方式は BOM などから判別する (UTF-8 または UTF-16)。
 //
-fn shallow_copy_rc_rels(target: &mut RcNode, source: &RcNode) {
-    for ch in source.children.borrow().iter() {
-        target.children.borrow_mut().push(Rc::clone(ch));
-    }
-    for at in source.attributes.borrow().iter() {
-        target.attributes.borrow_mut().push(Rc::clone(at));
+#[cfg(not(feature = "no_std"))]
+fn read_document_file(path: &Path) -> Result<NodePtr, Box<Error>> {
+    let xml_string = decode_xml_bytes(fs::read(path)?)?;
+    let abs_path = fs::canonicalize(path)?;
+    let mut uri = String::from("file://");
+    for (i, seg) in abs_path.to_string_lossy().replace('\\', "/").split('/').enumerate() {
+        if i != 0 || ! seg.is_empty() {
+            uri += "/";
+        }
+        uri += &percent_encode_path_segment(seg);
     }
+    let options = ParseOptions::new().document_uri(&uri);
+    return new_document_with_options(&xml_string, &options);
 }
 
-// =====================================================================
+// ---------------------------------------------------------------------
+// 文字列、ファイルのパスからの文書の構築。cf. new_document()
 //
-impl NodePtr {
-
-    // =================================================================
-    /// Turns XML DOM tree into XML string. cf. inner_xml()
-    ///
-    /// # Examples
-    ///
-    /// ```
-    /// use amxml::dom::*;
-    /// let xml_string = r#"<?xml version="1.0"?><article>About <em>XML</em> string</article>"#;
-    /// let doc = new_document(&xml_string).unwrap();
-    /// let root_elem = doc.root_element();
-    /// let result = root_elem.to_string();
-    /// assert_eq!(result, "<article>About <em>XML</em> string</article>");
-    /// ```
-    ///
-    pub fn to_string(&self) -> String {
-        return to_string_with_indent(&self.unwrap_rc(), 0, 0);
+impl FromStr for NodePtr {
+    type Err = Box<Error>;
+    fn from_str(xml_string: &str) -> Result<NodePtr, Box<Error>> {
+        return new_document(xml_string);
     }
+}
 
-    // =================================================================
-    /// Turns XML DOM tree into 'pretty' XML string with four spaces indent.
-    ///
-    /// # Examples
-    ///
-    /// ```
-    /// use amxml::dom::*;
-    /// let xml_string = r#"<?xml version="1.0"?><article>About <em>XML</em> string</article>"#;
-    /// let doc = new_document(&xml_string).unwrap();
-    /// let root_elem = doc.root_element();
-    /// let result = root_elem.to_pretty_string();
-    /// let guess = r#"<article>
-    ///     About 
-    ///     <em>
-    ///         XML
-    ///     </em>
-    ///      string
-    /// </article>
-    /// "#;
-    /// assert_eq!(result, guess);
-    /// ```
-    ///
-    pub fn to_pretty_string(&self) -> String {
-        return to_string_with_indent(&self.unwrap_rc(), 0, 4);
+impl<'a> TryFrom<&'a str> for NodePtr {
+    type Error = Box<Error>;
+    fn try_from(xml_string: &'a str) -> Result<NodePtr, Box<Error>> {
+        return new_document(xml_string);
     }
+}
 
-    // =================================================================
-    /// Turns XML DOM tree under self into XML string. cf. to_string()
-    ///
-    /// # Examples
-    ///
-    /// ```
-    /// use amxml::dom::*;
-    /// let xml_string = r#"<?xml version="1.0"?><article>About <em>XML</em> string</article>"#;
-    /// let doc = new_document(&xml_string).unwrap();
-    /// let root_elem = doc.root_element();
-    /// let result = root_elem.inner_xml();
-    /// assert_eq!(result, "About <em>XML</em> string");
-    /// ```
-    ///
-    pub fn inner_xml(&self) -> String {
-        let mut s = String::new();
-        for ch in self.children().iter() {
-            s += &ch.to_string();
-        }
-        return s;
+#[cfg(not(feature = "no_std"))]
+impl<'a> TryFrom<&'a Path> for NodePtr {
+    type Error = Box<Error>;
+    fn try_from(path: &'a Path) -> Result<NodePtr, Box<Error>> {
+        return read_document_file(path);
     }
+}
 
-    // =================================================================
-    /// Returns type of the node (NodeType::Element, etc.).
-    //
-    pub fn node_type(&self) -> NodeType {
-        return self.unwrap_rc().node_type.clone();
+// ---------------------------------------------------------------------
+// URIのパスの1区間として使えない文字を、%XX の形に符号化する。
+//
+#[cfg(not(feature = "no_std"))]
+fn percent_encode_path_segment(seg: &str) -> String {
+    let mut encoded = String::new();
+    for b in seg.bytes() {
+        match b {
+            b'A' ..= b'Z' | b'a' ..= b'z' | b'0' ..= b'9' |
+            b'-' | b'.' | b'_' | b'~' | b'!' | b'$' | b'&' | b'\'' |
+            b'(' | b')' | b'*' | b'+' | b',' | b';' | b'=' | b':' | b'@' => {
+                encoded.push(b as char);
+            },
+            _ => {
+                encoded += &format!("%{:02X}", b);
+            },
+        }
     }
+    return encoded;
+}
 
-    // =================================================================
-    /// Returns the name of the Element/Attribute node,
-    /// or the target of the Instruction node.
-    ///
-    /// # Examples
-    ///
-    /// ```
-    /// use amxml::dom::*;
-    /// let xml_string = r#"<ns:article>XML</ns:article>"#;
-    /// let doc = new_document(&xml_string).unwrap();
-    /// let root_elem = doc.root_element();
-    /// assert_eq!(root_elem.name(), "ns:article");
-    /// ```
-    ///
-    pub fn name(&self) -> String {
-        return self.unwrap_rc().name.clone();
-    }
+// ---------------------------------------------------------------------
+// ParseOptions で指定された適合性を検査する。
+// 文字参照として書かれた文字は、構築した木のテキストや属性値で、
+// そのまま書かれた文字は、元の文字列で検査する。
+//
+fn check_conformance(xml_string: &str, doc: &NodePtr,
+                options: &ParseOptions) -> Result<(), Box<Error>> {
 
-    // =================================================================
-    /// Returns the value of the Attribute node,
-    /// text of the Text/Comment node,
-    /// inst of XMLDecl/Instruction node.
-    ///
-    /// # Examples
-    ///
-    /// ```
-    /// use amxml::dom::*;
-    /// let xml_string = r#"<ns:article>XML</ns:article>"#;
-    /// let doc = new_document(&xml_string).unwrap();
-    /// let text_node = doc.get_first_node("//text()").unwrap();
-    /// assert_eq!(text_node.value(), "XML");
-    /// ```
-    ///
-    pub fn value(&self) -> String {
-        return self.unwrap_rc().value.clone();
-    }
+    let xml_decl = match doc.first_child() {
+        Some(ref ch) if ch.node_type() == NodeType::XMLDecl => ch.value(),
+        _ => String::new(),
+    };
 
-    // =================================================================
-    /// Returns the local name of Element.
-    ///
-    /// # Examples
-    ///
-    /// ```
-    /// use amxml::dom::*;
-    /// let xml_string = r#"<ns:article>foo</ns:article>"#;
-    /// let doc = new_document(&xml_string).unwrap();
-    /// let root_elem = doc.root_element();
-    /// assert_eq!(root_elem.local_name(), "article");
-    /// ```
-    ///
-    pub fn local_name(&self) -> String {
-        let name = &self.unwrap_rc().name;
-        let v: Vec<&str> = name.splitn(2, ":").collect();
-        if v.len() == 2 {
-            return String::from(v[1]);
-        } else {
-            return name.clone();
+    if let Some(ref encoding) = options.encoding {
+        if let Some(declared) = xml_decl_pseudo_attr(&xml_decl, "encoding") {
+            if ! declared.eq_ignore_ascii_case(encoding) {
+                return Err(xml_syntax_error!(
+                    "Encoding declaration {} does not match the encoding {}",
+                    declared, encoding));
+            }
         }
     }
 
-    // =================================================================
-    /// Returns the space name of Element, or "" if not with namespace.
-    ///
-    /// # Examples
-    ///
-    /// ```
-    /// use amxml::dom::*;
-    /// let xml_string = r#"<ns:article>foo</ns:article>"#;
-    /// let doc = new_document(&xml_string).unwrap();
-    /// let root_elem = doc.root_element();
-    /// assert_eq!(root_elem.space_name(), "ns");
-    /// ```
-    ///
-    pub fn space_name(&self) -> String {
-        let name = &self.unwrap_rc().name;
-        let v: Vec<&str> = name.splitn(2, ":").collect();
-        if v.len() == 2 {
-            return String::from(v[0]);
+    let declared_version = xml_decl_pseudo_attr(&xml_decl, "version");
+    let xml11 = match options.check_chars {
+        None => return Ok(()),
+        Some(XmlVersion::Xml10) => {
+            if let Some(ref v) = declared_version {
+                if v != "1.0" {
+                    return Err(xml_syntax_error!(
+                        "Document declares XML version {}", v));
+                }
+            }
+            false
+        },
+        Some(XmlVersion::Xml11) => true,
+        Some(XmlVersion::Declared) => declared_version == Some(String::from("1.1")),
+    };
+
+    for (pos, ch) in xml_string.char_indices() {
+        let allowed = if xml11 {
+            is_xml11_char(ch) && ! is_xml11_restricted_char(ch)
         } else {
-            return String::new();
+            is_xml_char(ch)
+        };
+        if ! allowed {
+            return Err(xml_syntax_error!(
+                "Invalid character U+{:04X} at byte {}", ch as u32, pos));
         }
     }
 
-    // =================================================================
-    /// Returns the URI for namespace.
-    ///
-    /// # Examples
-    ///
-    /// ```
-    /// use amxml::dom::*;
-    /// let xml_string = r#"<root xmlns="http://def" xmlns:ns="http://ns"><ns:a/><b/></root>"#;
-    /// let doc = new_document(&xml_string).unwrap();
-    /// let root = doc.root_element();
-    /// let elem_ns_a = doc.get_first_node("//ns:a").unwrap();
-    /// let elem_b = doc.get_first_node("//b").unwrap();
-    /// assert_eq!(elem_ns_a.namespace_uri(), "http://ns");
-    /// assert_eq!(elem_b.namespace_uri(), "http://def");
-    /// ```
-    ///
-    pub fn namespace_uri(&self) -> String {
-        let mut xmlns_attr = String::from("xmlns");
-        let space = self.space_name();
-        if space.as_str() != "" {
-            xmlns_attr += &":";
-            xmlns_attr += &space;
+    let is_allowed = |ch: char| if xml11 { is_xml11_char(ch) } else { is_xml_char(ch) };
+    let mut stack: Vec<NodePtr> = vec!{doc.rc_clone()};
+    while let Some(node) = stack.pop() {
+        if node.node_type() == NodeType::Text {
+            if let Some(ch) = node.value().chars().find(|c| ! is_allowed(*c)) {
+                return Err(xml_syntax_error!(
+                    "Invalid character U+{:04X} in text", ch as u32));
+            }
         }
-
-        let mut curr = self.unwrap_rc();
-        while (*curr).node_type != NodeType::DocumentRoot {
-            let val = wrap_rc_clone(&curr).attribute_value(xmlns_attr.as_str());
-            if let Some(value) = val {
-                return value.clone();
+        for at in node.attribute_nodes().iter() {
+            if let Some(ch) = at.value().chars().find(|c| ! is_allowed(*c)) {
+                return Err(xml_syntax_error!(
+                    "Invalid character U+{:04X} in attribute {}",
+                    ch as u32, at.name()));
             }
-            curr = match (*curr).parent {
-                Some(ref p) => p.borrow().upgrade().unwrap(),
-                None => return String::new(),
-            };
         }
-        return String::new();
+        stack.extend(node.children());
     }
+    return Ok(());
+}
 
-    // =================================================================
-    /// Returns the root (topmost) node of DOM tree.
-    ///
-    /// # Examples
-    ///
-    /// ```
-    /// use amxml::dom::*;
-    /// let xml_string = r#"<article><p>DOM</p></article>"#;
-    /// let doc = new_document(&xml_string).unwrap();
-    /// let elem_p = doc.get_first_node("/article/p").unwrap();
-    /// assert_eq!(elem_p.name(), "p");
-    /// let root = elem_p.root();
-    /// assert_eq!(root.node_type(), NodeType::DocumentRoot);
-    /// ```
-    ///
-    pub fn root(&self) -> NodePtr {
-        let mut curr = self.unwrap_rc();
-        loop {
-            curr = match (*curr).parent {
-                Some(ref p) => p.borrow().upgrade().unwrap(),
-                None => return wrap_rc_clone(&curr),
-            };
+// ---------------------------------------------------------------------
+// 名前付きキーの索引を作る。
+// 文書 (doc) のノード (属性を含む) を文書順にたどり、match_xnode に
+// 合致するものについて use_xnode を評価して、その値ごとに登録する。
+//
+fn build_key_index(doc: &NodePtr, match_xnode: &XNodePtr,
+            use_xnode: &XNodePtr) -> Result<HashMap<String, Vec<NodePtr>>, Box<Error>> {
+    let mut index: HashMap<String, Vec<NodePtr>> = HashMap::new();
+    let mut stack: Vec<NodePtr> = vec!{doc.rc_clone()};
+    while let Some(node) = stack.pop() {
+        let mut nodes = vec!{node.rc_clone()};
+        nodes.extend(node.attributes());
+        for n in nodes.iter() {
+            if ! match_pattern(n, match_xnode)? {
+                continue;
+            }
+            let values = match_xpath(n, use_xnode)?.atomize();
+            for item in values.iter() {
+                let value = item.cast_as("xs:string")?.get_as_raw_string()?;
+                let entry = index.entry(value).or_insert_with(|| vec!{});
+                if entry.last() != Some(n) {
+                    entry.push(n.rc_clone());
+                }
+            }
         }
+        let mut children = node.children();
+        children.reverse();
+        stack.extend(children);
     }
+    return Ok(index);
+}
 
-    // =================================================================
-    /// Returns the topmost Element node,
-    /// or root node when there is no Element node (illegal case),
-    ///
-    /// # Examples
-    ///
-    /// ```
-    /// use amxml::dom::*;
-    /// let xml_string = r#"<article><p>DOM</p></article>"#;
-    /// let doc = new_document(&xml_string).unwrap();
-    /// let elem_p = doc.get_first_node("/article/p").unwrap();
-    /// assert_eq!(elem_p.name(), "p");
-    /// let root_elem = elem_p.root_element();
-    /// assert_eq!(root_elem.name(), "article");
-    /// ```
-    ///
-    pub fn root_element(&self) -> NodePtr {
-        let doc_root = self.root();
-        for ch in doc_root.children().iter() {
-            if ch.node_type() == NodeType::Element {
-                return ch.clone();
-            }
+// ---------------------------------------------------------------------
+// NodePtr#build_attr_index() の索引を作る: 属性 attr_name をもつ要素を
+// 文書順にたどり、属性値ごとに集める。
+//
+fn build_attr_value_index(doc: &NodePtr, attr_name: &str) -> HashMap<String, Vec<NodePtr>> {
+    let mut index: HashMap<String, Vec<NodePtr>> = HashMap::new();
+    let mut stack: Vec<NodePtr> = vec!{doc.rc_clone()};
+    while let Some(node) = stack.pop() {
+        if let Some(value) = node.attribute_value(attr_name) {
+            index.entry(value).or_insert_with(|| vec!{}).push(node.rc_clone());
         }
-        return doc_root;
+        let mut children = node.children();
+        children.reverse();
+        stack.extend(children);
     }
+    return index;
+}
 
-    // =================================================================
-    /// Returns the parent of the 'node', or None
-    /// if 'node' has no parent (i.e. is DocumentRoot).
-    ///
-    /// # Examples
-    ///
-    /// ```
-    /// use amxml::dom::*;
-    /// let xml_string = r#"<article><chapter>foo</chapter></article>"#;
+// ---------------------------------------------------------------------
+// NodePtr#sort_children_by() の整列キー。
+//
+#[derive(Debug, Clone)]
+enum SortKey {
+    Number(f64),
+    Str(String),
+}
+
+// ---------------------------------------------------------------------
+// node を文脈ノードとして key_xnode を評価した結果の最初の項目を、
+// 整列キーとする。空ならば空文字列。
+//
+fn sort_key(node: &NodePtr, key_xnode: &XNodePtr) -> Result<SortKey, Box<Error>> {
+    let values = match_xpath(node, key_xnode)?.atomize();
+    if values.is_empty() {
+        return Ok(SortKey::Str(String::new()));
+    }
+    let item = values.get_item(0);
+    if item.is_numeric() {
+        return Ok(SortKey::Number(item.get_as_raw_double()?));
+    }
+    return Ok(SortKey::Str(item.cast_as("xs:string")?.get_as_raw_string()?));
+}
+
+// ---------------------------------------------------------------------
+// 数値は数値として比較し (NaN は最小)、文字列より前とする。
+// 文字列は符号位置の順に比較する。
+//
+fn compare_sort_keys(a: &SortKey, b: &SortKey) -> Ordering {
+    match (a, b) {
+        (&SortKey::Number(x), &SortKey::Number(y)) => {
+            match (x.is_nan(), y.is_nan()) {
+                (true, true) => return Ordering::Equal,
+                (true, false) => return Ordering::Less,
+                (false, true) => return Ordering::Greater,
+                _ => return x.partial_cmp(&y).unwrap_or(Ordering::Equal),
+            }
+        },
+        (&SortKey::Number(_), &SortKey::Str(_)) => return Ordering::Less,
+        (&SortKey::Str(_), &SortKey::Number(_)) => return Ordering::Greater,
+        (&SortKey::Str(ref x), &SortKey::Str(ref y)) => return x.cmp(y),
+    }
+}
+
+// ---------------------------------------------------------------------
+// XML宣言 (version="1.0" encoding="UTF-8" など) から、
+// 擬似属性の値を取り出す。
+//
+fn xml_decl_pseudo_attr(xml_decl: &str, name: &str) -> Option<String> {
+    return xml_decl_pseudo_attr_range(xml_decl, name)
+            .map(|(start, end)| String::from(&xml_decl[start .. end]));
+}
+
+// ---------------------------------------------------------------------
+// XML宣言の擬似属性の値 (引用符の内側) の位置 (開始、終了)。
+//
+fn xml_decl_pseudo_attr_range(xml_decl: &str, name: &str) -> Option<(usize, usize)> {
+    let mut offset = 0;
+    while let Some(pos) = xml_decl[offset ..].find(name) {
+        let before_ok = xml_decl[.. offset + pos].chars().last()
+                .map_or(true, |c| c.is_whitespace());
+        offset += pos + name.len();
+        if ! before_ok {
+            continue;
+        }
+        let rest = &xml_decl[offset ..];
+        let value = rest.trim_start();
+        if ! value.starts_with("=") {
+            continue;
+        }
+        let value = value[1 ..].trim_start();
+        let quote = match value.chars().next() {
+            Some(q) if q == '"' || q == '\'' => q,
+            _ => continue,
+        };
+        if let Some(end) = value[1 ..].find(quote) {
+            let start = offset + (rest.len() - value.len()) + 1;
+            return Some((start, start + end));
+        }
+    }
+    return None;
+}
+
+// ---------------------------------------------------------------------
+// XML宣言の符号化宣言 (encoding="...") を encoding に置き換える。
+// 符号化宣言がなければ、版情報 (version="...") の後に追加する。
+//
+fn xml_decl_with_encoding(xml_decl: &str, encoding: &str) -> String {
+    if let Some((start, end)) = xml_decl_pseudo_attr_range(xml_decl, "encoding") {
+        return format!("{}{}{}", &xml_decl[.. start], encoding, &xml_decl[end ..]);
+    }
+    if let Some((_, end)) = xml_decl_pseudo_attr_range(xml_decl, "version") {
+        return format!(r#"{} encoding="{}"{}"#,
+                &xml_decl[.. end + 1], encoding, &xml_decl[end + 1 ..]);
+    }
+    return format!(r#"version="1.0" encoding="{}" {}"#, encoding, xml_decl.trim());
+}
+
+// =====================================================================
+/// Parses the XML string, building the DOM nodes only for the
+/// subtrees whose root element matches the path, and returns
+/// the synthetic DocumentRoot node that has these subtrees as its
+/// children, in document order.
+/// Everything else is skipped by the streaming parser, so that
+/// a huge document can be processed with memory bounded by the
+/// size of the selected subtrees.
+///
+/// The path is a simple location path, such as
+/// <code>/*/items/item</code> or <code>//item</code>;
+/// see the module document of project for the syntax.
+/// A matching element inside a selected subtree is not selected
+/// separately.
+/// The namespace declarations on the skipped ancestors, for the
+/// prefixes used in each subtree, are copied to the root element of
+/// the subtree, so that the prefixes remain bound.
+///
+/// Keep the returned DocumentRoot while using the subtrees:
+/// as with other documents, the nodes do not keep their parent alive.
+///
+/// # Examples
+///
+/// ```
+/// use amxml::dom::*;
+/// let xml = r#"<root xmlns:p="http://p"><head>...</head><items><item>1</item><item><p:x/></item></items></root>"#;
+/// let doc = new_document_partial(xml, "/*/items/item").unwrap();
+/// let items = doc.children();
+/// assert_eq!(items.len(), 2);
+/// assert_eq!(items[0].to_string(), "<item>1</item>");
+/// assert_eq!(items[1].to_string(), r#"<item xmlns:p="http://p"><p:x/></item>"#);
+/// assert_eq!(doc.get_first_node("//p:x").unwrap().namespace_uri(), "http://p");
+///
+/// let doc = new_document_partial(r#"<a><b>1<b>2</b></b></a>"#, "//b").unwrap();
+/// assert_eq!(doc.to_string(), "<b>1<b>2</b></b>");
+/// let err = new_document_partial("<a>\n<b></a>", "//b").err().unwrap();
+/// assert!(err.to_string().ends_with("(line 2, column 8)"));
+/// assert!(new_document_partial("<a/>", "b").is_err());
+/// ```
+///
+/// # Errors
+///
+/// - When the path is not supported.
+/// - When there is syntax error, e.g. "&lt;foo&gt;xxx&lt;/bar&gt;",
+///   with the line and column where the error was detected.
+///
+pub fn new_document_partial(xml_string: &str, xpath: &str) -> Result<NodePtr, Box<Error>> {
+    let steps: Vec<PathStep> = compile_path(xpath, "new_document_partial")?;
+    let mut dec = SaxDecoder::new(&String::from(xml_string))?;
+
+    let doc_root = make_new_rc_node(NodeType::DocumentRoot, None, "", "");
+    let mut names: Vec<String> = vec!{};
+            // 文書要素から現在の要素に至る要素名。
+    let mut ns_decls: Vec<Vec<(String, String)>> = vec!{};
+            // names の各要素で宣言している名前空間 (属性名と値)。
+    let mut subtree_depth: usize = 0;
+            // 選択した部分木の根の深さ (1以上)。0ならば部分木の外。
+    let mut curr_node = Rc::clone(&doc_root);
+    loop {
+        let token = match dec.raw_token() {
+            Ok(token) => token,
+            Err(e) => {
+                let (line, column) = dec.position();
+                return Err(xml_syntax_error_at(line, column,
+                    &format!("XML syntax error: {}", e)));
+            },
+        };
+        match token {
+            XmlToken::EOF => {
+                break;
+            },
+            XmlToken::StartElement{name, attr} => {
+                names.push(name.clone());
+                ns_decls.push(attr.iter()
+                    .filter(|at| at.name() == "xmlns" || at.name().starts_with("xmlns:"))
+                    .map(|at| (String::from(at.name()), String::from(at.value())))
+                    .collect());
+                if subtree_depth == 0 {
+                    let name_refs: Vec<&str> = names.iter().map(|n| n.as_str()).collect();
+                    if ! match_path(&steps, &name_refs) {
+                        continue;
+                    }
+                    subtree_depth = names.len();
+                }
+                let e = make_new_child_rc_node(NodeType::Element,
+                            &mut curr_node, name.as_str(), "", usize::MAX);
+                curr_node = Rc::clone(&e);
+                for at in attr.iter() {
+                    let attr_node = make_new_rc_node(NodeType::Attribute,
+                            Some(&mut curr_node), at.name(), at.value());
+                    curr_node.attributes_mut().push(Rc::clone(&attr_node));
+                }
+            },
+            XmlToken::EndElement{name} => {
+                match names.pop() {
+                    Some(ref open_name) if *open_name == name => {},
+                    Some(open_name) => {
+                        let (line, column) = dec.position();
+                        return Err(xml_syntax_error_at(line, column, &format!(
+                            "Element name mismatch: {} and {}", open_name, name)));
+                    },
+                    None => {
+                        let (line, column) = dec.position();
+                        return Err(xml_syntax_error_at(line, column, &format!(
+                            "Unexpected end tag: {}", name)));
+                    },
+                }
+                ns_decls.pop();
+                if subtree_depth == 0 {
+                    continue;
+                }
+                if subtree_depth == names.len() + 1 {
+                    declare_inherited_namespaces(&mut wrap_rc_clone(&curr_node), &ns_decls);
+                    subtree_depth = 0;
+                }
+                curr_node = match curr_node.parent {
+                    Some(ref p) => p.borrow().upgrade().unwrap(),
+                    None => Rc::clone(&curr_node),
+                };
+            },
+            XmlToken::CharData{chardata} => {
+                if subtree_depth != 0 {
+                    make_new_child_rc_node(NodeType::Text,
+                                &mut curr_node, "", chardata.as_str(), usize::MAX);
+                }
+            },
+            XmlToken::ProcInst{target, inst} => {
+                if subtree_depth != 0 {
+                    make_new_child_rc_node(NodeType::Instruction,
+                                &mut curr_node, target.as_str(), inst.as_str(), usize::MAX);
+                }
+            },
+            XmlToken::Comment{comment} => {
+                if subtree_depth != 0 {
+                    make_new_child_rc_node(NodeType::Comment,
+                                &mut curr_node, "", comment.as_str(), usize::MAX);
+                }
+            },
+            XmlToken::Directive{directive: _directive} => {},
+        }
+    }
+    return Ok(NodePtr{rc_node: doc_root});
+}
+
+// ---------------------------------------------------------------------
+// new_document_partial() で選択した部分木の根 subtree に、
+// 部分木内で使っているが宣言していない接頭辞について、
+// 読み飛ばした祖先での宣言 (ns_decls: 外側から順) を複写する。
+//
+fn declare_inherited_namespaces(subtree: &mut NodePtr, ns_decls: &Vec<Vec<(String, String)>>) {
+    let mut free_prefixes: Vec<String> = vec!{};
+    collect_free_prefixes(subtree, &mut free_prefixes);
+    for prefix in free_prefixes.iter() {
+        let decl_name = xmlns_attribute_name(prefix);
+        let uri = ns_decls.iter().rev()
+            .filter_map(|decls| decls.iter().find(|(n, _)| *n == decl_name))
+            .map(|(_, uri)| uri.clone())
+            .next();
+        if let Some(uri) = uri {
+            if uri != "" {
+                subtree.set_attribute_unchecked(&decl_name, &uri);
+            }
+        }
+    }
+}
+
+// =====================================================================
+/// Parses the HTML-ish ("tag soup") string leniently and creates
+/// the DOM tree, and returns the topmost DocumentRoot node.
+/// The tree has the same form as the one new_document() creates,
+/// so it can be queried by XPath in the same way.
+///
+/// This function never fails. Roughly speaking:
+///
+/// - Element names and attribute names are converted to lower case.
+/// - Void elements, such as <code>br</code>, <code>img</code>,
+///   <code>input</code>, have no content and need no end tag.
+/// - Unclosed <code>p</code>, <code>li</code>, <code>dt</code>,
+///   <code>dd</code>, <code>tr</code>, <code>td</code>, <code>th</code>,
+///   <code>option</code> are closed by the start tag of a sibling
+///   (or of a block element, for <code>p</code>).
+/// - An end tag closes the nearest open element of that name,
+///   together with the elements opened within it.
+///   An end tag without matching open element is ignored.
+/// - Attributes may be unquoted or have no value.
+///   See the module document of sax for details.
+///
+/// Unlike HTML5 parsers, <code>html</code>, <code>head</code>,
+/// <code>body</code> elements are not supplemented.
+///
+/// # Examples
+///
+/// ```
+/// use amxml::dom::*;
+/// let html = r#"<UL><li class=x>one<li>two<br>three</ul><p>A<p>B"#;
+/// let doc = new_html_document(html);
+/// assert_eq!(doc.to_string(),
+///     r#"<ul><li class="x">one</li><li>two<br/>three</li></ul><p>A</p><p>B</p>"#);
+/// let result = doc.eval_xpath("count(//li)").unwrap();
+/// assert_eq!(result.to_string(), "2");
+/// ```
+///
+pub fn new_html_document(html_string: &str) -> NodePtr {
+
+    let mut dec = SaxDecoder::new_lenient(html_string);
+
+    let doc_root = make_new_rc_node(NodeType::DocumentRoot, None, "", "");
+    let mut curr_node = Rc::clone(&doc_root);
+    loop {
+        match dec.raw_token() {
+            Ok(XmlToken::StartElement{name, attr}) => {
+                while curr_node.node_type == NodeType::Element &&
+                      html_closes_implicitly(&curr_node.name, &name) {
+                    curr_node = parent_rc_node(&curr_node);
+                }
+                let e = make_new_child_rc_node(NodeType::Element,
+                            &mut curr_node,
+                            name.as_str(), "", usize::MAX);
+                for at in attr.iter() {
+                    let attr_node = make_new_rc_node(NodeType::Attribute,
+                            Some(&mut Rc::clone(&e)), at.name(), at.value());
+                    e.attributes_mut().push(Rc::clone(&attr_node));
+                }
+                if ! is_html_void_element(&name) {
+                    curr_node = e;
+                }
+            },
+            Ok(XmlToken::EndElement{name}) => {
+                if is_html_void_element(&name) {
+                    continue;
+                }
+                let mut node = Rc::clone(&curr_node);
+                while node.node_type == NodeType::Element {
+                    if node.name == name {
+                        curr_node = parent_rc_node(&node);
+                        break;
+                    }
+                    node = parent_rc_node(&node);
+                }
+            },
+            Ok(XmlToken::CharData{chardata}) => {
+                make_new_child_rc_node(NodeType::Text,
+                            &mut curr_node,
+                            "", chardata.as_str(), usize::MAX);
+            },
+            Ok(XmlToken::ProcInst{target, inst}) => {
+                make_new_child_rc_node(NodeType::Instruction,
+                            &mut curr_node,
+                            target.as_str(), inst.as_str(), usize::MAX);
+            },
+            Ok(XmlToken::Comment{comment}) => {
+                make_new_child_rc_node(NodeType::Comment,
+                            &mut curr_node,
+                            "", comment.as_str(), usize::MAX);
+            },
+            Ok(XmlToken::Directive{directive}) => {
+                add_prolog_directive(&mut curr_node, &directive);
+            },
+            Ok(XmlToken::EOF) | Err(_) => {
+                break;
+            },
+        }
+    }
+    return NodePtr{rc_node: doc_root};
+}
+
+// ---------------------------------------------------------------------
+// 文書根の直下 (プロローグ) に現れた <!DOCTYPE ...> などの指令を、
+// Directiveノードとして追加する。値は "<!" と ">" の間の文字列。
+// 要素内に現れた指令は無視する。
+//
+fn add_prolog_directive(curr_node: &mut RcNode, directive: &str) {
+    if curr_node.node_type != NodeType::DocumentRoot {
+        return;
+    }
+    let value = directive.trim_start_matches("<!").trim_end_matches('>');
+    make_new_child_rc_node(NodeType::Directive,
+                curr_node, "", value, usize::MAX);
+}
+
+// ---------------------------------------------------------------------
+// 親ノード。親がない (DocumentRoot) 場合は自分自身。
+//
+fn parent_rc_node(rc_node: &RcNode) -> RcNode {
+    return match rc_node.parent {
+        Some(ref p) => p.borrow().upgrade().unwrap(),
+        None => Rc::clone(rc_node),
+    };
+}
+
+// ---------------------------------------------------------------------
+// HTMLの空要素 (内容も終了タグも持たない要素)。
+//
+fn is_html_void_element(name: &str) -> bool {
+    return [
+        "area", "base", "br", "col", "embed", "hr", "img", "input",
+        "link", "meta", "param", "source", "track", "wbr",
+    ].contains(&name);
+}
+
+// ---------------------------------------------------------------------
+// 開いている要素 open_name が、開始タグ new_name によって暗黙に閉じるか否か。
+//
+fn html_closes_implicitly(open_name: &str, new_name: &str) -> bool {
+    match open_name {
+        "p" => return [
+            "address", "article", "aside", "blockquote", "dd", "div",
+            "dl", "dt", "fieldset", "figure", "footer", "form",
+            "h1", "h2", "h3", "h4", "h5", "h6", "header", "hr", "li",
+            "main", "nav", "ol", "p", "pre", "section", "table", "ul",
+        ].contains(&new_name),
+        "li" => return new_name == "li",
+        "dt" | "dd" => return new_name == "dt" || new_name == "dd",
+        "option" => return new_name == "option" || new_name == "optgroup",
+        "tr" => return new_name == "tr" ||
+                    new_name == "tbody" || new_name == "tfoot",
+        "td" | "th" => return [
+            "td", "th", "tr", "tbody", "tfoot",
+        ].contains(&new_name),
+        "thead" | "tbody" => return new_name == "tbody" || new_name == "tfoot",
+        _ => return false,
+    }
+}
+
+// =====================================================================
+/// Resolves the URI reference against the base URI, as specified in
+/// RFC 3986 (section 5.2), and returns the absolute URI.
+/// Returns the reference itself if it is absolute,
+/// and None if the base URI is not absolute.
+///
+/// # Examples
+///
+/// ```
+/// use amxml::dom::*;
+/// let base = "http://example.com/a/b/c?q";
+/// assert_eq!(resolve_uri("d", base).unwrap(), "http://example.com/a/b/d");
+/// assert_eq!(resolve_uri("../d#f", base).unwrap(), "http://example.com/a/d#f");
+/// assert_eq!(resolve_uri("/d", base).unwrap(), "http://example.com/d");
+/// assert_eq!(resolve_uri("//other.org/d", base).unwrap(), "http://other.org/d");
+/// assert_eq!(resolve_uri("?x", base).unwrap(), "http://example.com/a/b/c?x");
+/// assert_eq!(resolve_uri("", base).unwrap(), "http://example.com/a/b/c?q");
+/// assert_eq!(resolve_uri("mailto:x@example.com", base).unwrap(), "mailto:x@example.com");
+/// assert_eq!(resolve_uri("d", "a/b"), None);
+/// ```
+///
+pub fn resolve_uri(reference: &str, base: &str) -> Option<String> {
+    let r = split_uri(reference);
+    if r.scheme.is_some() {
+        return Some(compose_uri(&r.scheme, &r.authority,
+                &remove_dot_segments(r.path), &r.query, &r.fragment));
+    }
+    let b = split_uri(base);
+    if b.scheme.is_none() {
+        return None;
+    }
+
+    let (authority, path, query) = if r.authority.is_some() {
+        (r.authority, remove_dot_segments(r.path), r.query)
+    } else if r.path == "" {
+        (b.authority, String::from(b.path), r.query.or(b.query))
+    } else if r.path.starts_with("/") {
+        (b.authority, remove_dot_segments(r.path), r.query)
+    } else {
+        let merged = if b.authority.is_some() && b.path == "" {
+            format!("/{}", r.path)
+        } else {
+            match b.path.rfind('/') {
+                Some(pos) => format!("{}{}", &b.path[..= pos], r.path),
+                None => String::from(r.path),
+            }
+        };
+        (b.authority, remove_dot_segments(&merged), r.query)
+    };
+    return Some(compose_uri(&b.scheme, &authority, &path, &query, &r.fragment));
+}
+
+// ---------------------------------------------------------------------
+// URI参照の構成要素 (RFC 3986, 5.2の用語に合わせる)。
+//
+struct UriParts<'a> {
+    scheme: Option<&'a str>,
+    authority: Option<&'a str>,
+    path: &'a str,
+    query: Option<&'a str>,
+    fragment: Option<&'a str>,
+}
+
+// ---------------------------------------------------------------------
+// URI参照を構成要素に分解する。
+//
+fn split_uri(uri: &str) -> UriParts<'_> {
+    let (rest, fragment) = match uri.find('#') {
+        Some(pos) => (&uri[.. pos], Some(&uri[pos + 1 ..])),
+        None => (uri, None),
+    };
+    let (rest, query) = match rest.find('?') {
+        Some(pos) => (&rest[.. pos], Some(&rest[pos + 1 ..])),
+        None => (rest, None),
+    };
+    let (scheme, rest) = if uri_has_scheme(rest) {
+        let pos = rest.find(':').unwrap();
+        (Some(&rest[.. pos]), &rest[pos + 1 ..])
+    } else {
+        (None, rest)
+    };
+    let (authority, path) = if rest.starts_with("//") {
+        let after = &rest[2 ..];
+        match after.find('/') {
+            Some(pos) => (Some(&after[.. pos]), &after[pos ..]),
+            None => (Some(after), ""),
+        }
+    } else {
+        (None, rest)
+    };
+    return UriParts{ scheme, authority, path, query, fragment };
+}
+
+// ---------------------------------------------------------------------
+// スキーム (ALPHA *( ALPHA / DIGIT / "+" / "-" / "." ) ":") で
+// 始まるか否か。
+//
+fn uri_has_scheme(uri: &str) -> bool {
+    let colon = match uri.find(':') {
+        Some(pos) => pos,
+        None => return false,
+    };
+    let mut chars = uri[.. colon].chars();
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() => {},
+        _ => return false,
+    }
+    return chars.all(|c| c.is_ascii_alphanumeric() || c == '+' || c == '-' || c == '.');
+}
+
+// ---------------------------------------------------------------------
+// 構成要素からURIを組み立てる (RFC 3986, 5.3)。
+//
+fn compose_uri(scheme: &Option<&str>, authority: &Option<&str>, path: &str,
+                query: &Option<&str>, fragment: &Option<&str>) -> String {
+    let mut uri = String::new();
+    if let Some(scheme) = scheme {
+        uri += scheme;
+        uri += ":";
+    }
+    if let Some(authority) = authority {
+        uri += "//";
+        uri += authority;
+    }
+    uri += path;
+    if let Some(query) = query {
+        uri += "?";
+        uri += query;
+    }
+    if let Some(fragment) = fragment {
+        uri += "#";
+        uri += fragment;
+    }
+    return uri;
+}
+
+// ---------------------------------------------------------------------
+// パスから "." と ".." の区間を取り除く (RFC 3986, 5.2.4)。
+//
+fn remove_dot_segments(path: &str) -> String {
+    let mut output: Vec<&str> = vec!{};
+    let segments: Vec<&str> = path.split('/').collect();
+    for (i, seg) in segments.iter().enumerate() {
+        let is_last = i == segments.len() - 1;
+        match *seg {
+            "." => {
+                if is_last {
+                    output.push("");
+                }
+            },
+            ".." => {
+                if output.len() > 1 || (output.len() == 1 && output[0] != "") {
+                    output.pop();
+                }
+                if is_last {
+                    output.push("");
+                }
+            },
+            _ => output.push(seg),
+        }
+    }
+    let mut result = output.join("/");
+    if path.starts_with("/") && ! result.starts_with("/") {
+        result.insert(0, '/');
+    }
+    return result;
+}
+
+// =====================================================================
+/// Extracts the links under the node (or in the whole document):
+/// the values of the attributes given as (element name, attribute name)
+/// pairs, e.g. <code>&[("a", "href"), ("img", "src")]</code>,
+/// resolved to absolute URIs, in document order.
+/// Names are compared as written (prefix included);
+/// the element name <code>"*"</code> matches any element.
+///
+/// Each value (with leading and trailing white space removed)
+/// is resolved against the base URI of the attribute, i.e. with
+/// xml:base taken into account (cf. NodePtr#base_uri());
+/// <code>base</code> stands for the document URI where the document has
+/// none (e.g. the URL the document was fetched from), and may be "".
+/// Values that cannot be resolved to an absolute URI are skipped.
+///
+/// # Examples
+///
+/// ```
+/// use amxml::dom::*;
+/// let xml = r#"<html><body>
+///   <a href="news/1.html">1</a>
+///   <img src="/logo.png"/>
+///   <div xml:base="http://cdn.example.org/assets/"><img src="x.png"/></div>
+///   <a href="mailto:info@example.com">mail</a>
+///   <a name="top">no link</a>
+/// </body></html>"#;
+/// let doc = new_document(xml).unwrap();
+/// let links = extract_links(&doc, &[("a", "href"), ("img", "src")],
+///         "http://example.com/dir/index.html");
+/// assert_eq!(links, vec!{
+///     "http://example.com/dir/news/1.html",
+///     "http://example.com/logo.png",
+///     "http://cdn.example.org/assets/x.png",
+///     "mailto:info@example.com",
+/// });
+///
+/// let links = extract_links(&doc, &[("*", "src")], "");
+/// assert_eq!(links, vec!{ "http://cdn.example.org/assets/x.png" });
+/// ```
+///
+pub fn extract_links(node: &NodePtr, targets: &[(&str, &str)], base: &str) -> Vec<String> {
+    let mut links: Vec<String> = vec!{};
+    let mut stack: Vec<NodePtr> = vec!{node.rc_clone()};
+    while let Some(curr) = stack.pop() {
+        if curr.node_type() == NodeType::Element {
+            let name = curr.name();
+            for at in curr.attribute_nodes().iter() {
+                let at_name = at.name();
+                let is_target = targets.iter().any(|&(e, a)|
+                        (e == "*" || e == name) && a == at_name);
+                if ! is_target {
+                    continue;
+                }
+                let link_base = match at.base_uri() {
+                    Some(ref b) => resolve_uri(b, base).unwrap_or(b.clone()),
+                    None => String::from(base),
+                };
+                if let Some(uri) = resolve_uri(at.value().trim(), &link_base) {
+                    links.push(uri);
+                }
+            }
+        }
+        let mut children = curr.children();
+        children.reverse();
+        stack.append(&mut children);
+    }
+    return links;
+}
+
+// =====================================================================
+/// Sorts the nodes in document order, and removes the duplicates
+/// (the same node that appears more than once).
+/// The nodes may be gathered from several queries, or even
+/// from several documents. cf. NodePtr#cmp_document_order()
+///
+/// # Examples
+///
+/// ```
+/// use amxml::dom::*;
+/// let xml = r#"<root><a img="a1"/><b img="b1"/><a img="a2"/></root>"#;
+/// let doc = new_document(xml).unwrap();
+/// let mut nodes = doc.get_nodeset("//b").unwrap();
+/// nodes.append(&mut doc.get_nodeset("//a").unwrap());
+/// nodes.append(&mut doc.get_nodeset("//b").unwrap());
+/// sort_document_order(&mut nodes);
+/// let img: Vec<String> = nodes.iter().map(|n| n.attribute_value("img").unwrap()).collect();
+/// assert_eq!(img, vec!{"a1", "b1", "a2"});
+/// ```
+///
+pub fn sort_document_order(nodes: &mut Vec<NodePtr>) {
+    if nodes.len() <= 1 {
+        return;
+    }
+    nodes.sort_by(|a, b| a.cmp_document_order(b));
+    nodes.dedup();
+}
+
+// ---------------------------------------------------------------------
+//
+fn shallow_copy_rc_rels(target: &mut RcNode, source: &RcNode) {
+    for ch in source.children.borrow().iter() {
+        target.push_child(ch);
+    }
+    for at in source.attributes.borrow().iter() {
+        target.attributes_mut().push(Rc::clone(at));
+    }
+    target.raw.set(source.raw.get());
+}
+
+// ---------------------------------------------------------------------
+// 木の複製。NodePtr (Rc) はスレッド間で共有できないので、他のスレッド
+// で評価する場合 (xpath_impl::parallel) は、これを共有して、スレッドごと
+// に木を組み立て直す。ノード (属性を除く) を文書順に並べ、それぞれの
+// 子の数を記録する。文字列化して構文解析し直す場合と違い、隣接する
+// テキストノードなども元のまま再現する。
+//
+#[cfg(not(feature = "no_std"))]
+#[derive(Debug)]
+pub(crate) struct TreeSnapshot {
+    nodes: Vec<SnapshotNode>,
+    document_uri: Option<String>,
+}
+
+#[cfg(not(feature = "no_std"))]
+#[derive(Debug)]
+struct SnapshotNode {
+    node_type: NodeType,
+    name: String,
+    value: String,
+    raw: bool,
+    attributes: Vec<(String, String)>,
+    child_count: usize,
+}
+
+// ---------------------------------------------------------------------
+// 複製 snapshot から木を組み立て、その根を返す。
+// 深い木でもスタックを使い切らないよう、再帰せず作業スタック
+// (親, 残りの子の数) を使う。
+//
+#[cfg(not(feature = "no_std"))]
+pub(crate) fn new_tree_from_snapshot(snapshot: &TreeSnapshot) -> NodePtr {
+    let first = &snapshot.nodes[0];
+    let mut root = make_new_rc_node(first.node_type.clone(), None, &first.name, &first.value);
+    restore_snapshot_node(&mut root, first);
+    if snapshot.document_uri.is_some() {
+        *root.extras_or_new().document_uri.borrow_mut() = snapshot.document_uri.clone();
+    }
+
+    let mut stack: Vec<(RcNode, usize)> = vec!{(Rc::clone(&root), first.child_count)};
+    for sn in snapshot.nodes[1..].iter() {
+        while stack.last().map_or(false, |&(_, rest)| rest == 0) {
+            stack.pop();
+        }
+        let mut node = match stack.last_mut() {
+            Some(&mut (ref mut parent, ref mut rest)) => {
+                *rest -= 1;
+                let node = make_new_rc_node(sn.node_type.clone(),
+                                Some(parent), &sn.name, &sn.value);
+                parent.push_child(&node);
+                node
+            },
+            None => break,
+        };
+        restore_snapshot_node(&mut node, sn);
+        stack.push((node, sn.child_count));
+    }
+    return wrap_rc_clone(&root);
+}
+
+// ---------------------------------------------------------------------
+//
+#[cfg(not(feature = "no_std"))]
+fn restore_snapshot_node(rc_node: &mut RcNode, sn: &SnapshotNode) {
+    rc_node.raw.set(sn.raw);
+    for &(ref name, ref value) in sn.attributes.iter() {
+        let attr_node = make_new_rc_node(NodeType::Attribute,
+                        Some(rc_node), name, value);
+        rc_node.attributes_mut().push(attr_node);
+    }
+}
+
+// =====================================================================
+//
+impl NodePtr {
+
+    // =================================================================
+    /// Turns XML DOM tree into XML string. cf. inner_xml()
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use amxml::dom::*;
+    /// let xml_string = r#"<?xml version="1.0"?><article>About <em>XML</em> string</article>"#;
+    /// let doc = new_document(&xml_string).unwrap();
+    /// let root_elem = doc.root_element();
+    /// let result = root_elem.to_string();
+    /// assert_eq!(result, "<article>About <em>XML</em> string</article>");
+    /// ```
+    ///
+    pub fn to_string(&self) -> String {
+        return to_string_with_indent(&self.unwrap_rc(), 0, &SerializeOptions::new());
+    }
+
+    // =================================================================
+    /// Turns XML DOM tree into 'pretty' XML string with four spaces indent.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use amxml::dom::*;
+    /// let xml_string = r#"<?xml version="1.0"?><article>About <em>XML</em> string</article>"#;
+    /// let doc = new_document(&xml_string).unwrap();
+    /// let root_elem = doc.root_element();
+    /// let result = root_elem.to_pretty_string();
+    /// let guess = r#"<article>
+    ///     About 
+    ///     <em>
+    ///         XML
+    ///     </em>
+    ///      string
+    /// </article>
+    /// "#;
+    /// assert_eq!(result, guess);
+    /// ```
+    ///
+    pub fn to_pretty_string(&self) -> String {
+        return to_string_with_indent(&self.unwrap_rc(), 0,
+                    &SerializeOptions::new().indent(4));
+    }
+
+    // =================================================================
+    /// Turns XML DOM tree into XML string, as specified by options.
+    /// cf. SerializeOptions
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use amxml::dom::*;
+    /// let xml_string = r#"<doc><p>café</p><script>a &lt; b</script></doc>"#;
+    /// let doc = new_document(&xml_string).unwrap();
+    /// let options = SerializeOptions::new()
+    ///         .ascii_only(true)
+    ///         .cdata_section_elements(&["script"]);
+    /// let result = doc.to_string_with_options(&options);
+    /// assert_eq!(result, "<doc><p>caf&#xE9;</p><script><![CDATA[a < b]]></script></doc>");
+    /// ```
+    ///
+    pub fn to_string_with_options(&self, options: &SerializeOptions) -> String {
+        return to_string_with_indent(&self.unwrap_rc(), 0, options);
+    }
+
+    // =================================================================
+    /// Turns XML DOM tree into XML string without ignorable white space,
+    /// collapsing each run of white space in text into a single space.
+    /// cf. SerializeOptions#minify()
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use amxml::dom::*;
+    /// let xml_string = r#"<article>
+    ///     <title>About   <em>XML</em>
+    ///         string</title>
+    ///     <pre xml:space="preserve">  a  b  </pre>
+    /// </article>"#;
+    /// let doc = new_document(&xml_string).unwrap();
+    /// assert_eq!(doc.to_minified_string(),
+    ///     r#"<article><title>About <em>XML</em> string</title><pre xml:space="preserve">  a  b  </pre></article>"#);
+    /// ```
+    ///
+    pub fn to_minified_string(&self) -> String {
+        return to_string_with_indent(&self.unwrap_rc(), 0,
+                    &SerializeOptions::new().minify(true));
+    }
+
+    // =================================================================
+    /// Turns the subtree under self into XML string that can be parsed
+    /// on its own: namespaces used in the subtree but declared on the
+    /// ancestors of self are declared on self.
+    /// cf. SerializeOptions#standalone()
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use amxml::dom::*;
+    /// let xml_string = r#"<root xmlns="http://d" xmlns:p="http://p"><a><p:x p:y="1"/></a></root>"#;
+    /// let doc = new_document(&xml_string).unwrap();
+    /// let a = doc.root_element().first_child().unwrap();
+    /// assert_eq!(a.to_string(), r#"<a><p:x p:y="1"/></a>"#);
+    /// let result = a.to_standalone_string();
+    /// assert_eq!(result, r#"<a xmlns="http://d" xmlns:p="http://p"><p:x p:y="1"/></a>"#);
+    /// assert!(new_document(&result).is_ok());
+    /// ```
+    ///
+    pub fn to_standalone_string(&self) -> String {
+        return to_string_with_indent(&self.unwrap_rc(), 0,
+                    &SerializeOptions::new().standalone(true));
+    }
+
+    // =================================================================
+    /// Writes XML DOM tree to the writer in the given encoding:
+    /// "UTF-8", "UTF-16LE" or "ISO-8859-1" (case-insensitive;
+    /// "UTF-16" and "Latin-1" are accepted as aliases).
+    ///
+    /// The output starts with the byte order mark for UTF-16LE,
+    /// and the XML declaration of the document, if any, gets the
+    /// encoding declaration for the encoding ("UTF-16" for UTF-16LE,
+    /// as the BOM tells the byte order).
+    /// When serializing a node that is not a document with an XML
+    /// declaration, an XML declaration is written first.
+    ///
+    /// Characters that the encoding can't represent are written
+    /// as numeric character references (e.g. &amp;#x20AC;)
+    /// in text and attribute values.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use amxml::dom::*;
+    /// let doc = new_document(r#"<?xml version="1.0"?><p a="é">5€</p>"#).unwrap();
+    /// let mut latin1: Vec<u8> = vec!{};
+    /// doc.write_to_with_encoding(&mut latin1, "ISO-8859-1").unwrap();
+    /// assert_eq!(latin1, b"<?xml version=\"1.0\" encoding=\"ISO-8859-1\"?><p a=\"\xE9\">5&#x20AC;</p>");
+    ///
+    /// let mut utf16: Vec<u8> = vec!{};
+    /// doc.root_element().write_to_with_encoding(&mut utf16, "UTF-16LE").unwrap();
+    /// assert_eq!(&utf16[.. 4], b"\xFF\xFE<\0");
+    /// let units: Vec<u16> = utf16[2 ..].chunks(2)
+    ///         .map(|b| b[0] as u16 | (b[1] as u16) << 8).collect();
+    /// assert_eq!(String::from_utf16(&units).unwrap(),
+    ///         r#"<?xml version="1.0" encoding="UTF-16"?><p a="é">5€</p>"#);
+    ///
+    /// let doc = new_document(r#"<?xml version='1.0' encoding='UTF-8'?><p/>"#).unwrap();
+    /// let mut latin1: Vec<u8> = vec!{};
+    /// doc.write_to_with_encoding(&mut latin1, "latin-1").unwrap();
+    /// assert_eq!(latin1, b"<?xml version='1.0' encoding='ISO-8859-1'?><p/>");
+    /// assert!(doc.write_to_with_encoding(&mut latin1, "EBCDIC").is_err());
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// - When the encoding is not supported.
+    /// - When a name, comment or processing instruction contains
+    ///   a character that the encoding can't represent.
+    /// - When writing to the writer fails.
+    ///
+    #[cfg(not(feature = "no_std"))]
+    pub fn write_to_with_encoding<W: Write>(&self, mut writer: W,
+                encoding: &str) -> Result<(), Box<Error>> {
+        let output_encoding = match OutputEncoding::from_name(encoding) {
+            Some(e) => e,
+            None => return Err(uninplemented!(
+                    "write_to_with_encoding: unsupported encoding: {}", encoding)),
+        };
+        let declared_name = output_encoding.declared_name();
+        let mut options = SerializeOptions::new();
+        options.max_char = output_encoding.max_char();
+        options.encoding = Some(String::from(declared_name));
+
+        let has_xml_decl = self.node_type() == NodeType::DocumentRoot &&
+            self.children().iter().any(|ch| ch.node_type() == NodeType::XMLDecl);
+        let mut xml = if has_xml_decl {
+            String::new()
+        } else {
+            format!(r#"<?xml version="1.0" encoding="{}"?>"#, declared_name)
+        };
+        xml += &to_string_with_indent(&self.unwrap_rc(), 0, &options);
+
+        const CHUNK_SIZE: usize = 8192;
+        let mut buf: Vec<u8> = output_encoding.bom().to_vec();
+        for c in xml.chars() {
+            output_encoding.encode_char(c, &mut buf)?;
+            if CHUNK_SIZE <= buf.len() {
+                writer.write_all(&buf)?;
+                buf.clear();
+            }
+        }
+        writer.write_all(&buf)?;
+        writer.flush()?;
+        return Ok(());
+    }
+
+    // =================================================================
+    /// Turns XML DOM tree under self into XML string. cf. to_string()
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use amxml::dom::*;
+    /// let xml_string = r#"<?xml version="1.0"?><article>About <em>XML</em> string</article>"#;
+    /// let doc = new_document(&xml_string).unwrap();
+    /// let root_elem = doc.root_element();
+    /// let result = root_elem.inner_xml();
+    /// assert_eq!(result, "About <em>XML</em> string");
+    /// ```
+    ///
+    pub fn inner_xml(&self) -> String {
+        let mut s = String::new();
+        for ch in self.children().iter() {
+            s += &ch.to_string();
+        }
+        return s;
+    }
+
+    // =================================================================
+    /// Returns type of the node (NodeType::Element, etc.).
+    //
+    pub fn node_type(&self) -> NodeType {
+        return self.unwrap_rc().node_type.clone();
+    }
+
+    // =================================================================
+    /// Returns the name of the Element/Attribute node,
+    /// or the target of the Instruction node.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use amxml::dom::*;
+    /// let xml_string = r#"<ns:article>XML</ns:article>"#;
+    /// let doc = new_document(&xml_string).unwrap();
+    /// let root_elem = doc.root_element();
+    /// assert_eq!(root_elem.name(), "ns:article");
+    /// ```
+    ///
+    pub fn name(&self) -> String {
+        return self.unwrap_rc().name.clone();
+    }
+
+    // =================================================================
+    /// Returns the value of the Attribute node,
+    /// text of the Text/Comment node,
+    /// inst of XMLDecl/Instruction node.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use amxml::dom::*;
+    /// let xml_string = r#"<ns:article>XML</ns:article>"#;
+    /// let doc = new_document(&xml_string).unwrap();
+    /// let text_node = doc.get_first_node("//text()").unwrap();
+    /// assert_eq!(text_node.value(), "XML");
+    /// ```
+    ///
+    pub fn value(&self) -> String {
+        return self.unwrap_rc().value.clone();
+    }
+
+    // =================================================================
+    /// Returns the local name of Element.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use amxml::dom::*;
+    /// let xml_string = r#"<ns:article>foo</ns:article>"#;
+    /// let doc = new_document(&xml_string).unwrap();
+    /// let root_elem = doc.root_element();
+    /// assert_eq!(root_elem.local_name(), "article");
+    /// ```
+    ///
+    pub fn local_name(&self) -> String {
+        let name = &self.unwrap_rc().name;
+        let v: Vec<&str> = name.splitn(2, ":").collect();
+        if v.len() == 2 {
+            return String::from(v[1]);
+        } else {
+            return name.clone();
+        }
+    }
+
+    // =================================================================
+    /// Returns the space name of Element, or "" if not with namespace.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use amxml::dom::*;
+    /// let xml_string = r#"<ns:article>foo</ns:article>"#;
+    /// let doc = new_document(&xml_string).unwrap();
+    /// let root_elem = doc.root_element();
+    /// assert_eq!(root_elem.space_name(), "ns");
+    /// ```
+    ///
+    pub fn space_name(&self) -> String {
+        let name = &self.unwrap_rc().name;
+        let v: Vec<&str> = name.splitn(2, ":").collect();
+        if v.len() == 2 {
+            return String::from(v[0]);
+        } else {
+            return String::new();
+        }
+    }
+
+    // =================================================================
+    /// Returns the URI for namespace.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use amxml::dom::*;
+    /// let xml_string = r#"<root xmlns="http://def" xmlns:ns="http://ns"><ns:a/><b/></root>"#;
+    /// let doc = new_document(&xml_string).unwrap();
+    /// let root = doc.root_element();
+    /// let elem_ns_a = doc.get_first_node("//ns:a").unwrap();
+    /// let elem_b = doc.get_first_node("//b").unwrap();
+    /// assert_eq!(elem_ns_a.namespace_uri(), "http://ns");
+    /// assert_eq!(elem_b.namespace_uri(), "http://def");
+    /// ```
+    ///
+    pub fn namespace_uri(&self) -> String {
+        let mut xmlns_attr = String::from("xmlns");
+        let space = self.space_name();
+        if space.as_str() != "" {
+            xmlns_attr += &":";
+            xmlns_attr += &space;
+        }
+
+        let mut curr = self.unwrap_rc();
+        while (*curr).node_type != NodeType::DocumentRoot {
+            let val = wrap_rc_clone(&curr).attribute_value(xmlns_attr.as_str());
+            if let Some(value) = val {
+                return value.clone();
+            }
+            curr = match (*curr).parent {
+                Some(ref p) => p.borrow().upgrade().unwrap(),
+                None => return String::new(),
+            };
+        }
+        return String::new();
+    }
+
+    // =================================================================
+    /// Returns the language in effect for this node, that is,
+    /// the value of xml:lang on this node or its nearest ancestor
+    /// that has one. Returns None if not specified.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use amxml::dom::*;
+    /// let xml_string = r#"<root xml:lang="en"><p xml:lang="ja"><a/></p><b/></root>"#;
+    /// let doc = new_document(&xml_string).unwrap();
+    /// let elem_a = doc.get_first_node("//a").unwrap();
+    /// let elem_b = doc.get_first_node("//b").unwrap();
+    /// assert_eq!(elem_a.effective_lang().unwrap(), "ja");
+    /// assert_eq!(elem_b.effective_lang().unwrap(), "en");
+    /// assert_eq!(doc.effective_lang(), None);
+    /// ```
+    ///
+    pub fn effective_lang(&self) -> Option<String> {
+        return self.inherited_attribute_value("xml:lang");
+    }
+
+    // =================================================================
+    /// Returns true if white space in this node is to be preserved,
+    /// that is, the xml:space in effect (on this node or its nearest
+    /// ancestor that has one) is "preserve".
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use amxml::dom::*;
+    /// let xml_string = r#"<root xml:space="preserve"><pre><a/></pre><p xml:space="default"/></root>"#;
+    /// let doc = new_document(&xml_string).unwrap();
+    /// let elem_a = doc.get_first_node("//a").unwrap();
+    /// let elem_p = doc.get_first_node("//p").unwrap();
+    /// assert_eq!(elem_a.preserves_space(), true);
+    /// assert_eq!(elem_p.preserves_space(), false);
+    /// ```
+    ///
+    pub fn preserves_space(&self) -> bool {
+        match self.inherited_attribute_value("xml:space") {
+            Some(value) => return value == "preserve",
+            None => return false,
+        }
+    }
+
+    // =================================================================
+    /// Returns the document URI of this DocumentRoot node, recorded by
+    /// new_document_from_file(), ParseOptions#document_uri() or
+    /// set_document_uri(). Returns None for other nodes, or if not
+    /// recorded.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use amxml::dom::*;
+    /// let doc = new_document("<a/>").unwrap();
+    /// assert_eq!(doc.document_uri(), None);
+    /// doc.set_document_uri("http://example.com/a.xml");
+    /// assert_eq!(doc.document_uri().unwrap(), "http://example.com/a.xml");
+    /// assert_eq!(doc.root_element().document_uri(), None);
+    /// ```
+    ///
+    pub fn document_uri(&self) -> Option<String> {
+        if self.node_type() != NodeType::DocumentRoot {
+            return None;
+        }
+        return self.rc_node.extras().and_then(|e| e.document_uri.borrow().clone());
+    }
+
+    // =================================================================
+    /// Records the document URI of this DocumentRoot node;
+    /// ignored for other nodes.
+    ///
+    pub fn set_document_uri(&self, uri: &str) {
+        if self.node_type() == NodeType::DocumentRoot {
+            *self.rc_node.extras_or_new().document_uri.borrow_mut() = Some(String::from(uri));
+        }
+    }
+
+    // =================================================================
+    /// Returns the base URI of this node: the document URI for the
+    /// DocumentRoot node; for an element, its xml:base attribute resolved
+    /// against the base URI of its parent (or the parent's base URI if
+    /// it has no xml:base); for other nodes, the base URI of the parent.
+    /// Returns None if there is no base URI.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use amxml::dom::*;
+    /// let xml = r#"<a xml:base="http://example.com/doc/"><b xml:base="img/"><c v="1"/></b></a>"#;
+    /// let doc = new_document(xml).unwrap();
+    /// let attr_v = doc.get_first_node("//c/@v").unwrap();
+    /// assert_eq!(attr_v.base_uri().unwrap(), "http://example.com/doc/img/");
+    /// assert_eq!(doc.base_uri(), None);
+    ///
+    /// let options = ParseOptions::new().document_uri("http://example.com/x/a.xml");
+    /// let doc = new_document_with_options("<a xml:base='../y/'/>", &options).unwrap();
+    /// assert_eq!(doc.root_element().base_uri().unwrap(), "http://example.com/y/");
+    /// ```
+    ///
+    pub fn base_uri(&self) -> Option<String> {
+        let mut xml_bases: Vec<String> = vec!{};
+        let mut base: Option<String> = None;
+        let mut curr = self.unwrap_rc();
+        loop {
+            if (*curr).node_type == NodeType::DocumentRoot {
+                base = curr.extras().and_then(|e| e.document_uri.borrow().clone());
+                break;
+            }
+            if (*curr).node_type == NodeType::Element {
+                if let Some(value) = wrap_rc_clone(&curr).attribute_value("xml:base") {
+                    xml_bases.push(value);
+                    if uri_has_scheme(&xml_bases[xml_bases.len() - 1]) {
+                        break;
+                    }
+                }
+            }
+            curr = match (*curr).parent {
+                Some(ref p) => p.borrow().upgrade().unwrap(),
+                None => break,
+            };
+        }
+        while let Some(xml_base) = xml_bases.pop() {
+            base = match base {
+                Some(ref b) => resolve_uri(&xml_base, b).or(Some(xml_base)),
+                None => Some(xml_base),
+            };
+        }
+        return base;
+    }
+
+    // -----------------------------------------------------------------
+    // 自身または最も近い祖先の属性値 (xml:lang、xml:space など、
+    // 子孫に継承される属性)。
+    //
+    fn inherited_attribute_value(&self, name: &str) -> Option<String> {
+        let mut curr = self.unwrap_rc();
+        while (*curr).node_type != NodeType::DocumentRoot {
+            if let Some(value) = wrap_rc_clone(&curr).attribute_value(name) {
+                return Some(value);
+            }
+            curr = match (*curr).parent {
+                Some(ref p) => p.borrow().upgrade().unwrap(),
+                None => return None,
+            };
+        }
+        return None;
+    }
+
+    // =================================================================
+    /// Returns the root (topmost) node of DOM tree.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use amxml::dom::*;
+    /// let xml_string = r#"<article><p>DOM</p></article>"#;
+    /// let doc = new_document(&xml_string).unwrap();
+    /// let elem_p = doc.get_first_node("/article/p").unwrap();
+    /// assert_eq!(elem_p.name(), "p");
+    /// let root = elem_p.root();
+    /// assert_eq!(root.node_type(), NodeType::DocumentRoot);
+    /// ```
+    ///
+    pub fn root(&self) -> NodePtr {
+        let mut curr = self.unwrap_rc();
+        loop {
+            curr = match (*curr).parent {
+                Some(ref p) => p.borrow().upgrade().unwrap(),
+                None => return wrap_rc_clone(&curr),
+            };
+        }
+    }
+
+    // =================================================================
+    /// Returns the topmost Element node,
+    /// or root node when there is no Element node (illegal case),
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use amxml::dom::*;
+    /// let xml_string = r#"<article><p>DOM</p></article>"#;
+    /// let doc = new_document(&xml_string).unwrap();
+    /// let elem_p = doc.get_first_node("/article/p").unwrap();
+    /// assert_eq!(elem_p.name(), "p");
+    /// let root_elem = elem_p.root_element();
+    /// assert_eq!(root_elem.name(), "article");
+    /// ```
+    ///
+    pub fn root_element(&self) -> NodePtr {
+        let doc_root = self.root();
+        for ch in doc_root.children().iter() {
+            if ch.node_type() == NodeType::Element {
+                return ch.clone();
+            }
+        }
+        return doc_root;
+    }
+
+    // =================================================================
+    /// Returns the children of the document node before the root
+    /// element: the XML declaration, DOCTYPE, comments and processing
+    /// instructions, in document order.
+    /// White space between them is not included.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use amxml::dom::*;
+    /// let xml = "<?xml version=\"1.0\"?>\n<?pi a?>\n<!--c1-->\n<root/>\n<!--c2-->\n";
+    /// let doc = new_document(xml).unwrap();
+    /// let prolog: Vec<NodeType> = doc.prolog().iter().map(|n| n.node_type()).collect();
+    /// assert_eq!(prolog, vec!{NodeType::XMLDecl, NodeType::Instruction, NodeType::Comment});
+    /// let epilog = doc.root_element().epilog();
+    /// assert_eq!(epilog.len(), 1);
+    /// assert_eq!(epilog[0].value(), "c2");
+    /// assert_eq!(doc.to_string(), xml);
+    /// ```
+    ///
+    pub fn prolog(&self) -> Vec<NodePtr> {
+        return self.root().children().into_iter()
+                .take_while(|ch| ch.node_type() != NodeType::Element)
+                .filter(|ch| ! ch.is_prolog_white_space())
+                .collect();
+    }
+
+    // =================================================================
+    /// Returns the children of the document node after the root
+    /// element: comments and processing instructions, in document order.
+    /// White space between them is not included.
+    ///
+    /// # Examples
+    ///
+    /// See prolog().
+    ///
+    pub fn epilog(&self) -> Vec<NodePtr> {
+        return self.root().children().into_iter()
+                .skip_while(|ch| ch.node_type() != NodeType::Element)
+                .skip(1)
+                .filter(|ch| ! ch.is_prolog_white_space())
+                .collect();
+    }
+
+    // =================================================================
+    /// (Inner Use)
+    /// Whether the node is white space text outside of the root element,
+    /// which is not a node of the XPath data model.
+    ///
+    pub fn is_prolog_white_space(&self) -> bool {
+        if self.node_type() != NodeType::Text {
+            return false;
+        }
+        match self.parent() {
+            Some(p) => return p.node_type() == NodeType::DocumentRoot &&
+                              self.value().trim() == "",
+            None => return false,
+        }
+    }
+
+    // =================================================================
+    /// Returns the parent of the 'node', or None
+    /// if 'node' has no parent (i.e. is DocumentRoot).
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use amxml::dom::*;
+    /// let xml_string = r#"<article><chapter>foo</chapter></article>"#;
+    /// let doc = new_document(&xml_string).unwrap();
+    /// let elem_chapter = doc.get_first_node("//chapter").unwrap();
+    /// let p = elem_chapter.parent().unwrap();
+    /// assert_eq!(p.name(), "article");
+    /// ```
+    ///
+    pub fn parent(&self) -> Option<NodePtr> {
+        match self.unwrap_rc().parent {
+            Some(ref p) => {
+                let parent_node = p.borrow().upgrade().unwrap();
+                return Some(wrap_rc_clone(&parent_node));
+            },
+            None => return None,
+        }
+    }
+
+    // =================================================================
+    /// Returns the ancestors of the node: the parent, its parent, ...,
+    /// up to the DocumentRoot (or the top of the detached subtree).
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use amxml::dom::*;
+    /// let doc = new_document(r#"<a><b><c/></b></a>"#).unwrap();
+    /// let c = doc.get_first_node("//c").unwrap();
+    /// let names: Vec<String> = c.ancestors().iter().map(|n| n.name()).collect();
+    /// assert_eq!(names, vec!{"b", "a", ""});
+    /// ```
+    ///
+    pub fn ancestors(&self) -> Vec<NodePtr> {
+        let mut node_array: Vec<NodePtr> = vec!{};
+        let mut curr = self.parent();
+        while let Some(node) = curr {
+            curr = node.parent();
+            node_array.push(node);
+        }
+        return node_array;
+    }
+
+    // =================================================================
+    /// Returns the path that locates the node in the document,
+    /// in XPath notation, e.g. <code>/root/item[2]/@id</code>,
+    /// for messages and debugging.
+    ///
+    /// The position predicate is given only when there are other
+    /// siblings of the same name (or kind, for text, comment, etc.).
+    /// The path of the node that is not in a document starts with
+    /// the top of its subtree, without the leading slash.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use amxml::dom::*;
+    /// let doc = new_document(r#"<root><item/><item id="2">x<!--c--></item></root>"#).unwrap();
+    /// let id = doc.get_first_node("//item/@id").unwrap();
+    /// assert_eq!(id.display_path(), "/root/item[2]/@id");
+    /// let text = doc.get_first_node("//text()").unwrap();
+    /// assert_eq!(text.display_path(), "/root/item[2]/text()");
+    /// assert_eq!(doc.display_path(), "/");
+    /// assert_eq!(format!("{:.3?}", doc.root_element()),
+    ///         r#"NodePtr(/root <root> "x")"#);
+    /// assert_eq!(format!("{:?}", id), r#"NodePtr(/root/item[2]/@id id="2")"#);
+    /// ```
+    ///
+    pub fn display_path(&self) -> String {
+        let mut steps: Vec<String> = vec!{};
+        let mut curr = self.rc_clone();
+        loop {
+            if curr.node_type() == NodeType::DocumentRoot {
+                steps.push(String::new());
+                break;
+            }
+            let parent = match curr.parent() {
+                Some(p) => p,
+                None => {
+                    steps.push(curr.path_step());
+                    break;
+                },
+            };
+            let mut step = curr.path_step();
+            if curr.node_type() != NodeType::Attribute {
+                let mut position = 0;
+                let mut count = 0;
+                for sib in parent.children().iter() {
+                    if sib.node_type() == curr.node_type() && sib.name() == curr.name() {
+                        count += 1;
+                        if *sib == curr {
+                            position = count;
+                        }
+                    }
+                }
+                if 1 < count {
+                    step += &format!("[{}]", position);
+                }
+            }
+            steps.push(step);
+            curr = parent;
+        }
+        steps.reverse();
+        if steps.len() == 1 && steps[0] == "" {
+            return String::from("/");
+        }
+        return steps.join("/");
+    }
+
+    // -----------------------------------------------------------------
+    // display_path() の1段 (位置の述語を除く)。
+    //
+    fn path_step(&self) -> String {
+        match self.node_type() {
+            NodeType::Element => return self.name(),
+            NodeType::Attribute => return format!("@{}", self.name()),
+            NodeType::Text => return String::from("text()"),
+            NodeType::Comment => return String::from("comment()"),
+            NodeType::Instruction => return format!("processing-instruction({})", self.name()),
+            NodeType::XMLDecl => return String::from("(XMLDecl)"),
+            NodeType::Directive => return String::from("(Directive)"),
+            NodeType::DocumentRoot => return String::new(),
+        }
+    }
+
+    // -----------------------------------------------------------------
+    // 文字列値 (子孫のテキストを連結したもの) の先頭、max_chars 文字まで。
+    // 大きな要素でも、全体は連結しない。
+    //
+    fn text_prefix(&self, max_chars: usize) -> String {
+        let mut s = String::new();
+        let mut num_chars = 0;
+        let mut stack: Vec<NodePtr> = vec!{ self.rc_clone() };
+        while let Some(node) = stack.pop() {
+            if node.node_type() == NodeType::Text {
+                for c in node.value().chars() {
+                    if max_chars <= num_chars {
+                        return s;
+                    }
+                    s.push(c);
+                    num_chars += 1;
+                }
+            }
+            for ch in node.children().iter().rev() {
+                stack.push(ch.rc_clone());
+            }
+        }
+        return s;
+    }
+
+    // =================================================================
+    // Returns the vector of child nodes.
+    //
+    pub fn children(&self) -> Vec<NodePtr> {
+        let mut node_array: Vec<NodePtr> = vec!{};
+        let rc_node = self.unwrap_rc();
+        for ch in (*rc_node).children.borrow().iter() {
+            node_array.push(wrap_rc_clone(ch));
+        }
+        return node_array;
+    }
+
+    // =================================================================
+    // Returns the vector of attribute nodes.
+    //
+    pub fn attributes(&self) -> Vec<NodePtr> {
+        return (*self.attribute_nodes()).clone();
+    }
+
+    // =================================================================
+    /// Returns the attribute nodes, shared with the element:
+    /// the list is built once and kept until the attributes are
+    /// changed (e.g. by set_attribute() or delete_attribute()), so that
+    /// repeated access does not allocate. cf. attributes()
+    ///
+    /// The returned list is a snapshot; it is not updated by later
+    /// changes of the attributes.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::rc::Rc;
+    /// use amxml::dom::*;
+    /// let doc = new_document(r#"<root a="1" b="2"/>"#).unwrap();
+    /// let mut root = doc.root_element();
+    /// let attrs = root.attribute_nodes();
+    /// assert_eq!(attrs.len(), 2);
+    /// assert_eq!(attrs[1].value(), "2");
+    /// assert!(Rc::ptr_eq(&attrs, &root.attribute_nodes()));
+    ///
+    /// root.set_attribute("c", "3").unwrap();
+    /// assert_eq!(attrs.len(), 2);
+    /// assert_eq!(root.attribute_nodes().len(), 3);
+    /// ```
+    ///
+    pub fn attribute_nodes(&self) -> Rc<Vec<NodePtr>> {
+        let mut cache = self.rc_node.attribute_cache.borrow_mut();
+        if let Some(ref nodes) = *cache {
+            return Rc::clone(nodes);
+        }
+        let nodes: Rc<Vec<NodePtr>> = Rc::new(
+                self.rc_node.attributes.borrow().iter().map(wrap_rc_clone).collect());
+        *cache = Some(Rc::clone(&nodes));
+        return nodes;
+    }
+
+    // =================================================================
+    /// Returns the first child of the node.
+    /// This is equivalent to: nth_child(0)
+    ///
+    pub fn first_child(&self) -> Option<NodePtr> {
+        return self.nth_child(0);
+    }
+
+    // =================================================================
+    /// Returns the n'th child of the node, or None if there is
+    /// no such child.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use amxml::dom::*;
+    /// let xml_string = r#"<article><a/>foo<b/></article>"#;
+    /// let doc = new_document(&xml_string).unwrap();
+    /// let root_elem = doc.root_element();
+    /// assert_eq!(root_elem.name(), "article");
+    /// let ch0 = root_elem.first_child().unwrap();
+    /// assert_eq!(ch0.name(), "a");
+    /// let ch1 = root_elem.nth_child(1).unwrap();
+    /// assert_eq!(ch1.value(), "foo");
+    /// let ch2 = root_elem.nth_child(2).unwrap();
+    /// assert_eq!(ch2.name(), "b");
+    /// let ch3 = root_elem.nth_child(3);
+    /// assert!(ch3.is_none());
+    /// ```
+    ///
+    pub fn nth_child(&self, n: usize) -> Option<NodePtr> {
+        let rc_node = self.unwrap_rc();
+        if n < rc_node.children.borrow().len() {
+            return Some(wrap_rc_clone(&(*rc_node).children.borrow()[n]));
+        } else {
+            return None
+        }
+    }
+
+    // =================================================================
+    /// Returns the number of children of the node,
+    /// without building the vector of children.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use amxml::dom::*;
+    /// let doc = new_document(r#"<article><a/>foo<b/></article>"#).unwrap();
+    /// assert_eq!(doc.root_element().child_count(), 3);
+    /// ```
+    ///
+    pub fn child_count(&self) -> usize {
+        return self.unwrap_rc().children.borrow().len();
+    }
+
+    // =================================================================
+    /// Returns the position of the node among the children of its
+    /// parent (0 for the first child), or None if the node has no
+    /// parent or is an attribute.
+    ///
+    /// The positions of the children are numbered at the first call
+    /// after the children are changed, so repeated calls take
+    /// constant time.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use amxml::dom::*;
+    /// let doc = new_document(r#"<article><a/>foo<b v="1"/></article>"#).unwrap();
+    /// let b = doc.get_first_node("//b").unwrap();
+    /// assert_eq!(b.index_in_parent(), Some(2));
+    /// assert_eq!(doc.get_first_node("//b/@v").unwrap().index_in_parent(), None);
+    /// assert_eq!(doc.index_in_parent(), None);
+    ///
+    /// let a = doc.get_first_node("//a").unwrap();
+    /// doc.root_element().delete_child(&a);
+    /// assert_eq!(b.index_in_parent(), Some(1));
+    /// ```
+    ///
+    pub fn index_in_parent(&self) -> Option<usize> {
+        if self.node_type() == NodeType::Attribute {
+            return None;
+        }
+        let parent = self.parent()?;
+        return parent.rc_node.child_position(&self.rc_node);
+    }
+
+    // =================================================================
+    /// Returns the next sibling of the node (text, comment, etc.
+    /// as well as element), or None if it is the last child.
+    /// Attributes have no siblings.
+    /// Takes constant time; cf. index_in_parent().
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use amxml::dom::*;
+    /// let doc = new_document(r#"<article><a/>foo<b/></article>"#).unwrap();
+    /// let a = doc.get_first_node("//a").unwrap();
+    /// let text = a.next_sibling().unwrap();
+    /// assert_eq!(text.value(), "foo");
+    /// assert_eq!(text.next_sibling().unwrap().name(), "b");
+    /// assert!(text.next_sibling().unwrap().next_sibling().is_none());
+    /// ```
+    ///
+    pub fn next_sibling(&self) -> Option<NodePtr> {
+        let index = self.index_in_parent()?;
+        return self.parent()?.nth_child(index + 1);
+    }
+
+    // =================================================================
+    /// Returns the previous sibling of the node,
+    /// or None if it is the first child.
+    /// Takes constant time; cf. index_in_parent().
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use amxml::dom::*;
+    /// let doc = new_document(r#"<article><a/>foo<b/></article>"#).unwrap();
+    /// let b = doc.get_first_node("//b").unwrap();
+    /// assert_eq!(b.previous_sibling().unwrap().value(), "foo");
+    /// assert!(doc.root_element().first_child().unwrap().previous_sibling().is_none());
+    /// ```
+    ///
+    pub fn previous_sibling(&self) -> Option<NodePtr> {
+        let index = self.index_in_parent()?;
+        if index == 0 {
+            return None;
+        }
+        return self.parent()?.nth_child(index - 1);
+    }
+
+    // =================================================================
+    /// Returns true if the node has children in the XPath data model,
+    /// i.e. other than the XML declaration, DOCTYPE and white space
+    /// outside of the root element, as XPath fn:has-children() does;
+    /// without building the vector of children.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use amxml::dom::*;
+    /// let doc = new_document(r#"<?xml version="1.0"?> <article><a/>foo</article>"#).unwrap();
+    /// assert!(doc.has_children());
+    /// assert!(doc.root_element().has_children());
+    /// assert!(! doc.get_first_node("//a").unwrap().has_children());
+    /// ```
+    ///
+    pub fn has_children(&self) -> bool {
+        let rc_node = self.unwrap_rc();
+        let is_document = rc_node.node_type == NodeType::DocumentRoot;
+        return rc_node.children.borrow().iter().any(|ch| match ch.node_type {
+            NodeType::XMLDecl | NodeType::Directive => false,
+            NodeType::Text => ! is_document || ch.value.trim() != "",
+            _ => true,
+        });
+    }
+
+    // =================================================================
+    /// Returns the number of child elements of the node,
+    /// as count(*) in XPath would give,
+    /// without building the vector of children.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use amxml::dom::*;
+    /// let doc = new_document(r#"<article><a/>foo<b/></article>"#).unwrap();
+    /// assert_eq!(doc.root_element().child_element_count(), 2);
+    /// ```
+    ///
+    pub fn child_element_count(&self) -> usize {
+        return self.unwrap_rc().children.borrow().iter()
+                .filter(|ch| ch.node_type == NodeType::Element).count();
+    }
+
+    // =================================================================
+    /// Retrieves the descendant elements whose names (including the
+    /// namespace prefix, as name() returns) match the regular
+    /// expression, in document order;
+    /// the same result as "descendant::*[matches(name(), pattern)]"
+    /// would give, but each distinct name is matched only once.
+    ///
+    /// Available with the feature <code>regex</code>.
+    /// The syntax of the pattern is that of the regex crate.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use amxml::dom::*;
+    /// let xml = r#"<root><item1/><list><item2/><item/></list><item10/></root>"#;
+    /// let doc = new_document(xml).unwrap();
+    /// let items = doc.select_by_name_regex("^item[0-9]+$").unwrap();
+    /// let names: Vec<String> = items.iter().map(|n| n.name()).collect();
+    /// assert_eq!(names, vec!{"item1", "item2", "item10"});
+    /// assert!(doc.select_by_name_regex("[").is_err());
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// - When the pattern is not a valid regular expression.
+    ///
+    #[cfg(feature = "regex")]
+    pub fn select_by_name_regex(&self, pattern: &str) -> Result<Vec<NodePtr>, Box<Error>> {
+        let re = match Regex::new(pattern) {
+            Ok(re) => re,
+            Err(e) => return Err(dynamic_error!(
+                    "Invalid regular expression: {}: {}", pattern, e)),
+        };
+        let mut matched_names: HashMap<String, bool> = HashMap::new();
+        let mut nodes: Vec<NodePtr> = vec!{};
+        let mut stack: Vec<RcNode> = vec!{};
+        for ch in self.unwrap_rc().children.borrow().iter().rev() {
+            stack.push(Rc::clone(ch));
+        }
+        while let Some(rc_node) = stack.pop() {
+            if rc_node.node_type != NodeType::Element {
+                continue;
+            }
+            let matched = match matched_names.get(&rc_node.name) {
+                Some(b) => *b,
+                None => {
+                    let b = re.is_match(&rc_node.name);
+                    matched_names.insert(rc_node.name.clone(), b);
+                    b
+                },
+            };
+            if matched {
+                nodes.push(wrap_rc_clone(&rc_node));
+            }
+            for ch in rc_node.children.borrow().iter().rev() {
+                stack.push(Rc::clone(ch));
+            }
+        }
+        return Ok(nodes);
+    }
+
+    // =================================================================
+    /// Appends the node tree 'new_child' as the last child of
+    /// the element node.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use amxml::dom::*;
+    ///
+    /// let xml_string = r#"<article><a/>foo<b/></article>"#;
+    /// let doc = new_document(&xml_string).unwrap();
+    /// let elem_article = doc.get_first_node("//article").unwrap();
+    ///
+    /// let new_xml_string = r#"<c>baa</c>"#;
+    /// let new_doc = new_document(&new_xml_string).unwrap();
+    /// let elem_c = new_doc.root_element();
+    ///
+    /// // Appends 'c' as the last child of 'article'
+    /// elem_article.append_child(&elem_c);   // NB. don't pass &new_doc
+    /// let result = doc.to_string();
+    /// let guess = r#"<article><a/>foo<b/><c>baa</c></article>"#;
+    /// assert_eq!(result, guess);
+    /// ```
+    ///
+    pub fn append_child(&self, new_child: &NodePtr) {
+        self.append_child_with_fixup(new_child, NamespaceFixup::None);
+    }
+
+    // =================================================================
+    /// Appends the child node tree as the last child of 'self' node,
+    /// fixing up the namespace declarations of the child as specified.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use amxml::dom::*;
+    /// let doc = new_document(r#"<root xmlns:a="http://a"/>"#).unwrap();
+    /// let other = new_document(r#"<x xmlns:b="http://b" xmlns:a="http://a"><b:item a:id="1"/></x>"#).unwrap();
+    /// let item = other.get_first_node("//b:item").unwrap();
+    ///
+    /// doc.root_element().append_child_with_fixup(&item, NamespaceFixup::DeclareMissing);
+    /// assert_eq!(doc.to_string(),
+    ///     r#"<root xmlns:a="http://a"><b:item a:id="1" xmlns:b="http://b"/></root>"#);
+    /// ```
+    ///
+    pub fn append_child_with_fixup(&self, new_child: &NodePtr, fixup: NamespaceFixup) {
+        if fixup == NamespaceFixup::DeclareMissing {
+            new_child.rc_clone().declare_missing_namespaces(self);
+        }
+        let mut rc_self = self.unwrap_rc();
+        let rc_new_child = new_child.unwrap_rc();
+        let mut inserted = new_child.rc_clone();
+        if rc_new_child.parent.is_none() &&
+           rc_new_child.node_type != NodeType::DocumentRoot {
+            // 親のないノード (new_raw_text() で生成したものなど) は、
+            // 親を設定して追加する。
+            let mut rc_new_child_dup = make_new_child_rc_node(
+                rc_new_child.node_type.clone(),
+                &mut rc_self,
+                &rc_new_child.name,
+                &rc_new_child.value,
+                usize::MAX);
+            shallow_copy_rc_rels(&mut rc_new_child_dup, &rc_new_child);
+            inserted = wrap_rc_clone(&rc_new_child_dup);
+        } else {
+            rc_self.push_child(&rc_new_child);
+        }
+        self.clear_document_order();
+        self.notify_mutation(MutationEvent::NodeInserted{
+            parent: self.rc_clone(),
+            node: inserted,
+        });
+    }
+
+    // =================================================================
+    /// Inserts the child node tree as the previous sibling of 'self' node.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use amxml::dom::*;
+    ///
+    /// let xml_string = r#"<article><a/><b/><c/></article>"#;
+    /// let doc = new_document(&xml_string).unwrap();
+    /// let elem_b = doc.get_first_node("//b").unwrap();
+    ///
+    /// let new_xml_string = r#"<x>yyy</x>"#;
+    /// let new_doc = new_document(&new_xml_string).unwrap();
+    /// let elem_x = new_doc.root_element();
+    ///
+    /// // Inserts 'x' as the previous sibling of 'b'
+    /// elem_b.insert_as_previous_sibling(&elem_x);
+    /// let result = doc.to_string();
+    /// let guess = r#"<article><a/><x>yyy</x><b/><c/></article>"#;
+    /// assert_eq!(result, guess);
+    /// ```
+    ///
+    pub fn insert_as_previous_sibling(&self, new_node: &NodePtr) {
+        let parent = match self.parent() {
+            Some(p) => p,
+            None => return,
+        };
+        let n = parent.find_child_index(self);
+        if n != usize::MAX {
+            let mut rc_parent = parent.unwrap_rc();
+            let rc_new_node = new_node.unwrap_rc();
+
+            let mut rc_new_node_dup = make_new_child_rc_node(
+                rc_new_node.node_type.clone(),
+                &mut rc_parent,
+                &rc_new_node.name,
+                &rc_new_node.value,
+                n);
+            shallow_copy_rc_rels(&mut rc_new_node_dup, &rc_new_node);
+            self.clear_document_order();
+            self.notify_mutation(MutationEvent::NodeInserted{
+                parent: parent.rc_clone(),
+                node: wrap_rc_clone(&rc_new_node_dup),
+            });
+        }
+    }
+
+    // =================================================================
+    /// Inserts the child node tree as the next sibling of 'self' node.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use amxml::dom::*;
+    ///
+    /// let xml_string = r#"<article><a/><b/><c/></article>"#;
+    /// let doc = new_document(&xml_string).unwrap();
+    /// let elem_b = doc.get_first_node("//b").unwrap();
+    ///
+    /// let new_xml_string = r#"<x>yyy</x>"#;
+    /// let new_doc = new_document(&new_xml_string).unwrap();
+    /// let elem_x = new_doc.root_element();
+    ///
+    /// // Inserts 'x' as the next sibling of 'b'
+    /// elem_b.insert_as_next_sibling(&elem_x);
+    /// let result = doc.to_string();
+    /// let guess = r#"<article><a/><b/><x>yyy</x><c/></article>"#;
+    /// assert_eq!(result, guess);
+    /// ```
+    ///
+    pub fn insert_as_next_sibling(&self, new_node: &NodePtr) {
+        let parent = match self.parent() {
+            Some(p) => p,
+            None => return,
+        };
+        let n = parent.find_child_index(self);
+        if n != usize::MAX {
+            let mut rc_parent = parent.unwrap_rc();
+            let rc_new_node = new_node.unwrap_rc();
+
+            let mut rc_new_node_dup = make_new_child_rc_node(
+                rc_new_node.node_type.clone(),
+                &mut rc_parent,
+                &rc_new_node.name,
+                &rc_new_node.value,
+                n + 1);
+            shallow_copy_rc_rels(&mut rc_new_node_dup, &rc_new_node);
+            self.clear_document_order();
+            self.notify_mutation(MutationEvent::NodeInserted{
+                parent: parent.rc_clone(),
+                node: wrap_rc_clone(&rc_new_node_dup),
+            });
+        }
+    }
+
+    // =================================================================
+    /// Deletes the child node tree from 'self' node.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use amxml::dom::*;
+    ///
+    /// let xml_string = r#"<article><a/><b/><c/><d/></article>"#;
+    /// let doc = new_document(&xml_string).unwrap();
+    /// let elem_article = doc.get_first_node("//article").unwrap();
+    ///
+    /// let elem_b = elem_article.get_first_node("b").unwrap();
+    ///
+    /// // Deletes the child 'b' from 'article'
+    /// elem_article.delete_child(&elem_b);
+    /// let result = doc.to_string();
+    /// let guess = r#"<article><a/><c/><d/></article>"#;
+    /// assert_eq!(result, guess);
+    /// ```
+    ///
+    pub fn delete_child(&self, target: &NodePtr) {
+        let n = self.find_child_index(target);
+        if n != usize::MAX {
+            let rc_node = self.unwrap_rc();
+            (*rc_node).children_mut().remove(n);
+            self.clear_document_order();
+            self.notify_mutation(MutationEvent::NodeRemoved{
+                parent: self.rc_clone(),
+                node: target.rc_clone(),
+            });
+        }
+    }
+
+    // =================================================================
+    /// Normalizes the subtree of this node, like normalize() of DOM
+    /// Level 2: merges adjacent text nodes into one, and removes empty
+    /// text nodes.
+    /// Raw text nodes (see new_raw_text()) are neither merged nor removed.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use amxml::dom::*;
+    /// let doc = new_document(r#"<root><p>a</p></root>"#).unwrap();
+    /// let mut elem_p = doc.get_first_node("//p").unwrap();
+    /// elem_p.append_child(&new_document("<x>b</x>").unwrap().root_element().first_child().unwrap());
+    /// elem_p.append_child(&new_document("<x>c</x>").unwrap().root_element().first_child().unwrap());
+    /// assert_eq!(elem_p.children().len(), 3);
+    ///
+    /// doc.normalize();
+    /// assert_eq!(elem_p.children().len(), 1);
+    /// assert_eq!(elem_p.first_child().unwrap().value(), "abc");
+    /// assert_eq!(doc.to_string(), "<root><p>abc</p></root>");
+    /// ```
+    ///
+    pub fn normalize(&self) {
+        self.normalize_text_nodes(false);
+    }
+
+    // =================================================================
+    /// Normalizes the subtree of this node as normalize(), and also
+    /// trims leading and trailing whitespace of each (merged) text node,
+    /// removing the text nodes that become empty;
+    /// except in the elements where xml:space="preserve" is in effect.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use amxml::dom::*;
+    /// let doc = new_document(r#"<root>
+    ///     <p> a b </p>
+    ///     <pre xml:space="preserve"> c </pre>
+    /// </root>"#).unwrap();
+    /// doc.normalize_with_trim();
+    /// assert_eq!(doc.to_string(), r#"<root><p>a b</p><pre xml:space="preserve"> c </pre></root>"#);
+    /// ```
+    ///
+    pub fn normalize_with_trim(&self) {
+        self.normalize_text_nodes(true);
+    }
+
+    // -----------------------------------------------------------------
+    // 部分木の各要素について、隣接する (rawでない) テキスト・ノードを
+    // ひとつにまとめ、空のテキスト・ノードを削除する。
+    // trim: 前後の空白も削除するか否か (xml:space="preserve" の範囲を除く)。
+    // 深い木でもスタックを使い切らないよう、再帰せず作業スタックでたどる。
+    //
+    fn normalize_text_nodes(&self, trim: bool) {
+        let mut stack: Vec<NodePtr> = vec!{ self.rc_clone() };
+        while let Some(node) = stack.pop() {
+            let children = node.children();
+            let trim_here = trim && ! node.preserves_space();
+            let mut run: Vec<NodePtr> = vec!{};
+            for ch in children.iter() {
+                if ch.node_type() == NodeType::Text && ! ch.is_raw_text() {
+                    run.push(ch.rc_clone());
+                    continue;
+                }
+                node.merge_text_nodes(&run, trim_here);
+                run.clear();
+                if ch.node_type() == NodeType::Element {
+                    stack.push(ch.rc_clone());
+                }
+            }
+            node.merge_text_nodes(&run, trim_here);
+        }
+    }
+
+    // -----------------------------------------------------------------
+    // 子である連続したテキスト・ノード run を、値を連結したひとつの
+    // テキスト・ノードに置き換える (空ならば削除する)。
+    // 変化がなければ何もしない。
+    //
+    fn merge_text_nodes(&self, run: &Vec<NodePtr>, trim: bool) {
+        if run.len() == 0 {
+            return;
+        }
+        let mut value = String::new();
+        for text in run.iter() {
+            value += &text.value();
+        }
+        if trim {
+            value = String::from(value.trim_matches(is_space_char));
+        }
+        if run.len() == 1 && value != "" && value == run[0].value() {
+            return;
+        }
+
+        let index = self.find_child_index(&run[0]);
+        for text in run.iter() {
+            self.delete_child(text);
+        }
+        if value != "" {
+            let mut rc_node = self.unwrap_rc();
+            let rc_text = make_new_child_rc_node(NodeType::Text,
+                            &mut rc_node, "", &value, index);
+            self.clear_document_order();
+            self.notify_mutation(MutationEvent::NodeInserted{
+                parent: self.rc_clone(),
+                node: wrap_rc_clone(&rc_text),
+            });
+        }
+    }
+
+    // =================================================================
+    /// Replaces the child node tree with 'self' node.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use amxml::dom::*;
+    /// let xml_string = r#"<article><a/><b/><c/></article>"#;
+    /// let doc = new_document(&xml_string).unwrap();
+    /// let elem_b = doc.get_first_node("//b").unwrap();
+    ///
+    /// let new_xml_string = r#"<x>yyy</x>"#;
+    /// let new_doc = new_document(&new_xml_string).unwrap();
+    /// let elem_x = new_doc.root_element();
+    ///
+    /// // Replace 'b' with 'x'
+    /// elem_b.replace_with(&elem_x);
+    /// let result = doc.to_string();
+    /// let guess = r#"<article><a/><x>yyy</x><c/></article>"#;
+    /// assert_eq!(result, guess);
+    /// ```
+    ///
+    pub fn replace_with(&self, new_node: &NodePtr) {
+        self.replace_with_fixup(new_node, NamespaceFixup::None);
+    }
+
+    // =================================================================
+    /// Replaces the child node tree with 'self' node,
+    /// fixing up the namespace declarations of the new node as specified.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use amxml::dom::*;
+    /// let doc = new_document(r#"<root xmlns="http://def"><old/></root>"#).unwrap();
+    /// let other = new_document(r#"<x xmlns:p="http://p"><p:new><plain/></p:new></x>"#).unwrap();
+    /// let elem_new = other.get_first_node("//p:new").unwrap();
+    ///
+    /// let elem_old = doc.root_element().first_child().unwrap();
+    /// elem_old.replace_with_fixup(&elem_new, NamespaceFixup::DeclareMissing);
+    /// assert_eq!(doc.to_string(),
+    ///     r#"<root xmlns="http://def"><p:new xmlns:p="http://p" xmlns=""><plain/></p:new></root>"#);
+    /// ```
+    ///
+    pub fn replace_with_fixup(&self, new_node: &NodePtr, fixup: NamespaceFixup) {
+        let parent = match self.parent() {
+            Some(p) => p,
+            None => return,
+        };
+        if fixup == NamespaceFixup::DeclareMissing {
+            new_node.rc_clone().declare_missing_namespaces(&parent);
+        }
+        self.insert_as_previous_sibling(new_node);
+        parent.delete_child(self);
+        self.clear_document_order();
+    }
+
+    // =================================================================
+    /// Sorts the child elements of each node selected by parent_xpath
+    /// (evaluated with self as the context node), by the key that
+    /// key_xpath yields for each child, e.g. to write configuration
+    /// files in a deterministic order for version control.
+    ///
+    /// The key is the first item of the (atomized) result of key_xpath,
+    /// or the empty string if the result is empty.
+    /// Numeric keys (e.g. <code>number(@order)</code>) are compared as
+    /// numbers and precede the other keys, which are compared as
+    /// strings in codepoint order.
+    /// The sort is stable: the elements with the same key keep their
+    /// order.
+    /// Only the elements move; text, comments and processing
+    /// instructions stay at their positions, so that indentation is
+    /// kept.
+    ///
+    /// Each element that moves is notified to the mutation observers
+    /// as MutationEvent::NodeInserted.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use amxml::dom::*;
+    /// let xml = r#"<config>
+    ///   <server name="b" port="80"/>
+    ///   <!-- main -->
+    ///   <server name="a" port="8080"/>
+    ///   <server name="c" port="443"/>
+    /// </config>"#;
+    /// let doc = new_document(xml).unwrap();
+    /// doc.sort_children_by("/config", "@name").unwrap();
+    /// assert_eq!(doc.to_string(), r#"<config>
+    ///   <server name="a" port="8080"/>
+    ///   <!-- main -->
+    ///   <server name="b" port="80"/>
+    ///   <server name="c" port="443"/>
+    /// </config>"#);
+    ///
+    /// doc.sort_children_by("/config", "number(@port)").unwrap();
+    /// let ports: Vec<String> = doc.get_nodeset("/config/server").unwrap()
+    ///         .iter().map(|n| n.attribute_value("port").unwrap()).collect();
+    /// assert_eq!(ports, vec!{"80", "443", "8080"});
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// - When syntax error or unimplemented feature in the xpaths.
+    /// - When the evaluation of the xpaths fails.
+    ///
+    pub fn sort_children_by(&self, parent_xpath: &str,
+                key_xpath: &str) -> Result<(), Box<Error>> {
+        let parent_xnode = compile_xpath(&String::from(parent_xpath))?;
+        let key_xnode = compile_xpath(&String::from(key_xpath))?;
+        for parent in match_xpath(self, &parent_xnode)?.to_nodeset().iter() {
+            let children = parent.children();
+            let mut keyed: Vec<(SortKey, NodePtr)> = vec!{};
+            for ch in children.iter() {
+                if ch.node_type() == NodeType::Element {
+                    keyed.push((sort_key(ch, &key_xnode)?, ch.rc_clone()));
+                }
+            }
+            keyed.sort_by(|a, b| compare_sort_keys(&a.0, &b.0));
+
+            let mut sorted = keyed.into_iter().map(|(_, elem)| elem);
+            let mut new_children: Vec<RcNode> = vec!{};
+            let mut moved: Vec<NodePtr> = vec!{};
+            for ch in children.iter() {
+                if ch.node_type() != NodeType::Element {
+                    new_children.push(ch.unwrap_rc());
+                    continue;
+                }
+                if let Some(elem) = sorted.next() {
+                    if elem != *ch {
+                        moved.push(elem.rc_clone());
+                    }
+                    new_children.push(elem.unwrap_rc());
+                }
+            }
+            if moved.is_empty() {
+                continue;
+            }
+            *parent.unwrap_rc().children_mut() = new_children;
+            parent.clear_document_order();
+            for elem in moved.iter() {
+                parent.notify_mutation(MutationEvent::NodeInserted{
+                    parent: parent.rc_clone(),
+                    node: elem.rc_clone(),
+                });
+            }
+        }
+        return Ok(());
+    }
+
+    // =================================================================
+    /// Declares the namespace on this element, that is, sets the
+    /// attribute xmlns:prefix (or xmlns if prefix is empty) to uri.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use amxml::dom::*;
+    /// let doc = new_document(r#"<root><p:a/></root>"#).unwrap();
+    /// let mut root = doc.root_element();
+    /// root.set_namespace_declaration("p", "http://p").unwrap();
+    /// root.set_namespace_declaration("", "http://def").unwrap();
+    /// assert_eq!(doc.to_string(),
+    ///     r#"<root xmlns:p="http://p" xmlns="http://def"><p:a/></root>"#);
+    /// assert_eq!(doc.get_first_node("//p:a").unwrap().namespace_uri(), "http://p");
+    /// assert!(root.set_namespace_declaration("p q", "http://pq").is_err());
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// - prefix is neither empty nor an NCName.
+    ///
+    pub fn set_namespace_declaration(&mut self, prefix: &str, uri: &str) -> Result<(), Box<Error>> {
+        if prefix != "" && ! is_ncname(prefix) {
+            return Err(dynamic_error!("Invalid namespace prefix: {}", prefix));
+        }
+        self.set_attribute_unchecked(&xmlns_attribute_name(prefix), uri);
+        return Ok(());
+    }
+
+    // =================================================================
+    /// Returns the namespace declarations on this element
+    /// (not including those inherited from ancestors),
+    /// as pairs of prefix (empty for default namespace) and URI,
+    /// in the order of attributes.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use amxml::dom::*;
+    /// let doc = new_document(r#"<root xmlns="http://def" id="1" xmlns:p="http://p"><a/></root>"#).unwrap();
+    /// let decls = doc.root_element().declared_namespaces();
+    /// assert_eq!(decls, vec!{
+    ///     (String::from(""), String::from("http://def")),
+    ///     (String::from("p"), String::from("http://p")),
+    /// });
+    /// let elem_a = doc.root_element().first_child().unwrap();
+    /// assert!(elem_a.declared_namespaces().is_empty());
+    /// ```
+    ///
+    pub fn declared_namespaces(&self) -> Vec<(String, String)> {
+        let mut decls: Vec<(String, String)> = vec!{};
+        for at in self.attribute_nodes().iter() {
+            let name = at.name();
+            if name == "xmlns" {
+                decls.push((String::new(), at.value()));
+            } else if name.starts_with("xmlns:") {
+                decls.push((String::from(&name["xmlns:".len() ..]), at.value()));
+            }
+        }
+        return decls;
+    }
+
+    // -----------------------------------------------------------------
+    // この要素 (または祖先) において、接頭辞 prefix に束縛されている
+    // 名前空間URI。束縛されていなければNone。
+    //
+    fn lookup_namespace(&self, prefix: &str) -> Option<String> {
+        let xmlns_attr = xmlns_attribute_name(prefix);
+        let mut curr = self.rc_clone();
+        loop {
+            if curr.node_type() == NodeType::Element {
+                if let Some(uri) = curr.attribute_value(&xmlns_attr) {
+                    return Some(uri);
+                }
+            }
+            curr = match curr.parent() {
+                Some(p) => p,
+                None => return None,
+            };
+        }
+    }
+
+    // -----------------------------------------------------------------
+    // この部分木 (元の位置にある) を new_parent の子として移動する前に、
+    // 部分木の外 (元の祖先) で宣言された名前空間のうち、部分木内で
+    // 使っているものを、移動先での束縛と異なれば、部分木の根に宣言する。
+    //
+    fn declare_missing_namespaces(&mut self, new_parent: &NodePtr) {
+        if self.node_type() != NodeType::Element {
+            return;
+        }
+        let mut free_prefixes: Vec<String> = vec!{};
+        collect_free_prefixes(self, &mut free_prefixes);
+        for prefix in free_prefixes.iter() {
+            let old_uri = self.lookup_namespace(prefix).unwrap_or(String::new());
+            let new_uri = new_parent.lookup_namespace(prefix).unwrap_or(String::new());
+            if old_uri != new_uri && (old_uri != "" || prefix == "") {
+                self.set_attribute_unchecked(&xmlns_attribute_name(prefix), &old_uri);
+            }
+        }
+    }
+
+    // -----------------------------------------------------------------
+    // 部分木内で使っているが、部分木の外 (祖先) で宣言されている
+    // 名前空間の宣言 (属性名, 名前空間URI) の一覧。
+    // 祖先で束縛されていない既定の名前空間は、宣言しなくてよい。
+    //
+    fn inherited_namespace_decls(&self) -> Vec<(String, String)> {
+        let mut decls: Vec<(String, String)> = vec!{};
+        if self.node_type() != NodeType::Element {
+            return decls;
+        }
+        let mut free_prefixes: Vec<String> = vec!{};
+        collect_free_prefixes(self, &mut free_prefixes);
+        for prefix in free_prefixes.iter() {
+            if let Some(uri) = self.lookup_namespace(prefix) {
+                if uri != "" {
+                    decls.push((xmlns_attribute_name(prefix), uri));
+                }
+            }
+        }
+        return decls;
+    }
+
+    // -----------------------------------------------------------------
+    // find_child_index
+    //
+    fn find_child_index(&self, target: &NodePtr) -> usize {
+        return self.rc_node.child_position(&target.rc_node).unwrap_or(usize::MAX);
+    }
+
+    // =================================================================
+    /// Returns the attribute value of element,
+    /// or None if there is no such attribute.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use amxml::dom::*;
+    /// let xml_string = r#"<article id="a1">foo</article>"#;
+    /// let doc = new_document(&xml_string).unwrap();
+    /// let root_elem = doc.root_element();
+    /// assert_eq!(root_elem.attribute_value("id").unwrap(), "a1");
+    /// assert!(root_elem.attribute_value("none").is_none());
+    /// ```
+    ///
+    pub fn attribute_value(&self, name: &str) -> Option<String> {
+        let r_index = self.find_attribute_index(name);
+        if r_index != usize::MAX {
+            let rc_node = self.unwrap_rc();
+            return Some((*rc_node).attributes.borrow()[r_index].value.clone());
+        } else {
+            return None;
+        }
+    }
+
+    // =================================================================
+    /// Returns true if the element has the attribute.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use amxml::dom::*;
+    /// let xml_string = r#"<article id="a1">foo</article>"#;
+    /// let doc = new_document(&xml_string).unwrap();
+    /// let root_elem = doc.root_element();
+    /// assert!(root_elem.has_attribute("id"));
+    /// assert!(! root_elem.has_attribute("none"));
+    /// ```
+    ///
+    pub fn has_attribute(&self, name: &str) -> bool {
+        return self.find_attribute_index(name) != usize::MAX;
+    }
+
+    // =================================================================
+    /// Returns the attribute value of element, parsed as T by FromStr
+    /// after removing leading and trailing white space,
+    /// or None if there is no such attribute.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use amxml::dom::*;
+    /// let xml_string = r#"<item count=" 3 " price="a"/>"#;
+    /// let doc = new_document(&xml_string).unwrap();
+    /// let root_elem = doc.root_element();
+    /// assert_eq!(root_elem.attribute_as::<u32>("count").unwrap(), Some(3));
+    /// assert_eq!(root_elem.attribute_as::<u32>("none").unwrap(), None);
+    /// assert!(root_elem.attribute_as::<f64>("price").is_err());
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// - When the attribute value can't be parsed as T [err:FORG0001].
+    ///
+    pub fn attribute_as<T>(&self, name: &str) -> Result<Option<T>, Box<Error>>
+                where T: FromStr, T::Err: fmt::Display {
+        let value = match self.attribute_value(name) {
+            Some(value) => value,
+            None => return Ok(None),
+        };
+        match value.trim_matches(is_space_char).parse::<T>() {
+            Ok(v) => return Ok(Some(v)),
+            Err(e) => return Err(dynamic_error!(
+                    "Invalid value of attribute {}: \"{}\": {} [err:FORG0001]",
+                    name, value, e)),
+        }
+    }
+
+    // =================================================================
+    /// Returns the attribute value of element as boolean,
+    /// by the lexical space of xs:boolean, i.e. "true" or "1" for true,
+    /// "false" or "0" for false,
+    /// or None if there is no such attribute.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use amxml::dom::*;
+    /// let xml_string = r#"<item a="true" b="0" c="yes"/>"#;
+    /// let doc = new_document(&xml_string).unwrap();
+    /// let root_elem = doc.root_element();
+    /// assert_eq!(root_elem.attribute_bool("a").unwrap(), Some(true));
+    /// assert_eq!(root_elem.attribute_bool("b").unwrap(), Some(false));
+    /// assert!(root_elem.attribute_bool("c").is_err());
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// - When the attribute value is not a boolean [err:FORG0001].
+    ///
+    pub fn attribute_bool(&self, name: &str) -> Result<Option<bool>, Box<Error>> {
+        let value = match self.attribute_value(name) {
+            Some(value) => value,
+            None => return Ok(None),
+        };
+        match value.trim_matches(is_space_char) {
+            "true" | "1" => return Ok(Some(true)),
+            "false" | "0" => return Ok(Some(false)),
+            _ => return Err(dynamic_error!(
+                    "Invalid value of attribute {}: \"{}\": not a boolean [err:FORG0001]",
+                    name, value)),
+        }
+    }
+
+    // =================================================================
+    /// Returns the attribute value of element as integer,
+    /// or None if there is no such attribute.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use amxml::dom::*;
+    /// let xml_string = r#"<item a="-12" b="1.5"/>"#;
+    /// let doc = new_document(&xml_string).unwrap();
+    /// let root_elem = doc.root_element();
+    /// assert_eq!(root_elem.attribute_int("a").unwrap(), Some(-12));
+    /// assert!(root_elem.attribute_int("b").is_err());
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// - When the attribute value is not an integer [err:FORG0001].
+    ///
+    pub fn attribute_int(&self, name: &str) -> Result<Option<i64>, Box<Error>> {
+        return self.attribute_as::<i64>(name);
+    }
+
+    // =================================================================
+    /// Returns the attribute value of element as floating point number,
+    /// accepting "INF", "-INF" and "NaN" as in xs:double,
+    /// or None if there is no such attribute.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use amxml::dom::*;
+    /// let xml_string = r#"<item a="1.5e2" b="-INF" c="inf"/>"#;
+    /// let doc = new_document(&xml_string).unwrap();
+    /// let root_elem = doc.root_element();
+    /// assert_eq!(root_elem.attribute_float("a").unwrap(), Some(150.0));
+    /// assert_eq!(root_elem.attribute_float("b").unwrap(), Some(std::f64::NEG_INFINITY));
+    /// assert!(root_elem.attribute_float("c").is_err());
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// - When the attribute value is not a number [err:FORG0001].
+    ///
+    pub fn attribute_float(&self, name: &str) -> Result<Option<f64>, Box<Error>> {
+        let value = match self.attribute_value(name) {
+            Some(value) => value,
+            None => return Ok(None),
+        };
+        let v = value.trim_matches(is_space_char);
+        let parsed = match v {
+            "INF" | "+INF" => Some(f64::INFINITY),
+            "-INF" => Some(f64::NEG_INFINITY),
+            "NaN" => Some(f64::NAN),
+            _ if v.contains(|c: char| c.is_ascii_alphabetic() &&
+                                      c != 'e' && c != 'E') => None,
+            _ => v.parse::<f64>().ok(),
+        };
+        match parsed {
+            Some(f) => return Ok(Some(f)),
+            None => return Err(dynamic_error!(
+                    "Invalid value of attribute {}: \"{}\": not a number [err:FORG0001]",
+                    name, value)),
+        }
+    }
+
+    // =================================================================
+    /// Returns an iterator over the (name, value) pairs of the attributes
+    /// of element, in the order they appear in the start tag.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use amxml::dom::*;
+    /// let xml_string = r#"<article id="a1" title="t">foo</article>"#;
+    /// let doc = new_document(&xml_string).unwrap();
+    /// let root_elem = doc.root_element();
+    /// assert_eq!(root_elem.attributes_iter().len(), 2);
+    /// let mut s = String::new();
+    /// for (name, value) in root_elem.attributes_iter() {
+    ///     s += &format!("{}={}; ", name, value);
+    /// }
+    /// assert_eq!(s, "id=a1; title=t; ");
+    /// ```
+    ///
+    pub fn attributes_iter(&self) -> impl ExactSizeIterator<Item=(String, String)> {
+        let rc_node = self.unwrap_rc();
+        let pairs: Vec<(String, String)> = (*rc_node).attributes.borrow().iter()
+                .map(|at| (at.name.clone(), at.value.clone()))
+                .collect();
+        return pairs.into_iter();
+    }
+
+    // =================================================================
+    /// Renames the attribute (if exists) of element,
+    /// keeping its value and position.
+    /// If the element already has an attribute named new_name,
+    /// it is deleted.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use amxml::dom::*;
+    /// let xml_string = r#"<article id="a1" title="t">foo</article>"#;
+    /// let doc = new_document(&xml_string).unwrap();
+    /// let mut root_elem = doc.root_element();
+    /// root_elem.rename_attribute("id", "xml:id").unwrap();
+    /// assert_eq!(doc.to_string(), r#"<article xml:id="a1" title="t">foo</article>"#);
+    /// root_elem.rename_attribute("title", "xml:id").unwrap();
+    /// assert_eq!(doc.to_string(), r#"<article xml:id="t">foo</article>"#);
+    /// assert!(root_elem.rename_attribute("xml:id", "<id>").is_err());
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// - new_name is not a QName.
+    ///
+    pub fn rename_attribute(&mut self, old_name: &str, new_name: &str) -> Result<(), Box<Error>> {
+        return self.rename_attribute_with_check(old_name, new_name, NameCheck::Validate);
+    }
+
+    // =================================================================
+    /// Renames the attribute of element, as rename_attribute(),
+    /// but checks new_name only if check is NameCheck::Validate.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use amxml::dom::*;
+    /// let doc = new_document(r#"<article id="a1">foo</article>"#).unwrap();
+    /// let mut root_elem = doc.root_element();
+    /// root_elem.rename_attribute_with_check("id", "my id", NameCheck::Bypass).unwrap();
+    /// assert_eq!(doc.to_string(), r#"<article my id="a1">foo</article>"#);
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// - new_name is not a QName, and check is NameCheck::Validate.
+    ///
+    pub fn rename_attribute_with_check(&mut self, old_name: &str, new_name: &str,
+                check: NameCheck) -> Result<(), Box<Error>> {
+        check_name(new_name, "attribute", check)?;
+        self.rename_attribute_unchecked(old_name, new_name);
+        return Ok(());
+    }
+
+    // -----------------------------------------------------------------
+    //
+    fn rename_attribute_unchecked(&mut self, old_name: &str, new_name: &str) {
+        let r_index = self.find_attribute_index(old_name);
+        if r_index == usize::MAX || old_name == new_name {
+            return;
+        }
+
+        let mut rc_node = self.unwrap_rc();
+        let value = (*rc_node).attributes.borrow()[r_index].value.clone();
+        let old_new_value = self.attribute_value(new_name);
+        let attr_node = make_new_rc_node(NodeType::Attribute,
+                            Some(&mut rc_node), new_name, &value);
+        (*rc_node).attributes_mut()[r_index] = Rc::clone(&attr_node);
+
+        let dup_index = (*rc_node).attributes.borrow().iter().enumerate()
+                .position(|(i, at)| i != r_index && at.name == new_name);
+        if let Some(i) = dup_index {
+            (*rc_node).attributes_mut().remove(i);
+        }
+        self.clear_document_order();
+        self.notify_mutation(MutationEvent::AttributeChanged{
+            element: self.rc_clone(),
+            name: String::from(old_name),
+            old_value: Some(value.clone()),
+            new_value: None,
+        });
+        self.notify_mutation(MutationEvent::AttributeChanged{
+            element: self.rc_clone(),
+            name: String::from(new_name),
+            old_value: old_new_value,
+            new_value: Some(value),
+        });
+    }
+
+    // =================================================================
+    /// Updates the attribute value (if already exists) of element,
+    /// or adds the attribute (if not exist).
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use amxml::dom::*;
+    /// let xml_string = r#"<article id="a1">foo</article>"#;
+    /// let doc = new_document(&xml_string).unwrap();
+    /// let mut root_elem = doc.root_element();
+    /// assert_eq!(root_elem.attribute_value("id").unwrap(), "a1");
+    /// root_elem.set_attribute("id", "b1").unwrap();
+    /// assert_eq!(root_elem.attribute_value("id").unwrap(), "b1");
+    /// root_elem.set_attribute("title", "about xml").unwrap();
+    /// assert_eq!(root_elem.attribute_value("title").unwrap(), "about xml");
+    /// assert_eq!(doc.to_string(), r#"<article id="b1" title="about xml">foo</article>"#);
+    /// assert!(root_elem.set_attribute("sub title", "none").is_err());
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// - name is not a QName.
+    ///
+    pub fn set_attribute(&mut self, name: &str, value: &str) -> Result<(), Box<Error>> {
+        return self.set_attribute_with_check(name, value, NameCheck::Validate);
+    }
+
+    // =================================================================
+    /// Updates or adds the attribute of element, as set_attribute(),
+    /// but checks name only if check is NameCheck::Validate.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use amxml::dom::*;
+    /// let doc = new_document(r#"<article>foo</article>"#).unwrap();
+    /// let mut root_elem = doc.root_element();
+    /// root_elem.set_attribute_with_check("sub title", "none", NameCheck::Bypass).unwrap();
+    /// assert_eq!(doc.to_string(), r#"<article sub title="none">foo</article>"#);
+    /// assert!(doc.validate_well_formedness().is_err());
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// - name is not a QName, and check is NameCheck::Validate.
+    ///
+    pub fn set_attribute_with_check(&mut self, name: &str, value: &str,
+                check: NameCheck) -> Result<(), Box<Error>> {
+        check_name(name, "attribute", check)?;
+        self.set_attribute_unchecked(name, value);
+        return Ok(());
+    }
+
+    // -----------------------------------------------------------------
+    //
+    fn set_attribute_unchecked(&mut self, name: &str, value: &str) {
+
+        let mut rc_node = self.unwrap_rc();
+        let attr_node = make_new_rc_node(NodeType::Attribute,
+                            Some(&mut rc_node), name, value);
+
+        let old_value = self.attribute_value(name);
+        let r_index = self.find_attribute_index(name);
+        if r_index != usize::MAX {
+            (*rc_node).attributes_mut().remove(r_index);
+            (*rc_node).attributes_mut().insert(r_index, Rc::clone(&attr_node));
+        } else {
+            (*rc_node).attributes_mut().push(Rc::clone(&attr_node));
+        }
+        self.clear_document_order();
+        self.notify_mutation(MutationEvent::AttributeChanged{
+            element: self.rc_clone(),
+            name: String::from(name),
+            old_value: old_value,
+            new_value: Some(String::from(value)),
+        });
+    }
+
+    // =================================================================
+    /// Deletes the attribute (if already exists) of element.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use amxml::dom::*;
+    /// let xml_string = r#"<article id="a1">foo</article>"#;
     /// let doc = new_document(&xml_string).unwrap();
-    /// let elem_chapter = doc.get_first_node("//chapter").unwrap();
-    /// let p = elem_chapter.parent().unwrap();
-    /// assert_eq!(p.name(), "article");
+    /// let mut root_elem = doc.root_element();
+    /// root_elem.delete_attribute("id");
+    /// assert!(root_elem.attribute_value("id").is_none());
+    /// assert_eq!(doc.to_string(), r#"<article>foo</article>"#);
+    /// ```
+    ///
+    pub fn delete_attribute(&mut self, name: &str) {
+        let r_index = self.find_attribute_index(name);
+        if r_index != usize::MAX {
+            let rc_node = self.unwrap_rc();
+            let old_value = (*rc_node).attributes.borrow()[r_index].value.clone();
+            (*rc_node).attributes_mut().remove(r_index);
+            self.clear_document_order();
+            self.notify_mutation(MutationEvent::AttributeChanged{
+                element: self.rc_clone(),
+                name: String::from(name),
+                old_value: Some(old_value),
+                new_value: None,
+            });
+        }
+    }
+
+    // =================================================================
+    /// Evaluates the xpath that selects attribute nodes, with this node
+    /// as the context node, and replaces the value of each attribute
+    /// with func(old value).
+    /// Returns the number of attributes whose value is changed.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use amxml::dom::*;
+    /// let doc = new_document(r#"<root><a href=" x.html "/><a href="y.html"/></root>"#).unwrap();
+    /// let n = doc.update_attributes("//@href", |v| v.trim().to_string()).unwrap();
+    /// assert_eq!(n, 1);
+    /// assert_eq!(doc.to_string(), r#"<root><a href="x.html"/><a href="y.html"/></root>"#);
+    ///
+    /// doc.update_attributes("//a[@href = 'y.html']/@href", |v| format!("/{}", v)).unwrap();
+    /// assert_eq!(doc.to_string(), r#"<root><a href="x.html"/><a href="/y.html"/></root>"#);
+    /// assert!(doc.update_attributes("//a", |v| v.to_string()).is_err());
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// - When syntax error or unimplemented feature in xpath.
+    /// - When the xpath selects any node other than attribute;
+    ///   no attribute is changed then.
+    ///
+    pub fn update_attributes<F>(&self, xpath: &str, mut func: F) -> Result<usize, Box<Error>>
+                where F: FnMut(&str) -> String {
+        let nodeset = self.get_nodeset(xpath)?;
+        for node in nodeset.iter() {
+            if node.node_type() != NodeType::Attribute {
+                return Err(dynamic_error!(
+                    "update_attributes: not an attribute node: {}", node));
+            }
+        }
+
+        let mut count = 0;
+        for attr in nodeset.iter() {
+            let old_value = attr.value();
+            let new_value = func(&old_value);
+            if new_value == old_value {
+                continue;
+            }
+            if let Some(mut elem) = attr.parent() {
+                elem.set_attribute_unchecked(&attr.name(), &new_value);
+                count += 1;
+            }
+        }
+        return Ok(count);
+    }
+
+    // =================================================================
+    /// Creates a text node, without parent, that is serialized as is,
+    /// without escaping: for embedding pre-escaped content, such as
+    /// a fragment of XML generated elsewhere.
+    /// Insert it with append_child(), insert_as_next_sibling(), etc.
+    ///
+    /// # Caution
+    ///
+    /// The text is written into the output verbatim; it is up to the
+    /// caller to make sure that it is well-formed XML content.
+    /// Otherwise the output is broken, and if the text comes from
+    /// untrusted input, markup may be injected into the output.
+    ///
+    /// Only the serialization (to_string() and the like) treats the
+    /// node specially; to XPath, c14n, etc., it is an ordinary text
+    /// node whose value is the text.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use amxml::dom::*;
+    /// let doc = new_document("<root/>").unwrap();
+    /// let root = doc.root_element();
+    /// root.append_child(&NodePtr::new_raw_text("<b>&#169; 2018</b>"));
+    /// assert_eq!(doc.to_string(), "<root><b>&#169; 2018</b></root>");
+    /// assert!(root.first_child().unwrap().is_raw_text());
+    /// assert_eq!(doc.eval_xpath("string(/root)").unwrap().to_string(),
+    ///     r#""<b>&#169; 2018</b>""#);
+    /// ```
+    ///
+    pub fn new_raw_text(text: &str) -> NodePtr {
+        let rc_node = make_new_rc_node(NodeType::Text, None, "", text);
+        rc_node.raw.set(true);
+        return wrap_rc_clone(&rc_node);
+    }
+
+    // =================================================================
+    /// Returns true if the node is a text node created by
+    /// new_raw_text(), which is serialized without escaping.
+    ///
+    pub fn is_raw_text(&self) -> bool {
+        return self.unwrap_rc().raw.get();
+    }
+
+    // =================================================================
+    /// Replaces the children of element with a text node of the text
+    /// (or with nothing if the text is empty).
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use amxml::dom::*;
+    /// let doc = new_document(r#"<article><title>old <em>one</em></title></article>"#).unwrap();
+    /// let title = doc.get_first_node("//title").unwrap();
+    /// title.set_text("new & improved");
+    /// assert_eq!(doc.to_string(), r#"<article><title>new &amp; improved</title></article>"#);
+    /// ```
+    ///
+    pub fn set_text(&self, text: &str) {
+        let mut rc_node = self.unwrap_rc();
+        (*rc_node).children_mut().clear();
+        if text != "" {
+            make_new_child_rc_node(NodeType::Text, &mut rc_node, "", text, usize::MAX);
+        }
+        self.clear_document_order();
+        self.notify_mutation(MutationEvent::TextChanged{
+            element: self.rc_clone(),
+        });
+    }
+
+    // =================================================================
+    /// Re-formats the tree under this node (usually the document)
+    /// in place, so that to_string() gives the output indented by
+    /// step spaces per level:
+    /// replaces the ignorable white space (cf. SerializeOptions#minify())
+    /// with text nodes of a line break and indentation.
+    /// With step 0, just removes the ignorable white space.
+    ///
+    /// Unlike to_pretty_string(), the content of the elements that have
+    /// non white space text (mixed content), or are under
+    /// xml:space="preserve", is left as is.
+    /// The insertion and removal of the text nodes are notified to
+    /// the observers registered by on_mutation().
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use amxml::dom::*;
+    /// let xml_string = r#"<article>  <title>About <em>XML</em></title><body><p/>
+    /// </body></article>"#;
+    /// let doc = new_document(&xml_string).unwrap();
+    /// doc.format_in_place(2);
+    /// assert_eq!(doc.to_string(), r#"<article>
+    ///   <title>About <em>XML</em></title>
+    ///   <body>
+    ///     <p/>
+    ///   </body>
+    /// </article>
+    /// "#);
+    /// assert_eq!(doc.get_first_node("//body").unwrap().children().len(), 3);
+    /// doc.format_in_place(0);
+    /// assert_eq!(doc.to_string(),
+    ///     r#"<article><title>About <em>XML</em></title><body><p/></body></article>"#);
+    /// ```
+    ///
+    pub fn format_in_place(&self, step: usize) {
+        let mut level = 0;
+        let mut curr = self.parent();
+        while let Some(p) = curr {
+            if p.node_type() == NodeType::Element {
+                level += step;
+            }
+            curr = p.parent();
+        }
+        let preserve = match self.parent() {
+            Some(p) => p.preserves_space(),
+            None => false,
+        };
+
+        let mut stack: Vec<(NodePtr, usize, bool)> = vec!{(self.rc_clone(), level, preserve)};
+        while let Some((node, level, preserve)) = stack.pop() {
+            let child_level = match node.node_type() {
+                NodeType::DocumentRoot => level,
+                NodeType::Element => level + step,
+                _ => continue,
+            };
+            let preserve = match node.attribute_value("xml:space") {
+                Some(value) => value == "preserve",
+                None => preserve,
+            };
+            if preserve || has_non_space_text(&node.unwrap_rc()) {
+                continue;
+            }
+            node.format_children(step, child_level);
+            for ch in node.children().into_iter().rev() {
+                stack.push((ch, child_level, preserve));
+            }
+        }
+        self.clear_document_order();
+    }
+
+    // -----------------------------------------------------------------
+    // 子の空白だけのテキストを除き、(step が正ならば) 改行と字下げの
+    // テキストを、子の前 (文書直下ならば後) と終了タグの前に挿入する。
+    // level: 子の字下げ。
+    //
+    fn format_children(&self, step: usize, level: usize) {
+        let mut rc_node = self.unwrap_rc();
+        let old_children: Vec<RcNode> = rc_node.children.borrow().clone();
+        let mut new_children: Vec<RcNode> = vec!{};
+        let mut removed: Vec<RcNode> = vec!{};
+        let mut inserted: Vec<RcNode> = vec!{};
+        let is_document = rc_node.node_type == NodeType::DocumentRoot;
+        for ch in old_children.iter() {
+            if ch.node_type == NodeType::Text {
+                removed.push(Rc::clone(ch));
+                continue;
+            }
+            if 0 < step && ! is_document {
+                let ws = make_new_rc_node(NodeType::Text, Some(&mut rc_node),
+                            "", &format!("\n{}", " ".repeat(level)));
+                new_children.push(Rc::clone(&ws));
+                inserted.push(ws);
+            }
+            new_children.push(Rc::clone(ch));
+            if 0 < step && is_document {
+                let ws = make_new_rc_node(NodeType::Text, Some(&mut rc_node), "", "\n");
+                new_children.push(Rc::clone(&ws));
+                inserted.push(ws);
+            }
+        }
+        if 0 < step && ! is_document && ! new_children.is_empty() {
+            let ws = make_new_rc_node(NodeType::Text, Some(&mut rc_node),
+                        "", &format!("\n{}", " ".repeat(level - step)));
+            new_children.push(Rc::clone(&ws));
+            inserted.push(ws);
+        }
+        *rc_node.children_mut() = new_children;
+
+        for ch in removed.iter() {
+            self.notify_mutation(MutationEvent::NodeRemoved{
+                parent: self.rc_clone(),
+                node: wrap_rc_clone(ch),
+            });
+        }
+        for ch in inserted.iter() {
+            self.notify_mutation(MutationEvent::NodeInserted{
+                parent: self.rc_clone(),
+                node: wrap_rc_clone(ch),
+            });
+        }
+    }
+
+    // =================================================================
+    /// Registers the observer that is called after each mutation of
+    /// the document (or the tree, if not a document) that this node
+    /// belongs to, by the methods such as append_child(),
+    /// delete_child(), set_attribute(), set_text().
+    /// See MutationEvent for the events.
+    ///
+    /// The observer may not mutate the document.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::cell::RefCell;
+    /// use std::rc::Rc;
+    /// use amxml::dom::*;
+    /// let doc = new_document(r#"<root><a/><b/></root>"#).unwrap();
+    /// let log = Rc::new(RefCell::new(vec!{}));
+    /// let log_clone = Rc::clone(&log);
+    /// doc.on_mutation(move |event| {
+    ///     let s = match event {
+    ///         MutationEvent::NodeInserted{parent, node} =>
+    ///             format!("+{}/{}", parent.name(), node.name()),
+    ///         MutationEvent::NodeRemoved{parent, node} =>
+    ///             format!("-{}/{}", parent.name(), node.name()),
+    ///         MutationEvent::AttributeChanged{element, name, old_value, new_value} =>
+    ///             format!("@{}/{}: {:?} -> {:?}", element.name(), name, old_value, new_value),
+    ///         MutationEvent::TextChanged{element} =>
+    ///             format!("#{}", element.name()),
+    ///     };
+    ///     log_clone.borrow_mut().push(s);
+    /// });
+    ///
+    /// let root = doc.root_element();
+    /// let mut elem_a = doc.get_first_node("//a").unwrap();
+    /// let elem_b = doc.get_first_node("//b").unwrap();
+    /// elem_a.set_attribute("id", "1").unwrap();
+    /// elem_a.set_text("text");
+    /// root.delete_child(&elem_b);
+    /// let new_doc = new_document("<c/>").unwrap();
+    /// root.append_child(&new_doc.root_element());
+    /// assert_eq!(*log.borrow(), vec!{
+    ///     r#"@a/id: None -> Some("1")"#, "#a", "-root/b", "+root/c",
+    /// });
+    /// ```
+    ///
+    pub fn on_mutation<F>(&self, observer: F)
+                where F: Fn(&MutationEvent) + 'static {
+        let root = self.root().unwrap_rc();
+        root.extras_or_new().observers.0.borrow_mut().push(Rc::new(observer));
+    }
+
+    // =================================================================
+    /// Removes all the observers registered by on_mutation()
+    /// for the document that this node belongs to.
+    ///
+    pub fn clear_mutation_observers(&self) {
+        let root = self.root().unwrap_rc();
+        let extras = match root.extras() {
+            Some(extras) => extras,
+            None => return,
+        };
+        extras.observers.0.borrow_mut().clear();
+    }
+
+    // =================================================================
+    /// Attaches the value of any type to the node, under the key,
+    /// replacing the value already attached under the key.
+    /// Useful to carry the results of analysis (types, line numbers,
+    /// etc.) through multi-pass processing.
+    ///
+    /// The annotations are kept in the table of the document (or the
+    /// tree, if not a document) that the node belongs to, and survive
+    /// the mutation of the document, but are cleared when the node
+    /// (or its ancestor) is deleted by delete_child(), replace_with(),
+    /// etc.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use amxml::dom::*;
+    /// let doc = new_document(r#"<root><a/><b/></root>"#).unwrap();
+    /// let a = doc.get_first_node("//a").unwrap();
+    /// a.set_annotation("line", 3usize);
+    /// a.set_annotation("type", String::from("xs:integer"));
+    ///
+    /// let a = doc.get_first_node("//a").unwrap();
+    /// assert_eq!(*a.get_annotation::<usize>("line").unwrap(), 3);
+    /// assert_eq!(*a.get_annotation::<String>("type").unwrap(), "xs:integer");
+    /// assert!(a.get_annotation::<i32>("line").is_none());
+    /// assert!(a.get_annotation::<usize>("col").is_none());
+    ///
+    /// doc.root_element().append_child(&new_document("<c/>").unwrap().root_element());
+    /// assert!(a.get_annotation::<usize>("line").is_some());
+    /// doc.root_element().delete_child(&a);
+    /// assert!(a.get_annotation::<usize>("line").is_none());
+    /// ```
+    ///
+    pub fn set_annotation<T: Any>(&self, key: &str, value: T) {
+        let root = self.root().unwrap_rc();
+        let extras = root.extras_or_new();
+        let mut table = extras.annotations.0.borrow_mut();
+        if ! self.annotations_belong_to_self(&table) {
+            table.remove(&self.node_key());
+        }
+        let entry = table.entry(self.node_key())
+                .or_insert_with(|| (Rc::downgrade(&self.rc_node), vec!{}));
+        entry.1.retain(|&(ref k, _)| k != key);
+        entry.1.push((String::from(key), Rc::new(value)));
+    }
+
+    // =================================================================
+    /// Returns the value attached to the node under the key,
+    /// or None if there is no value, or the value is not of the type.
+    /// See set_annotation().
+    ///
+    pub fn get_annotation<T: Any>(&self, key: &str) -> Option<Rc<T>> {
+        let root = self.root().unwrap_rc();
+        let extras = match root.extras() {
+            Some(extras) => extras,
+            None => return None,
+        };
+        let table = extras.annotations.0.borrow();
+        if ! self.annotations_belong_to_self(&table) {
+            return None;
+        }
+        if let Some(&(_, ref values)) = table.get(&self.node_key()) {
+            for &(ref k, ref value) in values.iter() {
+                if k == key {
+                    return Rc::clone(value).downcast::<T>().ok();
+                }
+            }
+        }
+        return None;
+    }
+
+    // =================================================================
+    /// Removes the value attached to the node under the key, if any.
+    /// See set_annotation().
+    ///
+    pub fn remove_annotation(&self, key: &str) {
+        let root = self.root().unwrap_rc();
+        let extras = match root.extras() {
+            Some(extras) => extras,
+            None => return,
+        };
+        let mut table = extras.annotations.0.borrow_mut();
+        if let Some(entry) = table.get_mut(&self.node_key()) {
+            entry.1.retain(|&(ref k, _)| k != key);
+        }
+    }
+
+    // =================================================================
+    /// Defines the named key of the document (or the tree, if not
+    /// a document) that the node belongs to, as xsl:key of XSLT:
+    /// the nodes that match the match_xpath as a pattern
+    /// (see XPath#matches_node()) are indexed by the string values of
+    /// the use_xpath, evaluated with each of them as the context node.
+    /// Redefining the key of the same name replaces it.
+    ///
+    /// The index is built when key() is called first, and rebuilt
+    /// after the document is mutated.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use amxml::dom::*;
+    /// let xml = r#"<root><item cat="a" id="1"/><item cat="b" id="2"/><item cat="a" alt="b" id="3"/></root>"#;
+    /// let doc = new_document(xml).unwrap();
+    /// doc.define_key("cat", "item", "(@cat, @alt)").unwrap();
+    ///
+    /// let ids = |nodes: Vec<NodePtr>| -> Vec<String> {
+    ///     nodes.iter().map(|n| n.attribute_value("id").unwrap()).collect()
+    /// };
+    /// assert_eq!(ids(doc.key("cat", "a").unwrap()), vec!{"1", "3"});
+    /// assert_eq!(ids(doc.key("cat", "b").unwrap()), vec!{"2", "3"});
+    /// assert!(doc.key("cat", "c").unwrap().is_empty());
+    /// assert!(doc.key("undefined", "a").is_err());
+    ///
+    /// let mut item = doc.get_first_node("//item[@id='2']").unwrap();
+    /// item.set_attribute("cat", "c").unwrap();
+    /// assert_eq!(ids(doc.key("cat", "b").unwrap()), vec!{"3"});
+    /// assert_eq!(ids(doc.key("cat", "c").unwrap()), vec!{"2"});
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// - When syntax error or unimplemented feature in the xpaths.
+    ///
+    pub fn define_key(&self, name: &str, match_xpath: &str,
+                use_xpath: &str) -> Result<(), Box<Error>> {
+        let match_xnode = compile_xpath(&String::from(match_xpath))?;
+        let use_xnode = compile_xpath(&String::from(use_xpath))?;
+        let root = self.root().unwrap_rc();
+        let extras = root.extras_or_new();
+        let mut keys = extras.keys.0.borrow_mut();
+        keys.retain(|k| k.name != name);
+        keys.push(NamedKey{
+            name: String::from(name),
+            match_xnode: match_xnode,
+            use_xnode: use_xnode,
+            index: None,
+        });
+        return Ok(());
+    }
+
+    // =================================================================
+    /// Returns the nodes, in document order, that have the value of
+    /// the named key defined by define_key(), as fn:key() of XSLT.
+    ///
+    /// # Examples
+    ///
+    /// See define_key().
+    ///
+    /// # Errors
+    ///
+    /// - When the key is not defined.
+    /// - When the evaluation of the xpaths of the key fails.
+    ///
+    pub fn key(&self, name: &str, value: &str) -> Result<Vec<NodePtr>, Box<Error>> {
+        let doc = self.root();
+        let root = doc.unwrap_rc();
+        let extras = match root.extras() {
+            Some(extras) => extras,
+            None => return Err(dynamic_error!("Key {}: not defined", name)),
+        };
+        let (match_xnode, use_xnode, has_index) = match extras.keys.0.borrow()
+                        .iter().find(|k| k.name == name) {
+            Some(k) => (k.match_xnode.clone(), k.use_xnode.clone(), k.index.is_some()),
+            None => return Err(dynamic_error!("Key {}: not defined", name)),
+        };
+        if ! has_index {
+            let index = build_key_index(&doc, &match_xnode, &use_xnode)?;
+            if let Some(k) = extras.keys.0.borrow_mut().iter_mut().find(|k| k.name == name) {
+                k.index = Some(index);
+            }
+        }
+        let keys = extras.keys.0.borrow();
+        let nodes = keys.iter().find(|k| k.name == name)
+                .and_then(|k| k.index.as_ref())
+                .and_then(|index| index.get(value));
+        match nodes {
+            Some(nodes) => return Ok(nodes.clone()),
+            None => return Ok(vec!{}),
+        }
+    }
+
+    // =================================================================
+    /// Builds the index of the elements in the document (or the tree,
+    /// if not a document) that the node belongs to, by the value of
+    /// the attribute attr_name (compared as written, including the
+    /// prefix).
+    /// The index is discarded when the document is mutated, and
+    /// rebuilt when it is looked up next.
+    ///
+    /// The XPath evaluator consults the index for the descendant step
+    /// whose first predicate compares the attribute with strings that
+    /// don't depend on the context, such as
+    /// <code>//item[@id = 'x']</code>,
+    /// <code>descendant::item[@id = $ids]</code>,
+    /// instead of visiting all the descendants.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use amxml::dom::*;
+    /// let xml = r#"<root><item code="a" n="1"/><group><item code="b" n="2"/><item code="a" n="3"/></group></root>"#;
+    /// let doc = new_document(xml).unwrap();
+    /// doc.build_attr_index("code");
+    ///
+    /// let n = |nodes: Vec<NodePtr>| -> Vec<String> {
+    ///     nodes.iter().map(|n| n.attribute_value("n").unwrap()).collect()
+    /// };
+    /// assert_eq!(n(doc.attr_index_lookup("code", "a").unwrap()), vec!{"1", "3"});
+    /// assert_eq!(n(doc.get_nodeset("//item[@code = 'a']").unwrap()), vec!{"1", "3"});
+    /// assert_eq!(n(doc.get_nodeset("/root/group//item[@code = ('a', 'b')]").unwrap()), vec!{"2", "3"});
+    /// assert!(doc.attr_index_lookup("n", "1").is_none());
+    ///
+    /// let mut item = doc.get_first_node("//item[@n = '2']").unwrap();
+    /// item.set_attribute("code", "a").unwrap();
+    /// assert_eq!(n(doc.get_nodeset("//item[@code = 'a']").unwrap()), vec!{"1", "2", "3"});
     /// ```
     ///
-    pub fn parent(&self) -> Option<NodePtr> {
-        match self.unwrap_rc().parent {
-            Some(ref p) => {
-                let parent_node = p.borrow().upgrade().unwrap();
-                return Some(wrap_rc_clone(&parent_node));
+    pub fn build_attr_index(&self, attr_name: &str) {
+        let doc = self.root();
+        let index = build_attr_value_index(&doc, attr_name);
+        let root = doc.unwrap_rc();
+        let extras = root.extras_or_new();
+        let mut indexes = extras.attr_indexes.0.borrow_mut();
+        indexes.retain(|x| x.attr_name != attr_name);
+        indexes.push(AttrIndex{
+            attr_name: String::from(attr_name),
+            index: Some(index),
+        });
+    }
+
+    // =================================================================
+    /// Removes the index built by build_attr_index().
+    ///
+    pub fn drop_attr_index(&self, attr_name: &str) {
+        let root = self.root().unwrap_rc();
+        let extras = match root.extras() {
+            Some(extras) => extras,
+            None => return,
+        };
+        extras.attr_indexes.0.borrow_mut().retain(|x| x.attr_name != attr_name);
+    }
+
+    // =================================================================
+    /// Returns the elements, in document order, whose attribute
+    /// attr_name has the value, by the index built by
+    /// build_attr_index(); None if the index of attr_name is not built.
+    ///
+    /// # Examples
+    ///
+    /// See build_attr_index().
+    ///
+    pub fn attr_index_lookup(&self, attr_name: &str, value: &str) -> Option<Vec<NodePtr>> {
+        let doc = self.root();
+        let root = doc.unwrap_rc();
+        let extras = match root.extras() {
+            Some(extras) => extras,
+            None => return None,
+        };
+        let mut indexes = extras.attr_indexes.0.borrow_mut();
+        let entry = match indexes.iter_mut().find(|x| x.attr_name == attr_name) {
+            Some(entry) => entry,
+            None => return None,
+        };
+        if entry.index.is_none() {
+            entry.index = Some(build_attr_value_index(&doc, attr_name));
+        }
+        let nodes = entry.index.as_ref().and_then(|index| index.get(value));
+        match nodes {
+            Some(nodes) => return Some(nodes.clone()),
+            None => return Some(vec!{}),
+        }
+    }
+
+    // =================================================================
+    /// Numbers the node as xsl:number of XSLT does, and returns the
+    /// numbers joined by ".": e.g. "2.4.1" for the first section of
+    /// the fourth chapter of the second part, with level Multiple.
+    ///
+    /// The nodes counted are those that match the count_xpath as
+    /// a pattern (see XPath#matches_node()); if count_xpath is empty,
+    /// the nodes of the same type and name as this node.
+    /// Returns the empty string if no node is counted, e.g. when
+    /// neither this node nor its ancestors match with level Single.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use amxml::dom::*;
+    /// let xml = r#"<doc><part/><part><ch/><ch/><ch/><ch><note/><sec><title/></sec><sec/></ch></part></doc>"#;
+    /// let doc = new_document(xml).unwrap();
+    /// let title = doc.get_first_node("//title").unwrap();
+    /// assert_eq!(title.hierarchical_number("part|ch|sec", NumberLevel::Multiple).unwrap(), "2.4.1");
+    /// assert_eq!(title.hierarchical_number("ch", NumberLevel::Single).unwrap(), "4");
+    /// assert_eq!(title.hierarchical_number("*", NumberLevel::Any).unwrap(), "10");
+    /// assert_eq!(title.hierarchical_number("", NumberLevel::Single).unwrap(), "1");
+    /// assert_eq!(title.hierarchical_number("note", NumberLevel::Single).unwrap(), "");
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// - When syntax error or unimplemented feature in count_xpath.
+    ///
+    pub fn hierarchical_number(&self, count_xpath: &str,
+                level: NumberLevel) -> Result<String, Box<Error>> {
+        let count_xnode = if count_xpath == "" {
+            None
+        } else {
+            Some(compile_xpath(&String::from(count_xpath))?)
+        };
+        let is_counted = |node: &NodePtr| -> Result<bool, Box<Error>> {
+            match count_xnode {
+                Some(ref xnode) => return match_pattern(node, xnode),
+                None => return Ok(node.node_type() == self.node_type() &&
+                                  node.name() == self.name()),
+            }
+        };
+
+        let mut numbers: Vec<usize> = vec!{};
+        match level {
+            NumberLevel::Single | NumberLevel::Multiple => {
+                let mut curr = Some(self.rc_clone());
+                while let Some(node) = curr {
+                    if is_counted(&node)? {
+                        let mut n = 1;
+                        if node.node_type() != NodeType::Attribute {
+                            if let Some(parent) = node.parent() {
+                                for sibling in parent.children().iter() {
+                                    if *sibling == node {
+                                        break;
+                                    }
+                                    if is_counted(sibling)? {
+                                        n += 1;
+                                    }
+                                }
+                            }
+                        }
+                        numbers.insert(0, n);
+                        if level == NumberLevel::Single {
+                            break;
+                        }
+                    }
+                    curr = node.parent();
+                }
             },
-            None => return None,
+            NumberLevel::Any => {
+                // 文書順にたどり、このノードに達するまでに合致したものを数える。
+                // 属性ならば、その要素 (と要素より前のノード) の後に数える。
+                let target = if self.node_type() == NodeType::Attribute {
+                    self.parent().unwrap_or(self.rc_clone())
+                } else {
+                    self.rc_clone()
+                };
+                let mut n = 0;
+                let mut stack: Vec<NodePtr> = vec!{self.root()};
+                while let Some(node) = stack.pop() {
+                    if is_counted(&node)? {
+                        n += 1;
+                    }
+                    if node == target {
+                        break;
+                    }
+                    let mut children = node.children();
+                    children.reverse();
+                    stack.extend(children);
+                }
+                if target != *self && is_counted(self)? {
+                    n += 1;
+                }
+                if n != 0 {
+                    numbers.push(n);
+                }
+            },
+        }
+        return Ok(numbers.iter().map(|n| n.to_string())
+                        .collect::<Vec<String>>().join("."));
+    }
+
+    // -----------------------------------------------------------------
+    // 表にある (このノードのアドレスの) 注釈が、解放された別のノードの
+    // ものでなく、このノードのものであるか否か。
+    //
+    fn annotations_belong_to_self(&self,
+            table: &HashMap<usize, (Weak<Node>, Vec<(String, Rc<Any>)>)>) -> bool {
+        match table.get(&self.node_key()) {
+            Some(&(ref weak, _)) => return weak.upgrade()
+                    .map_or(false, |rc| Rc::ptr_eq(&rc, &self.rc_node)),
+            None => return true,
+        }
+    }
+
+    // -----------------------------------------------------------------
+    // 削除した部分木 (node とその子孫、属性) の注釈を、文書の表から除く。
+    //
+    fn clear_annotations_of_subtree(&self, node: &NodePtr) {
+        let root = self.root().unwrap_rc();
+        let extras = match root.extras() {
+            Some(extras) => extras,
+            None => return,
+        };
+        let mut table = extras.annotations.0.borrow_mut();
+        if table.is_empty() {
+            return;
+        }
+        let mut stack: Vec<NodePtr> = vec!{node.rc_clone()};
+        while let Some(n) = stack.pop() {
+            table.remove(&n.node_key());
+            for at in n.attribute_nodes().iter() {
+                table.remove(&at.node_key());
+            }
+            stack.extend(n.children());
         }
     }
 
+    // -----------------------------------------------------------------
+    // 注釈の表のキー (ノードのアドレス)。
+    //
+    fn node_key(&self) -> usize {
+        return &*self.rc_node as *const Node as usize;
+    }
+
     // =================================================================
-    // Returns the vector of child nodes.
+    /// Checks if this node (usually the document) and its descendants
+    /// can be serialized as a well-formed XML document (or fragment),
+    /// and reports all the violations found.
+    ///
+    /// The violations checked are: element, attribute and processing
+    /// instruction names that are not QNames (or NCNames),
+    /// duplicated attributes, characters not allowed in XML,
+    /// "--" in comments, "?&gt;" in processing instructions, and
+    /// the document that does not have exactly one root element.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use amxml::dom::*;
+    /// let doc = new_document(r#"<root><a/></root>"#).unwrap();
+    /// assert!(doc.validate_well_formedness().is_ok());
+    /// let mut a = doc.get_first_node("//a").unwrap();
+    /// a.set_text("\u{0}");
+    /// a.set_attribute_with_check("1st", "x", NameCheck::Bypass).unwrap();
+    /// let errs = doc.validate_well_formedness().unwrap_err();
+    /// assert_eq!(errs.len(), 2);
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// - Vector of errors, one for each violation, in document order.
+    ///
+    pub fn validate_well_formedness(&self) -> Result<(), Vec<Box<Error>>> {
+        let mut errors: Vec<Box<Error>> = vec!{};
+        let mut stack: Vec<NodePtr> = vec!{self.rc_clone()};
+        while let Some(node) = stack.pop() {
+            validate_node(&node, &mut errors);
+            let mut children = node.children();
+            children.reverse();
+            stack.extend(children);
+        }
+        if errors.is_empty() {
+            return Ok(());
+        } else {
+            return Err(errors);
+        }
+    }
+
+    // =================================================================
+    /// Returns the statistics of the tree under the node (including
+    /// the node itself and the attributes): the number of nodes of
+    /// each type, the total length of text, the depth, and the
+    /// estimated heap usage.
+    ///
+    /// The heap usage is an estimate: it sums up the sizes of the
+    /// node structures and the capacities of their strings and
+    /// vectors, but does not know the overhead of the allocator.
+    /// The tables of the document (for on_mutation(), set_annotation(),
+    /// define_key(), etc.) are allocated on the DocumentRoot node only
+    /// when first used.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use amxml::dom::*;
+    /// let xml = r#"<root a="1"><b>foo</b><!--c--><b>bar<c/></b></root>"#;
+    /// let doc = new_document(xml).unwrap();
+    /// let stats = doc.stats();
+    /// assert_eq!(stats.elements, 4);
+    /// assert_eq!(stats.attributes, 1);
+    /// assert_eq!(stats.texts, 2);
+    /// assert_eq!(stats.comments, 1);
+    /// assert_eq!(stats.text_bytes, 6);
+    /// assert_eq!(stats.max_depth, 3);
+    /// assert!(stats.estimated_heap_bytes > 0);
+    ///
+    /// doc.on_mutation(|_| {});
+    /// assert!(doc.stats().estimated_heap_bytes > stats.estimated_heap_bytes);
+    /// ```
+    ///
+    pub fn stats(&self) -> DocumentStats {
+        let mut stats = DocumentStats::default();
+        let mut stack: Vec<(RcNode, usize)> = vec!{(self.unwrap_rc(), 0)};
+        while let Some((rc_node, depth)) = stack.pop() {
+            stats.count_node(&rc_node, depth);
+            for at in rc_node.attributes.borrow().iter() {
+                stats.count_node(at, depth);
+            }
+            for ch in rc_node.children.borrow().iter() {
+                stack.push((Rc::clone(ch), depth + 1));
+            }
+        }
+        return stats;
+    }
+
+    // =================================================================
+    /// Releases the unused capacity of the vectors (children,
+    /// attributes, etc.) held by the nodes of the tree under the node.
+    /// Useful after building or editing a large document, that is
+    /// going to be kept for a while.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use amxml::dom::*;
+    /// let xml = r#"<root><a/><a/><a/><a/><a/></root>"#;
+    /// let doc = new_document(xml).unwrap();
+    /// let before = doc.stats().estimated_heap_bytes;
+    /// doc.shrink_to_fit();
+    /// assert!(doc.stats().estimated_heap_bytes <= before);
+    /// assert_eq!(doc.to_string(), xml);
+    /// ```
+    ///
+    pub fn shrink_to_fit(&self) {
+        let mut stack: Vec<RcNode> = vec!{self.unwrap_rc()};
+        while let Some(rc_node) = stack.pop() {
+            rc_node.children.borrow_mut().shrink_to_fit();
+            rc_node.attributes_mut().shrink_to_fit();
+            if let Some(extras) = rc_node.extras() {
+                extras.observers.0.borrow_mut().shrink_to_fit();
+            }
+            stack.extend(rc_node.children.borrow().iter().cloned());
+        }
+    }
+
+    // =================================================================
+    /// Returns true if the tree under the node is structurally equal to
+    /// the tree under the other node: the same types, names and values
+    /// of the nodes, the same attributes, and the same children in the
+    /// same order. Adjacent text nodes are compared as one.
+    /// The nodes may belong to different documents.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use amxml::dom::*;
+    /// let doc1 = new_document(r#"<a x="1" y="2"><b>text</b><!--c--></a>"#).unwrap();
+    /// let doc2 = new_document(r#"<a y="2" x="1">
+    ///     <b> text </b>
+    /// </a>"#).unwrap();
+    /// let options = DeepEqualOptions::new();
+    /// assert!(doc1.deep_equal(&doc1, &options));
+    /// assert!(! doc1.deep_equal(&doc2, &options));
+    ///
+    /// let options = DeepEqualOptions::new()
+    ///         .ignore_comments(true)
+    ///         .ignore_attribute_order(true)
+    ///         .ignore_whitespace(true);
+    /// assert!(doc1.deep_equal(&doc2, &options));
+    /// ```
+    ///
+    pub fn deep_equal(&self, other: &NodePtr, options: &DeepEqualOptions) -> bool {
+        let mut stack: Vec<(RcNode, RcNode)> = vec!{(self.unwrap_rc(), other.unwrap_rc())};
+        while let Some((a, b)) = stack.pop() {
+            if ! shallow_equal(&a, &b, options) {
+                return false;
+            }
+            let a_children = compared_children(&a, options);
+            let b_children = compared_children(&b, options);
+            if a_children.len() != b_children.len() {
+                return false;
+            }
+            for (a_ch, b_ch) in a_children.into_iter().zip(b_children.into_iter()) {
+                match (a_ch, b_ch) {
+                    (ComparedChild::Text(a_text), ComparedChild::Text(b_text)) => {
+                        if a_text != b_text {
+                            return false;
+                        }
+                    },
+                    (ComparedChild::Node(a_node), ComparedChild::Node(b_node)) => {
+                        stack.push((a_node, b_node));
+                    },
+                    _ => return false,
+                }
+            }
+        }
+        return true;
+    }
+
+    // =================================================================
+    /// Returns the structural hash of the tree under the node.
+    /// The trees equal by deep_equal() with the default options
+    /// have the same hash, so it can be used to find the duplicates
+    /// among many documents, before comparing them by deep_equal().
+    /// The hash is stable within the same build of the program.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use amxml::dom::*;
+    /// let doc1 = new_document(r#"<a x="1"><b>text</b></a>"#).unwrap();
+    /// let doc2 = new_document(r#"<a x="1"><b>text</b></a>"#).unwrap();
+    /// let doc3 = new_document(r#"<a x="1"><b>Text</b></a>"#).unwrap();
+    /// assert_eq!(doc1.tree_hash(), doc2.tree_hash());
+    /// assert_ne!(doc1.tree_hash(), doc3.tree_hash());
+    /// ```
+    ///
+    pub fn tree_hash(&self) -> u64 {
+        let options = DeepEqualOptions::new();
+        let mut hasher = DefaultHasher::new();
+        let mut stack: Vec<RcNode> = vec!{self.unwrap_rc()};
+        while let Some(rc_node) = stack.pop() {
+            node_type_tag(&rc_node.node_type).hash(&mut hasher);
+            rc_node.name.hash(&mut hasher);
+            rc_node.value.hash(&mut hasher);
+            compared_attributes(&rc_node, &options).hash(&mut hasher);
+            let children = compared_children(&rc_node, &options);
+            children.len().hash(&mut hasher);
+            for ch in children.into_iter().rev() {
+                match ch {
+                    ComparedChild::Text(text) => {
+                        node_type_tag(&NodeType::Text).hash(&mut hasher);
+                        text.hash(&mut hasher);
+                    },
+                    ComparedChild::Node(node) => stack.push(node),
+                }
+            }
+        }
+        return hasher.finish();
+    }
+
+    // -----------------------------------------------------------------
+    // 文書 (木の根) に登録された函数に、変更を通知する。
     //
-    pub fn children(&self) -> Vec<NodePtr> {
-        let mut node_array: Vec<NodePtr> = vec!{};
+    fn notify_mutation(&self, event: MutationEvent) {
+        if let MutationEvent::NodeRemoved{ref node, ..} = event {
+            self.clear_annotations_of_subtree(node);
+        }
+        let root = self.root().unwrap_rc();
+        let observers: Vec<MutationObserver> = match root.extras() {
+            Some(extras) => {
+                for k in extras.keys.0.borrow_mut().iter_mut() {
+                    k.index = None;
+                }
+                for x in extras.attr_indexes.0.borrow_mut().iter_mut() {
+                    x.index = None;
+                }
+                extras.observers.0.borrow().clone()
+            },
+            None => return,
+        };
+        for observer in observers.iter() {
+            observer(&event);
+        }
+    }
+
+    // -----------------------------------------------------------------
+    //
+    fn find_attribute_index(&self, name: &str) -> usize {
         let rc_node = self.unwrap_rc();
-        for ch in (*rc_node).children.borrow().iter() {
-            node_array.push(wrap_rc_clone(ch));
+        for (i, at) in (*rc_node).attributes.borrow().iter().enumerate() {
+            if at.name == name {
+                return i;
+            }
+        }
+        return usize::MAX;
+    }
+
+    // -----------------------------------------------------------------
+    // このノードが属する木全体の複製 (TreeSnapshot) を作る。
+    // 注釈、名前付きキー、属性値の索引など、木の根に付けた情報は
+    // 複製しないので、それらがある場合は None を返す。
+    //
+    #[cfg(not(feature = "no_std"))]
+    pub(crate) fn tree_snapshot(&self) -> Option<TreeSnapshot> {
+        let root = self.root().unwrap_rc();
+        let mut document_uri: Option<String> = None;
+        if let Some(extras) = root.extras() {
+            if ! extras.annotations.0.borrow().is_empty() ||
+               ! extras.keys.0.borrow().is_empty() ||
+               ! extras.attr_indexes.0.borrow().is_empty() {
+                return None;
+            }
+            document_uri = extras.document_uri.borrow().clone();
+        }
+
+        let mut nodes: Vec<SnapshotNode> = vec!{};
+        let mut stack: Vec<RcNode> = vec!{Rc::clone(&root)};
+        while let Some(rc_node) = stack.pop() {
+            let children = rc_node.children.borrow();
+            nodes.push(SnapshotNode {
+                node_type: rc_node.node_type.clone(),
+                name: rc_node.name.clone(),
+                value: rc_node.value.clone(),
+                raw: rc_node.raw.get(),
+                attributes: rc_node.attributes.borrow().iter()
+                        .map(|at| (at.name.clone(), at.value.clone())).collect(),
+                child_count: children.len(),
+            });
+            stack.extend(children.iter().rev().cloned());
+        }
+        return Some(TreeSnapshot {
+            nodes,
+            document_uri,
+        });
+    }
+
+    // -----------------------------------------------------------------
+    //
+    fn clear_document_order(&self) {
+        let root = self.root();
+        root.unwrap_rc().order.set(0);
+    }
+
+    // =================================================================
+    /// Compares the position of the nodes in document order.
+    /// An element precedes its attributes, and its attributes precede
+    /// its children.
+    ///
+    /// Nodes of different documents (or of different trees that are
+    /// not connected) are ordered by their trees, in the order that is
+    /// arbitrary but stable as long as the trees are alive.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::cmp::Ordering;
+    /// use amxml::dom::*;
+    /// let xml = r#"<root><a v="1"><b/></a><c/></root>"#;
+    /// let doc = new_document(xml).unwrap();
+    /// let a = doc.get_first_node("//a").unwrap();
+    /// let v = doc.get_first_node("//a/@v").unwrap();
+    /// let b = doc.get_first_node("//b").unwrap();
+    /// let c = doc.get_first_node("//c").unwrap();
+    /// assert_eq!(a.cmp_document_order(&v), Ordering::Less);
+    /// assert_eq!(b.cmp_document_order(&v), Ordering::Greater);
+    /// assert_eq!(c.cmp_document_order(&b), Ordering::Greater);
+    /// assert_eq!(c.cmp_document_order(&c), Ordering::Equal);
+    /// ```
+    ///
+    pub fn cmp_document_order(&self, other: &NodePtr) -> Ordering {
+        let self_root = self.root();
+        let other_root = other.root();
+        if self_root != other_root {
+            let self_addr = &*self_root.rc_node as *const Node as usize;
+            let other_addr = &*other_root.rc_node as *const Node as usize;
+            return self_addr.cmp(&other_addr);
+        }
+        return self.document_order().cmp(&other.document_order());
+    }
+
+    // =================================================================
+    /// (Inner Use)
+    ///
+    pub fn document_order(&self) -> i64 {
+        let root = self.root();
+        if root.unwrap_rc().order.get() == 0 {
+            root.setup_document_order();
+        }
+        return self.unwrap_rc().order.get();
+    }
+
+    // -----------------------------------------------------------------
+    //
+    // 深い木でもスタックを使い切らないよう、再帰せず作業スタック
+    // (ノード, 次に訪れる子の位置) でたどる。
+    //
+    fn setup_document_order(&self) {
+        let mut order = self.setup_document_order_of_node(1);
+        let mut stack: Vec<(NodePtr, usize)> = vec!{(self.rc_clone(), 0)};
+        while let Some((node, pos)) = stack.pop() {
+            if let Some(ch) = node.nth_child(pos) {
+                stack.push((node, pos + 1));
+                order = ch.setup_document_order_of_node(order + 1);
+                stack.push((ch, 0));
+            }
+        }
+    }
+
+    // -----------------------------------------------------------------
+    // ノード自身とその属性に順序番号を振り、次の番号を返す。
+    //
+    fn setup_document_order_of_node(&self, order_beg: i64) -> i64 {
+        let mut order = order_beg;
+        self.unwrap_rc().order.set(order);
+        order += 1;
+        for at in self.rc_node.attributes.borrow().iter() {
+            at.order.set(order);
+            order += 1;
+        }
+        return order;
+    }
+
+    // =================================================================
+    /// (Inner Use)
+    ///
+    pub fn rc_clone(&self) -> NodePtr {
+        return NodePtr {
+            rc_node: Rc::clone(&self.rc_node),
+        };
+    }
+
+    // -----------------------------------------------------------------
+    //
+    fn unwrap_rc(&self) -> RcNode {
+        return Rc::clone(&self.rc_node);
+    }
+}
+
+// ---------------------------------------------------------------------
+// check が NameCheck::Validate ならば、name が QName か否か調べる。
+// kind: エラーメッセージに使う名前の種類 ("attribute" など)。
+//
+fn check_name(name: &str, kind: &str, check: NameCheck) -> Result<(), Box<Error>> {
+    if check == NameCheck::Validate && ! is_qname(name) {
+        return Err(dynamic_error!("Invalid {} name: {}", kind, name));
+    }
+    return Ok(());
+}
+
+// ---------------------------------------------------------------------
+// 整形式の XML として直列化できないところを errors に追加する。
+// 子孫は調べない。
+//
+fn validate_node(node: &NodePtr, errors: &mut Vec<Box<Error>>) {
+    match node.node_type() {
+        NodeType::DocumentRoot => {
+            let children = node.children();
+            let n_elems = children.iter()
+                .filter(|ch| ch.node_type() == NodeType::Element).count();
+            if n_elems != 1 {
+                errors.push(xml_syntax_error!(
+                    "Document has {} root elements", n_elems));
+            }
+            if children.iter().any(|ch| ch.node_type() == NodeType::Text &&
+                        ch.value().trim() != "") {
+                errors.push(xml_syntax_error!(
+                    "Document has text outside of root element"));
+            }
+        },
+        NodeType::Element => {
+            if ! is_qname(&node.name()) {
+                errors.push(xml_syntax_error!(
+                    "Invalid element name: {}", node.name()));
+            }
+            let mut names: Vec<String> = vec!{};
+            for at in node.attribute_nodes().iter() {
+                let name = at.name();
+                if ! is_qname(&name) {
+                    errors.push(xml_syntax_error!(
+                        "Invalid attribute name: {}", name));
+                }
+                if names.contains(&name) {
+                    errors.push(xml_syntax_error!(
+                        "Duplicated attribute: {}", name));
+                }
+                if let Some(ch) = at.value().chars().find(|c| ! is_xml_char(*c)) {
+                    errors.push(xml_syntax_error!(
+                        "Invalid character U+{:04X} in attribute {}", ch as u32, name));
+                }
+                names.push(name);
+            }
+        },
+        NodeType::Text => {
+            if let Some(ch) = node.value().chars().find(|c| ! is_xml_char(*c)) {
+                errors.push(xml_syntax_error!(
+                    "Invalid character U+{:04X} in text", ch as u32));
+            }
+        },
+        NodeType::Comment => {
+            let value = node.value();
+            if value.contains("--") || value.ends_with("-") {
+                errors.push(xml_syntax_error!(
+                    "Comment must not contain \"--\": {}", value));
+            }
+            if let Some(ch) = value.chars().find(|c| ! is_xml_char(*c)) {
+                errors.push(xml_syntax_error!(
+                    "Invalid character U+{:04X} in comment", ch as u32));
+            }
+        },
+        NodeType::Instruction => {
+            let target = node.name();
+            if ! is_ncname(&target) || target.to_lowercase() == "xml" {
+                errors.push(xml_syntax_error!(
+                    "Invalid processing instruction target: {}", target));
+            }
+            let value = node.value();
+            if value.contains("?>") {
+                errors.push(xml_syntax_error!(
+                    "Processing instruction must not contain \"?>\": {}", value));
+            }
+            if let Some(ch) = value.chars().find(|c| ! is_xml_char(*c)) {
+                errors.push(xml_syntax_error!(
+                    "Invalid character U+{:04X} in processing instruction", ch as u32));
+            }
+        },
+        _ => {},
+    }
+}
+
+// ---------------------------------------------------------------------
+// 接頭辞 prefix を宣言する属性の名前 (xmlns:prefix、あるいは xmlns)。
+//
+fn xmlns_attribute_name(prefix: &str) -> String {
+    if prefix == "" {
+        return String::from("xmlns");
+    } else {
+        return format!("xmlns:{}", prefix);
+    }
+}
+
+// ---------------------------------------------------------------------
+// 部分木 node の要素名・属性名で使っている接頭辞 (既定の名前空間は "")
+// のうち、部分木内で宣言されていないもの (文書順)。
+// 深い木でもスタックを使い切らないよう、再帰せず作業スタックでたどる。
+//
+fn collect_free_prefixes(node: &NodePtr, free_prefixes: &mut Vec<String>) {
+    let mut declared: Vec<String> = vec!{};
+    let mut stack: Vec<PrefixWork> = vec!{PrefixWork::Node(node.rc_clone())};
+    while let Some(work) = stack.pop() {
+        let node = match work {
+            PrefixWork::Node(node) => node,
+            PrefixWork::End(n_declared) => {
+                declared.truncate(n_declared);
+                continue;
+            },
+        };
+        if node.node_type() != NodeType::Element {
+            continue;
+        }
+        stack.push(PrefixWork::End(declared.len()));
+        for (prefix, _) in node.declared_namespaces().into_iter() {
+            declared.push(prefix);
+        }
+
+        let mut used = vec!{node.space_name()};
+        for at in node.attribute_nodes().iter() {
+            let space = at.space_name();
+            if space != "" && space != "xmlns" && at.name() != "xmlns" {
+                used.push(space);
+            }
+        }
+        for prefix in used.into_iter() {
+            if prefix != "xml" && ! declared.contains(&prefix) &&
+               ! free_prefixes.contains(&prefix) {
+                free_prefixes.push(prefix);
+            }
         }
-        return node_array;
-    }
 
-    // =================================================================
-    // Returns the vector of attribute nodes.
-    //
-    pub fn attributes(&self) -> Vec<NodePtr> {
-        let mut node_array: Vec<NodePtr> = vec!{};
-        let rc_node = self.unwrap_rc();
-        for at in (*rc_node).attributes.borrow().iter() {
-            node_array.push(wrap_rc_clone(at));
+        for ch in node.children().into_iter().rev() {
+            stack.push(PrefixWork::Node(ch));
         }
-        return node_array;
     }
+}
 
-    // =================================================================
-    /// Returns the first child of the node.
-    /// This is equivalent to: nth_child(0)
+// ---------------------------------------------------------------------
+// collect_free_prefixes() の作業スタックの項目。
+//
+enum PrefixWork {
+    Node(NodePtr),
+    End(usize),         // 要素の終わり: declared をこの長さに戻す
+}
+
+// =====================================================================
+/// Statistics of a (sub)tree, returned by NodePtr#stats().
+///
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DocumentStats {
+    /// Number of DocumentRoot nodes (1 for the whole document).
+    pub document_roots: usize,
+    /// Number of Element nodes.
+    pub elements: usize,
+    /// Number of Attribute nodes.
+    pub attributes: usize,
+    /// Number of Text nodes.
+    pub texts: usize,
+    /// Number of Comment nodes.
+    pub comments: usize,
+    /// Number of Instruction (processing instruction) nodes.
+    pub instructions: usize,
+    /// Number of XMLDecl nodes.
+    pub xml_decls: usize,
+    /// Number of Directive nodes.
+    pub directives: usize,
+    /// Total length of the text nodes, in bytes (UTF-8).
+    pub text_bytes: usize,
+    /// Depth of the deepest node; 0 if the node has no children,
+    /// and the root element of a document is at depth 1.
+    pub max_depth: usize,
+    /// Estimated heap usage of the nodes, in bytes.
+    pub estimated_heap_bytes: usize,
+}
+
+impl DocumentStats {
+    // -----------------------------------------------------------------
+    /// Returns the total number of nodes (including attributes).
     ///
-    pub fn first_child(&self) -> Option<NodePtr> {
-        return self.nth_child(0);
+    pub fn node_count(&self) -> usize {
+        return self.document_roots + self.elements + self.attributes +
+               self.texts + self.comments + self.instructions +
+               self.xml_decls + self.directives;
     }
 
-    // =================================================================
-    /// Returns the n'th child of the node, or None if there is
-    /// no such child.
-    ///
-    /// # Examples
-    ///
-    /// ```
-    /// use amxml::dom::*;
-    /// let xml_string = r#"<article><a/>foo<b/></article>"#;
-    /// let doc = new_document(&xml_string).unwrap();
-    /// let root_elem = doc.root_element();
-    /// assert_eq!(root_elem.name(), "article");
-    /// let ch0 = root_elem.first_child().unwrap();
-    /// assert_eq!(ch0.name(), "a");
-    /// let ch1 = root_elem.nth_child(1).unwrap();
-    /// assert_eq!(ch1.value(), "foo");
-    /// let ch2 = root_elem.nth_child(2).unwrap();
-    /// assert_eq!(ch2.name(), "b");
-    /// let ch3 = root_elem.nth_child(3);
-    /// assert!(ch3.is_none());
-    /// ```
-    ///
-    pub fn nth_child(&self, n: usize) -> Option<NodePtr> {
-        let rc_node = self.unwrap_rc();
-        if n < rc_node.children.borrow().len() {
-            return Some(wrap_rc_clone(&(*rc_node).children.borrow()[n]));
-        } else {
-            return None
+    // -----------------------------------------------------------------
+    // ノード1個分を数える。
+    // ヒープ使用量は、Rc の参照カウント2個と Node 構造体、
+    // 文字列と Vec の容量の和として見積もる。
+    //
+    fn count_node(&mut self, rc_node: &RcNode, depth: usize) {
+        match rc_node.node_type {
+            NodeType::DocumentRoot => self.document_roots += 1,
+            NodeType::Element => self.elements += 1,
+            NodeType::Attribute => self.attributes += 1,
+            NodeType::Text => {
+                self.texts += 1;
+                self.text_bytes += rc_node.value.len();
+            },
+            NodeType::Comment => self.comments += 1,
+            NodeType::Instruction => self.instructions += 1,
+            NodeType::XMLDecl => self.xml_decls += 1,
+            NodeType::Directive => self.directives += 1,
         }
+        if self.max_depth < depth {
+            self.max_depth = depth;
+        }
+        self.estimated_heap_bytes +=
+            2 * mem::size_of::<usize>() +
+            mem::size_of::<Node>() +
+            rc_node.name.capacity() +
+            rc_node.value.capacity() +
+            rc_node.children.borrow().capacity() * mem::size_of::<RcNode>() +
+            rc_node.attributes.borrow().capacity() * mem::size_of::<RcNode>() +
+            rc_node.attribute_cache.borrow().as_ref()
+                .map_or(0, |nodes| nodes.capacity() * mem::size_of::<NodePtr>()) +
+            rc_node.extras().map_or(0, |extras|
+                mem::size_of::<DocumentExtras>() +
+                extras.observers.0.borrow().capacity() * mem::size_of::<MutationObserver>());
     }
+}
 
-    // =================================================================
-    /// Appends the node tree 'new_child' as the last child of
-    /// the element node.
-    ///
-    /// # Examples
-    ///
-    /// ```
-    /// use amxml::dom::*;
-    ///
-    /// let xml_string = r#"<article><a/>foo<b/></article>"#;
-    /// let doc = new_document(&xml_string).unwrap();
-    /// let elem_article = doc.get_first_node("//article").unwrap();
-    ///
-    /// let new_xml_string = r#"<c>baa</c>"#;
-    /// let new_doc = new_document(&new_xml_string).unwrap();
-    /// let elem_c = new_doc.root_element();
-    ///
-    /// // Appends 'c' as the last child of 'article'
-    /// elem_article.append_child(&elem_c);   // NB. don't pass &new_doc
-    /// let result = doc.to_string();
-    /// let guess = r#"<article><a/>foo<b/><c>baa</c></article>"#;
-    /// assert_eq!(result, guess);
-    /// ```
+// =====================================================================
+/// Options for NodePtr#deep_equal().
+///
+/// The default options compare everything but the boundaries of
+/// adjacent text nodes.
+///
+#[derive(Debug, Clone, Default)]
+pub struct DeepEqualOptions {
+    ignore_comments: bool,
+    ignore_attribute_order: bool,
+    ignore_whitespace: bool,
+}
+
+impl DeepEqualOptions {
+    // -----------------------------------------------------------------
+    /// Returns the default options.
     ///
-    pub fn append_child(&self, new_child: &NodePtr) {
-        let rc_self = self.unwrap_rc();
-        let rc_new_child = new_child.unwrap_rc();
-        rc_self.children.borrow_mut().push(Rc::clone(&rc_new_child));
-        self.clear_document_order();
+    pub fn new() -> DeepEqualOptions {
+        return DeepEqualOptions::default();
     }
 
-    // =================================================================
-    /// Inserts the child node tree as the previous sibling of 'self' node.
-    ///
-    /// # Examples
-    ///
-    /// ```
-    /// use amxml::dom::*;
-    ///
-    /// let xml_string = r#"<article><a/><b/><c/></article>"#;
-    /// let doc = new_document(&xml_string).unwrap();
-    /// let elem_b = doc.get_first_node("//b").unwrap();
+    // -----------------------------------------------------------------
+    /// Ignores the comments; the texts around a comment are compared
+    /// as one text.
     ///
-    /// let new_xml_string = r#"<x>yyy</x>"#;
-    /// let new_doc = new_document(&new_xml_string).unwrap();
-    /// let elem_x = new_doc.root_element();
+    pub fn ignore_comments(mut self, ignore: bool) -> DeepEqualOptions {
+        self.ignore_comments = ignore;
+        return self;
+    }
+
+    // -----------------------------------------------------------------
+    /// Compares the attributes of an element as a set, regardless of
+    /// the order they are written in.
     ///
-    /// // Inserts 'x' as the previous sibling of 'b'
-    /// elem_b.insert_as_previous_sibling(&elem_x);
-    /// let result = doc.to_string();
-    /// let guess = r#"<article><a/><x>yyy</x><b/><c/></article>"#;
-    /// assert_eq!(result, guess);
-    /// ```
+    pub fn ignore_attribute_order(mut self, ignore: bool) -> DeepEqualOptions {
+        self.ignore_attribute_order = ignore;
+        return self;
+    }
+
+    // -----------------------------------------------------------------
+    /// Ignores the texts that consist of white space only, and
+    /// compares other texts with white space normalized, i.e. leading
+    /// and trailing white space removed and the sequences of white
+    /// space replaced by a single space.
     ///
-    pub fn insert_as_previous_sibling(&self, new_node: &NodePtr) {
-        let parent = match self.parent() {
-            Some(p) => p,
-            None => return,
-        };
-        let n = parent.find_child_index(self);
-        if n != usize::MAX {
-            let mut rc_parent = parent.unwrap_rc();
-            let rc_new_node = new_node.unwrap_rc();
+    pub fn ignore_whitespace(mut self, ignore: bool) -> DeepEqualOptions {
+        self.ignore_whitespace = ignore;
+        return self;
+    }
+}
 
-            let mut rc_new_node_dup = make_new_child_rc_node(
-                rc_new_node.node_type.clone(),
-                &mut rc_parent,
-                &rc_new_node.name,
-                &rc_new_node.value,
-                n);
-            shallow_copy_rc_rels(&mut rc_new_node_dup, &rc_new_node);
+// ---------------------------------------------------------------------
+// NodePtr#deep_equal() で比較する子: 隣接するテキストは併合して1個とする。
+//
+enum ComparedChild {
+    Node(RcNode),
+    Text(String),
+}
+
+// ---------------------------------------------------------------------
+// ノード自身 (子以外) を比較する。
+//
+fn shallow_equal(a: &RcNode, b: &RcNode, options: &DeepEqualOptions) -> bool {
+    if a.node_type != b.node_type || a.name != b.name {
+        return false;
+    }
+    if a.node_type == NodeType::Text && options.ignore_whitespace {
+        if normalize_white_space(&a.value) != normalize_white_space(&b.value) {
+            return false;
         }
-        self.clear_document_order();
+    } else if a.value != b.value {
+        return false;
     }
+    return compared_attributes(a, options) == compared_attributes(b, options);
+}
 
-    // =================================================================
-    /// Inserts the child node tree as the next sibling of 'self' node.
-    ///
-    /// # Examples
-    ///
-    /// ```
-    /// use amxml::dom::*;
-    ///
-    /// let xml_string = r#"<article><a/><b/><c/></article>"#;
-    /// let doc = new_document(&xml_string).unwrap();
-    /// let elem_b = doc.get_first_node("//b").unwrap();
-    ///
-    /// let new_xml_string = r#"<x>yyy</x>"#;
-    /// let new_doc = new_document(&new_xml_string).unwrap();
-    /// let elem_x = new_doc.root_element();
-    ///
-    /// // Inserts 'x' as the next sibling of 'b'
-    /// elem_b.insert_as_next_sibling(&elem_x);
-    /// let result = doc.to_string();
-    /// let guess = r#"<article><a/><b/><x>yyy</x><c/></article>"#;
-    /// assert_eq!(result, guess);
-    /// ```
-    ///
-    pub fn insert_as_next_sibling(&self, new_node: &NodePtr) {
-        let parent = match self.parent() {
-            Some(p) => p,
-            None => return,
-        };
-        let n = parent.find_child_index(self);
-        if n != usize::MAX {
-            let mut rc_parent = parent.unwrap_rc();
-            let rc_new_node = new_node.unwrap_rc();
+// ---------------------------------------------------------------------
+// 比較する属性の (名前、値) の一覧。
+//
+fn compared_attributes(rc_node: &RcNode, options: &DeepEqualOptions) -> Vec<(String, String)> {
+    let mut attrs: Vec<(String, String)> = rc_node.attributes.borrow().iter()
+                .map(|at| (at.name.clone(), at.value.clone())).collect();
+    if options.ignore_attribute_order {
+        attrs.sort();
+    }
+    return attrs;
+}
 
-            let mut rc_new_node_dup = make_new_child_rc_node(
-                rc_new_node.node_type.clone(),
-                &mut rc_parent,
-                &rc_new_node.name,
-                &rc_new_node.value,
-                n + 1);
-            shallow_copy_rc_rels(&mut rc_new_node_dup, &rc_new_node);
+// ---------------------------------------------------------------------
+// 比較する子の一覧。
+// コメントを無視する場合は除き、隣接するテキストは併合する。
+// 空白を無視する場合は、テキストの空白を正規化し、空になれば除く。
+//
+fn compared_children(rc_node: &RcNode, options: &DeepEqualOptions) -> Vec<ComparedChild> {
+    let mut children: Vec<ComparedChild> = vec!{};
+    let mut text = String::new();
+    for ch in rc_node.children.borrow().iter() {
+        match ch.node_type {
+            NodeType::Text => {
+                text += &ch.value;
+                continue;
+            },
+            NodeType::Comment if options.ignore_comments => {
+                continue;
+            },
+            _ => {},
         }
-        self.clear_document_order();
+        flush_compared_text(&mut children, &mut text, options);
+        children.push(ComparedChild::Node(Rc::clone(ch)));
     }
+    flush_compared_text(&mut children, &mut text, options);
+    return children;
+}
 
-    // =================================================================
-    /// Deletes the child node tree from 'self' node.
-    ///
-    /// # Examples
-    ///
-    /// ```
-    /// use amxml::dom::*;
-    ///
-    /// let xml_string = r#"<article><a/><b/><c/><d/></article>"#;
-    /// let doc = new_document(&xml_string).unwrap();
-    /// let elem_article = doc.get_first_node("//article").unwrap();
-    ///
-    /// let elem_b = elem_article.get_first_node("b").unwrap();
-    ///
-    /// // Deletes the child 'b' from 'article'
-    /// elem_article.delete_child(&elem_b);
-    /// let result = doc.to_string();
-    /// let guess = r#"<article><a/><c/><d/></article>"#;
-    /// assert_eq!(result, guess);
-    /// ```
-    ///
-    pub fn delete_child(&self, target: &NodePtr) {
-        let n = self.find_child_index(target);
-        if n != usize::MAX {
-            let rc_node = self.unwrap_rc();
-            (*rc_node).children.borrow_mut().remove(n);
-        }
-        self.clear_document_order();
+fn flush_compared_text(children: &mut Vec<ComparedChild>, text: &mut String,
+                options: &DeepEqualOptions) {
+    let t = if options.ignore_whitespace {
+        normalize_white_space(text)
+    } else {
+        text.clone()
+    };
+    if t != "" {
+        children.push(ComparedChild::Text(t));
     }
+    text.clear();
+}
 
-    // =================================================================
-    /// Replaces the child node tree with 'self' node.
-    ///
-    /// # Examples
-    ///
-    /// ```
-    /// use amxml::dom::*;
-    /// let xml_string = r#"<article><a/><b/><c/></article>"#;
-    /// let doc = new_document(&xml_string).unwrap();
-    /// let elem_b = doc.get_first_node("//b").unwrap();
-    ///
-    /// let new_xml_string = r#"<x>yyy</x>"#;
-    /// let new_doc = new_document(&new_xml_string).unwrap();
-    /// let elem_x = new_doc.root_element();
-    ///
-    /// // Replace 'b' with 'x'
-    /// elem_b.replace_with(&elem_x);
-    /// let result = doc.to_string();
-    /// let guess = r#"<article><a/><x>yyy</x><c/></article>"#;
-    /// assert_eq!(result, guess);
-    /// ```
+// ---------------------------------------------------------------------
+// 前後の空白を除去し、連続する空白を1個の空白に置き換える。
+//
+fn normalize_white_space(s: &str) -> String {
+    return s.split(|c| c == ' ' || c == '\t' || c == '\r' || c == '\n')
+            .filter(|t| *t != "")
+            .collect::<Vec<&str>>().join(" ");
+}
+
+// ---------------------------------------------------------------------
+// NodePtr#tree_hash() でノード型を表す値。
+//
+fn node_type_tag(node_type: &NodeType) -> u8 {
+    return match *node_type {
+        NodeType::DocumentRoot => 0,
+        NodeType::Element => 1,
+        NodeType::Text => 2,
+        NodeType::Comment => 3,
+        NodeType::XMLDecl => 4,
+        NodeType::Instruction => 5,
+        NodeType::Attribute => 6,
+        NodeType::Directive => 7,
+    };
+}
+
+// =====================================================================
+/// Options for NodePtr#to_string_with_options().
+///
+/// The default options give the same output as NodePtr#to_string().
+///
+#[derive(Debug, Clone, Default)]
+pub struct SerializeOptions {
+    indent: usize,
+    ascii_only: bool,
+    raw_quotes_in_text: bool,
+    cdata_section_elements: Vec<String>,
+    minify: bool,
+    standalone: bool,
+    max_char: Option<char>,         // これより大きい文字は文字参照にする
+    encoding: Option<String>,       // XML宣言の符号化宣言を置き換える
+    entities: HashMap<char, String>, // 実体参照として出力する文字 → 実体名
+}
+
+impl SerializeOptions {
+    // -----------------------------------------------------------------
+    /// Returns the default options.
     ///
-    pub fn replace_with(&self, new_node: &NodePtr) {
-        let parent = match self.parent() {
-            Some(p) => p,
-            None => return,
-        };
-        self.insert_as_previous_sibling(new_node);
-        parent.delete_child(self);
-        self.clear_document_order();
+    pub fn new() -> SerializeOptions {
+        return SerializeOptions::default();
     }
 
     // -----------------------------------------------------------------
-    // find_child_index
-    //
-    fn find_child_index(&self, target: &NodePtr) -> usize {
-        let rc_node = self.unwrap_rc();
-        let target_node = target.unwrap_rc();
-        for (i, ch) in (*rc_node).children.borrow().iter().enumerate() {
-            if Rc::ptr_eq(ch, &target_node) {
-                return i;
-            }
-        }
-        return usize::MAX;
+    /// Indents each element, text, etc. by the given number of spaces
+    /// per level, as NodePtr#to_pretty_string() does with 4.
+    /// 0 (default) means no indentation and no line breaks.
+    ///
+    pub fn indent(mut self, step: usize) -> SerializeOptions {
+        self.indent = step;
+        return self;
     }
 
-    // =================================================================
-    /// Returns the attribute value of element,
-    /// or None if there is no such attribute.
-    ///
-    /// # Examples
-    ///
-    /// ```
-    /// use amxml::dom::*;
-    /// let xml_string = r#"<article id="a1">foo</article>"#;
-    /// let doc = new_document(&xml_string).unwrap();
-    /// let root_elem = doc.root_element();
-    /// assert_eq!(root_elem.attribute_value("id").unwrap(), "a1");
-    /// assert!(root_elem.attribute_value("none").is_none());
-    /// ```
+    // -----------------------------------------------------------------
+    /// Writes non-ASCII characters in text and attribute values as
+    /// numeric character references (e.g. &amp;#xE9;), for sinks that
+    /// accept ASCII only.
+    /// Names, comments and processing instructions are written as is,
+    /// since character references are not allowed there.
     ///
-    pub fn attribute_value(&self, name: &str) -> Option<String> {
-        let r_index = self.find_attribute_index(name);
-        if r_index != usize::MAX {
-            let rc_node = self.unwrap_rc();
-            return Some((*rc_node).attributes.borrow()[r_index].value.clone());
-        } else {
-            return None;
-        }
+    pub fn ascii_only(mut self, ascii_only: bool) -> SerializeOptions {
+        self.ascii_only = ascii_only;
+        return self;
     }
 
-    // =================================================================
-    /// Updates the attribute value (if already exists) of element,
-    /// or adds the attribute (if not exist).
-    ///
-    /// # Examples
+    // -----------------------------------------------------------------
+    /// Writes quotation marks (" and ') in text as is, instead of
+    /// &amp;quot; and &amp;apos;.
+    /// They are always escaped in attribute values.
     ///
-    /// ```
-    /// use amxml::dom::*;
-    /// let xml_string = r#"<article id="a1">foo</article>"#;
-    /// let doc = new_document(&xml_string).unwrap();
-    /// let mut root_elem = doc.root_element();
-    /// assert_eq!(root_elem.attribute_value("id").unwrap(), "a1");
-    /// root_elem.set_attribute("id", "b1");
-    /// assert_eq!(root_elem.attribute_value("id").unwrap(), "b1");
-    /// root_elem.set_attribute("title", "about xml");
-    /// assert_eq!(root_elem.attribute_value("title").unwrap(), "about xml");
-    /// assert_eq!(doc.to_string(), r#"<article id="b1" title="about xml">foo</article>"#);
-    /// ```
+    pub fn raw_quotes_in_text(mut self, raw: bool) -> SerializeOptions {
+        self.raw_quotes_in_text = raw;
+        return self;
+    }
+
+    // -----------------------------------------------------------------
+    /// Writes text children of the elements with the given names
+    /// as CDATA sections, instead of escaping the markup characters.
     ///
-    pub fn set_attribute(&mut self, name: &str, value: &str) {
+    pub fn cdata_section_elements(mut self, names: &[&str]) -> SerializeOptions {
+        self.cdata_section_elements = names.iter().map(|s| String::from(*s)).collect();
+        return self;
+    }
 
-        let mut rc_node = self.unwrap_rc();
-        let attr_node = make_new_rc_node(NodeType::Attribute,
-                            Some(&mut rc_node), name, value);
+    // -----------------------------------------------------------------
+    /// Drops ignorable white space, that is, white space only text in
+    /// the document (outside of the root element) or in element only
+    /// content, and collapses each run of white space in other text
+    /// into a single space, as NodePtr#to_minified_string() does.
+    /// White space under xml:space="preserve" is written as is.
+    ///
+    pub fn minify(mut self, minify: bool) -> SerializeOptions {
+        self.minify = minify;
+        return self;
+    }
 
-        let r_index = self.find_attribute_index(name);
-        if r_index != usize::MAX {
-            (*rc_node).attributes.borrow_mut().remove(r_index);
-            (*rc_node).attributes.borrow_mut().insert(r_index, Rc::clone(&attr_node));
-        } else {
-            (*rc_node).attributes.borrow_mut().push(Rc::clone(&attr_node));
-        }
-        self.clear_document_order();
+    // -----------------------------------------------------------------
+    /// Declares, on the element being serialized, the namespaces that
+    /// its subtree uses but that are declared on its ancestors,
+    /// so that the output parses on its own with the same namespace
+    /// URIs, as NodePtr#to_standalone_string() does.
+    /// Has no effect when serializing the whole document.
+    ///
+    pub fn standalone(mut self, standalone: bool) -> SerializeOptions {
+        self.standalone = standalone;
+        return self;
     }
 
-    // =================================================================
-    /// Deletes the attribute (if already exists) of element.
+    // -----------------------------------------------------------------
+    /// Writes the character as the entity reference
+    /// <code>&amp;name;</code> in text and attribute values,
+    /// e.g. to round-trip the entities registered by
+    /// ParseOptions#entity(). The value must be a single character;
+    /// otherwise this has no effect.
+    /// The output is well-formed only if the entity is declared
+    /// (e.g. in the DOCTYPE) or known to the reader.
     ///
     /// # Examples
     ///
     /// ```
     /// use amxml::dom::*;
-    /// let xml_string = r#"<article id="a1">foo</article>"#;
-    /// let doc = new_document(&xml_string).unwrap();
-    /// let mut root_elem = doc.root_element();
-    /// root_elem.delete_attribute("id");
-    /// assert!(root_elem.attribute_value("id").is_none());
-    /// assert_eq!(doc.to_string(), r#"<article>foo</article>"#);
+    /// let options = ParseOptions::new().entity("nbsp", "\u{A0}");
+    /// let doc = new_document_with_options("<p a='1&nbsp;m'>&nbsp;&#xA0;</p>", &options).unwrap();
+    /// let options = SerializeOptions::new().entity("nbsp", "\u{A0}");
+    /// assert_eq!(doc.to_string_with_options(&options), r#"<p a="1&nbsp;m">&nbsp;&nbsp;</p>"#);
     /// ```
     ///
-    pub fn delete_attribute(&mut self, name: &str) {
-        let r_index = self.find_attribute_index(name);
-        if r_index != usize::MAX {
-            let rc_node = self.unwrap_rc();
-            (*rc_node).attributes.borrow_mut().remove(r_index);
+    pub fn entity(mut self, name: &str, value: &str) -> SerializeOptions {
+        let mut chars = value.chars();
+        if let (Some(ch), None) = (chars.next(), chars.next()) {
+            self.entities.insert(ch, String::from(name));
         }
-        self.clear_document_order();
+        return self;
     }
 
     // -----------------------------------------------------------------
-    //
-    fn find_attribute_index(&self, name: &str) -> usize {
-        let rc_node = self.unwrap_rc();
-        for (i, at) in (*rc_node).attributes.borrow().iter().enumerate() {
-            if at.name == name {
-                return i;
+    /// Writes the characters of the HTML 4 character entities as
+    /// entity references, e.g. U+00A0 as <code>&amp;nbsp;</code>
+    /// (feature "html-entities"). cf. html_entities
+    ///
+    #[cfg(feature = "html-entities")]
+    pub fn html_entities(mut self) -> SerializeOptions {
+        for (name, ch) in HTML4_ENTITIES.iter() {
+            self = self.entity(name, &ch.to_string());
+        }
+        return self;
+    }
+}
+
+// ---------------------------------------------------------------------
+// 文字列化の作業スタックの項目。
+//
+enum SerializeWork {
+    Node(RcNode, usize, bool, bool),    // (ノード, 字下げ, CDATA区間として出力するか,
+                                        //  空白を保存するか)
+    EndTag(RcNode, usize),              // (要素, 字下げ)
+}
+
+// ---------------------------------------------------------------------
+// 文字列化する子ノードの一覧。
+// options.minify ならば (空白を保存する場合を除き)、無視できる空白
+// (文書直下、または要素内容のみの要素の、空白だけのテキスト) を除き、
+// 連続するテキストを、空白の並びを1個の空白に縮めたテキストにまとめる。
+//
+fn serialized_children(rc_node: &RcNode, options: &SerializeOptions,
+                preserve: bool) -> Vec<RcNode> {
+    let children = rc_node.children.borrow();
+    if ! options.minify || preserve {
+        return children.clone();
+    }
+    let ignorable = rc_node.node_type == NodeType::DocumentRoot ||
+        ! has_non_space_text(rc_node);
+    let mut result: Vec<RcNode> = vec!{};
+    let mut text = String::new();
+    for ch in children.iter() {
+        if ch.node_type == NodeType::Text {
+            text += &ch.value;
+            continue;
+        }
+        push_collapsed_text(&mut result, &text, ignorable);
+        text.clear();
+        result.push(Rc::clone(ch));
+    }
+    push_collapsed_text(&mut result, &text, ignorable);
+    return result;
+}
+
+// ---------------------------------------------------------------------
+// 空白の並びを1個の空白に縮めたテキストノード (木には繋がない) を
+// result に追加する。空のテキスト、および ignorable ならば空白だけの
+// テキストは追加しない。
+//
+fn push_collapsed_text(result: &mut Vec<RcNode>, text: &str, ignorable: bool) {
+    if text == "" || (ignorable && text.chars().all(is_space_char)) {
+        return;
+    }
+    let mut collapsed = String::new();
+    let mut in_space = false;
+    for c in text.chars() {
+        if is_space_char(c) {
+            if ! in_space {
+                collapsed.push(' ');
             }
+            in_space = true;
+        } else {
+            collapsed.push(c);
+            in_space = false;
         }
-        return usize::MAX;
     }
+    result.push(make_new_rc_node(NodeType::Text, None, "", &collapsed));
+}
 
-    // -----------------------------------------------------------------
-    //
-    fn clear_document_order(&self) {
-        let root = self.root();
-        root.unwrap_rc().order.set(0);
+// ---------------------------------------------------------------------
+// 子に、空白以外の文字を含むテキストがあるか否か (混在内容か否か)。
+//
+fn has_non_space_text(rc_node: &RcNode) -> bool {
+    return rc_node.children.borrow().iter().any(|ch|
+        ch.node_type == NodeType::Text && ! ch.value.chars().all(is_space_char));
+}
+
+// ---------------------------------------------------------------------
+// XML の空白文字 (#x20 | #x9 | #xD | #xA)。
+//
+fn is_space_char(c: char) -> bool {
+    return c == ' ' || c == '\t' || c == '\r' || c == '\n';
+}
+
+// ---------------------------------------------------------------------
+// 深い木でもスタックを使い切らないよう、再帰せず作業スタックでたどる。
+//
+fn to_string_with_indent(rc_node: &RcNode, indent: usize,
+                options: &SerializeOptions) -> String {
+    let step = options.indent;
+    let mut s = String::new();
+    let preserve = match wrap_rc_clone(rc_node).parent() {
+        Some(p) => p.preserves_space(),
+        None => false,
+    };
+    let mut inherited_decls = if options.standalone {
+        wrap_rc_clone(rc_node).inherited_namespace_decls()
+    } else {
+        vec!{}
+    };
+    let mut stack: Vec<SerializeWork> = vec!{
+        SerializeWork::Node(Rc::clone(rc_node), indent, false, preserve),
+    };
+    while let Some(work) = stack.pop() {
+        let (rc_node, indent, cdata, preserve) = match work {
+            SerializeWork::EndTag(rc_node, indent) => {
+                s += &format!("{}</{}>", " ".repeat(indent), rc_node.name);
+                s += &nl_if_positive(step);
+                continue;
+            },
+            SerializeWork::Node(rc_node, indent, cdata, preserve) =>
+                (rc_node, indent, cdata, preserve),
+        };
+        match rc_node.node_type {
+            NodeType::DocumentRoot => {
+                let children = serialized_children(&rc_node, options, preserve);
+                for ch in children.iter().rev() {
+                    stack.push(SerializeWork::Node(Rc::clone(ch), indent, false, preserve));
+                }
+            },
+            NodeType::Element => {
+                s += &format!("{}<{}", " ".repeat(indent), rc_node.name);
+                for at in rc_node.attributes.borrow().iter() {
+                    s += &format!(r#" {}="{}""#,
+                        at.name, encode_attribute_value(&at.value, options));
+                }
+                for (name, uri) in inherited_decls.drain(..) {
+                    s += &format!(r#" {}="{}""#,
+                        name, encode_attribute_value(&uri, options));
+                }
+                let preserve = match wrap_rc_clone(&rc_node).attribute_value("xml:space") {
+                    Some(value) => value == "preserve",
+                    None => preserve,
+                };
+                let children = serialized_children(&rc_node, options, preserve);
+                if children.len() == 0 {
+                    s += &"/>";
+                    s += &nl_if_positive(step);
+                } else {
+                    s += &">";
+                    s += &nl_if_positive(step);
+                    stack.push(SerializeWork::EndTag(Rc::clone(&rc_node), indent));
+                    let cdata = options.cdata_section_elements.contains(&rc_node.name);
+                    for ch in children.iter().rev() {
+                        stack.push(SerializeWork::Node(Rc::clone(ch), indent + step, cdata, preserve));
+                    }
+                }
+            },
+            NodeType::Text => {
+                if rc_node.raw.get() {
+                    s += &format!("{}{}{}",
+                        &" ".repeat(indent),
+                        &rc_node.value,
+                        &nl_if_positive(step));
+                } else if cdata {
+                    s += &format!("{}{}{}",
+                        &" ".repeat(indent),
+                        &encode_cdata_section(&rc_node.value, options),
+                        &nl_if_positive(step));
+                } else {
+                    s += &format!("{}{}{}",
+                        &" ".repeat(indent),
+                        &encode_text(&(rc_node.value), options),
+                        &nl_if_positive(step));
+                }
+            },
+            NodeType::Comment => {
+                s += &format!("{}<!--{}-->{}",
+                    &" ".repeat(indent),
+                    &escape_comment(&rc_node.value),
+                    &nl_if_positive(step));
+            },
+            NodeType::XMLDecl => {
+                let xml_decl = match options.encoding {
+                    Some(ref encoding) => xml_decl_with_encoding(&rc_node.value, encoding),
+                    None => rc_node.value.clone(),
+                };
+                s += &format!("{}<?xml {}?>{}",
+                    &" ".repeat(indent),
+                    &xml_decl,
+                    &nl_if_positive(step));
+            },
+            NodeType::Instruction => {
+                s += &format!("{}<?{} {}?>{}",
+                    &" ".repeat(indent),
+                    &rc_node.name,
+                    &escape_processing_instruction(&rc_node.value),
+                    &nl_if_positive(step));
+            },
+            NodeType::Directive => {
+                s += &format!("{}<!{}>{}",
+                    &" ".repeat(indent),
+                    &rc_node.value,
+                    &nl_if_positive(step));
+            },
+            _ => {},
+        }
     }
+    return s;
+}
 
-    // =================================================================
-    /// (Inner Use)
-    ///
-    pub fn document_order(&self) -> i64 {
-        let root = self.root();
-        if root.unwrap_rc().order.get() == 0 {
-            root.setup_document_order();
+// ---------------------------------------------------------------------
+//
+// 空白文字 (タブ、改行) は、読み直したとき正規化されないよう
+// 文字参照にする (escape::escape_attribute())。
+//
+fn encode_attribute_value(s: &String, options: &SerializeOptions) -> String {
+    return encode_non_ascii(&escape_attribute(s), options);
+}
+
+// ---------------------------------------------------------------------
+//
+fn encode_text(s: &String, options: &SerializeOptions) -> String {
+    let str = if options.raw_quotes_in_text {
+        escape_text(s)
+    } else {
+        escape_text_and_quotes(s)
+    };
+    return encode_non_ascii(&str, options);
+}
+
+// ---------------------------------------------------------------------
+// CDATAセクションとして出力する。
+// 「]]>」は2つのCDATAセクションに分割する (escape::escape_cdata_section())。
+// 非ASCII文字を文字参照にする場合は、CDATAセクションの外に出す。
+//
+fn encode_cdata_section(s: &String, options: &SerializeOptions) -> String {
+    let sections = escape_cdata_section(s);
+    let max_char = match output_max_char(options) {
+        Some(max_char) => max_char,
+        None => return sections,
+    };
+    if sections.chars().all(|c| c <= max_char) {
+        return sections;
+    }
+    let mut str = String::new();
+    for c in sections.chars() {
+        if c <= max_char {
+            str.push(c);
+        } else {
+            str += &format!("]]>&#x{:X};<![CDATA[", c as u32);
         }
-        return self.unwrap_rc().order.get();
     }
+    return str.replace("<![CDATA[]]>", "");
+}
+
+// ---------------------------------------------------------------------
+//
+// 実体参照として出力する文字 (SerializeOptions#entity()) は、
+// 文字参照より優先する。
+//
+fn encode_non_ascii(s: &String, options: &SerializeOptions) -> String {
+    let max_char = output_max_char(options).unwrap_or(char::MAX);
+    let entity_name = |c: char| options.entities.get(&c).map(|n| n.as_str());
+    if s.chars().all(|c| c <= max_char && entity_name(c).is_none()) {
+        return s.clone();
+    }
+    let mut str = String::new();
+    for c in s.chars() {
+        if let Some(name) = entity_name(c) {
+            str += &format!("&{};", name);
+        } else if c <= max_char {
+            str.push(c);
+        } else {
+            str += &format!("&#x{:X};", c as u32);
+        }
+    }
+    return str;
+}
+
+// ---------------------------------------------------------------------
+// そのまま出力できる最大の文字。これより大きい文字は文字参照にする。
+// None ならばすべての文字をそのまま出力する。
+//
+fn output_max_char(options: &SerializeOptions) -> Option<char> {
+    if options.ascii_only {
+        return Some('\u{7F}');
+    }
+    return options.max_char;
+}
+
+// ---------------------------------------------------------------------
+// NodePtr#write_to_with_encoding() の出力符号化方式。
+//
+#[cfg(not(feature = "no_std"))]
+#[derive(Clone, Copy, PartialEq)]
+enum OutputEncoding {
+    Utf8,
+    Utf16Le,
+    Latin1,
+}
 
+#[cfg(not(feature = "no_std"))]
+impl OutputEncoding {
     // -----------------------------------------------------------------
+    // 符号化方式の名前 (大文字小文字を区別しない) から。
     //
-    fn setup_document_order(&self) {
-        self.setup_document_order_sub(1);
+    fn from_name(name: &str) -> Option<OutputEncoding> {
+        match name.to_ascii_uppercase().as_str() {
+            "UTF-8" | "UTF8" => return Some(OutputEncoding::Utf8),
+            "UTF-16LE" | "UTF-16" => return Some(OutputEncoding::Utf16Le),
+            "ISO-8859-1" | "LATIN-1" | "LATIN1" => return Some(OutputEncoding::Latin1),
+            _ => return None,
+        }
     }
 
     // -----------------------------------------------------------------
+    // 符号化宣言に書く名前。
+    // BOMを付けるUTF-16LEは、XML 1.0 (4.3.3) に従い "UTF-16" と宣言する。
     //
-    fn setup_document_order_sub(&self, order_beg: i64) -> i64 {
-        let mut order = order_beg;
-        self.unwrap_rc().order.set(order);
-        order += 1;
-        for at in self.attributes().iter() {
-            at.unwrap_rc().order.set(order);
-            order += 1;
-        }
-        for ch in self.children().iter() {
-            order = ch.setup_document_order_sub(order + 1);
+    fn declared_name(&self) -> &'static str {
+        match *self {
+            OutputEncoding::Utf8 => return "UTF-8",
+            OutputEncoding::Utf16Le => return "UTF-16",
+            OutputEncoding::Latin1 => return "ISO-8859-1",
         }
-        return order;
     }
 
-    // =================================================================
-    /// (Inner Use)
-    ///
-    pub fn rc_clone(&self) -> NodePtr {
-        return NodePtr {
-            rc_node: Rc::clone(&self.rc_node),
-        };
+    // -----------------------------------------------------------------
+    // 先頭に出力するBOM。
+    //
+    fn bom(&self) -> &'static [u8] {
+        match *self {
+            OutputEncoding::Utf16Le => return &[0xFF, 0xFE],
+            _ => return &[],
+        }
     }
 
     // -----------------------------------------------------------------
+    // そのまま出力できる最大の文字。
     //
-    fn unwrap_rc(&self) -> RcNode {
-        return Rc::clone(&self.rc_node);
+    fn max_char(&self) -> Option<char> {
+        match *self {
+            OutputEncoding::Latin1 => return Some('\u{FF}'),
+            _ => return None,
+        }
     }
-}
 
-// ---------------------------------------------------------------------
-//
-fn to_string_with_indent(rc_node: &RcNode, indent: usize, step: usize) -> String {
-    match rc_node.node_type {
-        NodeType::DocumentRoot => {
-            let mut s = String::new();
-            for ch in rc_node.children.borrow().iter() {
-                s += &to_string_with_indent(ch, indent, step);
-            }
-            return s;
-        },
-        NodeType::Element => {
-            let mut s = String::new();
-            s += &format!("{}<{}", " ".repeat(indent), rc_node.name);
-            for at in rc_node.attributes.borrow().iter() {
-                s += &format!(r#" {}="{}""#,
-                    at.name, encode_entity(&at.value));
-            }
-            if rc_node.children.borrow().len() == 0 {
-                s += &"/>";
-            } else {
-                s += &">";
-                s += &nl_if_positive(step);
-                for ch in rc_node.children.borrow().iter() {
-                    s += &to_string_with_indent(ch, indent + step, step);
+    // -----------------------------------------------------------------
+    // 1文字を符号化して buf に追加する。
+    // 符号化できない文字 (名前、コメントなど、文字参照にできない箇所に
+    // 現れたもの) ならばエラー。
+    //
+    fn encode_char(&self, c: char, buf: &mut Vec<u8>) -> Result<(), Box<Error>> {
+        match *self {
+            OutputEncoding::Utf8 => {
+                let mut b = [0; 4];
+                buf.extend_from_slice(c.encode_utf8(&mut b).as_bytes());
+            },
+            OutputEncoding::Utf16Le => {
+                let mut u = [0; 2];
+                for unit in c.encode_utf16(&mut u).iter() {
+                    buf.push((*unit & 0xFF) as u8);
+                    buf.push((*unit >> 8) as u8);
                 }
-                s += &format!("{}</{}>", " ".repeat(indent), rc_node.name);
-            }
-            s += &nl_if_positive(step);
-            return s;
-        },
-        NodeType::Text => {
-            return format!("{}{}{}",
-                &" ".repeat(indent),
-                &encode_entity(&(rc_node.value)),
-                &nl_if_positive(step));
-        },
-        NodeType::Comment => {
-            return format!("{}<!--{}-->{}",
-                &" ".repeat(indent),
-                &rc_node.value,
-                &nl_if_positive(step));
-        },
-        NodeType::XMLDecl => {
-            return format!("{}<?xml {}?>{}",
-                &" ".repeat(indent),
-                &rc_node.value,
-                &nl_if_positive(step));
-        },
-        NodeType::Instruction => {
-            return format!("{}<?{} {}?>{}",
-                &" ".repeat(indent),
-                &rc_node.name,
-                &rc_node.value,
-                &nl_if_positive(step));
-        },
-        _ => return String::new(),
-    }
-}
-
-// ---------------------------------------------------------------------
-//
-fn encode_entity(s: &String) -> String {
-    let specs = [
-        [ "&", "&amp;" ],
-        [ ">", "&gt;" ],
-        [ "<", "&lt;" ],
-        [ "\"", "&quot;" ],
-        [ "'", "&apos;" ],
-    ];
-    let mut str = s.clone();
-    for spec in specs.iter() {
-        str = str.replace(spec[0], spec[1]);
+            },
+            OutputEncoding::Latin1 => {
+                if '\u{FF}' < c {
+                    return Err(uninplemented!(
+                        "Character U+{:04X} can't be encoded in {} outside of text and attribute values",
+                        c as u32, self.declared_name()));
+                }
+                buf.push(c as u8);
+            },
+        }
+        return Ok(());
     }
-    return str
 }
 
 // ---------------------------------------------------------------------
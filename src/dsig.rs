@@ -0,0 +1,291 @@
+//
+// dsig.rs
+//
+// amxml: XML processor with XPath.
+// Copyright (C) 2018 KOYAMA Hiro <tac@amris.co.jp>
+//
+//!
+//! Digests for XML Signature.
+//!
+//! Available with the feature <code>crypto</code>.
+//!
+//! NodePtr#c14n_digests() computes the SHA-256 digests of the
+//! Canonical XML (see the module c14n) of the nodes selected by
+//! XPath, and NodePtr#verify_reference_digests() checks the digests
+//! in the References of the
+//! <a href="https://www.w3.org/TR/xmldsig-core1/">XML Signature</a>
+//! enveloped in the document.
+//!
+//! Only the digests are verified; verifying the SignatureValue,
+//! i.e. the signature of the SignedInfo by the key of the signer,
+//! is left to the cryptographic library of the caller, which may
+//! get the octets to verify by SignedInfo's canonicalize().
+//!
+//! # Examples
+//!
+//! ```
+//! use amxml::dom::*;
+//! let xml = r##"<root Id="r"><a>1</a><ds:Signature xmlns:ds="http://www.w3.org/2000/09/xmldsig#"><ds:SignedInfo>
+//!   <ds:CanonicalizationMethod Algorithm="http://www.w3.org/TR/2001/REC-xml-c14n-20010315"/>
+//!   <ds:SignatureMethod Algorithm="http://www.w3.org/2001/04/xmldsig-more#rsa-sha256"/>
+//!   <ds:Reference URI="">
+//!     <ds:Transforms><ds:Transform Algorithm="http://www.w3.org/2000/09/xmldsig#enveloped-signature"/></ds:Transforms>
+//!     <ds:DigestMethod Algorithm="http://www.w3.org/2001/04/xmlenc#sha256"/>
+//!     <ds:DigestValue>oaByC02H30Y3Wt1B4+gGHTNGjFUo1cA/xfp4bN1KS+4=</ds:DigestValue>
+//!   </ds:Reference>
+//! </ds:SignedInfo><ds:SignatureValue>...</ds:SignatureValue></ds:Signature></root>"##;
+//! let doc = new_document(xml).unwrap();
+//! assert_eq!(doc.verify_reference_digests().unwrap(), 1);
+//!
+//! let mut a = doc.get_first_node("//a").unwrap();
+//! a.set_attribute("x", "tampered").unwrap();
+//! assert!(doc.verify_reference_digests().is_err());
+//! ```
+//!
+
+use std::error::Error;
+use sha2::{Digest, Sha256};
+
+use binary::*;
+use c14n::*;
+use dom::*;
+use xmlerror::*;
+
+/// Namespace URI of XML Signature.
+pub const DSIG_NS_URI: &str = "http://www.w3.org/2000/09/xmldsig#";
+
+const ALG_SHA256: &str = "http://www.w3.org/2001/04/xmlenc#sha256";
+const ALG_ENVELOPED: &str = "http://www.w3.org/2000/09/xmldsig#enveloped-signature";
+const ALG_C14N: &str = "http://www.w3.org/TR/2001/REC-xml-c14n-20010315";
+const ALG_C14N_WITH_COMMENTS: &str = "http://www.w3.org/TR/2001/REC-xml-c14n-20010315#WithComments";
+
+// =====================================================================
+/// Returns the SHA-256 digest of the octets.
+///
+/// # Examples
+///
+/// ```
+/// use amxml::binary::*;
+/// use amxml::dsig::*;
+/// assert_eq!(encode_hex(&sha256(b"<a>1</a>")),
+///     "3838997C59D257450A1508A52A1C3BCDFBABB24EC65A21B4D644E0CEA99FC29B");
+/// ```
+///
+pub fn sha256(octets: &[u8]) -> Vec<u8> {
+    let mut hasher = Sha256::new();
+    hasher.update(octets);
+    return hasher.finalize().to_vec();
+}
+
+// =====================================================================
+//
+impl NodePtr {
+
+    // =================================================================
+    /// Evaluates the xpath and returns the SHA-256 digests of the
+    /// Canonical XML (without comments) of the selected nodes,
+    /// in the order of the nodes.
+    /// The Signature elements of XML Signature in them are excluded,
+    /// as the enveloped signature transform does.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use amxml::dom::*;
+    /// use amxml::binary::*;
+    /// let doc = new_document(r#"<root><a>1</a><b><a>1</a></b></root>"#).unwrap();
+    /// let digests = doc.c14n_digests("//a").unwrap();
+    /// assert_eq!(digests.len(), 2);
+    /// assert_eq!(encode_base64(&digests[1]), "ODiZfFnSV0UKFQilKhw7zfursk7GWiG01kTgzqmfwps=");
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// - When syntax error or unimplemented feature in xpath.
+    /// - When the result contains an item that is not a node.
+    ///
+    pub fn c14n_digests(&self, xpath: &str) -> Result<Vec<Vec<u8>>, Box<Error>> {
+        let mut options = C14nOptions::new();
+        for sig in signature_elements(&self.root()).iter() {
+            options = options.exclude(sig);
+        }
+        let mut digests: Vec<Vec<u8>> = vec!{};
+        for node in self.get_nodeset(xpath)?.iter() {
+            digests.push(sha256(node.canonicalize_with_options(&options).as_bytes()));
+        }
+        return Ok(digests);
+    }
+
+    // =================================================================
+    /// Verifies the DigestValue of each Reference in the Signature
+    /// elements of XML Signature in the document that the node belongs
+    /// to, and returns the number of the References verified.
+    /// See the module document for an example.
+    ///
+    /// Supported are the same-document references
+    /// (<code>URI=""</code> for the whole document, and
+    /// <code>URI="#id"</code> for the element with the attribute
+    /// Id, ID or id), the transforms of enveloped signature and
+    /// Canonical XML 1.0, and the digest method of SHA-256.
+    /// As these references require, comments are excluded.
+    ///
+    /// # Errors
+    ///
+    /// - When there is no Signature element in the document.
+    /// - When a Reference uses a URI, transform or digest method
+    ///   that is not supported.
+    /// - When the referenced element is not found, or more than one
+    ///   element has the Id.
+    /// - When the digest of a Reference does not match.
+    ///
+    pub fn verify_reference_digests(&self) -> Result<usize, Box<Error>> {
+        let doc = self.root();
+        let signatures = signature_elements(&doc);
+        if signatures.is_empty() {
+            return Err(dynamic_error!("No Signature element"));
+        }
+        let mut n_verified: usize = 0;
+        for sig in signatures.iter() {
+            for signed_info in dsig_children(sig, "SignedInfo").iter() {
+                for reference in dsig_children(signed_info, "Reference").iter() {
+                    verify_reference(&doc, sig, reference)?;
+                    n_verified += 1;
+                }
+            }
+        }
+        return Ok(n_verified);
+    }
+}
+
+// ---------------------------------------------------------------------
+// Reference 要素ひとつの DigestValue を検証する。
+//
+fn verify_reference(doc: &NodePtr, sig: &NodePtr, reference: &NodePtr) -> Result<(), Box<Error>> {
+    let uri = reference.attribute_value("URI").unwrap_or(String::new());
+    let target = if uri == "" {
+        doc.rc_clone()
+    } else if uri.starts_with("#") && ! uri.starts_with("#xpointer(") {
+        // 同じIdの要素が複数あれば検証しない (signature wrapping 対策)。
+        let id = &uri[1 ..];
+        let mut found: Vec<NodePtr> = doc.walk().node_type(NodeType::Element)
+            .filter(|e| ["Id", "ID", "id"].iter().any(|name|
+                e.attribute_value(name).map_or(false, |v| v == id)))
+            .collect();
+        match found.len() {
+            0 => return Err(dynamic_error!("Reference {}: element not found", uri)),
+            1 => found.remove(0),
+            n => return Err(dynamic_error!(
+                    "Reference {}: {} elements with the same Id", uri, n)),
+        }
+    } else {
+        return Err(dynamic_error!("Reference {}: URI not supported", uri));
+    };
+
+    let mut options = C14nOptions::new();
+    for transforms in dsig_children(reference, "Transforms").iter() {
+        for transform in dsig_children(transforms, "Transform").iter() {
+            let alg = transform.attribute_value("Algorithm").unwrap_or(String::new());
+            if alg == ALG_ENVELOPED {
+                options = options.exclude(sig);
+            } else if alg != ALG_C14N && alg != ALG_C14N_WITH_COMMENTS {
+                return Err(dynamic_error!(
+                    "Reference {}: transform {} not supported", uri, alg));
+            }
+        }
+    }
+
+    let digest_method = dsig_children(reference, "DigestMethod").iter()
+            .filter_map(|m| m.attribute_value("Algorithm")).next()
+            .unwrap_or(String::new());
+    if digest_method != ALG_SHA256 {
+        return Err(dynamic_error!(
+            "Reference {}: digest method {} not supported", uri, digest_method));
+    }
+    let digest_value = match dsig_children(reference, "DigestValue").first() {
+        Some(v) => {
+            let text: String = v.children().iter().map(|ch| ch.value()).collect();
+            decode_base64(&text)?
+        },
+        None => return Err(dynamic_error!("Reference {}: no DigestValue", uri)),
+    };
+
+    let digest = sha256(target.canonicalize_with_options(&options).as_bytes());
+    if digest != digest_value {
+        return Err(dynamic_error!("Reference {}: digest mismatch", uri));
+    }
+    return Ok(());
+}
+
+// ---------------------------------------------------------------------
+// 文書にある XML Signature の Signature 要素 (文書順)。
+//
+fn signature_elements(doc: &NodePtr) -> Vec<NodePtr> {
+    return doc.walk().node_type(NodeType::Element)
+            .filter(|e| is_dsig_element(e, "Signature")).collect();
+}
+
+// ---------------------------------------------------------------------
+// XML Signature の名前空間にある、局所名 local_name の子要素。
+//
+fn dsig_children(node: &NodePtr, local_name: &str) -> Vec<NodePtr> {
+    return node.children().into_iter()
+            .filter(|e| is_dsig_element(e, local_name)).collect();
+}
+
+// ---------------------------------------------------------------------
+//
+fn is_dsig_element(node: &NodePtr, local_name: &str) -> bool {
+    return node.node_type() == NodeType::Element &&
+           node.local_name() == local_name &&
+           node.namespace_uri() == DSIG_NS_URI;
+}
+
+// =====================================================================
+//
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // -----------------------------------------------------------------
+    //
+    fn signed_document(uri: &str, transform: &str) -> NodePtr {
+        let xml = format!(r##"<root xmlns:ds="http://www.w3.org/2000/09/xmldsig#"><item Id="i1">x</item><ds:Signature><ds:SignedInfo><ds:Reference URI="{}"><ds:Transforms><ds:Transform Algorithm="{}"/></ds:Transforms><ds:DigestMethod Algorithm="http://www.w3.org/2001/04/xmlenc#sha256"/><ds:DigestValue>7OW/R7ROhiVCQsJUCNXkPWAcPVrCGWc8U0/HUGyttWA=</ds:DigestValue></ds:Reference></ds:SignedInfo></ds:Signature></root>"##, uri, transform);
+        return new_document(&xml).unwrap();
+    }
+
+    // -----------------------------------------------------------------
+    //
+    #[test]
+    fn test_verify_reference_digests() {
+        // 名前空間宣言は祖先から継承する。
+        let doc = signed_document("#i1", ALG_C14N);
+        assert_eq!(doc.verify_reference_digests().unwrap(), 1);
+
+        let doc = signed_document("#i2", ALG_C14N);
+        assert!(doc.verify_reference_digests().is_err());
+        let doc = signed_document("#i1", "http://www.w3.org/2001/10/xml-exc-c14n#");
+        assert!(doc.verify_reference_digests().is_err());
+        let doc = signed_document("", ALG_ENVELOPED);
+        assert!(doc.verify_reference_digests().is_err());
+
+        let doc = new_document("<root/>").unwrap();
+        assert!(doc.verify_reference_digests().is_err());
+    }
+
+    // -----------------------------------------------------------------
+    // 同じIdの要素が複数ある文書 (signature wrapping) は検証しない。
+    //
+    #[test]
+    fn test_verify_reference_duplicated_id() {
+        let doc = signed_document("#i1", ALG_C14N);
+        let xml = doc.to_string().replace("<item Id=\"i1\">",
+                    "<wrapper><item id=\"i1\">y</item></wrapper><item Id=\"i1\">");
+        let doc = new_document(&xml).unwrap();
+        let err = doc.verify_reference_digests().err().unwrap();
+        assert!(err.to_string().contains("2 elements with the same Id"), "{}", err);
+
+        let xml = doc.to_string().replace("<wrapper><item id=\"i1\">y</item></wrapper>", "");
+        let doc = new_document(&xml).unwrap();
+        assert_eq!(doc.verify_reference_digests().unwrap(), 1);
+    }
+}
@@ -0,0 +1,197 @@
+//
+// escape.rs
+//
+// amxml: XML processor with XPath.
+// Copyright (C) 2018 KOYAMA Hiro <tac@amris.co.jp>
+//
+//!
+//! Escaping rules of the XML serializers.
+//!
+//! All the serializers of this crate (NodePtr#to_string() and the like,
+//! project::project_xml(), c14n, from_serde) escape the text through the
+//! functions of this module, so that the same rule is applied to the
+//! same kind of content:
+//!
+//! - Text: <code>&amp;</code>, <code>&lt;</code> and <code>&gt;</code>
+//!   (so that <code>]]&gt;</code> never appears), and optionally
+//!   the quotes.
+//! - Attribute value (quoted with <code>"</code>): the characters
+//!   above, both quotes, and tab, newline and carriage return as
+//!   character references, which would otherwise be normalized into
+//!   spaces when read again.
+//! - Comment: can't contain escapes; a space is inserted into
+//!   <code>--</code> and after the trailing <code>-</code>.
+//! - Processing instruction: a space is inserted into <code>?&gt;</code>.
+//! - CDATA section: <code>]]&gt;</code> is split into two sections.
+//! - Canonical XML has its own rules (escape_c14n_text(),
+//!   escape_c14n_attribute()).
+//!
+//! # Examples
+//!
+//! ```
+//! use amxml::escape::*;
+//! assert_eq!(escape_text("a < b && c > d"), "a &lt; b &amp;&amp; c &gt; d");
+//! assert_eq!(escape_attribute("say \"hi\"\n"), "say &quot;hi&quot;&#xA;");
+//! assert_eq!(escape_comment("a--b-"), "a- -b- ");
+//! assert_eq!(escape_cdata_section("x]]>y"), "<![CDATA[x]]]]><![CDATA[>y]]>");
+//! ```
+//!
+
+#[cfg(feature = "no_std")]
+use std::prelude::*;
+
+// ---------------------------------------------------------------------
+// 文字列中の各文字を、replacement が返す文字列 (Noneならばその文字
+// のまま) に置き換える。
+//
+fn replace_chars<F>(s: &str, replacement: F) -> String
+            where F: Fn(char) -> Option<&'static str> {
+    let mut result = String::with_capacity(s.len());
+    for c in s.chars() {
+        match replacement(c) {
+            Some(r) => result += r,
+            None => result.push(c),
+        }
+    }
+    return result;
+}
+
+// =====================================================================
+/// Escapes &amp;, &lt; and &gt; in the text.
+///
+pub fn escape_text(s: &str) -> String {
+    return replace_chars(s, |c| match c {
+        '&' => Some("&amp;"),
+        '<' => Some("&lt;"),
+        '>' => Some("&gt;"),
+        _ => None,
+    });
+}
+
+// =====================================================================
+/// Escapes &amp;, &lt;, &gt;, " and ' in the text,
+/// as NodePtr#to_string() does by default.
+///
+pub fn escape_text_and_quotes(s: &str) -> String {
+    return replace_chars(s, |c| match c {
+        '&' => Some("&amp;"),
+        '<' => Some("&lt;"),
+        '>' => Some("&gt;"),
+        '"' => Some("&quot;"),
+        '\'' => Some("&apos;"),
+        _ => None,
+    });
+}
+
+// =====================================================================
+/// Escapes the attribute value: as escape_text_and_quotes(), and
+/// tab, newline and carriage return as character references.
+///
+pub fn escape_attribute(s: &str) -> String {
+    return replace_chars(s, |c| match c {
+        '&' => Some("&amp;"),
+        '<' => Some("&lt;"),
+        '>' => Some("&gt;"),
+        '"' => Some("&quot;"),
+        '\'' => Some("&apos;"),
+        '\t' => Some("&#x9;"),
+        '\n' => Some("&#xA;"),
+        '\r' => Some("&#xD;"),
+        _ => None,
+    });
+}
+
+// =====================================================================
+/// Makes the text a valid content of comment, which must not contain
+/// "--" nor end with "-", by inserting spaces.
+///
+pub fn escape_comment(s: &str) -> String {
+    let mut result = String::with_capacity(s.len());
+    let mut prev = ' ';
+    for c in s.chars() {
+        if prev == '-' && c == '-' {
+            result.push(' ');
+        }
+        result.push(c);
+        prev = c;
+    }
+    if prev == '-' {
+        result.push(' ');
+    }
+    return result;
+}
+
+// =====================================================================
+/// Makes the text a valid content of processing instruction,
+/// which must not contain "?&gt;", by inserting spaces.
+///
+pub fn escape_processing_instruction(s: &str) -> String {
+    return s.replace("?>", "? >");
+}
+
+// =====================================================================
+/// Encloses the text in CDATA section(s); "]]&gt;" in the text is
+/// split into two sections.
+///
+pub fn escape_cdata_section(s: &str) -> String {
+    return format!("<![CDATA[{}]]>", s.replace("]]>", "]]]]><![CDATA[>"));
+}
+
+// =====================================================================
+/// Escapes the text as Canonical XML requires:
+/// &amp;, &lt;, &gt; and carriage return.
+///
+pub fn escape_c14n_text(s: &str) -> String {
+    return replace_chars(s, |c| match c {
+        '&' => Some("&amp;"),
+        '<' => Some("&lt;"),
+        '>' => Some("&gt;"),
+        '\r' => Some("&#xD;"),
+        _ => None,
+    });
+}
+
+// =====================================================================
+/// Escapes the attribute value as Canonical XML requires:
+/// &amp;, &lt;, ", tab, newline and carriage return.
+///
+pub fn escape_c14n_attribute(s: &str) -> String {
+    return replace_chars(s, |c| match c {
+        '&' => Some("&amp;"),
+        '<' => Some("&lt;"),
+        '"' => Some("&quot;"),
+        '\t' => Some("&#x9;"),
+        '\n' => Some("&#xA;"),
+        '\r' => Some("&#xD;"),
+        _ => None,
+    });
+}
+
+// =====================================================================
+//
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // -----------------------------------------------------------------
+    //
+    #[test]
+    fn test_escape() {
+        let s = "<a b=\"c\" d='e'>&\t\r\n]]>";
+        assert_eq!(escape_text(s), "&lt;a b=\"c\" d='e'&gt;&amp;\t\r\n]]&gt;");
+        assert_eq!(escape_text_and_quotes(s),
+            "&lt;a b=&quot;c&quot; d=&apos;e&apos;&gt;&amp;\t\r\n]]&gt;");
+        assert_eq!(escape_attribute(s),
+            "&lt;a b=&quot;c&quot; d=&apos;e&apos;&gt;&amp;&#x9;&#xD;&#xA;]]&gt;");
+        assert_eq!(escape_c14n_text(s), "&lt;a b=\"c\" d='e'&gt;&amp;\t&#xD;\n]]&gt;");
+        assert_eq!(escape_c14n_attribute(s),
+            "&lt;a b=&quot;c&quot; d='e'>&amp;&#x9;&#xD;&#xA;]]>");
+
+        assert_eq!(escape_comment("ok - fine"), "ok - fine");
+        assert_eq!(escape_comment("a---b"), "a- - -b");
+        assert_eq!(escape_comment("-"), "- ");
+        assert_eq!(escape_processing_instruction("a ?> b"), "a ? > b");
+        assert_eq!(escape_cdata_section("]]>]]>"),
+            "<![CDATA[]]]]><![CDATA[>]]]]><![CDATA[>]]>");
+    }
+}
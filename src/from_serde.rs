@@ -0,0 +1,689 @@
+//
+// from_serde.rs
+//
+// amxml: XML processor with XPath.
+// Copyright (C) 2018 KOYAMA Hiro <tac@amris.co.jp>
+//
+//!
+//! Building DOM tree from Rust values that implement serde::Serialize
+//! (feature "serde").
+//!
+//! <strong>dom::from_serde()</strong> converts the value into the
+//! document according to <strong>MappingOptions</strong>:
+//!
+//! - The struct (or map) becomes an element, whose name is the
+//!   struct name (or the root name specified in the options)
+//!   at the top level, or the field name otherwise.
+//! - Each field becomes a child element, whose content is the value.
+//! - Fields whose names start with the attribute prefix
+//!   (default <code>@</code>; use <code>#\[serde(rename = "@id")\]</code>),
+//!   or listed in <code>attributes()</code>, become attributes.
+//! - The field named as the text field (default <code>#text</code>)
+//!   becomes the text content.
+//! - Sequence (Vec, tuple) fields become repeated elements of the
+//!   field name. Sequence that is not a field (e.g. the top level
+//!   value, or sequence in sequence) becomes the element whose children
+//!   are named as the item name (default <code>item</code>).
+//! - Numbers, booleans, strings become text;
+//!   bytes become Base64 text.
+//! - None is omitted; unit becomes the empty element.
+//! - Unit variant of enum becomes text of the variant name;
+//!   other variants become the child element of the variant name.
+//!
+//! # Examples
+//!
+//! ```
+//! extern crate amxml;
+//! #[macro_use] extern crate serde_derive;
+//! use amxml::dom::*;
+//!
+//! #[derive(Serialize)]
+//! struct Order {
+//!     #[serde(rename = "@id")]
+//!     id: u32,
+//!     item: Vec<String>,
+//!     note: Option<String>,
+//! }
+//!
+//! fn main() {
+//!     let order = Order {
+//!         id: 7,
+//!         item: vec!{ String::from("pen"), String::from("ink") },
+//!         note: None,
+//!     };
+//!     let doc = from_serde(&order, &MappingOptions::new()).unwrap();
+//!     assert_eq!(doc.to_string(),
+//!         r#"<Order id="7"><item>pen</item><item>ink</item></Order>"#);
+//! }
+//! ```
+//!
+
+use std::error::Error;
+use std::fmt;
+use serde::ser::{self, Serialize};
+
+use binary::encode_base64;
+use dom::*;
+use escape::{escape_attribute, escape_text};
+use sax::is_qname;
+use xmlerror::*;
+
+// =====================================================================
+/// Mapping for dom::from_serde().
+///
+#[derive(Debug, Clone)]
+pub struct MappingOptions {
+    root_name: String,
+    pub(crate) attribute_prefix: String,    // to_serde でも使う。
+    attributes: Vec<String>,
+    pub(crate) text_field: String,          // to_serde でも使う。
+    item_name: String,
+}
+
+impl Default for MappingOptions {
+    fn default() -> MappingOptions {
+        return MappingOptions{
+            root_name: String::new(),
+            attribute_prefix: String::from("@"),
+            attributes: vec!{},
+            text_field: String::from("#text"),
+            item_name: String::from("item"),
+        };
+    }
+}
+
+impl MappingOptions {
+    // -----------------------------------------------------------------
+    /// Returns the default mapping.
+    ///
+    pub fn new() -> MappingOptions {
+        return MappingOptions::default();
+    }
+
+    // -----------------------------------------------------------------
+    /// Name of the document element
+    /// (default: the struct name, or <code>root</code>).
+    ///
+    pub fn root_name(mut self, name: &str) -> MappingOptions {
+        self.root_name = String::from(name);
+        return self;
+    }
+
+    // -----------------------------------------------------------------
+    /// Prefix of field names that are mapped to attributes
+    /// (default <code>@</code>). The prefix is removed from the
+    /// attribute name. Empty prefix disables this mapping.
+    ///
+    pub fn attribute_prefix(mut self, prefix: &str) -> MappingOptions {
+        self.attribute_prefix = String::from(prefix);
+        return self;
+    }
+
+    // -----------------------------------------------------------------
+    /// Field names that are mapped to attributes, without renaming
+    /// the fields.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// extern crate amxml;
+    /// #[macro_use] extern crate serde_derive;
+    /// use amxml::dom::*;
+    ///
+    /// #[derive(Serialize)]
+    /// struct Link { href: String, title: String }
+    ///
+    /// fn main() {
+    ///     let link = Link { href: String::from("a.html"), title: String::from("A") };
+    ///     let options = MappingOptions::new()
+    ///             .root_name("a")
+    ///             .attributes(&["href"])
+    ///             .text_field("title");
+    ///     let doc = from_serde(&link, &options).unwrap();
+    ///     assert_eq!(doc.to_string(), r#"<a href="a.html">A</a>"#);
+    /// }
+    /// ```
+    ///
+    pub fn attributes(mut self, names: &[&str]) -> MappingOptions {
+        self.attributes = names.iter().map(|s| String::from(*s)).collect();
+        return self;
+    }
+
+    // -----------------------------------------------------------------
+    /// Field name that is mapped to the text content
+    /// (default <code>#text</code>).
+    ///
+    pub fn text_field(mut self, name: &str) -> MappingOptions {
+        self.text_field = String::from(name);
+        return self;
+    }
+
+    // -----------------------------------------------------------------
+    /// Element name for items of sequence that is not a field
+    /// (default <code>item</code>).
+    ///
+    pub fn item_name(mut self, name: &str) -> MappingOptions {
+        self.item_name = String::from(name);
+        return self;
+    }
+}
+
+// =====================================================================
+/// Builds the document from the value, according to the mapping.
+/// See the module document of from_serde.
+///
+/// # Errors
+///
+/// - When the field mapped to attribute or text is not a scalar value.
+/// - When the key of map is not a scalar value.
+/// - When the field name (or map key) is not valid as an XML name.
+/// - When the Serialize implementation of the value fails.
+///
+pub fn from_serde<T: ?Sized + Serialize>(value: &T,
+                options: &MappingOptions) -> Result<NodePtr, Box<Error>> {
+    let value = value.serialize(ValueSerializer)?;
+    let root_name = match value {
+        _ if options.root_name != "" => options.root_name.as_str(),
+        SerdeValue::Struct(ref name, _) if name != "" => name.as_str(),
+        _ => "root",
+    };
+    let mut xml = String::new();
+    write_element(&mut xml, root_name, &value, options)?;
+    return new_document(&xml);
+}
+
+// ---------------------------------------------------------------------
+// Serializeした結果の中間表現。
+//
+#[derive(Debug)]
+enum SerdeValue {
+    Absent,                                     // None
+    Text(String),                               // スカラー値
+    Seq(Vec<SerdeValue>),                       // シーケンス、タプル
+    Struct(String, Vec<(String, SerdeValue)>),  // (型名, メンバー)
+    Variant(String, Box<SerdeValue>),           // (変種名, 値)
+}
+
+// ---------------------------------------------------------------------
+// フィールドを書き出す。シーケンスはフィールド名の要素の繰り返しになる。
+//
+fn write_field(xml: &mut String, name: &str, value: &SerdeValue,
+                options: &MappingOptions) -> Result<(), Box<Error>> {
+    match value {
+        SerdeValue::Absent => {},
+        SerdeValue::Seq(items) => {
+            for item in items.iter() {
+                write_element(xml, name, item, options)?;
+            }
+        },
+        _ => write_element(xml, name, value, options)?,
+    }
+    return Ok(());
+}
+
+// ---------------------------------------------------------------------
+// 要素をひとつ書き出す。
+//
+fn write_element(xml: &mut String, name: &str, value: &SerdeValue,
+                options: &MappingOptions) -> Result<(), Box<Error>> {
+    check_name(name)?;
+    let mut attrs = String::new();
+    let mut content = String::new();
+    match value {
+        SerdeValue::Absent => {},
+        SerdeValue::Text(s) => {
+            content += &escape_text(s);
+        },
+        SerdeValue::Seq(items) => {
+            for item in items.iter() {
+                write_element(&mut content, &options.item_name, item, options)?;
+            }
+        },
+        SerdeValue::Variant(variant, inner) => {
+            write_field(&mut content, variant, inner, options)?;
+        },
+        SerdeValue::Struct(_, members) => {
+            for (key, value) in members.iter() {
+                if let Some(attr_name) = attribute_name(key, options) {
+                    match value {
+                        SerdeValue::Absent => {},
+                        SerdeValue::Text(s) => {
+                            check_name(attr_name)?;
+                            attrs += &format!(r#" {}="{}""#,
+                                            attr_name, escape_attribute(s));
+                        },
+                        _ => return Err(type_error!(
+                            "Field mapped to attribute is not a scalar value: {}", key)),
+                    }
+                } else if *key == options.text_field {
+                    match value {
+                        SerdeValue::Absent => {},
+                        SerdeValue::Text(s) => content += &escape_text(s),
+                        _ => return Err(type_error!(
+                            "Field mapped to text is not a scalar value: {}", key)),
+                    }
+                } else {
+                    write_field(&mut content, key, value, options)?;
+                }
+            }
+        },
+    }
+    if content == "" {
+        *xml += &format!("<{}{}/>", name, attrs);
+    } else {
+        *xml += &format!("<{}{}>{}</{}>", name, attrs, content, name);
+    }
+    return Ok(());
+}
+
+// ---------------------------------------------------------------------
+// 属性に対応づけるフィールドならば、その属性名。
+//
+fn attribute_name<'a>(key: &'a str, options: &MappingOptions) -> Option<&'a str> {
+    if options.attributes.iter().any(|a| a == key) {
+        return Some(key);
+    }
+    if options.attribute_prefix != "" && key.starts_with(&options.attribute_prefix) {
+        return Some(&key[options.attribute_prefix.len() ..]);
+    }
+    return None;
+}
+
+// ---------------------------------------------------------------------
+// 要素名、属性名として使える名前 (QName) か否か。
+//
+fn check_name(name: &str) -> Result<(), Box<Error>> {
+    if ! is_qname(name) {
+        return Err(dynamic_error!("Invalid XML name: {}", name));
+    }
+    return Ok(());
+}
+
+// =====================================================================
+// Serializeの失敗 (Serialize::serialize() 内で custom() により生成)。
+//
+impl ser::Error for XmlError {
+    fn custom<T: fmt::Display>(msg: T) -> XmlError {
+        return *dynamic_error!("{}", msg);
+    }
+}
+
+// ---------------------------------------------------------------------
+// 値を中間表現SerdeValueに変換するSerializer。
+//
+struct ValueSerializer;
+
+impl ser::Serializer for ValueSerializer {
+    type Ok = SerdeValue;
+    type Error = XmlError;
+    type SerializeSeq = SeqCollector;
+    type SerializeTuple = SeqCollector;
+    type SerializeTupleStruct = SeqCollector;
+    type SerializeTupleVariant = SeqCollector;
+    type SerializeMap = StructCollector;
+    type SerializeStruct = StructCollector;
+    type SerializeStructVariant = StructCollector;
+
+    fn serialize_bool(self, v: bool) -> Result<SerdeValue, XmlError> {
+        return Ok(SerdeValue::Text(v.to_string()));
+    }
+    fn serialize_i8(self, v: i8) -> Result<SerdeValue, XmlError> {
+        return Ok(SerdeValue::Text(v.to_string()));
+    }
+    fn serialize_i16(self, v: i16) -> Result<SerdeValue, XmlError> {
+        return Ok(SerdeValue::Text(v.to_string()));
+    }
+    fn serialize_i32(self, v: i32) -> Result<SerdeValue, XmlError> {
+        return Ok(SerdeValue::Text(v.to_string()));
+    }
+    fn serialize_i64(self, v: i64) -> Result<SerdeValue, XmlError> {
+        return Ok(SerdeValue::Text(v.to_string()));
+    }
+    fn serialize_u8(self, v: u8) -> Result<SerdeValue, XmlError> {
+        return Ok(SerdeValue::Text(v.to_string()));
+    }
+    fn serialize_u16(self, v: u16) -> Result<SerdeValue, XmlError> {
+        return Ok(SerdeValue::Text(v.to_string()));
+    }
+    fn serialize_u32(self, v: u32) -> Result<SerdeValue, XmlError> {
+        return Ok(SerdeValue::Text(v.to_string()));
+    }
+    fn serialize_u64(self, v: u64) -> Result<SerdeValue, XmlError> {
+        return Ok(SerdeValue::Text(v.to_string()));
+    }
+    fn serialize_f32(self, v: f32) -> Result<SerdeValue, XmlError> {
+        return Ok(SerdeValue::Text(v.to_string()));
+    }
+    fn serialize_f64(self, v: f64) -> Result<SerdeValue, XmlError> {
+        return Ok(SerdeValue::Text(v.to_string()));
+    }
+    fn serialize_char(self, v: char) -> Result<SerdeValue, XmlError> {
+        return Ok(SerdeValue::Text(v.to_string()));
+    }
+    fn serialize_str(self, v: &str) -> Result<SerdeValue, XmlError> {
+        return Ok(SerdeValue::Text(String::from(v)));
+    }
+    fn serialize_bytes(self, v: &[u8]) -> Result<SerdeValue, XmlError> {
+        return Ok(SerdeValue::Text(encode_base64(v)));
+    }
+    fn serialize_none(self) -> Result<SerdeValue, XmlError> {
+        return Ok(SerdeValue::Absent);
+    }
+    fn serialize_some<T: ?Sized + Serialize>(self, value: &T) -> Result<SerdeValue, XmlError> {
+        return value.serialize(self);
+    }
+    fn serialize_unit(self) -> Result<SerdeValue, XmlError> {
+        return Ok(SerdeValue::Struct(String::new(), vec!{}));
+    }
+    fn serialize_unit_struct(self, name: &'static str) -> Result<SerdeValue, XmlError> {
+        return Ok(SerdeValue::Struct(String::from(name), vec!{}));
+    }
+    fn serialize_unit_variant(self, _name: &'static str, _variant_index: u32,
+                variant: &'static str) -> Result<SerdeValue, XmlError> {
+        return Ok(SerdeValue::Text(String::from(variant)));
+    }
+    fn serialize_newtype_struct<T: ?Sized + Serialize>(self, _name: &'static str,
+                value: &T) -> Result<SerdeValue, XmlError> {
+        return value.serialize(self);
+    }
+    fn serialize_newtype_variant<T: ?Sized + Serialize>(self, _name: &'static str,
+                _variant_index: u32, variant: &'static str,
+                value: &T) -> Result<SerdeValue, XmlError> {
+        return Ok(SerdeValue::Variant(String::from(variant),
+                        Box::new(value.serialize(self)?)));
+    }
+    fn serialize_seq(self, _len: Option<usize>) -> Result<SeqCollector, XmlError> {
+        return Ok(SeqCollector::new(None));
+    }
+    fn serialize_tuple(self, _len: usize) -> Result<SeqCollector, XmlError> {
+        return Ok(SeqCollector::new(None));
+    }
+    fn serialize_tuple_struct(self, _name: &'static str,
+                _len: usize) -> Result<SeqCollector, XmlError> {
+        return Ok(SeqCollector::new(None));
+    }
+    fn serialize_tuple_variant(self, _name: &'static str, _variant_index: u32,
+                variant: &'static str, _len: usize) -> Result<SeqCollector, XmlError> {
+        return Ok(SeqCollector::new(Some(variant)));
+    }
+    fn serialize_map(self, _len: Option<usize>) -> Result<StructCollector, XmlError> {
+        return Ok(StructCollector::new("", None));
+    }
+    fn serialize_struct(self, name: &'static str,
+                _len: usize) -> Result<StructCollector, XmlError> {
+        return Ok(StructCollector::new(name, None));
+    }
+    fn serialize_struct_variant(self, _name: &'static str, _variant_index: u32,
+                variant: &'static str, _len: usize) -> Result<StructCollector, XmlError> {
+        return Ok(StructCollector::new(variant, Some(variant)));
+    }
+}
+
+// ---------------------------------------------------------------------
+// シーケンス、タプル (の変種) の要素を集める。
+//
+struct SeqCollector {
+    items: Vec<SerdeValue>,
+    variant: Option<&'static str>,
+}
+
+impl SeqCollector {
+    fn new(variant: Option<&'static str>) -> SeqCollector {
+        return SeqCollector{
+            items: vec!{},
+            variant: variant,
+        };
+    }
+
+    fn push<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<(), XmlError> {
+        self.items.push(value.serialize(ValueSerializer)?);
+        return Ok(());
+    }
+
+    fn finish(self) -> Result<SerdeValue, XmlError> {
+        let value = SerdeValue::Seq(self.items);
+        match self.variant {
+            Some(variant) => return Ok(SerdeValue::Variant(
+                                String::from(variant), Box::new(value))),
+            None => return Ok(value),
+        }
+    }
+}
+
+impl ser::SerializeSeq for SeqCollector {
+    type Ok = SerdeValue;
+    type Error = XmlError;
+    fn serialize_element<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<(), XmlError> {
+        return self.push(value);
+    }
+    fn end(self) -> Result<SerdeValue, XmlError> {
+        return self.finish();
+    }
+}
+
+impl ser::SerializeTuple for SeqCollector {
+    type Ok = SerdeValue;
+    type Error = XmlError;
+    fn serialize_element<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<(), XmlError> {
+        return self.push(value);
+    }
+    fn end(self) -> Result<SerdeValue, XmlError> {
+        return self.finish();
+    }
+}
+
+impl ser::SerializeTupleStruct for SeqCollector {
+    type Ok = SerdeValue;
+    type Error = XmlError;
+    fn serialize_field<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<(), XmlError> {
+        return self.push(value);
+    }
+    fn end(self) -> Result<SerdeValue, XmlError> {
+        return self.finish();
+    }
+}
+
+impl ser::SerializeTupleVariant for SeqCollector {
+    type Ok = SerdeValue;
+    type Error = XmlError;
+    fn serialize_field<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<(), XmlError> {
+        return self.push(value);
+    }
+    fn end(self) -> Result<SerdeValue, XmlError> {
+        return self.finish();
+    }
+}
+
+// ---------------------------------------------------------------------
+// 構造体 (の変種)、マップのメンバーを集める。
+//
+struct StructCollector {
+    name: &'static str,
+    members: Vec<(String, SerdeValue)>,
+    variant: Option<&'static str>,
+    next_key: Option<String>,
+}
+
+impl StructCollector {
+    fn new(name: &'static str, variant: Option<&'static str>) -> StructCollector {
+        return StructCollector{
+            name: name,
+            members: vec!{},
+            variant: variant,
+            next_key: None,
+        };
+    }
+
+    fn push<T: ?Sized + Serialize>(&mut self, key: &str, value: &T) -> Result<(), XmlError> {
+        self.members.push((String::from(key), value.serialize(ValueSerializer)?));
+        return Ok(());
+    }
+
+    fn finish(self) -> Result<SerdeValue, XmlError> {
+        let value = SerdeValue::Struct(String::from(self.name), self.members);
+        match self.variant {
+            Some(variant) => return Ok(SerdeValue::Variant(
+                                String::from(variant), Box::new(value))),
+            None => return Ok(value),
+        }
+    }
+}
+
+impl ser::SerializeMap for StructCollector {
+    type Ok = SerdeValue;
+    type Error = XmlError;
+    fn serialize_key<T: ?Sized + Serialize>(&mut self, key: &T) -> Result<(), XmlError> {
+        match key.serialize(ValueSerializer)? {
+            SerdeValue::Text(s) => self.next_key = Some(s),
+            _ => return Err(*type_error!("Key of map is not a scalar value")),
+        }
+        return Ok(());
+    }
+    fn serialize_value<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<(), XmlError> {
+        match self.next_key.take() {
+            Some(key) => return self.push(&key, value),
+            None => return Err(*cant_occur!("serialize_value: キーがない。")),
+        }
+    }
+    fn end(self) -> Result<SerdeValue, XmlError> {
+        return self.finish();
+    }
+}
+
+impl ser::SerializeStruct for StructCollector {
+    type Ok = SerdeValue;
+    type Error = XmlError;
+    fn serialize_field<T: ?Sized + Serialize>(&mut self, key: &'static str,
+                value: &T) -> Result<(), XmlError> {
+        return self.push(key, value);
+    }
+    fn end(self) -> Result<SerdeValue, XmlError> {
+        return self.finish();
+    }
+}
+
+impl ser::SerializeStructVariant for StructCollector {
+    type Ok = SerdeValue;
+    type Error = XmlError;
+    fn serialize_field<T: ?Sized + Serialize>(&mut self, key: &'static str,
+                value: &T) -> Result<(), XmlError> {
+        return self.push(key, value);
+    }
+    fn end(self) -> Result<SerdeValue, XmlError> {
+        return self.finish();
+    }
+}
+
+// =====================================================================
+//
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    #[derive(Serialize)]
+    struct Book {
+        #[serde(rename = "@isbn")]
+        isbn: String,
+        title: String,
+        author: Vec<String>,
+        year: Option<u16>,
+        price: f64,
+        format: Format,
+        tags: BTreeMap<String, bool>,
+        matrix: Vec<Vec<u8>>,
+        cover: Option<Cover>,
+    }
+
+    #[derive(Serialize)]
+    enum Format {
+        Hardcover,
+        Ebook { size: u32 },
+    }
+
+    #[derive(Serialize)]
+    struct Cover(String);
+
+    #[derive(Serialize)]
+    struct Note {
+        lang: &'static str,
+        body: &'static str,
+    }
+
+    // -----------------------------------------------------------------
+    //
+    #[test]
+    fn test_from_serde() {
+        let mut tags = BTreeMap::new();
+        tags.insert(String::from("new"), true);
+        tags.insert(String::from("sale"), false);
+        let book = Book {
+            isbn: String::from("4-00-000000-0"),
+            title: String::from("Fish & <Chips>"),
+            author: vec!{ String::from("A"), String::from("B") },
+            year: None,
+            price: 12.5,
+            format: Format::Hardcover,
+            tags: tags,
+            matrix: vec!{ vec!{1, 2}, vec!{} },
+            cover: Some(Cover(String::from("red"))),
+        };
+        let doc = from_serde(&book, &MappingOptions::new()).unwrap();
+        assert_eq!(doc.to_string(), concat!(
+            r#"<Book isbn="4-00-000000-0">"#,
+            r#"<title>Fish &amp; &lt;Chips&gt;</title>"#,
+            r#"<author>A</author><author>B</author>"#,
+            r#"<price>12.5</price>"#,
+            r#"<format>Hardcover</format>"#,
+            r#"<tags><new>true</new><sale>false</sale></tags>"#,
+            r#"<matrix><item>1</item><item>2</item></matrix><matrix/>"#,
+            r#"<cover>red</cover>"#,
+            r#"</Book>"#));
+
+        let format = Format::Ebook { size: 300 };
+        let doc = from_serde(&format, &MappingOptions::new()).unwrap();
+        assert_eq!(doc.to_string(), r#"<root><Ebook><size>300</size></Ebook></root>"#);
+
+        let doc = from_serde(&vec!{1, 2}, &MappingOptions::new()
+                        .root_name("list").item_name("n")).unwrap();
+        assert_eq!(doc.to_string(), r#"<list><n>1</n><n>2</n></list>"#);
+
+        let note = Note { lang: "en", body: "a \"quoted\" text" };
+        let options = MappingOptions::new()
+                        .attributes(&["lang"])
+                        .text_field("body");
+        let doc = from_serde(&note, &options).unwrap();
+        assert_eq!(doc.to_string(), r#"<Note lang="en">a &quot;quoted&quot; text</Note>"#);
+        let options = MappingOptions::new().attributes(&["lang", "body"]);
+        let doc = from_serde(&note, &options).unwrap();
+        assert_eq!(doc.get_first_node("//@body").unwrap().value(), "a \"quoted\" text");
+
+        // 属性に対応づけたフィールドが構造体である。
+        let options = MappingOptions::new().attributes(&["format"]);
+        let book = Book {
+            format: Format::Ebook { size: 1 },
+            cover: None,
+            ..book_sample()
+        };
+        assert!(from_serde(&book, &options).is_err());
+
+        // XMLの名前として使えないキー。
+        let mut map = BTreeMap::new();
+        map.insert("not a name", 1);
+        assert!(from_serde(&map, &MappingOptions::new()).is_err());
+    }
+
+    fn book_sample() -> Book {
+        return Book {
+            isbn: String::new(),
+            title: String::new(),
+            author: vec!{},
+            year: Some(2018),
+            price: 0.0,
+            format: Format::Hardcover,
+            tags: BTreeMap::new(),
+            matrix: vec!{},
+            cover: None,
+        };
+    }
+}
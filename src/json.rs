@@ -0,0 +1,329 @@
+//
+// json.rs
+//
+// amxml: XML processor with XPath.
+// Copyright (C) 2018 KOYAMA Hiro <tac@amris.co.jp>
+//
+//!
+//! Conversion of DOM subtrees to JSON text.
+//!
+//! <strong>NodePtr#to_json()</strong> converts the element (or the
+//! document) to JSON according to <strong>JsonMapping</strong>:
+//!
+//! - The element becomes an object with a single member, whose name
+//!   is the element name.
+//! - Attributes become members named <code>@name</code>.
+//! - Child elements become members named by their element names;
+//!   repeated siblings of the same name are collected into an array.
+//! - Text content becomes the member <code>#text</code>, or the value
+//!   itself (string) if the element has no attributes nor child elements.
+//!   Whitespace-only text between elements is ignored.
+//! - Empty element without attributes becomes <code>null</code>.
+//! - Comments and processing instructions are ignored.
+//!
+//! All values are strings; no type conversion is done.
+//!
+//! # Examples
+//!
+//! ```
+//! use amxml::dom::*;
+//! use amxml::json::*;
+//! let xml = r#"<order id="7"><item>pen</item><item>ink</item><note lang="en">fast</note><gift/></order>"#;
+//! let doc = new_document(xml).unwrap();
+//! assert_eq!(doc.to_json(&JsonMapping::new()),
+//!     r##"{"order":{"@id":"7","item":["pen","ink"],"note":{"@lang":"en","#text":"fast"},"gift":null}}"##);
+//! ```
+//!
+
+use std::collections::HashMap;
+#[cfg(feature = "no_std")]
+use std::prelude::*;
+
+use dom::*;
+
+// =====================================================================
+/// Mapping for NodePtr#to_json().
+///
+#[derive(Debug, Clone)]
+pub struct JsonMapping {
+    attribute_prefix: String,
+    text_key: String,
+    force_array: Vec<String>,
+}
+
+impl Default for JsonMapping {
+    fn default() -> JsonMapping {
+        return JsonMapping{
+            attribute_prefix: String::from("@"),
+            text_key: String::from("#text"),
+            force_array: vec!{},
+        };
+    }
+}
+
+impl JsonMapping {
+    // -----------------------------------------------------------------
+    /// Returns the default mapping: attributes as <code>@name</code>,
+    /// text as <code>#text</code>.
+    ///
+    pub fn new() -> JsonMapping {
+        return JsonMapping::default();
+    }
+
+    // -----------------------------------------------------------------
+    /// Prefix of member names for attributes (default <code>@</code>).
+    ///
+    pub fn attribute_prefix(mut self, prefix: &str) -> JsonMapping {
+        self.attribute_prefix = String::from(prefix);
+        return self;
+    }
+
+    // -----------------------------------------------------------------
+    /// Member name for text content (default <code>#text</code>).
+    ///
+    pub fn text_key(mut self, key: &str) -> JsonMapping {
+        self.text_key = String::from(key);
+        return self;
+    }
+
+    // -----------------------------------------------------------------
+    /// Element names that are always converted into arrays,
+    /// even when there is only one such sibling,
+    /// so that the shape of JSON does not depend on the number of
+    /// elements.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use amxml::dom::*;
+    /// use amxml::json::*;
+    /// let doc = new_document(r#"<list><item>a</item></list>"#).unwrap();
+    /// let mapping = JsonMapping::new().force_array(&["item"]);
+    /// assert_eq!(doc.to_json(&mapping), r#"{"list":{"item":["a"]}}"#);
+    /// ```
+    ///
+    pub fn force_array(mut self, names: &[&str]) -> JsonMapping {
+        self.force_array = names.iter().map(|s| String::from(*s)).collect();
+        return self;
+    }
+}
+
+// =====================================================================
+//
+impl NodePtr {
+
+    // =================================================================
+    /// Converts the subtree to JSON text according to the mapping.
+    /// See the module document.
+    ///
+    /// For the document, the document element is converted.
+    /// For the attribute or text node, its value is converted into
+    /// JSON string.
+    ///
+    pub fn to_json(&self, mapping: &JsonMapping) -> String {
+        match self.node_type() {
+            NodeType::DocumentRoot => {
+                let root_elem = self.root_element();
+                if root_elem.node_type() != NodeType::Element {
+                    return String::from("null");
+                }
+                return root_elem.to_json(mapping);
+            },
+            NodeType::Element => {
+                return format!("{{{}:{}}}",
+                        json_string(&self.name()), element_value(self, mapping));
+            },
+            _ => {
+                return json_string(&self.value());
+            },
+        }
+    }
+}
+
+// ---------------------------------------------------------------------
+// 要素の値 (オブジェクト、文字列、またはnull)。
+// 深い木でもスタックを使い切らないよう、再帰せず作業スタックでたどる。
+//
+fn element_value(elem: &NodePtr, mapping: &JsonMapping) -> String {
+    let mut stack: Vec<JsonFrame> = vec!{JsonFrame::new(elem)};
+    loop {
+        let child = {
+            let top = stack.last_mut().unwrap();
+            top.next_child()
+        };
+        if let Some(ch) = child {
+            match ch.node_type() {
+                NodeType::Element => stack.push(JsonFrame::new(&ch)),
+                NodeType::Text => stack.last_mut().unwrap().text += &ch.value(),
+                _ => {},
+            }
+            continue;
+        }
+        let frame = stack.pop().unwrap();
+        let name = frame.elem.name();
+        let value = frame.value(mapping);
+        match stack.last_mut() {
+            Some(parent) => parent.add_child_value(name, value),
+            None => return value,
+        }
+    }
+}
+
+// ---------------------------------------------------------------------
+// element_value() の作業スタックの項目: 値を組み立て中の要素。
+//
+struct JsonFrame {
+    elem: NodePtr,
+    children: Vec<NodePtr>,
+    next: usize,                            // 次にたどる子の位置
+    groups: Vec<(String, Vec<String>)>,     // 子要素の値を名前ごとに (最初に現れた順)
+    group_index: HashMap<String, usize>,    // 名前 → groups での位置
+    text: String,
+}
+
+impl JsonFrame {
+    fn new(elem: &NodePtr) -> JsonFrame {
+        return JsonFrame{
+            elem: elem.rc_clone(),
+            children: elem.children(),
+            next: 0,
+            groups: vec!{},
+            group_index: HashMap::new(),
+            text: String::new(),
+        };
+    }
+
+    fn next_child(&mut self) -> Option<NodePtr> {
+        if self.next < self.children.len() {
+            self.next += 1;
+            return Some(self.children[self.next - 1].rc_clone());
+        }
+        return None;
+    }
+
+    fn add_child_value(&mut self, name: String, value: String) {
+        if let Some(&i) = self.group_index.get(&name) {
+            self.groups[i].1.push(value);
+            return;
+        }
+        self.group_index.insert(name.clone(), self.groups.len());
+        self.groups.push((name, vec!{value}));
+    }
+
+    fn value(self, mapping: &JsonMapping) -> String {
+        let mut members: Vec<(String, String)> = vec!{};
+        for at in self.elem.attribute_nodes().iter() {
+            members.push((format!("{}{}", mapping.attribute_prefix, at.name()),
+                            json_string(&at.value())));
+        }
+
+        if members.len() == 0 && self.groups.len() == 0 {
+            if self.text == "" {
+                return String::from("null");
+            }
+            return json_string(&self.text);
+        }
+
+        for (name, values) in self.groups.into_iter() {
+            if values.len() == 1 && ! mapping.force_array.contains(&name) {
+                members.push((name, values[0].clone()));
+            } else {
+                members.push((name, format!("[{}]", values.join(","))));
+            }
+        }
+        if self.text.trim() != "" {
+            members.push((mapping.text_key.clone(), json_string(&self.text)));
+        }
+
+        let members: Vec<String> = members.iter()
+                .map(|&(ref name, ref value)| format!("{}:{}", json_string(name), value))
+                .collect();
+        return format!("{{{}}}", members.join(","));
+    }
+}
+
+// ---------------------------------------------------------------------
+// JSON の文字列リテラル。
+//
+pub(crate) fn json_string(s: &str) -> String {
+    let mut result = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => result += "\\\"",
+            '\\' => result += "\\\\",
+            '\n' => result += "\\n",
+            '\r' => result += "\\r",
+            '\t' => result += "\\t",
+            c if (c as u32) < 0x20 => result += &format!("\\u{:04x}", c as u32),
+            c => result.push(c),
+        }
+    }
+    result.push('"');
+    return result;
+}
+
+// =====================================================================
+//
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // -----------------------------------------------------------------
+    //
+    #[test]
+    fn test_to_json() {
+        let xml = r#"<?xml version="1.0"?>
+<!-- comment -->
+<root a="1" b='q"t'>
+    <x>1</x>
+    <y k="v"/>
+    <x>2</x>
+    <z>mixed <b>bold</b> text</z>
+    <e/>
+    <t>line1
+line2	tab\</t>
+</root>"#;
+        let doc = new_document(xml).unwrap();
+        assert_eq!(doc.to_json(&JsonMapping::new()),
+            r##"{"root":{"@a":"1","@b":"q\"t","x":["1","2"],"y":{"@k":"v"},"z":{"b":"bold","#text":"mixed  text"},"e":null,"t":"line1\nline2\ttab\\"}}"##);
+
+        let mapping = JsonMapping::new()
+                .attribute_prefix("-")
+                .text_key("$")
+                .force_array(&["y", "e"]);
+        let elem_z = doc.get_first_node("//z").unwrap();
+        assert_eq!(elem_z.to_json(&mapping),
+            r#"{"z":{"b":"bold","$":"mixed  text"}}"#);
+        let elem_y = doc.get_first_node("//y").unwrap();
+        assert_eq!(elem_y.to_json(&mapping), r#"{"y":{"-k":"v"}}"#);
+        let root = doc.root_element();
+        assert!(root.to_json(&mapping).contains(r#""y":[{"-k":"v"}],"#));
+        assert!(root.to_json(&mapping).contains(r#""e":[null],"#));
+
+        let attr = doc.get_first_node("//@b").unwrap();
+        assert_eq!(attr.to_json(&mapping), r#""q\"t""#);
+    }
+
+    // -----------------------------------------------------------------
+    // 深い木、子の多い要素。
+    //
+    #[test]
+    fn test_to_json_large() {
+        let depth = 100000;
+        let xml = format!("{}x{}", "<a>".repeat(depth), "</a>".repeat(depth));
+        let doc = new_document(&xml).unwrap();
+        let json = format!("{}\"x\"{}", r#"{"a":"#.repeat(depth), "}".repeat(depth));
+        assert_eq!(doc.to_json(&JsonMapping::new()), json);
+
+        let mut xml = String::from("<r>");
+        for i in 0 .. 20000 {
+            xml += &format!("<e{}>{}</e{}><x/>", i, i, i);
+        }
+        xml += "</r>";
+        let doc = new_document(&xml).unwrap();
+        let json = doc.to_json(&JsonMapping::new());
+        assert!(json.starts_with(r#"{"r":{"e0":"0","x":[null,null,"#));
+        assert!(json.contains(r#"null],"e1":"1","e2":"2","#));
+        assert!(json.ends_with(r#","e19999":"19999"}}"#));
+    }
+}
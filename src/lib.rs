@@ -20,12 +20,20 @@
 //! assert_eq!(result, xml_string);
 //! ```
 //!
+//! HTML-ish documents that are not well-formed XML can be read by
+//! <strong>new_html_document()</strong>, which never fails and builds the
+//! DOM tree of the same form leniently.
+//!
 //! # Navigating DOM tree
 //!
 //! Navigating DOM tree, or retrieving the DOM node, can be done by
 //! <strong>root_element()</strong>, <strong>parent()</strong>,
 //! <strong>first_child()</strong>, <strong>nth_child()</strong>,
+//! <strong>next_sibling()</strong>, <strong>previous_sibling()</strong>,
 //! <strong>attribute_value()</strong> methods.
+//! Moving to the parent, the n'th child and the siblings takes
+//! constant time, regardless of the number of children
+//! (see the benchmarks in benches/, run by <code>cargo bench</code>).
 //!
 //! See the description and example of corresponding method.
 //! 
@@ -124,6 +132,11 @@
 //! 
 //! ```
 //!
+//! For a single value, <strong>query_string()</strong>,
+//! <strong>query_i64()</strong>, <strong>query_f64()</strong>,
+//! <strong>query_bool()</strong>, <strong>query_node()</strong> methods
+//! convert the result to the Rust type.
+//!
 //! # Manipurating the DOM node
 //!
 //! Inserting / replacing / deleting the DOM node can be done by
@@ -131,25 +144,142 @@
 //! <strong>insert_as_previous_sibling()</strong>, 
 //! <strong>insert_as_next_sibling()</strong>,
 //! <strong>delete_child()</strong>, <strong>replace_with()</strong>,
-//! <strong>set_attribute()</strong>, <strong>delete_attribute()</strong> methods.
+//! <strong>set_attribute()</strong>, <strong>delete_attribute()</strong>,
+//! <strong>set_text()</strong> methods.
 //!
 //! See the description and example of corresponding method.
 //!
+//! The mutations can be observed by <strong>on_mutation()</strong>,
+//! e.g. to invalidate caches built on the document.
+//!
+//! # Sample application
+//!
+//! The sample application, which pretty prints the XML file, is built
+//! only with the feature <code>bin</code>, so that the library users
+//! don't build it:
+//! <code>cargo run --features bin -- file.xml</code>
+//!
+//! The library reads files only when asked to, by
+//! <strong>new_document_from_file()</strong> or
+//! <strong>NodePtr::try_from(&Path)</strong>;
+//! otherwise the document is given as string.
+//!
+//! # Without std
+//!
+//! With the feature <code>no_std</code>, the crate is built with
+//! <code>core</code> and <code>alloc</code> only (<code>#![no_std]</code>),
+//! for embedded use: the parser (SaxDecoder, new_document() etc.),
+//! the DOM and the XPath evaluator work as with <code>std</code>.
+//! The errors are <code>Box&lt;core::error::Error&gt;</code>;
+//! <code>HashMap</code> and the floating-point functions come from
+//! the crates <code>hashbrown</code> and <code>libm</code>.
+//!
+//! What needs the operating system is left out:
+//! files and <code>std::io</code> streams
+//! (new_document_from_file(), NodePtr::try_from(&Path),
+//! NodePtr#write_to_with_encoding(), SaxDecoder::from_reader(),
+//! decode_xml_bytes(), project_document(), and the module
+//! <code>pipeline</code>),
+//! threads (EvalOptions#parallel() evaluates sequentially),
+//! the output of fn:trace() without a listener (discarded),
+//! and the system clock (fn:random-number-generator() without a seed
+//! starts from a fixed seed).
+//! The results of math:exp() and the like may differ from those with
+//! <code>std</code> in the last digit.
+//! The features that need <code>std</code> (<code>bin</code>,
+//! <code>serde</code>, <code>regex</code>, <code>crypto</code>,
+//! <code>quick-xml-interop</code>, <code>unicode-normalization</code>,
+//! <code>unicode-segmentation</code>) can't be combined with it.
+//!
+
+#![cfg_attr(feature = "no_std", no_std)]
+
+#[cfg(all(feature = "no_std", any(feature = "bin", feature = "serde",
+        feature = "regex", feature = "crypto", feature = "quick-xml-interop",
+        feature = "unicode-normalization", feature = "unicode-segmentation")))]
+compile_error!("feature \"no_std\" can't be combined with features that need std");
+
+#[cfg(feature = "no_std")]
+#[macro_use]
+extern crate alloc;
+#[cfg(feature = "icu")]
+extern crate icu_collator;
+#[cfg(feature = "icu")]
+extern crate icu_locid;
+#[cfg(feature = "no_std")]
+extern crate hashbrown;
+#[cfg(feature = "no_std")]
+extern crate libm;
+#[cfg(feature = "serde")]
+#[macro_use]
+extern crate serde;
+#[cfg(feature = "unicode-normalization")]
+extern crate unicode_normalization;
+#[cfg(feature = "unicode-segmentation")]
+extern crate unicode_segmentation;
+#[cfg(feature = "regex")]
+extern crate regex;
+#[cfg(feature = "crypto")]
+extern crate sha2;
+#[cfg(feature = "quick-xml-interop")]
+extern crate quick_xml;
+#[cfg(all(test, feature = "serde"))]
+#[macro_use]
+extern crate serde_derive;
+// テストの println!() など (テストは no_std でも std のもとで動く)。
+#[cfg(all(test, feature = "no_std"))]
+#[macro_use]
+extern crate std as test_std;
+
+#[cfg(feature = "no_std")]
+#[path = "no_std.rs"]
+mod std;
 
 #[macro_use]
 pub mod xmlerror;
 pub mod sax;
+pub mod escape;
 pub mod dom;
+pub mod cursor;
+pub mod walker;
 
 pub mod xpath;
+pub mod project;
+#[cfg(not(feature = "no_std"))]
+pub mod pipeline;
+pub mod binary;
+pub mod datetime;
+pub mod json;
+pub mod c14n;
+pub mod catalog;
+pub mod xsd;
+#[cfg(feature = "html-entities")]
+pub mod html_entities;
+#[cfg(feature = "crypto")]
+pub mod dsig;
+#[cfg(feature = "serde")]
+pub mod from_serde;
+#[cfg(feature = "serde")]
+pub mod to_serde;
+#[cfg(feature = "quick-xml-interop")]
+pub mod quick_xml_interop;
 mod xpath_impl {
     pub mod lexer;
     pub mod parser;
     pub mod xitem;
     pub mod xsequence;
+    pub mod cast;
+    pub mod collation;
+    pub mod resolver;
+    pub mod stack_guard;
     pub mod eval;
     pub mod func;
     pub mod oper;
+    #[cfg(not(feature = "no_std"))]
+    pub mod parallel;
+    pub mod batch;
+    pub mod optimizer;
+    #[cfg(test)]
     pub mod helpers;
 }
 
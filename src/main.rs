@@ -3,10 +3,10 @@
 //
 extern crate amxml;
 
+use std::convert::TryFrom;
 use std::env;
 use std::error::Error;
-use std::fs::File;
-use std::io::prelude::*;
+use std::path::Path;
 use std::process;
 use amxml::dom::*;
 
@@ -27,12 +27,7 @@ fn main() {
 }
 
 fn pretty_print(filename: &str) -> Result<(), Box<Error>> {
-    let mut fp = File::open(&filename)?;
-
-    let mut xml_string = String::new();
-    fp.read_to_string(&mut xml_string)?;
-
-    let doc = new_document(&xml_string)?;
+    let doc = NodePtr::try_from(Path::new(filename))?;
 
     println!("{}", doc.to_pretty_string());
 
@@ -0,0 +1,109 @@
+//
+// no_std.rs
+//
+// amxml: XML processor with XPath.
+// Copyright (C) 2018 KOYAMA Hiro <tac@amris.co.jp>
+//
+// feature "no_std" でビルドする場合に、クレートの根の `std` になる
+// モジュール (lib.rs の mod 宣言を参照)。
+// Rust 2015 では `use std::...` はクレートの根からの経路なので、
+// 各モジュールは書き換えずに、core と alloc の該当部分を使う。
+// HashMap と HashSet は hashbrown、浮動小数点数の函数は libm による。
+//
+
+pub use core::{any, cell, char, cmp, convert, f64, hash, hint, i64, mem,
+                ops, u32, usize};
+#[cfg(feature = "async")]
+pub use core::{future, pin, task};
+pub use alloc::{fmt, rc, slice, str};
+
+pub mod error {
+    pub use core::error::Error;
+}
+
+pub mod collections {
+    pub use alloc::collections::*;
+    pub use hashbrown::{HashMap, HashSet};
+
+    pub mod hash_map {
+        pub use super::super::DefaultHasher;
+    }
+}
+
+// ---------------------------------------------------------------------
+// std の prelude にあって、core の prelude にないもの。
+// 各モジュールで #[cfg(feature = "no_std")] use std::prelude::*; とする。
+//
+pub mod prelude {
+    pub use alloc::boxed::Box;
+    pub use alloc::string::{String, ToString};
+    pub use alloc::vec::Vec;
+    pub use super::F64Ext;
+}
+
+// ---------------------------------------------------------------------
+// std::collections::hash_map::DefaultHasher の代わり (FNV-1a)。
+// std のものと同じく、鍵を与えずに作れて、値はプロセスによらない。
+//
+#[derive(Debug, Clone)]
+pub struct DefaultHasher(u64);
+
+impl DefaultHasher {
+    pub fn new() -> DefaultHasher {
+        return DefaultHasher(0xcbf29ce484222325);
+    }
+}
+
+impl hash::Hasher for DefaultHasher {
+    fn write(&mut self, bytes: &[u8]) {
+        for b in bytes.iter() {
+            self.0 = (self.0 ^ (*b as u64)).wrapping_mul(0x100000001b3);
+        }
+    }
+    fn finish(&self) -> u64 {
+        return self.0;
+    }
+}
+
+// ---------------------------------------------------------------------
+// core の f64 にない (std で定義されている) メソッド。
+//
+pub trait F64Ext {
+    fn floor(self) -> f64;
+    fn ceil(self) -> f64;
+    fn trunc(self) -> f64;
+    fn fract(self) -> f64;
+    fn powi(self, n: i32) -> f64;
+    fn powf(self, n: f64) -> f64;
+    fn sqrt(self) -> f64;
+    fn exp(self) -> f64;
+    fn ln(self) -> f64;
+    fn log10(self) -> f64;
+    fn sin(self) -> f64;
+    fn cos(self) -> f64;
+    fn tan(self) -> f64;
+    fn asin(self) -> f64;
+    fn acos(self) -> f64;
+    fn atan(self) -> f64;
+    fn atan2(self, other: f64) -> f64;
+}
+
+impl F64Ext for f64 {
+    fn floor(self) -> f64 { return libm::floor(self); }
+    fn ceil(self) -> f64 { return libm::ceil(self); }
+    fn trunc(self) -> f64 { return libm::trunc(self); }
+    fn fract(self) -> f64 { return self - libm::trunc(self); }
+    fn powi(self, n: i32) -> f64 { return libm::pow(self, n as f64); }
+    fn powf(self, n: f64) -> f64 { return libm::pow(self, n); }
+    fn sqrt(self) -> f64 { return libm::sqrt(self); }
+    fn exp(self) -> f64 { return libm::exp(self); }
+    fn ln(self) -> f64 { return libm::log(self); }
+    fn log10(self) -> f64 { return libm::log10(self); }
+    fn sin(self) -> f64 { return libm::sin(self); }
+    fn cos(self) -> f64 { return libm::cos(self); }
+    fn tan(self) -> f64 { return libm::tan(self); }
+    fn asin(self) -> f64 { return libm::asin(self); }
+    fn acos(self) -> f64 { return libm::acos(self); }
+    fn atan(self) -> f64 { return libm::atan(self); }
+    fn atan2(self, other: f64) -> f64 { return libm::atan2(self, other); }
+}
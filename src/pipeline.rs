@@ -0,0 +1,418 @@
+//
+// pipeline.rs
+//
+// amxml: XML processor with XPath.
+// Copyright (C) 2018 KOYAMA Hiro <tac@amris.co.jp>
+//
+//!
+//! Streaming pipeline of SAX filters.
+//!
+//! <strong>Pipeline</strong> reads the XML document with SaxDecoder,
+//! passes each token through the chain of filters, and writes the
+//! resulting tokens as XML, without building the DOM tree.
+//! A filter implements the trait <strong>SaxFilter</strong>: it takes
+//! one token and yields zero or more tokens to the next filter.
+//!
+//! Filters provided:
+//!
+//! - <strong>RemoveComments</strong>: removes comments.
+//! - <strong>RemoveElements</strong>: removes the elements of the given
+//!   names, together with their contents.
+//! - <strong>RenameNamespace</strong>: replaces the namespace URI
+//!   in the namespace declarations.
+//!
+//! # Examples
+//!
+//! ```
+//! use std::error::Error;
+//! use amxml::pipeline::*;
+//! use amxml::sax::XmlToken;
+//!
+//! // Custom filter: upper-cases the text.
+//! struct UpperCase;
+//! impl SaxFilter for UpperCase {
+//!     fn filter(&mut self, token: XmlToken, out: &mut Vec<XmlToken>) -> Result<(), Box<Error>> {
+//!         match token {
+//!             XmlToken::CharData{chardata} => {
+//!                 out.push(XmlToken::CharData{chardata: chardata.to_uppercase()});
+//!             },
+//!             _ => out.push(token),
+//!         }
+//!         return Ok(());
+//!     }
+//! }
+//!
+//! let xml = r#"<root xmlns="http://old/"><!--c--><name>x</name><ssn>123</ssn></root>"#;
+//! let mut out: Vec<u8> = vec!{};
+//! Pipeline::new()
+//!     .filter(RemoveComments)
+//!     .filter(RemoveElements::new(&["ssn"]))
+//!     .filter(RenameNamespace{from: String::from("http://old/"), to: String::from("http://new/")})
+//!     .filter(UpperCase)
+//!     .run(xml.as_bytes(), &mut out).unwrap();
+//! assert_eq!(String::from_utf8(out).unwrap(),
+//!     r#"<root xmlns="http://new/"><name>X</name></root>"#);
+//! ```
+//!
+//! ### Note
+//!
+//! As SaxDecoder, the pipeline does not translate namespace prefixes
+//! into URIs: element names are compared as written, including the
+//! prefix.
+//! The tokens written are not checked to be well-formed.
+//! An element without contents (in the output) is written as an
+//! empty-element tag.
+//!
+
+use std::error::Error;
+use std::io::{Read, Write};
+
+use escape::*;
+use sax::{Attr, SaxDecoder, XmlToken};
+
+// =====================================================================
+/// Transformer of the token stream, for Pipeline.
+///
+/// At the end of the document, the filter receives XmlToken::EOF,
+/// and may yield the tokens it keeps before passing EOF on.
+///
+pub trait SaxFilter {
+    /// Takes the token, and pushes the tokens to be passed to the
+    /// next filter (none, if the token is to be removed) into out.
+    ///
+    /// # Errors
+    ///
+    /// - Error of the filter aborts the pipeline.
+    ///
+    fn filter(&mut self, token: XmlToken, out: &mut Vec<XmlToken>) -> Result<(), Box<Error>>;
+}
+
+// =====================================================================
+/// Chain of SaxFilter.
+/// See the module document for details.
+///
+#[derive(Default)]
+pub struct Pipeline {
+    filters: Vec<Box<SaxFilter>>,
+}
+
+impl Pipeline {
+    // -----------------------------------------------------------------
+    /// Creates the pipeline without filters,
+    /// which copies the document as it is read.
+    ///
+    pub fn new() -> Pipeline {
+        return Pipeline{
+            filters: vec!{},
+        };
+    }
+
+    // -----------------------------------------------------------------
+    /// Appends the filter to the end of the chain.
+    ///
+    pub fn filter<F: SaxFilter + 'static>(mut self, filter: F) -> Pipeline {
+        self.filters.push(Box::new(filter));
+        return self;
+    }
+
+    // -----------------------------------------------------------------
+    /// Passes the token through the filters, and returns the tokens
+    /// that come out of the last filter.
+    ///
+    /// # Errors
+    ///
+    /// - When any of the filters fails.
+    ///
+    pub fn apply(&mut self, token: XmlToken) -> Result<Vec<XmlToken>, Box<Error>> {
+        let mut tokens: Vec<XmlToken> = vec!{ token };
+        for filter in self.filters.iter_mut() {
+            let mut next: Vec<XmlToken> = vec!{};
+            for token in tokens.into_iter() {
+                filter.filter(token, &mut next)?;
+            }
+            tokens = next;
+        }
+        return Ok(tokens);
+    }
+
+    // -----------------------------------------------------------------
+    /// Reads the XML document from reader, passes it through the
+    /// filters, and writes the result to writer.
+    ///
+    /// # Errors
+    ///
+    /// - When there is syntax error in the XML document.
+    /// - When any of the filters fails.
+    /// - When reading or writing fails.
+    ///
+    pub fn run<R: Read + 'static, W: Write>(&mut self, reader: R,
+                mut writer: W) -> Result<(), Box<Error>> {
+        let mut dec = SaxDecoder::from_reader(reader)?;
+        let mut tag_open = false;
+                // 開始タグの ">" をまだ書いていない (空要素タグになり得る)。
+        loop {
+            let token = dec.raw_token()?;
+            let is_eof = token == XmlToken::EOF;
+            for token in self.apply(token)?.iter() {
+                write_token(token, &mut writer, &mut tag_open)?;
+            }
+            if is_eof {
+                break;
+            }
+        }
+        if tag_open {
+            writer.write_all(b">")?;
+        }
+        return Ok(());
+    }
+}
+
+// ---------------------------------------------------------------------
+// トークンを XML として書き出す。
+// 開始タグは ">" を書かずにおき、直後が終了タグならば空要素タグにする。
+//
+fn write_token<W: Write>(token: &XmlToken, writer: &mut W,
+                tag_open: &mut bool) -> Result<(), Box<Error>> {
+    if *tag_open {
+        *tag_open = false;
+        if let XmlToken::EndElement{..} = token {
+            writer.write_all(b"/>")?;
+            return Ok(());
+        }
+        writer.write_all(b">")?;
+    }
+    match token {
+        XmlToken::EOF => {},
+        XmlToken::StartElement{name, attr} => {
+            write!(writer, "<{}", name)?;
+            for at in attr.iter() {
+                write!(writer, r#" {}="{}""#,
+                        at.name(), escape_attribute(at.value()))?;
+            }
+            *tag_open = true;
+        },
+        XmlToken::EndElement{name} => {
+            write!(writer, "</{}>", name)?;
+        },
+        XmlToken::CharData{chardata} => {
+            writer.write_all(escape_text_and_quotes(chardata).as_bytes())?;
+        },
+        XmlToken::ProcInst{target, inst} => {
+            write!(writer, "<?{} {}?>", target,
+                    escape_processing_instruction(inst))?;
+        },
+        XmlToken::Comment{comment} => {
+            write!(writer, "<!--{}-->", escape_comment(comment))?;
+        },
+        XmlToken::Directive{directive} => {
+            writer.write_all(directive.as_bytes())?;
+        },
+    }
+    return Ok(());
+}
+
+// =====================================================================
+/// Filter that removes comments.
+///
+pub struct RemoveComments;
+
+impl SaxFilter for RemoveComments {
+    fn filter(&mut self, token: XmlToken, out: &mut Vec<XmlToken>) -> Result<(), Box<Error>> {
+        if let XmlToken::Comment{..} = token {
+            return Ok(());
+        }
+        out.push(token);
+        return Ok(());
+    }
+}
+
+// =====================================================================
+/// Filter that removes the elements of the given names
+/// (as written, including the prefix), with their contents.
+///
+pub struct RemoveElements {
+    names: Vec<String>,
+    depth: usize,
+            // 除去中の要素の深さ (1以上)。0ならば除去中でない。
+}
+
+impl RemoveElements {
+    // -----------------------------------------------------------------
+    /// Creates the filter that removes the elements of the names.
+    ///
+    pub fn new(names: &[&str]) -> RemoveElements {
+        return RemoveElements{
+            names: names.iter().map(|s| String::from(*s)).collect(),
+            depth: 0,
+        };
+    }
+}
+
+impl SaxFilter for RemoveElements {
+    fn filter(&mut self, token: XmlToken, out: &mut Vec<XmlToken>) -> Result<(), Box<Error>> {
+        match token {
+            XmlToken::StartElement{ref name, ..} => {
+                if self.depth != 0 || self.names.contains(name) {
+                    self.depth += 1;
+                    return Ok(());
+                }
+            },
+            XmlToken::EndElement{..} => {
+                if self.depth != 0 {
+                    self.depth -= 1;
+                    return Ok(());
+                }
+            },
+            XmlToken::EOF => {},
+            _ => {
+                if self.depth != 0 {
+                    return Ok(());
+                }
+            },
+        }
+        out.push(token);
+        return Ok(());
+    }
+}
+
+// =====================================================================
+/// Filter that replaces the namespace URI <code>from</code> with
+/// <code>to</code> in the namespace declarations
+/// (attributes <code>xmlns</code> and <code>xmlns:<em>prefix</em></code>),
+/// so that the elements and attributes in the namespace are moved to
+/// the new one. The prefixes are kept.
+///
+pub struct RenameNamespace {
+    pub from: String,
+    pub to: String,
+}
+
+impl SaxFilter for RenameNamespace {
+    fn filter(&mut self, token: XmlToken, out: &mut Vec<XmlToken>) -> Result<(), Box<Error>> {
+        match token {
+            XmlToken::StartElement{name, attr} => {
+                let attr = attr.iter().map(|at| {
+                    let is_decl = at.name() == "xmlns" || at.name().starts_with("xmlns:");
+                    if is_decl && at.value() == self.from {
+                        Attr::new(at.name(), &self.to)
+                    } else {
+                        at.clone()
+                    }
+                }).collect();
+                out.push(XmlToken::StartElement{name, attr});
+            },
+            _ => out.push(token),
+        }
+        return Ok(());
+    }
+}
+
+// =====================================================================
+//
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::Cursor;
+    use xmlerror::*;
+
+    // -----------------------------------------------------------------
+    //
+    fn subtest_pipeline(pipeline: &mut Pipeline, xml: &str, guess: &str) {
+        let mut out: Vec<u8> = vec!{};
+        pipeline.run(Cursor::new(String::from(xml).into_bytes()), &mut out).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), guess, "xml: {}", xml);
+    }
+
+    // -----------------------------------------------------------------
+    // フィルタがなければ、読んだとおりに書き出す
+    // (空要素は空要素タグ、文字参照は必要なもののみ)。
+    //
+    #[test]
+    fn test_pipeline_copy() {
+        subtest_pipeline(&mut Pipeline::new(),
+            r#"<?xml version="1.0"?><!DOCTYPE root><root a="&lt;&#x9;"><!--c--><?pi x?><b/><c>1 &amp; 2<![CDATA[<3>]]></c></root>"#,
+            r#"<?xml version="1.0"?><!DOCTYPE root><root a="&lt;&#x9;"><!--c--><?pi x?><b/><c>1 &amp; 2&lt;3&gt;</c></root>"#);
+    }
+
+    // -----------------------------------------------------------------
+    //
+    #[test]
+    fn test_pipeline_filters() {
+        let xml = r#"<root xmlns:p="http://old/" xmlns:q="http://other/"><!--c--><p:a><pii>x<pii>y</pii></pii>A</p:a><pii/><b>B</b></root>"#;
+
+        subtest_pipeline(&mut Pipeline::new().filter(RemoveComments), xml,
+            r#"<root xmlns:p="http://old/" xmlns:q="http://other/"><p:a><pii>x<pii>y</pii></pii>A</p:a><pii/><b>B</b></root>"#);
+        subtest_pipeline(&mut Pipeline::new().filter(RemoveElements::new(&["pii", "b"])), xml,
+            r#"<root xmlns:p="http://old/" xmlns:q="http://other/"><!--c--><p:a>A</p:a></root>"#);
+        subtest_pipeline(&mut Pipeline::new().filter(RenameNamespace{
+                from: String::from("http://old/"), to: String::from("http://new/")}), xml,
+            r#"<root xmlns:p="http://new/" xmlns:q="http://other/"><!--c--><p:a><pii>x<pii>y</pii></pii>A</p:a><pii/><b>B</b></root>"#);
+
+        // 要素を除去した結果、内容のなくなった要素は空要素タグになる。
+        subtest_pipeline(&mut Pipeline::new()
+                .filter(RemoveComments)
+                .filter(RemoveElements::new(&["p:a", "pii"])), xml,
+            r#"<root xmlns:p="http://old/" xmlns:q="http://other/"><b>B</b></root>"#);
+        subtest_pipeline(&mut Pipeline::new()
+                .filter(RemoveElements::new(&["p:a", "pii", "b"])), "<root><p:a/></root>",
+            "<root/>");
+    }
+
+    // -----------------------------------------------------------------
+    // トークンを溜めておき、EOF で書き出すフィルタ。
+    //
+    struct Reverse {
+        chardata: Vec<XmlToken>,
+    }
+
+    impl SaxFilter for Reverse {
+        fn filter(&mut self, token: XmlToken, out: &mut Vec<XmlToken>) -> Result<(), Box<Error>> {
+            match token {
+                XmlToken::CharData{..} => self.chardata.push(token),
+                XmlToken::EOF => {
+                    out.push(XmlToken::Comment{comment: String::from("texts")});
+                    while let Some(t) = self.chardata.pop() {
+                        out.push(t);
+                    }
+                    out.push(token);
+                },
+                _ => out.push(token),
+            }
+            return Ok(());
+        }
+    }
+
+    // -----------------------------------------------------------------
+    //
+    #[test]
+    fn test_pipeline_custom_filter() {
+        let mut pipeline = Pipeline::new()
+                .filter(Reverse{chardata: vec!{}})
+                .filter(RemoveElements::new(&["x"]));
+        subtest_pipeline(&mut pipeline, "<root>1<x>2</x>3</root>",
+            "<root/><!--texts-->321");
+        assert_eq!(pipeline.apply(XmlToken::CharData{chardata: String::from("4")}).unwrap(),
+            vec!{});
+    }
+
+    // -----------------------------------------------------------------
+    //
+    #[test]
+    fn test_pipeline_error() {
+        struct Fail;
+        impl SaxFilter for Fail {
+            fn filter(&mut self, token: XmlToken, out: &mut Vec<XmlToken>) -> Result<(), Box<Error>> {
+                if let XmlToken::Comment{..} = token {
+                    return Err(dynamic_error!("comment found"));
+                }
+                out.push(token);
+                return Ok(());
+            }
+        }
+        let mut out: Vec<u8> = vec!{};
+        let result = Pipeline::new().filter(Fail).run(Cursor::new(b"<a><!--c--></a>".to_vec()), &mut out);
+        assert!(result.is_err());
+        let result = Pipeline::new().run(Cursor::new(b"<a><b x=></a>".to_vec()), &mut out);
+        assert!(result.is_err());
+    }
+}
@@ -0,0 +1,320 @@
+//
+// project.rs
+//
+// amxml: XML processor with XPath.
+// Copyright (C) 2018 KOYAMA Hiro <tac@amris.co.jp>
+//
+//!
+//! XML projection: streaming copy that keeps only the parts of
+//! the document selected by simple paths.
+//!
+//! <strong>project_document()</strong> reads the XML document from
+//! the reader, and writes to the writer only the subtrees whose root
+//! element matches any of the given paths, together with the start /
+//! end tags (and attributes) of their ancestors.
+//! Neither the DOM tree nor the whole input is held in memory:
+//! the input is decoded incrementally as SaxDecoder::from_reader()
+//! (UTF-8, or UTF-16 with BOM), and only the start tags of the open
+//! elements are kept, so the projected document can be loaded by
+//! <strong>new_document()</strong> with much less memory than the
+//! original one.
+//!
+//! # Examples
+//!
+//! ```
+//! # #[cfg(not(feature = "no_std"))] {
+//! use amxml::project::*;
+//! let xml = r#"<root><head><title>T</title></head><body><p>1</p><p>2</p><div>x</div></body></root>"#;
+//! let mut out: Vec<u8> = vec!{};
+//! project_document(xml.as_bytes(), &mut out, &["/root/body/p", "//title"]).unwrap();
+//! assert_eq!(String::from_utf8(out).unwrap(),
+//!     r#"<root><head><title>T</title></head><body><p>1</p><p>2</p></body></root>"#);
+//! # }
+//! ```
+//!
+//! ### Paths
+//!
+//! The paths are a subset of XPath location paths,
+//! consisting of element name tests (or <code>*</code>)
+//! separated by <code>/</code> (child) or <code>//</code> (descendant),
+//! such as <code>/root/body/p</code>, <code>//title</code>,
+//! <code>/root//item/*</code>.
+//! Paths must be absolute. Predicates, attribute steps and other axes
+//! are not supported.
+//! Element names are compared as written, including the prefix.
+//!
+//! ### Note
+//!
+//! The document element is always kept, so that the output is
+//! well-formed even when no element matches.
+//! Text, comments and processing instructions are kept only inside
+//! the matched subtrees, or outside the document element.
+//! Empty elements are written as a pair of start and end tags.
+//!
+//! project_document() is not available with the feature
+//! <code>no_std</code>.
+//!
+
+use std::error::Error;
+#[cfg(not(feature = "no_std"))]
+use std::io::{Read, Write};
+#[cfg(feature = "no_std")]
+use std::prelude::*;
+
+#[cfg(not(feature = "no_std"))]
+use escape::*;
+#[cfg(not(feature = "no_std"))]
+use sax::{SaxDecoder, XmlToken};
+use xmlerror::*;
+
+// =====================================================================
+/// Copies the XML document from reader to writer, keeping only the
+/// subtrees that match any of keep_xpaths and their ancestors.
+/// See the module document for details.
+///
+/// # Errors
+///
+/// - When the path is not supported (see the module document).
+/// - When there is syntax error in the XML document.
+/// - When reading or writing fails.
+///
+#[cfg(not(feature = "no_std"))]
+pub fn project_document<R: Read + 'static, W: Write>(reader: R, mut writer: W,
+                keep_xpaths: &[&str]) -> Result<(), Box<Error>> {
+
+    let mut patterns: Vec<Vec<PathStep>> = vec!{};
+    for xpath in keep_xpaths.iter() {
+        patterns.push(compile_path(xpath, "project_document")?);
+    }
+
+    let mut dec = SaxDecoder::from_reader(reader)?;
+
+    let mut stack: Vec<OpenElement> = vec!{};
+    let mut keep_depth: usize = 0;
+            // 保持する部分木の根の深さ (1以上)。0ならば部分木の外。
+    loop {
+        match dec.raw_token()? {
+            XmlToken::EOF => {
+                break;
+            },
+            XmlToken::StartElement{name, attr} => {
+                let mut start_tag = format!("<{}", name);
+                for at in attr.iter() {
+                    start_tag += &format!(r#" {}="{}""#,
+                            at.name(), escape_attribute(at.value()));
+                }
+                start_tag += ">";
+                stack.push(OpenElement{
+                    name: name,
+                    start_tag: start_tag,
+                    written: false,
+                });
+
+                if keep_depth == 0 {
+                    let names: Vec<&str> =
+                            stack.iter().map(|e| e.name.as_str()).collect();
+                    if patterns.iter().any(|p| match_path(p, &names)) {
+                        keep_depth = stack.len();
+                    } else if stack.len() != 1 {
+                        continue;       // 祖先になるかどうか、まだ不明。
+                    }
+                }
+                for e in stack.iter_mut() {
+                    if ! e.written {
+                        writer.write_all(e.start_tag.as_bytes())?;
+                        e.written = true;
+                    }
+                }
+            },
+            XmlToken::EndElement{name} => {
+                let e = match stack.pop() {
+                    Some(e) => e,
+                    None => return Err(xml_syntax_error!(
+                            "Unexpected end tag: {}", name)),
+                };
+                if e.name != name {
+                    return Err(xml_syntax_error!(
+                        "Element name mismatch: {} and {}", e.name, name));
+                }
+                if e.written {
+                    write!(writer, "</{}>", name)?;
+                }
+                if keep_depth == stack.len() + 1 {
+                    keep_depth = 0;
+                }
+            },
+            XmlToken::CharData{chardata} => {
+                if keep_depth != 0 || stack.len() == 0 {
+                    writer.write_all(escape_text_and_quotes(&chardata).as_bytes())?;
+                }
+            },
+            XmlToken::ProcInst{target, inst} => {
+                if keep_depth != 0 || stack.len() == 0 {
+                    write!(writer, "<?{} {}?>", target,
+                            escape_processing_instruction(&inst))?;
+                }
+            },
+            XmlToken::Comment{comment} => {
+                if keep_depth != 0 || stack.len() == 0 {
+                    write!(writer, "<!--{}-->", escape_comment(&comment))?;
+                }
+            },
+            XmlToken::Directive{directive} => {
+                if stack.len() == 0 {
+                    writer.write_all(directive.as_bytes())?;
+                }
+            },
+        }
+    }
+    return Ok(());
+}
+
+// ---------------------------------------------------------------------
+// 開始タグを読んだが、まだ終了タグを読んでいない要素。
+// written: 開始タグを出力済みか否か。
+//
+#[cfg(not(feature = "no_std"))]
+struct OpenElement {
+    name: String,
+    start_tag: String,
+    written: bool,
+}
+
+// ---------------------------------------------------------------------
+// パスの1段: 軸 (child または descendant) と要素名 (または "*")。
+//
+#[derive(Debug, PartialEq)]
+pub(crate) struct PathStep {
+    descendant: bool,
+    name: String,
+}
+
+// ---------------------------------------------------------------------
+// "/a/b", "//b", "/a//*" などのパスを PathStep の並びに変換する。
+// func_name: エラーメッセージに使う、呼び出し側の函数名。
+//
+pub(crate) fn compile_path(xpath: &str, func_name: &str) -> Result<Vec<PathStep>, Box<Error>> {
+    let xpath = xpath.trim();
+    if ! xpath.starts_with("/") {
+        return Err(uninplemented!(
+            "{}: path must be absolute: {}", func_name, xpath));
+    }
+    let mut steps: Vec<PathStep> = vec!{};
+    let mut descendant = false;
+    for (i, name) in xpath.split('/').enumerate() {
+        if i == 0 {
+            continue;           // 先頭の "/" の前
+        }
+        if name == "" {
+            if descendant {
+                return Err(xpath_syntax_error!(
+                    "{}: unexpected '/': {}", func_name, xpath));
+            }
+            descendant = true;
+            continue;
+        }
+        let valid = name == "*" ||
+            name.chars().all(|c| c.is_alphanumeric() ||
+                c == '_' || c == '-' || c == '.' || c == ':');
+        if ! valid {
+            return Err(uninplemented!(
+                "{}: unsupported step '{}' in: {}", func_name, name, xpath));
+        }
+        steps.push(PathStep{
+            descendant: descendant,
+            name: String::from(name),
+        });
+        descendant = false;
+    }
+    if descendant || steps.len() == 0 {
+        return Err(xpath_syntax_error!(
+            "{}: path ends with '/': {}", func_name, xpath));
+    }
+    return Ok(steps);
+}
+
+// ---------------------------------------------------------------------
+// 文書要素から現在の要素に至る要素名の並び names が、
+// パス steps に合致するか否か。
+//
+pub(crate) fn match_path(steps: &[PathStep], names: &[&str]) -> bool {
+    if steps.len() == 0 {
+        return names.len() == 0;
+    }
+    if names.len() == 0 {
+        return false;
+    }
+    let step = &steps[0];
+    let name_ok = step.name == "*" || step.name == names[0];
+    if name_ok && match_path(&steps[1..], &names[1..]) {
+        return true;
+    }
+    if step.descendant {
+        return match_path(steps, &names[1..]);
+    }
+    return false;
+}
+
+// =====================================================================
+//
+#[cfg(all(test, not(feature = "no_std")))]
+mod test {
+    use super::*;
+    use std::io;
+
+    // -----------------------------------------------------------------
+    //
+    fn subtest_project(xml: &'static str, keep_xpaths: &[&str], guess: &str) {
+        let mut out: Vec<u8> = vec!{};
+        project_document(xml.as_bytes(), &mut out, keep_xpaths).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), guess,
+                "keep_xpaths: {:?}", keep_xpaths);
+    }
+
+    // -----------------------------------------------------------------
+    //
+    #[test]
+    fn test_project_document() {
+        let xml = r#"<?xml version="1.0"?><!--c--><root a="1"><x><y>Y1<z/></y></x><y k="&lt;">Y2</y><w>W</w></root>"#;
+
+        subtest_project(xml, &["/root/y"],
+            r#"<?xml version="1.0"?><!--c--><root a="1"><y k="&lt;">Y2</y></root>"#);
+        subtest_project(xml, &["//y"],
+            r#"<?xml version="1.0"?><!--c--><root a="1"><x><y>Y1<z></z></y></x><y k="&lt;">Y2</y></root>"#);
+        subtest_project(xml, &["/root/*/y/z", "/root/w"],
+            r#"<?xml version="1.0"?><!--c--><root a="1"><x><y><z></z></y></x><w>W</w></root>"#);
+        subtest_project(xml, &["/root//z"],
+            r#"<?xml version="1.0"?><!--c--><root a="1"><x><y><z></z></y></x></root>"#);
+        subtest_project(xml, &["//nothing"],
+            r#"<?xml version="1.0"?><!--c--><root a="1"></root>"#);
+        subtest_project(xml, &["/root"],
+            r#"<?xml version="1.0"?><!--c--><root a="1"><x><y>Y1<z></z></y></x><y k="&lt;">Y2</y><w>W</w></root>"#);
+
+        // 属性値の文字参照のタブ・改行は、読み直しても保たれるよう文字参照で出力する。
+        subtest_project(r#"<root a="x&#x9;y&#xA;'"><b>"it's"</b></root>"#, &["/root/b"],
+            r#"<root a="x&#x9;y&#xA;&apos;"><b>&quot;it&apos;s&quot;</b></root>"#);
+
+        // UTF-16 (BOM) の入力も逐次的に復号する。
+        let mut bytes: Vec<u8> = vec!{0xFF, 0xFE};
+        for c in "<r><a>\u{3042}</a><b/></r>".encode_utf16() {
+            bytes.push((c & 0xFF) as u8);
+            bytes.push((c >> 8) as u8);
+        }
+        let mut out: Vec<u8> = vec!{};
+        project_document(io::Cursor::new(bytes), &mut out, &["//a"]).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "<r><a>\u{3042}</a></r>");
+    }
+
+    // -----------------------------------------------------------------
+    //
+    #[test]
+    fn test_project_document_error() {
+        let mut out: Vec<u8> = vec!{};
+        for path in ["root/a", "/root/a[1]", "/root/@a", "/root/", "/root///a"].iter() {
+            let result = project_document("<root/>".as_bytes(), &mut out, &[path]);
+            assert!(result.is_err(), "path: {}", path);
+        }
+        let result = project_document("<root><a></b></root>".as_bytes(), &mut out, &["//a"]);
+        assert!(result.is_err());
+    }
+}
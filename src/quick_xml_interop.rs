@@ -0,0 +1,274 @@
+//
+// quick_xml_interop.rs
+//
+// amxml: XML processor with XPath.
+// Copyright (C) 2018 KOYAMA Hiro <tac@amris.co.jp>
+//
+//!
+//! Conversion between DOM tree and quick-xml events
+//! (feature "quick-xml-interop").
+//!
+//! Projects that already read or write XML with quick-xml can use
+//! the XPath engine of amxml for a part of the document:
+//!
+//! - <strong>from_quick_xml_reader()</strong> /
+//!   <strong>from_quick_xml_events()</strong> build the document
+//!   from the events of quick_xml::Reader (until Event::Eof).
+//! - <strong>to_quick_xml_events()</strong> /
+//!   <strong>write_quick_xml()</strong> turn the subtree under the node
+//!   into the events, to be written by quick_xml::Writer.
+//!
+//! # Examples
+//!
+//! ```
+//! extern crate amxml;
+//! extern crate quick_xml;
+//! use amxml::quick_xml_interop::*;
+//! use quick_xml::{Reader, Writer};
+//!
+//! fn main() {
+//!     let mut reader = Reader::from_str(r#"<root><a v="1"/><a v="2"/></root>"#);
+//!     let doc = from_quick_xml_reader(&mut reader).unwrap();
+//!     let a = doc.get_first_node("//a[@v = '2']").unwrap();
+//!
+//!     let mut writer = Writer::new(Vec::new());
+//!     write_quick_xml(&a, &mut writer).unwrap();
+//!     assert_eq!(writer.into_inner(), br#"<a v="2"/>"#);
+//! }
+//! ```
+//!
+
+use std::error::Error;
+use std::io::{BufRead, Write};
+use quick_xml::Reader;
+use quick_xml::Writer;
+use quick_xml::events::{BytesDecl, BytesEnd, BytesPI, BytesStart, BytesText, Event};
+
+use dom::*;
+
+// =====================================================================
+/// Builds the document from the events read by the reader,
+/// until Event::Eof.
+///
+/// The events are assembled as they were read (with the reader's
+/// configuration, e.g. trimming of text), and parsed as XML string;
+/// so the result is the same as new_document() of the source,
+/// as far as the reader keeps the markup intact.
+///
+/// # Errors
+///
+/// - When the reader fails.
+/// - When the events are not encoded in UTF-8.
+/// - When the events don't form the well-formed document.
+///
+pub fn from_quick_xml_reader<R: BufRead>(reader: &mut Reader<R>) -> Result<NodePtr, Box<Error>> {
+    let mut writer = Writer::new(Vec::new());
+    let mut buf: Vec<u8> = vec!{};
+    loop {
+        match reader.read_event_into(&mut buf)? {
+            Event::Eof => break,
+            event => writer.write_event(event)?,
+        }
+        buf.clear();
+    }
+    return new_document(&String::from_utf8(writer.into_inner())?);
+}
+
+// =====================================================================
+/// Builds the document from the events, up to Event::Eof (if any).
+/// cf. from_quick_xml_reader()
+///
+/// # Examples
+///
+/// ```
+/// extern crate amxml;
+/// extern crate quick_xml;
+/// use amxml::quick_xml_interop::*;
+/// use quick_xml::events::{BytesEnd, BytesStart, BytesText, Event};
+///
+/// fn main() {
+///     let mut start = BytesStart::new("p");
+///     start.push_attribute(("class", "x&y"));
+///     let events = vec!{
+///         Event::Start(start),
+///         Event::Text(BytesText::new("1 < 2")),
+///         Event::End(BytesEnd::new("p")),
+///     };
+///     let doc = from_quick_xml_events(events).unwrap();
+///     assert_eq!(doc.root_element().attribute_value("class").unwrap(), "x&y");
+///     assert_eq!(doc.eval_xpath("string(/p)").unwrap().to_string(), r#""1 < 2""#);
+/// }
+/// ```
+///
+/// # Errors
+///
+/// - When the events are not encoded in UTF-8.
+/// - When the events don't form the well-formed document.
+///
+pub fn from_quick_xml_events<'a, I>(events: I) -> Result<NodePtr, Box<Error>>
+        where I: IntoIterator<Item = Event<'a>> {
+    let mut writer = Writer::new(Vec::new());
+    for event in events {
+        match event {
+            Event::Eof => break,
+            event => writer.write_event(event)?,
+        }
+    }
+    return new_document(&String::from_utf8(writer.into_inner())?);
+}
+
+// ---------------------------------------------------------------------
+// to_quick_xml_events() の作業スタックの項目。
+//
+enum EventWork {
+    Node(NodePtr),
+    End(String),                // 要素名
+}
+
+// =====================================================================
+/// Turns the subtree under the node (or the whole document) into
+/// quick-xml events, in document order.
+/// Attribute node as the argument yields no events.
+///
+/// Text (other than raw text) and attribute values are escaped
+/// by quick-xml; elements without children become Event::Empty.
+///
+/// # Examples
+///
+/// ```
+/// extern crate amxml;
+/// extern crate quick_xml;
+/// use amxml::dom::*;
+/// use amxml::quick_xml_interop::*;
+/// use quick_xml::events::Event;
+///
+/// fn main() {
+///     let doc = new_document(r#"<?xml version="1.0"?><root>a<!--c--><b/></root>"#).unwrap();
+///     let events = to_quick_xml_events(&doc);
+///     assert_eq!(events.len(), 6);
+///     match events[0] {
+///         Event::Decl(ref decl) => assert_eq!(&*decl.version().unwrap(), b"1.0"),
+///         _ => panic!(),
+///     }
+///     match events[4] {
+///         Event::Empty(ref e) => assert_eq!(e.name().as_ref(), b"b"),
+///         _ => panic!(),
+///     }
+/// }
+/// ```
+///
+pub fn to_quick_xml_events(node: &NodePtr) -> Vec<Event<'static>> {
+    let mut events: Vec<Event<'static>> = vec!{};
+    let mut stack: Vec<EventWork> = vec!{ EventWork::Node(node.rc_clone()) };
+    while let Some(work) = stack.pop() {
+        let node = match work {
+            EventWork::End(name) => {
+                events.push(Event::End(BytesEnd::new(name)));
+                continue;
+            },
+            EventWork::Node(node) => node,
+        };
+        match node.node_type() {
+            NodeType::DocumentRoot => {
+                for ch in node.children().iter().rev() {
+                    stack.push(EventWork::Node(ch.rc_clone()));
+                }
+            },
+            NodeType::Element => {
+                let mut start = BytesStart::new(node.name());
+                for at in node.attribute_nodes().iter() {
+                    start.push_attribute((at.name().as_str(), at.value().as_str()));
+                }
+                let children = node.children();
+                if children.len() == 0 {
+                    events.push(Event::Empty(start));
+                } else {
+                    events.push(Event::Start(start));
+                    stack.push(EventWork::End(node.name()));
+                    for ch in children.iter().rev() {
+                        stack.push(EventWork::Node(ch.rc_clone()));
+                    }
+                }
+            },
+            NodeType::Text => {
+                let text = if node.is_raw_text() {
+                    BytesText::from_escaped(node.value())
+                } else {
+                    BytesText::new(&node.value()).into_owned()
+                };
+                events.push(Event::Text(text));
+            },
+            NodeType::Comment => {
+                events.push(Event::Comment(BytesText::from_escaped(node.value())));
+            },
+            NodeType::XMLDecl => {
+                let content = format!("xml {}", node.value());
+                events.push(Event::Decl(BytesDecl::from_start(
+                        BytesStart::from_content(content, 3))));
+            },
+            NodeType::Instruction => {
+                let content = if node.value() == "" {
+                    node.name()
+                } else {
+                    format!("{} {}", node.name(), node.value())
+                };
+                events.push(Event::PI(BytesPI::new(content)));
+            },
+            NodeType::Directive => {
+                let value = node.value();
+                if value.starts_with("DOCTYPE") {
+                    let dtd = String::from(value["DOCTYPE".len() ..].trim_start());
+                    events.push(Event::DocType(BytesText::from_escaped(dtd)));
+                }
+            },
+            _ => {},
+        }
+    }
+    return events;
+}
+
+// =====================================================================
+/// Writes the subtree under the node (or the whole document) with
+/// the quick-xml writer. cf. to_quick_xml_events()
+///
+/// # Errors
+///
+/// - When writing to the writer fails.
+///
+pub fn write_quick_xml<W: Write>(node: &NodePtr,
+                writer: &mut Writer<W>) -> Result<(), Box<Error>> {
+    for event in to_quick_xml_events(node) {
+        writer.write_event(event)?;
+    }
+    return Ok(());
+}
+
+// =====================================================================
+//
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // -----------------------------------------------------------------
+    // quick-xml の読み込み → DOM → quick-xml の書き出しで、
+    // 元の文書と同じ文字列になる。
+    //
+    #[test]
+    fn test_round_trip() {
+        let xml = r#"<?xml version="1.0" encoding="UTF-8"?><!DOCTYPE root><?pi data?><root xmlns:p="http://p"><p:a x="&lt;&amp;&quot;">1 &lt; 2</p:a><!-- c --><b><![CDATA[<raw>]]></b><e/></root>"#;
+        let mut reader = Reader::from_str(xml);
+        let doc = from_quick_xml_reader(&mut reader).unwrap();
+        assert_eq!(doc.eval_xpath("string(//p:a/@x)").unwrap().to_string(), r#""<&""""#);
+        assert_eq!(doc.eval_xpath("string(//b)").unwrap().to_string(), r#""<raw>""#);
+
+        let mut writer = Writer::new(Vec::new());
+        write_quick_xml(&doc, &mut writer).unwrap();
+        let written = String::from_utf8(writer.into_inner()).unwrap();
+        assert_eq!(written, r#"<?xml version="1.0" encoding="UTF-8"?><!DOCTYPE root><?pi data?><root xmlns:p="http://p"><p:a x="&lt;&amp;&quot;">1 &lt; 2</p:a><!-- c --><b>&lt;raw&gt;</b><e/></root>"#);
+        assert_eq!(new_document(&written).unwrap().to_string(), doc.to_string());
+
+        // 整形式でないイベント列はエラー。
+        let mut reader = Reader::from_str("<a><b></a>");
+        assert!(from_quick_xml_reader(&mut reader).is_err());
+    }
+}
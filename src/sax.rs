@@ -67,7 +67,19 @@
 //! their corresponding URIs.
 //!
 //! SaxDecoder does not care Directives &lt;!DOCTYPE ...&gt;,
-//! &lt;!ELEMENT ...&gt;, etc.
+//! &lt;!ELEMENT ...&gt;, etc., except the attribute types declared
+//! by &lt;!ATTLIST ...&gt; in the internal subset, which are used
+//! to normalize the attribute values.
+//!
+//! ### Attribute-value normalization
+//!
+//! As the XML spec requires, white space characters (tab and newline)
+//! written in an attribute value are replaced by spaces; those written
+//! as character references (&amp;#x9; etc.) are not.
+//! The value of an attribute declared with the type other than CDATA
+//! is further trimmed, and each run of spaces is collapsed into one.
+//! set_normalize_attributes(false) disables this, and the attribute
+//! values are given as written (with references decoded).
 //!
 //! SaxDecoder recognizes the XML declaration as ProcInit.
 //! Caller should check if target equals to "xml".
@@ -75,17 +87,62 @@
 //! SaxDecoder accepts some illegal XML documents, like those
 //! that have more than one XML declarations, more than one root elements.
 //!
+//! ### Lenient mode
+//!
+//! SaxDecoder::new_lenient() creates the decoder for HTML-ish
+//! ("tag soup") documents. In this mode raw_token() never returns Err:
+//!
+//! - Element names and attribute names are converted to lower case.
+//! - Attribute values may be unquoted, or omitted (<code>&lt;input disabled&gt;</code>
+//!   gives the attribute <code>disabled=""</code>).
+//!   When the same attribute appears more than once, the first one wins.
+//! - The contents of <code>script</code> and <code>style</code> elements
+//!   are returned as CharData as they are, up to the end tag.
+//! - '&lt;' that does not begin a tag is treated as a character.
+//! - Comments etc. that are not terminated extend to the end of input.
+//! - Some HTML entities, such as <code>&amp;nbsp;</code>, are decoded.
+//!
+//! SaxDecoder does not check that the tags are balanced in this mode
+//! either. See dom::new_html_document() for the DOM tree built from
+//! such documents.
+//!
+//! ### Byte stream input
+//!
+//! SaxDecoder::from_reader() reads the XML document from the byte
+//! stream (std::io::Read), decoding it incrementally as the tokens are
+//! requested; the whole document is never held in memory.
+//! The encoding is detected by the byte order mark (UTF-8, UTF-16LE,
+//! UTF-16BE), or, without it, by the first characters "&lt;?" encoded
+//! in UTF-16; otherwise UTF-8 is assumed.
+//! The encoding declaration in the XML declaration is not consulted.
+//!
 
 use std::char;
 use std::error::Error;
+#[cfg(not(feature = "no_std"))]
+use std::io::{self, Read};
+#[cfg(feature = "no_std")]
+use std::prelude::*;
+use std::str;
 use std::u32;
 use std::usize;
+#[cfg(feature = "html-entities")]
+use html_entities::HTML4_ENTITIES;
 use xmlerror::*;
 
 // =====================================================================
 //
 const EOF: char = '\u{0000}';
 
+// 入力を Read から読む場合に、1回に読むバイト数。
+#[cfg(not(feature = "no_std"))]
+const READ_CHUNK_SIZE: usize = 8192;
+
+// 入力を Read から読む場合に、読み終えた文字を捨てる際、
+// ungetchar() のために残しておく文字数。
+#[cfg(not(feature = "no_std"))]
+const UNGET_MARGIN: usize = 64;
+
 // =====================================================================
 /// SaxDecoder represents an XML parser reading a particular input stream.
 /// See the module document for details.
@@ -96,11 +153,37 @@ pub struct SaxDecoder {
     to_close: String,
             // <foo/> が現れてStartElementを返し、次にEndElementを返す
             // 必要があるとき、そのタグ名。
+    lenient: bool,
+            // HTML風の文書を寛容に解析するモード。
+    raw_text_until: String,
+            // 寛容モードで <script> などの開始タグを返した直後、
+            // その終了タグまでを文字データとして読む必要があるとき、
+            // そのタグ名。
+    normalize_attributes: bool,
+            // 属性値を正規化するか否か。
+    tokenized_attributes: Vec<(String, String)>,
+            // 内部サブセットで CDATA 以外の型を宣言された属性の
+            // (要素名, 属性名)。
+    ignore_doctype: bool,
+            // DOCTYPE の宣言を使わないか否か。
+    #[cfg(not(feature = "no_std"))]
+    reader: Option<CharReader>,
+            // 入力を Read から逐次読む場合、その読み込み器。
+            // char_vec には、読み込んで復号した文字のうち、
+            // 読み終えていないもの (と直前の少し) を置く。
+    read_error: Option<Box<Error>>,
+            // 入力を読む際に生じたエラー。raw_token() で返す。
+    drained_position: (usize, usize),
+            // char_vec から捨てた文字の (改行の数, 最後の行の文字数)。
+            // position() で、現在位置の行と桁を数える際に使う。
+    entities: Vec<(String, String)>,
+            // 定義済み実体のほかに登録した (実体名, 置換文字列)。
 }
 
 // =====================================================================
 /// XmlToken, return type of SaxDecoder#raw_token()
 ///
+#[derive(Debug, Clone, PartialEq)]
 pub enum XmlToken {
     EOF,
     StartElement {
@@ -128,12 +211,22 @@ pub enum XmlToken {
 // =====================================================================
 /// In XmlToken::StartElement, attribute of element.
 ///
+#[derive(Debug, Clone, PartialEq)]
 pub struct Attr {
     name: String,
     value: String,
 }
 
 impl Attr {
+    /// Creates the attribute, e.g. for XmlToken::StartElement made
+    /// by a filter of the pipeline (cf. amxml::pipeline).
+    /// The value is not escaped.
+    pub fn new(name: &str, value: &str) -> Attr {
+        return Attr{
+            name: String::from(name),
+            value: String::from(value),
+        };
+    }
     pub fn name(&self) -> &str {
         return self.name.as_str();
     }
@@ -156,19 +249,167 @@ impl SaxDecoder {
                 // 解析前に改行すべてを #x0A に標準化する。
             index: 0,
             to_close: String::from(""),
+            lenient: false,
+            raw_text_until: String::from(""),
+            normalize_attributes: true,
+            tokenized_attributes: vec!{},
+            ignore_doctype: false,
+            #[cfg(not(feature = "no_std"))]
+            reader: None,
+            read_error: None,
+            drained_position: (0, 0),
+            entities: vec!{},
         });
     }
 
+    // -----------------------------------------------------------------
+    /// Creates a new XML parser reading from the byte stream.
+    /// See the module document for details.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use amxml::sax::*;
+    /// let bytes: Vec<u8> = vec!{0xFF, 0xFE, b'<', 0, b'a', 0, b'/', 0, b'>', 0};
+    /// let mut dec = SaxDecoder::from_reader(std::io::Cursor::new(bytes)).unwrap();
+    /// match dec.raw_token().unwrap() {
+    ///     XmlToken::StartElement{name, ..} => assert_eq!(name, "a"),
+    ///     _ => panic!(),
+    /// }
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// - When reading the first bytes fails.
+    ///
+    /// Errors in reading or decoding the rest of the stream are
+    /// returned by raw_token().
+    ///
+    #[cfg(not(feature = "no_std"))]
+    pub fn from_reader<R>(reader: R) -> Result<SaxDecoder, Box<Error>>
+                where R: Read + 'static {
+        let mut dec = SaxDecoder::new("")?;
+        dec.reader = Some(CharReader::new(Box::new(reader))?);
+        return Ok(dec);
+    }
+
+    // -----------------------------------------------------------------
+    /// Creates a new parser in lenient mode, for HTML-ish documents.
+    /// See the module document for details.
+    ///
+    pub fn new_lenient(html_string: &str) -> SaxDecoder {
+        return SaxDecoder{
+            char_vec: html_string.chars().filter(|x| *x != '\r').collect(),
+            index: 0,
+            to_close: String::from(""),
+            lenient: true,
+            raw_text_until: String::from(""),
+            normalize_attributes: false,
+            tokenized_attributes: vec!{},
+            ignore_doctype: false,
+            #[cfg(not(feature = "no_std"))]
+            reader: None,
+            read_error: None,
+            drained_position: (0, 0),
+            entities: vec!{},
+        };
+    }
+
+    // -----------------------------------------------------------------
+    /// Sets whether the attribute values are normalized
+    /// (true by default, false in lenient mode).
+    /// See the module document for details.
+    ///
+    pub fn set_normalize_attributes(&mut self, normalize: bool) {
+        self.normalize_attributes = normalize;
+    }
+
+    // -----------------------------------------------------------------
+    /// Sets whether the declarations in &lt;!DOCTYPE ...&gt; are ignored
+    /// (false by default); the Directive is returned anyway.
+    ///
+    pub fn set_ignore_doctype(&mut self, ignore: bool) {
+        self.ignore_doctype = ignore;
+    }
+
+    // -----------------------------------------------------------------
+    /// Registers the named entity, so that the entity reference
+    /// <code>&amp;name;</code> in text and attribute values is
+    /// replaced by the value (as is, without further decoding).
+    /// Besides the five entities predefined in XML, references to
+    /// the entities not registered are left as they are.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use amxml::sax::*;
+    /// let mut dec = SaxDecoder::new("<p a='&co;'>&co; &amp;co; &other;</p>").unwrap();
+    /// dec.add_entity("co", "Example, Inc.");
+    /// match dec.raw_token().unwrap() {
+    ///     XmlToken::StartElement{attr, ..} => assert_eq!(attr[0].value(), "Example, Inc."),
+    ///     _ => panic!(),
+    /// }
+    /// assert_eq!(dec.raw_token().unwrap(), XmlToken::CharData{
+    ///     chardata: String::from("Example, Inc. &co; &other;")});
+    /// ```
+    ///
+    pub fn add_entity(&mut self, name: &str, value: &str) {
+        match self.entities.iter_mut().find(|e| e.0 == name) {
+            Some(e) => e.1 = String::from(value),
+            None => self.entities.push((String::from(name), String::from(value))),
+        }
+    }
+
+    // -----------------------------------------------------------------
+    /// Registers the character entities of HTML 4, such as
+    /// <code>&amp;nbsp;</code> and <code>&amp;eacute;</code>
+    /// (feature "html-entities"). cf. add_entity(), html_entities
+    ///
+    #[cfg(feature = "html-entities")]
+    pub fn add_html_entities(&mut self) {
+        for (name, ch) in HTML4_ENTITIES.iter() {
+            self.add_entity(name, &ch.to_string());
+        }
+    }
+
+    // -----------------------------------------------------------------
+    /// Returns the current position (line and column, both from 1;
+    /// the column in characters) in the input, i.e. just after the
+    /// token last returned by raw_token().
+    /// Carriage returns removed as end-of-line handling are not
+    /// counted.
+    ///
+    pub fn position(&self) -> (usize, usize) {
+        let (mut line, mut column) = self.drained_position;
+        let index = self.index.min(self.char_vec.len());
+        count_position(&self.char_vec[.. index], &mut line, &mut column);
+        return (line + 1, column + 1);
+    }
+
     // -----------------------------------------------------------------
     /// Returns the next XML token in the input stream.
     /// At end of the input stream, raw_token() returns XmlToken::EOF.
     ///
     pub fn raw_token(&mut self) -> Result<XmlToken, Box<Error>> {
+        let token = self.next_token();
+        if let Some(e) = self.read_error.take() {
+            return Err(e);
+        }
+        return token;
+    }
+
+    // -----------------------------------------------------------------
+    // 次のトークンを返す。
+    //
+    fn next_token(&mut self) -> Result<XmlToken, Box<Error>> {
         if self.to_close != "" {
             let name = self.to_close.clone();
             self.to_close = String::from("");
             return Ok(XmlToken::EndElement{name});
         }
+        if self.lenient {
+            return Ok(self.lenient_token());
+        }
 
         let mut ch = self.getchar();
         if ch == EOF {
@@ -226,6 +467,10 @@ impl SaxDecoder {
                 } else {                            // <!DOCTYPE ...>, etc.
                     let directive = format!("<!{}{}",
                         ch, &self.get_until_matching_bracket()?);
+                    if directive.starts_with("<!DOCTYPE") && ! self.ignore_doctype {
+                        self.tokenized_attributes.extend(
+                            parse_tokenized_attributes(&directive));
+                    }
                     return Ok(XmlToken::Directive{directive: directive});
                 }
 
@@ -257,10 +502,20 @@ impl SaxDecoder {
                             self.skip_spaces();
                             ch = self.getchar();
                             if ch == '"' || ch == '\'' {
-                                let attr_value = self.get_until_ch(ch)?;
+                                let mut attr_value = self.get_until_ch(ch)?;
+                                if self.normalize_attributes {
+                                    attr_value = attr_value.replace(
+                                        |c| c == '\t' || c == '\n', " ");
+                                }
+                                let mut value = decode_entity(&attr_value, &self.entities);
+                                if self.normalize_attributes &&
+                                   self.tokenized_attributes.iter().any(
+                                        |&(ref e, ref a)| *e == name && *a == attr_name) {
+                                    value = collapse_spaces(&value);
+                                }
                                 attr.push(Attr{
                                     name: attr_name,
-                                    value: decode_entity(&attr_value),
+                                    value: value,
                                 });
                             } else {
                                 self.get_until_ch('>')?;
@@ -293,7 +548,199 @@ impl SaxDecoder {
         } else {
             self.ungetchar();
             let chardata = self.get_chardata();
-            return Ok(XmlToken::CharData{chardata: decode_entity(&chardata)});
+            return Ok(XmlToken::CharData{chardata: decode_entity(&chardata, &self.entities)});
+        }
+    }
+
+    // -----------------------------------------------------------------
+    // 寛容モードで次のトークンを返す。エラーにはしない。
+    //
+    fn lenient_token(&mut self) -> XmlToken {
+        if self.raw_text_until != "" {
+            let name = self.raw_text_until.clone();
+            self.raw_text_until = String::from("");
+            let chardata = self.get_raw_text(&name);
+            if chardata != "" {
+                return XmlToken::CharData{chardata};
+            }
+        }
+
+        let mut ch = self.getchar();
+        if ch == EOF {
+            return XmlToken::EOF;
+        } else if ch != '<' {
+            self.ungetchar();
+            let chardata = self.get_chardata();
+            return XmlToken::CharData{chardata: decode_html_entity(&chardata, &self.entities)};
+        }
+
+        ch = self.getchar();
+        if ch == '/' {                          // 終了タグ
+            let name = self.get_name().to_lowercase();
+            self.get_until_ch_or_eof('>');
+            if name == "" {                     // </ > などは読み捨てる
+                return self.lenient_token();
+            }
+            return XmlToken::EndElement{name};
+
+        } else if ch == '?' {                   // 処理命令
+            let target = self.get_name();
+            self.skip_spaces();
+            let inst = self.get_until_or_eof("?>");
+            return XmlToken::ProcInst{target, inst};
+
+        } else if ch == '!' {
+            if self.look_ahead_keyword("--") {
+                let comment = self.get_until_or_eof("-->");
+                return XmlToken::Comment{comment};
+            } else if self.look_ahead_keyword("[CDATA[") {
+                let chardata = self.get_until_or_eof("]]>");
+                return XmlToken::CharData{chardata};
+            } else {                            // <!DOCTYPE html> など
+                let directive = format!("<!{}>", self.get_until_ch_or_eof('>'));
+                return XmlToken::Directive{directive};
+            }
+
+        } else if ! is_name_first_char(ch) {    // タグでない '<'
+            self.ungetchar();
+            let chardata = format!("<{}", self.get_chardata());
+            return XmlToken::CharData{chardata: decode_html_entity(&chardata, &self.entities)};
+        }
+
+        // -------------------------------------------------------------
+        // 開始タグ
+        //
+        self.ungetchar();
+        let name = self.get_name().to_lowercase();
+        let mut attr: Vec<Attr> = vec!{};
+        loop {
+            self.skip_spaces();
+            ch = self.getchar();
+            if ch == EOF || ch == '>' {
+                break;
+            } else if ch == '/' {
+                if self.look_ahead_keyword(">") {
+                    self.to_close = name.clone();
+                    break;
+                }
+                continue;
+            }
+            self.ungetchar();
+            let attr_name = self.get_html_attr_name().to_lowercase();
+            if attr_name == "" {
+                self.getchar();                 // 属性名にならない文字は読み捨てる
+                continue;
+            }
+            self.skip_spaces();
+            let mut attr_value = String::new();
+            if self.look_ahead_keyword("=") {
+                self.skip_spaces();
+                ch = self.getchar();
+                if ch == '"' || ch == '\'' {
+                    attr_value = self.get_until_ch_or_eof(ch);
+                } else {
+                    self.ungetchar();
+                    attr_value = self.get_html_unquoted_value();
+                }
+            }
+            if ! attr.iter().any(|at| at.name == attr_name) {
+                attr.push(Attr{
+                    name: attr_name,
+                    value: decode_html_entity(&attr_value, &self.entities),
+                });
+            }
+        }
+        if self.to_close == "" && (name == "script" || name == "style") {
+            self.raw_text_until = name.clone();
+        }
+        return XmlToken::StartElement{name, attr};
+    }
+
+    // -----------------------------------------------------------------
+    // 寛容モード: 終了タグ "</name" (大文字小文字を区別しない) の直前、
+    // または EOF までを取得する。
+    //
+    fn get_raw_text(&mut self, name: &str) -> String {
+        let close_tag: Vec<char> = format!("</{}", name).chars().collect();
+        let mut s = String::new();
+        loop {
+            self.fill_buffer(close_tag.len());
+            let rest = &self.char_vec[self.index.min(self.char_vec.len())..];
+            if rest.len() == 0 {
+                return s;
+            }
+            if close_tag.len() <= rest.len() &&
+               rest.iter().zip(close_tag.iter()).all(|(a, b)|
+                        a.to_lowercase().eq(b.to_lowercase())) {
+                return s;
+            }
+            s.push(self.getchar());
+        }
+    }
+
+    // -----------------------------------------------------------------
+    // 寛容モード: 属性名。空白、'='、'>'、'/' の直前までとする。
+    //
+    fn get_html_attr_name(&mut self) -> String {
+        let mut s = String::new();
+        loop {
+            let ch = self.getchar();
+            if ch == EOF {
+                return s;
+            } else if is_space(ch) || ch == '=' || ch == '>' || ch == '/' ||
+                      ch == '"' || ch == '\'' || ch == '<' {
+                self.ungetchar();
+                return s;
+            }
+            s.push(ch);
+        }
+    }
+
+    // -----------------------------------------------------------------
+    // 寛容モード: 引用符で囲まれていない属性値。空白、'>' の直前までとする。
+    //
+    fn get_html_unquoted_value(&mut self) -> String {
+        let mut s = String::new();
+        loop {
+            let ch = self.getchar();
+            if ch == EOF {
+                return s;
+            } else if is_space(ch) || ch == '>' {
+                self.ungetchar();
+                return s;
+            }
+            s.push(ch);
+        }
+    }
+
+    // -----------------------------------------------------------------
+    // get_until() と同様。ただし区切り文字列が現れなければ EOF までを返す。
+    //
+    fn get_until_or_eof(&mut self, delim: &str) -> String {
+        let mut s = String::new();
+        loop {
+            if self.look_ahead_keyword(delim) == true {
+                return s;
+            }
+            let ch = self.getchar();
+            if ch == EOF {
+                return s;
+            }
+            s.push(ch);
+        }
+    }
+
+    // -----------------------------------------------------------------
+    // get_until_ch() と同様。ただし区切り文字が現れなければ EOF までを返す。
+    //
+    fn get_until_ch_or_eof(&mut self, delim: char) -> String {
+        let mut s = String::new();
+        loop {
+            let ch = self.getchar();
+            if ch == EOF || ch == delim {
+                return s;
+            }
+            s.push(ch);
         }
     }
 
@@ -441,6 +888,7 @@ impl SaxDecoder {
     // -----------------------------------------------------------------
     //
     fn getchar(&mut self) -> char {
+        self.fill_buffer(1);
         self.index += 1;
         if self.char_vec.len() <= self.index - 1 {
             return EOF;
@@ -456,6 +904,256 @@ impl SaxDecoder {
             self.index -= 1;
         }
     }
+
+    // -----------------------------------------------------------------
+    // 入力を Read から読む場合、現在位置以降に ahead 文字あるように、
+    // 読み込んで復号する (入力の末尾に達した場合は、そこまで)。
+    // 読み込む前に、読み終えた文字を (ungetchar() の分を残して) 捨てる。
+    //
+    #[cfg(not(feature = "no_std"))]
+    fn fill_buffer(&mut self, ahead: usize) {
+        while self.char_vec.len() < self.index + ahead {
+            if READ_CHUNK_SIZE < self.index && self.index <= self.char_vec.len() {
+                let n = self.index - UNGET_MARGIN;
+                count_position(&self.char_vec[.. n],
+                        &mut self.drained_position.0, &mut self.drained_position.1);
+                self.char_vec.drain(.. n);
+                self.index -= n;
+            }
+            let result = match self.reader {
+                Some(ref mut reader) => reader.read_chars(&mut self.char_vec),
+                None => return,
+            };
+            match result {
+                Ok(true) => {},
+                Ok(false) => {
+                    self.reader = None;
+                },
+                Err(e) => {
+                    self.reader = None;
+                    self.read_error = Some(e);
+                },
+            }
+        }
+    }
+
+    // -----------------------------------------------------------------
+    // feature "no_std" では Read から読むことはなく、入力はすべて
+    // char_vec にある。
+    //
+    #[cfg(feature = "no_std")]
+    fn fill_buffer(&mut self, _ahead: usize) {
+    }
+}
+
+// ---------------------------------------------------------------------
+// 文字列中の改行の数を line に加え、column を最後の行の文字数に進める。
+//
+fn count_position(chars: &[char], line: &mut usize, column: &mut usize) {
+    for ch in chars.iter() {
+        if *ch == '\n' {
+            *line += 1;
+            *column = 0;
+        } else {
+            *column += 1;
+        }
+    }
+}
+
+// =====================================================================
+/// Decodes the bytes of XML document into string, in the same way as
+/// SaxDecoder::from_reader(): the encoding is UTF-16 (little or big
+/// endian) if detected by the byte order mark or the first characters
+/// "&lt;?", otherwise UTF-8. The byte order mark is removed, and
+/// so are carriage returns as end-of-line handling.
+///
+/// # Examples
+///
+/// ```
+/// use amxml::sax::*;
+/// let bytes = b"\xEF\xBB\xBF<a>\r\n</a>".to_vec();
+/// assert_eq!(decode_xml_bytes(bytes).unwrap(), "<a>\n</a>");
+/// let bytes = b"\xFF\xFE<\0a\0/\0>\0".to_vec();
+/// assert_eq!(decode_xml_bytes(bytes).unwrap(), "<a/>");
+/// assert!(decode_xml_bytes(b"<a>\xFF</a>".to_vec()).is_err());
+/// ```
+///
+/// # Errors
+///
+/// - When the bytes are not valid in the encoding.
+///
+#[cfg(not(feature = "no_std"))]
+pub fn decode_xml_bytes(bytes: Vec<u8>) -> Result<String, Box<Error>> {
+    let mut reader = CharReader::new(Box::new(io::Cursor::new(bytes)))?;
+    let mut chars: Vec<char> = vec!{};
+    while reader.read_chars(&mut chars)? {}
+    return Ok(chars.into_iter().collect());
+}
+
+// =====================================================================
+// 入力のバイト列の符号化方式。
+//
+#[cfg(not(feature = "no_std"))]
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum InputEncoding {
+    Utf8,
+    Utf16Le,
+    Utf16Be,
+}
+
+// ---------------------------------------------------------------------
+// バイト列の先頭 (4バイトまで) から符号化方式を判別する。
+// 戻り値は (符号化方式, BOMのバイト数)。
+// XML 1.0 Appendix F.1 の方法のうち、UTF-8 と UTF-16 に関するもの。
+//
+#[cfg(not(feature = "no_std"))]
+fn detect_encoding(head: &[u8]) -> (InputEncoding, usize) {
+    if head.starts_with(&[0xEF, 0xBB, 0xBF]) {
+        return (InputEncoding::Utf8, 3);
+    } else if head.starts_with(&[0xFF, 0xFE]) {
+        return (InputEncoding::Utf16Le, 2);
+    } else if head.starts_with(&[0xFE, 0xFF]) {
+        return (InputEncoding::Utf16Be, 2);
+    } else if head.starts_with(&[0x3C, 0x00, 0x3F, 0x00]) {
+        return (InputEncoding::Utf16Le, 0);
+    } else if head.starts_with(&[0x00, 0x3C, 0x00, 0x3F]) {
+        return (InputEncoding::Utf16Be, 0);
+    } else {
+        return (InputEncoding::Utf8, 0);
+    }
+}
+
+// =====================================================================
+// Read からバイト列を読み、文字列に復号する。
+//
+#[cfg(not(feature = "no_std"))]
+struct CharReader {
+    reader: Box<Read>,
+    encoding: InputEncoding,
+    pending: Vec<u8>,
+            // 読み込んで、まだ復号していないバイト列
+            // (文字の途中で切れている部分)。
+    eof: bool,
+}
+
+#[cfg(not(feature = "no_std"))]
+impl CharReader {
+    // -----------------------------------------------------------------
+    // 先頭の4バイトまでを読んで符号化方式を判別し、BOMを読み飛ばす。
+    //
+    fn new(mut reader: Box<Read>) -> Result<CharReader, Box<Error>> {
+        let mut head = [0u8; 4];
+        let mut len = 0;
+        let mut eof = false;
+        while len < head.len() {
+            let n = read_retrying(&mut reader, &mut head[len ..])?;
+            if n == 0 {
+                eof = true;
+                break;
+            }
+            len += n;
+        }
+        let (encoding, bom_len) = detect_encoding(&head[.. len]);
+        return Ok(CharReader{
+            reader: reader,
+            encoding: encoding,
+            pending: head[bom_len .. len].to_vec(),
+            eof: eof,
+        });
+    }
+
+    // -----------------------------------------------------------------
+    // 読み込んで復号した文字を chars に追加する。
+    // 入力の末尾に達していて、追加する文字がなければ false を返す。
+    // XML 1.0: 行末の処理として、'\r' は除去する。
+    //
+    fn read_chars(&mut self, chars: &mut Vec<char>) -> Result<bool, Box<Error>> {
+        loop {
+            let len = chars.len();
+            self.decode_pending(chars)?;
+            if len < chars.len() {
+                return Ok(true);
+            }
+            if self.eof {
+                if ! self.pending.is_empty() {
+                    return Err(xml_syntax_error!(
+                        "Incomplete {:?} sequence at end of input", self.encoding));
+                }
+                return Ok(false);
+            }
+            let mut buf = [0u8; READ_CHUNK_SIZE];
+            let n = read_retrying(&mut self.reader, &mut buf)?;
+            if n == 0 {
+                self.eof = true;
+            }
+            self.pending.extend_from_slice(&buf[.. n]);
+        }
+    }
+
+    // -----------------------------------------------------------------
+    // pending のうち、文字として完結している部分を復号する。
+    //
+    fn decode_pending(&mut self, chars: &mut Vec<char>) -> Result<(), Box<Error>> {
+        match self.encoding {
+            InputEncoding::Utf8 => {
+                let valid_len = match str::from_utf8(&self.pending) {
+                    Ok(s) => s.len(),
+                    Err(e) => {
+                        if e.error_len().is_some() {
+                            return Err(xml_syntax_error!(
+                                "Invalid UTF-8 sequence in input"));
+                        }
+                        e.valid_up_to()
+                    },
+                };
+                if let Ok(s) = str::from_utf8(&self.pending[.. valid_len]) {
+                    chars.extend(s.chars().filter(|x| *x != '\r'));
+                }
+                self.pending.drain(.. valid_len);
+            },
+            InputEncoding::Utf16Le | InputEncoding::Utf16Be => {
+                let mut units: Vec<u16> = self.pending.chunks(2)
+                    .filter(|b| b.len() == 2)
+                    .map(|b| if self.encoding == InputEncoding::Utf16Le {
+                            (b[0] as u16) | (b[1] as u16) << 8
+                        } else {
+                            (b[0] as u16) << 8 | (b[1] as u16)
+                        })
+                    .collect();
+                // 末尾が上位サロゲートならば、次に読むものと合わせて復号する。
+                if let Some(&last) = units.last() {
+                    if 0xD800 <= last && last <= 0xDBFF {
+                        units.pop();
+                    }
+                }
+                for r in char::decode_utf16(units.iter().cloned()) {
+                    match r {
+                        Ok(ch) => if ch != '\r' {
+                            chars.push(ch);
+                        },
+                        Err(_) => return Err(xml_syntax_error!(
+                                "Invalid UTF-16 sequence in input")),
+                    }
+                }
+                self.pending.drain(.. units.len() * 2);
+            },
+        }
+        return Ok(());
+    }
+}
+
+// ---------------------------------------------------------------------
+// Read#read()。割り込まれた (ErrorKind::Interrupted) 場合は再試行する。
+//
+#[cfg(not(feature = "no_std"))]
+fn read_retrying(reader: &mut Box<Read>, buf: &mut [u8]) -> Result<usize, Box<Error>> {
+    loop {
+        match reader.read(buf) {
+            Ok(n) => return Ok(n),
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {},
+            Err(e) => return Err(Box::new(e)),
+        }
+    }
 }
 
 // ---------------------------------------------------------------------
@@ -490,6 +1188,135 @@ fn is_name_first_char(ch: char) -> bool {
     }
 }
 
+// =====================================================================
+/// Checks if the string matches the production Name of XML 1.0,
+/// i.e. whether it can be used as the name of element or attribute.
+///
+/// # Examples
+///
+/// ```
+/// use amxml::sax::*;
+/// assert!(is_xml_name("p:title"));
+/// assert!(! is_xml_name("my title"));
+/// assert!(! is_xml_name("1st"));
+/// ```
+///
+pub fn is_xml_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    match chars.next() {
+        Some(ch) if is_name_first_char(ch) => return chars.all(is_name_char),
+        _ => return false,
+    }
+}
+
+// =====================================================================
+/// Checks if the string is an NCName (Name without colon)
+/// of Namespaces in XML.
+///
+/// # Examples
+///
+/// ```
+/// use amxml::sax::*;
+/// assert!(is_ncname("title"));
+/// assert!(! is_ncname("p:title"));
+/// ```
+///
+pub fn is_ncname(name: &str) -> bool {
+    return ! name.contains(':') && is_xml_name(name);
+}
+
+// =====================================================================
+/// Checks if the string is a QName (NCName, optionally prefixed
+/// by NCName and colon) of Namespaces in XML.
+///
+/// # Examples
+///
+/// ```
+/// use amxml::sax::*;
+/// assert!(is_qname("title"));
+/// assert!(is_qname("p:title"));
+/// assert!(! is_qname(":title"));
+/// assert!(! is_qname("p:q:title"));
+/// ```
+///
+pub fn is_qname(name: &str) -> bool {
+    match name.find(':') {
+        Some(pos) => return is_ncname(&name[.. pos]) && is_ncname(&name[pos + 1 ..]),
+        None => return is_ncname(name),
+    }
+}
+
+// =====================================================================
+/// Checks if the character matches the production Char of XML 1.0,
+/// i.e. whether it can appear in the XML document.
+///
+/// # Examples
+///
+/// ```
+/// use amxml::sax::*;
+/// assert!(is_xml_char('\n'));
+/// assert!(! is_xml_char('\u{0}'));
+/// assert!(! is_xml_char('\u{FFFE}'));
+/// ```
+///
+pub fn is_xml_char(ch: char) -> bool {
+    return char_is_in_ranges(ch, &[
+        ( 0x0009, 0x000A ),
+        ( 0x000D, 0x000D ),
+        ( 0x0020, 0xD7FF ),
+        ( 0xE000, 0xFFFD ),
+        ( 0x10000, 0x10FFFF ),
+    ]);
+}
+
+// =====================================================================
+/// Checks if the character matches the production Char of XML 1.1.
+/// Unlike XML 1.0, control characters other than U+0000 are allowed,
+/// though most of them only as character references
+/// (see is_xml11_restricted_char()).
+///
+/// # Examples
+///
+/// ```
+/// use amxml::sax::*;
+/// assert!(is_xml11_char('\u{1}'));
+/// assert!(! is_xml11_char('\u{0}'));
+/// assert!(! is_xml11_char('\u{FFFE}'));
+/// ```
+///
+pub fn is_xml11_char(ch: char) -> bool {
+    return char_is_in_ranges(ch, &[
+        ( 0x0001, 0xD7FF ),
+        ( 0xE000, 0xFFFD ),
+        ( 0x10000, 0x10FFFF ),
+    ]);
+}
+
+// =====================================================================
+/// Checks if the character matches the production RestrictedChar of
+/// XML 1.1, i.e. whether it must be written as a character reference
+/// in the XML 1.1 document.
+///
+/// # Examples
+///
+/// ```
+/// use amxml::sax::*;
+/// assert!(is_xml11_restricted_char('\u{1}'));
+/// assert!(is_xml11_restricted_char('\u{7F}'));
+/// assert!(! is_xml11_restricted_char('\u{85}'));
+/// assert!(! is_xml11_restricted_char('\n'));
+/// ```
+///
+pub fn is_xml11_restricted_char(ch: char) -> bool {
+    return char_is_in_ranges(ch, &[
+        ( 0x0001, 0x0008 ),
+        ( 0x000B, 0x000C ),
+        ( 0x000E, 0x001F ),
+        ( 0x007F, 0x0084 ),
+        ( 0x0086, 0x009F ),
+    ]);
+}
+
 // ---------------------------------------------------------------------
 // [84] Letter ::= BaseChar | Ideographic
 // [85] BaseChar ::= ...
@@ -869,8 +1696,12 @@ fn char_is_in_ranges(ch: char, ch_ranges: &[(u32, u32)]) -> bool {
 // [66] CharRef ::= '&#' [0-9]+ ';'
 //                | '&#x' [0-9a-fA-F]+ ';'      // キャラクター参照
 // [68] EntityRef ::= '&' Name ';'              // 実体参照
+// 登録した実体 (entities) の参照があれば、その前後を別々にデコードする。
 //
-fn decode_entity(s: &String) -> String {
+fn decode_entity(s: &String, entities: &[(String, String)]) -> String {
+    if ! entities.is_empty() && s.contains('&') {
+        return decode_registered_entity(s, entities);
+    }
 
     // -----------------------------------------------------------------
     //
@@ -916,6 +1747,157 @@ fn decode_entity(s: &String) -> String {
 }
 
 
+// ---------------------------------------------------------------------
+// 登録した実体の参照を、その置換文字列にする。置換文字列は再びデコード
+// せず、参照の間の部分は、定義済み実体とキャラクター参照をデコードする。
+//
+fn decode_registered_entity(s: &String, entities: &[(String, String)]) -> String {
+    let mut result = String::new();
+    let mut part = String::new();
+    let mut rest = s.as_str();
+    while let Some(pos) = rest.find('&') {
+        part += &rest[.. pos];
+        rest = &rest[pos ..];
+        let value = match rest.find(';') {
+            Some(end) => entities.iter().find(|e| e.0 == &rest[1 .. end])
+                                 .map(|e| (&e.1, end)),
+            None => None,
+        };
+        match value {
+            Some((value, end)) => {
+                result += &decode_entity(&part, &[]);
+                result += value;
+                part.clear();
+                rest = &rest[end + 1 ..];
+            },
+            None => {
+                part.push('&');
+                rest = &rest[1 ..];
+            },
+        }
+    }
+    part += rest;
+    result += &decode_entity(&part, &[]);
+    return result;
+}
+
+// ---------------------------------------------------------------------
+// CDATA 以外の型の属性値の正規化: 前後の空白 (#x20) を除き、
+// 連続する空白を1個にする。
+//
+fn collapse_spaces(s: &str) -> String {
+    let words: Vec<&str> = s.split(' ').filter(|w| *w != "").collect();
+    return words.join(" ");
+}
+
+// ---------------------------------------------------------------------
+// <!DOCTYPE ...> の内部サブセットにある属性リスト宣言から、
+// CDATA 以外の型を宣言された属性の (要素名, 属性名) を取り出す。
+// [52] AttlistDecl ::= '<!ATTLIST' S Name AttDef* S? '>'
+// [53] AttDef ::= S Name S AttType S DefaultDecl
+// [60] DefaultDecl ::= '#REQUIRED' | '#IMPLIED'
+//                    | (('#FIXED' S)? AttValue)
+//
+fn parse_tokenized_attributes(doctype: &str) -> Vec<(String, String)> {
+    let mut result: Vec<(String, String)> = vec!{};
+    for decl in doctype.split("<!ATTLIST").skip(1) {
+        let tokens = attlist_tokens(decl);
+        if tokens.is_empty() {
+            continue;
+        }
+        let elem_name = &tokens[0];
+        let mut i = 1;
+        while i + 1 < tokens.len() {
+            let attr_name = &tokens[i];
+            let attr_type = &tokens[i + 1];
+            i += 2;
+            if attr_type == "NOTATION" {
+                i += 1;
+            }
+            if i < tokens.len() && tokens[i] == "#FIXED" {
+                i += 1;
+            }
+            i += 1;
+            if attr_type != "CDATA" {
+                result.push((elem_name.clone(), attr_name.clone()));
+            }
+        }
+    }
+    return result;
+}
+
+// ---------------------------------------------------------------------
+// 属性リスト宣言を、'>' までの字句 (名前、引用符で囲んだ値、
+// 括弧で囲んだ列挙) に分ける。
+//
+fn attlist_tokens(decl: &str) -> Vec<String> {
+    let mut tokens: Vec<String> = vec!{};
+    let mut chars = decl.chars().peekable();
+    while let Some(ch) = chars.next() {
+        if ch == '>' {
+            break;
+        } else if ch.is_whitespace() {
+            continue;
+        } else if ch == '"' || ch == '\'' {
+            let mut token = String::new();
+            while let Some(c) = chars.next() {
+                if c == ch {
+                    break;
+                }
+                token.push(c);
+            }
+            tokens.push(token);
+        } else if ch == '(' {
+            let mut token = String::from("(");
+            while let Some(c) = chars.next() {
+                token.push(c);
+                if c == ')' {
+                    break;
+                }
+            }
+            tokens.push(token);
+        } else {
+            let mut token = ch.to_string();
+            while let Some(&c) = chars.peek() {
+                if c.is_whitespace() || c == '>' || c == '(' || c == '"' || c == '\'' {
+                    break;
+                }
+                token.push(c);
+                chars.next();
+            }
+            tokens.push(token);
+        }
+    }
+    return tokens;
+}
+
+// ---------------------------------------------------------------------
+// 寛容モード: よく使われるHTMLの文字実体参照もデコードする。
+//
+fn decode_html_entity(s: &String, entities: &[(String, String)]) -> String {
+    let html_entity_specs = [
+        [ "&nbsp;", "\u{00A0}" ],
+        [ "&copy;", "\u{00A9}" ],
+        [ "&reg;", "\u{00AE}" ],
+        [ "&trade;", "\u{2122}" ],
+        [ "&laquo;", "\u{00AB}" ],
+        [ "&raquo;", "\u{00BB}" ],
+        [ "&ndash;", "\u{2013}" ],
+        [ "&mdash;", "\u{2014}" ],
+        [ "&hellip;", "\u{2026}" ],
+        [ "&middot;", "\u{00B7}" ],
+        [ "&times;", "\u{00D7}" ],
+        [ "&yen;", "\u{00A5}" ],
+    ];
+    let mut buf = s.clone();
+    if buf.contains("&") {
+        for spec in html_entity_specs.iter() {
+            buf = buf.replace(spec[0], spec[1]);
+        }
+    }
+    return decode_entity(&buf, entities);
+}
+
 // =====================================================================
 //
 #[cfg(test)]
@@ -955,6 +1937,10 @@ mod test {
               r#"Err: Syntax Error in XML: Unexpected EOF while searching ?>; EOF"# ],
             [ r#"<!DOCTYPE a [ <!ENTITY a b> ]>"#,
               r#"[D]: <!DOCTYPE a [ <!ENTITY a b> ]>; EOF"# ],
+            [ "<a b=\"x\ty\n z\" c=\"x&#x9;y&#xA;z\"/>",
+              "[S]: a; b = \"x y  z\"; c = \"x\ty\nz\"; [E]: a; EOF" ],
+            [ "<!DOCTYPE a [ <!ATTLIST a b NMTOKENS #IMPLIED c CDATA '1' d (x|y) #FIXED 'x'> ]><a b=' p \n q ' c=' p  q ' d=' x '/>",
+              "[D]: <!DOCTYPE a [ <!ATTLIST a b NMTOKENS #IMPLIED c CDATA '1' d (x|y) #FIXED 'x'> ]>; [S]: a; b = \"p q\"; c = \" p  q \"; d = \"x\"; [E]: a; EOF" ],
         ];
 
         for spec in test_specs.iter() {
@@ -1005,5 +1991,223 @@ mod test {
             assert_eq!(result, guess);
         }
     }
-}
 
+    // -----------------------------------------------------------------
+    //
+    #[test]
+    fn test_lenient_decoder() {
+        let test_specs = [
+            [ r#"<UL><LI Class=a>one<li>two</UL>"#,
+              r#"[S]: ul; [S]: li; class = "a"; [T]: "one"; [S]: li; [T]: "two"; [E]: ul; EOF"# ],
+            [ r#"<input disabled value='x' type=text data-x="1" DISABLED=no />"#,
+              r#"[S]: input; disabled = ""; value = "x"; type = "text"; data-x = "1"; [E]: input; EOF"# ],
+            [ r#"<p>a < b &nbsp;&amp;&copy; c<br>"#,
+              r#"[S]: p; [T]: "a "; [T]: "< b \u{a0}&© c"; [S]: br; EOF"# ],
+            [ r#"<script>if (a<b && c) { x = "</p>"; }</SCRIPT>x"#,
+              r#"[S]: script; [T]: "if (a<b && c) { x = \"</p>\"; }"; [E]: script; [T]: "x"; EOF"# ],
+            [ r#"<style></style><!DOCTYPE html><!--open"#,
+              r#"[S]: style; [E]: style; [D]: <!DOCTYPE html>; [C]: open; EOF"# ],
+            [ r#"</ ><a href="x"#,
+              r#"[S]: a; href = "x"; EOF"# ],
+        ];
+
+        for spec in test_specs.iter() {
+            let mut dec = SaxDecoder::new_lenient(spec[0]);
+            let mut result = String::from("");
+            loop {
+                match dec.raw_token().unwrap() {
+                    XmlToken::EOF => {
+                        result += "EOF";
+                        break;
+                    },
+                    XmlToken::StartElement{name, attr} => {
+                        result += &format!("[S]: {}; ", name);
+                        for at in attr.iter() {
+                            result += &format!("{} = \"{}\"; ", at.name, at.value);
+                        }
+                    },
+                    XmlToken::EndElement{name} => {
+                        result += &format!("[E]: {}; ", name);
+                    },
+                    XmlToken::CharData{chardata} => {
+                        result += &format!("[T]: {:?}; ", chardata);
+                    },
+                    XmlToken::ProcInst{target, inst} => {
+                        result += &format!("[P]: {}; {}; ", target, inst);
+                    },
+                    XmlToken::Comment{comment} => {
+                        result += &format!("[C]: {}; ", comment);
+                    },
+                    XmlToken::Directive{directive} => {
+                        result += &format!("[D]: {}; ", directive);
+                    },
+                }
+            }
+            assert_eq!(result, spec[1], "html: {}", spec[0]);
+        }
+    }
+
+    // -----------------------------------------------------------------
+    // 1回の read() で高々 n バイトしか返さない Read。
+    //
+    #[cfg(not(feature = "no_std"))]
+    struct SlowReader {
+        bytes: Vec<u8>,
+        pos: usize,
+        n: usize,
+    }
+    #[cfg(not(feature = "no_std"))]
+    impl Read for SlowReader {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let len = self.n.min(buf.len()).min(self.bytes.len() - self.pos);
+            buf[.. len].copy_from_slice(&self.bytes[self.pos .. self.pos + len]);
+            self.pos += len;
+            return Ok(len);
+        }
+    }
+
+    #[cfg(not(feature = "no_std"))]
+    fn tokens_from_reader(bytes: Vec<u8>, n: usize) -> Result<String, Box<Error>> {
+        let mut dec = SaxDecoder::from_reader(SlowReader{bytes, pos: 0, n})?;
+        let mut result = String::from("");
+        loop {
+            match dec.raw_token()? {
+                XmlToken::EOF => return Ok(result + "EOF"),
+                XmlToken::StartElement{name, attr} => {
+                    result += &format!("[S]: {}; ", name);
+                    for at in attr.iter() {
+                        result += &format!("{} = \"{}\"; ", at.name, at.value);
+                    }
+                },
+                XmlToken::EndElement{name} => result += &format!("[E]: {}; ", name),
+                XmlToken::CharData{chardata} => result += &format!("[T]: {:?}; ", chardata),
+                XmlToken::ProcInst{target, inst} => result += &format!("[P]: {}; {}; ", target, inst),
+                XmlToken::Comment{comment} => result += &format!("[C]: {}; ", comment),
+                XmlToken::Directive{directive} => result += &format!("[D]: {}; ", directive),
+            }
+        }
+    }
+
+    #[cfg(not(feature = "no_std"))]
+    fn utf16(s: &str, little_endian: bool, bom: bool) -> Vec<u8> {
+        let mut units: Vec<u16> = if bom { vec!{0xFEFF} } else { vec!{} };
+        units.extend(s.encode_utf16());
+        return units.iter().flat_map(|u| if little_endian {
+            vec!{(*u & 0xFF) as u8, (*u >> 8) as u8}
+        } else {
+            vec!{(*u >> 8) as u8, (*u & 0xFF) as u8}
+        }).collect();
+    }
+
+    // -----------------------------------------------------------------
+    // SaxDecoder::from_reader(): 符号化方式の判別と逐次的な復号。
+    //
+    #[cfg(not(feature = "no_std"))]
+    #[test]
+    fn test_from_reader() {
+        let xml = "<?xml version=\"1.0\"?>\r\n<a x=\"\u{20AC}\">\u{1F600}<![CDATA[<>]]></a>";
+        let expected = r#"[P]: xml; version="1.0"; [T]: "\n"; [S]: a; x = "€"; [T]: "😀"; [T]: "<>"; [E]: a; EOF"#;
+        let mut utf8_bom = vec!{0xEF, 0xBB, 0xBF};
+        utf8_bom.extend(xml.bytes());
+        let inputs = [
+            xml.as_bytes().to_vec(),
+            utf8_bom,
+            utf16(xml, true, true),
+            utf16(xml, false, true),
+            utf16(xml, true, false),
+            utf16(xml, false, false),
+        ];
+        for input in inputs.iter() {
+            for n in [1, 3, 4096].iter() {
+                assert_eq!(tokens_from_reader(input.clone(), *n).unwrap(), expected,
+                    "input: {:?}, n: {}", input, n);
+            }
+        }
+
+        // 読み込みの単位を越える文書
+        let mut long_xml = String::from("<r>");
+        for i in 0 .. 5000 {
+            long_xml += &format!("<e i=\"{}\"><![CDATA[x]]></e>", i);
+        }
+        long_xml += "</r>";
+        let result = tokens_from_reader(long_xml.into_bytes(), 1000).unwrap();
+        assert!(result.contains(r#"[S]: e; i = "4999"; [T]: "x"; [E]: e; [E]: r; EOF"#));
+        assert_eq!(result.matches("[S]: e;").count(), 5000);
+
+        // 誤った符号化
+        let errors = [
+            b"<a>\xFF</a>".to_vec(),
+            b"<a>\xE2\x82".to_vec(),
+            vec!{0xFF, 0xFE, b'<', 0, 0x00, 0xDC},
+            vec!{0xFF, 0xFE, b'<', 0, b'a'},
+        ];
+        for input in errors.iter() {
+            assert!(tokens_from_reader(input.clone(), 2).is_err(), "input: {:?}", input);
+        }
+    }
+
+    // -----------------------------------------------------------------
+    // 登録した実体の参照。置換文字列は再びデコードしない。
+    //
+    #[test]
+    fn test_entities() {
+        let mut dec = SaxDecoder::new(r#"<a v="&x;&amp;x;">&y;&#38;y;&lt;&z; &y</a>"#).unwrap();
+        dec.add_entity("x", "&lt;");
+        dec.add_entity("y", "[y]");
+        dec.add_entity("y", "<y>");
+        assert_eq!(dec.raw_token().unwrap(), XmlToken::StartElement{
+            name: String::from("a"),
+            attr: vec!{Attr::new("v", "&lt;&x;")},
+        });
+        assert_eq!(dec.raw_token().unwrap(), XmlToken::CharData{
+            chardata: String::from("<y>&y;<&z; &y")});
+
+        let mut dec = SaxDecoder::new_lenient("<p>&copy;&c;</p>");
+        dec.add_entity("c", "C");
+        dec.raw_token().unwrap();
+        assert_eq!(dec.raw_token().unwrap(), XmlToken::CharData{
+            chardata: String::from("\u{A9}C")});
+    }
+
+    // -----------------------------------------------------------------
+    // position(): 読み終えた文字を捨てた後も、行と桁を数える。
+    //
+    #[test]
+    fn test_position() {
+        let mut xml = String::from("<r>\n");
+        for _ in 0 .. 3000 {
+            xml += "<e>xxxx</e>\n";
+        }
+        xml += "<e></r>";
+        #[allow(unused_mut)]
+        let mut decoders = vec!{SaxDecoder::new(&xml).unwrap()};
+        #[cfg(not(feature = "no_std"))]
+        decoders.push(SaxDecoder::from_reader(
+                io::Cursor::new(xml.clone().into_bytes())).unwrap());
+        for dec in decoders.iter_mut() {
+            assert_eq!(dec.position(), (1, 1));
+            dec.raw_token().unwrap();
+            assert_eq!(dec.position(), (1, 4));
+            loop {
+                match dec.raw_token().unwrap() {
+                    XmlToken::EndElement{ref name} if name == "r" => break,
+                    _ => {},
+                }
+            }
+            assert_eq!(dec.position(), (3002, 8));
+        }
+    }
+
+    // -----------------------------------------------------------------
+    // HTML 4 の文字実体 (feature "html-entities")。
+    //
+    #[cfg(feature = "html-entities")]
+    #[test]
+    fn test_html_entities() {
+        let mut dec = SaxDecoder::new("<p>&eacute;&Omega;&hearts;&amp;&apos;&nbsp</p>").unwrap();
+        dec.add_html_entities();
+        dec.raw_token().unwrap();
+        assert_eq!(dec.raw_token().unwrap(), XmlToken::CharData{
+            chardata: String::from("\u{E9}\u{3A9}\u{2665}&'&nbsp")});
+    }
+}
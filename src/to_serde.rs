@@ -0,0 +1,717 @@
+//
+// to_serde.rs
+//
+// amxml: XML processor with XPath.
+// Copyright (C) 2018 KOYAMA Hiro <tac@amris.co.jp>
+//
+//!
+//! Deserializing DOM subtrees into Rust values that implement
+//! serde::Deserialize (feature "serde").
+//!
+//! <strong>dom::to_serde()</strong> feeds the element (typically the
+//! node found by XPath) into the Deserialize implementation:
+//!
+//! - The element becomes the struct (or map); the name of the element
+//!   itself is not checked.
+//! - Attributes and child elements become the fields of the same name.
+//!   The attribute is also found by the field name with the attribute
+//!   prefix of <strong>MappingOptions</strong> (default <code>@</code>),
+//!   such as <code>#[serde(rename = "@id")]</code>.
+//! - Repeated child elements of the same name become the Vec field.
+//!   Sequence field from the attribute or text is split by whitespace.
+//! - Text content (not whitespace-only) becomes the field named as the
+//!   text field (default <code>#text</code>).
+//! - Element that has neither attributes nor child elements becomes
+//!   the scalar value of its text content; numbers and booleans are
+//!   parsed (leading and trailing whitespace is ignored).
+//! - Missing field becomes None for the Option field.
+//!   For the Vec field, use <code>#\[serde(default)\]</code> to accept
+//!   no elements.
+//! - Enum is either the text of the unit variant name, or the element
+//!   that has the child element of the variant name.
+//!
+//! These rules are the reverse of dom::from_serde().
+//!
+//! # Examples
+//!
+//! ```
+//! extern crate amxml;
+//! #[macro_use] extern crate serde_derive;
+//! use amxml::dom::*;
+//!
+//! #[derive(Deserialize, Debug, PartialEq)]
+//! struct Item {
+//!     #[serde(rename = "@id")]
+//!     id: u32,
+//!     name: String,
+//!     #[serde(default)]
+//!     tag: Vec<String>,
+//!     price: Option<f64>,
+//! }
+//!
+//! fn main() {
+//!     let xml = r#"<order><item id="1"><name>pen</name><tag>a</tag><tag>b</tag></item><item id="2"><name>ink</name><price>2.5</price></item></order>"#;
+//!     let doc = new_document(xml).unwrap();
+//!     let node = doc.get_first_node("//item[price]").unwrap();
+//!     let item: Item = to_serde(&node).unwrap();
+//!     assert_eq!(item, Item {
+//!         id: 2, name: String::from("ink"), tag: vec!{}, price: Some(2.5),
+//!     });
+//! }
+//! ```
+//!
+
+use std::error::Error;
+use std::fmt;
+use serde::de::{self, DeserializeOwned, Deserializer, Visitor,
+                IntoDeserializer};
+
+use dom::*;
+use from_serde::MappingOptions;
+use xmlerror::*;
+
+// =====================================================================
+/// Deserializes the element (or the document element of the document,
+/// or the value of the attribute / text node) into the value of type T,
+/// with the default mapping.
+/// See the module document of to_serde.
+///
+/// # Errors
+///
+/// - When the subtree does not match the type T,
+///   e.g. a required field is missing, or a number can't be parsed.
+///
+pub fn to_serde<T: DeserializeOwned>(node: &NodePtr) -> Result<T, Box<Error>> {
+    return to_serde_with_options(node, &MappingOptions::new());
+}
+
+// =====================================================================
+/// Deserializes the subtree as to_serde(), according to the mapping
+/// (the attribute prefix and the text field are used).
+///
+pub fn to_serde_with_options<T: DeserializeOwned>(node: &NodePtr,
+                options: &MappingOptions) -> Result<T, Box<Error>> {
+    match node.node_type() {
+        NodeType::DocumentRoot => {
+            return to_serde_with_options(&node.root_element(), options);
+        },
+        NodeType::Element => {
+            let de = ElementDeserializer{ node: node.rc_clone(), options: options };
+            return Ok(T::deserialize(de)?);
+        },
+        _ => {
+            return Ok(T::deserialize(TextDeserializer{ text: node.value() })?);
+        },
+    }
+}
+
+// =====================================================================
+// Deserializeの失敗。
+//
+impl de::Error for XmlError {
+    fn custom<T: fmt::Display>(msg: T) -> XmlError {
+        return *dynamic_error!("{}", msg);
+    }
+}
+
+// ---------------------------------------------------------------------
+// 要素の文字列値 (子孫のテキストを連結したもの)。
+// 深い木でもスタックを使い切らないよう、TreeWalker で子孫をたどる。
+//
+fn collect_text(node: &NodePtr, s: &mut String) {
+    for text in node.walk().node_type(NodeType::Text) {
+        *s += &text.value();
+    }
+}
+
+// ---------------------------------------------------------------------
+// 要素に属性または子要素があるか否か。
+//
+fn has_structure(node: &NodePtr) -> bool {
+    return node.attribute_nodes().len() != 0 ||
+        node.children().iter().any(|ch| ch.node_type() == NodeType::Element);
+}
+
+// ---------------------------------------------------------------------
+// 文字列 (属性値、テキスト) のDeserializer。
+// 数値、真偽値は、前後の空白を除いて解析する。
+//
+struct TextDeserializer {
+    text: String,
+}
+
+macro_rules! deserialize_parsed {
+    ( $( $method:ident, $visit:ident, $t:ty ; )* ) => {
+        $(
+            fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, XmlError> {
+                match self.text.trim().parse::<$t>() {
+                    Ok(v) => return visitor.$visit(v),
+                    Err(_) => return Err(*dynamic_error!(
+                        "Can't parse as {}: {}", stringify!($t), self.text)),
+                }
+            }
+        )*
+    }
+}
+
+impl<'de> Deserializer<'de> for TextDeserializer {
+    type Error = XmlError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, XmlError> {
+        return visitor.visit_string(self.text);
+    }
+
+    deserialize_parsed! {
+        deserialize_bool, visit_bool, bool;
+        deserialize_i8, visit_i8, i8;
+        deserialize_i16, visit_i16, i16;
+        deserialize_i32, visit_i32, i32;
+        deserialize_i64, visit_i64, i64;
+        deserialize_u8, visit_u8, u8;
+        deserialize_u16, visit_u16, u16;
+        deserialize_u32, visit_u32, u32;
+        deserialize_u64, visit_u64, u64;
+        deserialize_f32, visit_f32, f32;
+        deserialize_f64, visit_f64, f64;
+    }
+
+    fn deserialize_char<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, XmlError> {
+        let mut chars = self.text.chars();
+        match (chars.next(), chars.next()) {
+            (Some(c), None) => return visitor.visit_char(c),
+            _ => return Err(*dynamic_error!("Can't parse as char: {}", self.text)),
+        }
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, XmlError> {
+        return visitor.visit_some(self);
+    }
+
+    fn deserialize_unit<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, XmlError> {
+        return visitor.visit_unit();
+    }
+
+    fn deserialize_unit_struct<V: Visitor<'de>>(self, _name: &'static str,
+                visitor: V) -> Result<V::Value, XmlError> {
+        return visitor.visit_unit();
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(self, _name: &'static str,
+                visitor: V) -> Result<V::Value, XmlError> {
+        return visitor.visit_newtype_struct(self);
+    }
+
+    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, XmlError> {
+        let tokens: Vec<TextDeserializer> = self.text.split_whitespace()
+                .map(|s| TextDeserializer{ text: String::from(s) }).collect();
+        return visitor.visit_seq(de::value::SeqDeserializer::new(tokens.into_iter()));
+    }
+
+    fn deserialize_tuple<V: Visitor<'de>>(self, _len: usize,
+                visitor: V) -> Result<V::Value, XmlError> {
+        return self.deserialize_seq(visitor);
+    }
+
+    fn deserialize_tuple_struct<V: Visitor<'de>>(self, _name: &'static str,
+                _len: usize, visitor: V) -> Result<V::Value, XmlError> {
+        return self.deserialize_seq(visitor);
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(self, _name: &'static str,
+                _variants: &'static [&'static str],
+                visitor: V) -> Result<V::Value, XmlError> {
+        let variant: de::value::StringDeserializer<XmlError> =
+                String::from(self.text.trim()).into_deserializer();
+        return visitor.visit_enum(variant);
+    }
+
+    forward_to_deserialize_any! {
+        str string bytes byte_buf map struct identifier ignored_any
+    }
+}
+
+impl<'de> IntoDeserializer<'de, XmlError> for TextDeserializer {
+    type Deserializer = TextDeserializer;
+    fn into_deserializer(self) -> TextDeserializer {
+        return self;
+    }
+}
+
+// ---------------------------------------------------------------------
+// 要素のDeserializer。
+//
+struct ElementDeserializer<'a> {
+    node: NodePtr,
+    options: &'a MappingOptions,
+}
+
+impl<'a> ElementDeserializer<'a> {
+    fn text(&self) -> TextDeserializer {
+        let mut s = String::new();
+        collect_text(&self.node, &mut s);
+        return TextDeserializer{ text: s };
+    }
+
+    // -----------------------------------------------------------------
+    // 構造体のメンバー: 属性、子要素 (名前ごとにまとめる)、テキスト。
+    // fields が与えられていれば、属性名はフィールド名に合わせる。
+    //
+    fn members(&self, fields: &[&str]) -> Vec<(String, FieldValue)> {
+        let mut members: Vec<(String, FieldValue)> = vec!{};
+        for at in self.node.attribute_nodes().iter() {
+            let prefixed = format!("{}{}", self.options.attribute_prefix, at.name());
+            let key = if fields.contains(&prefixed.as_str()) { prefixed } else { at.name() };
+            members.push((key, FieldValue::Text(at.value())));
+        }
+
+        let mut text = String::new();
+        for ch in self.node.children().iter() {
+            match ch.node_type() {
+                NodeType::Element => {
+                    let name = ch.name();
+                    let pos = members.iter().position(|&(ref key, ref value)|
+                        *key == name && match value {
+                            FieldValue::Elements(_) => true,
+                            _ => false,
+                        });
+                    match pos {
+                        Some(i) => {
+                            if let FieldValue::Elements(ref mut nodes) = members[i].1 {
+                                nodes.push(ch.rc_clone());
+                            }
+                        },
+                        None => members.push((name, FieldValue::Elements(vec!{ch.rc_clone()}))),
+                    }
+                },
+                NodeType::Text => text += &ch.value(),
+                _ => {},
+            }
+        }
+        if text.trim() != "" {
+            members.push((self.options.text_field.clone(),
+                            FieldValue::Text(text)));
+        }
+        return members;
+    }
+
+    fn visit_members<'de, V: Visitor<'de>>(self, fields: &[&str],
+                visitor: V) -> Result<V::Value, XmlError> {
+        let members = self.members(fields);
+        return visitor.visit_map(MemberAccess{
+            members: members.into_iter(),
+            value: None,
+            options: self.options,
+        });
+    }
+}
+
+macro_rules! forward_to_text {
+    ( $( $method:ident )* ) => {
+        $(
+            fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, XmlError> {
+                return self.text().$method(visitor);
+            }
+        )*
+    }
+}
+
+impl<'a, 'de> Deserializer<'de> for ElementDeserializer<'a> {
+    type Error = XmlError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, XmlError> {
+        if has_structure(&self.node) {
+            return self.visit_members(&[], visitor);
+        }
+        return self.text().deserialize_any(visitor);
+    }
+
+    forward_to_text! {
+        deserialize_bool deserialize_i8 deserialize_i16 deserialize_i32
+        deserialize_i64 deserialize_u8 deserialize_u16 deserialize_u32
+        deserialize_u64 deserialize_f32 deserialize_f64 deserialize_char
+        deserialize_str deserialize_string deserialize_bytes
+        deserialize_byte_buf deserialize_identifier
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, XmlError> {
+        return visitor.visit_some(self);
+    }
+
+    fn deserialize_unit<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, XmlError> {
+        return visitor.visit_unit();
+    }
+
+    fn deserialize_unit_struct<V: Visitor<'de>>(self, _name: &'static str,
+                visitor: V) -> Result<V::Value, XmlError> {
+        return visitor.visit_unit();
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(self, _name: &'static str,
+                visitor: V) -> Result<V::Value, XmlError> {
+        return visitor.visit_newtype_struct(self);
+    }
+
+    // -----------------------------------------------------------------
+    // 要素自体をシーケンスとする場合は、子要素 (名前は問わない) を項目とする。
+    //
+    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, XmlError> {
+        let options = self.options;
+        let items: Vec<ElementDeserializer> = self.node.children().iter()
+                .filter(|ch| ch.node_type() == NodeType::Element)
+                .map(|ch| ElementDeserializer{ node: ch.rc_clone(), options: options })
+                .collect();
+        return visitor.visit_seq(de::value::SeqDeserializer::new(items.into_iter()));
+    }
+
+    fn deserialize_tuple<V: Visitor<'de>>(self, _len: usize,
+                visitor: V) -> Result<V::Value, XmlError> {
+        return self.deserialize_seq(visitor);
+    }
+
+    fn deserialize_tuple_struct<V: Visitor<'de>>(self, _name: &'static str,
+                _len: usize, visitor: V) -> Result<V::Value, XmlError> {
+        return self.deserialize_seq(visitor);
+    }
+
+    fn deserialize_map<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, XmlError> {
+        return self.visit_members(&[], visitor);
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(self, _name: &'static str,
+                fields: &'static [&'static str],
+                visitor: V) -> Result<V::Value, XmlError> {
+        return self.visit_members(fields, visitor);
+    }
+
+    // -----------------------------------------------------------------
+    // 単位変種はテキスト、その他の変種は変種名の子要素。
+    //
+    fn deserialize_enum<V: Visitor<'de>>(self, name: &'static str,
+                variants: &'static [&'static str],
+                visitor: V) -> Result<V::Value, XmlError> {
+        let child_elems: Vec<NodePtr> = self.node.children().into_iter()
+                .filter(|ch| ch.node_type() == NodeType::Element).collect();
+        if child_elems.len() == 0 {
+            return self.text().deserialize_enum(name, variants, visitor);
+        }
+        if child_elems.len() != 1 {
+            return Err(*dynamic_error!(
+                "Element for enum {} must have one child element: {}",
+                name, self.node.name()));
+        }
+        return visitor.visit_enum(VariantAccess{
+            node: child_elems[0].rc_clone(),
+            options: self.options,
+        });
+    }
+
+    fn deserialize_ignored_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, XmlError> {
+        return visitor.visit_unit();
+    }
+}
+
+impl<'a, 'de> IntoDeserializer<'de, XmlError> for ElementDeserializer<'a> {
+    type Deserializer = ElementDeserializer<'a>;
+    fn into_deserializer(self) -> ElementDeserializer<'a> {
+        return self;
+    }
+}
+
+// ---------------------------------------------------------------------
+// 構造体のメンバーの値: 属性値、テキスト、または同名の子要素の並び。
+//
+enum FieldValue {
+    Text(String),
+    Elements(Vec<NodePtr>),
+}
+
+// ---------------------------------------------------------------------
+// メンバーの値のDeserializer。
+// 子要素の並びは、シーケンスとして読む場合以外は、先頭の要素を読む。
+//
+struct FieldDeserializer<'a> {
+    value: FieldValue,
+    options: &'a MappingOptions,
+}
+
+macro_rules! forward_to_field {
+    ( $( $method:ident ( $( $arg:ident : $t:ty ),* ) )* ) => {
+        $(
+            fn $method<V: Visitor<'de>>(self, $( $arg: $t, )* visitor: V) -> Result<V::Value, XmlError> {
+                match self.value {
+                    FieldValue::Text(text) => {
+                        return TextDeserializer{ text: text }.$method($( $arg, )* visitor);
+                    },
+                    FieldValue::Elements(nodes) => {
+                        let de = ElementDeserializer{
+                            node: nodes[0].rc_clone(),
+                            options: self.options,
+                        };
+                        return de.$method($( $arg, )* visitor);
+                    },
+                }
+            }
+        )*
+    }
+}
+
+impl<'a, 'de> Deserializer<'de> for FieldDeserializer<'a> {
+    type Error = XmlError;
+
+    forward_to_field! {
+        deserialize_any() deserialize_bool() deserialize_i8() deserialize_i16()
+        deserialize_i32() deserialize_i64() deserialize_u8() deserialize_u16()
+        deserialize_u32() deserialize_u64() deserialize_f32() deserialize_f64()
+        deserialize_char() deserialize_str() deserialize_string()
+        deserialize_bytes() deserialize_byte_buf() deserialize_unit()
+        deserialize_map() deserialize_identifier() deserialize_ignored_any()
+        deserialize_unit_struct(name: &'static str)
+        deserialize_newtype_struct(name: &'static str)
+        deserialize_struct(name: &'static str, fields: &'static [&'static str])
+        deserialize_enum(name: &'static str, variants: &'static [&'static str])
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, XmlError> {
+        return visitor.visit_some(self);
+    }
+
+    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, XmlError> {
+        match self.value {
+            FieldValue::Text(text) => {
+                return TextDeserializer{ text: text }.deserialize_seq(visitor);
+            },
+            FieldValue::Elements(nodes) => {
+                let options = self.options;
+                let items: Vec<ElementDeserializer> = nodes.into_iter()
+                        .map(|node| ElementDeserializer{ node: node, options: options })
+                        .collect();
+                return visitor.visit_seq(de::value::SeqDeserializer::new(items.into_iter()));
+            },
+        }
+    }
+
+    fn deserialize_tuple<V: Visitor<'de>>(self, _len: usize,
+                visitor: V) -> Result<V::Value, XmlError> {
+        return self.deserialize_seq(visitor);
+    }
+
+    fn deserialize_tuple_struct<V: Visitor<'de>>(self, _name: &'static str,
+                _len: usize, visitor: V) -> Result<V::Value, XmlError> {
+        return self.deserialize_seq(visitor);
+    }
+}
+
+// ---------------------------------------------------------------------
+// 構造体のメンバーを順に渡すMapAccess。
+//
+struct MemberAccess<'a> {
+    members: ::std::vec::IntoIter<(String, FieldValue)>,
+    value: Option<FieldValue>,
+    options: &'a MappingOptions,
+}
+
+impl<'a, 'de> de::MapAccess<'de> for MemberAccess<'a> {
+    type Error = XmlError;
+
+    fn next_key_seed<K: de::DeserializeSeed<'de>>(&mut self,
+                seed: K) -> Result<Option<K::Value>, XmlError> {
+        match self.members.next() {
+            Some((key, value)) => {
+                self.value = Some(value);
+                let key_de: de::value::StringDeserializer<XmlError> = key.into_deserializer();
+                return Ok(Some(seed.deserialize(key_de)?));
+            },
+            None => return Ok(None),
+        }
+    }
+
+    fn next_value_seed<V: de::DeserializeSeed<'de>>(&mut self,
+                seed: V) -> Result<V::Value, XmlError> {
+        match self.value.take() {
+            Some(value) => return seed.deserialize(FieldDeserializer{
+                                value: value,
+                                options: self.options,
+                            }),
+            None => return Err(*cant_occur!("next_value_seed: 値がない。")),
+        }
+    }
+}
+
+// ---------------------------------------------------------------------
+// 変種名の子要素を渡すEnumAccess。
+//
+struct VariantAccess<'a> {
+    node: NodePtr,
+    options: &'a MappingOptions,
+}
+
+impl<'a, 'de> de::EnumAccess<'de> for VariantAccess<'a> {
+    type Error = XmlError;
+    type Variant = ElementDeserializer<'a>;
+
+    fn variant_seed<V: de::DeserializeSeed<'de>>(self,
+                seed: V) -> Result<(V::Value, ElementDeserializer<'a>), XmlError> {
+        let name_de: de::value::StringDeserializer<XmlError> =
+                self.node.name().into_deserializer();
+        let variant = seed.deserialize(name_de)?;
+        return Ok((variant, ElementDeserializer{
+            node: self.node,
+            options: self.options,
+        }));
+    }
+}
+
+impl<'a, 'de> de::VariantAccess<'de> for ElementDeserializer<'a> {
+    type Error = XmlError;
+
+    fn unit_variant(self) -> Result<(), XmlError> {
+        return Ok(());
+    }
+
+    fn newtype_variant_seed<T: de::DeserializeSeed<'de>>(self,
+                seed: T) -> Result<T::Value, XmlError> {
+        return seed.deserialize(self);
+    }
+
+    fn tuple_variant<V: Visitor<'de>>(self, _len: usize,
+                visitor: V) -> Result<V::Value, XmlError> {
+        return self.deserialize_seq(visitor);
+    }
+
+    fn struct_variant<V: Visitor<'de>>(self, fields: &'static [&'static str],
+                visitor: V) -> Result<V::Value, XmlError> {
+        return self.visit_members(fields, visitor);
+    }
+}
+
+// =====================================================================
+//
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    #[derive(Deserialize, Debug, PartialEq)]
+    struct Book {
+        #[serde(rename = "@isbn")]
+        isbn: String,
+        lang: Option<String>,
+        title: String,
+        #[serde(default)]
+        author: Vec<String>,
+        year: Option<u16>,
+        price: f64,
+        format: Format,
+        tags: BTreeMap<String, bool>,
+        #[serde(default)]
+        scores: Vec<i32>,
+        cover: Option<Cover>,
+        note: Note,
+    }
+
+    #[derive(Deserialize, Debug, PartialEq)]
+    enum Format {
+        Hardcover,
+        Ebook { size: u32 },
+    }
+
+    #[derive(Deserialize, Debug, PartialEq)]
+    struct Cover(String);
+
+    #[derive(Deserialize, Debug, PartialEq)]
+    struct Note {
+        kind: char,
+        #[serde(rename = "#text")]
+        body: String,
+    }
+
+    // -----------------------------------------------------------------
+    //
+    #[test]
+    fn test_to_serde() {
+        let xml = r#"<shelf>
+<Book isbn="4-00-000000-0" lang="ja" scores="3 -1 4">
+    <title>Fish &amp; Chips</title>
+    <author>A</author>
+    <author>B</author>
+    <price> 12.5 </price>
+    <format>Hardcover</format>
+    <tags><new>true</new><sale>false</sale></tags>
+    <cover>red</cover>
+    <note kind="x">see <b>also</b></note>
+</Book>
+<Book isbn="4-00-000001-9">
+    <title>Ebook</title>
+    <year>2018</year>
+    <price>1</price>
+    <format><Ebook><size>300</size></Ebook></format>
+    <tags/>
+    <note kind="y">-</note>
+</Book>
+</shelf>"#;
+        let doc = new_document(xml).unwrap();
+        let books: Vec<Book> = to_serde(&doc).unwrap();
+        assert_eq!(books.len(), 2);
+        let mut tags = BTreeMap::new();
+        tags.insert(String::from("new"), true);
+        tags.insert(String::from("sale"), false);
+        assert_eq!(books[0], Book {
+            isbn: String::from("4-00-000000-0"),
+            lang: Some(String::from("ja")),
+            title: String::from("Fish & Chips"),
+            author: vec!{ String::from("A"), String::from("B") },
+            year: None,
+            price: 12.5,
+            format: Format::Hardcover,
+            tags: tags,
+            scores: vec!{3, -1, 4},
+            cover: Some(Cover(String::from("red"))),
+            note: Note { kind: 'x', body: String::from("see ") },
+        });
+        assert_eq!(books[1].format, Format::Ebook { size: 300 });
+        assert_eq!(books[1].year, Some(2018));
+        assert_eq!(books[1].author, Vec::<String>::new());
+
+        // XPathで選んだノード。
+        let node = doc.get_first_node("//Book[year]/format").unwrap();
+        let format: Format = to_serde(&node).unwrap();
+        assert_eq!(format, Format::Ebook { size: 300 });
+        let node = doc.get_first_node("//Book[1]/@isbn").unwrap();
+        let isbn: String = to_serde(&node).unwrap();
+        assert_eq!(isbn, "4-00-000000-0");
+
+        // 属性接頭辞を変える。
+        #[derive(Deserialize, Debug, PartialEq)]
+        struct Kind {
+            #[serde(rename = "-kind")]
+            kind: String,
+            #[serde(rename = "$")]
+            body: String,
+        }
+        let node = doc.get_first_node("//Book[1]/note").unwrap();
+        let options = MappingOptions::new().attribute_prefix("-").text_field("$");
+        let kind: Kind = to_serde_with_options(&node, &options).unwrap();
+        assert_eq!(kind, Kind { kind: String::from("x"), body: String::from("see ") });
+
+        // 型に合わない。
+        let node = doc.get_first_node("//Book[1]/title").unwrap();
+        assert!(to_serde::<f64>(&node).is_err());
+        let node = doc.get_first_node("//Book[2]").unwrap();
+        let result: Result<Book, Box<Error>> = to_serde_with_options(&node,
+                        &MappingOptions::new().attribute_prefix(""));
+        assert!(result.is_err());
+    }
+
+    // -----------------------------------------------------------------
+    // 要素の文字列値は子孫のテキストを連結する。深い木でもよい。
+    //
+    #[test]
+    fn test_to_serde_deep_text() {
+        let depth = 100000;
+        let xml = format!("<r>{}x{}</r>", "<a>".repeat(depth), "</a>".repeat(depth));
+        let doc = new_document(&xml).unwrap();
+        let node = doc.get_first_node("/r").unwrap();
+        let text: String = to_serde(&node).unwrap();
+        assert_eq!(text, "x");
+    }
+}
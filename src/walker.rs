@@ -0,0 +1,367 @@
+//
+// walker.rs
+//
+// amxml: XML processor with XPath.
+// Copyright (C) 2018 KOYAMA Hiro <tac@amris.co.jp>
+//
+//!
+//! Tree walking iterator over the DOM tree.
+//!
+//! A <strong>TreeWalker</strong>, created by NodePtr#walk(), iterates
+//! over the nodes on an axis (descendants by default), optionally
+//! only those of a node type and / or a name.
+//! It covers the simple traversals, such as
+//! <code>descendant::item</code>, without compiling and evaluating
+//! an XPath expression.
+//!
+//! While iterating, skip_children() tells the walker not to descend
+//! into the node it has just returned.
+//!
+//! # Examples
+//!
+//! ```
+//! use amxml::dom::*;
+//! use amxml::walker::*;
+//! let xml = r#"<root><item id="1"><item id="2"/></item><skip><item id="3"/></skip><item id="4"/></root>"#;
+//! let doc = new_document(xml).unwrap();
+//!
+//! let ids: Vec<String> = doc.walk()
+//!         .node_type(NodeType::Element).named("item")
+//!         .map(|n| n.attribute_value("id").unwrap()).collect();
+//! assert_eq!(ids, vec!{"1", "2", "3", "4"});
+//!
+//! let mut walker = doc.walk().node_type(NodeType::Element);
+//! let mut names = vec!{};
+//! while let Some(node) = walker.next() {
+//!     if node.name() == "skip" {
+//!         walker.skip_children();
+//!     }
+//!     names.push(node.name());
+//! }
+//! assert_eq!(names, vec!{"root", "item", "item", "skip", "item"});
+//!
+//! let item2 = doc.get_first_node(r#"//item[@id="2"]"#).unwrap();
+//! let names: Vec<String> = item2.walk().axis(Axis::Ancestor)
+//!         .node_type(NodeType::Element).map(|n| n.name()).collect();
+//! assert_eq!(names, vec!{"item", "root"});
+//! ```
+//!
+
+#[cfg(feature = "no_std")]
+use std::prelude::*;
+
+use dom::*;
+
+// =====================================================================
+/// Axis along which TreeWalker iterates, as the axis of XPath
+/// of the same name.
+/// Attributes are not on any of these axes.
+///
+/// The forward axes iterate in document order, and the reverse
+/// axes (Ancestor, AncestorOrSelf, PrecedingSibling, Preceding)
+/// in reverse document order, i.e. the nearest node first.
+///
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Axis {
+    Child,
+    Descendant,
+    DescendantOrSelf,
+    Parent,
+    Ancestor,
+    AncestorOrSelf,
+    FollowingSibling,
+    PrecedingSibling,
+    Following,
+    Preceding,
+}
+
+// =====================================================================
+/// Iterator over the nodes on an axis, created by NodePtr#walk().
+/// See the module document.
+///
+#[derive(Debug, Clone)]
+pub struct TreeWalker {
+    origin: NodePtr,
+    axis: Axis,
+    node_type: Option<NodeType>,
+    name: Option<String>,
+    started: bool,
+    pending: Vec<NodePtr>,
+            // これから訪れるノード (末尾から取り出す)。
+    expand: Option<NodePtr>,
+            // 直前に返したノード。次に進む前に、その子を pending に積む。
+}
+
+// =====================================================================
+//
+impl NodePtr {
+
+    // =================================================================
+    /// Returns the TreeWalker that iterates over the descendants of
+    /// the node; see the module document of walker.
+    ///
+    pub fn walk(&self) -> TreeWalker {
+        return TreeWalker{
+            origin: self.rc_clone(),
+            axis: Axis::Descendant,
+            node_type: None,
+            name: None,
+            started: false,
+            pending: vec!{},
+            expand: None,
+        };
+    }
+}
+
+impl TreeWalker {
+    // =================================================================
+    /// Sets the axis to iterate along (Descendant by default).
+    ///
+    pub fn axis(mut self, axis: Axis) -> TreeWalker {
+        self.axis = axis;
+        return self;
+    }
+
+    // =================================================================
+    /// Returns only the nodes of the node type.
+    ///
+    pub fn node_type(mut self, node_type: NodeType) -> TreeWalker {
+        self.node_type = Some(node_type);
+        return self;
+    }
+
+    // =================================================================
+    /// Returns only the nodes of the name (including the namespace
+    /// prefix, as NodePtr#name() returns).
+    ///
+    pub fn named(mut self, name: &str) -> TreeWalker {
+        self.name = Some(String::from(name));
+        return self;
+    }
+
+    // =================================================================
+    /// Does not descend into the node returned last by next().
+    /// Effective on the axes Descendant, DescendantOrSelf and
+    /// Following; the other axes do not descend anyway.
+    ///
+    pub fn skip_children(&mut self) {
+        self.expand = None;
+    }
+
+    // -----------------------------------------------------------------
+    // 軸上のノードのうち、最初に訪れるもの (子孫をたどる軸の場合は
+    // その起点) を pending に、訪れる順と逆に積む。
+    //
+    fn start(&mut self) {
+        let origin = self.origin.rc_clone();
+        let mut nodes: Vec<NodePtr> = vec!{};
+        match self.axis {
+            Axis::Child | Axis::Descendant => {
+                nodes = origin.children();
+            },
+            Axis::DescendantOrSelf => {
+                nodes.push(origin);
+            },
+            Axis::Parent => {
+                nodes.extend(origin.parent());
+            },
+            Axis::Ancestor | Axis::AncestorOrSelf => {
+                let mut curr = origin.parent();
+                if self.axis == Axis::AncestorOrSelf {
+                    nodes.push(origin);
+                }
+                while let Some(node) = curr {
+                    curr = node.parent();
+                    nodes.push(node);
+                }
+            },
+            Axis::FollowingSibling => {
+                nodes = following_siblings(&origin);
+            },
+            Axis::PrecedingSibling => {
+                nodes = preceding_siblings(&origin);
+            },
+            Axis::Following => {
+                let mut curr = Some(origin);
+                while let Some(node) = curr {
+                    nodes.extend(following_siblings(&node));
+                    curr = node.parent();
+                }
+            },
+            Axis::Preceding => {
+                let mut curr = Some(origin);
+                while let Some(node) = curr {
+                    for sibling in preceding_siblings(&node).iter() {
+                        push_subtree_reverse(sibling, &mut nodes);
+                    }
+                    curr = node.parent();
+                }
+            },
+        }
+        nodes.reverse();
+        self.pending = nodes;
+    }
+
+    // -----------------------------------------------------------------
+    //
+    fn matches(&self, node: &NodePtr) -> bool {
+        if let Some(ref node_type) = self.node_type {
+            if node.node_type() != *node_type {
+                return false;
+            }
+        }
+        if let Some(ref name) = self.name {
+            if node.name() != *name {
+                return false;
+            }
+        }
+        return true;
+    }
+}
+
+impl Iterator for TreeWalker {
+    type Item = NodePtr;
+
+    fn next(&mut self) -> Option<NodePtr> {
+        if ! self.started {
+            self.started = true;
+            self.start();
+        }
+        let descends = match self.axis {
+            Axis::Descendant | Axis::DescendantOrSelf | Axis::Following => true,
+            _ => false,
+        };
+        loop {
+            if let Some(node) = self.expand.take() {
+                let mut children = node.children();
+                children.reverse();
+                self.pending.extend(children);
+            }
+            let node = match self.pending.pop() {
+                Some(node) => node,
+                None => return None,
+            };
+            if descends {
+                self.expand = Some(node.rc_clone());
+            }
+            if self.matches(&node) {
+                return Some(node);
+            }
+        }
+    }
+}
+
+// ---------------------------------------------------------------------
+// 兄弟のうち、node より後にあるもの (文書順)。
+//
+fn following_siblings(node: &NodePtr) -> Vec<NodePtr> {
+    match node.parent() {
+        Some(parent) => {
+            let children = parent.children();
+            match children.iter().position(|ch| ch == node) {
+                Some(pos) => return children[pos + 1 ..].to_vec(),
+                None => return vec!{},
+            }
+        },
+        None => return vec!{},
+    }
+}
+
+// ---------------------------------------------------------------------
+// 兄弟のうち、node より前にあるもの (文書順の逆)。
+//
+fn preceding_siblings(node: &NodePtr) -> Vec<NodePtr> {
+    match node.parent() {
+        Some(parent) => {
+            let children = parent.children();
+            match children.iter().position(|ch| ch == node) {
+                Some(pos) => return children[.. pos].iter().rev().cloned().collect(),
+                None => return vec!{},
+            }
+        },
+        None => return vec!{},
+    }
+}
+
+// ---------------------------------------------------------------------
+// node を根とする部分木のノードを、文書順の逆に nodes に追加する。
+// 深い木でもスタックを使い切らないよう、再帰しない。
+//
+fn push_subtree_reverse(node: &NodePtr, nodes: &mut Vec<NodePtr>) {
+    let mut subtree: Vec<NodePtr> = vec!{};
+    let mut stack: Vec<NodePtr> = vec!{node.rc_clone()};
+    while let Some(n) = stack.pop() {
+        let mut children = n.children();
+        children.reverse();
+        stack.extend(children);
+        subtree.push(n);
+    }
+    subtree.reverse();
+    nodes.extend(subtree);
+}
+
+// =====================================================================
+//
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // -----------------------------------------------------------------
+    //
+    fn names(walker: TreeWalker) -> String {
+        let names: Vec<String> = walker.map(|n| n.name()).collect();
+        return names.join(" ");
+    }
+
+    // -----------------------------------------------------------------
+    //
+    #[test]
+    fn test_walker_axis() {
+        let doc = new_document(r#"<root><a><x/><y/></a><b><z/></b><c/></root>"#).unwrap();
+        let b = doc.get_first_node("//b").unwrap();
+
+        assert_eq!(names(doc.walk()), "root a x y b z c");
+        assert_eq!(names(b.walk().axis(Axis::Child)), "z");
+        assert_eq!(names(b.walk().axis(Axis::Descendant)), "z");
+        assert_eq!(names(b.walk().axis(Axis::DescendantOrSelf)), "b z");
+        assert_eq!(names(b.walk().axis(Axis::Parent)), "root");
+        assert_eq!(names(b.walk().axis(Axis::Ancestor)), "root ");
+        assert_eq!(names(b.walk().axis(Axis::AncestorOrSelf)), "b root ");
+        assert_eq!(names(b.walk().axis(Axis::FollowingSibling)), "c");
+        assert_eq!(names(b.walk().axis(Axis::PrecedingSibling)), "a");
+        assert_eq!(names(b.walk().axis(Axis::Following)), "c");
+        assert_eq!(names(b.walk().axis(Axis::Preceding)), "y x a");
+
+        let x = doc.get_first_node("//x").unwrap();
+        assert_eq!(names(x.walk().axis(Axis::Following)), "y b z c");
+        let z = doc.get_first_node("//z").unwrap();
+        assert_eq!(names(z.walk().axis(Axis::Preceding)), "y x a");
+        assert_eq!(names(doc.walk().axis(Axis::Parent)), "");
+    }
+
+    // -----------------------------------------------------------------
+    //
+    #[test]
+    fn test_walker_filter() {
+        let doc = new_document(r#"<root><a>t1<a/></a><!--c--><b>t2</b></root>"#).unwrap();
+
+        assert_eq!(names(doc.walk().named("a")), "a a");
+        let texts: Vec<String> = doc.walk().node_type(NodeType::Text)
+                    .map(|n| n.value()).collect();
+        assert_eq!(texts, vec!{"t1", "t2"});
+        assert_eq!(doc.walk().node_type(NodeType::Comment).count(), 1);
+
+        // 返さなかったノードの子孫も訪れる。
+        assert_eq!(names(doc.walk().named("b")), "b");
+
+        // Following 軸でも、返したノードの子孫を飛ばせる。
+        let t1 = doc.get_first_node("//a/text()").unwrap();
+        let mut walker = t1.walk().axis(Axis::Following).node_type(NodeType::Element);
+        let mut visited = vec!{};
+        while let Some(node) = walker.next() {
+            walker.skip_children();
+            visited.push(node.name());
+        }
+        assert_eq!(visited, vec!{"a", "b"});
+    }
+}
@@ -11,6 +11,9 @@
 
 use std::error::Error;
 use std::fmt;
+#[cfg(feature = "no_std")]
+use std::prelude::*;
+use xpath::Sequence;
 
 // =====================================================================
 //
@@ -41,6 +44,147 @@ const ERROR_PREFIX: [(XmlErrorType, &str); 7] = [
 pub struct XmlError {
     error_type: XmlErrorType,
     descri: String,
+    syntax_info: Option<SyntaxErrorInfo>,
+    user_error: Option<UserError>,
+    position: Option<XmlPosition>,
+}
+
+impl XmlError {
+    /// Returns the type code of this error.
+    ///
+    pub fn error_type(&self) -> &XmlErrorType {
+        return &self.error_type;
+    }
+
+    /// Returns where the syntax error was detected, if this is a
+    /// syntax error in XPath that carries position information.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use amxml::dom::*;
+    /// use amxml::xmlerror::*;
+    /// let doc = new_document("<root/>").unwrap();
+    /// let err = doc.eval_xpath("count(/root").err().unwrap();
+    /// let info = err.downcast_ref::<XmlError>().unwrap().syntax_info().unwrap();
+    /// assert_eq!(info.offset(), 11);
+    /// assert_eq!(info.expected(), &vec![String::from(")")]);
+    /// ```
+    ///
+    pub fn syntax_info(&self) -> Option<&SyntaxErrorInfo> {
+        return self.syntax_info.as_ref();
+    }
+
+    /// Returns the error raised by fn:error() in XPath, if this is
+    /// such a (dynamic) error.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use amxml::dom::*;
+    /// use amxml::xmlerror::*;
+    /// let doc = new_document("<root/>").unwrap();
+    /// let err = doc.eval_xpath("error('app:E01', 'Out of stock', (3, 'pen'))").err().unwrap();
+    /// let user_error = err.downcast_ref::<XmlError>().unwrap().user_error().unwrap();
+    /// assert_eq!(user_error.code(), "app:E01");
+    /// assert_eq!(user_error.description(), "Out of stock");
+    /// assert_eq!(user_error.value().unwrap().to_string(), r#"(3, "pen")"#);
+    /// assert_eq!(err.to_string(), "Dynamic Error: Out of stock [app:E01]");
+    /// ```
+    ///
+    pub fn user_error(&self) -> Option<&UserError> {
+        return self.user_error.as_ref();
+    }
+
+    /// Returns where the syntax error was detected in the XML string,
+    /// if this is a syntax error in XML reported by new_document(),
+    /// etc. that carries position information.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use amxml::dom::*;
+    /// use amxml::xmlerror::*;
+    /// let err = new_document("<root>\n  <a></b>\n</root>").err().unwrap();
+    /// let pos = err.downcast_ref::<XmlError>().unwrap().position().unwrap();
+    /// assert_eq!((pos.line(), pos.column()), (2, 10));
+    /// assert!(err.to_string().ends_with("(line 2, column 10)"));
+    /// ```
+    ///
+    pub fn position(&self) -> Option<&XmlPosition> {
+        return self.position.as_ref();
+    }
+}
+
+// =====================================================================
+//
+#[derive(Debug, Clone)]
+/// Error raised by fn:error() in XPath: the error code, description
+/// and error object (the third argument) as given in XPath.
+///
+pub struct UserError {
+    code: String,
+    description: String,
+    value: Option<Sequence>,
+}
+
+impl UserError {
+    /// Error code: the QName given as the first argument of fn:error()
+    /// (e.g. "app:E01", or "Q{http://example.com/}E01"),
+    /// or "err:FOER0000" if omitted.
+    ///
+    pub fn code(&self) -> &str {
+        return self.code.as_str();
+    }
+
+    /// Description: the second argument of fn:error(), if any.
+    ///
+    pub fn description(&self) -> &str {
+        return self.description.as_str();
+    }
+
+    /// Error object: the third argument of fn:error(), if given.
+    ///
+    pub fn value(&self) -> Option<&Sequence> {
+        return self.value.as_ref();
+    }
+}
+
+// =====================================================================
+//
+#[derive(Debug, PartialEq, Clone)]
+/// Position of a syntax error in XPath, and what was expected there.
+///
+/// Offset and length are counted in characters (not bytes) from the
+/// beginning of the xpath string, so that the range
+/// <em>offset .. offset + length</em> can be underlined as is.
+///
+pub struct SyntaxErrorInfo {
+    offset: usize,
+    length: usize,
+    expected: Vec<String>,
+}
+
+impl SyntaxErrorInfo {
+    /// Offset of the offending token, in characters.
+    ///
+    pub fn offset(&self) -> usize {
+        return self.offset;
+    }
+
+    /// Length of the offending token, in characters.
+    /// 0 when the error is detected at the end of xpath.
+    ///
+    pub fn length(&self) -> usize {
+        return self.length;
+    }
+
+    /// Tokens or grammar constructs that would have been accepted
+    /// at the position. May be empty.
+    ///
+    pub fn expected(&self) -> &Vec<String> {
+        return &self.expected;
+    }
 }
 
 impl fmt::Display for XmlError {
@@ -55,6 +199,34 @@ impl Error for XmlError {
     }
 }
 
+// =====================================================================
+//
+#[derive(Debug, PartialEq, Clone, Copy)]
+/// Position of a syntax error in XML.
+///
+/// Line and column are counted from 1, and the column in characters
+/// (not bytes); the position is where the parser detected the error,
+/// usually just after the offending markup.
+///
+pub struct XmlPosition {
+    line: usize,
+    column: usize,
+}
+
+impl XmlPosition {
+    /// Line number, from 1.
+    ///
+    pub fn line(&self) -> usize {
+        return self.line;
+    }
+
+    /// Column number in characters, from 1.
+    ///
+    pub fn column(&self) -> usize {
+        return self.column;
+    }
+}
+
 // =====================================================================
 //
 pub fn xmlerror(error_type: XmlErrorType, descri: &str) -> Box<XmlError> {
@@ -70,9 +242,60 @@ pub fn xmlerror(error_type: XmlErrorType, descri: &str) -> Box<XmlError> {
     return Box::new(XmlError {
         error_type: error_type,
         descri: format!("{} {}", prefix, descri),
+        syntax_info: None,
+        user_error: None,
+        position: None,
     });
 }
 
+// ---------------------------------------------------------------------
+// fn:error() による動的エラー。
+// 文言は「説明 [誤りコード]」とする (組み込みのエラーの [err:XXXX] と同様)。
+//
+pub fn user_error(code: &str, description: &str,
+            value: Option<Sequence>) -> Box<XmlError> {
+
+    let mut err = xmlerror(XmlErrorType::DynamicError,
+            &format!("{} [{}]", description, code));
+    err.user_error = Some(UserError {
+        code: String::from(code),
+        description: String::from(description),
+        value: value,
+    });
+    return err;
+}
+
+// ---------------------------------------------------------------------
+// 位置情報つきのXPath構文エラー。
+// 文言は、既定では英語のen、feature "ja-messages" 指定時は日本語のja。
+//
+pub fn xpath_syntax_error_at(offset: usize, length: usize,
+            expected: &[&str], en: &str, ja: &str) -> Box<XmlError> {
+
+    let descri = if cfg!(feature = "ja-messages") { ja } else { en };
+    let mut err = xmlerror(XmlErrorType::XPathSyntaxError, descri);
+    err.syntax_info = Some(SyntaxErrorInfo {
+        offset: offset,
+        length: length,
+        expected: expected.iter().map(|s| String::from(*s)).collect(),
+    });
+    return err;
+}
+
+// ---------------------------------------------------------------------
+// 位置情報つきのXML構文エラー。
+// 文言の末尾に「(line 行, column 桁)」を付ける。
+//
+pub fn xml_syntax_error_at(line: usize, column: usize, descri: &str) -> Box<XmlError> {
+    let mut err = xmlerror(XmlErrorType::XmlSyntaxError,
+            &format!("{} (line {}, column {})", descri, line, column));
+    err.position = Some(XmlPosition {
+        line: line,
+        column: column,
+    });
+    return err;
+}
+
 // ---------------------------------------------------------------------
 //
 macro_rules! cant_occur {
@@ -100,6 +323,7 @@ macro_rules! xml_syntax_error {
     }
 }
 
+#[allow(unused_macros)]
 macro_rules! xpath_syntax_error {
     (
         $( $e:expr ),*
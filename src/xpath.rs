@@ -14,6 +14,9 @@
 //! <strong>each_node()</strong>, <strong>get_first_node()</strong>,
 //! <strong>get_nodeset()</strong>
 //! accept xpath as argument.
+//! <strong>evaluate_standalone()</strong> evaluates xpath without
+//! a document, with any item (atomic value, map, array) as the
+//! context item, and with variables bound.
 //!
 //! cf. <a href="../dom/index.html">Module amxml::dom</a> -&gt; <a href="../dom/struct.NodePtr.html">Struct NodePtr</a> -&gt; <a href="../dom/struct.NodePtr.html#methods">Methods</a>.
 //!
@@ -23,13 +26,33 @@
 //!
 //! Japanese translation of the document above: <a href="https://www.amris.co.jp/xp/xpath31.html">XML Path Language (XPath) 3.1: 日本語訳</a>
 //!
+//! For legacy xpaths, EvalOptions#spec_level(SpecLevel::XPath10)
+//! restricts the syntax and functions to those of XPath 1.0,
+//! and converts the operands as XPath 1.0 does.
+//!
 //! ### Notes
 //!
 //! This processor supports only some of atomic types:
-//! String, Integer, Decimal, Double, Boolean.
+//! String, anyURI, Integer, Decimal, Double, Boolean, hexBinary,
+//! base64Binary, dateTime.
+//! Values are cast between these types (and to xs:untypedAtomic)
+//! as the specification defines: a string that is not a valid lexical
+//! form of the target type is error [err:FORG0001],
+//! NaN or infinity cast to xs:decimal or xs:integer is [err:FOCA0002],
+//! and a pair of types that can't be cast (e.g. xs:boolean to
+//! xs:dateTime) is [err:XPTY0004].
+//! Arguments of built-in functions are cast in the same way.
+//! xs:QName is not implemented.
 //! Features related to 'Type' is restrictive, since this processor
 //! does not refer xml schema.
 //!
+//! Prefixed names in name tests are matched lexically, i.e. the prefix
+//! in the xpath must be the same as in the document,
+//! while URIQualifiedName such as <em>Q{http://example.com}item</em>
+//! is matched by the namespace URI.
+//! URIQualifiedName is also accepted for functions, types and variables;
+//! the namespaces of fn, math, map, array and xs are known.
+//!
 //! Internally both Decimal and Double are implemented with type f64,
 //! but there are some difference.
 //! Decimal division <em>5.0 div 0.0</em> is error (division by zero),
@@ -37,39 +60,106 @@
 //!
 //! ### Built-in functions that are implemented
 //!
-//! - nilled, string, data
-//! - abs, ceiling, floor, round
+//! - nilled, string, data, base-uri, document-uri
+//! - trace
+//! - abs, ceiling, floor, round, round-half-to-even
+//! - math:pi, math:exp, math:exp10, math:log, math:log10, math:pow, math:sqrt, math:sin, math:cos, math:tan, math:asin, math:acos, math:atan, math:atan2
+//! - random-number-generator
 //! - codepoints-to-string, string-to-codepoints
 //! - compare, codepoint-equal
-//! - concat, string-join, substring, string-length, normalize-space, upper-case, lower-case, translate
+//! - concat, string-join, substring, string-length, normalize-space, normalize-unicode, upper-case, lower-case, translate
 //! - contains, starts-with, ends-with, substring-before, substring-after
+//! - resolve-uri
+//! - parse-ietf-date
 //! - true, false
 //! - not
-//! - name, local-name, namespace-uri, number, lang, root
-//! - boolean, index-of
+//! - name, local-name, namespace-uri, number, lang, root, has-children
+//! - siblings (XPath 4.0)
+//! - boolean, distinct-values, index-of
 //! - empty, exists, head, tail, insert-before, remove, reverse, subsequence
 //! - zero-or-one, one-or-more, exactly-one
+//! - error
 //! - count, avg, max, min, sum
-//! - position, last
-//! - for-each, filter
+//! - id (by xml:id attributes)
+//! - doc, doc-available, unparsed-text, unparsed-text-available, collection
+//! - position, last, static-base-uri
+//! - function-lookup, function-name, function-arity
+//! - for-each, filter, apply
 //! - map:size, map:keys, map:contains, map:get
 //! - array:size, array:get, array:flatten
 //!
+//! fn:doc() and the like fetch the resources with the handlers
+//! registered by EvalOptions#uri_scheme(); with the feature
+//! <code>async</code>, NodePtr#eval_xpath_async() fetches them with
+//! AsyncDocumentResolver instead.
+//! Relative URIs are resolved against the static base URI set by
+//! EvalOptions#base_uri(), and then mapped by the XML catalog set by
+//! EvalOptions#catalog(), if any.
+//! fn:collection() returns the nodes registered by
+//! EvalOptions#add_collection().
+//!
+//! fn:random-number-generator uses SplitMix64, so the same seed
+//! always gives the same numbers and permutations;
+//! without a seed, the current time is used.
+//!
+//! fn:normalize-unicode normalizes non-ASCII strings only with the
+//! feature <code>unicode-normalization</code>.
+//!
+//! fn:string-length counts characters (Unicode code points).
+//! To count grapheme clusters (user-perceived characters, such as
+//! a letter with combining marks or an emoji sequence), use
+//! am:grapheme-length, which counts non-ASCII strings only with the
+//! feature <code>unicode-segmentation</code>.
+//!
+//! ### Extension functions
+//!
+//! - am:group-by(seq, key-fn): map from each key to the items that yield it
+//! - am:group-adjacent(seq, key-fn): array of runs of adjacent items with the same key
+//! - am:parse-rfc3339(string): xs:dateTime of an RFC 3339 timestamp, parsed more leniently than casting
+//! - am:parse-html(string): document node of the string parsed as HTML-ish document, in the lenient mode of dom::new_html_document()
+//! - am:random-permute(seq, seed): items of seq in a pseudo-random order that is reproducible for the same seed
+//! - am:put-in(target, keys-array, value): copy of nested maps/arrays with the value at the key path replaced
+//! - am:update(target, key, fn): copy of a map/array with fn applied to the value at key
+//! - am:child-count(node), am:child-element-count(node): number of child nodes/elements, counted without building the list of children
+//! - am:grapheme-length(string): number of extended grapheme clusters
+//! - am:evaluate(xpath), am:evaluate(xpath, context): result of evaluating the string as an xpath (only with EvalOptions#allow_evaluate(true))
+//!
 //! ### Features that are not implemented yet
 //!
 //! - treat as
-//! - KindTest: SchemaElementTest | SchemaAttributeTest | DocumentTest
+//! - KindTest: SchemaElementTest | SchemaAttributeTest
 //! - Many built-in functions that are new in XPath 2.0 and above
-//! - Collation (in built-in functions: contains, starts-with, etc.)
-//! - XPath 1.0 compatible mode
+//! - Collation in built-in functions other than compare, index-of,
+//!   distinct-values, max, min (e.g. contains, starts-with)
 //! - namespace axis (deprecated as of XPath 2.0)
 //!
 
+use std::cmp::Ordering;
+use std::convert::TryFrom;
 use std::error::Error;
-
+use std::fmt;
+#[cfg(feature = "async")]
+use std::future::Future;
+#[cfg(feature = "async")]
+use std::pin::Pin;
+use std::rc::Rc;
+#[cfg(feature = "async")]
+use std::task::{Context, Poll};
+#[cfg(feature = "no_std")]
+use std::prelude::*;
+
+use catalog::Catalog;
 use dom::*;
+use xmlerror::*;
+use xpath_impl::cast::parse_xs_double;
+use xpath_impl::batch::*;
+use xpath_impl::collation::*;
+use xpath_impl::resolver::*;
 use xpath_impl::parser::*;
 use xpath_impl::eval::*;
+use xpath_impl::optimizer::*;
+#[cfg(not(feature = "no_std"))]
+use xpath_impl::parallel::*;
 use xpath_impl::xitem::*;
 use xpath_impl::xsequence::*;
 
@@ -95,6 +185,8 @@ impl NodePtr {
     /// # Errors
     ///
     /// - When syntax error or unimplemented feature in xpath.
+    /// - When the xpath is nested too deeply (or recursion of functions is
+    ///   too deep) to be compiled or evaluated within the stack.
     ///
     pub fn eval_xpath(&self, xpath: &str) -> Result<Sequence, Box<Error>> {
         let xnode = compile_xpath(&String::from(xpath))?;
@@ -102,12 +194,114 @@ impl NodePtr {
         return Ok(new_sequence(&result));
     }
 
+    // =================================================================
+    // 評価方法の選択肢を指定して、xpathを評価する。
+    /// Evaluates the xpath with options and returns the sequence.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use amxml::dom::*;
+    /// use amxml::xpath::EvalOptions;
+    /// let xml = r#"<root><b v="2"/><a v="1"/><c v="3"/></root>"#;
+    /// let doc = new_document(xml).unwrap();
+    /// let options = EvalOptions::new().parallel(true);
+    /// let result = doc.eval_xpath_with_options("//a | //b | //c", &options).unwrap();
+    /// assert_eq!(result.to_string(), r#"(<b v="2">, <a v="1">, <c v="3">)"#);
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// - When syntax error or unimplemented feature in xpath.
+    ///
+    pub fn eval_xpath_with_options(&self, xpath: &str,
+                options: &EvalOptions) -> Result<Sequence, Box<Error>> {
+        let xpath10 = options.spec_level == SpecLevel::XPath10;
+        #[cfg(not(feature = "no_std"))]
+        {
+            if options.parallel && options.trace_listener.is_none() &&
+               ! options.collations.has_user_collations() &&
+               ! options.resolvers.has_handlers() && options.base_uri.is_none() &&
+               ! xpath10 && ! options.allow_evaluate {
+                if let Some(result) = match_xpath_parallel(self, &String::from(xpath))? {
+                    return Ok(new_sequence(&result));
+                }
+            }
+        }
+        let xnode = compile_xpath(&String::from(xpath))?;
+        if xpath10 {
+            check_xpath10_syntax(&xnode)?;
+        }
+        let result = match_xpath_with_dynamic_evaluation(self, &xnode,
+                        options.trace_listener.clone(), &options.collations,
+                        &options.resolvers, options.base_uri.clone(), xpath10,
+                        options.allow_evaluate)?;
+        return Ok(new_sequence(&result));
+    }
+
+    // =================================================================
+    // 資源を非同期に取得して、xpathを評価する。
+    /// Evaluates the xpath, fetching the resources for fn:doc(),
+    /// fn:doc-available(), fn:unparsed-text() and
+    /// fn:unparsed-text-available() with the resolver asynchronously
+    /// (with the feature <code>async</code>).
+    ///
+    /// The resources whose URIs are given as string literals are
+    /// fetched concurrently before the evaluation, which itself does
+    /// not block; URIs computed during the evaluation can't be fetched
+    /// [err:FODC0002].
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// struct HttpResolver { client: reqwest::Client }
+    /// impl AsyncDocumentResolver for HttpResolver {
+    ///     fn fetch(&self, uri: &str) -> Pin<Box<Future<Output = Result<String, Box<Error>>>>> {
+    ///         let request = self.client.get(uri).send();
+    ///         return Box::pin(async move {
+    ///             Ok(request.await?.text().await?)
+    ///         });
+    ///     }
+    /// }
+    /// let result = doc.eval_xpath_async(
+    ///         r#"doc("https://example.com/feed.xml")//item/title"#, &resolver).await?;
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// - When syntax error or unimplemented feature in xpath.
+    /// - When the resource is not available (when used).
+    ///
+    #[cfg(feature = "async")]
+    pub fn eval_xpath_async<R>(&self, xpath: &str, resolver: &R) -> EvalXPathAsync
+                where R: AsyncDocumentResolver {
+        let mut future = EvalXPathAsync{
+            node: self.rc_clone(),
+            xnode: None,
+            error: None,
+            pending: vec!{},
+            fetched: vec!{},
+        };
+        match compile_xpath(&String::from(xpath)) {
+            Ok(xnode) => {
+                for uri in collect_literal_uris(&xnode).into_iter() {
+                    let fetch = resolver.fetch(&uri);
+                    future.pending.push((uri, fetch));
+                }
+                future.xnode = Some(xnode);
+            },
+            Err(e) => future.error = Some(e),
+        }
+        return future;
+    }
+
     // =================================================================
     // XML構文木のあるノードを起点として、xpathに合致するノード集合を取得し、
     // その最初のノードを返す。
     /// Retrieves the first node that match with xpath.
     /// Returns None if not found,
     /// or when syntax error or unimplemented feature in xpath.
+    /// The search stops at the first match, as select_first().
     ///
     /// # Examples
     ///
@@ -124,15 +318,131 @@ impl NodePtr {
     /// - When syntax error or unimplemented feature in xpath.
     ///
     pub fn get_first_node(&self, xpath: &str) -> Option<NodePtr> {
-        let node_set_array = match self.get_nodeset(xpath) {
-            Ok(n) => n,
-            Err(_) => return None,
-        };
-        if node_set_array.len() != 0 {
-            return Some(node_set_array[0].rc_clone());
-        } else {
-            return None;
+        return self.select_first(xpath).unwrap_or(None);
+    }
+
+    // =================================================================
+    // XML構文木のあるノードを起点として、xpathに合致する最初のノードを
+    // 取得する。見つかった時点で探索を打ち切る。
+    /// Retrieves the first node (in document order) that matches with
+    /// xpath, or None if not found.
+    ///
+    /// Unlike get_nodeset(), the evaluation stops as soon as the
+    /// node is found, for xpaths such as <code>//item[@id = 'x']</code>,
+    /// <code>/root/item</code>, <code>descendant::item</code>:
+    /// paths whose steps are on the child, descendant or
+    /// descendant-or-self axis, with predicates that don't depend
+    /// on the context position (comparisons, and / or, not(), etc.;
+    /// not <code>[1]</code> nor <code>[last()]</code>).
+    /// Other xpaths are evaluated entirely, with the same result.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use amxml::dom::*;
+    /// let xml = r#"<root><a id="1" v="x"/><a id="2" v="y"/><a id="3" v="y"/></root>"#;
+    /// let doc = new_document(xml).unwrap();
+    /// let node = doc.select_first("//a[@v = 'y']").unwrap().unwrap();
+    /// assert_eq!(node.attribute_value("id").unwrap(), "2");
+    /// assert!(doc.select_first("//b").unwrap().is_none());
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// - When syntax error or unimplemented feature in xpath.
+    ///
+    pub fn select_first(&self, xpath: &str) -> Result<Option<NodePtr>, Box<Error>> {
+        let mut nodeset = self.select_up_to(xpath, 1)?;
+        return Ok(nodeset.pop());
+    }
+
+    // =================================================================
+    // XML構文木のあるノードを起点として、xpathに合致するノードを
+    // 文書順に最大n個取得する。n個見つかった時点で探索を打ち切る。
+    /// Retrieves at most n nodes that match with xpath, in document
+    /// order: the first n nodes of get_nodeset().
+    /// The evaluation stops as soon as n nodes are found,
+    /// for the xpaths described in select_first().
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use amxml::dom::*;
+    /// let xml = r#"<root><a id="1"/><b><a id="2"/></b><a id="3"/></root>"#;
+    /// let doc = new_document(xml).unwrap();
+    /// let nodeset = doc.select_up_to("//a", 2).unwrap();
+    /// assert_eq!(nodeset.len(), 2);
+    /// assert_eq!(nodeset[1].attribute_value("id").unwrap(), "2");
+    /// assert_eq!(doc.select_up_to("//a[@id != '1']", 5).unwrap().len(), 2);
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// - When syntax error or unimplemented feature in xpath.
+    ///
+    pub fn select_up_to(&self, xpath: &str, n: usize) -> Result<Vec<NodePtr>, Box<Error>> {
+        let xnode = compile_xpath(&String::from(xpath))?;
+        if n == 0 {
+            return Ok(vec!{});
         }
+        let result = match_xpath_with_item_limit(self, &xnode, n)?;
+        return Ok(result.to_nodeset());
+    }
+
+    // =================================================================
+    // xpathに合致するノードのうち、文書順で offset 個を読み飛ばし、
+    // 続く最大 limit 個を取得する。
+    /// Retrieves a page of the nodes that match with xpath: skips
+    /// offset nodes in document order and returns at most limit nodes
+    /// that follow, i.e. get_nodeset()[offset .. offset + limit].
+    ///
+    /// As select_up_to(), the evaluation stops as soon as the nodes up
+    /// to the page, and one more to tell whether the next page exists,
+    /// are found; so the total number of the matching nodes is known
+    /// only as a lower bound, unless the evaluation reaches the end.
+    /// cf. Page#total()
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use amxml::dom::*;
+    /// use amxml::xpath::TotalCount;
+    /// let xml = r#"<root><a id="1"/><a id="2"/><a id="3"/><a id="4"/><a id="5"/></root>"#;
+    /// let doc = new_document(xml).unwrap();
+    ///
+    /// let page = doc.select_paged("//a", 2, 2).unwrap();
+    /// let ids: Vec<String> = page.nodes().iter().map(|n| n.attribute_value("id").unwrap()).collect();
+    /// assert_eq!(ids, vec!{"3", "4"});
+    /// assert!(page.has_next());
+    /// assert_eq!(page.total(), TotalCount::AtLeast(5));
+    ///
+    /// let page = doc.select_paged("//a", 4, 2).unwrap();
+    /// assert_eq!(page.nodes().len(), 1);
+    /// assert!(! page.has_next());
+    /// assert_eq!(page.total(), TotalCount::Exact(5));
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// - When syntax error or unimplemented feature in xpath.
+    ///
+    pub fn select_paged(&self, xpath: &str,
+                offset: usize, limit: usize) -> Result<Page, Box<Error>> {
+        let xnode = compile_xpath(&String::from(xpath))?;
+        let n = offset.saturating_add(limit).saturating_add(1);
+        let result = match_xpath_with_item_hint(self, &xnode, n)?;
+        let nodeset = result.to_nodeset();
+        let total = if nodeset.len() < n {
+            TotalCount::Exact(nodeset.len())
+        } else {
+            TotalCount::AtLeast(nodeset.len())
+        };
+        let nodes: Vec<NodePtr> = nodeset.into_iter().skip(offset).take(limit).collect();
+        return Ok(Page{
+            nodes: nodes,
+            offset: offset,
+            total: total,
+        });
     }
 
     // =================================================================
@@ -167,115 +477,1791 @@ impl NodePtr {
         return Ok(());
     }
 
-    // =================================================================
-    // XML構文木のあるノードを起点として、xpathに合致するノード集合を
-    // 文書順で取得する。
-    /// Retrieves all nodes that match with xpath in document order.
-    ///
-    /// # Examples
+    // =================================================================
+    // XML構文木のあるノードを起点として、xpathに合致するノード集合を
+    // 文書順で取得する。
+    /// Retrieves all nodes that match with xpath in document order.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use amxml::dom::*;
+    /// let xml = r#"<root img="basic"><a img="a1"/><a img="a2"/></root>"#;
+    /// let doc = new_document(xml).unwrap();
+    /// let nodeset = doc.get_nodeset("//a").unwrap();
+    /// assert_eq!(nodeset[0].attribute_value("img").unwrap(), "a1");
+    /// assert_eq!(nodeset[1].attribute_value("img").unwrap(), "a2");
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// - When syntax error or unimplemented feature in xpath.
+    ///
+    pub fn get_nodeset(&self, xpath: &str) -> Result<Vec<NodePtr>, Box<Error>> {
+        let xnode = compile_xpath(&String::from(xpath))?;
+
+        let result = match_xpath(self, &xnode)?;
+
+        let nodeset = result.to_nodeset();
+        return Ok(nodeset);
+    }
+
+    // =================================================================
+    /// Evaluates the xpath and returns the string value of the result,
+    /// or None if the result is the empty sequence.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use amxml::dom::*;
+    /// let xml = r#"<root><a id="a1">x<b>y</b></a></root>"#;
+    /// let doc = new_document(xml).unwrap();
+    /// assert_eq!(doc.query_string("//a/@id").unwrap(), Some(String::from("a1")));
+    /// assert_eq!(doc.query_string("//a").unwrap(), Some(String::from("xy")));
+    /// assert_eq!(doc.query_string("//a/@title").unwrap(), None);
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// - When syntax error or unimplemented feature in xpath.
+    /// - When the result has two or more items.
+    /// - When the item is a map, an array or a function.
+    ///
+    pub fn query_string(&self, xpath: &str) -> Result<Option<String>, Box<Error>> {
+        return match self.query_singleton(xpath)? {
+            Some(xitem) => Ok(Some(xitem.get_as_raw_string()?)),
+            None => Ok(None),
+        };
+    }
+
+    // =================================================================
+    /// Evaluates the xpath and returns the result as i64,
+    /// or None if the result is the empty sequence.
+    /// A node (or string) is converted if its value (with leading and
+    /// trailing white space trimmed) is an integer literal;
+    /// a decimal or double is converted if it has no fractional part.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use amxml::dom::*;
+    /// let xml = r#"<root><a n=" 42 "/><a n="4.2"/></root>"#;
+    /// let doc = new_document(xml).unwrap();
+    /// assert_eq!(doc.query_i64("//a[1]/@n").unwrap(), Some(42));
+    /// assert_eq!(doc.query_i64("count(//a)").unwrap(), Some(2));
+    /// assert_eq!(doc.query_i64("//b").unwrap(), None);
+    /// assert!(doc.query_i64("//a[2]/@n").is_err());
+    /// assert!(doc.query_i64("//a/@n").is_err());
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// - When syntax error or unimplemented feature in xpath.
+    /// - When the result has two or more items.
+    /// - When the item can't be converted to integer.
+    ///
+    pub fn query_i64(&self, xpath: &str) -> Result<Option<i64>, Box<Error>> {
+        let xitem = match self.query_singleton(xpath)? {
+            Some(xitem) => xitem.atomize(),
+            None => return Ok(None),
+        };
+        let value = match xitem {
+            XItem::XIString{ref value} => value.trim().parse::<i64>().ok(),
+            XItem::XIInteger{value} => Some(value),
+            XItem::XIDecimal{value} | XItem::XIDouble{value} => {
+                if value.fract() == 0.0 &&
+                   (i64::MIN as f64) <= value && value < (i64::MAX as f64) {
+                    Some(value as i64)
+                } else {
+                    None
+                }
+            },
+            _ => None,
+        };
+        return match value {
+            Some(value) => Ok(Some(value)),
+            None => Err(type_error!("Item {}: can't convert to integer", xitem.to_string())),
+        };
+    }
+
+    // =================================================================
+    /// Evaluates the xpath and returns the result as f64,
+    /// or None if the result is the empty sequence.
+    /// A node (or string) is converted if its value (with leading and
+    /// trailing white space trimmed) is a numeric literal,
+    /// or one of "INF", "-INF", "NaN".
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use amxml::dom::*;
+    /// let xml = r#"<root><a v="1.5"/><a v="-INF"/><a v="abc"/></root>"#;
+    /// let doc = new_document(xml).unwrap();
+    /// assert_eq!(doc.query_f64("//a[1]/@v").unwrap(), Some(1.5));
+    /// assert_eq!(doc.query_f64("//a[2]/@v").unwrap(), Some(std::f64::NEG_INFINITY));
+    /// assert_eq!(doc.query_f64("sum(//a[1]/@v) * 2").unwrap(), Some(3.0));
+    /// assert!(doc.query_f64("//a[3]/@v").is_err());
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// - When syntax error or unimplemented feature in xpath.
+    /// - When the result has two or more items.
+    /// - When the item can't be converted to number.
+    ///
+    pub fn query_f64(&self, xpath: &str) -> Result<Option<f64>, Box<Error>> {
+        let xitem = match self.query_singleton(xpath)? {
+            Some(xitem) => xitem.atomize(),
+            None => return Ok(None),
+        };
+        let value = match xitem {
+            XItem::XIString{ref value} => parse_xs_double(value.trim()),
+            XItem::XIInteger{..} | XItem::XIDecimal{..} | XItem::XIDouble{..} =>
+                xitem.get_as_raw_double().ok(),
+            _ => None,
+        };
+        return match value {
+            Some(value) => Ok(Some(value)),
+            None => Err(type_error!("Item {}: can't convert to number", xitem.to_string())),
+        };
+    }
+
+    // =================================================================
+    /// Evaluates the xpath and returns the result as bool,
+    /// or None if the result is the empty sequence.
+    /// A node (or string) is converted if its value (with leading and
+    /// trailing white space trimmed) is "true", "false", "1" or "0";
+    /// a number is true unless it is zero or NaN.
+    ///
+    /// Note that, unlike Sequence#as_bool(), this is not the effective
+    /// boolean value: an element &lt;a&gt;false&lt;/a&gt; is false.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use amxml::dom::*;
+    /// let xml = r#"<root><a on="true"/><a on=" 0 "/><a on="yes"/></root>"#;
+    /// let doc = new_document(xml).unwrap();
+    /// assert_eq!(doc.query_bool("//a[1]/@on").unwrap(), Some(true));
+    /// assert_eq!(doc.query_bool("//a[2]/@on").unwrap(), Some(false));
+    /// assert_eq!(doc.query_bool("count(//a) = 3").unwrap(), Some(true));
+    /// assert!(doc.query_bool("//a[3]/@on").is_err());
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// - When syntax error or unimplemented feature in xpath.
+    /// - When the result has two or more items.
+    /// - When the item can't be converted to boolean.
+    ///
+    pub fn query_bool(&self, xpath: &str) -> Result<Option<bool>, Box<Error>> {
+        let xitem = match self.query_singleton(xpath)? {
+            Some(xitem) => xitem.atomize(),
+            None => return Ok(None),
+        };
+        let xitem = match xitem {
+            XItem::XIString{ref value} => new_xitem_string(value.trim()),
+            _ => xitem,
+        };
+        return match xitem.get_as_raw_boolean() {
+            Ok(value) => Ok(Some(value)),
+            Err(_) => Err(type_error!("Item {}: can't convert to boolean", xitem.to_string())),
+        };
+    }
+
+    // =================================================================
+    /// Evaluates the xpath and returns the resulting node,
+    /// or None if the result is the empty sequence.
+    /// Unlike get_first_node(), the result must not have two or more
+    /// nodes, and errors are reported.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use amxml::dom::*;
+    /// let xml = r#"<root><a id="a1"/><a id="a2"/></root>"#;
+    /// let doc = new_document(xml).unwrap();
+    /// let a2 = doc.query_node("//a[@id='a2']").unwrap().unwrap();
+    /// assert_eq!(a2.attribute_value("id").unwrap(), "a2");
+    /// assert!(doc.query_node("//b").unwrap().is_none());
+    /// assert!(doc.query_node("//a").is_err());
+    /// assert!(doc.query_node("count(//a)").is_err());
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// - When syntax error or unimplemented feature in xpath.
+    /// - When the result has two or more items.
+    /// - When the item is not a node.
+    ///
+    pub fn query_node(&self, xpath: &str) -> Result<Option<NodePtr>, Box<Error>> {
+        return match self.query_singleton(xpath)? {
+            Some(xitem) => match xitem.as_nodeptr() {
+                Some(node) => Ok(Some(node)),
+                None => Err(type_error!("Item {}: not a node", xitem.to_string())),
+            },
+            None => Ok(None),
+        };
+    }
+
+    // =================================================================
+    /// Tests whether the node matches the xpath as a pattern
+    /// (as in the match attribute of XSLT templates);
+    /// see XPath#matches_node().
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use amxml::dom::*;
+    /// let xml = r#"<root><sec><p id="1"/></sec><p id="2"/></root>"#;
+    /// let doc = new_document(xml).unwrap();
+    /// let p1 = doc.get_first_node("//p[@id='1']").unwrap();
+    /// assert!(p1.matches("sec/p").unwrap());
+    /// assert!(p1.matches("p[@id = '1']").unwrap());
+    /// assert!(! p1.matches("root/p").unwrap());
+    /// assert!(p1.matches("sec[").is_err());
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// - When syntax error or unimplemented feature in xpath.
+    /// - When the evaluation fails.
+    ///
+    pub fn matches(&self, xpath: &str) -> Result<bool, Box<Error>> {
+        let xnode = compile_xpath(&String::from(xpath))?;
+        return match_pattern(self, &xnode);
+    }
+
+    // =================================================================
+    /// Returns the nearest ancestor-or-self of the node that matches
+    /// the xpath as a pattern, like closest() of the HTML DOM;
+    /// or None if there is no such node.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use amxml::dom::*;
+    /// let xml = r#"<root><sec id="s1"><sec id="s2"><p/></sec></sec></root>"#;
+    /// let doc = new_document(xml).unwrap();
+    /// let p = doc.get_first_node("//p").unwrap();
+    /// let sec = p.closest("sec").unwrap().unwrap();
+    /// assert_eq!(sec.attribute_value("id").unwrap(), "s2");
+    /// let sec = p.closest("root/sec").unwrap().unwrap();
+    /// assert_eq!(sec.attribute_value("id").unwrap(), "s1");
+    /// assert_eq!(p.closest("p").unwrap().unwrap(), p);
+    /// assert!(p.closest("table").unwrap().is_none());
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// - When syntax error or unimplemented feature in xpath.
+    /// - When the evaluation fails.
+    ///
+    pub fn closest(&self, xpath: &str) -> Result<Option<NodePtr>, Box<Error>> {
+        let xnode = compile_xpath(&String::from(xpath))?;
+        let mut curr = Some(self.rc_clone());
+        while let Some(node) = curr {
+            if match_pattern(&node, &xnode)? {
+                return Ok(Some(node));
+            }
+            curr = node.parent();
+        }
+        return Ok(None);
+    }
+
+    // -----------------------------------------------------------------
+    // xpathを評価し、結果が空シーケンスならばNone、
+    // 項目がひとつならばその項目を返す。2つ以上ならばエラー。
+    //
+    fn query_singleton(&self, xpath: &str) -> Result<Option<XItem>, Box<Error>> {
+        let xnode = compile_xpath(&String::from(xpath))?;
+        let result = match_xpath(self, &xnode)?;
+        match result.len() {
+            0 => return Ok(None),
+            1 => return Ok(Some(result.get_item(0).clone())),
+            n => return Err(type_error!(
+                    "{}: {} items, while at most one is expected", xpath, n)),
+        }
+    }
+}
+
+// =====================================================================
+/// Page of the nodes retrieved by NodePtr#select_paged().
+///
+#[derive(Debug, Clone, PartialEq)]
+pub struct Page {
+    nodes: Vec<NodePtr>,
+    offset: usize,
+    total: TotalCount,
+}
+
+// =====================================================================
+/// Total number of the matching nodes, as far as known by
+/// NodePtr#select_paged().
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TotalCount {
+    /// The evaluation reached the end: exactly this number.
+    Exact(usize),
+    /// The evaluation stopped early: this number or more.
+    AtLeast(usize),
+}
+
+impl Page {
+    // =================================================================
+    /// Nodes of the page, in document order.
+    ///
+    pub fn nodes(&self) -> &Vec<NodePtr> {
+        return &self.nodes;
+    }
+
+    // =================================================================
+    /// Takes the nodes of the page.
+    ///
+    pub fn into_nodes(self) -> Vec<NodePtr> {
+        return self.nodes;
+    }
+
+    // =================================================================
+    /// Number of the nodes skipped before the page.
+    ///
+    pub fn offset(&self) -> usize {
+        return self.offset;
+    }
+
+    // =================================================================
+    /// Total number of the matching nodes (including those of other
+    /// pages).
+    ///
+    pub fn total(&self) -> TotalCount {
+        return self.total;
+    }
+
+    // =================================================================
+    /// Whether there are matching nodes after the page.
+    ///
+    pub fn has_next(&self) -> bool {
+        let total = match self.total {
+            TotalCount::Exact(n) | TotalCount::AtLeast(n) => n,
+        };
+        return self.offset + self.nodes.len() < total;
+    }
+}
+
+// =====================================================================
+/// Level of the XPath specification, for EvalOptions#spec_level().
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SpecLevel {
+    /// XPath 1.0: 1.0 syntax and functions only, with the
+    /// type conversions of XPath 1.0 (XPath 1.0 compatibility mode).
+    XPath10,
+    /// XPath 3.1 (default).
+    XPath31,
+}
+
+impl Default for SpecLevel {
+    fn default() -> SpecLevel {
+        return SpecLevel::XPath31;
+    }
+}
+
+// =====================================================================
+/// Options for NodePtr#eval_xpath_with_options().
+///
+#[derive(Clone, Default)]
+pub struct EvalOptions {
+    parallel: bool,
+    trace_listener: Option<TraceListener>,
+    collations: CollationRegistry,
+    resolvers: ResolverRegistry,
+    base_uri: Option<String>,
+    spec_level: SpecLevel,
+    allow_evaluate: bool,
+}
+
+impl fmt::Debug for EvalOptions {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        return write!(f, "EvalOptions {{ parallel: {}, trace_listener: {}, collations: {:?}, resolvers: {:?}, base_uri: {:?}, spec_level: {:?}, allow_evaluate: {} }}",
+            self.parallel,
+            if self.trace_listener.is_some() { "Some(..)" } else { "None" },
+            self.collations,
+            self.resolvers,
+            self.base_uri,
+            self.spec_level,
+            self.allow_evaluate);
+    }
+}
+
+impl EvalOptions {
+    // -----------------------------------------------------------------
+    /// Returns the default options: same as NodePtr#eval_xpath().
+    ///
+    pub fn new() -> EvalOptions {
+        return EvalOptions::default();
+    }
+
+    // -----------------------------------------------------------------
+    /// Evaluates the branches of top-level union expression,
+    /// like <code>//a | //b | //c</code>, on separate threads,
+    /// and merges the results in document order.
+    ///
+    /// Since DOM nodes can't be shared between threads,
+    /// the tree is copied once into a snapshot, and each thread builds
+    /// its own tree from it (without serializing or parsing XML).
+    /// This pays off only when the branches are expensive
+    /// compared to copying the document.
+    ///
+    /// Falls back to the ordinary evaluation when the xpath is not
+    /// a top-level union, when any branch fails or returns non-node items,
+    /// or when the document has state that the snapshot doesn't carry:
+    /// annotations (including the types by Schema#validate()),
+    /// named keys, or attribute value indexes.
+    /// With the feature <code>no_std</code>, which has no threads,
+    /// the xpath is always evaluated in the ordinary way.
+    ///
+    pub fn parallel(mut self, parallel: bool) -> EvalOptions {
+        self.parallel = parallel;
+        return self;
+    }
+
+    // -----------------------------------------------------------------
+    /// Installs the listener that receives the output of fn:trace(),
+    /// i.e. the label (empty for fn:trace#1) and the value
+    /// serialized like Sequence#to_string().
+    /// Without the listener, the output goes to the standard error,
+    /// as "label: value".
+    ///
+    /// Since the listener can't be shared between threads,
+    /// parallel(true) is ignored when the listener is installed.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::cell::RefCell;
+    /// use std::rc::Rc;
+    /// use amxml::dom::*;
+    /// use amxml::xpath::EvalOptions;
+    /// let xml = r#"<root><a v="1"/><a v="2"/></root>"#;
+    /// let doc = new_document(xml).unwrap();
+    /// let log = Rc::new(RefCell::new(vec!{}));
+    /// let log_clone = Rc::clone(&log);
+    /// let options = EvalOptions::new().trace_listener(move |label, value| {
+    ///     log_clone.borrow_mut().push(format!("{}={}", label, value));
+    /// });
+    /// let result = doc.eval_xpath_with_options(
+    ///         "count(trace(//a/@v, 'v'))", &options).unwrap();
+    /// assert_eq!(result.to_string(), "2");
+    /// assert_eq!(*log.borrow(), vec!{r#"v=(v="1", v="2")"#});
+    /// ```
+    ///
+    pub fn trace_listener<F>(mut self, listener: F) -> EvalOptions
+                where F: Fn(&str, &str) + 'static {
+        self.trace_listener = Some(Rc::new(listener));
+        return self;
+    }
+
+    // -----------------------------------------------------------------
+    /// Registers the collation for the URI, to be used in the built-in
+    /// functions that take the collation argument: compare, index-of,
+    /// distinct-values, max, min.
+    /// The collation compares two strings.
+    /// The collation already registered for the same URI is replaced.
+    ///
+    /// The following collations are always available:
+    ///
+    /// - <code>http://www.w3.org/2005/xpath-functions/collation/codepoint</code>
+    ///   (default): compares the strings codepoint by codepoint.
+    /// - <code>http://www.w3.org/2005/xpath-functions/collation/html-ascii-case-insensitive</code>:
+    ///   same as above, but ASCII letters are compared case-insensitively.
+    ///
+    /// With feature <code>icu</code>, the collations based on the Unicode
+    /// Collation Algorithm (<code>http://www.w3.org/2013/collation/UCA</code>,
+    /// with parameters such as <code>?lang=de;strength=primary</code>)
+    /// are also available; the parameters <code>lang</code>,
+    /// <code>strength</code>, <code>alternate</code>, <code>caseFirst</code>
+    /// and <code>numeric</code> are supported, and the others are ignored
+    /// unless <code>fallback=no</code>.
+    /// Other collations can be provided by the application with this method,
+    /// e.g. implemented by a collation library.
+    /// Specifying an unregistered collation is an error [err:FOCH0002].
+    ///
+    /// Since the collation can't be shared between threads,
+    /// parallel(true) is ignored when a collation is registered.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use amxml::dom::*;
+    /// use amxml::xpath::EvalOptions;
+    /// let doc = new_document("<root/>").unwrap();
+    /// let options = EvalOptions::new().collation("http://example.com/by-length",
+    ///         |a, b| a.chars().count().cmp(&b.chars().count()));
+    /// let result = doc.eval_xpath_with_options(
+    ///         "max(('ccc', 'bbbbb', 'a'), 'http://example.com/by-length')", &options).unwrap();
+    /// assert_eq!(result.to_string(), r#""bbbbb""#);
+    ///
+    /// let result = doc.eval_xpath_with_options(
+    ///         "distinct-values(('A', 'a', 'b'), 'http://www.w3.org/2005/xpath-functions/collation/html-ascii-case-insensitive')",
+    ///         &options).unwrap();
+    /// assert_eq!(result.to_string(), r#"("A", "b")"#);
+    /// ```
+    ///
+    pub fn collation<F>(mut self, uri: &str, collation: F) -> EvalOptions
+                where F: Fn(&str, &str) -> Ordering + 'static {
+        self.collations.register(uri, Rc::new(collation));
+        return self;
+    }
+
+    // -----------------------------------------------------------------
+    /// Registers the handler for the URI scheme (such as "http" or
+    /// "file"; case-insensitive), to be used in fn:doc(),
+    /// fn:doc-available(), fn:unparsed-text() and
+    /// fn:unparsed-text-available().
+    /// The handler receives the whole URI and returns the content of
+    /// the resource; fn:doc() parses it as XML document.
+    /// The handler already registered for the same scheme is replaced.
+    ///
+    /// No scheme is handled by default, i.e. the resources are not
+    /// accessible unless the application allows it explicitly.
+    /// Relative URIs are resolved against the static base URI set by
+    /// base_uri(); without it, they are errors [err:FODC0005].
+    /// Within an evaluation, fn:doc() returns the same document node
+    /// for the same URI.
+    ///
+    /// Since the handler can't be shared between threads,
+    /// parallel(true) is ignored when a handler is registered.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use amxml::dom::*;
+    /// use amxml::xpath::EvalOptions;
+    /// let doc = new_document("<root/>").unwrap();
+    /// let options = EvalOptions::new().uri_scheme("mem", |uri| {
+    ///     match uri {
+    ///         "mem:books" => Ok(String::from("<books><book/><book/></books>")),
+    ///         "mem:motd" => Ok(String::from("hello")),
+    ///         _ => Err(From::from("not found")),
+    ///     }
+    /// });
+    /// let result = doc.eval_xpath_with_options(
+    ///         r#"count(doc("mem:books")//book), unparsed-text("mem:motd"), doc-available("mem:none")"#,
+    ///         &options).unwrap();
+    /// assert_eq!(result.to_string(), r#"(2, "hello", false)"#);
+    /// ```
+    ///
+    pub fn uri_scheme<F>(mut self, scheme: &str, handler: F) -> EvalOptions
+                where F: Fn(&str) -> Result<String, Box<Error>> + 'static {
+        self.resolvers.register(scheme, Rc::new(handler));
+        return self;
+    }
+
+    // -----------------------------------------------------------------
+    /// Sets the XML catalog, by which fn:doc(), fn:unparsed-text(), etc.
+    /// map the URIs (see Catalog#resolve_uri()) before fetching the
+    /// resources with the handlers registered by uri_scheme().
+    /// The URI of the document returned by fn:doc() is the one before
+    /// mapping.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use amxml::dom::*;
+    /// use amxml::catalog::*;
+    /// use amxml::xpath::EvalOptions;
+    /// let catalog = new_catalog(r#"
+    ///     <catalog xmlns="urn:oasis:names:tc:entity:xmlns:xml:catalog">
+    ///         <rewriteURI uriStartString="http://example.com/" rewritePrefix="mem:"/>
+    ///     </catalog>"#).unwrap();
+    /// let doc = new_document("<root/>").unwrap();
+    /// let options = EvalOptions::new()
+    ///     .uri_scheme("mem", |uri| {
+    ///         match uri {
+    ///             "mem:books.xml" => Ok(String::from("<books><book/><book/></books>")),
+    ///             _ => Err(From::from("not found")),
+    ///         }
+    ///     })
+    ///     .catalog(catalog);
+    /// let result = doc.eval_xpath_with_options(
+    ///         r#"count(doc("http://example.com/books.xml")//book)"#,
+    ///         &options).unwrap();
+    /// assert_eq!(result.to_string(), "2");
+    /// ```
+    ///
+    pub fn catalog(mut self, catalog: Catalog) -> EvalOptions {
+        self.resolvers.set_catalog(catalog);
+        return self;
+    }
+
+    // -----------------------------------------------------------------
+    /// Registers the nodes (typically documents) as the collection
+    /// returned by fn:collection(uri); the empty URI registers the
+    /// default collection, returned by fn:collection().
+    /// Registering the same URI again replaces the collection.
+    ///
+    /// The documents in the collections are also returned by fn:doc()
+    /// for their document URIs (see NodePtr#set_document_uri()).
+    /// Nodes of different documents can be compared
+    /// (<code>is</code>, <code>&lt;&lt;</code>, <code>&gt;&gt;</code>)
+    /// and combined (<code>|</code>, etc.); the order of the documents
+    /// is arbitrary but stable during the evaluation.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use amxml::dom::*;
+    /// use amxml::xpath::EvalOptions;
+    /// let doc1 = new_document(r#"<book title="A"><ch/><ch/></book>"#).unwrap();
+    /// let doc2 = new_document(r#"<book title="B"><ch/></book>"#).unwrap();
+    /// doc2.set_document_uri("urn:corpus/b.xml");
+    /// let options = EvalOptions::new()
+    ///     .add_collection("urn:corpus", vec!{doc1.rc_clone(), doc2.rc_clone()});
+    ///
+    /// let main = new_document("<root/>").unwrap();
+    /// let result = main.eval_xpath_with_options(
+    ///         r#"count(collection("urn:corpus")//ch), doc("urn:corpus/b.xml")/book/@title/string()"#,
+    ///         &options).unwrap();
+    /// assert_eq!(result.to_string(), r#"(3, "B")"#);
+    /// ```
+    ///
+    pub fn add_collection(mut self, uri: &str, nodes: Vec<NodePtr>) -> EvalOptions {
+        self.resolvers.add_collection(uri, nodes);
+        return self;
+    }
+
+    // -----------------------------------------------------------------
+    /// Sets the static base URI, i.e. the value of fn:static-base-uri(),
+    /// against which fn:resolve-uri#1, fn:doc(), etc. resolve
+    /// relative URIs. There is no static base URI by default.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use amxml::dom::*;
+    /// use amxml::xpath::EvalOptions;
+    /// let doc = new_document("<root/>").unwrap();
+    /// let options = EvalOptions::new().base_uri("http://example.com/a/b.xml");
+    /// let result = doc.eval_xpath_with_options(
+    ///         "static-base-uri(), resolve-uri('c.xml')", &options).unwrap();
+    /// assert_eq!(result.to_string(),
+    ///         r#"("http://example.com/a/b.xml", "http://example.com/a/c.xml")"#);
+    /// ```
+    ///
+    pub fn base_uri(mut self, uri: &str) -> EvalOptions {
+        self.base_uri = Some(String::from(uri));
+        return self;
+    }
+
+    // -----------------------------------------------------------------
+    /// Sets the level of the XPath specification; SpecLevel::XPath31
+    /// by default.
+    ///
+    /// With SpecLevel::XPath10, the xpath must consist of the syntax
+    /// and functions of XPath 1.0: sequences, <code>if</code>,
+    /// <code>for</code>, value comparisons, inline functions, maps,
+    /// arrays, etc. are syntax errors [err:XPST0003], and functions
+    /// new in XPath 2.0 and above are not found [err:XPST0017].
+    /// The operands are converted as in XPath 1.0:
+    ///
+    /// - The argument of a function that expects a single item is the
+    ///   first item of the sequence; it is converted with string()
+    ///   if the function expects a string, and number() if it expects
+    ///   a number.
+    /// - The operands of arithmetic operators are converted with
+    ///   number() (so that an empty node-set is NaN).
+    /// - General comparisons with a boolean compare the boolean values,
+    ///   <code>&lt;</code> etc. compare numbers, and <code>=</code>
+    ///   compares numbers if either operand is a number, strings otherwise.
+    ///
+    /// parallel(true) is ignored with SpecLevel::XPath10.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use amxml::dom::*;
+    /// use amxml::xpath::*;
+    /// let doc = new_document(r#"<root><p>10</p><p>20</p></root>"#).unwrap();
+    /// let options = EvalOptions::new().spec_level(SpecLevel::XPath10);
+    /// let result = doc.eval_xpath_with_options(
+    ///         "string-length(//p) = 2 and //p > 15", &options).unwrap();
+    /// assert_eq!(result.to_string(), "true");
+    /// assert!(doc.eval_xpath_with_options("1 to 3", &options).is_err());
+    /// assert!(doc.eval_xpath("string-length(//p)").is_err());
+    /// ```
+    ///
+    pub fn spec_level(mut self, level: SpecLevel) -> EvalOptions {
+        self.spec_level = level;
+        return self;
+    }
+
+    // -----------------------------------------------------------------
+    /// Allows the extension function <code>am:evaluate($xpath)</code> /
+    /// <code>am:evaluate($xpath, $context)</code>, which compiles the
+    /// string <code>$xpath</code> and evaluates it with the variables in
+    /// scope, and with <code>$context</code> as the context item if given.
+    ///
+    /// Disabled by default, since the evaluated string may come from
+    /// the document; am:evaluate() is then a dynamic error.
+    ///
+    /// parallel(true) is ignored when am:evaluate() is allowed.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use amxml::dom::*;
+    /// use amxml::xpath::*;
+    /// let xml = r#"<root><rule test="@v = 'b'"/><p v="a"/><p v="b"/></root>"#;
+    /// let doc = new_document(xml).unwrap();
+    /// let xpath = "for $t in //rule/@test return //p[am:evaluate($t, .)]";
+    /// let options = EvalOptions::new().allow_evaluate(true);
+    /// let result = doc.eval_xpath_with_options(xpath, &options).unwrap();
+    /// assert_eq!(result.to_string(), r#"<p v="b">"#);
+    /// assert!(doc.eval_xpath(xpath).is_err());
+    /// ```
+    ///
+    pub fn allow_evaluate(mut self, allow: bool) -> EvalOptions {
+        self.allow_evaluate = allow;
+        return self;
+    }
+}
+
+// =====================================================================
+/// Resolver that fetches the resources for fn:doc(), fn:unparsed-text(),
+/// etc. asynchronously, for NodePtr#eval_xpath_async()
+/// (with the feature <code>async</code>).
+///
+/// The future may be provided by any async runtime, e.g. an HTTP
+/// client running on tokio.
+///
+#[cfg(feature = "async")]
+pub trait AsyncDocumentResolver {
+    /// Returns the future that yields the content of the resource.
+    fn fetch(&self, uri: &str) -> Pin<Box<Future<Output = Result<String, Box<Error>>>>>;
+}
+
+// =====================================================================
+/// Future returned by NodePtr#eval_xpath_async().
+///
+#[cfg(feature = "async")]
+pub struct EvalXPathAsync {
+    node: NodePtr,
+    xnode: Option<XNodePtr>,
+    error: Option<Box<Error>>,
+    pending: Vec<(String, Pin<Box<Future<Output = Result<String, Box<Error>>>>>)>,
+    fetched: Vec<(String, Result<String, String>)>,
+}
+
+#[cfg(feature = "async")]
+impl Future for EvalXPathAsync {
+    type Output = Result<Sequence, Box<Error>>;
+
+    // -----------------------------------------------------------------
+    // 取得中の資源をすべて待ってから、取得した資源を返す処理函数を
+    // 登録して、同期的に評価する。
+    //
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        if let Some(e) = this.error.take() {
+            return Poll::Ready(Err(e));
+        }
+        let mut i = 0;
+        while i < this.pending.len() {
+            match this.pending[i].1.as_mut().poll(cx) {
+                Poll::Ready(result) => {
+                    let (uri, _) = this.pending.remove(i);
+                    this.fetched.push((uri, result.map_err(|e| e.to_string())));
+                },
+                Poll::Pending => i += 1,
+            }
+        }
+        if ! this.pending.is_empty() {
+            return Poll::Pending;
+        }
+
+        let fetched = Rc::new(this.fetched.clone());
+        let mut resolvers = new_resolver_registry();
+        for &(ref uri, _) in fetched.iter() {
+            if let Some(scheme) = uri_scheme(uri) {
+                let fetched = Rc::clone(&fetched);
+                resolvers.register(&scheme, Rc::new(move |uri: &str| {
+                    for &(ref t_uri, ref result) in fetched.iter() {
+                        if t_uri == uri {
+                            return result.clone().map_err(From::from);
+                        }
+                    }
+                    return Err(From::from(
+                        "URI given other than as string literal is not fetched"));
+                }));
+            }
+        }
+        let xnode = match this.xnode {
+            Some(ref xnode) => xnode.clone(),
+            None => return Poll::Ready(Err(cant_occur!("EvalXPathAsync: xnode"))),
+        };
+        let result = match_xpath_with_resolvers(&this.node, &xnode,
+                        None, &new_collation_registry(), &resolvers);
+        return Poll::Ready(result.map(|xseq| new_sequence(&xseq)));
+    }
+}
+
+// =====================================================================
+/// Sequence: return value type of NodePtr#eval_xpath().
+/// This is an ordered collection of zero or more items.
+///
+#[derive(Debug, Clone)]
+pub struct Sequence {
+    seq: XSequence,
+}
+
+// ---------------------------------------------------------------------
+//
+pub(crate) fn new_sequence(xseq: &XSequence) -> Sequence {
+    return Sequence{seq: xseq.clone()};
+}
+
+// =====================================================================
+/// Item: either an atomic value or a node.
+///
+#[derive(Debug, Clone)]
+pub struct Item {
+    item: XItem,
+}
+
+// ---------------------------------------------------------------------
+//
+fn new_item(xitem: &XItem) -> Item {
+    return Item{item: xitem.clone()};
+}
+
+// =====================================================================
+/// Value: the result of NodePtr#eval_xpath(), under the name used
+/// when it is converted to (or built from) Rust types.
+///
+/// # Examples
+///
+/// ```
+/// use std::convert::TryFrom;
+/// use amxml::dom::*;
+/// use amxml::xpath::Value;
+/// let xml = r#"<root><a v="3"/><a v="4"/></root>"#;
+/// let doc = new_document(xml).unwrap();
+/// let result: Value = doc.eval_xpath("sum(//a/@v)").unwrap();
+/// assert_eq!(result.as_f64().unwrap(), 7.0);
+/// assert_eq!(i64::try_from(result).unwrap(), 7);
+/// ```
+///
+pub type Value = Sequence;
+
+// =====================================================================
+//
+impl Sequence {
+
+    // -----------------------------------------------------------------
+    /// Converts the sequence to the string, like:
+    ///
+    /// (&lt;elem attr="val"&gt;, 1, 2.0, 3e0, "str", true)
+    ///
+    /// This is a sequence (ordered collection of items) of length 6.
+    /// Here, the first item is a node.
+    /// 1 is Integer item, 2.0 (with decimal point) is Decimal item,
+    /// 3e0 (scientific notation) is Double item.
+    /// And "str" is String item, true is Boolean item.
+    ///
+    /// Other items are written after the adaptive serialization
+    /// (XSLT and XQuery Serialization 3.1, section 10), except for nodes:
+    ///
+    /// - Double quotes in strings are doubled: <code>"say ""hi"""</code>
+    /// - Maps: <code>map{"a": 1, "b": (2, 3)}</code>
+    /// - Arrays: <code>[1, (), "x"]</code>
+    /// - Function items: <code>fn:concat#3</code>,
+    ///   <code>(anonymous-function)#1</code>
+    /// - Other atomic values, as constructor functions:
+    ///   <code>xs:dateTime("2020-01-01T00:00:00Z")</code>
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use amxml::dom::*;
+    /// let doc = new_document("<root/>").unwrap();
+    /// let result = doc.eval_xpath(r#"map{"k": [1, ("a", 'say "hi"')]}, concat#3, function($x) {$x}"#).unwrap();
+    /// assert_eq!(result.to_string(),
+    ///     r#"(map{"k": [1, ("a", "say ""hi""")]}, fn:concat#3, (anonymous-function)#1)"#);
+    /// ```
+    ///
+    pub fn to_string(&self) -> String {
+        return self.seq.to_string();
+    }
+
+    // -----------------------------------------------------------------
+    /// Serializes the sequence as JSON text, after the JSON output
+    /// method (XSLT and XQuery Serialization 3.1, section 11):
+    /// maps become objects (with the keys converted to strings),
+    /// arrays become arrays, strings and other atomic values become
+    /// strings, numbers and booleans are written as they are,
+    /// and nodes become strings of their XML serialization.
+    /// The empty sequence (and the empty array member) is null.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use amxml::dom::*;
+    /// let doc = new_document(r#"<root><a id="1">x</a></root>"#).unwrap();
+    /// let result = doc.eval_xpath(
+    ///         r#"map{"id": 1, "tags": ["a", "b"], "ok": true(), "none": (), "elem": //a}"#).unwrap();
+    /// assert_eq!(result.to_xdm_json().unwrap(),
+    ///     r#"{"id":1,"tags":["a","b"],"ok":true,"none":null,"elem":"<a id=\"1\">x</a>"}"#);
+    ///
+    /// assert!(doc.eval_xpath("(1, 2)").unwrap().to_xdm_json().is_err());
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// - When the sequence (or a member of array, or a value of map)
+    ///   has more than one item [err:SERE0023].
+    /// - When two keys of a map are converted to the same string
+    ///   [err:SERE0022].
+    /// - When there is NaN or infinity [err:SERE0020],
+    ///   a function item other than map or array [err:SERE0021],
+    ///   or an attribute node [err:SENR0001].
+    ///
+    pub fn to_xdm_json(&self) -> Result<String, Box<Error>> {
+        return self.seq.to_xdm_json();
+    }
+
+    // -----------------------------------------------------------------
+    /// Returns the empty sequence.
+    ///
+    pub fn empty() -> Sequence {
+        return new_sequence(&new_xsequence());
+    }
+
+    // -----------------------------------------------------------------
+    /// Returns the sequence of the items of this sequence followed by
+    /// those of the other.
+    ///
+    pub fn concat(&self, other: &Sequence) -> Sequence {
+        let mut xseq = self.seq.clone();
+        xseq.append(&other.seq);
+        return new_sequence(&xseq);
+    }
+
+    // -----------------------------------------------------------------
+    /// Returns the length of sequence.
+    ///
+    pub fn len(&self) -> usize {
+        return self.seq.len();
+    }
+
+    // -----------------------------------------------------------------
+    /// Returns an N'th item in the sequence.
+    /// pos must be less than self.len().
+    ///
+    pub fn get_item(&self, pos: usize) -> Item {
+        let xitem = self.seq.get_item(pos);
+        return new_item(xitem);
+    }
+
+    // -----------------------------------------------------------------
+    /// Returns true if the sequence is empty.
+    ///
+    pub fn is_empty(&self) -> bool {
+        return self.seq.is_empty();
+    }
+
+    // -----------------------------------------------------------------
+    /// Returns an iterator over the items in the sequence.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use amxml::dom::*;
+    /// let doc = new_document("<root/>").unwrap();
+    /// let result = doc.eval_xpath("(1, 'two', 3.0)").unwrap();
+    /// let v: Vec<String> = result.iter().map(|i| i.to_string()).collect();
+    /// assert_eq!(v, vec!["1", "\"two\"", "3.0"]);
+    /// ```
+    ///
+    pub fn iter<'a>(&'a self) -> SequenceIter<'a> {
+        return SequenceIter{seq: self, pos: 0};
+    }
+
+    // -----------------------------------------------------------------
+    /// Returns the effective boolean value of the sequence,
+    /// as fn:boolean() does.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use amxml::dom::*;
+    /// let doc = new_document("<root><a/></root>").unwrap();
+    /// assert_eq!(doc.eval_xpath("//a").unwrap().as_bool().unwrap(), true);
+    /// assert_eq!(doc.eval_xpath("//b").unwrap().as_bool().unwrap(), false);
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// - When the effective boolean value is not defined,
+    ///   e.g. for a sequence of two or more atomic values.
+    ///
+    pub fn as_bool(&self) -> Result<bool, Box<Error>> {
+        return self.seq.effective_boolean_value();
+    }
+
+    // -----------------------------------------------------------------
+    /// Returns the numeric value of the sequence, as fn:number() does:
+    /// NaN for the empty sequence.
+    ///
+    /// # Errors
+    ///
+    /// - When the sequence has two or more items.
+    /// - When the item is a map, an array or a function.
+    ///
+    pub fn as_f64(&self) -> Result<f64, Box<Error>> {
+        if self.seq.is_empty() {
+            return Ok(f64::NAN);
+        }
+        return self.seq.get_singleton_item()?.get_as_raw_double();
+    }
+
+    // -----------------------------------------------------------------
+    /// Returns the string value of the sequence, as fn:string() does:
+    /// the empty string for the empty sequence.
+    ///
+    /// Unlike to_string(), strings are not quoted and nodes are
+    /// represented by their string value.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use amxml::dom::*;
+    /// let doc = new_document("<root><a>x<b>y</b></a></root>").unwrap();
+    /// assert_eq!(doc.eval_xpath("//a").unwrap().as_string().unwrap(), "xy");
+    /// assert_eq!(doc.eval_xpath("'str'").unwrap().as_string().unwrap(), "str");
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// - When the sequence has two or more items.
+    /// - When the item is a map, an array or a function.
+    ///
+    pub fn as_string(&self) -> Result<String, Box<Error>> {
+        if self.seq.is_empty() {
+            return Ok(String::new());
+        }
+        return self.seq.get_singleton_item()?.get_as_raw_string();
+    }
+
+    // -----------------------------------------------------------------
+    /// Returns the nodes in the sequence.
+    ///
+    /// # Errors
+    ///
+    /// - When the sequence contains an item that is not a node.
+    ///
+    pub fn as_nodes(&self) -> Result<Vec<NodePtr>, Box<Error>> {
+        let mut nodes: Vec<NodePtr> = vec!{};
+        for xitem in self.seq.iter() {
+            match xitem.as_nodeptr() {
+                Some(node) => nodes.push(node),
+                None => return Err(type_error!(
+                        "Item {}: not a node", xitem.to_string())),
+            }
+        }
+        return Ok(nodes);
+    }
+}
+
+// =====================================================================
+/// Iterator over the items of Sequence.
+///
+pub struct SequenceIter<'a> {
+    seq: &'a Sequence,
+    pos: usize,
+}
+
+impl<'a> Iterator for SequenceIter<'a> {
+    type Item = Item;
+    fn next(&mut self) -> Option<Item> {
+        if self.pos < self.seq.len() {
+            self.pos += 1;
+            return Some(self.seq.get_item(self.pos - 1));
+        } else {
+            return None;
+        }
+    }
+}
+
+impl<'a> IntoIterator for &'a Sequence {
+    type Item = Item;
+    type IntoIter = SequenceIter<'a>;
+    fn into_iter(self) -> SequenceIter<'a> {
+        return self.iter();
+    }
+}
+
+// =====================================================================
+// Rust の型からの変換。
+//
+impl From<bool> for Sequence {
+    fn from(value: bool) -> Sequence {
+        return new_sequence(&new_singleton_boolean(value));
+    }
+}
+
+impl From<i64> for Sequence {
+    fn from(value: i64) -> Sequence {
+        return new_sequence(&new_singleton_integer(value));
+    }
+}
+
+impl From<f64> for Sequence {
+    fn from(value: f64) -> Sequence {
+        return new_sequence(&new_singleton_double(value));
+    }
+}
+
+impl<'a> From<&'a str> for Sequence {
+    fn from(value: &str) -> Sequence {
+        return new_sequence(&new_singleton_string(value));
+    }
+}
+
+impl From<String> for Sequence {
+    fn from(value: String) -> Sequence {
+        return new_sequence(&new_singleton_string(&value));
+    }
+}
+
+impl From<NodePtr> for Sequence {
+    fn from(value: NodePtr) -> Sequence {
+        return new_sequence(&new_singleton_node(&value));
+    }
+}
+
+impl From<Vec<NodePtr>> for Sequence {
+    fn from(value: Vec<NodePtr>) -> Sequence {
+        return new_sequence(&new_xsequence_from_node_array(&value));
+    }
+}
+
+// =====================================================================
+// Rust の型への変換。
+// 数値と文字列はシングルトンであることを要する。
+//
+impl TryFrom<Sequence> for bool {
+    type Error = Box<Error>;
+    fn try_from(value: Sequence) -> Result<bool, Box<Error>> {
+        return value.as_bool();
+    }
+}
+
+impl TryFrom<Sequence> for i64 {
+    type Error = Box<Error>;
+    fn try_from(value: Sequence) -> Result<i64, Box<Error>> {
+        return value.seq.get_singleton_item()?.get_as_raw_integer();
+    }
+}
+
+impl TryFrom<Sequence> for f64 {
+    type Error = Box<Error>;
+    fn try_from(value: Sequence) -> Result<f64, Box<Error>> {
+        return value.seq.get_singleton_item()?.get_as_raw_double();
+    }
+}
+
+impl TryFrom<Sequence> for String {
+    type Error = Box<Error>;
+    fn try_from(value: Sequence) -> Result<String, Box<Error>> {
+        return value.seq.get_singleton_item()?.get_as_raw_string();
+    }
+}
+
+impl TryFrom<Sequence> for NodePtr {
+    type Error = Box<Error>;
+    fn try_from(value: Sequence) -> Result<NodePtr, Box<Error>> {
+        return value.seq.get_singleton_node();
+    }
+}
+
+impl TryFrom<Sequence> for Vec<NodePtr> {
+    type Error = Box<Error>;
+    fn try_from(value: Sequence) -> Result<Vec<NodePtr>, Box<Error>> {
+        return value.as_nodes();
+    }
+}
+
+// =====================================================================
+//
+impl Item {
+    // -----------------------------------------------------------------
+    /// Converts the item to the string.
+    ///
+    pub fn to_string(&self) -> String {
+        return self.item.to_string();
+    }
+
+    // -----------------------------------------------------------------
+    /// Returns item as NodePtr (when this item is a node).
+    ///
+    pub fn as_nodeptr(&self) -> Option<NodePtr> {
+        return self.item.as_nodeptr();
+    }
+
+    // -----------------------------------------------------------------
+    /// Returns the type name of the item, such as "xs:integer",
+    /// "xs:string", "node", "map(*)".
+    ///
+    pub fn xs_type(&self) -> String {
+        return self.item.xs_type();
+    }
+
+    // -----------------------------------------------------------------
+    /// Returns the string value of the item, as fn:string() does.
+    ///
+    /// # Errors
+    ///
+    /// - When the item is a map, an array or a function.
+    ///
+    pub fn as_string(&self) -> Result<String, Box<Error>> {
+        return self.item.get_as_raw_string();
+    }
+
+    // -----------------------------------------------------------------
+    /// Returns the numeric value of the item, as fn:number() does.
+    ///
+    /// # Errors
+    ///
+    /// - When the item is a map, an array or a function.
+    ///
+    pub fn as_f64(&self) -> Result<f64, Box<Error>> {
+        return self.item.get_as_raw_double();
+    }
+}
+
+// =====================================================================
+/// XPath: compiled xpath, whose parse tree can be inspected.
+///
+/// The parse tree is a binary tree: each node has a kind
+/// (such as "OperatorPath", "AxisChild", "FunctionCall"), a name
+/// (element name, literal value, operator etc.; may be empty),
+/// and at most two children (left and right).
+/// Lists, such as steps of a path or arguments of a function call,
+/// are represented as chains of right children.
+///
+/// The names of kinds are those used in the debug dump of the parser;
+/// they follow the production names of the XPath 3.1 grammar.
+///
+/// # Examples
+///
+/// ```
+/// use amxml::xpath::*;
+/// let xpath = XPath::compile("/root/a[@v = 1]").unwrap();
+///
+/// struct StepCounter { steps: Vec<String> }
+/// impl XNodeVisitor for StepCounter {
+///     fn enter(&mut self, node: &XPathNode) -> bool {
+///         if node.kind() == "AxisChild" {
+///             self.steps.push(node.name());
+///         }
+///         return true;
+///     }
+/// }
+///
+/// let mut counter = StepCounter{ steps: vec!{} };
+/// xpath.visit(&mut counter);
+/// assert_eq!(counter.steps, vec!{"root", "a"});
+/// ```
+///
+#[derive(Debug, Clone)]
+pub struct XPath {
+    xnode: XNodePtr,
+}
+
+// =====================================================================
+/// XPathNode: a node of the parse tree of XPath.
+///
+#[derive(Debug, Clone)]
+pub struct XPathNode {
+    xnode: XNodePtr,
+}
+
+// =====================================================================
+/// Visitor for XPath#visit().
+///
+pub trait XNodeVisitor {
+    // -----------------------------------------------------------------
+    /// Called before the children of the node are visited.
+    /// Returns false to skip the children (and leave() of this node).
+    ///
+    fn enter(&mut self, _node: &XPathNode) -> bool {
+        return true;
+    }
+
+    // -----------------------------------------------------------------
+    /// Called after the children of the node are visited.
+    ///
+    fn leave(&mut self, _node: &XPathNode) {
+    }
+}
+
+// =====================================================================
+//
+impl XPath {
+    // -----------------------------------------------------------------
+    /// Compiles the xpath.
+    ///
+    /// # Errors
+    ///
+    /// - When there is syntax error in the xpath.
+    ///
+    pub fn compile(xpath: &str) -> Result<XPath, Box<Error>> {
+        let xnode = compile_xpath(&String::from(xpath))?;
+        return Ok(XPath{xnode: xnode});
+    }
+
+    // -----------------------------------------------------------------
+    /// Returns the root node of the parse tree.
+    ///
+    pub fn root(&self) -> XPathNode {
+        return XPathNode{xnode: self.xnode.clone()};
+    }
+
+    // -----------------------------------------------------------------
+    /// Walks the parse tree in depth-first order (left child first),
+    /// calling enter() and leave() of the visitor.
+    ///
+    pub fn visit<V: XNodeVisitor>(&self, visitor: &mut V) {
+        visit_node(&self.root(), visitor);
+    }
+
+    // -----------------------------------------------------------------
+    /// Dumps the parse tree as S-expression, like:
+    ///
+    /// <code>(OperatorAdd "+" (IntegerLiteral "1") (IntegerLiteral "2"))</code>
+    ///
+    /// Each node is written as its kind, followed by its name
+    /// (quoted; omitted when empty) and its children.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use amxml::xpath::*;
+    /// let xpath = XPath::compile("1 + 2").unwrap();
+    /// assert_eq!(xpath.to_sexpr(),
+    ///     r#"(OperatorAdd "+" (IntegerLiteral "1") (IntegerLiteral "2"))"#);
+    /// ```
+    ///
+    pub fn to_sexpr(&self) -> String {
+        return self.root().to_sexpr();
+    }
+
+    // -----------------------------------------------------------------
+    /// Tests whether the node matches this xpath as a pattern
+    /// (as in the match attribute of XSLT templates), that is,
+    /// whether the node is selected by the xpath evaluated with
+    /// the node itself or any of its ancestors as the context node.
+    ///
+    /// The xpath is not evaluated over the entire document:
+    /// the node test of the last step is checked first, and
+    /// an absolute path is evaluated only once.
+    /// Returns false if the evaluation fails.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use amxml::dom::*;
+    /// use amxml::xpath::*;
+    /// let doc = new_document(r#"<root><sec><p id="1"/></sec><p id="2"/></root>"#).unwrap();
+    /// let p1 = doc.get_first_node("//p[@id='1']").unwrap();
+    /// let p2 = doc.get_first_node("//p[@id='2']").unwrap();
+    ///
+    /// let pattern = XPath::compile("sec/p").unwrap();
+    /// assert!(pattern.matches_node(&p1));
+    /// assert!(! pattern.matches_node(&p2));
+    ///
+    /// let pattern = XPath::compile("/root/p | sec").unwrap();
+    /// assert!(! pattern.matches_node(&p1));
+    /// assert!(pattern.matches_node(&p2));
+    /// assert!(XPath::compile("p[@id = '1']").unwrap().matches_node(&p1));
+    /// ```
+    ///
+    pub fn matches_node(&self, node: &NodePtr) -> bool {
+        return match_pattern(node, &self.xnode).unwrap_or(false);
+    }
+
+    // -----------------------------------------------------------------
+    /// Evaluates this xpath with the node as the context node,
+    /// and returns the sequence; see NodePtr#eval_xpath().
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use amxml::dom::*;
+    /// use amxml::xpath::*;
+    /// let doc = new_document(r#"<root><a v="1"/><a v="2"/></root>"#).unwrap();
+    /// let xpath = XPath::compile("count(//a)").unwrap();
+    /// assert_eq!(xpath.eval(&doc).unwrap().to_string(), "2");
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// - When the evaluation fails, as NodePtr#eval_xpath().
+    ///
+    pub fn eval(&self, node: &NodePtr) -> Result<Sequence, Box<Error>> {
+        let result = match_xpath(node, &self.xnode)?;
+        return Ok(new_sequence(&result));
+    }
+
+    // -----------------------------------------------------------------
+    /// Evaluates this xpath with the focus given by the caller:
+    /// the context item is the item in <code>context</code> (a node,
+    /// an atomic value, a map or an array; absent if empty), and
+    /// position() and last() return <code>position</code> and
+    /// <code>size</code>.
+    ///
+    /// This is for the processor that iterates over a sequence itself
+    /// (e.g. while streaming) and evaluates the xpath for each item.
+    /// When the context item is absent, position and size are ignored.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use amxml::dom::*;
+    /// use amxml::xpath::*;
+    /// let doc = new_document(r#"<root><a v="x"/><a v="y"/><a v="z"/></root>"#).unwrap();
+    /// let items = doc.eval_xpath("//a").unwrap();
+    /// let xpath = XPath::compile("concat(@v, ':', position(), '/', last())").unwrap();
+    /// let mut result = vec!{};
+    /// for (i, item) in items.iter().enumerate() {
+    ///     let context = Value::from(item.as_nodeptr().unwrap());
+    ///     let value = xpath.evaluate_with_context(&context, i + 1, items.len()).unwrap();
+    ///     result.push(value.as_string().unwrap());
+    /// }
+    /// assert_eq!(result, vec!{"x:1/3", "y:2/3", "z:3/3"});
+    ///
+    /// let xpath = XPath::compile(". * 10 + position()").unwrap();
+    /// assert_eq!(xpath.evaluate_with_context(&Value::from(4), 2, 5).unwrap().to_string(), "42");
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// - When <code>context</code> has more than one item [err:XPTY0004].
+    /// - When <code>position</code> is not between 1 and <code>size</code>.
+    /// - When the evaluation fails, as NodePtr#eval_xpath().
+    ///
+    pub fn evaluate_with_context(&self, context: &Value,
+                position: usize, size: usize) -> Result<Sequence, Box<Error>> {
+        if 1 < context.len() {
+            return Err(type_error!(
+                "Context item must be a single item: {} items [err:XPTY0004]",
+                context.len()));
+        }
+        let (position, size) = if context.is_empty() {
+            (0, 0)
+        } else {
+            if position < 1 || size < position {
+                return Err(dynamic_error!(
+                    "Context position must be between 1 and context size: position = {}, size = {}",
+                    position, size));
+            }
+            (position, size)
+        };
+        let result = evaluate_xnode_with_focus(&context.seq, &self.xnode,
+                        position, size)?;
+        return Ok(new_sequence(&result));
+    }
+
+    // -----------------------------------------------------------------
+    /// Returns the optimized xpath, which evaluates to the same result
+    /// faster, for the xpath evaluated many times.
+    /// This xpath itself is left unchanged.
+    ///
+    /// - Constant subexpressions that evaluate to a single number,
+    ///   string or boolean are replaced with literals.
+    /// - <code>descendant-or-self::node()/child::x</code> (that is,
+    ///   <code>//x</code>) is rewritten into <code>descendant::x</code>,
+    ///   unless the step <code>x</code> has predicates.
+    /// - Other constant subexpressions (such as <code>(1 to 10)</code>)
+    ///   in predicates and in the bodies of for / some / every
+    ///   expressions are evaluated only once, before the whole xpath.
+    ///
+    /// The optimized xpath is meant to be evaluated; its parse tree
+    /// does not correspond to the source text.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use amxml::dom::*;
+    /// use amxml::xpath::*;
+    /// let xpath = XPath::compile("(60 * 60) + 1").unwrap();
+    /// assert_eq!(xpath.optimize().to_sexpr(), r#"(IntegerLiteral "3601")"#);
+    ///
+    /// let doc = new_document(r#"<root><a><b/></a><b/></root>"#).unwrap();
+    /// let xpath = XPath::compile("//b").unwrap().optimize();
+    /// assert!(xpath.to_sexpr().contains(r#"(AxisDescendant "b")"#));
+    /// assert_eq!(xpath.eval(&doc).unwrap().len(), 2);
+    /// ```
+    ///
+    pub fn optimize(&self) -> XPath {
+        return XPath{xnode: optimize_xpath(&self.xnode)};
+    }
+}
+
+// =====================================================================
+/// XPathSet: compiled xpaths that are evaluated together,
+/// for extracting many fields from each document.
+///
+/// The simple downward paths among them, i.e. those consisting only of
+/// steps on the child, descendant, descendant-or-self, self and
+/// attribute axes without predicates (such as <code>/feed/title</code>,
+/// <code>//item/@id</code>, <code>.//link</code>), are evaluated
+/// together in a single traversal of the tree.
+/// The other xpaths are evaluated one by one, as XPath#eval() does.
+///
+/// # Examples
+///
+/// ```
+/// use amxml::dom::*;
+/// use amxml::xpath::*;
+/// let xml = r#"<feed><title>News</title><item id="1"><title>A</title></item><item id="2"><title>B</title></item></feed>"#;
+/// let doc = new_document(xml).unwrap();
+/// let set = XPathSet::compile(&["/feed/title", "//item/@id", "//item/title", "count(//item)"]).unwrap();
+/// assert_eq!(set.batched_len(), 3);
+/// let results = set.evaluate_all(&doc).unwrap();
+/// assert_eq!(results[0].as_string().unwrap(), "News");
+/// assert_eq!(results[1].len(), 2);
+/// assert_eq!(results[2].to_string(), "(<title>, <title>)");
+/// assert_eq!(results[3].to_string(), "2");
+/// ```
+///
+#[derive(Debug, Clone)]
+pub struct XPathSet {
+    xpaths: Vec<XPath>,
+    simple_paths: Vec<Option<SimplePath>>,
+}
+
+// =====================================================================
+//
+impl XPathSet {
+    // -----------------------------------------------------------------
+    /// Compiles the xpaths.
+    ///
+    /// # Errors
+    ///
+    /// - When there is syntax error in any of the xpaths.
+    ///
+    pub fn compile(xpaths: &[&str]) -> Result<XPathSet, Box<Error>> {
+        let mut set = XPathSet{ xpaths: vec!{}, simple_paths: vec!{} };
+        for xpath in xpaths.iter() {
+            let xpath = XPath::compile(xpath)?;
+            set.simple_paths.push(simple_path(&xpath.xnode));
+            set.xpaths.push(xpath);
+        }
+        return Ok(set);
+    }
+
+    // -----------------------------------------------------------------
+    /// Returns the number of the xpaths.
+    ///
+    pub fn len(&self) -> usize {
+        return self.xpaths.len();
+    }
+
+    // -----------------------------------------------------------------
+    /// Returns the number of the xpaths that are evaluated together
+    /// in a single traversal of the tree.
     ///
-    /// ```
-    /// use amxml::dom::*;
-    /// let xml = r#"<root img="basic"><a img="a1"/><a img="a2"/></root>"#;
-    /// let doc = new_document(xml).unwrap();
-    /// let nodeset = doc.get_nodeset("//a").unwrap();
-    /// assert_eq!(nodeset[0].attribute_value("img").unwrap(), "a1");
-    /// assert_eq!(nodeset[1].attribute_value("img").unwrap(), "a2");
-    /// ```
+    pub fn batched_len(&self) -> usize {
+        return self.simple_paths.iter().filter(|p| p.is_some()).count();
+    }
+
+    // -----------------------------------------------------------------
+    /// Evaluates the xpaths with the node as the context node, and
+    /// returns the sequences in the same order as the xpaths.
     ///
     /// # Errors
     ///
-    /// - When syntax error or unimplemented feature in xpath.
+    /// - When the evaluation of an xpath that is not a simple downward
+    ///   path fails, as NodePtr#eval_xpath().
     ///
-    pub fn get_nodeset(&self, xpath: &str) -> Result<Vec<NodePtr>, Box<Error>> {
-        let xnode = compile_xpath(&String::from(xpath))?;
-
-        let result = match_xpath(self, &xnode)?;
-
-        let nodeset = result.to_nodeset();
-        return Ok(nodeset);
+    pub fn evaluate_all(&self, node: &NodePtr) -> Result<Vec<Sequence>, Box<Error>> {
+        let paths: Vec<SimplePath> = self.simple_paths.iter()
+                    .filter_map(|p| p.clone()).collect();
+        let mut batched = match_simple_paths(node, &paths).into_iter();
+        let mut results: Vec<Sequence> = vec!{};
+        for (xpath, path) in self.xpaths.iter().zip(self.simple_paths.iter()) {
+            match path {
+                Some(_) => results.push(new_sequence(&batched.next().unwrap())),
+                None => results.push(xpath.eval(node)?),
+            }
+        }
+        return Ok(results);
     }
 }
 
 // =====================================================================
-/// Sequence: return value type of NodePtr#eval_xpath().
-/// This is an ordered collection of zero or more items.
+/// Compiles the xpath, recovering from syntax errors, for tools
+/// (such as editors) that deal with incomplete expressions.
+/// For example, completions can be offered for the valid prefix.
 ///
-#[derive(Debug)]
-pub struct Sequence {
-    seq: XSequence,
-}
-
-// ---------------------------------------------------------------------
-//
-fn new_sequence(xseq: &XSequence) -> Sequence {
-    return Sequence{seq: xseq.clone()};
+/// Returns the parse tree of the longest valid prefix of the xpath
+/// (with unclosed brackets closed), together with the list of syntax
+/// errors. The list is empty if the xpath is valid.
+/// The first error is the one the ordinary compilation reports;
+/// the following ones report the brackets closed in the recovery.
+/// The errors carry their positions; see XmlError#syntax_info().
+///
+/// The parse tree is None if no prefix is valid.
+///
+/// # Examples
+///
+/// ```
+/// use amxml::xpath::*;
+/// use amxml::xmlerror::*;
+/// let (xpath, errors) = compile_xpath_lenient("/root/item) + 1");
+/// assert_eq!(xpath.unwrap().to_sexpr(),
+///     XPath::compile("/root/item").unwrap().to_sexpr());
+/// assert_eq!(errors.len(), 1);
+/// let info = errors[0].downcast_ref::<XmlError>().unwrap().syntax_info().unwrap();
+/// assert_eq!(info.offset(), 10);
+///
+/// let (xpath, errors) = compile_xpath_lenient("count(/root/a");
+/// assert_eq!(xpath.unwrap().root().kind(), "FunctionCall");
+/// assert_eq!(errors.len(), 2);
+/// ```
+///
+pub fn compile_xpath_lenient(xpath: &str) -> (Option<XPath>, Vec<Box<Error>>) {
+    let (xnode, errors) = compile_xpath_recovering(&String::from(xpath));
+    return (xnode.map(|xnode| XPath{xnode: xnode}), errors);
 }
 
 // =====================================================================
-/// Item: either an atomic value or a node.
+/// Evaluates the xpath without a document:
+/// the context item is the item in <code>context</code>, which may be
+/// an atomic value, a map or an array as well as a node,
+/// or absent if <code>context</code> is empty;
+/// and the variables are bound to the values in <code>vars</code>
+/// (the names may be written with or without "$").
 ///
-pub struct Item {
-    item: XItem,
+/// # Examples
+///
+/// ```
+/// use amxml::xpath::*;
+/// let result = evaluate_standalone(". * 2 + $n", &Value::from(20), &[("n", Value::from(2))]).unwrap();
+/// assert_eq!(result.to_string(), "42");
+///
+/// let order = evaluate_standalone(
+///         "map{'items': [map{'price': 3}, map{'price': 4}]}", &Value::empty(), &[]).unwrap();
+/// let total = evaluate_standalone("sum(?items?*?price)", &order, &[]).unwrap();
+/// assert_eq!(total.to_string(), "7");
+///
+/// assert!(evaluate_standalone(".", &Value::from(vec!{}), &[]).unwrap().is_empty());
+/// assert!(evaluate_standalone(".", &Value::from("a").concat(&Value::from("b")), &[]).is_err());
+/// ```
+///
+/// # Errors
+///
+/// - When <code>context</code> has more than one item [err:XPTY0004].
+/// - When syntax error or unimplemented feature in xpath.
+/// - When the evaluation fails, as NodePtr#eval_xpath().
+///
+pub fn evaluate_standalone(xpath: &str, context: &Value,
+                vars: &[(&str, Value)]) -> Result<Sequence, Box<Error>> {
+    if 1 < context.len() {
+        return Err(type_error!(
+            "Context item must be a single item: {} items [err:XPTY0004]",
+            context.len()));
+    }
+    let xnode = compile_xpath(&String::from(xpath))?;
+    let xvars: Vec<(String, XSequence)> = vars.iter()
+            .map(|&(name, ref value)|
+                (String::from(name.trim_start_matches('$')), value.seq.clone()))
+            .collect();
+    let result = evaluate_xnode_with_context(&context.seq, &xnode, &xvars)?;
+    return Ok(new_sequence(&result));
 }
 
 // ---------------------------------------------------------------------
 //
-fn new_item(xitem: &XItem) -> Item {
-    return Item{item: xitem.clone()};
+fn visit_node<V: XNodeVisitor>(node: &XPathNode, visitor: &mut V) {
+    if visitor.enter(node) {
+        for child in node.children().iter() {
+            visit_node(child, visitor);
+        }
+        visitor.leave(node);
+    }
 }
 
 // =====================================================================
 //
-impl Sequence {
-
+impl XPathNode {
     // -----------------------------------------------------------------
-    /// Converts the sequence to the string, like:
-    ///
-    /// (&lt;elem attr="val"&gt;, 1, 2.0, 3e0, "str", true)
+    /// Returns the kind of the node, such as "OperatorPath", "AxisChild".
     ///
-    /// This is a sequence (ordered collection of items) of length 6.
-    /// Here, the first item is a node.
-    /// 1 is Integer item, 2.0 (with decimal point) is Decimal item,
-    /// 3e0 (scientific notation) is Double item.
-    /// And "str" is String item, true is Boolean item.
+    pub fn kind(&self) -> String {
+        return get_xnode_type(&self.xnode).to_string();
+    }
+
+    // -----------------------------------------------------------------
+    /// Returns the name of the node (may be empty).
     ///
-    pub fn to_string(&self) -> String {
-        return self.seq.to_string();
+    pub fn name(&self) -> String {
+        return get_xnode_name(&self.xnode);
     }
 
     // -----------------------------------------------------------------
-    /// Returns the length of sequence.
+    /// Returns the left child, if any.
     ///
-    pub fn len(&self) -> usize {
-        return self.seq.len();
+    pub fn left(&self) -> Option<XPathNode> {
+        let xl = get_left(&self.xnode);
+        if is_nil_xnode(&xl) {
+            return None;
+        }
+        return Some(XPathNode{xnode: xl});
     }
 
     // -----------------------------------------------------------------
-    /// Returns an N'th item in the sequence.
-    /// pos must be less than self.len().
+    /// Returns the right child, if any.
     ///
-    pub fn get_item(&self, pos: usize) -> Item {
-        let xitem = self.seq.get_item(pos);
-        return new_item(xitem);
+    pub fn right(&self) -> Option<XPathNode> {
+        let xr = get_right(&self.xnode);
+        if is_nil_xnode(&xr) {
+            return None;
+        }
+        return Some(XPathNode{xnode: xr});
     }
-}
 
-// =====================================================================
-//
-impl Item {
     // -----------------------------------------------------------------
-    /// Converts the item to the string.
+    /// Returns the children (left and then right; nil children are
+    /// omitted).
     ///
-    pub fn to_string(&self) -> String {
-        return self.item.to_string();
+    pub fn children(&self) -> Vec<XPathNode> {
+        let mut children: Vec<XPathNode> = vec!{};
+        if let Some(xl) = self.left() {
+            children.push(xl);
+        }
+        if let Some(xr) = self.right() {
+            children.push(xr);
+        }
+        return children;
     }
 
     // -----------------------------------------------------------------
-    /// Returns item as NodePtr (when this item is a node).
+    /// Dumps the subtree as S-expression. See XPath#to_sexpr().
     ///
-    pub fn as_nodeptr(&self) -> Option<NodePtr> {
-        return self.item.as_nodeptr();
+    pub fn to_sexpr(&self) -> String {
+        let mut buf = format!("({}", self.kind());
+        let name = self.name();
+        if name != "" {
+            buf += &format!(" {:?}", name);
+        }
+        for child in self.children().iter() {
+            buf += " ";
+            buf += &child.to_sexpr();
+        }
+        buf += ")";
+        return buf;
     }
 }
 
@@ -284,11 +2270,685 @@ impl Item {
 #[cfg(test)]
 mod test {
     use super::*;
+    use std::cell::Cell;
 
     use xpath_impl::helpers::compress_spaces;
     use xpath_impl::helpers::subtest_xpath;
     use xpath_impl::helpers::subtest_eval_xpath;
 
+    // -----------------------------------------------------------------
+    // XPath: 構文木の走査
+    //
+    #[test]
+    fn test_xpath_visit() {
+        struct Recorder {
+            log: Vec<String>,
+        }
+        impl XNodeVisitor for Recorder {
+            fn enter(&mut self, node: &XPathNode) -> bool {
+                self.log.push(format!("+{}", node.kind()));
+                return node.kind() != "FunctionCall";
+            }
+            fn leave(&mut self, node: &XPathNode) {
+                self.log.push(format!("-{}", node.kind()));
+            }
+        }
+
+        let xpath = XPath::compile("/root").unwrap();
+        let mut recorder = Recorder{ log: vec!{} };
+        xpath.visit(&mut recorder);
+        assert_eq!(recorder.log.join(" "),
+            "+OperatorPath +AxisRoot -AxisRoot +AxisChild -AxisChild -OperatorPath");
+
+        let xpath = XPath::compile("count(//a) + 1").unwrap();
+        let mut recorder = Recorder{ log: vec!{} };
+        xpath.visit(&mut recorder);
+        assert!(recorder.log.contains(&String::from("+FunctionCall")));
+        assert!(! recorder.log.contains(&String::from("-FunctionCall")));
+        assert!(! recorder.log.contains(&String::from("+AxisDescendantOrSelf")));
+
+        assert!(XPath::compile("/root[").is_err());
+    }
+
+    // -----------------------------------------------------------------
+    // XPath: S式
+    //
+    #[test]
+    fn test_xpath_to_sexpr() {
+        let xpath = XPath::compile("/root").unwrap();
+        assert_eq!(xpath.to_sexpr(),
+            r#"(OperatorPath "parse_path_expr Slash" (AxisRoot "node()") (AxisChild "root"))"#);
+        let xpath = XPath::compile("concat('a', $x)").unwrap();
+        assert_eq!(xpath.to_sexpr(),
+            r#"(FunctionCall "fn:concat" (ArgumentTop (StringLiteral "a") (ArgumentTop (VarRef "x"))))"#);
+    }
+
+    // -----------------------------------------------------------------
+    //
+    #[test]
+    fn test_xpath_matches_node() {
+        let xml = compress_spaces(r#"
+<root>
+    <chap id="c1">
+        <para img="a" type="note"/>
+        <sec><para img="b"/></sec>
+    </chap>
+    <para img="c"/>
+    <note img="d">text</note>
+</root>
+        "#);
+        let doc = new_document(&xml).unwrap();
+        let nodes = doc.get_nodeset("//*[@img] | //@type | //text()").unwrap();
+
+        let cases = [
+            ( "para", "abc" ),
+            ( "chap/para", "a" ),
+            ( "chap//para", "ab" ),
+            ( "/root/para", "c" ),
+            ( "//para", "abc" ),
+            ( "para[1]", "abc" ),
+            ( "para[@type = 'note']", "a" ),
+            ( "*[@img = ('b', 'd')]", "bd" ),
+            ( "sec/para | note", "bd" ),
+            ( "@type", "@" ),
+            ( "text()", "t" ),
+            ( "node()", "abcdt" ),          // 属性は子ではない。
+            ( "chap/para/@*", "@" ),
+            ( "chap/@*", "" ),
+            ( "1 + 1", "" ),
+            ( "para/..", "" ),
+        ];
+        for (pattern, expected) in cases.iter() {
+            let xpath = XPath::compile(pattern).unwrap();
+            let mut matched = String::new();
+            for node in nodes.iter() {
+                if xpath.matches_node(node) {
+                    matched += &match node.node_type() {
+                        NodeType::Attribute => String::from("@"),
+                        NodeType::Text => String::from("t"),
+                        _ => node.attribute_value("img").unwrap(),
+                    };
+                }
+            }
+            assert_eq!(&matched, expected, "pattern: {}", pattern);
+        }
+    }
+
+    // -----------------------------------------------------------------
+    //
+    #[test]
+    fn test_compile_xpath_lenient() {
+        let cases = [
+            ( "/root/a", "/root/a", 0 ),
+            ( "/root/a[@x", "/root/a[@x]", 2 ),
+            ( "count(/root/a", "count(/root/a)", 2 ),
+            ( "(1, (2, 3", "(1, (2, 3))", 3 ),
+            ( "/root/a[1] )", "/root/a[1]", 1 ),
+            ( "/root/a[1] ) + 2", "/root/a[1]", 1 ),
+        ];
+        for (xpath, recovered, n_errors) in cases.iter() {
+            let (partial, errors) = compile_xpath_lenient(xpath);
+            assert_eq!(partial.unwrap().to_sexpr(),
+                XPath::compile(recovered).unwrap().to_sexpr(), "xpath: {}", xpath);
+            assert_eq!(errors.len(), *n_errors, "xpath: {}", xpath);
+            for e in errors.iter() {
+                let info = e.downcast_ref::<XmlError>().unwrap().syntax_info();
+                assert!(info.is_some(), "xpath: {}", xpath);
+            }
+        }
+
+        let (partial, errors) = compile_xpath_lenient(")");
+        assert!(partial.is_none());
+        assert_eq!(errors.len(), 1);
+    }
+
+    // -----------------------------------------------------------------
+    // EvalOptions#uri_scheme() で登録した処理函数による資源の取得。
+    //
+    #[test]
+    fn test_uri_scheme() {
+        let doc = new_document("<root/>").unwrap();
+        let count = Rc::new(Cell::new(0));
+        let count_clone = Rc::clone(&count);
+        let options = EvalOptions::new().uri_scheme("mem", move |uri| {
+            count_clone.set(count_clone.get() + 1);
+            match uri {
+                "mem:a" => Ok(String::from(r#"<a><b v="1"/><b v="2"/></a>"#)),
+                "mem:broken" => Ok(String::from("<a></b>")),
+                "mem:text" => Ok(String::from("x < y")),
+                _ => Err(From::from("not found")),
+            }
+        });
+        let cases = [
+            ( r#"doc("mem:a")/a/b/@v/string()"#, r#"("1", "2")"# ),
+            ( r#"doc("mem:a") is doc("mem:a")"#, "true" ),
+            ( r#"doc(())"#, "()" ),
+            ( r#"doc-available("mem:a")"#, "true" ),
+            ( r#"doc-available("mem:broken")"#, "false" ),
+            ( r#"doc-available("mem:none")"#, "false" ),
+            ( r#"doc-available("other:a")"#, "false" ),
+            ( r#"unparsed-text("mem:text")"#, r#""x < y""# ),
+            ( r#"unparsed-text(())"#, "()" ),
+            ( r#"unparsed-text-available("mem:text")"#, "true" ),
+            ( r#"unparsed-text-available("mem:none")"#, "false" ),
+            ( r#"for $u in ("a", "text") return unparsed-text-available("mem:" || $u)"#, "(true, true)" ),
+        ];
+        for (xpath, expected) in cases.iter() {
+            let result = doc.eval_xpath_with_options(xpath, &options).unwrap();
+            assert_eq!(result.to_string(), *expected, "xpath: {}", xpath);
+        }
+
+        count.set(0);
+        doc.eval_xpath_with_options(r#"doc("mem:a"), doc("mem:a")"#, &options).unwrap();
+        assert_eq!(count.get(), 1);
+
+        let errors = [
+            ( r#"doc("mem:none")"#, "FODC0002" ),
+            ( r#"doc("mem:broken")"#, "FODC0002" ),
+            ( r#"doc("other:a")"#, "FODC0002" ),
+            ( r#"doc("a.xml")"#, "FODC0005" ),
+            ( r#"unparsed-text("mem:none")"#, "FOUT1170" ),
+        ];
+        for (xpath, code) in errors.iter() {
+            let err = doc.eval_xpath_with_options(xpath, &options).err().unwrap();
+            assert!(err.to_string().contains(code), "xpath: {}: {}", xpath, err);
+        }
+        let err = doc.eval_xpath(r#"doc("mem:a")"#).err().unwrap();
+        assert!(err.to_string().contains("FODC0002"), "{}", err);
+    }
+
+    // -----------------------------------------------------------------
+    // EvalOptions#base_uri() で指定した静的なベースURIによる、
+    // 相対URIの解決と、取得した文書の文書URI。
+    //
+    #[test]
+    fn test_static_base_uri() {
+        let doc = new_document("<root/>").unwrap();
+        let options = EvalOptions::new()
+            .base_uri("mem:/dir/index.xml")
+            .uri_scheme("mem", |uri| {
+                match uri {
+                    "mem:/dir/a.xml" => Ok(String::from(r#"<a><b xml:base="sub/"/></a>"#)),
+                    _ => Err(From::from("not found")),
+                }
+            });
+        let cases = [
+            ( "static-base-uri()", r#""mem:/dir/index.xml""# ),
+            ( "resolve-uri('../x')", r#""mem:/x""# ),
+            ( "count(doc('a.xml')//b)", "1" ),
+            ( "doc('a.xml') is doc('mem:/dir/a.xml')", "true" ),
+            ( "document-uri(doc('a.xml'))", r#""mem:/dir/a.xml""# ),
+            ( "base-uri(doc('a.xml')//b)", r#""mem:/dir/sub/""# ),
+            ( "document-uri(/)", "()" ),
+        ];
+        for (xpath, expected) in cases.iter() {
+            let result = doc.eval_xpath_with_options(xpath, &options).unwrap();
+            assert_eq!(result.to_string(), *expected, "xpath: {}", xpath);
+        }
+
+        doc.set_document_uri("http://example.com/doc.xml");
+        let result = doc.eval_xpath("document-uri(/), base-uri(/root)").unwrap();
+        assert_eq!(result.to_string(),
+            r#"("http://example.com/doc.xml", "http://example.com/doc.xml")"#);
+    }
+
+    // -----------------------------------------------------------------
+    // evaluate_standalone(): 文書なしの評価。
+    //
+    #[test]
+    fn test_evaluate_standalone() {
+        let doc = new_document(r#"<root><a v="1"/><a v="2"/></root>"#).unwrap();
+        let nodes = doc.eval_xpath("//a").unwrap();
+        let array = evaluate_standalone("[1, 'x', map{'k': true()}]", &Value::empty(), &[]).unwrap();
+        let cases = [
+            ( "count($nodes)", Value::empty(), "2" ),
+            ( "$nodes/@v/string()", Value::empty(), r#"("1", "2")"# ),
+            ( "?3?k", array.clone(), "true" ),
+            ( "array:size(.)", array.clone(), "3" ),
+            ( "upper-case(.)", Value::from("abc"), r#""ABC""# ),
+            ( "string(@v)", Value::from(nodes.get_item(1).as_nodeptr().unwrap()), r#""2""# ),
+            ( "for $i in 1 to 2 return $i + $k", Value::empty(), "(11, 12)" ),
+            ( "$undefined", Value::empty(), "()" ),
+        ];
+        for &(xpath, ref context, expected) in cases.iter() {
+            let result = evaluate_standalone(xpath, context,
+                    &[("nodes", nodes.clone()), ("$k", Value::from(10))]).unwrap();
+            assert_eq!(result.to_string(), expected, "xpath: {}", xpath);
+        }
+        assert!(evaluate_standalone("(1", &Value::empty(), &[]).is_err());
+    }
+
+    // -----------------------------------------------------------------
+    // evaluate_with_context(): 最上位の position() / last() は指定した値。
+    // 述語やパスの内側では、それぞれの文脈の値になる。
+    //
+    #[test]
+    fn test_evaluate_with_context() {
+        let doc = new_document(r#"<root><a/><a/><a/></root>"#).unwrap();
+        let root = Value::from(doc.root_element());
+        let cases = [
+            ( "position()", Value::from("s"), 3, 7, "3" ),
+            ( "last() - position()", Value::from("s"), 3, 7, "4" ),
+            ( "position() = last()", Value::from(1), 7, 7, "true" ),
+            ( "count(a[position() < 3]), position()", root.clone(), 5, 9, "(2, 5)" ),
+            ( "count(a[position() < last()])", root.clone(), 1, 1, "2" ),
+            ( "for $i in 1 to 2 return $i * position()", root.clone(), 4, 4, "(4, 8)" ),
+            ( "position()", Value::empty(), 3, 7, "0" ),
+        ];
+        for &(xpath, ref context, position, size, expected) in cases.iter() {
+            let result = XPath::compile(xpath).unwrap()
+                    .evaluate_with_context(context, position, size).unwrap();
+            assert_eq!(result.to_string(), expected, "xpath: {}", xpath);
+        }
+
+        let xpath = XPath::compile("position()").unwrap();
+        assert!(xpath.evaluate_with_context(&Value::from(1), 0, 3).is_err());
+        assert!(xpath.evaluate_with_context(&Value::from(1), 4, 3).is_err());
+        assert!(xpath.evaluate_with_context(
+                &Value::from(1).concat(&Value::from(2)), 1, 2).is_err());
+    }
+
+    // -----------------------------------------------------------------
+    // select_first() / select_up_to(): 必要な個数だけ探索する。
+    // 結果は、get_nodeset() の先頭と同じでなければならない。
+    //
+    #[test]
+    fn test_select_up_to() {
+        let doc = new_document(r#"
+<root>
+    <x v="1"><x v="2"><y/><x v="3"/></x></x>
+    <a><x v="4"/><b><x v="5"/></b></a>
+    <x v="6"/>
+</root>"#).unwrap();
+        let xpaths = [
+            "//x", "/root/x", "//x[@v > 2]", "//x[@v > 2 and not(x)]",
+            "//x[1]", "//x[last()]", "//x[position() > 1]", "//x[x]",
+            "/root/a//x", "descendant::x[@v != '4']", "//x | //a", "//x/@v",
+            "/root/*[@v = 1]/x", "//x[@v = (1 to 3)][. is ../x[1]]",
+        ];
+        for xpath in xpaths.iter() {
+            let all = doc.get_nodeset(xpath).unwrap();
+            for n in 0..4 {
+                let limited = doc.select_up_to(xpath, n).unwrap();
+                assert_eq!(limited, all.iter().take(n).cloned().collect::<Vec<NodePtr>>(),
+                        "xpath: {}, n: {}", xpath, n);
+            }
+            assert_eq!(doc.select_first(xpath).unwrap(), all.first().cloned(),
+                    "xpath: {}", xpath);
+        }
+
+        // 先頭のノードが見つかれば、以降のノードについては述語を評価しない。
+        let doc = new_document(r#"<root><a v="1"/><a v="x"/></root>"#).unwrap();
+        let xpath = "//a[1 idiv (2 - number(@v)) eq 1]";
+        assert!(doc.get_nodeset(xpath).is_err());
+        let node = doc.select_first(xpath).unwrap().unwrap();
+        assert_eq!(node.attribute_value("v").unwrap(), "1");
+        assert_eq!(doc.get_first_node(xpath), Some(node));
+        assert!(doc.select_first("//a[").is_err());
+    }
+
+    // -----------------------------------------------------------------
+    // select_paged(): get_nodeset() の一部分と同じ結果になる。
+    //
+    #[test]
+    fn test_select_paged() {
+        let doc = new_document(r#"
+<root>
+    <x v="1"><x v="2"><y/><x v="3"/></x></x>
+    <a><x v="4"/><b><x v="5"/></b></a>
+    <x v="6"/>
+</root>"#).unwrap();
+        let xpaths = [
+            "//x", "//x[@v > 2]", "//x[last()]", "//x | //a", "/root/a//x",
+        ];
+        for xpath in xpaths.iter() {
+            let all = doc.get_nodeset(xpath).unwrap();
+            for offset in 0..8 {
+                for limit in 0..4 {
+                    let page = doc.select_paged(xpath, offset, limit).unwrap();
+                    let expected: Vec<NodePtr> = all.iter().skip(offset).take(limit).cloned().collect();
+                    assert_eq!(page.nodes(), &expected,
+                            "xpath: {}, offset: {}, limit: {}", xpath, offset, limit);
+                    assert_eq!(page.offset(), offset);
+                    assert_eq!(page.has_next(), offset + limit < all.len(),
+                            "xpath: {}, offset: {}, limit: {}", xpath, offset, limit);
+                    match page.total() {
+                        TotalCount::Exact(n) => assert_eq!(n, all.len()),
+                        TotalCount::AtLeast(n) => {
+                            assert!(offset + limit < n && n <= all.len());
+                        },
+                    }
+                }
+            }
+        }
+
+        let page = doc.select_paged("//x", 1, 2).unwrap();
+        assert_eq!(page.total(), TotalCount::AtLeast(4));
+        let v: Vec<String> = page.into_nodes().iter().map(|n| n.attribute_value("v").unwrap()).collect();
+        assert_eq!(v, vec!{"2", "3"});
+        assert_eq!(doc.select_paged("//x", 0, 10).unwrap().total(), TotalCount::Exact(6));
+        assert!(doc.select_paged("//x[", 0, 10).is_err());
+    }
+
+    // -----------------------------------------------------------------
+    // EvalOptions#spec_level(SpecLevel::XPath10): XPath 1.0 互換モード。
+    //
+    #[test]
+    fn test_spec_level_xpath10() {
+        let doc = new_document(r#"<root><p v="5">10</p><p v="20">x</p></root>"#).unwrap();
+        let options = EvalOptions::new().spec_level(SpecLevel::XPath10);
+        let cases = [
+            ( "string-length(//p)", "2" ),
+            ( "substring('12345', '2', '3')", r#""234""# ),
+            ( "concat(//p, '-', //@v)", r#""10-5""# ),
+            ( "//p + 1", "1.1e1" ),
+            ( "//nothing + 1", "NaN" ),
+            ( "'1' = 1", "true" ),
+            ( "'x' = true()", "true" ),
+            ( "//nothing = false()", "true" ),
+            ( "//p = 'x'", "true" ),
+            ( "//@v > 10", "true" ),
+            ( "'10' < '9'", "false" ),
+            ( "count(//p[@v < 10])", "1" ),
+            ( "-//@v", "-5e0" ),
+            ( "(//p)[2]/@v", r#"v="20""# ),
+            ( "//p[last()] = 'x' and not(starts-with(//p, 'x'))", "true" ),
+        ];
+        for &(xpath, expected) in cases.iter() {
+            let result = doc.eval_xpath_with_options(xpath, &options).unwrap();
+            assert_eq!(result.to_string(), expected, "xpath: {}", xpath);
+        }
+
+        for xpath in ["1, 2", "()", "1 to 3", "if (1) then 2 else 3",
+                      "for $i in //p return $i", "1 eq 1", "//p instance of node()*",
+                      "'a' || 'b'", "//p ! .", "1e3", "+1", "map{}", "[1]",
+                      "upper-case('a')", "//p/string()",
+                      "element()", "//p is //p"].iter() {
+            assert!(doc.eval_xpath_with_options(xpath, &options).is_err(),
+                    "xpath: {}", xpath);
+        }
+
+        // 既定 (XPath 3.1) では XPath 1.0 の変換をおこなわない。
+        assert!(doc.eval_xpath("string-length(//p)").is_err());
+    }
+
+    // -----------------------------------------------------------------
+    // 評価時の誤りメッセージは、ノードを文書中の位置で示す。
+    //
+    #[test]
+    fn test_error_message_node_path() {
+        let doc = new_document(r#"<root><p>a</p><q/><p>b</p></root>"#).unwrap();
+        let err = doc.eval_xpath("//p eq 'a'").unwrap_err().to_string();
+        assert!(err.contains("/root/p[1]") && err.contains("/root/p[2]"), "{}", err);
+        let err = doc.eval_xpath("(//q | //p) cast as xs:string").unwrap_err().to_string();
+        assert!(err.contains("(at /root/q)"), "{}", err);
+    }
+
+    // -----------------------------------------------------------------
+    // Sequence#to_xdm_json(): JSON出力方法による直列化。
+    //
+    #[test]
+    fn test_to_xdm_json() {
+        let doc = new_document(r#"<root a="1"><b>x &amp; "y"</b></root>"#).unwrap();
+        let cases = [
+            ( "()", "null" ),
+            ( "'a\"b\\c'", r#""a\"b\\c""# ),
+            ( "[1, (), [2.5, 1e2], true()]", "[1,null,[2.5,100],true]" ),
+            ( "map{1: 'x', 'k': map{}}", r#"{"1":"x","k":{}}"# ),
+            ( "[]", "[]" ),
+            ( "//b", r#""<b>x &amp; &quot;y&quot;</b>""# ),
+            ( "'2020-01-02T03:04:05Z' cast as xs:dateTime", r#""2020-01-02T03:04:05Z""# ),
+            ( "(1, 2)", "SERE0023" ),
+            ( "[(1, 2)]", "SERE0023" ),
+            ( "map{1: 'x', '1': 'y'}", "SERE0022" ),
+            ( "number('x')", "SERE0020" ),
+            ( "[concat#2]", "SERE0021" ),
+            ( "/root/@a", "SENR0001" ),
+        ];
+        for &(xpath, expected) in cases.iter() {
+            let result = match doc.eval_xpath(xpath).unwrap().to_xdm_json() {
+                Ok(json) => json,
+                Err(e) => e.to_string(),
+            };
+            assert!(result == expected || result.contains(expected),
+                    "xpath: {}: {}", xpath, result);
+        }
+    }
+
+    // -----------------------------------------------------------------
+    // EvalOptions#add_collection(): fn:collection() と、複数の文書に
+    // またがるノードの比較・結合。
+    //
+    #[test]
+    fn test_collection() {
+        let doc1 = new_document(r#"<d n="1"><p/><p/></d>"#).unwrap();
+        let doc2 = new_document(r#"<d n="2"><p/></d>"#).unwrap();
+        let doc3 = new_document(r#"<d n="3"/>"#).unwrap();
+        doc2.set_document_uri("http://example.com/corpus/2.xml");
+        let options = EvalOptions::new()
+            .base_uri("http://example.com/")
+            .add_collection("http://example.com/corpus", vec!{doc1.rc_clone(), doc2.rc_clone()})
+            .add_collection("", vec!{doc3.rc_clone()});
+        let main = new_document("<root/>").unwrap();
+        let cases = [
+            ( r#"count(collection("http://example.com/corpus"))"#, "2" ),
+            ( r#"collection("corpus")/d/@n/string()"#, r#"("1", "2")"# ),
+            ( r#"count(collection("corpus")//p)"#, "3" ),
+            ( "collection()/d/@n/string()", r#""3""# ),
+            ( "collection(())/d/@n/string()", r#""3""# ),
+            ( r#"doc("corpus/2.xml") is collection("corpus")[2]"#, "true" ),
+            ( r#"collection("corpus")[1] is collection("corpus")[2]"#, "false" ),
+            ( r#"count(collection("corpus")//p | collection("corpus")//p)"#, "3" ),
+            ( r#"count((collection("corpus")//p, /root) | /root)"#, "4" ),
+            ( r#"count(collection("corpus")//p intersect doc("corpus/2.xml")//p)"#, "1" ),
+            ( r#"let $a := collection("corpus")[1]//p[1], $b := doc("corpus/2.xml")//p return ($a << $b) != ($b << $a)"#, "true" ),
+            ( r#"let $p := collection("corpus")[1]//p return $p[1] << $p[2]"#, "true" ),
+            ( r#"collection("unknown")"#, "Dynamic Error" ),
+        ];
+        for &(xpath, expected) in cases.iter() {
+            let result = match main.eval_xpath_with_options(xpath, &options) {
+                Ok(result) => result.to_string(),
+                Err(e) => e.to_string(),
+            };
+            assert!(result.starts_with(expected), "xpath: {}: {}", xpath, result);
+        }
+
+        // 既定のコレクションがなければエラー。
+        assert!(main.eval_xpath("collection()").is_err());
+    }
+
+    // -----------------------------------------------------------------
+    // EvalOptions#allow_evaluate(true): am:evaluate() による動的評価。
+    //
+    #[test]
+    fn test_allow_evaluate() {
+        let doc = new_document(r#"<root><expr>count(//p)</expr><p>1</p><p>2</p></root>"#).unwrap();
+        let options = EvalOptions::new().allow_evaluate(true);
+        let cases = [
+            ( "am:evaluate('1 + 2')", "3" ),
+            ( "am:evaluate(//expr)", "2" ),
+            ( "let $x := 10 return am:evaluate('$x * 2')", "20" ),
+            ( "//p[am:evaluate('. = \"2\"')]", "<p>" ),
+            ( "//p[am:evaluate('position() = last()')]", "<p>" ),
+            ( "(//p)[2]/am:evaluate('string(.)')", r#""2""# ),
+            ( "am:evaluate('string(.)', (//p)[2])", r#""2""# ),
+            ( "am:evaluate('name(/*)', //p[1])", r#""root""# ),
+            ( "am:evaluate('.', ())", "()" ),
+            ( "am:evaluate('am:evaluate(\"count(//p)\")')", "2" ),
+        ];
+        for &(xpath, expected) in cases.iter() {
+            let result = doc.eval_xpath_with_options(xpath, &options).unwrap();
+            assert_eq!(result.to_string(), expected, "xpath: {}", xpath);
+        }
+
+        for xpath in ["am:evaluate('(1')", "am:evaluate('nosuch()')",
+                      "am:evaluate(('1', '2'))"].iter() {
+            assert!(doc.eval_xpath_with_options(xpath, &options).is_err(),
+                    "xpath: {}", xpath);
+        }
+
+        // 既定では許可しない。
+        let err = doc.eval_xpath("am:evaluate('1')").unwrap_err();
+        assert!(err.to_string().contains("XTDE3175"), "{}", err);
+    }
+
+    // -----------------------------------------------------------------
+    // EvalOptions#catalog() で設定したカタログによるURIの写像。
+    //
+    #[test]
+    fn test_catalog() {
+        use catalog::new_catalog;
+        let doc = new_document("<root/>").unwrap();
+        let catalog = new_catalog(r#"
+<catalog xmlns="urn:oasis:names:tc:entity:xmlns:xml:catalog">
+    <uri name="http://example.com/a.xml" uri="mem:/mirror/a.xml"/>
+    <rewriteSystem systemIdStartString="http://example.com/text/" rewritePrefix="mem:/mirror/"/>
+</catalog>"#).unwrap();
+        let options = EvalOptions::new()
+            .base_uri("http://example.com/index.xml")
+            .uri_scheme("mem", |uri| {
+                match uri {
+                    "mem:/mirror/a.xml" => Ok(String::from("<a><b/></a>")),
+                    "mem:/mirror/t" => Ok(String::from("text")),
+                    _ => Err(From::from("not found")),
+                }
+            })
+            .catalog(catalog);
+        let cases = [
+            ( "count(doc('a.xml')//b)", "1" ),
+            ( "document-uri(doc('a.xml'))", r#""http://example.com/a.xml""# ),
+            ( "unparsed-text('text/t')", r#""text""# ),
+            ( "doc-available('b.xml')", "false" ),
+        ];
+        for (xpath, expected) in cases.iter() {
+            let result = doc.eval_xpath_with_options(xpath, &options).unwrap();
+            assert_eq!(result.to_string(), *expected, "xpath: {}", xpath);
+        }
+    }
+
+    // -----------------------------------------------------------------
+    // 資源を非同期に取得する評価。
+    // 各資源の Future は、1回目の poll では Pending を返す。
+    //
+    #[cfg(feature = "async")]
+    #[test]
+    fn test_eval_xpath_async() {
+        use std::cell::RefCell;
+        use std::task::{RawWaker, RawWakerVTable, Waker};
+
+        struct Delayed {
+            polled: bool,
+            value: Option<Result<String, Box<Error>>>,
+        }
+        impl Future for Delayed {
+            type Output = Result<String, Box<Error>>;
+            fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+                let this = self.get_mut();
+                if ! this.polled {
+                    this.polled = true;
+                    cx.waker().wake_by_ref();
+                    return Poll::Pending;
+                }
+                return Poll::Ready(this.value.take().unwrap());
+            }
+        }
+
+        struct MemResolver {
+            log: Rc<RefCell<Vec<String>>>,
+        }
+        impl AsyncDocumentResolver for MemResolver {
+            fn fetch(&self, uri: &str) -> Pin<Box<Future<Output = Result<String, Box<Error>>>>> {
+                self.log.borrow_mut().push(String::from(uri));
+                let value = match uri {
+                    "mem:a" => Ok(String::from("<a><b/><b/></a>")),
+                    "mem:text" => Ok(String::from("hello")),
+                    _ => Err(From::from("not found")),
+                };
+                return Box::pin(Delayed{ polled: false, value: Some(value) });
+            }
+        }
+
+        fn noop_raw_waker() -> RawWaker {
+            fn clone(_: *const ()) -> RawWaker { return noop_raw_waker(); }
+            fn noop(_: *const ()) {}
+            static VTABLE: RawWakerVTable = RawWakerVTable::new(clone, noop, noop, noop);
+            return RawWaker::new(0 as *const (), &VTABLE);
+        }
+
+        fn block_on(mut future: EvalXPathAsync) -> (Result<Sequence, Box<Error>>, usize) {
+            let waker = unsafe { Waker::from_raw(noop_raw_waker()) };
+            let mut cx = Context::from_waker(&waker);
+            let mut n_polls = 0;
+            loop {
+                n_polls += 1;
+                if let Poll::Ready(result) = Pin::new(&mut future).poll(&mut cx) {
+                    return (result, n_polls);
+                }
+            }
+        }
+
+        let doc = new_document("<root/>").unwrap();
+        let log = Rc::new(RefCell::new(vec!{}));
+        let resolver = MemResolver{ log: Rc::clone(&log) };
+
+        let xpath = r#"count(doc("mem:a")//b), unparsed-text("mem:text"), doc-available("mem:none"), count(doc("mem:a")/a)"#;
+        let (result, n_polls) = block_on(doc.eval_xpath_async(xpath, &resolver));
+        assert_eq!(result.unwrap().to_string(), r#"(2, "hello", false, 1)"#);
+        assert_eq!(n_polls, 2);
+        assert_eq!(*log.borrow(), vec!{"mem:a", "mem:text", "mem:none"});
+
+        let (result, _) = block_on(doc.eval_xpath_async(r#"doc("mem:" || "a")"#, &resolver));
+        assert!(result.unwrap_err().to_string().contains("FODC0002"));
+
+        let (result, n_polls) = block_on(doc.eval_xpath_async("count(", &resolver));
+        assert!(result.is_err());
+        assert_eq!(n_polls, 1);
+    }
+
+    // -----------------------------------------------------------------
+    // 入れ子の深い式、深い文書でスタックを使い切らない。
+    //
+    #[test]
+    fn test_deep_nesting() {
+        let doc = new_document("<root/>").unwrap();
+
+        let xpath = format!("{}1{}", "(".repeat(10000), ")".repeat(10000));
+        let err = doc.eval_xpath(&xpath).err().unwrap();
+        assert!(err.to_string().starts_with("Static Error"), "{}", err);
+
+        let xpath = format!("1{}", " + 1".repeat(100000));
+        assert!(XPath::compile(&xpath).is_ok());
+        let err = doc.eval_xpath(&xpath).err().unwrap();
+        assert!(err.to_string().starts_with("Dynamic Error"), "{}", err);
+
+        let xpath = format!("{}1", "-".repeat(100000));
+        assert!(doc.eval_xpath(&xpath).is_err());
+        assert_eq!(doc.eval_xpath("--1").unwrap().to_string(), "1");
+        assert_eq!(doc.eval_xpath("- + -1").unwrap().to_string(), "1");
+
+        let xpath = format!("{}1{}", "(".repeat(10), ")".repeat(10));
+        assert_eq!(doc.eval_xpath(&xpath).unwrap().to_string(), "1");
+
+        let depth = 100000;
+        let xml = format!("{}x{}", "<a>".repeat(depth), "</a>".repeat(depth));
+        let doc = new_document(&xml).unwrap();
+        assert_eq!(doc.to_string(), xml);
+        assert_eq!(doc.eval_xpath("string(/)").unwrap().to_string(), r#""x""#);
+        let leaf = doc.get_first_node("/descendant::text()").unwrap();
+        assert_eq!(leaf.value(), "x");
+        assert_eq!(leaf.eval_xpath("count(ancestor::a)").unwrap().to_string(),
+                        depth.to_string());
+        assert_eq!(doc.root_element().to_standalone_string(), xml);
+        let part = new_document_partial(&xml, "/a/a").unwrap();
+        assert_eq!(part.to_string(), xml["<a>".len() .. xml.len() - "</a>".len()]);
+        let other_doc = new_document("<b/>").unwrap();
+        let mut other = other_doc.root_element();
+        other.append_child(&doc.root_element());
+        assert_eq!(other.to_string(), format!("<b>{}</b>", xml));
+        drop(doc);
+        assert_eq!(leaf.value(), "x");
+    }
+
     // -----------------------------------------------------------------
     // - child::para は文脈ノードの子の para 要素すべてを選択する。
     // - para は文脈ノードの para 子要素すべてを選択する。
@@ -1083,5 +3743,50 @@ for $student in /root/student return
         ]);
     }
 
+    // -----------------------------------------------------------------
+    // Value: Rust の型との変換
+    //
+    #[test]
+    fn test_value_conversion() {
+        let xml = compress_spaces(r#"
+<root>
+    <a v="3">x</a>
+    <a v="4">y</a>
+</root>
+        "#);
+        let doc = new_document(&xml).unwrap();
+
+        let v = doc.eval_xpath("count(//a)").unwrap();
+        assert_eq!(i64::try_from(v).unwrap(), 2);
+        let v = doc.eval_xpath("//a[1]/@v").unwrap();
+        assert_eq!(v.as_f64().unwrap(), 3.0);
+        assert_eq!(v.as_string().unwrap(), "3");
+        assert_eq!(f64::try_from(v).unwrap(), 3.0);
+        let v = doc.eval_xpath("()").unwrap();
+        assert!(v.as_f64().unwrap().is_nan());
+        assert_eq!(v.as_string().unwrap(), "");
+        assert_eq!(v.as_bool().unwrap(), false);
+        assert!(String::try_from(v).is_err());
+
+        let v = doc.eval_xpath("//a").unwrap();
+        assert_eq!(v.as_bool().unwrap(), true);
+        assert!(v.as_string().is_err());
+        let s: Vec<String> = v.iter().map(|i| i.as_string().unwrap()).collect();
+        assert_eq!(s, vec!["x", "y"]);
+        let nodes = Vec::<NodePtr>::try_from(v.clone()).unwrap();
+        assert_eq!(nodes.len(), 2);
+        assert!(NodePtr::try_from(v).is_err());
+
+        let v = doc.eval_xpath("(1, 'a')").unwrap();
+        assert!(v.as_nodes().is_err());
+        assert!(bool::try_from(v.clone()).is_err());
+        let types: Vec<String> = (&v).into_iter().map(|i| i.xs_type()).collect();
+        assert_eq!(types, vec!["xs:integer", "xs:string"]);
+
+        assert_eq!(Value::from(true).to_string(), "true");
+        assert_eq!(Value::from(5i64).to_string(), "5");
+        assert_eq!(Value::from("s").to_string(), r#""s""#);
+        assert_eq!(Value::from(nodes).len(), 2);
+    }
 }
 
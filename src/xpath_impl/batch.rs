@@ -0,0 +1,277 @@
+//
+// xpath_impl/batch.rs
+//
+// amxml: XML processor with XPath.
+// Copyright (C) 2018 KOYAMA Hiro <tac@amris.co.jp>
+//
+
+#[cfg(feature = "no_std")]
+use std::prelude::*;
+
+use dom::*;
+use xpath_impl::eval::*;
+use xpath_impl::parser::*;
+use xpath_impl::xsequence::*;
+
+// ---------------------------------------------------------------------
+// 単純パスのステップの軸。
+//
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum StepAxis {
+    Child,
+    Descendant,
+    DescendantOrSelf,
+    SelfNode,
+    Attribute,
+}
+
+// =====================================================================
+// 単純パス: 述語のない、下向きの軸 (child、descendant、
+// descendant-or-self、self、attribute) のステップだけから成るパス。
+// 例: /root/item/@id、//item/name、.//text()
+//
+#[derive(Debug, Clone)]
+pub struct SimplePath {
+    absolute: bool,
+    steps: Vec<(StepAxis, XNodePtr)>,
+            // (軸, ノード・テストを表すAxisNNNNのxnode)
+}
+
+// ---------------------------------------------------------------------
+// xnode が単純パスならば、そのステップ列を返す。そうでなければNone。
+//
+pub fn simple_path(xnode: &XNodePtr) -> Option<SimplePath> {
+    let mut path = SimplePath{ absolute: false, steps: vec!{} };
+    let mut curr = xnode.clone();
+    loop {
+        let (step, rest) = match get_xnode_type(&curr) {
+            XNodeType::OperatorPath => (get_left(&curr), Some(get_right(&curr))),
+            _ => (curr.clone(), None),
+        };
+        let axis = match get_xnode_type(&step) {
+            XNodeType::AxisRoot if path.steps.is_empty() && ! path.absolute => {
+                path.absolute = true;
+                None
+            },
+            XNodeType::ContextItem if path.steps.is_empty() && ! path.absolute => {
+                None
+            },
+            XNodeType::AxisChild => Some(StepAxis::Child),
+            XNodeType::AxisDescendant => Some(StepAxis::Descendant),
+            XNodeType::AxisDescendantOrSelf => Some(StepAxis::DescendantOrSelf),
+            XNodeType::AxisSelf => Some(StepAxis::SelfNode),
+            XNodeType::AxisAttribute => Some(StepAxis::Attribute),
+            _ => return None,
+        };
+        if let Some(axis) = axis {
+            if ! is_nil_xnode(&get_right(&step)) {
+                return None;            // 述語がある。
+            }
+            path.steps.push((axis, step.clone()));
+        }
+        match rest {
+            Some(ref rest) if ! is_nil_xnode(rest) => curr = rest.clone(),
+            _ => break,
+        }
+    }
+    return Some(path);
+}
+
+// =====================================================================
+// 単純パスの集まりを、文書を1回たどるだけで評価する。
+// 結果は paths と同じ順で、それぞれ文書順のノード列。
+//
+// 各ノードについて、そのノードを文脈ノードとして次に照合すべき
+// ステップの集合 (パス番号, ステップ番号) を求めながら、文書順にたどる
+// (非決定性オートマトン)。ステップ番号がパスの長さに達したノードが、
+// そのパスの結果である。
+// 絶対パスがあれば文書根から、なければ start_node からたどる。
+// 相対パスは、start_node を訪れたときに開始する。
+//
+pub fn match_simple_paths(start_node: &NodePtr, paths: &[SimplePath]) -> Vec<XSequence> {
+    let mut results: Vec<Vec<NodePtr>> = vec![vec!{}; paths.len()];
+    let has_absolute = paths.iter().any(|p| p.absolute);
+    let top = if has_absolute { start_node.root() } else { start_node.rc_clone() };
+    let on_start_path = |node: &NodePtr| -> bool {
+        // start_node (またはその祖先) であれば、まだ相対パスを
+        // 開始していないので、状態がなくても枝刈りしない。
+        let mut curr = Some(start_node.rc_clone());
+        while let Some(n) = curr {
+            if n == *node {
+                return true;
+            }
+            curr = n.parent();
+        }
+        return false;
+    };
+
+    let mut stack: Vec<(NodePtr, Vec<(usize, usize)>)> = vec!{(top, vec!{})};
+    while let Some((node, inherited)) = stack.pop() {
+        let mut states = inherited;
+        if node == *start_node {
+            for (i, path) in paths.iter().enumerate() {
+                if ! path.absolute {
+                    states.push((i, 0));
+                }
+            }
+        }
+        if node == node.root() {
+            for (i, path) in paths.iter().enumerate() {
+                if path.absolute {
+                    states.push((i, 0));
+                }
+            }
+        }
+        let states = close_states(&node, states, paths);
+        collect_results(&node, &states, paths, &mut results);
+
+        let mut attr_states: Vec<(usize, usize)> = vec!{};
+        let mut child_states: Vec<(usize, usize)> = vec!{};
+        for &(i, k) in states.iter() {
+            if k < paths[i].steps.len() {
+                match paths[i].steps[k].0 {
+                    StepAxis::Attribute => attr_states.push((i, k)),
+                    StepAxis::Child |
+                    StepAxis::Descendant |
+                    StepAxis::DescendantOrSelf => child_states.push((i, k)),
+                    StepAxis::SelfNode => {},
+                }
+            }
+        }
+
+        if ! attr_states.is_empty() {
+            for attr in node.attribute_nodes().iter() {
+                let states = advance(attr, &attr_states, paths);
+                let states = close_states(attr, states, paths);
+                collect_results(attr, &states, paths, &mut results);
+            }
+        }
+
+        let mut children = node.children();
+        children.reverse();
+        for child in children.into_iter() {
+            let states = advance(&child, &child_states, paths);
+            if ! states.is_empty() || on_start_path(&child) {
+                stack.push((child, states));
+            }
+        }
+    }
+
+    return results.iter().map(|nodes| new_xsequence_from_node_array(nodes)).collect();
+}
+
+// ---------------------------------------------------------------------
+// 親 (または属性の場合は所有要素) の状態 states から、ノード node の
+// 状態を求める。
+// child、attribute: ノード・テストに合格すれば次のステップへ。
+// descendant、descendant-or-self: 加えて、node の子孫についても
+// 同じステップを照合するので、状態を引き継ぐ。
+//
+fn advance(node: &NodePtr, states: &Vec<(usize, usize)>,
+                paths: &[SimplePath]) -> Vec<(usize, usize)> {
+    let mut next: Vec<(usize, usize)> = vec!{};
+    for &(i, k) in states.iter() {
+        let (axis, ref xnode) = paths[i].steps[k];
+        match axis {
+            StepAxis::Child | StepAxis::Attribute | StepAxis::Descendant => {
+                if match_node_test(node, xnode) {
+                    next.push((i, k + 1));
+                }
+                if axis == StepAxis::Descendant {
+                    next.push((i, k));
+                }
+            },
+            StepAxis::DescendantOrSelf => {
+                next.push((i, k));      // 自身の照合は close_states で。
+            },
+            StepAxis::SelfNode => {},
+        }
+    }
+    return next;
+}
+
+// ---------------------------------------------------------------------
+// self、descendant-or-self のステップは、ノード自身をも対象とするので、
+// 合格すれば同じノードで次のステップに進める (ε遷移の閉包)。
+// 重複を除いて返す。
+//
+fn close_states(node: &NodePtr, states: Vec<(usize, usize)>,
+                paths: &[SimplePath]) -> Vec<(usize, usize)> {
+    let mut closed: Vec<(usize, usize)> = vec!{};
+    let mut work = states;
+    while let Some((i, k)) = work.pop() {
+        if closed.contains(&(i, k)) {
+            continue;
+        }
+        closed.push((i, k));
+        if k < paths[i].steps.len() {
+            let (axis, ref xnode) = paths[i].steps[k];
+            if (axis == StepAxis::SelfNode || axis == StepAxis::DescendantOrSelf) &&
+               match_node_test(node, xnode) {
+                work.push((i, k + 1));
+            }
+        }
+    }
+    return closed;
+}
+
+// ---------------------------------------------------------------------
+//
+fn collect_results(node: &NodePtr, states: &Vec<(usize, usize)>,
+                paths: &[SimplePath], results: &mut Vec<Vec<NodePtr>>) {
+    let mut done: Vec<usize> = vec!{};
+    for &(i, k) in states.iter() {
+        if k == paths[i].steps.len() && ! done.contains(&i) {
+            results[i].push(node.rc_clone());
+            done.push(i);
+        }
+    }
+}
+
+// =====================================================================
+//
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // -----------------------------------------------------------------
+    // 1回の走査で得た結果が、個別に評価した結果と一致すること。
+    //
+    #[test]
+    fn test_match_simple_paths() {
+        let xml = r#"<?xml version="1.0"?>
+<root id="r"><a id="1"><b id="2">t<b id="3"/></b></a><b id="4" x="y"/><!--c--></root>"#;
+        let doc = new_document(xml).unwrap();
+        let a = doc.get_first_node("//a").unwrap();
+        let xpaths = [
+            "/", "/root", "/root/a/b", "//b", "//b/@id", "//@*", "//node()",
+            "/descendant::b", "//b//b", "b/b", "b", "descendant-or-self::*",
+            ".//text()", "self::a", "self::b", "/root/*/b//@id", "//comment()",
+            "@id", "/root/b/@x/self::node()",
+        ];
+        let mut paths = vec!{};
+        for xpath in xpaths.iter() {
+            let xnode = compile_xpath(&String::from(*xpath)).unwrap();
+            paths.push(simple_path(&xnode).expect(xpath));
+        }
+        for start in [doc.rc_clone(), a].iter() {
+            let results = match_simple_paths(start, &paths);
+            for (i, xpath) in xpaths.iter().enumerate() {
+                let xnode = compile_xpath(&String::from(*xpath)).unwrap();
+                let expected = match_xpath(start, &xnode).unwrap();
+                assert_eq!(results[i].to_string(), expected.to_string(), "{}", xpath);
+            }
+        }
+    }
+
+    // -----------------------------------------------------------------
+    //
+    #[test]
+    fn test_simple_path() {
+        for xpath in ["/root/a[1]", "..", "count(//a)", "//a | //b",
+                      "a/following-sibling::b", "$x/a", "a/string()"].iter() {
+            let xnode = compile_xpath(&String::from(*xpath)).unwrap();
+            assert!(simple_path(&xnode).is_none(), "{}", xpath);
+        }
+    }
+}
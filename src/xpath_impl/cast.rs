@@ -0,0 +1,397 @@
+//
+// xpath_impl/cast.rs
+//
+// amxml: XML processor with XPath.
+// Copyright (C) 2018 KOYAMA Hiro <tac@amris.co.jp>
+//
+// 原子型へのキャスト (XPath and XQuery Functions and Operators 3.1, 19)。
+// キャスト先の型ごとの函数をCAST_TBLに登録する。新しい型を実装したら、
+// その函数をCAST_TBLに追加すればよい。
+//
+// - 原子化した値 (xs:string、xs:untypedAtomic相当) からのキャストは、
+//   キャスト先の型の字句表現として解析する。字句表現として正しくなければ
+//   エラー [err:FORG0001]。
+// - NaN、無限大を xs:decimal、xs:integer にキャストしようとすると
+//   エラー [err:FOCA0002]、xs:integer の範囲を超えるとエラー
+//   [err:FOCA0003]。
+// - キャストできない型の組み合わせ (例えば xs:boolean から xs:dateTime)
+//   はエラー [err:XPTY0004]。
+// - xs:QName は未実装 (名前空間接頭辞を解決する静的文脈がない)。
+//
+
+use std::error::Error;
+use std::f64;
+use std::i64;
+#[cfg(feature = "no_std")]
+use std::prelude::*;
+
+use xmlerror::*;
+use binary::*;
+use datetime::*;
+use xpath_impl::xitem::*;
+
+// ---------------------------------------------------------------------
+// キャスト先の型と、キャストする函数 (原子化した値を受け取る)。
+//
+const CAST_TBL: [(
+        &str,                   // 型名
+        fn(&XItem) -> Result<XItem, Box<Error>>);
+                                // キャストする函数
+        10] = [
+    ( "xs:string",          cast_to_string ),
+    ( "xs:untypedAtomic",   cast_to_string ),
+    ( "xs:anyURI",          cast_to_any_uri ),
+    ( "xs:double",          cast_to_double ),
+    ( "xs:decimal",         cast_to_decimal ),
+    ( "xs:integer",         cast_to_integer ),
+    ( "xs:boolean",         cast_to_boolean ),
+    ( "xs:dateTime",        cast_to_date_time ),
+    ( "xs:base64Binary",    cast_to_base64_binary ),
+    ( "xs:hexBinary",       cast_to_hex_binary ),
+];
+
+// ---------------------------------------------------------------------
+// 型名を正規化する: 接頭辞のない型名 ("integer") には "xs:" を補う。
+//
+fn normalize_type_name(type_name: &str) -> String {
+    if type_name.contains(":") {
+        return String::from(type_name);
+    }
+    return format!("xs:{}", type_name);
+}
+
+// ---------------------------------------------------------------------
+// CAST_TBLに登録してある (キャスト先として実装してある) 型か否か。
+//
+pub fn is_cast_target(type_name: &str) -> bool {
+    let type_name = normalize_type_name(type_name);
+    return CAST_TBL.iter().any(|(t_type_name, _)| *t_type_name == type_name);
+}
+
+// ---------------------------------------------------------------------
+// アイテムを原子化し、type_name の型にキャストする。
+//
+pub fn cast_xitem(xitem: &XItem, type_name: &str) -> Result<XItem, Box<Error>> {
+    let type_name = normalize_type_name(type_name);
+    for (t_type_name, t_func) in CAST_TBL.iter() {
+        if *t_type_name == type_name {
+            return t_func(&xitem.atomize());
+        }
+    }
+    return Err(static_error!(
+            "Unknown atomic type for cast: {} [err:XPST0051]", type_name));
+}
+
+// ---------------------------------------------------------------------
+// キャストできない型の組み合わせのエラー。
+//
+fn cant_cast(xitem: &XItem, type_name: &str) -> Box<Error> {
+    return type_error!("Item {}: can't cast {} to {} [err:XPTY0004]",
+                xitem.describe(), xitem.xs_type(), type_name);
+}
+
+// ---------------------------------------------------------------------
+// 字句表現として正しくないことのエラー。
+//
+fn invalid_lexical(value: &str, type_name: &str) -> Box<Error> {
+    return dynamic_error!("Invalid {}: \"{}\" [err:FORG0001]", type_name, value);
+}
+
+// ---------------------------------------------------------------------
+// 字句表現として解析する文字列 (xs:string、xs:untypedAtomic、
+// xs:anyURI の値)。それ以外ならばNone。
+//
+fn lexical_value(xitem: &XItem) -> Option<&str> {
+    match xitem {
+        XItem::XIString{value} => return Some(value.as_str()),
+        XItem::XIAnyURI{value} => return Some(value.as_str()),
+        _ => return None,
+    }
+}
+
+// ---------------------------------------------------------------------
+// 空白の縮約 (whiteSpace="collapse")。
+//
+fn collapse_whitespace(s: &str) -> String {
+    return s.split_whitespace().collect::<Vec<&str>>().join(" ");
+}
+
+// ---------------------------------------------------------------------
+// xs:double の字句表現 (前後の空白は除去済み) を数値に変換する。
+// Rust の parse() が受け付ける "inf"、"infinity" などは受け付けない。
+//
+pub fn parse_xs_double(s: &str) -> Option<f64> {
+    match s {
+        "INF" | "+INF" => return Some(f64::INFINITY),
+        "-INF" => return Some(f64::NEG_INFINITY),
+        "NaN" => return Some(f64::NAN),
+        _ => {},
+    }
+    if s.chars().any(|c| c.is_alphabetic() && c != 'e' && c != 'E') {
+        return None;
+    }
+    return s.parse::<f64>().ok();
+}
+
+// ---------------------------------------------------------------------
+// xs:decimal の字句表現: 符号、数字列、小数点 (指数部はない)。
+//
+pub fn parse_xs_decimal(s: &str) -> Option<f64> {
+    let digits = s.trim_start_matches(|c| c == '+' || c == '-');
+    if s.len() - digits.len() > 1 {
+        return None;
+    }
+    let mut has_digit = false;
+    let mut has_point = false;
+    for c in digits.chars() {
+        match c {
+            '0' ..= '9' => has_digit = true,
+            '.' if ! has_point => has_point = true,
+            _ => return None,
+        }
+    }
+    if ! has_digit {
+        return None;
+    }
+    return s.parse::<f64>().ok();
+}
+
+// ---------------------------------------------------------------------
+// xs:integer の字句表現: 符号と数字列。
+// 範囲を超える場合はエラー [err:FOCA0003]。
+//
+fn parse_xs_integer(s: &str) -> Result<Option<i64>, Box<Error>> {
+    let digits = s.trim_start_matches(|c| c == '+' || c == '-');
+    if s.len() - digits.len() > 1 || digits == "" ||
+       ! digits.chars().all(|c| c.is_ascii_digit()) {
+        return Ok(None);
+    }
+    match s.parse::<i64>() {
+        Ok(n) => return Ok(Some(n)),
+        Err(_) => return Err(dynamic_error!(
+                "Integer out of range: {} [err:FOCA0003]", s)),
+    }
+}
+
+// ---------------------------------------------------------------------
+// 浮動小数点数を xs:integer に (0方向に切り捨てて) 変換する。
+//
+fn f64_to_integer(f: f64) -> Result<i64, Box<Error>> {
+    if f.is_nan() || f.is_infinite() {
+        return Err(dynamic_error!(
+            "Can't cast {} to xs:integer [err:FOCA0002]", f));
+    }
+    let t = f.trunc();
+    if t < (i64::MIN as f64) || (i64::MAX as f64) <= t {
+        return Err(dynamic_error!(
+            "Integer out of range: {} [err:FOCA0003]", f));
+    }
+    return Ok(t as i64);
+}
+
+// ---------------------------------------------------------------------
+//
+fn cast_to_string(xitem: &XItem) -> Result<XItem, Box<Error>> {
+    match xitem {
+        XItem::XItemXNodePtr{..} | XItem::XIMap{..} | XItem::XIArray{..} => {
+            return Err(cant_cast(xitem, "xs:string"));
+        },
+        _ => return Ok(new_xitem_string(&xitem.get_as_raw_string()?)),
+    }
+}
+
+// ---------------------------------------------------------------------
+//
+fn cast_to_any_uri(xitem: &XItem) -> Result<XItem, Box<Error>> {
+    match lexical_value(xitem) {
+        Some(s) => return Ok(new_xitem_any_uri(&collapse_whitespace(s))),
+        None => return Err(cant_cast(xitem, "xs:anyURI")),
+    }
+}
+
+// ---------------------------------------------------------------------
+//
+fn cast_to_double(xitem: &XItem) -> Result<XItem, Box<Error>> {
+    if let Some(s) = lexical_value(xitem) {
+        match parse_xs_double(s.trim()) {
+            Some(d) => return Ok(new_xitem_double(d)),
+            None => return Err(invalid_lexical(s, "xs:double")),
+        }
+    }
+    match xitem {
+        XItem::XIInteger{value} => return Ok(new_xitem_double(*value as f64)),
+        XItem::XIDecimal{value} => return Ok(new_xitem_double(*value)),
+        XItem::XIDouble{value} => return Ok(new_xitem_double(*value)),
+        XItem::XIBoolean{value} => {
+            return Ok(new_xitem_double(if *value { 1.0 } else { 0.0 }));
+        },
+        _ => return Err(cant_cast(xitem, "xs:double")),
+    }
+}
+
+// ---------------------------------------------------------------------
+//
+fn cast_to_decimal(xitem: &XItem) -> Result<XItem, Box<Error>> {
+    if let Some(s) = lexical_value(xitem) {
+        match parse_xs_decimal(s.trim()) {
+            Some(d) => return Ok(new_xitem_decimal(d)),
+            None => return Err(invalid_lexical(s, "xs:decimal")),
+        }
+    }
+    match xitem {
+        XItem::XIInteger{value} => return Ok(new_xitem_decimal(*value as f64)),
+        XItem::XIDecimal{value} => return Ok(new_xitem_decimal(*value)),
+        XItem::XIDouble{value} => {
+            if value.is_nan() || value.is_infinite() {
+                return Err(dynamic_error!(
+                    "Can't cast {} to xs:decimal [err:FOCA0002]", xitem));
+            }
+            return Ok(new_xitem_decimal(*value));
+        },
+        XItem::XIBoolean{value} => {
+            return Ok(new_xitem_decimal(if *value { 1.0 } else { 0.0 }));
+        },
+        _ => return Err(cant_cast(xitem, "xs:decimal")),
+    }
+}
+
+// ---------------------------------------------------------------------
+//
+fn cast_to_integer(xitem: &XItem) -> Result<XItem, Box<Error>> {
+    if let Some(s) = lexical_value(xitem) {
+        match parse_xs_integer(s.trim())? {
+            Some(n) => return Ok(new_xitem_integer(n)),
+            None => return Err(invalid_lexical(s, "xs:integer")),
+        }
+    }
+    match xitem {
+        XItem::XIInteger{value} => return Ok(new_xitem_integer(*value)),
+        XItem::XIDecimal{value} | XItem::XIDouble{value} => {
+            return Ok(new_xitem_integer(f64_to_integer(*value)?));
+        },
+        XItem::XIBoolean{value} => {
+            return Ok(new_xitem_integer(if *value { 1 } else { 0 }));
+        },
+        _ => return Err(cant_cast(xitem, "xs:integer")),
+    }
+}
+
+// ---------------------------------------------------------------------
+//
+fn cast_to_boolean(xitem: &XItem) -> Result<XItem, Box<Error>> {
+    if let Some(s) = lexical_value(xitem) {
+        match s.trim() {
+            "true" | "1" => return Ok(new_xitem_boolean(true)),
+            "false" | "0" => return Ok(new_xitem_boolean(false)),
+            _ => return Err(invalid_lexical(s, "xs:boolean")),
+        }
+    }
+    match xitem {
+        XItem::XIInteger{value} => return Ok(new_xitem_boolean(*value != 0)),
+        XItem::XIDecimal{value} | XItem::XIDouble{value} => {
+            return Ok(new_xitem_boolean(*value != 0.0 && ! value.is_nan()));
+        },
+        XItem::XIBoolean{value} => return Ok(new_xitem_boolean(*value)),
+        _ => return Err(cant_cast(xitem, "xs:boolean")),
+    }
+}
+
+// ---------------------------------------------------------------------
+//
+fn cast_to_date_time(xitem: &XItem) -> Result<XItem, Box<Error>> {
+    if let Some(s) = lexical_value(xitem) {
+        return Ok(new_xitem_date_time(&parse_date_time(s)?));
+    }
+    match xitem {
+        XItem::XIDateTime{value} => return Ok(new_xitem_date_time(value)),
+        _ => return Err(cant_cast(xitem, "xs:dateTime")),
+    }
+}
+
+// ---------------------------------------------------------------------
+//
+fn cast_to_base64_binary(xitem: &XItem) -> Result<XItem, Box<Error>> {
+    if let Some(s) = lexical_value(xitem) {
+        return Ok(new_xitem_base64_binary(&decode_base64(s)?));
+    }
+    match xitem.get_as_raw_binary() {
+        Ok(octets) => return Ok(new_xitem_base64_binary(&octets)),
+        Err(_) => return Err(cant_cast(xitem, "xs:base64Binary")),
+    }
+}
+
+// ---------------------------------------------------------------------
+//
+fn cast_to_hex_binary(xitem: &XItem) -> Result<XItem, Box<Error>> {
+    if let Some(s) = lexical_value(xitem) {
+        return Ok(new_xitem_hex_binary(&decode_hex(s.trim())?));
+    }
+    match xitem.get_as_raw_binary() {
+        Ok(octets) => return Ok(new_xitem_hex_binary(&octets)),
+        Err(_) => return Err(cant_cast(xitem, "xs:hexBinary")),
+    }
+}
+
+// =====================================================================
+//
+#[cfg(test)]
+mod test {
+    use xpath_impl::helpers::subtest_eval_xpath;
+
+    // -----------------------------------------------------------------
+    // キャストの組み合わせと、エラーの種類。
+    //
+    #[test]
+    fn test_cast_matrix() {
+        let xml = r#"<root base="base"><n> 12 </n><t>true</t><u> http://example.com/a  b </u></root>"#;
+
+        subtest_eval_xpath("cast_matrix", &xml, &[
+            // 文字列から
+            ( "' 42 ' cast as xs:integer", "42" ),
+            ( "'-0012' cast as xs:integer", "-12" ),
+            ( "'x' cast as xs:integer", "Dynamic Error" ),
+            ( "'1.5' cast as xs:integer", "Dynamic Error" ),
+            ( "'99999999999999999999' cast as xs:integer", "Dynamic Error" ),
+            ( "'1.5' cast as xs:decimal", "1.5" ),
+            ( "'.5' cast as xs:decimal", "0.5" ),
+            ( "'1e3' cast as xs:decimal", "Dynamic Error" ),
+            ( "'1e3' cast as xs:double", "1e3" ),
+            ( "'-INF' cast as xs:double", "-Infinity" ),
+            ( "'inf' castable as xs:double", "false" ),
+            ( "' 1 ' cast as xs:boolean", "true" ),
+            ( "'yes' castable as xs:boolean", "false" ),
+            ( "'a b' cast as xs:anyURI", r#"xs:anyURI("a b")"# ),
+            ( "n cast as xs:integer", "12" ),
+            ( "t cast as xs:boolean", "true" ),
+            ( "string(u cast as xs:anyURI)", r#""http://example.com/a b""# ),
+            // 数値から
+            ( "2.7 cast as xs:integer", "2" ),
+            ( "-2.7e0 cast as xs:integer", "-2" ),
+            ( "('NaN' cast as xs:double) cast as xs:integer", "Dynamic Error" ),
+            ( "(1 div 0e0) cast as xs:decimal", "Dynamic Error" ),
+            ( "1e30 cast as xs:integer", "Dynamic Error" ),
+            ( "0 cast as xs:boolean", "false" ),
+            ( "12 cast as xs:double", "1.2e1" ),
+            ( "12 cast as xs:string", r#""12""# ),
+            ( "12 cast as xs:untypedAtomic", r#""12""# ),
+            // 真偽値から
+            ( "true() cast as xs:integer", "1" ),
+            ( "false() cast as xs:double", "0e0" ),
+            ( "true() cast as xs:string", r#""true""# ),
+            // キャストできない組み合わせ
+            ( "true() cast as xs:dateTime", "Type Error" ),
+            ( "1 cast as xs:anyURI", "Type Error" ),
+            ( "1 cast as xs:hexBinary", "Type Error" ),
+            ( "('00' cast as xs:hexBinary) cast as xs:integer", "Type Error" ),
+            ( "('2020-01-02T03:04:05Z' cast as xs:dateTime) cast as xs:double", "Type Error" ),
+            ( "1 castable as xs:dateTime", "false" ),
+            // 未知の型
+            ( "1 cast as xs:NOTATION", "Static Error" ),
+            ( "1 castable as xs:foo", "false" ),
+            // anyURI
+            ( "('http://a/' cast as xs:anyURI) instance of xs:anyURI", "true" ),
+            ( "('http://a/' cast as xs:anyURI) eq 'http://a/'", "true" ),
+            ( "('http://a/' cast as xs:anyURI) cast as xs:string", r#""http://a/""# ),
+        ]);
+    }
+}
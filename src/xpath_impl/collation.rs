@@ -0,0 +1,279 @@
+//
+// xpath_impl/collation.rs
+//
+// amxml: XML processor with XPath.
+// Copyright (C) 2018 KOYAMA Hiro <tac@amris.co.jp>
+//
+
+use std::cmp::Ordering;
+use std::error::Error;
+use std::fmt;
+use std::rc::Rc;
+#[cfg(feature = "no_std")]
+use std::prelude::*;
+
+use xmlerror::*;
+
+// ---------------------------------------------------------------------
+// 照合順序: 2つの文字列を比較する函数。
+//
+pub type Collation = Rc<Fn(&str, &str) -> Ordering>;
+
+// ---------------------------------------------------------------------
+// 組み込みの照合順序のURI。
+//
+pub const CODEPOINT_COLLATION_URI: &str =
+    "http://www.w3.org/2005/xpath-functions/collation/codepoint";
+pub const HTML_ASCII_CASE_INSENSITIVE_COLLATION_URI: &str =
+    "http://www.w3.org/2005/xpath-functions/collation/html-ascii-case-insensitive";
+#[cfg(feature = "icu")]
+pub const UCA_COLLATION_URI: &str = "http://www.w3.org/2013/collation/UCA";
+
+// =====================================================================
+// 照合順序の登録簿: URI → 照合順序。
+// 組み込みの照合順序 (codepoint、html-ascii-case-insensitive) は
+// 最初から登録してある。
+//
+#[derive(Clone)]
+pub struct CollationRegistry {
+    collations: Vec<(String, Collation)>,
+}
+
+impl fmt::Debug for CollationRegistry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let uris: Vec<&str> = self.collations.iter()
+                        .map(|&(ref uri, _)| uri.as_str()).collect();
+        return write!(f, "CollationRegistry {:?}", uris);
+    }
+}
+
+impl Default for CollationRegistry {
+    fn default() -> CollationRegistry {
+        return new_collation_registry();
+    }
+}
+
+pub fn new_collation_registry() -> CollationRegistry {
+    let codepoint: Collation = Rc::new(|a: &str, b: &str| a.cmp(b));
+    let ascii_case_insensitive: Collation = Rc::new(|a: &str, b: &str| {
+        return a.to_ascii_lowercase().cmp(&b.to_ascii_lowercase());
+    });
+    return CollationRegistry{
+        collations: vec!{
+            (String::from(CODEPOINT_COLLATION_URI), codepoint),
+            (String::from(HTML_ASCII_CASE_INSENSITIVE_COLLATION_URI),
+                ascii_case_insensitive),
+        },
+    };
+}
+
+impl CollationRegistry {
+    // -----------------------------------------------------------------
+    // 照合順序を登録する。同じURIで登録済みならば置き換える。
+    //
+    pub fn register(&mut self, uri: &str, collation: Collation) {
+        for entry in self.collations.iter_mut() {
+            if entry.0 == uri {
+                entry.1 = collation;
+                return;
+            }
+        }
+        self.collations.push((String::from(uri), collation));
+    }
+
+    // -----------------------------------------------------------------
+    // 組み込み以外の照合順序を登録してあるか否か。
+    //
+    #[cfg(any(test, not(feature = "no_std")))]
+    pub fn has_user_collations(&self) -> bool {
+        return self.collations.iter().any(|&(ref uri, _)|
+                uri != CODEPOINT_COLLATION_URI &&
+                uri != HTML_ASCII_CASE_INSENSITIVE_COLLATION_URI);
+    }
+
+    // -----------------------------------------------------------------
+    // URIに対応する照合順序。未登録ならばエラー [err:FOCH0002]。
+    // feature "icu" の場合、登録していない UCA の照合順序
+    // (http://www.w3.org/2013/collation/UCA?...) はその都度作る。
+    //
+    pub fn get(&self, uri: &str) -> Result<Collation, Box<Error>> {
+        for &(ref t_uri, ref collation) in self.collations.iter() {
+            if t_uri == uri {
+                return Ok(Rc::clone(collation));
+            }
+        }
+        #[cfg(feature = "icu")]
+        {
+            if uri == UCA_COLLATION_URI ||
+               uri.starts_with(&format!("{}?", UCA_COLLATION_URI)) {
+                return uca_collation(&uri[UCA_COLLATION_URI.len() ..]);
+            }
+        }
+        return Err(dynamic_error!(
+                "Unsupported collation: {} [err:FOCH0002]", uri));
+    }
+
+    // -----------------------------------------------------------------
+    // 既定の照合順序 (Unicode 符号位置の順)。
+    //
+    pub fn default_collation(&self) -> Collation {
+        return self.get(CODEPOINT_COLLATION_URI)
+                    .unwrap_or(Rc::new(|a: &str, b: &str| a.cmp(b)));
+    }
+}
+
+// ---------------------------------------------------------------------
+// UCA の照合順序 (XPath and XQuery Functions and Operators 3.1, 5.3.3)。
+// query は "?lang=de;strength=primary" のような形 (空でもよい)。
+// 扱うパラメーターは lang、strength、alternate、caseFirst、numeric。
+// それ以外のパラメーターや解釈できない値は、fallback=no ならばエラー
+// [err:FOCH0002]、そうでなければ (既定) 無視する。
+//
+#[cfg(feature = "icu")]
+fn uca_collation(query: &str) -> Result<Collation, Box<Error>> {
+    use icu_collator::{AlternateHandling, CaseFirst, Collator,
+                        CollatorOptions, Numeric, Strength};
+    use icu_locid::Locale;
+
+    let mut params: Vec<(&str, &str)> = vec!{};
+    for param in query.trim_start_matches('?').split(';') {
+        if param != "" {
+            let mut kv = param.splitn(2, '=');
+            let key = kv.next().unwrap_or("");
+            let value = kv.next().unwrap_or("");
+            params.push((key, value));
+        }
+    }
+    let fallback = params.iter().all(|&(key, value)|
+                        key != "fallback" || value != "no");
+
+    let mut locale = Locale::UND;
+    let mut options = CollatorOptions::new();
+    for &(key, value) in params.iter() {
+        let supported = match key {
+            "fallback" => value == "yes" || value == "no",
+            "lang" => match value.parse::<Locale>() {
+                Ok(l) => { locale = l; true },
+                Err(_) => false,
+            },
+            "strength" => {
+                options.strength = match value {
+                    "primary" | "1" => Some(Strength::Primary),
+                    "secondary" | "2" => Some(Strength::Secondary),
+                    "tertiary" | "3" => Some(Strength::Tertiary),
+                    "quaternary" | "4" => Some(Strength::Quaternary),
+                    "identical" | "5" => Some(Strength::Identical),
+                    _ => None,
+                };
+                options.strength.is_some()
+            },
+            "alternate" => {
+                options.alternate_handling = match value {
+                    "non-ignorable" => Some(AlternateHandling::NonIgnorable),
+                    "shifted" => Some(AlternateHandling::Shifted),
+                    _ => None,
+                };
+                options.alternate_handling.is_some()
+            },
+            "caseFirst" => {
+                options.case_first = match value {
+                    "upper" => Some(CaseFirst::UpperFirst),
+                    "lower" => Some(CaseFirst::LowerFirst),
+                    "off" => Some(CaseFirst::Off),
+                    _ => None,
+                };
+                options.case_first.is_some()
+            },
+            "numeric" => {
+                options.numeric = match value {
+                    "yes" => Some(Numeric::On),
+                    "no" => Some(Numeric::Off),
+                    _ => None,
+                };
+                options.numeric.is_some()
+            },
+            _ => false,
+        };
+        if ! supported && ! fallback {
+            return Err(dynamic_error!(
+                    "Unsupported collation parameter: {}={} [err:FOCH0002]",
+                    key, value));
+        }
+    }
+
+    let collator = match Collator::try_new(&(&locale).into(), options) {
+        Ok(collator) => collator,
+        Err(e) => return Err(dynamic_error!(
+                    "Unsupported collation: {}{}: {:?} [err:FOCH0002]",
+                    UCA_COLLATION_URI, query, e)),
+    };
+    return Ok(Rc::new(move |a: &str, b: &str| collator.compare(a, b)));
+}
+
+// =====================================================================
+//
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // -----------------------------------------------------------------
+    //
+    #[test]
+    fn test_collation_registry() {
+        let mut registry = new_collation_registry();
+        assert!(! registry.has_user_collations());
+
+        let codepoint = registry.get(CODEPOINT_COLLATION_URI).unwrap();
+        assert_eq!(codepoint("B", "a"), Ordering::Less);
+        let ci = registry.get(HTML_ASCII_CASE_INSENSITIVE_COLLATION_URI).unwrap();
+        assert_eq!(ci("B", "a"), Ordering::Greater);
+        assert_eq!(ci("ABC", "abc"), Ordering::Equal);
+        assert_eq!(ci("Ä", "ä"), Ordering::Less);   // ASCII以外は区別する。
+        assert!(registry.get("http://example.com/unknown").is_err());
+
+        registry.register("http://example.com/length",
+                Rc::new(|a: &str, b: &str| a.len().cmp(&b.len())));
+        assert!(registry.has_user_collations());
+        let by_length = registry.get("http://example.com/length").unwrap();
+        assert_eq!(by_length("zz", "aaa"), Ordering::Less);
+    }
+
+    // -----------------------------------------------------------------
+    //
+    #[cfg(feature = "icu")]
+    #[test]
+    fn test_uca_collation() {
+        let registry = new_collation_registry();
+        assert!(! registry.has_user_collations());
+
+        let uca = registry.get(UCA_COLLATION_URI).unwrap();
+        assert_eq!(uca("a", "B"), Ordering::Less);
+        assert_eq!(uca("a", "A"), Ordering::Less);
+        assert_eq!(uca("Äb", "az"), Ordering::Less);
+
+        let primary = registry.get(
+                "http://www.w3.org/2013/collation/UCA?strength=primary").unwrap();
+        assert_eq!(primary("a", "A"), Ordering::Equal);
+        assert_eq!(primary("résumé", "RESUME"), Ordering::Equal);
+
+        let numeric = registry.get(
+                "http://www.w3.org/2013/collation/UCA?numeric=yes").unwrap();
+        assert_eq!(numeric("a10", "a9"), Ordering::Greater);
+        assert_eq!(uca("a10", "a9"), Ordering::Less);
+
+        let sv = registry.get(
+                "http://www.w3.org/2013/collation/UCA?lang=sv").unwrap();
+        let de = registry.get(
+                "http://www.w3.org/2013/collation/UCA?lang=de").unwrap();
+        assert_eq!(sv("ä", "z"), Ordering::Greater);
+        assert_eq!(de("ä", "z"), Ordering::Less);
+
+        // 未知のパラメーター: fallback=no ならばエラー。
+        assert!(registry.get(
+            "http://www.w3.org/2013/collation/UCA?reorder=Grek").is_ok());
+        assert!(registry.get(
+            "http://www.w3.org/2013/collation/UCA?reorder=Grek;fallback=no").is_err());
+        assert!(registry.get(
+            "http://www.w3.org/2013/collation/UCA?strength=x;fallback=no").is_err());
+    }
+}
@@ -10,17 +10,24 @@ use std::cmp::Ordering;
 use std::error::Error;
 use std::f64;
 use std::i64;
+use std::rc::Rc;
 use std::str::FromStr;
 use std::usize;
+#[cfg(feature = "no_std")]
+use std::prelude::*;
 
 use dom::*;
 use xmlerror::*;
+use xpath_impl::collation::*;
+use xpath_impl::resolver::*;
 use xpath_impl::lexer::*;
 use xpath_impl::parser::*;
 use xpath_impl::xitem::*;
 use xpath_impl::xsequence::*;
 use xpath_impl::func::*;
 use xpath_impl::oper::*;
+use xpath_impl::stack_guard::*;
+use xsd::type_annotation_matches;
 
 // ---------------------------------------------------------------------
 // 文字列→数値の変換。
@@ -52,19 +59,70 @@ struct VarNameValue {
     value: XSequence,
 }
 
-#[derive(Debug, PartialEq, Clone)]
+#[derive(Clone)]
 pub struct EvalEnv {
     position: usize,                        // 組み込み函数 position() の値
     last: usize,                            // 組み込み函数 last() の値
-    var_vec: Vec<VarNameValue>,             // 変数表
-                                            // 同名の変数にはスコープ規則を適用
+    var_vec: Vec<VarNameValue>,             // 変数表 (束縛した順)
+    var_index: HashMap<String, Vec<usize>>, // 変数名 → var_vec上の位置 (内側ほど後)
+    scope_marks: Vec<usize>,                // 各スコープ開始時のvar_vecの長さ
+    trace_listener: Option<TraceListener>,  // 組み込み函数 trace() の出力先
+                                            // None ならば標準エラー出力
+    collations: CollationRegistry,          // 照合順序の登録簿
+    resolvers: ResolverRegistry,            // URIスキームの登録簿
+    documents: Vec<(String, NodePtr)>,      // fn:doc() で取得した文書
+                                            // (同じURIには同じ文書を返す)
+    static_base_uri: Option<String>,        // 静的なベースURI
+    item_limit: Option<usize>,              // 評価結果のうち先頭から何個の
+                                            // アイテムが必要か (ヒント)
+    stack_guard: StackGuard,                // 評価の入れ子によるスタック消費量の監視
+    xpath10: bool,                          // XPath 1.0 互換モードか否か
+    dynamic_evaluation: bool,               // am:evaluate() を許可するか否か
+    clock_seed: u64,                        // $seedを省略した fn:random-number-generator()
+                                            // の内部状態 (評価ごとに1回だけ決める)
+    axis_cache: HashMap<(NodePtr, usize), AxisCacheEntry>,
+                                            // (文脈ノード, LocStepの識別子) →
+                                            // 軸とノード・テストで得たノード
+}
+
+// ---------------------------------------------------------------------
+// 述語で last() を参照するLocStepについて、軸とノード・テストを
+// 適用した結果 (述語を適用する前のノード配列)。
+// 構文木のノードが解放されて識別子が再利用されないよう、
+// LocStepのノード自体も保持しておく。
+//
+#[derive(Clone)]
+struct AxisCacheEntry {
+    _step: XNodePtr,
+    nodes: Rc<XSequence>,
 }
 
+// キャッシュするLocStepの最大数 (これ以上は都度計算する)。
+const AXIS_CACHE_CAPACITY: usize = 4096;
+
+// ---------------------------------------------------------------------
+// 組み込み函数 trace() の出力を受け取る函数: (ラベル、値を文字列化したもの)
+//
+pub type TraceListener = Rc<Fn(&str, &str)>;
+
 fn new_eval_env() -> EvalEnv {
     return EvalEnv{
         position: 0,
         last: 0,
         var_vec: vec!{},
+        var_index: HashMap::new(),
+        scope_marks: vec!{},
+        trace_listener: None,
+        collations: new_collation_registry(),
+        resolvers: new_resolver_registry(),
+        documents: vec!{},
+        static_base_uri: None,
+        item_limit: None,
+        stack_guard: new_stack_guard(),
+        xpath10: false,
+        dynamic_evaluation: false,
+        clock_seed: clock_seed(),
+        axis_cache: HashMap::new(),
     }
 }
 
@@ -74,34 +132,56 @@ impl EvalEnv {
     // 文書順に整列し、重複を除去する。
     //
     pub fn sort_by_doc_order(&self, node_array: &mut Vec<NodePtr>) {
-        if node_array.len() <= 1 {
-            return;
-        }
-        node_array.sort_by(|a, b| {
-            return self.compare_by_doc_order(a, b);
-        });
-        let mut i = node_array.len() - 1;
-        while 0 < i {
-            if node_array[i] == node_array[i - 1] {
-                node_array.remove(i);
-            }
-            i -= 1;
-        }
+        sort_document_order(node_array);
     }
 
     // -----------------------------------------------------------------
     // 文書順を比較し、Ordering::{Less,Equal,Greater} を返す。
     //
     pub fn compare_by_doc_order(&self, a: &NodePtr, b: &NodePtr) -> Ordering {
-        let a_order = a.document_order();
-        let b_order = b.document_order();
-        return a_order.cmp(&b_order);
+        return a.cmp_document_order(b);
     }
 
     // -----------------------------------------------------------------
+    // 変数のスコープを開始する。
+    // 以後に束縛した変数は、対応するpop_scope()で一括して削除される。
+    // 同名の変数は、内側のスコープで束縛したものが外側のものを隠す。
+    //
+    fn push_scope(&mut self) {
+        self.scope_marks.push(self.var_vec.len());
+    }
+
+    // -----------------------------------------------------------------
+    // 最も内側のスコープを終了し、そこで束縛した変数を削除する。
+    //
+    fn pop_scope(&mut self) {
+        let mark = match self.scope_marks.pop() {
+            Some(mark) => mark,
+            None => return,
+        };
+        while self.var_vec.len() > mark {
+            if let Some(entry) = self.var_vec.pop() {
+                let is_empty = match self.var_index.get_mut(&entry.name) {
+                    Some(indexes) => {
+                        indexes.pop();
+                        indexes.is_empty()
+                    },
+                    None => false,
+                };
+                if is_empty {
+                    self.var_index.remove(&entry.name);
+                }
+            }
+        }
+    }
+
+    // -----------------------------------------------------------------
+    // 現在のスコープで変数を束縛する。
     //
     fn set_var(&mut self, name: &str, value: &XSequence) {
-        self.var_vec.insert(0, VarNameValue{
+        self.var_index.entry(String::from(name))
+                      .or_insert(vec!{}).push(self.var_vec.len());
+        self.var_vec.push(VarNameValue{
             name: String::from(name),
             value: value.clone(),
         });
@@ -110,36 +190,35 @@ impl EvalEnv {
     // -----------------------------------------------------------------
     //
     fn set_var_item(&mut self, name: &str, value: &XItem) {
-        self.var_vec.insert(0, VarNameValue{
-            name: String::from(name),
-            value: new_singleton(value),
-        });
+        self.set_var(name, &new_singleton(value));
     }
 
     // -----------------------------------------------------------------
+    // 変数の値 (最も内側で束縛したもの)。
     //
-    fn remove_var(&mut self, name: &str) {
-        let mut index = usize::MAX;
-        for (i, entry) in self.var_vec.iter().enumerate() {
-            if entry.name == name {
-                index = i;
-                break;
+    fn get_var(&self, name: &str) -> Option<XSequence> {
+        if let Some(indexes) = self.var_index.get(name) {
+            if let Some(index) = indexes.last() {
+                return Some(self.var_vec[*index].value.clone());
             }
         }
-        if index != usize::MAX {
-            self.var_vec.remove(index as usize);
-        }
+        return None;
     }
 
     // -----------------------------------------------------------------
+    // 現在見えている変数のうち、names に挙げたものについて、
+    // (名前、値) の一覧を返す。束縛されていない名前は無視する。
+    // 函数アイテム (クロージャ) が、作られた時点のスコープを
+    // 捕捉するのに使う。
     //
-    fn get_var(&self, name: &str) -> Option<XSequence> {
-        for entry in self.var_vec.iter() {
-            if entry.name == name {
-                return Some(entry.value.clone());
+    pub fn scope_snapshot(&self, names: &Vec<String>) -> Vec<(String, XSequence)> {
+        let mut snapshot: Vec<(String, XSequence)> = vec!{};
+        for name in names.iter() {
+            if let Some(value) = self.get_var(name) {
+                snapshot.push((name.clone(), value));
             }
         }
-        return None;
+        return snapshot;
     }
 
     // -----------------------------------------------------------------
@@ -163,33 +242,461 @@ impl EvalEnv {
     pub fn get_last(&self) -> usize {
         return self.last;
     }
+
+    // -----------------------------------------------------------------
+    // $seedを省略した fn:random-number-generator() の内部状態。
+    // 同じ評価の中では、何度呼び出しても同じ値。
+    //
+    pub fn get_clock_seed(&self) -> u64 {
+        return self.clock_seed;
+    }
+
+    // -----------------------------------------------------------------
+    // 組み込み函数 trace() の出力。
+    //
+    pub fn trace(&self, label: &str, value: &str) {
+        match self.trace_listener {
+            Some(ref listener) => listener(label, value),
+            #[cfg(not(feature = "no_std"))]
+            None => eprintln!("{}: {}", label, value),
+            #[cfg(feature = "no_std")]
+            None => {},
+        }
+    }
+
+    // -----------------------------------------------------------------
+    // URIに対応する照合順序。
+    //
+    pub fn get_collation(&self, uri: &str) -> Result<Collation, Box<Error>> {
+        return self.collations.get(uri);
+    }
+
+    // -----------------------------------------------------------------
+    // 既定の照合順序。
+    //
+    pub fn default_collation(&self) -> Collation {
+        return self.collations.default_collation();
+    }
+
+    // -----------------------------------------------------------------
+    // 静的なベースURI。
+    //
+    pub fn static_base_uri(&self) -> Option<String> {
+        return self.static_base_uri.clone();
+    }
+
+    // -----------------------------------------------------------------
+    // XPath 1.0 互換モードか否か。
+    //
+    pub fn is_xpath10(&self) -> bool {
+        return self.xpath10;
+    }
+
+    // -----------------------------------------------------------------
+    // am:evaluate() による動的評価を許可しているか否か。
+    //
+    pub fn is_dynamic_evaluation_allowed(&self) -> bool {
+        return self.dynamic_evaluation;
+    }
+
+    // -----------------------------------------------------------------
+    // 相対URIを静的なベースURIに対して解決する。
+    // 静的なベースURIがなければ (あるいは絶対URIでなければ)、そのまま返す。
+    //
+    pub fn resolve_against_static_base(&self, uri: &str) -> String {
+        if let Some(ref base) = self.static_base_uri {
+            if let Some(resolved) = resolve_uri(uri, base) {
+                return resolved;
+            }
+        }
+        return String::from(uri);
+    }
+
+    // -----------------------------------------------------------------
+    // URIの資源 (文字列) を取得する。
+    // 相対URIは静的なベースURIに対して解決する。
+    //
+    pub fn fetch_resource(&self, uri: &str) -> Result<String, Box<Error>> {
+        return self.resolvers.fetch(&self.resolve_against_static_base(uri));
+    }
+
+    // -----------------------------------------------------------------
+    // 登録してあるコレクションのノード列。
+    // URIが None ならば既定のコレクション。相対URIは静的なベースURIに
+    // 対して解決する。登録していなければエラー [err:FODC0002]。
+    //
+    pub fn fetch_collection(&self, uri: Option<&str>) -> Result<XSequence, Box<Error>> {
+        let uri = match uri {
+            Some(uri) => self.resolve_against_static_base(uri),
+            None => String::new(),
+        };
+        match self.resolvers.collection(&uri) {
+            Some(nodes) => return Ok(new_xsequence_from_node_array(&nodes)),
+            None if uri == "" => return Err(dynamic_error!(
+                    "No default collection [err:FODC0002]")),
+            None => return Err(dynamic_error!(
+                    "Unknown collection: {} [err:FODC0002]", uri)),
+        }
+    }
+
+    // -----------------------------------------------------------------
+    // URIの資源を取得し、XML文書として解析する。
+    // 評価中、同じURIに対しては同じ文書 (ノード) を返す。
+    // コレクションに含まれる文書で、文書URIが一致するものがあれば、
+    // それを返す。
+    // 解析できなければエラー [err:FODC0002]。
+    // 文書には、(解決後の) URIを文書URIとして記録する。
+    //
+    pub fn fetch_document(&mut self, uri: &str) -> Result<NodePtr, Box<Error>> {
+        let uri = self.resolve_against_static_base(uri);
+        let uri = uri.as_str();
+        for &(ref t_uri, ref doc) in self.documents.iter() {
+            if t_uri == uri {
+                return Ok(doc.rc_clone());
+            }
+        }
+        if let Some(doc) = self.resolvers.collection_document(uri) {
+            return Ok(doc);
+        }
+        let text = self.resolvers.fetch(uri)?;
+        let doc = match new_document(&text) {
+            Ok(doc) => doc,
+            Err(e) => return Err(dynamic_error!(
+                    "Error parsing resource: {}: {} [err:FODC0002]", uri, e)),
+        };
+        doc.set_document_uri(uri);
+        self.documents.push((String::from(uri), doc.rc_clone()));
+        return Ok(doc);
+    }
 }
 
 // =====================================================================
 // (EVAL)
 //
 pub fn match_xpath(start_node: &NodePtr, xnode: &XNodePtr) -> Result<XSequence, Box<Error>> {
+    return match_xpath_with_trace_listener(start_node, xnode, None);
+}
+
+// ---------------------------------------------------------------------
+// match_xpath() と同様。ただし、評価結果のうち先頭の limit 個のみを返す。
+// 対応している式 (「//x[@a='v']」など) は、limit 個に達した時点で
+// 探索を打ち切る。
+//
+pub fn match_xpath_with_item_limit(start_node: &NodePtr, xnode: &XNodePtr,
+                limit: usize) -> Result<XSequence, Box<Error>> {
+    let result = match_xpath_with_item_hint(start_node, xnode, limit)?;
+    let mut limited = new_xsequence();
+    for item in result.iter().take(limit) {
+        limited.push(item);
+    }
+    return Ok(limited);
+}
+
+// ---------------------------------------------------------------------
+// match_xpath() と同様。ただし、$seedを省略した
+// fn:random-number-generator() の内部状態を指定する
+// (並列評価で、各スレッドの評価が同じ値を使うように)。
+//
+pub fn match_xpath_with_clock_seed(start_node: &NodePtr, xnode: &XNodePtr,
+                clock_seed: u64) -> Result<XSequence, Box<Error>> {
+    let mut eval_env = new_eval_env();
+    eval_env.clock_seed = clock_seed;
+    let start_xsequence = new_singleton_node(start_node);
+    return evaluate_xnode(&start_xsequence, xnode, &mut eval_env);
+}
+
+// ---------------------------------------------------------------------
+// match_xpath_with_item_limit() と同様。ただし結果を切り詰めない。
+// 探索を打ち切れない式では評価結果の全体を返すので、limit 個より
+// 多いこともある (その場合も、先頭の limit 個は match_xpath() と同じ)。
+//
+pub fn match_xpath_with_item_hint(start_node: &NodePtr, xnode: &XNodePtr,
+                limit: usize) -> Result<XSequence, Box<Error>> {
+    let mut eval_env = new_eval_env();
+    eval_env.item_limit = Some(limit);
+    let start_xsequence = new_singleton_node(start_node);
+    return evaluate_xnode(&start_xsequence, xnode, &mut eval_env);
+}
+
+// ---------------------------------------------------------------------
+// match_xpath() と同様。ただし組み込み函数 trace() の出力先を指定する。
+//
+pub fn match_xpath_with_trace_listener(start_node: &NodePtr, xnode: &XNodePtr,
+                trace_listener: Option<TraceListener>) -> Result<XSequence, Box<Error>> {
+    return match_xpath_with_collations(start_node, xnode,
+                trace_listener, &new_collation_registry());
+}
+
+// ---------------------------------------------------------------------
+// match_xpath_with_trace_listener() と同様。ただし照合順序の登録簿を
+// 指定する。
+//
+pub fn match_xpath_with_collations(start_node: &NodePtr, xnode: &XNodePtr,
+                trace_listener: Option<TraceListener>,
+                collations: &CollationRegistry) -> Result<XSequence, Box<Error>> {
+    return match_xpath_with_resolvers(start_node, xnode,
+                trace_listener, collations, &new_resolver_registry());
+}
+
+// ---------------------------------------------------------------------
+// match_xpath_with_collations() と同様。ただし、fn:doc() などが資源を
+// 取得するのに使う、URIスキームの登録簿を指定する。
+//
+pub fn match_xpath_with_resolvers(start_node: &NodePtr, xnode: &XNodePtr,
+                trace_listener: Option<TraceListener>,
+                collations: &CollationRegistry,
+                resolvers: &ResolverRegistry) -> Result<XSequence, Box<Error>> {
+    return match_xpath_with_static_base_uri(start_node, xnode,
+                trace_listener, collations, resolvers, None);
+}
+
+// ---------------------------------------------------------------------
+// match_xpath_with_resolvers() と同様。ただし、静的なベースURI
+// (fn:static-base-uri() の値、相対URIの解決に使う) を指定する。
+//
+pub fn match_xpath_with_static_base_uri(start_node: &NodePtr, xnode: &XNodePtr,
+                trace_listener: Option<TraceListener>,
+                collations: &CollationRegistry,
+                resolvers: &ResolverRegistry,
+                static_base_uri: Option<String>) -> Result<XSequence, Box<Error>> {
+    return match_xpath_with_spec_level(start_node, xnode,
+                trace_listener, collations, resolvers, static_base_uri, false);
+}
+
+// ---------------------------------------------------------------------
+// match_xpath_with_static_base_uri() と同様。ただし、XPath 1.0 互換
+// モードで評価するか否かを指定する。
+// 互換モードでは、函数の引数、一般比較、算術演算の被演算子を
+// XPath 1.0 の規則で変換する (構文の検査は呼び出し側でおこなう)。
+//
+pub fn match_xpath_with_spec_level(start_node: &NodePtr, xnode: &XNodePtr,
+                trace_listener: Option<TraceListener>,
+                collations: &CollationRegistry,
+                resolvers: &ResolverRegistry,
+                static_base_uri: Option<String>,
+                xpath10: bool) -> Result<XSequence, Box<Error>> {
+    return match_xpath_with_dynamic_evaluation(start_node, xnode,
+                trace_listener, collations, resolvers, static_base_uri,
+                xpath10, false);
+}
+
+// ---------------------------------------------------------------------
+// match_xpath_with_spec_level() と同様。ただし、am:evaluate() による
+// 動的評価 (文字列として与えた式の評価) を許可するか否かを指定する。
+//
+pub fn match_xpath_with_dynamic_evaluation(start_node: &NodePtr, xnode: &XNodePtr,
+                trace_listener: Option<TraceListener>,
+                collations: &CollationRegistry,
+                resolvers: &ResolverRegistry,
+                static_base_uri: Option<String>,
+                xpath10: bool,
+                dynamic_evaluation: bool) -> Result<XSequence, Box<Error>> {
 
     let mut eval_env = new_eval_env();
+    eval_env.trace_listener = trace_listener;
+    eval_env.collations = collations.clone();
+    eval_env.resolvers = resolvers.clone();
+    eval_env.static_base_uri = static_base_uri;
+    eval_env.xpath10 = xpath10;
+    eval_env.dynamic_evaluation = dynamic_evaluation;
 
     let start_xsequence = new_singleton_node(start_node);
     return evaluate_xnode(&start_xsequence, xnode, &mut eval_env);
 }
 
+// ---------------------------------------------------------------------
+// 文脈アイテム (ノードとは限らない。空ならば文脈アイテムなし) と
+// 変数の値を指定して、式を評価する。
+//
+pub fn evaluate_xnode_with_context(context_xseq: &XSequence, xnode: &XNodePtr,
+                vars: &Vec<(String, XSequence)>) -> Result<XSequence, Box<Error>> {
+    let mut eval_env = new_eval_env();
+    for &(ref name, ref value) in vars.iter() {
+        eval_env.set_var(name, value);
+    }
+    return evaluate_xnode(context_xseq, xnode, &mut eval_env);
+}
+
+// ---------------------------------------------------------------------
+// 文脈アイテム (空ならば文脈アイテムなし) に加えて、文脈位置と
+// 文脈サイズ (position() と last() の値) を指定して、式を評価する。
+// 文脈を呼び出し側で管理する場合 (シーケンスの各アイテムを順に
+// 評価する場合など) に使う。
+//
+pub fn evaluate_xnode_with_focus(context_xseq: &XSequence, xnode: &XNodePtr,
+                position: usize, last: usize) -> Result<XSequence, Box<Error>> {
+    let mut eval_env = new_eval_env();
+    eval_env.position = position;
+    eval_env.last = last;
+    return evaluate_xnode(context_xseq, xnode, &mut eval_env);
+}
+
+// ---------------------------------------------------------------------
+// am:evaluate() の実体: 文字列として与えた式を構文解析し、
+// 現在の評価環境 (変数、position / last など) のもとで評価する。
+// context_xseq が None ならば、現在の文脈シーケンスで評価する。
+// Some ならば、それを文脈アイテム (空ならば文脈アイテムなし) とし、
+// position / last は 1 (文脈アイテムなしならば 0) とする。
+//
+pub fn evaluate_dynamic_xpath(xpath: &str, current_xseq: &XSequence,
+                context_xseq: Option<&XSequence>,
+                eval_env: &mut EvalEnv) -> Result<XSequence, Box<Error>> {
+    if ! eval_env.is_dynamic_evaluation_allowed() {
+        return Err(dynamic_error!(
+                "am:evaluate: Dynamic evaluation is disabled [err:XTDE3175]"));
+    }
+    let xnode = compile_xpath(&String::from(xpath))?;
+    let context_xseq = match context_xseq {
+        Some(xseq) => xseq,
+        None => return evaluate_xnode(current_xseq, &xnode, eval_env),
+    };
+    let position = eval_env.position;
+    let last = eval_env.last;
+    let n = if context_xseq.is_empty() { 0 } else { 1 };
+    eval_env.position = n;
+    eval_env.last = n;
+    let result = evaluate_xnode(context_xseq, &xnode, eval_env);
+    eval_env.position = position;
+    eval_env.last = last;
+    return result;
+}
+
+// ---------------------------------------------------------------------
+// 文脈アイテムも変数も参照しない式を評価する (構文木の最適化で使う)。
+//
+pub fn evaluate_constant_xnode(xnode: &XNodePtr) -> Result<XSequence, Box<Error>> {
+    let mut eval_env = new_eval_env();
+    return evaluate_xnode(&new_xsequence(), xnode, &mut eval_env);
+}
+
+// =====================================================================
+// (PATTERN)
+// ノード node がパターン xnode に合致するか否か。
+// すなわち、node の祖先 (または自分自身) のいずれかを文脈ノードとして
+// xnode を評価した結果に、node が含まれるか否か。
+//
+// 文書全体を対象として評価することはしない。
+// - 最後のステップのノードテストに合致しなければ、評価せずに偽とする。
+// - 絶対パス (/ で始まる) ならば、文脈ノードによらないので、1回だけ評価する。
+//
+pub fn match_pattern(node: &NodePtr, xnode: &XNodePtr) -> Result<bool, Box<Error>> {
+    if let Some(step_xnode) = last_step_xnode(xnode) {
+        if ! match_node_test(node, &step_xnode) {
+            return Ok(false);
+        }
+    }
+
+    let is_absolute = is_absolute_path_xnode(xnode);
+    let mut curr = Some(node.rc_clone());
+    while let Some(context_node) = curr {
+        let result = match_xpath(&context_node, xnode)?;
+        if result.to_nodeset().iter().any(|n| n == node) {
+            return Ok(true);
+        }
+        if is_absolute {
+            break;
+        }
+        curr = context_node.parent();
+    }
+    return Ok(false);
+}
+
+// ---------------------------------------------------------------------
+// パスの最後のステップ (AxisNNNN)。パスでなければNone。
+//
+fn last_step_xnode(xnode: &XNodePtr) -> Option<XNodePtr> {
+    match get_xnode_type(xnode) {
+        XNodeType::OperatorPath => {
+            let right = get_right(xnode);
+            if ! is_nil_xnode(&right) {
+                return last_step_xnode(&right);
+            }
+            return last_step_xnode(&get_left(xnode));
+        },
+        XNodeType::AxisAncestor |
+        XNodeType::AxisAncestorOrSelf |
+        XNodeType::AxisAttribute |
+        XNodeType::AxisChild |
+        XNodeType::AxisDescendant |
+        XNodeType::AxisDescendantOrSelf |
+        XNodeType::AxisFollowing |
+        XNodeType::AxisFollowingSibling |
+        XNodeType::AxisParent |
+        XNodeType::AxisPreceding |
+        XNodeType::AxisPrecedingSibling |
+        XNodeType::AxisSelf => {
+            return Some(xnode.clone());
+        },
+        _ => return None,
+    }
+}
+
+// ---------------------------------------------------------------------
+// 絶対パス (最初のステップが AxisRoot) か否か。
+//
+fn is_absolute_path_xnode(xnode: &XNodePtr) -> bool {
+    match get_xnode_type(xnode) {
+        XNodeType::OperatorPath => return is_absolute_path_xnode(&get_left(xnode)),
+        XNodeType::AxisRoot => return true,
+        _ => return false,
+    }
+}
+
 // ---------------------------------------------------------------------
 // あるXMLノードに対して、XPath構文木のあるノードを適用し、評価結果を返す。
 //
 fn evaluate_xnode(xseq: &XSequence, xnode: &XNodePtr,
                     eval_env: &mut EvalEnv) -> Result<XSequence, Box<Error>> {
 
+    // -----------------------------------------------------------------
+    // 評価結果のうち先頭から何個あれば足りるかのヒント
+    // (fn:subsequence() の第1引数を評価する場合など)。
+    // この xnode の評価にのみ適用し、部分式には引き継がない。
+    // 対応していない式は、ヒントを無視してすべてのアイテムを返す。
+    //
+    let item_limit = eval_env.item_limit.take();
+
     if is_nil_xnode(xnode) {
         panic!("Can't occur: evaluate_xnode, xnode is nil");
     }
+    if eval_env.stack_guard.exhausted() {
+        return Err(dynamic_error!("Expression (or recursion) nested too deeply."));
+    }
 
     let xnode_type = get_xnode_type(&xnode);
     match xnode_type {
 
         XNodeType::OperatorPath => {
+            // ---------------------------------------------------------
+            // (0) 先頭の何個かのみ必要で、「descendant-or-self::node()/
+            //     child::x」(「//x」) の形ならば、descendant::x として
+            //     必要な個数だけ探索する。
+            //
+            if let Some(limit) = item_limit {
+                if xseq.len() == 1 {
+                    if let Some(step) = descendant_shortcut_step(xnode) {
+                        if let Ok(node) = xseq.get_singleton_node() {
+                            let node_array = match_descendants_limited(
+                                    &node, &step, false, limit, eval_env)?;
+                            return Ok(new_xsequence_from_node_array(&node_array));
+                        }
+                    }
+                }
+            }
+
+            // ---------------------------------------------------------
+            // (0b) 「//x[@attr = 値]」の形で、文書に attr の索引
+            //      (NodePtr#build_attr_index()) があれば、descendant::x と
+            //      して索引から取り出す。
+            //
+            if attr_index_predicate(&get_right(&get_right(xnode))).is_some() {
+                if let Some(step) = descendant_shortcut_step(xnode) {
+                    if let Some(result) = match_location_path_by_attr_index(xseq, &step, eval_env)? {
+                        return Ok(result);
+                    }
+                }
+            }
+
             // ---------------------------------------------------------
             // (1) 左辺値を評価する。
             //     ノードのみのシーケンスでなければエラー (空シーケンスは可)。
@@ -219,6 +726,8 @@ fn evaluate_xnode(xseq: &XSequence, xnode: &XNodePtr,
 
             // ---------------------------------------------------------
             // (2) lhsの各ノードについて、右辺値を評価する。
+            //     lhsが1個だけならば、右辺値の評価結果がそのまま
+            //     (整列して) 結果になるので、必要な個数のヒントを渡す。
             //
             let mut node_exists = false;
             let mut atom_exists = false;
@@ -226,6 +735,9 @@ fn evaluate_xnode(xseq: &XSequence, xnode: &XNodePtr,
 
             for item in lhs.iter() {
                 let xseq = new_singleton(&item);
+                if lhs.len() == 1 {
+                    eval_env.item_limit = item_limit;
+                }
                 let val_seq = evaluate_xnode(&xseq, &right_xnode, eval_env)?;
 
                 if val_seq.is_empty() {
@@ -254,11 +766,15 @@ fn evaluate_xnode(xseq: &XSequence, xnode: &XNodePtr,
 
             // ---------------------------------------------------------
             // (3) 最後に、ノードのみのシーケンスであれば、整列、重複排除する。
+            //     lhsが1個だけで、右辺値が整列済みならば、そのまま返す。
             //
             if node_exists {
+                if lhs.len() == 1 && result_seq.is_doc_ordered() {
+                    return Ok(result_seq);
+                }
                 let mut nodeset = result_seq.to_nodeset();
                 eval_env.sort_by_doc_order(&mut nodeset);
-                let sorted_seq = new_xsequence_from_node_array(&nodeset);
+                let sorted_seq = new_xsequence_in_doc_order(&nodeset);
                 return Ok(sorted_seq);
             } else {
                 return Ok(result_seq);
@@ -311,6 +827,11 @@ fn evaluate_xnode(xseq: &XSequence, xnode: &XNodePtr,
         XNodeType::AxisPrecedingSibling |
         XNodeType::AxisRoot |
         XNodeType::AxisSelf => {
+            if let Some(limit) = item_limit {
+                if let Some(result) = match_location_path_limited(xseq, xnode, limit, eval_env)? {
+                    return Ok(result);
+                }
+            }
             return match_location_path(xseq, xnode, eval_env);
         },
 
@@ -369,38 +890,32 @@ fn evaluate_xnode(xseq: &XSequence, xnode: &XNodePtr,
         },
 
         XNodeType::OperatorGeneralEQ => {
-            let lhs = evaluate_xnode(xseq, &get_left(xnode), eval_env)?;
-            let rhs = evaluate_xnode(xseq, &get_right(xnode), eval_env)?;
+            let (lhs, rhs) = evaluate_comparison_operands(xseq, xnode, false, eval_env)?;
             return general_compare_eq(&lhs, &rhs);
         },
 
         XNodeType::OperatorGeneralNE => {
-            let lhs = evaluate_xnode(xseq, &get_left(xnode), eval_env)?;
-            let rhs = evaluate_xnode(xseq, &get_right(xnode), eval_env)?;
+            let (lhs, rhs) = evaluate_comparison_operands(xseq, xnode, false, eval_env)?;
             return general_compare_ne(&lhs, &rhs);
         },
 
         XNodeType::OperatorGeneralLT => {
-            let lhs = evaluate_xnode(xseq, &get_left(xnode), eval_env)?;
-            let rhs = evaluate_xnode(xseq, &get_right(xnode), eval_env)?;
+            let (lhs, rhs) = evaluate_comparison_operands(xseq, xnode, true, eval_env)?;
             return general_compare_lt(&lhs, &rhs);
         },
 
         XNodeType::OperatorGeneralLE => {
-            let lhs = evaluate_xnode(xseq, &get_left(xnode), eval_env)?;
-            let rhs = evaluate_xnode(xseq, &get_right(xnode), eval_env)?;
+            let (lhs, rhs) = evaluate_comparison_operands(xseq, xnode, true, eval_env)?;
             return general_compare_le(&lhs, &rhs);
         },
 
         XNodeType::OperatorGeneralGT => {
-            let lhs = evaluate_xnode(xseq, &get_left(xnode), eval_env)?;
-            let rhs = evaluate_xnode(xseq, &get_right(xnode), eval_env)?;
+            let (lhs, rhs) = evaluate_comparison_operands(xseq, xnode, true, eval_env)?;
             return general_compare_gt(&lhs, &rhs);
         },
 
         XNodeType::OperatorGeneralGE => {
-            let lhs = evaluate_xnode(xseq, &get_left(xnode), eval_env)?;
-            let rhs = evaluate_xnode(xseq, &get_right(xnode), eval_env)?;
+            let (lhs, rhs) = evaluate_comparison_operands(xseq, xnode, true, eval_env)?;
             return general_compare_ge(&lhs, &rhs);
         },
 
@@ -441,14 +956,12 @@ fn evaluate_xnode(xseq: &XSequence, xnode: &XNodePtr,
         },
 
         XNodeType::OperatorAdd => {
-            let lhs = evaluate_xnode(xseq, &get_left(xnode), eval_env)?;
-            let rhs = evaluate_xnode(xseq, &get_right(xnode), eval_env)?;
+            let (lhs, rhs) = evaluate_arithmetic_operands(xseq, xnode, eval_env)?;
             return op_numeric_add(&vec!{lhs, rhs});
         },
 
         XNodeType::OperatorSubtract => {
-            let lhs = evaluate_xnode(xseq, &get_left(xnode), eval_env)?;
-            let rhs = evaluate_xnode(xseq, &get_right(xnode), eval_env)?;
+            let (lhs, rhs) = evaluate_arithmetic_operands(xseq, xnode, eval_env)?;
             return op_numeric_subtract(&vec!{lhs, rhs});
         },
 
@@ -458,19 +971,20 @@ fn evaluate_xnode(xseq: &XSequence, xnode: &XNodePtr,
         },
 
         XNodeType::OperatorUnaryMinus => {
-            let rhs = evaluate_xnode(xseq, &get_right(xnode), eval_env)?;
+            let mut rhs = evaluate_xnode(xseq, &get_right(xnode), eval_env)?;
+            if eval_env.is_xpath10() {
+                rhs = xpath10_number(&rhs)?;
+            }
             return op_numeric_unary_minus(&vec!{rhs});
         },
 
         XNodeType::OperatorMultiply => {
-            let lhs = evaluate_xnode(xseq, &get_left(xnode), eval_env)?;
-            let rhs = evaluate_xnode(xseq, &get_right(xnode), eval_env)?;
+            let (lhs, rhs) = evaluate_arithmetic_operands(xseq, xnode, eval_env)?;
             return op_numeric_multiply(&vec!{lhs, rhs});
         },
 
         XNodeType::OperatorDiv => {
-            let lhs = evaluate_xnode(xseq, &get_left(xnode), eval_env)?;
-            let rhs = evaluate_xnode(xseq, &get_right(xnode), eval_env)?;
+            let (lhs, rhs) = evaluate_arithmetic_operands(xseq, xnode, eval_env)?;
             return op_numeric_divide(&vec!{lhs, rhs});
         },
 
@@ -481,8 +995,7 @@ fn evaluate_xnode(xseq: &XSequence, xnode: &XNodePtr,
         },
 
         XNodeType::OperatorMod => {
-            let lhs = evaluate_xnode(xseq, &get_left(xnode), eval_env)?;
-            let rhs = evaluate_xnode(xseq, &get_right(xnode), eval_env)?;
+            let (lhs, rhs) = evaluate_arithmetic_operands(xseq, xnode, eval_env)?;
             return op_numeric_mod(&vec!{lhs, rhs});
         },
 
@@ -556,10 +1069,11 @@ fn evaluate_xnode(xseq: &XSequence, xnode: &XNodePtr,
             let range = evaluate_xnode(xseq, &get_left(xnode), eval_env)?;
             let mut result = new_xsequence();
             for xitem in range.iter() {
+                eval_env.push_scope();
                 eval_env.set_var_item(var_name.as_str(), xitem);
-                let rhs = evaluate_xnode(xseq, &get_right(xnode), eval_env)?;
-                result.append(&rhs);
-                eval_env.remove_var(var_name.as_str());
+                let rhs = evaluate_xnode(xseq, &get_right(xnode), eval_env);
+                eval_env.pop_scope();
+                result.append(&rhs?);
             }
             return Ok(result);
         },
@@ -575,11 +1089,12 @@ fn evaluate_xnode(xseq: &XSequence, xnode: &XNodePtr,
             let var_value = evaluate_xnode(xseq, &get_left(xnode), eval_env)?;
             let var_name = get_xnode_name(&xnode);
 
+            eval_env.push_scope();
             eval_env.set_var(var_name.as_str(), &var_value);
-            let rhs = evaluate_xnode(xseq, &get_right(xnode), eval_env)?;
-            eval_env.remove_var(var_name.as_str());
+            let rhs = evaluate_xnode(xseq, &get_right(xnode), eval_env);
+            eval_env.pop_scope();
 
-            return Ok(rhs);
+            return rhs;
         },
 
         XNodeType::SomeExpr => {
@@ -591,12 +1106,13 @@ fn evaluate_xnode(xseq: &XSequence, xnode: &XNodePtr,
             let range = evaluate_xnode(xseq, &get_left(xnode), eval_env)?;
 
             for xitem in range.iter() {
+                eval_env.push_scope();
                 eval_env.set_var_item(var_name.as_str(), xitem);
-                let rhs = evaluate_xnode(xseq, &get_right(xnode), eval_env)?;
-                if rhs.effective_boolean_value()? == true {
+                let rhs = evaluate_xnode(xseq, &get_right(xnode), eval_env);
+                eval_env.pop_scope();
+                if rhs?.effective_boolean_value()? == true {
                     return Ok(new_singleton_boolean(true));
                 }
-                eval_env.remove_var(var_name.as_str());
             }
             return Ok(new_singleton_boolean(false));
         },
@@ -609,12 +1125,13 @@ fn evaluate_xnode(xseq: &XSequence, xnode: &XNodePtr,
             let var_name = get_xnode_name(&xnode);
             let range = evaluate_xnode(xseq, &get_left(xnode), eval_env)?;
             for xitem in range.iter() {
+                eval_env.push_scope();
                 eval_env.set_var_item(var_name.as_str(), xitem);
-                let rhs = evaluate_xnode(xseq, &get_right(xnode), eval_env)?;
-                if rhs.effective_boolean_value()? == false {
+                let rhs = evaluate_xnode(xseq, &get_right(xnode), eval_env);
+                eval_env.pop_scope();
+                if rhs?.effective_boolean_value()? == false {
                     return Ok(new_singleton_boolean(false));
                 }
-                eval_env.remove_var(var_name.as_str());
             }
             return Ok(new_singleton_boolean(true));
         },
@@ -626,6 +1143,17 @@ fn evaluate_xnode(xseq: &XSequence, xnode: &XNodePtr,
             return Ok(new_singleton_boolean(b));
         },
 
+        XNodeType::OperatorTreatAs => {
+            let expr_xseq = evaluate_xnode(xseq, &get_left(xnode), eval_env)?;
+            let sequence_type_xnode = get_right(xnode);
+            if ! match_sequence_type(&expr_xseq, &sequence_type_xnode)? {
+                return Err(dynamic_error!(
+                        "treat as: {} does not match the type [err:XPDY0050]",
+                        expr_xseq));
+            }
+            return Ok(expr_xseq);
+        },
+
         XNodeType::OperatorCastableAs => {
             let value = evaluate_xnode(xseq, &get_left(xnode), eval_env)?;
             let single_type_xnode = get_right(xnode);
@@ -643,6 +1171,10 @@ fn evaluate_xnode(xseq: &XSequence, xnode: &XNodePtr,
         }
 
         XNodeType::FunctionCall => {
+            if get_xnode_name(&xnode) == "fn:subsequence" {
+                return evaluate_subsequence_call(xseq, xnode, eval_env);
+            }
+
             // rightに連なっているArgumentTopノード群のleft以下にある
             // 式を評価し、argsArray (引数の配列) を得た後、
             // この引数列を渡して函数を評価する。
@@ -682,9 +1214,12 @@ fn evaluate_xnode(xseq: &XSequence, xnode: &XNodePtr,
         XNodeType::InlineFunction |
         XNodeType::NamedFunctionRef |
         XNodeType::PartialFunctionCall => {
-            // インライン函数 | 名前付き函数参照:
+            // インライン函数 | 名前付き函数参照 | 部分函数:
             // この時点では評価せず、シングルトンとして包んで返す。
-            return Ok(new_singleton_xnodeptr(&xnode));
+            // 本体 (部分函数の場合は引数) が参照する変数は、この時点の
+            // 値を捕捉しておき、呼び出すときに束縛する (クロージャー)。
+            let captured = capture_variables(&xnode, eval_env);
+            return Ok(new_singleton(&new_xitem_closure(&xnode, &captured)));
         },
 
         XNodeType::Map |
@@ -770,6 +1305,274 @@ fn evaluate_xnode(xseq: &XSequence, xnode: &XNodePtr,
     }
 }
 
+// ---------------------------------------------------------------------
+// 一般比較の両辺を評価する。
+// XPath 1.0 互換モードでは、XPath 1.0 の規則で変換する。
+// relational: 演算子が <, <=, >, >= のいずれかであるか否か。
+//
+fn evaluate_comparison_operands(xseq: &XSequence, xnode: &XNodePtr,
+                relational: bool,
+                eval_env: &mut EvalEnv) -> Result<(XSequence, XSequence), Box<Error>> {
+    let lhs = evaluate_xnode(xseq, &get_left(xnode), eval_env)?;
+    let rhs = evaluate_xnode(xseq, &get_right(xnode), eval_env)?;
+    if ! eval_env.is_xpath10() {
+        return Ok((lhs, rhs));
+    }
+    return xpath10_comparison_operands(&lhs, &rhs, relational);
+}
+
+// ---------------------------------------------------------------------
+// 算術演算の両辺を評価する。
+// XPath 1.0 互換モードでは、それぞれ number() で xs:double に変換する。
+//
+fn evaluate_arithmetic_operands(xseq: &XSequence, xnode: &XNodePtr,
+                eval_env: &mut EvalEnv) -> Result<(XSequence, XSequence), Box<Error>> {
+    let lhs = evaluate_xnode(xseq, &get_left(xnode), eval_env)?;
+    let rhs = evaluate_xnode(xseq, &get_right(xnode), eval_env)?;
+    if ! eval_env.is_xpath10() {
+        return Ok((lhs, rhs));
+    }
+    return Ok((xpath10_number(&lhs)?, xpath10_number(&rhs)?));
+}
+
+// ---------------------------------------------------------------------
+// fn:subsequence($sourceSeq, $startingLoc, $length) の呼び出し。
+// 第2、第3引数を先に評価し、$sourceSeq のうち必要な個数がわかれば、
+// それをヒントとして第1引数を評価する (「subsequence(//x, 1, 10)」で
+// 文書全体を探索しないように)。
+//
+fn evaluate_subsequence_call(xseq: &XSequence, xnode: &XNodePtr,
+                eval_env: &mut EvalEnv) -> Result<XSequence, Box<Error>> {
+    let mut arg_xnodes: Vec<XNodePtr> = vec!{};
+    let mut curr_xnode = get_right(&xnode);
+    while ! is_nil_xnode(&curr_xnode) {
+        if get_xnode_type(&curr_xnode) != XNodeType::ArgumentTop {
+            return Err(cant_occur!("FunctionCall: rightがArgumentTopでない。"));
+        }
+        arg_xnodes.push(get_left(&curr_xnode));
+        curr_xnode = get_right(&curr_xnode);
+    }
+
+    let mut args_array: Vec<XSequence> = vec!{new_xsequence()};
+    for arg_xnode in arg_xnodes.iter().skip(1) {
+        args_array.push(evaluate_xnode(xseq, arg_xnode, eval_env)?);
+    }
+    let mut item_limit: Option<usize> = None;
+    if args_array.len() == 3 {
+        let starting_loc = args_array[1].get_singleton_item()
+                .and_then(|item| item.get_as_raw_double());
+        let length = args_array[2].get_singleton_item()
+                .and_then(|item| item.get_as_raw_double());
+        if let (Ok(starting_loc), Ok(length)) = (starting_loc, length) {
+            item_limit = subsequence_item_limit(starting_loc, Some(length));
+        }
+    }
+    if let Some(arg_xnode) = arg_xnodes.first() {
+        eval_env.item_limit = item_limit;
+        args_array[0] = evaluate_xnode(xseq, arg_xnode, eval_env)?;
+    }
+    return evaluate_function("fn:subsequence", &mut args_array, xseq, eval_env);
+}
+
+// ---------------------------------------------------------------------
+// 「descendant-or-self::node()/child::x」の形のパスで、x の述語が
+// (あれば) 文脈位置によらないものならば、同じノードテストと述語の
+// descendant::x を返す。
+//
+fn descendant_shortcut_step(xnode: &XNodePtr) -> Option<XNodePtr> {
+    let left = get_left(xnode);
+    let right = get_right(xnode);
+    if get_xnode_type(&left) != XNodeType::AxisDescendantOrSelf ||
+       get_xnode_name(&left) != "node()" ||
+       ! is_nil_xnode(&get_left(&left)) || ! is_nil_xnode(&get_right(&left)) ||
+       is_nil_xnode(&right) ||
+       get_xnode_type(&right) != XNodeType::AxisChild ||
+       ! is_position_independent(&get_right(&right)) {
+        return None;
+    }
+    let descendant = new_xnode(XNodeType::AxisDescendant, &get_xnode_name(&right));
+    return Some(copy_xnode_with_children(&descendant, &get_left(&right), &get_right(&right)));
+}
+
+// ---------------------------------------------------------------------
+// 述語の並び (PredicateTop → PredicateTop → ...) が、いずれも
+// 文脈位置や文脈サイズによらず、ノードごとに個別に判定できるか否か
+// (述語がなければtrue)。
+// 述語の式が真偽値を返す演算子 (比較、and、or など) であって、
+// 内部で position()、last() を呼び出していなければ、個別に判定できる。
+// (数値を返す式は位置の指定になるので、個別には判定できない。)
+//
+fn is_position_independent(predicates: &XNodePtr) -> bool {
+    let mut curr = predicates.clone();
+    while ! is_nil_xnode(&curr) {
+        if get_xnode_type(&curr) != XNodeType::PredicateTop {
+            return false;
+        }
+        let expr = get_left(&curr);
+        let boolean_valued = match get_xnode_type(&expr) {
+            XNodeType::OperatorOr |
+            XNodeType::OperatorAnd |
+            XNodeType::OperatorGeneralEQ |
+            XNodeType::OperatorGeneralNE |
+            XNodeType::OperatorGeneralLT |
+            XNodeType::OperatorGeneralGT |
+            XNodeType::OperatorGeneralLE |
+            XNodeType::OperatorGeneralGE |
+            XNodeType::OperatorValueEQ |
+            XNodeType::OperatorValueNE |
+            XNodeType::OperatorValueLT |
+            XNodeType::OperatorValueGT |
+            XNodeType::OperatorValueLE |
+            XNodeType::OperatorValueGE |
+            XNodeType::OperatorIsSameNode |
+            XNodeType::OperatorNodeBefore |
+            XNodeType::OperatorNodeAfter |
+            XNodeType::OperatorInstanceOf |
+            XNodeType::OperatorCastableAs |
+            XNodeType::SomeExpr |
+            XNodeType::EveryExpr => true,
+            XNodeType::FunctionCall => match get_xnode_name(&expr).as_str() {
+                "fn:not" | "fn:boolean" | "fn:exists" | "fn:empty" |
+                "fn:contains" | "fn:starts-with" | "fn:ends-with" |
+                "fn:matches" | "fn:has-children" => true,
+                _ => false,
+            },
+            _ => false,
+        };
+        if ! boolean_valued || refers_to_context_position(&expr) {
+            return false;
+        }
+        curr = get_right(&curr);
+    }
+    return true;
+}
+
+// ---------------------------------------------------------------------
+// 式の中で position() または last() を呼び出しているか否か。
+// 入れ子になった述語の中の呼び出しも、安全側に倒して含める。
+//
+fn refers_to_context_position(xnode: &XNodePtr) -> bool {
+    return refers_to_function(xnode, &["fn:position", "fn:last"]);
+}
+
+// ---------------------------------------------------------------------
+// 式の中で、func_names のいずれかの函数を呼び出しているか (あるいは
+// 名前付き函数参照として参照しているか) 否か。
+//
+fn refers_to_function(xnode: &XNodePtr, func_names: &[&str]) -> bool {
+    let mut stack: Vec<XNodePtr> = vec!{ xnode.clone() };
+    while let Some(curr) = stack.pop() {
+        if is_nil_xnode(&curr) {
+            continue;
+        }
+        match get_xnode_type(&curr) {
+            XNodeType::FunctionCall |
+            XNodeType::NamedFunctionRef => {
+                let name = get_xnode_name(&curr);
+                if func_names.iter().any(|f| name.starts_with(f)) {
+                    return true;
+                }
+            },
+            _ => {},
+        }
+        stack.push(get_left(&curr));
+        stack.push(get_right(&curr));
+    }
+    return false;
+}
+
+// ---------------------------------------------------------------------
+// 文脈ノードが1個で、descendant軸、descendant-or-self軸、またはchild軸の、
+// 述語がない (あるいは文脈位置によらない) LocStepならば、先頭から
+// limit 個まで探索した結果を返す。
+// それ以外の場合はNone (通常どおり評価する)。
+//
+fn match_location_path_limited(xseq: &XSequence, xnode: &XNodePtr,
+                limit: usize, eval_env: &mut EvalEnv) -> Result<Option<XSequence>, Box<Error>> {
+    if xseq.len() != 1 || ! is_position_independent(&get_right(xnode)) {
+        return Ok(None);
+    }
+    let node = match xseq.get_singleton_node() {
+        Ok(node) => node,
+        Err(_) => return Ok(None),
+    };
+    let node_array = match get_xnode_type(xnode) {
+        XNodeType::AxisDescendant =>
+            match_descendants_limited(&node, xnode, false, limit, eval_env)?,
+        XNodeType::AxisDescendantOrSelf =>
+            match_descendants_limited(&node, xnode, true, limit, eval_env)?,
+        XNodeType::AxisChild =>
+            match_children_limited(&node, xnode, limit, eval_env)?,
+        _ => return Ok(None),
+    };
+    return Ok(Some(new_xsequence_from_node_array(&node_array)));
+}
+
+// ---------------------------------------------------------------------
+// 子孫 (include_selfならば自身も) のうち、ノードテストと述語 (文脈位置
+// によらないもの) に合致するものを、文書順に limit 個まで集める。
+//
+fn match_descendants_limited(node: &NodePtr, xnode: &XNodePtr,
+                include_self: bool, limit: usize,
+                eval_env: &mut EvalEnv) -> Result<Vec<NodePtr>, Box<Error>> {
+    let mut node_array: Vec<NodePtr> = vec!{};
+    let mut stack: Vec<NodePtr> = vec!{};
+    if include_self {
+        stack.push(node.rc_clone());
+    } else {
+        let mut children = node.children();
+        children.reverse();
+        stack.append(&mut children);
+    }
+    while let Some(curr) = stack.pop() {
+        if limit <= node_array.len() {
+            break;
+        }
+        if match_step_individually(&curr, xnode, eval_env)? {
+            node_array.push(curr.rc_clone());
+        }
+        let mut children = curr.children();
+        children.reverse();
+        stack.append(&mut children);
+    }
+    return Ok(node_array);
+}
+
+// ---------------------------------------------------------------------
+// 子のうち、ノードテストと述語 (文脈位置によらないもの) に合致する
+// ものを、文書順に limit 個まで集める。
+//
+fn match_children_limited(node: &NodePtr, xnode: &XNodePtr, limit: usize,
+                eval_env: &mut EvalEnv) -> Result<Vec<NodePtr>, Box<Error>> {
+    let mut node_array: Vec<NodePtr> = vec!{};
+    for child in node.children().iter() {
+        if limit <= node_array.len() {
+            break;
+        }
+        if match_step_individually(child, xnode, eval_env)? {
+            node_array.push(child.rc_clone());
+        }
+    }
+    return Ok(node_array);
+}
+
+// ---------------------------------------------------------------------
+// ノードが、LocStepのノードテストと述語 (文脈位置によらないもの) に
+// 合致するか否か。
+//
+fn match_step_individually(node: &NodePtr, xnode: &XNodePtr,
+                eval_env: &mut EvalEnv) -> Result<bool, Box<Error>> {
+    if ! match_node_test(node, xnode) {
+        return Ok(false);
+    }
+    let predicates = get_right(xnode);
+    if is_nil_xnode(&predicates) {
+        return Ok(true);
+    }
+    let filtered = filter_by_predicates(&new_singleton_node(node), &predicates, eval_env)?;
+    return Ok(! filtered.is_empty());
+}
+
 // ---------------------------------------------------------------------
 // XSequence中の各ノードに対し、xnodeで示されるLocStepを適用して
 // 合致するノード集合を取得し、その合併をXSequenceとして返す。
@@ -794,6 +1597,192 @@ fn match_location_path(xseq: &XSequence, xnode: &XNodePtr,
 fn match_loc_step(node: &NodePtr, xnode: &XNodePtr,
                 eval_env: &mut EvalEnv) -> Result<XSequence, Box<Error>> {
 
+    if let Some(result) = match_loc_step_by_attr_index(node, xnode, eval_env)? {
+        return Ok(result);
+    }
+
+    // 述語で last() を参照する場合、同じ文脈ノードから同じLocStepを
+    // 何度もたどることが多い (「../item[last()]」など) ので、
+    // 述語を適用する前のノード配列をキャッシュしておく。
+    let rhs = get_right(&xnode);
+    if ! is_nil_xnode(&rhs) && refers_to_function(&rhs, &["fn:last"]) {
+        let key = (node.rc_clone(), get_xnode_id(xnode));
+        let nodes = match eval_env.axis_cache.get(&key) {
+            Some(entry) => Rc::clone(&entry.nodes),
+            None => {
+                let nodes = Rc::new(new_xsequence_from_node_array(
+                                        &match_axis(node, xnode)?));
+                if eval_env.axis_cache.len() < AXIS_CACHE_CAPACITY {
+                    eval_env.axis_cache.insert(key, AxisCacheEntry{
+                        _step: xnode.clone(),
+                        nodes: Rc::clone(&nodes),
+                    });
+                }
+                nodes
+            },
+        };
+        return filter_by_predicates(&nodes, &rhs, eval_env);
+    }
+
+    let node_array = match_axis(node, xnode)?;
+
+    // 述語によって絞り込む。
+    if ! is_nil_xnode(&rhs) {
+        let result = filter_by_predicates(
+                &new_xsequence_from_node_array(&node_array), &rhs, eval_env)?;
+        return Ok(result);
+    } else {
+        return Ok(new_xsequence_from_node_array(&node_array));
+    }
+}
+
+// ---------------------------------------------------------------------
+// 述語の並びの先頭が「@attr = 値」(または「値 = @attr」) であって、
+// 値が文脈によらない式 (文字列リテラル、変数参照、それらの並び) ならば、
+// (属性名, 値の式) を返す。
+// 索引は接頭辞を含む属性名で引くので、接頭辞つきの名前は (束縛が
+// 固定されている) xml: だけを扱う。
+//
+fn attr_index_predicate(predicates: &XNodePtr) -> Option<(String, XNodePtr)> {
+    if is_nil_xnode(predicates) ||
+       get_xnode_type(predicates) != XNodeType::PredicateTop {
+        return None;
+    }
+    let expr = get_left(predicates);
+    if get_xnode_type(&expr) != XNodeType::OperatorGeneralEQ {
+        return None;
+    }
+    let lhs = get_left(&expr);
+    let rhs = get_right(&expr);
+    for &(attr, value) in [(&lhs, &rhs), (&rhs, &lhs)].iter() {
+        let name = get_xnode_name(attr);
+        if get_xnode_type(attr) == XNodeType::AxisAttribute &&
+           is_nil_xnode(&get_left(attr)) && is_nil_xnode(&get_right(attr)) &&
+           ! name.contains(|c| c == '*' || c == '(' || c == '{') &&
+           (! name.contains(':') || name.starts_with("xml:")) &&
+           is_context_independent_value(value) {
+            return Some((name, value.clone()));
+        }
+    }
+    return None;
+}
+
+// ---------------------------------------------------------------------
+// 文字列リテラル、変数参照、およびそれらの並び (括弧つき) か否か。
+//
+fn is_context_independent_value(xnode: &XNodePtr) -> bool {
+    match get_xnode_type(xnode) {
+        XNodeType::StringLiteral | XNodeType::VarRef => return true,
+        XNodeType::ParenthesizedExpr => {
+            let left = get_left(xnode);
+            return ! is_nil_xnode(&left) && is_context_independent_value(&left);
+        },
+        XNodeType::OperatorConcatenate => {
+            return is_context_independent_value(&get_left(xnode)) &&
+                   is_context_independent_value(&get_right(xnode));
+        },
+        _ => return false,
+    }
+}
+
+// ---------------------------------------------------------------------
+// 各文脈ノードに match_loc_step_by_attr_index() を適用した結果の合併。
+// いずれかの文脈ノードについて索引が使えなければNone。
+//
+fn match_location_path_by_attr_index(xseq: &XSequence, xnode: &XNodePtr,
+                eval_env: &mut EvalEnv) -> Result<Option<XSequence>, Box<Error>> {
+    let nodes = xseq.to_nodeset();
+    if nodes.len() == 0 || nodes.len() != xseq.len() {
+        return Ok(None);
+    }
+    let mut node_array: Vec<NodePtr> = vec!{};
+    for node in nodes.iter() {
+        match match_loc_step_by_attr_index(node, xnode, eval_env)? {
+            Some(result) => node_array.append(&mut result.to_nodeset()),
+            None => return Ok(None),
+        }
+    }
+    eval_env.sort_by_doc_order(&mut node_array);
+    return Ok(Some(new_xsequence_in_doc_order(&node_array)));
+}
+
+// ---------------------------------------------------------------------
+// descendant軸またはdescendant-or-self軸のLocStepで、先頭の述語が
+// attr_index_predicate() の形であり、文書に attr の索引があれば、
+// 索引から値の合致する要素を取り出し、ノードテストと残りの述語を
+// 適用する。
+// 値が文字列 (またはノード) 以外の場合は、比較の意味が変わる (数値
+// として比較する) ので、索引を使わない。
+// 索引を使えない場合はNone (通常どおり軸をたどる)。
+//
+fn match_loc_step_by_attr_index(node: &NodePtr, xnode: &XNodePtr,
+                eval_env: &mut EvalEnv) -> Result<Option<XSequence>, Box<Error>> {
+    let include_self = match get_xnode_type(xnode) {
+        XNodeType::AxisDescendant => false,
+        XNodeType::AxisDescendantOrSelf => true,
+        _ => return Ok(None),
+    };
+    let predicates = get_right(xnode);
+    let (attr_name, value_xnode) = match attr_index_predicate(&predicates) {
+        Some(p) => p,
+        None => return Ok(None),
+    };
+    if node.attr_index_lookup(&attr_name, "").is_none() {
+        return Ok(None);
+    }
+
+    let mut values: Vec<String> = vec!{};
+    for item in evaluate_xnode(&new_xsequence(), &value_xnode, eval_env)?.atomize().iter() {
+        match item {
+            XItem::XIString{value} | XItem::XIAnyURI{value} => values.push(value.clone()),
+            _ => return Ok(None),
+        }
+    }
+
+    let mut node_array: Vec<NodePtr> = vec!{};
+    for value in values.iter() {
+        for candidate in node.attr_index_lookup(&attr_name, value).unwrap_or(vec!{}) {
+            if match_node_test(&candidate, xnode) &&
+               is_descendant_of(&candidate, node, include_self) {
+                node_array.push(candidate);
+            }
+        }
+    }
+    if 1 < values.len() {
+        eval_env.sort_by_doc_order(&mut node_array);
+    }
+
+    let result = new_xsequence_from_node_array(&node_array);
+    let rest = get_right(&predicates);
+    if is_nil_xnode(&rest) {
+        return Ok(Some(result));
+    }
+    return Ok(Some(filter_by_predicates(&result, &rest, eval_env)?));
+}
+
+// ---------------------------------------------------------------------
+// node が ancestor の子孫 (include_selfならば自身も含む) か否か。
+//
+fn is_descendant_of(node: &NodePtr, ancestor: &NodePtr, include_self: bool) -> bool {
+    if include_self && node == ancestor {
+        return true;
+    }
+    let mut curr = node.parent();
+    while let Some(n) = curr {
+        if n == *ancestor {
+            return true;
+        }
+        curr = n.parent();
+    }
+    return false;
+}
+
+// ---------------------------------------------------------------------
+// XML木のあるノードを起点として、xNodeで示されるLocStepの
+// 軸とノード・テストに合致するノードの配列を返す (述語は適用しない)。
+//
+fn match_axis(node: &NodePtr, xnode: &XNodePtr) -> Result<Vec<NodePtr>, Box<Error>> {
+
     let mut node_array: Vec<NodePtr> = vec!{};
 
     match get_xnode_type(&xnode) {
@@ -806,7 +1795,10 @@ fn match_loc_step(node: &NodePtr, xnode: &XNodePtr,
         },
 
         XNodeType::AxisAttribute => {
-            node_array = match_along_axis(node, xnode, NodePtr::attributes);
+            node_array = node.attribute_nodes().iter()
+                    .filter(|n| match_node_test(n, xnode))
+                    .map(|n| n.rc_clone())
+                    .collect();
         },
 
         XNodeType::AxisChild => {
@@ -856,20 +1848,11 @@ fn match_loc_step(node: &NodePtr, xnode: &XNodePtr,
         },
 
         _ => {
-            return Err(cant_occur!("match_loc_step: xnode_type: {:?}",
+            return Err(cant_occur!("match_axis: xnode_type: {:?}",
                     get_xnode_type(&xnode)));
         },
     }
-
-    // 述語によって絞り込む。
-    let rhs = get_right(&xnode);
-    if ! is_nil_xnode(&rhs) {
-        let result = filter_by_predicates(
-                &new_xsequence_from_node_array(&node_array), &rhs, eval_env)?;
-        return Ok(result);
-    } else {
-        return Ok(new_xsequence_from_node_array(&node_array));
-    }
+    return Ok(node_array);
 }
 
 // ---------------------------------------------------------------------
@@ -894,10 +1877,12 @@ fn match_along_axis<F>(node: &NodePtr, xnode: &XNodePtr,
 //
 fn array_ancestor(node: &NodePtr) -> Vec<NodePtr> {
     let mut node_array: Vec<NodePtr> = vec!{};
-    if let Some(parent) = node.parent() {
-        node_array.append(&mut array_ancestor(&parent));
+    let mut curr = node.rc_clone();
+    while let Some(parent) = curr.parent() {
         node_array.push(parent.rc_clone());
+        curr = parent;
     }
+    node_array.reverse();
     return node_array;
 }
 
@@ -917,8 +1902,7 @@ pub fn array_ancestor_or_self(node: &NodePtr) -> Vec<NodePtr> {
 fn array_descendant(node: &NodePtr) -> Vec<NodePtr> {
     let mut node_array: Vec<NodePtr> = vec!{};
     for ch in node.children().iter() {
-        node_array.push(ch.rc_clone());
-        node_array.append(&mut array_descendant(ch));
+        push_descendant_or_self(ch, &mut node_array);
     }
     return node_array;
 }
@@ -933,22 +1917,35 @@ fn array_descendant_or_self(node: &NodePtr) -> Vec<NodePtr> {
     return node_array;
 }
 
+// ---------------------------------------------------------------------
+// node自身とその子孫を、文書順にnode_arrayの末尾に追加する。
+// 深い木でもスタックを使い切らないよう、再帰せず作業スタックでたどる。
+//
+fn push_descendant_or_self(node: &NodePtr, node_array: &mut Vec<NodePtr>) {
+    let mut stack: Vec<NodePtr> = vec!{node.rc_clone()};
+    while let Some(curr) = stack.pop() {
+        let mut children = curr.children();
+        children.reverse();
+        stack.append(&mut children);
+        node_array.push(curr);
+    }
+}
+
 // ---------------------------------------------------------------------
 // following軸で合致する候補ノード。
+// 自身から順に祖先をたどり、それぞれの後続兄弟の部分木を文書順に集める。
 //
 fn array_following(node: &NodePtr) -> Vec<NodePtr> {
     let mut node_array: Vec<NodePtr> = vec!{};
     if node.node_type() != NodeType::Attribute {
-        let all_nodes = array_descendant_or_self(&node.root());
-        let descendant_or_self_nodes = array_descendant_or_self(&node);
-        let mut self_occured = false;
-        for ch in all_nodes.iter() {
-            if self_occured && ! descendant_or_self_nodes.contains(ch) {
-                node_array.push(ch.rc_clone());
-            }
-            if ch == node {
-                self_occured = true;
+        let mut curr = node.rc_clone();
+        while let Some(parent) = curr.parent() {
+            if let Some(index) = curr.index_in_parent() {
+                for ch in parent.children()[index + 1 ..].iter() {
+                    push_descendant_or_self(ch, &mut node_array);
+                }
             }
+            curr = parent;
         }
     }
     return node_array;
@@ -958,69 +1955,55 @@ fn array_following(node: &NodePtr) -> Vec<NodePtr> {
 // following-sibling軸で合致する候補ノード。
 //
 fn array_following_sibling(node: &NodePtr) -> Vec<NodePtr> {
-    let mut node_array: Vec<NodePtr> = vec!{};
-    if node.node_type() != NodeType::Attribute {
-        if let Some(parent) = node.parent() {
-            let mut occured = false;
-            for ch in parent.children().iter() {
-                if occured {
-                    node_array.push(ch.rc_clone());
-                }
-                if ch == node {
-                    occured = true;
-                }
-            }
-        }
+    if let (Some(parent), Some(index)) = (node.parent(), node.index_in_parent()) {
+        return parent.children().split_off(index + 1);
     }
-    return node_array;
+    return vec!{};
 }
 
 // ---------------------------------------------------------------------
 // preceding軸で合致する候補ノード。
+// 自身から順に祖先をたどり、それぞれの先行兄弟の部分木を集める。
+// 上位の祖先で集めたものほど文書順で前になるので、最後に並べ替える。
 //
 fn array_preceding(node: &NodePtr) -> Vec<NodePtr> {
-    let mut node_array: Vec<NodePtr> = vec!{};
+    let mut chunks: Vec<Vec<NodePtr>> = vec!{};
     if node.node_type() != NodeType::Attribute {
-        let all_nodes = array_descendant_or_self(&node.root());
-        let ancestor_nodes = array_ancestor(&node);
-        let mut self_occured = false;
-        for ch in all_nodes.iter() {
-            if ch == node {
-                self_occured = true;
-            }
-            if ! self_occured && ! ancestor_nodes.contains(ch) {
-                node_array.push(ch.rc_clone());
+        let mut curr = node.rc_clone();
+        while let Some(parent) = curr.parent() {
+            let mut chunk: Vec<NodePtr> = vec!{};
+            if let Some(index) = curr.index_in_parent() {
+                for ch in parent.children()[.. index].iter() {
+                    push_descendant_or_self(ch, &mut chunk);
+                }
             }
+            chunks.push(chunk);
+            curr = parent;
         }
     }
+    let mut node_array: Vec<NodePtr> = vec!{};
+    for chunk in chunks.iter_mut().rev() {
+        node_array.append(chunk);
+    }
     return node_array;
 }
-
-// ---------------------------------------------------------------------
-// preceding-sibling軸で合致する候補ノード。
-//
-fn array_preceding_sibling(node: &NodePtr) -> Vec<NodePtr> {
-    let mut node_array: Vec<NodePtr> = vec!{};
-    if node.node_type() != NodeType::Attribute {
-        if let Some(parent) = node.parent() {
-            let mut occured = false;
-            for ch in parent.children().iter() {
-                if ch == node {
-                    occured = true;
-                }
-                if ! occured {
-                    node_array.push(ch.rc_clone());
-                }
-            }
-        }
+
+// ---------------------------------------------------------------------
+// preceding-sibling軸で合致する候補ノード。
+//
+fn array_preceding_sibling(node: &NodePtr) -> Vec<NodePtr> {
+    if let (Some(parent), Some(index)) = (node.parent(), node.index_in_parent()) {
+        let mut siblings = parent.children();
+        siblings.truncate(index);
+        return siblings;
     }
-    return node_array;
+    return vec!{};
 }
 
 // ---------------------------------------------------------------------
 // ノード・テスト (名前テストまたは種類テスト)。
 //
-fn match_node_test(node: &NodePtr, xnode: &XNodePtr) -> bool {
+pub fn match_node_test(node: &NodePtr, xnode: &XNodePtr) -> bool {
 
     // xnode: AxisNNNN;
     // get_left(&xnode) がNilのとき:
@@ -1052,7 +2035,9 @@ fn match_name_test(node: &NodePtr, xnode: &XNodePtr) -> bool {
     // (XNodeType::KindTestのノードを作るよりも処理が簡単)
     //
     if name_test_pattern.as_str() == "node()" {
-        return true;
+        return node.node_type() != NodeType::XMLDecl &&
+               node.node_type() != NodeType::Directive &&
+               ! node.is_prolog_white_space();
     }
 
     // -------------------------------------------------------------
@@ -1074,12 +2059,9 @@ fn match_name_test(node: &NodePtr, xnode: &XNodePtr) -> bool {
     }
 
     // -------------------------------------------------------------
-    // 名前の照合にもとづく判定 (「*」とも照合)
+    // 名前の照合にもとづく判定 (「*」、「Q{uri}local」とも照合)
     //
-    if name_test_pattern == node.name() {
-        return true;
-    }
-    if name_test_pattern.as_str() == "*" {
+    if match_node_name(node, &name_test_pattern) {
         return true;
     }
 
@@ -1096,10 +2078,40 @@ fn match_name_test(node: &NodePtr, xnode: &XNodePtr) -> bool {
     return false;
 }
 
+// ---------------------------------------------------------------------
+// 要素名・属性名の照合。name は QName、「*」、または
+// URIQualifiedName (Q{uri}local、Q{uri}*)。
+// QName は字句どおりに、URIQualifiedName は名前空間URIと局所名で照合する。
+//
+fn match_node_name(node: &NodePtr, name: &str) -> bool {
+    if name == "*" || name == node.name() {
+        return true;
+    }
+    if let Some((uri, local)) = split_uri_qualified_name(name) {
+        return (local == "*" || local == node.local_name()) &&
+               uri == expanded_namespace_uri(node);
+    }
+    return false;
+}
+
+// ---------------------------------------------------------------------
+// 要素・属性の展開名の名前空間URI。
+// 接頭辞のない属性は名前空間に属さない。接頭辞 xml は宣言不要。
+//
+fn expanded_namespace_uri(node: &NodePtr) -> String {
+    let prefix = node.space_name();
+    if prefix == "" && node.node_type() == NodeType::Attribute {
+        return String::new();
+    }
+    if prefix == "xml" {
+        return String::from("http://www.w3.org/XML/1998/namespace");
+    }
+    return node.namespace_uri();
+}
+
 // ---------------------------------------------------------------------
 // ノードの種類テスト
 //
-// DocumentTest                                  // ☆
 // SchemaElementTest                             // ☆
 // SchemaAttributeTest                           // ☆
 // NamespaceNodeTest                             // ☆
@@ -1107,6 +2119,9 @@ fn match_name_test(node: &NodePtr, xnode: &XNodePtr) -> bool {
 //
 // 2.5.1 Predefined Schema Types
 // - 未検証の要素ノードについては、型註釈が「xs:untyped」になる。
+//   (属性ノードについては「xs:untypedAtomic」。)
+// - Schema#validate() で検証したノードは、その型註釈で照合する
+//   (以下の derives-from の第1引数が、型註釈の型になる)。
 //
 // 2.5.5.3 Element Test
 // - element()、element(*): 任意の要素ノードに合致。
@@ -1124,6 +2139,11 @@ fn match_name_test(node: &NodePtr, xnode: &XNodePtr) -> bool {
 //       derives-from(xs:untyped, TypeName) が true。
 //       nilledプロパティーはtrueでもfalseでもよい。
 //
+// 2.5.5.x Document Test
+// - document-node(): 文書ノードに合致。
+// - document-node(ElementTest):
+//       文書ノードであって、唯一の子要素が ElementTest に合致する。
+//
 // 2.5.5.5 Attribute Test
 // - attribute()、attribute(*): 任意の属性ノードに合致。
 // - attribute(AttributeName): 属性名が一致。
@@ -1140,37 +2160,27 @@ fn match_kind_test(node: &NodePtr, xnode: &XNodePtr) -> bool {
     let test_xnode = get_left(xnode);
     match get_xnode_type(&test_xnode) {
         XNodeType::DocumentTest => {
-            // DocumentTestは未実装。
-        },
-
-        XNodeType::ElementTest => {
-            if node_type != NodeType::Element {
+            if node_type != NodeType::DocumentRoot {
                 return false;
             }
-
-            let element_name = get_xnode_name(&test_xnode);
-                    // 明示的に指定がない場合の既定値は *
-            if element_name != "*" && element_name != node.name() {
-                return false;
+            let element_test_xnode = get_left(&test_xnode);
+            if is_nil_xnode(&element_test_xnode) {
+                return true;
             }
-
-            let type_name_xnode = get_left(&test_xnode);
-            let type_name_ex = get_xnode_name(&type_name_xnode);
-                    // 明示的に指定がない場合の既定値は xs:anyType?
-            let type_name = type_name_ex.trim_right_matches('?');
-            let with_q = type_name_ex.ends_with("?");
-            if ! derives_from("xs:untyped", &type_name) {
-                return false;
+            if get_xnode_type(&element_test_xnode) != XNodeType::ElementTest {
+                return false;       // SchemaElementTestは未実装。
             }
-            if ! with_q {
-                if let Some(p) = node.attribute_value("xsi:nil") {
-                    if p == "true" {
-                        return false;
-                    }
-                }
+            let elems: Vec<NodePtr> = node.children().into_iter()
+                    .filter(|n| n.node_type() == NodeType::Element)
+                    .collect();
+            if elems.len() != 1 {
+                return false;
             }
+            return match_element_test(&elems[0], &element_test_xnode);
+        },
 
-            return true;
+        XNodeType::ElementTest => {
+            return match_element_test(node, &test_xnode);
         },
 
         XNodeType::AttributeTest => {
@@ -1180,14 +2190,18 @@ fn match_kind_test(node: &NodePtr, xnode: &XNodePtr) -> bool {
 
             let attribute_name = get_xnode_name(&test_xnode);
                     // 明示的に指定がない場合の既定値は *
-            if attribute_name != "*" && attribute_name != node.name() {
+            if ! match_node_name(node, &attribute_name) {
                 return false;
             }
 
             let type_name_xnode = get_left(&test_xnode);
             let type_name = get_xnode_name(&type_name_xnode);
                     // 明示的に指定がない場合の既定値は xs:anyType
-            if ! derives_from("xs:untypedAtomic", &type_name) {
+            let type_matches = match type_annotation_matches(node, &type_name) {
+                Some(b) => b,
+                None => derives_from("xs:untypedAtomic", &type_name),
+            };
+            if ! type_matches {
                 return false;
             }
 
@@ -1213,7 +2227,8 @@ fn match_kind_test(node: &NodePtr, xnode: &XNodePtr) -> bool {
         },
 
         XNodeType::TextTest => {
-            return node_type == NodeType::Text;
+            return node_type == NodeType::Text &&
+                   ! node.is_prolog_white_space();
         },
 
         XNodeType::NamespaceNodeTest => {
@@ -1221,7 +2236,11 @@ fn match_kind_test(node: &NodePtr, xnode: &XNodePtr) -> bool {
         },
 
         XNodeType::AnyKindTest => {
-            return true;
+            // XML宣言、DOCTYPEなど、ルート要素の外の空白は
+            // XPathのデータモデルのノードではない。
+            return node_type != NodeType::XMLDecl &&
+                   node_type != NodeType::Directive &&
+                   ! node.is_prolog_white_space();
         },
 
         _ => {},
@@ -1230,6 +2249,44 @@ fn match_kind_test(node: &NodePtr, xnode: &XNodePtr) -> bool {
     return false;
 }
 
+// ---------------------------------------------------------------------
+// ElementTest (element(...)) と照合する。
+// DocumentTest の内側の ElementTest にも使う。
+//
+fn match_element_test(node: &NodePtr, test_xnode: &XNodePtr) -> bool {
+    if node.node_type() != NodeType::Element {
+        return false;
+    }
+
+    let element_name = get_xnode_name(test_xnode);
+            // 明示的に指定がない場合の既定値は *
+    if ! match_node_name(node, &element_name) {
+        return false;
+    }
+
+    let type_name_xnode = get_left(test_xnode);
+    let type_name_ex = get_xnode_name(&type_name_xnode);
+            // 明示的に指定がない場合の既定値は xs:anyType?
+    let type_name = type_name_ex.trim_right_matches('?');
+    let with_q = type_name_ex.ends_with("?");
+    let type_matches = match type_annotation_matches(node, type_name) {
+        Some(b) => b,
+        None => derives_from("xs:untyped", type_name),
+    };
+    if ! type_matches {
+        return false;
+    }
+    if ! with_q {
+        if let Some(p) = node.attribute_value("xsi:nil") {
+            if p == "true" {
+                return false;
+            }
+        }
+    }
+
+    return true;
+}
+
 // ---------------------------------------------------------------------
 // Location Stepで得たシーケンスに対して、述語を順次適用してしぼり込み、
 // 最終的に得られるシーケンスを返す。
@@ -1250,18 +2307,23 @@ fn match_kind_test(node: &NodePtr, xnode: &XNodePtr) -> bool {
 fn filter_by_predicates(xseq: &XSequence, xnode: &XNodePtr,
             eval_env: &mut EvalEnv) -> Result<XSequence, Box<Error>> {
 
-    let mut curr_xseq = xseq.clone();
+    // 最初の述語は xseq に直接適用する (複製しない)。
+    let mut curr_xseq: Option<XSequence> = None;
     let mut curr_xnode = xnode.clone();
 
     while ! is_nil_xnode(&curr_xnode) {
+        let target = match curr_xseq {
+            Some(ref filtered) => filtered,
+            None => xseq,
+        };
         match get_xnode_type(&curr_xnode) {
             XNodeType::PredicateTop => {
-                curr_xseq = filter_by_predicate(&curr_xseq,
-                            &get_left(&curr_xnode), false, eval_env)?;
+                curr_xseq = Some(filter_by_predicate(target,
+                            &get_left(&curr_xnode), false, eval_env)?);
             },
             XNodeType::PredicateRevTop => {
-                curr_xseq = filter_by_predicate(&curr_xseq,
-                            &get_left(&curr_xnode), true, eval_env)?;
+                curr_xseq = Some(filter_by_predicate(target,
+                            &get_left(&curr_xnode), true, eval_env)?);
             },
             _ => {
                 return Err(cant_occur!(
@@ -1272,7 +2334,7 @@ fn filter_by_predicates(xseq: &XSequence, xnode: &XNodePtr,
 
         curr_xnode = get_right(&curr_xnode);
     }
-    return Ok(curr_xseq);
+    return Ok(curr_xseq.unwrap_or_else(|| xseq.clone()));
 }
 
 // ---------------------------------------------------------------------
@@ -1287,6 +2349,27 @@ fn filter_by_predicate(xseq: &XSequence, xnode: &XNodePtr,
         return Err(cant_occur!("filter_by_predicate: xnode is nil"));
     }
 
+    // 「[3]」「[last()]」「[last() - 1]」のように、文脈アイテムにも
+    // 文脈位置にもよらない位置を表す述語は、一度だけ評価して
+    // その位置のアイテムを取り出す。
+    if is_last_relative_position(xnode) {
+        let last = xseq.len();
+        let old_position = eval_env.set_position(0);
+        let old_last = eval_env.set_last(last);
+        let val = evaluate_xnode(&new_xsequence(), xnode, eval_env);
+        eval_env.set_last(old_last);
+        eval_env.set_position(old_position);
+        let mut result = new_xsequence();
+        if let Ok(XItem::XIInteger{value}) = val?.get_singleton_item() {
+            if 1 <= value && value <= usize_to_i64(last) {
+                let position = value as usize;
+                let index = if ! reverse_order { position - 1 } else { last - position };
+                result.push(&xseq.get_item(index));
+            }
+        }
+        return Ok(result);
+    }
+
     let mut result = new_xsequence();
     for (i, xitem) in xseq.iter().enumerate() {
 
@@ -1305,19 +2388,13 @@ fn filter_by_predicate(xseq: &XSequence, xnode: &XNodePtr,
         eval_env.set_position(old_position);
 
         // 評価結果をもとに、このアイテムを残すかどうか判定する。
-        let mut do_push = false;
-        match val.get_singleton_item() {
-            Ok(XItem::XIInteger{value}) => {
-                do_push = value == usize_to_i64(position);
-            },
-            Ok(XItem::XINode{value: _}) => {
-                do_push = true;
-            },
-            Ok(XItem::XIBoolean{value}) => {
-                do_push = value;
-            },
-            _ => {},
-        }
+        // 数値1個ならば文脈位置と比較し、それ以外は実効ブール値による。
+        let do_push = match val.get_singleton_item() {
+            Ok(XItem::XIInteger{value}) => value == usize_to_i64(position),
+            Ok(ref item) if item.is_numeric() =>
+                item.get_as_raw_double()? == position as f64,
+            _ => val.effective_boolean_value()?,
+        };
         if do_push {
             result.push(&xitem.clone());
         }
@@ -1326,6 +2403,26 @@ fn filter_by_predicate(xseq: &XSequence, xnode: &XNodePtr,
 
 }
 
+// ---------------------------------------------------------------------
+// 述語が、整数リテラルと last() の加減算のみからなる (したがって
+// 値は文脈アイテムにも文脈位置にもよらない整数である) か否か。
+//
+fn is_last_relative_position(xnode: &XNodePtr) -> bool {
+    match get_xnode_type(xnode) {
+        XNodeType::IntegerLiteral => return true,
+        XNodeType::FunctionCall => {
+            return get_xnode_name(xnode) == "fn:last" &&
+                   is_nil_xnode(&get_right(xnode));
+        },
+        XNodeType::OperatorAdd |
+        XNodeType::OperatorSubtract => {
+            return is_last_relative_position(&get_left(xnode)) &&
+                   is_last_relative_position(&get_right(xnode));
+        },
+        _ => return false,
+    }
+}
+
 // ---------------------------------------------------------------------
 // インライン函数/マップ/配列に、引数を適用する。
 // xseq: 引数の値を評価する際、対象とするシーケンス (文脈ノード)。
@@ -1373,12 +2470,14 @@ fn apply_argument(xseq: &XSequence, curr_xseq: &XSequence,
     }
 
     // -----------------------------------------------------------------
-    // インライン函数
+    // インライン函数 | 名前付き函数参照 | 部分函数
     //
-    if let Ok(inline_func_xnode) = curr_xseq.get_singleton_xnodeptr() {
-        match get_xnode_type(&inline_func_xnode) {
-            XNodeType::InlineFunction => {
-                return call_inline_func(&inline_func_xnode,
+    if let Ok((func_xnode, captured)) = curr_xseq.get_singleton_function() {
+        match get_xnode_type(&func_xnode) {
+            XNodeType::InlineFunction |
+            XNodeType::NamedFunctionRef |
+            XNodeType::PartialFunctionCall => {
+                return call_function(&func_xnode, &captured,
                             argument_xseq, xseq, eval_env);
             },
             _ => {}
@@ -1451,24 +2550,19 @@ fn call_inline_func(inline_func_xnode: &XNodePtr,
                     "インライン函数: 引数の型が合致していない: {}。",
                     val.to_string()));
         }
+    }
+    eval_env.push_scope();
+    for (i, val) in argument_xseq.iter().enumerate() {
         eval_env.set_var(&param_names[i], &val);
     }
 
     // -----------------------------------------------------------------
-    // インライン函数を実行する。
-    //
-    let value = evaluate_xnode(context_xseq, &func_body_xnode, eval_env)?;
-
-    // -----------------------------------------------------------------
-    // 変数 (仮引数) を削除する。
+    // インライン函数を実行し、変数 (仮引数) を削除する。
     //
-    let mut i: i64 = (param_names.len() as i64) - 1;
-    while 0 <= i {
-        eval_env.remove_var(&param_names[i as usize]);
-        i -= 1;
-    }
+    let value = evaluate_xnode(context_xseq, &func_body_xnode, eval_env);
+    eval_env.pop_scope();
 
-    return Ok(value);
+    return value;
 }
 
 // ---------------------------------------------------------------------
@@ -1553,27 +2647,61 @@ fn call_partial_func(func_xnode: &XNodePtr,
 // ---------------------------------------------------------------------
 // 函数呼び出し。
 // インライン函数、名前付き函数参照、部分函数。
+// captured: 函数アイテムを作った時点で捕捉した変数。呼び出しの間だけ
+//           束縛する (仮引数は内側のスコープで束縛するので、同名ならば
+//           仮引数が優先)。
 //
 pub fn call_function(func_xnode: &XNodePtr,
+                captured: &Vec<(String, XSequence)>,
                 argument_xseq: Vec<XSequence>,
                 context_xseq: &XSequence,
                 eval_env: &mut EvalEnv) -> Result<XSequence, Box<Error>> {
 
-    match get_xnode_type(&func_xnode) {
+    eval_env.push_scope();
+    for (name, value) in captured.iter() {
+        eval_env.set_var(name, value);
+    }
+    let result: Result<XSequence, Box<Error>> = match get_xnode_type(&func_xnode) {
         XNodeType::InlineFunction => {
-            return call_inline_func(func_xnode, argument_xseq, context_xseq, eval_env);
+            call_inline_func(func_xnode, argument_xseq, context_xseq, eval_env)
         },
         XNodeType::NamedFunctionRef => {
-            return call_named_func(func_xnode, argument_xseq, context_xseq, eval_env);
+            call_named_func(func_xnode, argument_xseq, context_xseq, eval_env)
         },
         XNodeType::PartialFunctionCall => {
-            return call_partial_func(func_xnode, argument_xseq, context_xseq, eval_env);
+            call_partial_func(func_xnode, argument_xseq, context_xseq, eval_env)
         },
         _ => {
-            return Err(cant_occur!("call_h_function: XNodeType = {:?}",
-                    get_xnode_type(&func_xnode)));
+            Err(cant_occur!("call_h_function: XNodeType = {:?}",
+                    get_xnode_type(&func_xnode)))
         },
+    };
+    eval_env.pop_scope();
+    return result;
+}
+
+// ---------------------------------------------------------------------
+// 函数アイテムにする xnode の部分木が参照する変数のうち、現在束縛
+// されているものについて、(名前、値) の一覧を返す。
+// 深い部分木でもスタックを使い切らないよう、再帰せず作業スタックでたどる。
+//
+fn capture_variables(xnode: &XNodePtr, eval_env: &EvalEnv) -> Vec<(String, XSequence)> {
+    let mut names: Vec<String> = vec!{};
+    let mut stack: Vec<XNodePtr> = vec!{xnode.clone()};
+    while let Some(curr) = stack.pop() {
+        if get_xnode_type(&curr) == XNodeType::VarRef {
+            let name = get_xnode_name(&curr);
+            if ! names.contains(&name) {
+                names.push(name);
+            }
+        }
+        for ch in [get_left(&curr), get_right(&curr)].iter() {
+            if ! is_nil_xnode(ch) {
+                stack.push(ch.clone());
+            }
+        }
     }
+    return eval_env.scope_snapshot(&names);
 }
 
 // ---------------------------------------------------------------------
@@ -1589,10 +2717,11 @@ pub fn call_function(func_xnode: &XNodePtr,
 //                  | MapTest
 //                  | ArrayTest
 //                  | AtomicOrUnionType
-//                  | ParenthesizedItemType                            ☆
+//                  | ParenthesizedItemType
 // [ 82] AtomicOrUnionType ::= EQName
 // [102] FunctionTest ::= AnyFunctionTest
 //                      | TypedFunctionTest
+// [111] ParenthesizedItemType ::= "(" ItemType ")"
 //
 fn match_sequence_type(xseq: &XSequence, xnode: &XNodePtr) -> Result<bool, Box<Error>> {
     if get_xnode_type(xnode) != XNodeType::SequenceType {
@@ -1623,6 +2752,14 @@ fn match_sequence_type(xseq: &XSequence, xnode: &XNodePtr) -> Result<bool, Box<E
     // -----------------------------------------------------------------
     // ItemTypeに応じて、シーケンスの各アイテムを判定する。
     //
+    return match_sequence_item_type(xseq, &type_xnode);
+}
+
+// ---------------------------------------------------------------------
+// シーケンス xseq の各アイテムを、ItemType である type_xnode と照合する。
+// 出現数は照合済みとする。
+//
+fn match_sequence_item_type(xseq: &XSequence, type_xnode: &XNodePtr) -> Result<bool, Box<Error>> {
     match get_xnode_type(&type_xnode) {
         XNodeType::KindTest => {            // element(name) など
             return Ok(match_sequence_kind_test(xseq, &type_xnode));
@@ -1651,14 +2788,13 @@ fn match_sequence_type(xseq: &XSequence, xnode: &XNodePtr) -> Result<bool, Box<E
             return Ok(match_sequence_atomic_or_union_type(xseq, &type_xnode));
         },
 
-        XNodeType::ParenthesizedItemType => {
-            // =====================================================
-            return Ok(false);
+        XNodeType::ParenthesizedItemType => {   // 括弧を外して照合する
+            return match_sequence_item_type(xseq, &get_left(&type_xnode));
         },
 
         _ => {
             return Err(cant_occur!(
-                "match_sequence_type: xnodeの左辺値のxnode_typeが想定外: {:?}",
+                "match_sequence_item_type: xnode_typeが想定外: {:?}",
                 type_xnode));
         },
     }
@@ -2043,6 +3179,16 @@ fn subtype_entry(xnode: &XNodePtr) -> usize {
 //
 fn subtype_itemtype(ai: &XNodePtr, bi: &XNodePtr) -> bool {
 
+    // -----------------------------------------------------------------
+    // ParenthesizedItemType は括弧を外して比較する。
+    //
+    if get_xnode_type(ai) == XNodeType::ParenthesizedItemType {
+        return subtype_itemtype(&get_left(ai), bi);
+    }
+    if get_xnode_type(bi) == XNodeType::ParenthesizedItemType {
+        return subtype_itemtype(ai, &get_left(bi));
+    }
+
     // -----------------------------------------------------------------
     // 1. Ai and Bi are AtomicOrUnionTypes, and derives-from(Ai, Bi) returns true.
     // 2. Ai is a pure union type, and every type t in the transitive membership of Ai satisfies subtype-itemType(t, Bi).
@@ -2077,8 +3223,8 @@ fn subtype_itemtype(ai: &XNodePtr, bi: &XNodePtr) -> bool {
     // 10. Bi is processing-instruction(Bn), and
     //     Ai is also processing-instruction(Bn).
     //
-//  --------------------------------------------------------------------- ☆
     // 11. Bi is document-node() and Ai is either document-node() or document-node(E) for any ElementTest E.
+//  --------------------------------------------------------------------- ☆
     // 12. Bi is document-node(Be) and Ai is document-node(Ae), and subtype-itemtype(Ae, Be).
 
     // 13. Bi is either element() or element(*), and Ai is an ElementTest.
@@ -2126,6 +3272,13 @@ fn subtype_itemtype(ai: &XNodePtr, bi: &XNodePtr) -> bool {
                     return true;
                 }
             },
+            XNodeType::DocumentTest => {                    // 11.
+                if get_xnode_type(ai) == XNodeType::KindTest &&
+                   get_xnode_type(&get_left(ai)) == XNodeType::DocumentTest &&
+                   is_nil_xnode(&get_left(&get_left(bi))) {
+                    return true;
+                }
+            },
             XNodeType::PITest => {                          // 9. 10.
                 let pi_name = get_xnode_name(bi);
                 if pi_name == "" {
@@ -2351,6 +3504,9 @@ fn derives_from(ai: &str, bi: &str) -> bool {
         ( "xs:anyURI",        "xs:string" ),
                 // anyURIは常にstringに昇格可能
         ( "xs:boolean",       "xs:anyAtomicType" ),
+        ( "xs:base64Binary",  "xs:anyAtomicType" ),
+        ( "xs:hexBinary",     "xs:anyAtomicType" ),
+        ( "xs:dateTime",      "xs:anyAtomicType" ),
         ( "xs:untypedAtomic", "xs:anyAtomicType" ),
         ( "xs:anyAtomicType", "xs:anySimpleType" ),
         ( "xs:anySimpleType", "xs:anyType" ),
@@ -2575,6 +3731,7 @@ mod test {
             ( "some $x in 1 to 2, $y in 2 to 3 satisfies $x + $y = 7", "false" ),
             ( r#"some $a in /root/a satisfies $a/@v = "y""#, "true" ),
             ( r#"some $a in /root/a satisfies $a/@v = "w""#, "false" ),
+            ( "(some $x in 1 to 3 satisfies $x = 2, count($x))", "(true, 0)" ),
         ]);
     }
 
@@ -2594,6 +3751,7 @@ mod test {
         subtest_eval_xpath("every_expr", &xml, &[
             ( "every $x in 3 to 5 satisfies $x > 2", "true" ),
             ( "every $x in 3 to 5 satisfies $x > 3", "false" ),
+            ( "(every $x in 3 to 5 satisfies $x > 3, count($x))", "(false, 0)" ),
             ( "every $x in 1 to 2, $y in 2 to 3 satisfies $x + $y > 2", "true" ),
             ( "every $x in 1 to 2, $y in 2 to 3 satisfies $x + $y > 4", "false" ),
             ( r#"every $a in /root/a satisfies $a/@v != "w""#, "true" ),
@@ -2645,6 +3803,59 @@ mod test {
         ]);
     }
 
+    // -----------------------------------------------------------------
+    // cast as xs:hexBinary | xs:base64Binary
+    //
+    #[test]
+    fn test_cast_as_binary() {
+        let xml = compress_spaces(r#"
+<root base="base">
+    <hex>48656c6c6f</hex>
+    <b64>SGVsbG8=</b64>
+    <bad>XYZ</bad>
+</root>
+        "#);
+
+        subtest_eval_xpath("cast_as_binary", &xml, &[
+            ( r#"hex cast as xs:hexBinary"#, r#"xs:hexBinary("48656C6C6F")"# ),
+            ( r#"string(hex cast as xs:hexBinary)"#, r#""48656C6C6F""# ),
+            ( r#"(hex cast as xs:hexBinary) cast as xs:base64Binary"#, r#"xs:base64Binary("SGVsbG8=")"# ),
+            ( r#"(b64 cast as xs:base64Binary) cast as xs:string"#, r#""SGVsbG8=""# ),
+            ( r#"bad castable as xs:hexBinary"#, "false" ),
+            ( r#"b64 castable as xs:base64Binary"#, "true" ),
+            ( r#"1 castable as xs:hexBinary"#, "false" ),
+            ( r#"(hex cast as xs:hexBinary) instance of xs:hexBinary"#, "true" ),
+            ( r#"(hex cast as xs:hexBinary) instance of xs:anyAtomicType"#, "true" ),
+            ( r#"(hex cast as xs:hexBinary) instance of xs:base64Binary"#, "false" ),
+            ( r#"bad cast as xs:hexBinary"#, "Dynamic Error" ),
+        ]);
+    }
+
+    // -----------------------------------------------------------------
+    // xs:hexBinary | xs:base64Binary の比較
+    //
+    #[test]
+    fn test_compare_binary() {
+        let xml = compress_spaces(r#"
+<root base="base">
+    <hex>48656C6C6F</hex>
+    <hex2>48656c6c6f</hex2>
+    <b64>SGVsbG8=</b64>
+</root>
+        "#);
+
+        subtest_eval_xpath("compare_binary", &xml, &[
+            ( r#"(hex cast as xs:hexBinary) eq (hex2 cast as xs:hexBinary)"#, "true" ),
+            ( r#"(hex cast as xs:hexBinary) ne (hex2 cast as xs:hexBinary)"#, "false" ),
+            ( r#"('00' cast as xs:hexBinary) lt ('01' cast as xs:hexBinary)"#, "true" ),
+            ( r#"('0100' cast as xs:hexBinary) gt ('01' cast as xs:hexBinary)"#, "true" ),
+            ( r#"(hex cast as xs:hexBinary) = hex2"#, "true" ),
+            ( r#"(hex cast as xs:hexBinary) eq (b64 cast as xs:base64Binary)"#, "Type Error" ),
+            ( r#"(hex cast as xs:hexBinary) = (b64 cast as xs:base64Binary)"#, "false" ),
+            ( r#"(hex cast as xs:hexBinary) eq 1"#, "Type Error" ),
+        ]);
+    }
+
     // -----------------------------------------------------------------
     // 軸: following
     //
@@ -2719,6 +3930,26 @@ mod test {
         ]);
     }
 
+    // -----------------------------------------------------------------
+    // 軸: following, preceding (テキスト・ノード、属性ノード)
+    //
+    #[test]
+    fn test_axis_following_preceding_node() {
+        let xml = compress_spaces(r#"
+<root>t1<a>t2<b>t3</b>t4<c base="base" v="1">t5<d>t6</d></c>t7<e>t8</e></a>t9<f>t10</f></root>
+        "#);
+        subtest_eval_xpath("axis_following_preceding_node", &xml, &[
+            ( "string-join(following::text(), ',')", r#""t7,t8,t9,t10""# ),
+            ( "string-join(preceding::text(), ',')", r#""t1,t2,t3,t4""# ),
+            ( "string-join(following::*/name(), ',')", r#""e,f""# ),
+            ( "string-join(preceding::*/name(), ',')", r#""b""# ),
+            ( "count(@v/following::node())", "0" ),
+            ( "count(@v/preceding::node())", "0" ),
+            ( "count(/root/following::node())", "0" ),
+            ( "count(/preceding::node())", "0" ),
+        ]);
+    }
+
     // -----------------------------------------------------------------
     // element() | element(*) | element(sel)
     // element(sel, type_anno) | element(sel, type_anno?)
@@ -2771,6 +4002,93 @@ mod test {
         ]);
     }
 
+    // -----------------------------------------------------------------
+    // URIQualifiedName (Q{uri}local) による名前テスト、函数名、変数名、型名。
+    //
+    #[test]
+    fn test_uri_qualified_name() {
+        let xml = compress_spaces(r#"
+<root xmlns="http://d" xmlns:p="http://p" xmlns:q="http://p">
+    <item a="1" p:a="2" xml:lang="en"/>
+    <p:item/>
+    <q:item/>
+    <x xmlns=""><item/></x>
+</root>
+        "#);
+
+        subtest_eval_xpath("uri_qualified_name", &xml, &[
+            ( "count(//Q{http://p}item)", "2" ),
+            ( "count(//Q{http://d}item)", "1" ),
+            ( "count(//Q{}item)", "1" ),
+            ( "count(//Q{http://p}*)", "2" ),
+            ( "count(//Q{http://none}item)", "0" ),
+            ( "count(/Q{http://d}root/Q{}x/Q{}item)", "1" ),
+            ( "//@Q{}a/string()", r#""1""# ),
+            ( "//@Q{http://p}a/string()", r#""2""# ),
+            ( "//@Q{http://d}a/string()", "()" ),
+            ( "//@Q{http://www.w3.org/XML/1998/namespace}lang/string()", r#""en""# ),
+            ( "count(//element(Q{http://p}item))", "2" ),
+            ( "count(//@attribute(Q{http://p}a))", "1" ),
+            ( "Q{http://www.w3.org/2005/xpath-functions}concat('a', 'b')", r#""ab""# ),
+            ( "Q{http://www.w3.org/2005/xpath-functions/math}sqrt(4)", "2e0" ),
+            ( "Q{http://www.w3.org/2005/xpath-functions}count#1((1, 2))", "2" ),
+            ( "function-lookup('Q{http://www.w3.org/2005/xpath-functions}count', 1)((1, 2))", "2" ),
+            ( "Q{http://example.com}count(1)", "Syntax Error in XPath" ),
+            ( "let $Q{}x := 3 return $x + $Q{}x", "6" ),
+            ( "let $Q{http://u}x := 3 return $Q{http://u}x", "3" ),
+            ( "for $Q{}i in 1 to 2 return $i", "(1, 2)" ),
+            ( "function($Q{}a) { $a + 1 }(1)", "2" ),
+            ( "5 cast as Q{http://www.w3.org/2001/XMLSchema}string", r#""5""# ),
+            ( "5 instance of Q{http://www.w3.org/2001/XMLSchema}integer", "true" ),
+        ]);
+    }
+
+    // -----------------------------------------------------------------
+    // document-node() | document-node(element(root))
+    //
+    #[test]
+    fn test_kind_test_document() {
+        let xml = compress_spaces(r#"
+<root>
+    <a base="base">
+        <sel img="z0"/>
+    </a>
+</root>
+        "#);
+
+        subtest_eval_xpath("kind_test_document", &xml, &[
+            ( "count(/self::document-node())", "1" ),
+            ( "count(/self::document-node(element()))", "1" ),
+            ( "count(/self::document-node(element(root)))", "1" ),
+            ( "count(/self::document-node(element(a)))", "0" ),
+            ( "count(self::document-node())", "0" ),
+            ( "count(ancestor-or-self::document-node())", "1" ),
+            ( "count(//sel/ancestor::document-node(element(root)))", "1" ),
+        ]);
+    }
+
+    // -----------------------------------------------------------------
+    // node(): XML宣言、DOCTYPEとは合致しない。
+    //
+    #[test]
+    fn test_kind_test_node_prolog() {
+        let xml = compress_spaces(r#"
+<?xml version='1.0' encoding='UTF-8'?>
+<!DOCTYPE root SYSTEM "root.dtd">
+<!-- prolog -->
+<root><a/></root>
+        "#);
+
+        subtest_eval_xpath("kind_test_node_prolog", &xml, &[
+            ( "count(/node())", "2" ),
+            ( "count(/child::node())", "2" ),
+            ( "count(//.)", "4" ),
+            ( "name(/*)", r#""root""# ),
+            ( "count(root(/root/a)/comment())", "1" ),
+            ( "contains(string(/), 'version')", "false" ),
+        ]);
+    }
+
     // -----------------------------------------------------------------
     // processing-instruction()
     //
@@ -2798,6 +4116,33 @@ mod test {
         ]);
     }
 
+    // -----------------------------------------------------------------
+    // ルート要素の前後 (prolog / epilog) の処理命令とコメント。
+    // 間の空白はXPathのデータモデルのノードではない。
+    //
+    #[test]
+    fn test_prolog_epilog() {
+        let xml = r#"<?xml version="1.0"?>
+<?pi1 a?>
+<!--c1-->
+<root>
+  <a/>
+</root>
+<?pi2 b?>
+<!--c2-->
+"#;
+
+        subtest_eval_xpath("prolog_epilog", &xml, &[
+            ( "/node()", "(<?pi1 a?>, <!--c1-->, <root>, <?pi2 b?>, <!--c2-->)" ),
+            ( "/processing-instruction()", "(<?pi1 a?>, <?pi2 b?>)" ),
+            ( "/comment()", "(<!--c1-->, <!--c2-->)" ),
+            ( "count(/text())", "0" ),
+            ( "count(//text())", "2" ),
+            ( "/root/following-sibling::node()", "(<?pi2 b?>, <!--c2-->)" ),
+            ( "/root/preceding-sibling::comment()", "<!--c1-->" ),
+        ]);
+    }
+
     // -----------------------------------------------------------------
     // ContextItemExpr
     //
@@ -2913,6 +4258,92 @@ mod test {
         ]);
     }
 
+    // -----------------------------------------------------------------
+    // fn:subsequence() の第1引数は、必要な個数だけ探索する。
+    // 結果は、すべて探索した場合と同じでなければならない。
+    //
+    #[test]
+    fn test_subsequence_item_limit() {
+        let xml = compress_spaces(r#"
+<root>
+    <x v="1"><x v="2"><y/><x v="3"/></x></x>
+    <a><x v="4"/><b><x v="5"/></b></a>
+    <x v="6"/>
+</root>
+        "#);
+
+        subtest_eval_xpath("subsequence_item_limit", &xml, &[
+            ( "subsequence(//x, 1, 3)/@v/string()", r#"("1", "2", "3")"# ),
+            ( "subsequence(//x, 2, 2)/@v/string()", r#"("2", "3")"# ),
+            ( "subsequence(//x, 0, 1)", "()" ),
+            ( "subsequence(//x, 5.5, 1e300)/@v/string()", r#""6""# ),
+            ( "subsequence(/root//x, 3, 2)/@v/string()", r#"("3", "4")"# ),
+            ( "subsequence(/root/descendant::x, 4, 1)/@v/string()", r#""4""# ),
+            ( "subsequence(//a//x, 2, 1)/@v/string()", r#""5""# ),
+            ( "subsequence(//x[number(@v) > 3], 1, 2)/@v/string()", r#"("4", "5")"# ),
+            ( "subsequence(//x[1], 1, 3)/@v/string()", r#"("1", "2", "3")"# ),
+            ( "subsequence(//x/@v, 2, 2)/string()", r#"("2", "3")"# ),
+            ( "subsequence(//x, 1, 0 div 0e0)", "()" ),
+            ( "count(//x), count(subsequence(//x, 1, 2))", "(6, 2)" ),
+        ]);
+    }
+
+    // -----------------------------------------------------------------
+    // last() を参照する述語: 軸のキャッシュ、位置を表す述語の一括評価。
+    // 結果は、アイテムごとに評価した場合と同じでなければならない。
+    //
+    #[test]
+    fn test_last_in_predicate() {
+        let xml = compress_spaces(r#"
+<root>
+    <a><i v="1"/><i v="2"/><i v="3"/></a>
+    <a><i v="4"/><j/><i v="5"/></a>
+    <a/>
+</root>
+        "#);
+
+        subtest_eval_xpath("last_in_predicate", &xml, &[
+            ( "//i[. is ../i[last()]]/@v/string()", r#"("3", "5")"# ),
+            ( "//i[. is ../i[last() - 1]]/@v/string()", r#"("2", "4")"# ),
+            ( "//a/i[last()]/@v/string()", r#"("3", "5")"# ),
+            ( "//a/i[2]/@v/string()", r#"("2", "5")"# ),
+            ( "//a/i[last() + 1]", "()" ),
+            ( "//a/i[0]", "()" ),
+            ( "(//i)[last()]/@v/string()", r#""5""# ),
+            ( "(//i)[last() - 3]/@v/string()", r#""2""# ),
+            ( "//i[@v = '5']/preceding-sibling::*[1]/name()", r#""j""# ),
+            ( "//i[@v = '5']/preceding-sibling::*[last()]/@v/string()", r#""4""# ),
+            ( "//i[@v = '3']/preceding::i[last()]/@v/string()", r#""1""# ),
+            ( "//a/i[position() > last() - 2]/@v/string()", r#"("2", "3", "4", "5")"# ),
+            ( "//a/i[last()][@v != '3']/@v/string()", r#""5""# ),
+            ( "//a[i[last()]/@v = '5']/i[1]/@v/string()", r#""4""# ),
+            ( "count(//a[i[last()]])", "2" ),
+        ]);
+    }
+
+    // -----------------------------------------------------------------
+    // 変数のスコープ: 同名の変数を入れ子に束縛する。
+    //
+    #[test]
+    fn test_variable_scope() {
+        let xml = compress_spaces(r#"
+<root>
+</root>
+        "#);
+
+        subtest_eval_xpath("variable_scope", &xml, &[
+            ( "let $x := 1 return (let $x := 2 return $x, $x)", "(2, 1)" ),
+            ( "let $x := 1, $x := $x + 1 return $x", "2" ),
+            ( "for $x in 1 to 2 return (for $x in ($x * 10) return $x, $x)", "(10, 1, 20, 2)" ),
+            ( "for $x in 1 to 2, $y in $x to 2 return $x * 10 + $y", "(11, 12, 22)" ),
+            ( "let $x := 3 return (some $x in (1, 2) satisfies $x = 2, $x)", "(true, 3)" ),
+            ( "let $x := 3 return (every $x in (1, 2) satisfies $x = 1, $x)", "(false, 3)" ),
+            ( "let $x := 5 return (function($x) { $x * 2 }(4), $x)", "(8, 5)" ),
+            ( "let $f := function($x) { let $x := $x + 1 return $x } return ($f(1), $f(10))", "(2, 11)" ),
+            ( "(let $x := 1 return $x, count($x))", "(1, 0)" ),
+        ]);
+    }
+
     // -----------------------------------------------------------------
     // InlineFunction
     //
@@ -2932,6 +4363,27 @@ mod test {
         ]);
     }
 
+    // -----------------------------------------------------------------
+    // InlineFunction: 作った時点の変数を捕捉する (クロージャー)。
+    //
+    #[test]
+    fn test_inline_function_closure() {
+        let xml = compress_spaces(r#"
+<root>
+</root>
+        "#);
+
+        subtest_eval_xpath("inline_function_closure", &xml, &[
+            ( "let $f := let $y := 2 return function() { $y } return $f()", "2" ),
+            ( "let $adders := for $n in 1 to 3 return function($x) { $x + $n } return $adders[2](10)", "12" ),
+            ( "for-each(1 to 3, let $k := 10 return function($x) { $x * $k })", "(10, 20, 30)" ),
+            ( "let $y := 1 return let $f := function() { $y } return let $y := 5 return $f()", "1" ),
+            ( "let $y := 1 return let $f := function($y) { $y } return $f(7)", "7" ),
+            ( "let $g := let $s := 'a' return starts-with(?, $s) return $g('abc')", "true" ),
+            ( "let $f := let $y := 2 return function() { $y } return ($f(), count($y))", "(2, 0)" ),
+        ]);
+    }
+
     // -----------------------------------------------------------------
     // NamedFunctionRef
     //
@@ -3031,13 +4483,13 @@ mod test {
                     "Su" : "Sunday",
                     "Mo" : "Monday"
                 }[.("Su") = "Sunday"]
-              "#, r#"{"Su" => "Sunday", "Mo" => "Monday"}"# ),
+              "#, r#"map{"Su": "Sunday", "Mo": "Monday"}"# ),
             ( r#"
                 map {
                     "Su" : "Sunday",
                     "Mo" : "Monday"
                 }[?Su = "Sunday"]
-              "#, r#"{"Su" => "Sunday", "Mo" => "Monday"}"# ),
+              "#, r#"map{"Su": "Sunday", "Mo": "Monday"}"# ),
 
             // NCName
             ( r#"
@@ -3131,6 +4583,12 @@ mod test {
             ( r#"(5, "ss") instance of xs:anyAtomicType+ "#, "true" ),
             ( r#" . instance of element() + "#, "true" ),
 
+            // DocumentTest
+            ( r#" / instance of document-node() "#, "true" ),
+            ( r#" / instance of document-node(element(root)) "#, "true" ),
+            ( r#" / instance of document-node(element(elem)) "#, "false" ),
+            ( r#" . instance of document-node() "#, "false" ),
+
             // ArrayTest
             ( r#" [1, 2] instance of array(*) "#, "true" ),
             ( r#" [1, 2] instance of array(xs:integer) "#, "true" ),
@@ -3318,8 +4776,139 @@ mod test {
         "#);
 
         subtest_eval_xpath("instance_of_parenthesized_item_type", &xml, &[
-//            ( " (1) instance of (xs:integer) ", "true" ),
+            ( " (1) instance of (xs:integer) ", "true" ),
+            ( " (1) instance of (xs:string) ", "false" ),
+            ( " (1, 2) instance of (xs:integer)+ ", "true" ),
+            ( " (1, 2) instance of (xs:integer)? ", "false" ),
+            ( " () instance of (xs:integer)* ", "true" ),
+            ( " () instance of (xs:integer) ", "false" ),
+            ( " 1 instance of ((xs:integer)) ", "true" ),
+            ( " 1 instance of ((xs:decimal))? ", "true" ),
+            ( " . instance of (element(elem)) ", "true" ),
+            ( " . instance of (attribute()) ", "false" ),
+            ( " (1, 'a') instance of (item())* ", "true" ),
+            ( " [1] instance of (array(xs:integer)) ", "true" ),
+            ( " function($a) { $a } instance of (function(*)) ", "true" ),
+            ( " (function($a) { $a }, 1) instance of (function(*))+ ", "false" ),
+            ( " function($a as xs:integer) as xs:integer { $a } instance of function((xs:integer)) as (xs:decimal) ", "true" ),
+            ( " function($a as xs:integer) as xs:string { 'a' } instance of function((xs:integer)) as (xs:decimal) ", "false" ),
+        ]);
+    }
+
+    // -----------------------------------------------------------------
+    // Expr (コンマで区切った並び) と ExprSingle (for、let、if、quantified
+    // を含む) を書ける位置
+    //
+    #[test]
+    fn test_expr_positions() {
+        let xml = compress_spaces(r#"
+<root>
+    <a v="1">x</a>
+    <a v="2">y</a>
+</root>
+        "#);
+
+        subtest_eval_xpath("expr_positions", &xml, &[
+            // [ 52] Predicate ::= "[" Expr "]"
+            ( "//a[let $v := '2' return @v = $v]/@v/string()", r#""2""# ),
+            ( "//a[for $i in ('1', '3') return @v[. = $i]]/@v/string()", r#""1""# ),
+            ( "//a[some $i in ('2', '3') satisfies @v = $i]/@v/string()", r#""2""# ),
+            ( "//a[if (@v = '1') then true() else false()]/@v/string()", r#""1""# ),
+            ( "//a[(@v, 'z')] ! string(@v)", r#"("1", "2")"# ),
+            ( "//a['x'] ! string(@v)", r#"("1", "2")"# ),
+            ( "//a[2.0]/@v/string()", r#""2""# ),
+            ( "//a[1, 2]", "Type Error" ),
+            // [ 61] ParenthesizedExpr ::= "(" Expr? ")"
+            ( "(let $x := 1 return $x, 2)", "(1, 2)" ),
+            // [ 15] IfExpr ::= "if" "(" Expr ")" "then" ExprSingle "else" ExprSingle
+            ( "if ((), 1) then 'y' else 'n'", r#""y""# ),
+            ( "if (1) then let $x := 1 return $x else 2", "1" ),
+            // [ 50] ArgumentList ::= "(" (Argument ("," Argument)*)? ")"
+            ( "concat('a', let $b := 'b' return $b, 'c')", r#""abc""# ),
+            // [ 74] SquareArrayConstructor, [ 75] CurlyArrayConstructor
+            ( "[let $x := 1 return $x, 2]", "[1, 2]" ),
+            ( "array{let $x := 1 return $x, 2}", "[1, 2]" ),
+            ( "array{}", "[]" ),
+            // [ 70] MapConstructorEntry ::= MapKeyExpr ":" MapValueExpr
+            ( "map{let $k := 'a' return $k : let $v := 1 return $v}", r#"map{"a": 1}"# ),
+            ( "let $k := 'a' return map{$k : 1}?a", "1" ),
+            ( "let $k := 'a' return map{$k:1}?a", "1" ),
+            // [ 54] KeySpecifier ::= ... | ParenthesizedExpr | "*"
+            ( "[1, 2, 3]?(let $i := 2 return $i, 3)", "(2, 3)" ),
+            // [  4] FunctionBody ::= EnclosedExpr
+            ( "function() { let $x := 1 return $x, 2 }()", "(1, 2)" ),
+            ( "function() {}()", "()" ),
+            // [ 55] ArrowFunctionSpecifier ::= EQName | VarRef | ParenthesizedExpr
+            ( "(1, 2) => (function($s) { count($s) })()", "2" ),
+            ( "let $f := count#1 return (1, 2) => $f()", "2" ),
+            // [ 11] ForExpr, [ 14] LetExpr, [ 16] QuantifiedExpr: ExprSingle
+            ( "for $x in let $y := (1, 2) return $y return $x", "(1, 2)" ),
+            ( "let $x := 1, $y := $x + 1 return $y", "2" ),
+            ( "every $x in (1, 2) satisfies some $y in (1, 2) satisfies $x = $y", "true" ),
+            // 演算子の被演算子には ExprSingle を書けない。
+            ( "1 + let $x := 1 return $x", "Syntax Error in XPath" ),
+            ( "'a' || if (1) then 'b' else 'c'", "Syntax Error in XPath" ),
+            // [ 26] TreatExpr
+            ( "(1 treat as xs:integer) + 1", "2" ),
+            ( "(1, 2) treat as xs:integer+", "(1, 2)" ),
+            ( "'a' treat as xs:integer", "Dynamic Error" ),
         ]);
     }
+
+    // -----------------------------------------------------------------
+    // 属性値の索引 (NodePtr#build_attr_index()) を使って評価しても、
+    // 索引なしの場合と同じ結果になる。
+    //
+    #[test]
+    fn test_attr_index() {
+        use dom::*;
+        let xml = compress_spaces(r#"
+<root>
+    <item k="a" n="1"/>
+    <group k="a" n="2">
+        <item k="b" n="3"/>
+        <item k="a" n="4"><item k="a" n="5"/></item>
+        <item k="10" n="6"/>
+    </group>
+    <item k="b" n="7"/>
+</root>
+        "#);
+        let xpaths = [
+            "//item[@k = 'a'] ! string(@n)",
+            "//*[@k = 'a'] ! string(@n)",
+            "//item['a' = @k] ! string(@n)",
+            "//item[@k = ('b', 'a')] ! string(@n)",
+            "//item[@k = 'a'][2] ! string(@n)",
+            "//item[@k = 'a'][@n != '4'] ! string(@n)",
+            "//item[@k = 'z'] ! string(@n)",
+            "//item[@k = 10] ! string(@n)",
+            "/root/group//item[@k = 'a'] ! string(@n)",
+            "/root/group/descendant::item[@k = 'a'][1] ! string(@n)",
+            "//group/descendant-or-self::*[@k = 'a'] ! string(@n)",
+            "//item/descendant::item[@k = 'a'] ! string(@n)",
+            "let $v := ('b', 'x') return //item[@k = $v] ! string(@n)",
+            "for $i in //item return count($i//item[@k = 'a'])",
+        ];
+
+        let plain = new_document(&xml).unwrap();
+        let indexed = new_document(&xml).unwrap();
+        indexed.build_attr_index("k");
+        for xpath in xpaths.iter() {
+            assert_eq!(indexed.eval_xpath(xpath).unwrap().to_string(),
+                       plain.eval_xpath(xpath).unwrap().to_string(), "{}", xpath);
+        }
+
+        // 文書を変更すると、索引を作り直す。
+        for doc in [&plain, &indexed].iter() {
+            let mut item = doc.get_first_node("//item[@n = '7']").unwrap();
+            item.set_attribute("k", "a").unwrap();
+        }
+        for xpath in xpaths.iter() {
+            assert_eq!(indexed.eval_xpath(xpath).unwrap().to_string(),
+                       plain.eval_xpath(xpath).unwrap().to_string(), "{}", xpath);
+        }
+        assert_eq!(indexed.eval_xpath("//item[@k = 'a'] ! string(@n)").unwrap().to_string(),
+                   r#"("1", "4", "5", "7")"#);
+    }
 }
 
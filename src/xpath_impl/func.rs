@@ -5,14 +5,25 @@
 // Copyright (C) 2018 KOYAMA Hiro <tac@amris.co.jp>
 //
 
+use std::cmp::Ordering;
 use std::error::Error;
 use std::f64;
 use std::i64;
 use std::usize;
+#[cfg(not(feature = "no_std"))]
+use std::time::{SystemTime, UNIX_EPOCH};
+#[cfg(feature = "no_std")]
+use std::prelude::*;
 
+use datetime::*;
 use dom::*;
 use xmlerror::*;
+use xpath::new_sequence;
+use xpath_impl::cast::is_cast_target;
+use xpath_impl::collation::*;
 use xpath_impl::eval::*;
+use xpath_impl::parser::*;
+use xpath_impl::resolver::uri_scheme;
 use xpath_impl::xitem::*;
 use xpath_impl::xsequence::*;
 
@@ -28,19 +39,48 @@ fn usize_to_i64(n: usize) -> i64 {
 const FUNC_SIGNATURE_TBL: [(
         &str,               // NamedFunctionRef形式の函数名
         &str);              // シグニチャー
-        86] = [
+        151] = [
     ( "fn:nilled#0", "function() as xs:boolean?" ),
     ( "fn:nilled#1", "function(node()?) as xs:boolean?" ),
     ( "fn:string#0", "function() as xs:string" ),
     ( "fn:string#1", "function(item()?) as xs:string" ),
     ( "fn:data#0", "function() as xs:anyAtomicType*" ),
     ( "fn:data#1", "function(item()*) as xs:anyAtomicType*" ),
+    ( "fn:base-uri#0", "function() as xs:anyURI?" ),
+    ( "fn:base-uri#1", "function(node()?) as xs:anyURI?" ),
+    ( "fn:document-uri#0", "function() as xs:anyURI?" ),
+    ( "fn:document-uri#1", "function(node()?) as xs:anyURI?" ),
+    ( "fn:error#0", "function() as item()*" ),
+    ( "fn:error#1", "function(xs:string?) as item()*" ),
+    ( "fn:error#2", "function(xs:string?, xs:string) as item()*" ),
+    ( "fn:error#3", "function(xs:string?, xs:string, item()*) as item()*" ),
+    ( "fn:trace#1", "function(item()*) as item()*" ),
+    ( "fn:trace#2", "function(item()*, xs:string) as item()*" ),
     ( "fn:abs#1", "function(numeric?) as numeric?" ),
     ( "fn:ceiling#1", "function(numeric?) as numeric?" ),
     ( "fn:floor#1", "function(numeric?) as numeric?" ),
     ( "fn:round#1", "function(numeric?) as numeric?" ),
+    ( "fn:round#2", "function(numeric?, xs:integer) as numeric?" ),
+    ( "fn:round-half-to-even#1", "function(numeric?) as numeric?" ),
+    ( "fn:round-half-to-even#2", "function(numeric?, xs:integer) as numeric?" ),
     ( "fn:number#0", "function() as xs:double" ),
     ( "fn:number#1", "function(xs:anyAtomicType?) as xs:double" ),
+    ( "math:pi#0", "function() as xs:double" ),
+    ( "math:exp#1", "function(xs:double?) as xs:double?" ),
+    ( "math:exp10#1", "function(xs:double?) as xs:double?" ),
+    ( "math:log#1", "function(xs:double?) as xs:double?" ),
+    ( "math:log10#1", "function(xs:double?) as xs:double?" ),
+    ( "math:pow#2", "function(xs:double?, numeric) as xs:double?" ),
+    ( "math:sqrt#1", "function(xs:double?) as xs:double?" ),
+    ( "math:sin#1", "function(xs:double?) as xs:double?" ),
+    ( "math:cos#1", "function(xs:double?) as xs:double?" ),
+    ( "math:tan#1", "function(xs:double?) as xs:double?" ),
+    ( "math:asin#1", "function(xs:double?) as xs:double?" ),
+    ( "math:acos#1", "function(xs:double?) as xs:double?" ),
+    ( "math:atan#1", "function(xs:double?) as xs:double?" ),
+    ( "math:atan2#2", "function(xs:double, xs:double) as xs:double" ),
+    ( "fn:random-number-generator#0", "function() as map(xs:string, item())" ),
+    ( "fn:random-number-generator#1", "function(xs:anyAtomicType?) as map(xs:string, item())" ),
     ( "fn:codepoints-to-string#1", "function(xs:integer*) as xs:string" ),
     ( "fn:string-to-codepoints#1", "function(xs:string*) as xs:integer*" ),
     ( "fn:compare#2", "function(xs:string?, xs:string?) as xs:integer?" ),
@@ -54,8 +94,10 @@ const FUNC_SIGNATURE_TBL: [(
     ( "fn:substring#3", "function(xs:string?, xs:double, xs:double) as xs:string" ),
     ( "fn:string-length#0", "function() as xs:integer" ),
     ( "fn:string-length#1", "function(xs:string?) as xs:integer" ),
-    ( "fn:normalize-space#0", "function() as xs:integer" ),
-    ( "fn:normalize-space#1", "function(xs:string?) as xs:integer" ),
+    ( "fn:normalize-space#0", "function() as xs:string" ),
+    ( "fn:normalize-space#1", "function(xs:string?) as xs:string" ),
+    ( "fn:normalize-unicode#1", "function(xs:string?) as xs:string" ),
+    ( "fn:normalize-unicode#2", "function(xs:string?, xs:string) as xs:string" ),
     ( "fn:upper-case#1", "function(xs:string?) as xs:string" ),
     ( "fn:lower-case#1", "function(xs:string?) as xs:string" ),
     ( "fn:translate#3", "function(xs:string?, xs:string, xs:string) as xs:string" ),
@@ -69,6 +111,9 @@ const FUNC_SIGNATURE_TBL: [(
     ( "fn:substring-before#3", "function(xs:string?, xs:string?, xs:string) as xs:string" ),
     ( "fn:substring-after#2", "function(xs:string?, xs:string?) as xs:string" ),
     ( "fn:substring-after#3", "function(xs:string?, xs:string?, xs:string) as xs:string" ),
+    ( "fn:resolve-uri#1", "function(xs:string?) as xs:anyURI?" ),
+    ( "fn:resolve-uri#2", "function(xs:string?, xs:string) as xs:anyURI?" ),
+    ( "fn:parse-ietf-date#1", "function(xs:string?) as xs:dateTime?" ),
     ( "fn:true#0", "function() as xs:boolean" ),
     ( "fn:false#0", "function() as xs:boolean" ),
     ( "fn:boolean#1", "function(item()*) as xs:boolean" ),
@@ -83,6 +128,10 @@ const FUNC_SIGNATURE_TBL: [(
     ( "fn:lang#2", "function(xs:string?, node()) as xs:boolean" ),
     ( "fn:root#0", "function() as node()" ),
     ( "fn:root#1", "function(node()?) as node()?" ),
+    ( "fn:has-children#0", "function() as xs:boolean" ),
+    ( "fn:has-children#1", "function(node()?) as xs:boolean" ),
+    ( "fn:siblings#0", "function() as node()*" ),
+    ( "fn:siblings#1", "function(node()?) as node()*" ),
     ( "fn:empty#1", "function(item()*) as xs:boolean" ),
     ( "fn:exists#1", "function(item()*) as xs:boolean" ),
     ( "fn:head#1", "function(item()*) as item()?" ),
@@ -92,11 +141,13 @@ const FUNC_SIGNATURE_TBL: [(
     ( "fn:reverse#1", "function(item()*) as item()*" ),
     ( "fn:subsequence#2", "function(item()*, xs:double) as item()*" ),
     ( "fn:subsequence#3", "function(item()*, xs:double, xs:double) as item()*" ),
+    ( "fn:distinct-values#1", "function(xs:anyAtomicType*) as xs:anyAtomicType*" ),
+    ( "fn:distinct-values#2", "function(xs:anyAtomicType*, xs:string) as xs:anyAtomicType*" ),
     ( "fn:index-of#2", "function(xs:anyAtomicType*, xs:anyAtomicType) as xs:integer*" ),
     ( "fn:index-of#3", "function(xs:anyAtomicType*, xs:anyAtomicType, xs:string) as xs:integer*" ),
     ( "fn:zero-or-one#1", "function(item()*) as item()?" ),
-    ( "fn:one-or-more#1", "function(item()*) as item()?" ),
-    ( "fn:exactly-one#1", "function(item()*) as item()?" ),
+    ( "fn:one-or-more#1", "function(item()*) as item()+" ),
+    ( "fn:exactly-one#1", "function(item()*) as item()" ),
     ( "fn:count#1", "function(item()*) as xs:integer" ),
     ( "fn:avg#1", "function(xs:anyAtomicType*) as xs:anyAtomicType?" ),
     ( "fn:max#1", "function(xs:anyAtomicType*) as xs:anyAtomicType?" ),
@@ -105,10 +156,23 @@ const FUNC_SIGNATURE_TBL: [(
     ( "fn:min#2", "function(xs:anyAtomicType*, xs:string) as xs:anyAtomicType?"  ),
     ( "fn:sum#1", "function(xs:anyAtomicType*) as xs:anyAtomicType?" ),
     ( "fn:sum#2", "function(xs:anyAtomicType*, xs:anyAtomicType?) as xs:anyAtomicType?" ),
+    ( "fn:id#1", "function(xs:string*) as element()*" ),
+    ( "fn:id#2", "function(xs:string*, node()) as element()*" ),
+    ( "fn:doc#1", "function(xs:string?) as document-node()?" ),
+    ( "fn:doc-available#1", "function(xs:string?) as xs:boolean" ),
+    ( "fn:unparsed-text#1", "function(xs:string?) as xs:string?" ),
+    ( "fn:unparsed-text-available#1", "function(xs:string?) as xs:boolean" ),
+    ( "fn:collection#0", "function() as item()*" ),
+    ( "fn:collection#1", "function(xs:string?) as item()*" ),
     ( "fn:position#0", "function() as xs:integer" ),
     ( "fn:last#0", "function() as xs:integer" ),
+    ( "fn:static-base-uri#0", "function() as xs:anyURI?" ),
+    ( "fn:function-lookup#2", "function(xs:string, xs:integer) as function(*)?" ),
+    ( "fn:function-name#1", "function(function(*)) as xs:string?" ),
+    ( "fn:function-arity#1", "function(function(*)) as xs:integer" ),
     ( "fn:for-each#2", "function(item()*, function(item()) as item()*) as item()*" ),
     ( "fn:filter#2", "function(item()*, function(item()) as xs:boolean) as item()*" ),
+    ( "fn:apply#2", "function(function(*), array(*)) as item()*" ),
     ( "map:size#1", "function(map(*)) as xs:integer" ),
     ( "map:keys#1", "function(map(*)) as xs:anyAtomicType*" ),
     ( "map:contains#2", "function(map(*), xs:anyAtomicType) as xs:boolean" ),
@@ -116,6 +180,18 @@ const FUNC_SIGNATURE_TBL: [(
     ( "array:size#1", "function(array(*)) as xs:integer" ),
     ( "array:get#2", "function(array(*), xs:integer) as item()*" ),
     ( "array:flatten#1", "function(item()*) as item()*" ),
+    ( "am:group-by#2", "function(item()*, function(item()) as xs:anyAtomicType?) as map(*)" ),
+    ( "am:group-adjacent#2", "function(item()*, function(item()) as xs:anyAtomicType?) as array(*)" ),
+    ( "am:parse-rfc3339#1", "function(xs:string?) as xs:dateTime?" ),
+    ( "am:parse-html#1", "function(xs:string) as document-node()" ),
+    ( "am:random-permute#2", "function(item()*, xs:anyAtomicType?) as item()*" ),
+    ( "am:put-in#3", "function(item()*, array(*), item()*) as item()*" ),
+    ( "am:update#3", "function(item(), xs:anyAtomicType, function(item()*) as item()*) as item()" ),
+    ( "am:child-count#1", "function(node()?) as xs:integer" ),
+    ( "am:child-element-count#1", "function(node()?) as xs:integer" ),
+    ( "am:grapheme-length#1", "function(xs:string?) as xs:integer" ),
+    ( "am:evaluate#1", "function(xs:string) as item()*" ),
+    ( "am:evaluate#2", "function(xs:string, item()?) as item()*" ),
 ];
 
 // ---------------------------------------------------------------------
@@ -155,34 +231,73 @@ const FUNC_CONTEXT_TBL: [(
         &str,                   // 函数名
         fn(&Vec<&XSequence>, &XSequence, &mut EvalEnv) -> Result<XSequence, Box<Error>>);
                                 // 函数の実体: (引数、文脈シーケンス、評価環境)
-        15] = [
+        48] = [
 // 2
     ( 0, "fn:nilled",          fn_nilled_0 ),
     ( 0, "fn:string",          fn_string_0 ),
     ( 0, "fn:data",            fn_data_0 ),
+    ( 0, "fn:base-uri",        fn_base_uri_0 ),
+    ( 0, "fn:document-uri",    fn_document_uri_0 ),
+// 3
+    ( 1, "fn:trace",           fn_trace ),
+    ( 2, "fn:trace",           fn_trace ),
 // 4.5
     ( 0, "fn:number",          fn_number_0 ),
+// 5.3
+    ( 3, "fn:compare",         fn_compare_collation ),
 // 5.4
     ( 0, "fn:string-length",   fn_string_length_0 ),
     ( 0, "fn:normalize-space", fn_normalize_space_0 ),
+// 6
+    ( 1, "fn:resolve-uri",     fn_resolve_uri_1 ),
 // 13
     ( 0, "fn:name",            fn_name_0 ),
     ( 0, "fn:local-name",      fn_local_name_0 ),
     ( 0, "fn:namespace-uri",   fn_namespace_uri_0 ),
     ( 1, "fn:lang",            fn_lang_1 ),
     ( 0, "fn:root",            fn_root_0 ),
+    ( 0, "fn:has-children",    fn_has_children_0 ),
+    ( 0, "fn:siblings",        fn_siblings_0 ),
+// 14.2
+    ( 1, "fn:distinct-values", fn_distinct_values ),
+    ( 2, "fn:distinct-values", fn_distinct_values ),
+    ( 2, "fn:index-of",        fn_index_of ),
+    ( 3, "fn:index-of",        fn_index_of ),
+// 14.4
+    ( 1, "fn:max",             fn_max ),
+    ( 2, "fn:max",             fn_max ),
+    ( 1, "fn:min",             fn_min ),
+    ( 2, "fn:min",             fn_min ),
+// 14.5
+    ( 1, "fn:id",              fn_id_1 ),
+// 14.6
+    ( 1, "fn:doc",             fn_doc ),
+    ( 1, "fn:doc-available",   fn_doc_available ),
+    ( 1, "fn:unparsed-text",   fn_unparsed_text ),
+    ( 1, "fn:unparsed-text-available", fn_unparsed_text_available ),
+    ( 0, "fn:collection",      fn_collection_0 ),
+    ( 1, "fn:collection",      fn_collection ),
+// 4.9
+    ( 0, "fn:random-number-generator", fn_random_number_generator ),
+    ( 1, "fn:random-number-generator", fn_random_number_generator ),
 // 15
     ( 0, "fn:position",        fn_position ),
     ( 0, "fn:last",            fn_last ),
+    ( 0, "fn:static-base-uri", fn_static_base_uri ),
 // 16.2
     ( 2, "fn:for-each",        fn_for_each ),
     ( 2, "fn:filter",          fn_filter ),
+    ( 2, "fn:apply",           fn_apply ),
+// am: (拡張函数)
+    ( 2, "am:random-permute",  am_random_permute ),
+    ( 2, "am:group-by",        am_group_by ),
+    ( 2, "am:group-adjacent",  am_group_adjacent ),
+    ( 3, "am:update",          am_update ),
+    ( 1, "am:evaluate",        am_evaluate ),
+    ( 2, "am:evaluate",        am_evaluate ),
 
     // [focus-dependent] に該当する他の函数:
-    // fn:base-uri#0
-    // fn:document-uri#0
     // fn:element-with-id#1
-    // fn:id#1
     // fn:idref#1
     // fn:path#0
 ];
@@ -193,18 +308,43 @@ const FUNC_TBL: [(
         &str,                   // 函数名
         fn(&Vec<&XSequence>) -> Result<XSequence, Box<Error>>);
                                 // 函数の実体: (引数)
-        62] = [
+        98] = [
 // 2
     ( 1, "fn:nilled",                 fn_nilled ),
     ( 1, "fn:string",                 fn_string ),
     ( 1, "fn:data",                   fn_data ),
+    ( 1, "fn:base-uri",               fn_base_uri ),
+    ( 1, "fn:document-uri",           fn_document_uri ),
+// 3
+    ( 0, "fn:error",                  fn_error ),
+    ( 1, "fn:error",                  fn_error ),
+    ( 2, "fn:error",                  fn_error ),
+    ( 3, "fn:error",                  fn_error ),
 // 4.4
     ( 1, "fn:abs",                    fn_abs ),
     ( 1, "fn:ceiling",                fn_ceiling ),
     ( 1, "fn:floor",                  fn_floor ),
     ( 1, "fn:round",                  fn_round ),
+    ( 2, "fn:round",                  fn_round ),
+    ( 1, "fn:round-half-to-even",     fn_round_half_to_even ),
+    ( 2, "fn:round-half-to-even",     fn_round_half_to_even ),
 // 4.5
     ( 1, "fn:number",                 fn_number ),
+// 4.8
+    ( 0, "math:pi",                   math_pi ),
+    ( 1, "math:exp",                  math_exp ),
+    ( 1, "math:exp10",                math_exp10 ),
+    ( 1, "math:log",                  math_log ),
+    ( 1, "math:log10",                math_log10 ),
+    ( 2, "math:pow",                  math_pow ),
+    ( 1, "math:sqrt",                 math_sqrt ),
+    ( 1, "math:sin",                  math_sin ),
+    ( 1, "math:cos",                  math_cos ),
+    ( 1, "math:tan",                  math_tan ),
+    ( 1, "math:asin",                 math_asin ),
+    ( 1, "math:acos",                 math_acos ),
+    ( 1, "math:atan",                 math_atan ),
+    ( 2, "math:atan2",                math_atan2 ),
 // 5.2.1
     ( 1, "fn:codepoints-to-string",   fn_codepoints_to_string ),
     ( 1, "fn:string-to-codepoints",   fn_string_to_codepoints ),
@@ -219,6 +359,8 @@ const FUNC_TBL: [(
     ( 3, "fn:substring",              fn_substring ),
     ( 1, "fn:string-length",          fn_string_length ),
     ( 1, "fn:normalize-space",        fn_normalize_space ),
+    ( 1, "fn:normalize-unicode",      fn_normalize_unicode ),
+    ( 2, "fn:normalize-unicode",      fn_normalize_unicode ),
     ( 1, "fn:upper-case",             fn_upper_case ),
     ( 1, "fn:lower-case",             fn_lower_case ),
     ( 3, "fn:translate",              fn_translate ),
@@ -228,18 +370,24 @@ const FUNC_TBL: [(
     ( 2, "fn:ends-with",              fn_ends_with ),
     ( 2, "fn:substring-before",       fn_substring_before ),
     ( 2, "fn:substring-after",        fn_substring_after ),
+// 6
+    ( 2, "fn:resolve-uri",            fn_resolve_uri ),
 // 7.1
     ( 0, "fn:true",                   fn_true ),
     ( 0, "fn:false",                  fn_false ),
 // 7.3
     ( 1, "fn:boolean",                fn_boolean ),
     ( 1, "fn:not",                    fn_not ),
+// 9.8
+    ( 1, "fn:parse-ietf-date",        fn_parse_ietf_date ),
 // 13
     ( 1, "fn:name",                   fn_name ),
     ( 1, "fn:local-name",             fn_local_name ),
     ( 1, "fn:namespace-uri",          fn_namespace_uri ),
     ( 2, "fn:lang",                   fn_lang ),
     ( 1, "fn:root",                   fn_root ),
+    ( 1, "fn:has-children",           fn_has_children ),
+    ( 1, "fn:siblings",               fn_siblings ),
 // 14.1
     ( 1, "fn:empty",                  fn_empty ),
     ( 1, "fn:exists",                 fn_exists ),
@@ -250,8 +398,6 @@ const FUNC_TBL: [(
     ( 1, "fn:reverse",                fn_reverse ),
     ( 2, "fn:subsequence",            fn_subsequence ),
     ( 3, "fn:subsequence",            fn_subsequence ),
-// 14.2
-    ( 2, "fn:index-of",               fn_index_of ),
 // 14.3
     ( 1, "fn:zero-or-one",            fn_zero_or_one ),
     ( 1, "fn:one-or-more",            fn_one_or_more ),
@@ -259,10 +405,14 @@ const FUNC_TBL: [(
 // 14.4
     ( 1, "fn:count",                  fn_count ),
     ( 1, "fn:avg",                    fn_avg ),
-    ( 1, "fn:max",                    fn_max ),
-    ( 1, "fn:min",                    fn_min ),
     ( 1, "fn:sum",                    fn_sum ),
     ( 2, "fn:sum",                    fn_sum ),
+// 14.5
+    ( 2, "fn:id",                     fn_id ),
+// 16.1
+    ( 2, "fn:function-lookup",        fn_function_lookup ),
+    ( 1, "fn:function-name",          fn_function_name ),
+    ( 1, "fn:function-arity",         fn_function_arity ),
 // 17.1
     ( 1, "map:size",                  map_size ),
     ( 1, "map:keys",                  map_keys ),
@@ -272,6 +422,13 @@ const FUNC_TBL: [(
     ( 1, "array:size",                array_size ),
     ( 2, "array:get",                 array_get ),
     ( 1, "array:flatten",             array_flatten ),
+// am: (拡張函数)
+    ( 1, "am:parse-rfc3339",          am_parse_rfc3339 ),
+    ( 1, "am:parse-html",             am_parse_html ),
+    ( 3, "am:put-in",                 am_put_in ),
+    ( 1, "am:child-count",            am_child_count ),
+    ( 1, "am:child-element-count",    am_child_element_count ),
+    ( 1, "am:grapheme-length",        am_grapheme_length ),
 ];
 
 // ---------------------------------------------------------------------
@@ -296,6 +453,19 @@ pub fn check_function_spec(func_name: &str, num_args: usize) -> bool {
     return false;
 }
 
+// ---------------------------------------------------------------------
+// FUNC_TBLに登録してある (引数のみで結果が決まる) 函数か否か。
+//
+pub fn is_context_independent_function(func_name: &str, num_args: usize) -> bool {
+    for (t_num_args, t_func_name, _func) in FUNC_TBL.iter() {
+        if (*t_num_args == num_args || *t_num_args == M) &&
+           *t_func_name == func_name {
+            return true;
+        }
+    }
+    return false;
+}
+
 // ---------------------------------------------------------------------
 // args: FunctionCallノードの右にたどった各ArgumentTopノードの、
 //       評価結果の配列
@@ -307,8 +477,13 @@ pub fn evaluate_function(func_name: &str, args: &Vec<XSequence>,
                 eval_env: &mut EvalEnv) -> Result<XSequence, Box<Error>> {
 
     let num_args = args.len();
+    let coerced_args = if eval_env.is_xpath10() {
+        coerce_arguments(func_name, &xpath10_coerce_arguments(func_name, args)?)?
+    } else {
+        coerce_arguments(func_name, args)?
+    };
     let mut ref_args: Vec<&XSequence> = vec!{};
-    for xseq in args.iter() {
+    for xseq in coerced_args.iter() {
         ref_args.push(xseq);
     }
 
@@ -331,6 +506,129 @@ pub fn evaluate_function(func_name: &str, args: &Vec<XSequence>,
                     func_name));
 }
 
+// ---------------------------------------------------------------------
+// 引数の変換 (function conversion rules)。
+// FUNC_SIGNATURE_TBLの仮引数の型が原子型であれば、実引数中のノードを
+// 原子化する (fn:data と同じ)。さらに、キャストの表 (xpath_impl::cast)
+// にある型を期待する仮引数については、原子化した値 (xs:untypedAtomic相当)
+// をその型にキャストする。
+// 原子値のみの実引数はそのまま (各函数の実体で検査する)。
+//
+fn coerce_arguments(func_name: &str, args: &Vec<XSequence>) -> Result<Vec<XSequence>, Box<Error>> {
+    if ! args.iter().any(|xseq| xseq.iter().any(|item| item.as_nodeptr().is_some())) {
+        return Ok(args.clone());
+    }
+
+    let mut signature = get_function_signature(&format!("{}#{}", func_name, args.len()));
+    if signature == "" {                // 引数の個数が可変の函数 (fn:concat)
+        signature = get_function_signature(&format!("{}#2", func_name));
+    }
+    let param_types = signature_param_types(&signature);
+    if param_types.len() == 0 {
+        return Ok(args.clone());
+    }
+
+    let mut coerced: Vec<XSequence> = vec!{};
+    for (i, xseq) in args.iter().enumerate() {
+        let param_type = &param_types[usize::min(i, param_types.len() - 1)];
+        let base_type = param_type.trim_right_matches(|c| c == '?' || c == '*' || c == '+');
+        if ! (base_type.starts_with("xs:") || base_type == "numeric") {
+            coerced.push(xseq.clone());
+            continue;
+        }
+        let cast_type = match base_type {
+            "numeric" => "xs:double",
+            _ if is_cast_target(base_type) => base_type,
+            _ => "",
+        };
+        let mut result = new_xsequence();
+        for item in xseq.iter() {
+            if item.as_nodeptr().is_none() {
+                result.push(item);
+            } else if cast_type == "" {
+                result.push(&item.atomize());
+            } else {
+                result.push(&item.atomize().cast_as(cast_type)?);
+            }
+        }
+        coerced.push(result);
+    }
+    return Ok(coerced);
+}
+
+// ---------------------------------------------------------------------
+// XPath 1.0 互換モードの引数の変換。
+// 仮引数の型が高々1個のアイテムを期待するものであれば、実引数の
+// 先頭のアイテムのみを取る。さらに、xs:string 型の仮引数については
+// string() で、数値型の仮引数については number() で変換する。
+//
+fn xpath10_coerce_arguments(func_name: &str, args: &Vec<XSequence>) -> Result<Vec<XSequence>, Box<Error>> {
+    let mut signature = get_function_signature(&format!("{}#{}", func_name, args.len()));
+    if signature == "" {                // 引数の個数が可変の函数 (fn:concat)
+        signature = get_function_signature(&format!("{}#2", func_name));
+    }
+    let param_types = signature_param_types(&signature);
+    if param_types.len() == 0 {
+        return Ok(args.clone());
+    }
+
+    let mut coerced: Vec<XSequence> = vec!{};
+    for (i, xseq) in args.iter().enumerate() {
+        let param_type = &param_types[usize::min(i, param_types.len() - 1)];
+        if param_type.ends_with("*") || param_type.ends_with("+") {
+            coerced.push(xseq.clone());
+            continue;
+        }
+        let base_type = param_type.trim_right_matches('?');
+        let result = match base_type {
+            "xs:string" => xpath10_string(xseq)?,
+            "xs:double" | "numeric" => xpath10_number(xseq)?,
+            _ if xseq.len() > 1 => new_singleton(xseq.get_item(0)),
+            _ => xseq.clone(),
+        };
+        coerced.push(result);
+    }
+    return Ok(coerced);
+}
+
+// ---------------------------------------------------------------------
+// 函数シグニチャ "function(T1, T2) as R" から仮引数の型 [T1, T2] を取り出す。
+// 型自体が括弧やカンマを含むこと (function(item()) as xs:boolean など) に
+// 注意する。
+//
+fn signature_param_types(signature: &str) -> Vec<String> {
+    let mut param_types: Vec<String> = vec!{};
+    if ! signature.starts_with("function(") {
+        return param_types;
+    }
+    let mut depth = 0;
+    let mut curr = String::new();
+    for c in signature["function(".len() ..].chars() {
+        match c {
+            '(' => {
+                depth += 1;
+                curr.push(c);
+            },
+            ')' if depth == 0 => {
+                break;
+            },
+            ')' => {
+                depth -= 1;
+                curr.push(c);
+            },
+            ',' if depth == 0 => {
+                param_types.push(String::from(curr.trim()));
+                curr = String::new();
+            },
+            _ => curr.push(c),
+        }
+    }
+    if curr.trim() != "" {
+        param_types.push(String::from(curr.trim()));
+    }
+    return param_types;
+}
+
 // ---------------------------------------------------------------------
 // 2 Accessors
 //      node-name
@@ -415,10 +713,114 @@ fn fn_data(args: &Vec<&XSequence>) -> Result<XSequence, Box<Error>> {
     return Ok(args[0].atomize());
 }
 
+// ---------------------------------------------------------------------
+// 2.5 fn:base-uri
+// fn:base-uri() as xs:anyURI?
+// fn:base-uri($arg as node()?) as xs:anyURI?
+//
+// ベースURIは、文書URIと xml:base 属性から求める (NodePtr#base_uri())。
+//
+fn fn_base_uri_0(_args: &Vec<&XSequence>, context_xseq: &XSequence,
+               _eval_env: &mut EvalEnv) -> Result<XSequence, Box<Error>> {
+    return fn_base_uri(&vec!{context_xseq});
+}
+
+fn fn_base_uri(args: &Vec<&XSequence>) -> Result<XSequence, Box<Error>> {
+    if args[0].is_empty() {
+        return Ok(new_xsequence());
+    }
+    let node = match args[0].get_singleton_node() {
+        Ok(node) => node,
+        Err(_) => return Err(type_error!("base-uri(): Item is not a node [err:XPTY0004]")),
+    };
+    match node.base_uri() {
+        Some(uri) => return Ok(new_singleton_string(&uri)),
+        None => return Ok(new_xsequence()),
+    }
+}
+
+// ---------------------------------------------------------------------
+// 2.6 fn:document-uri
+// fn:document-uri() as xs:anyURI?
+// fn:document-uri($arg as node()?) as xs:anyURI?
+//
+// 文書ノードであって、文書URIを記録してある場合にのみ、その値を返す。
+//
+fn fn_document_uri_0(_args: &Vec<&XSequence>, context_xseq: &XSequence,
+               _eval_env: &mut EvalEnv) -> Result<XSequence, Box<Error>> {
+    return fn_document_uri(&vec!{context_xseq});
+}
+
+fn fn_document_uri(args: &Vec<&XSequence>) -> Result<XSequence, Box<Error>> {
+    if args[0].is_empty() {
+        return Ok(new_xsequence());
+    }
+    let node = match args[0].get_singleton_node() {
+        Ok(node) => node,
+        Err(_) => return Err(type_error!("document-uri(): Item is not a node [err:XPTY0004]")),
+    };
+    match node.document_uri() {
+        Some(uri) => return Ok(new_singleton_string(&uri)),
+        None => return Ok(new_xsequence()),
+    }
+}
+
 // ---------------------------------------------------------------------
 // 3 Error and diagnostics
 //
 
+// ---------------------------------------------------------------------
+// 3.1.1 fn:error
+// fn:error() as none
+// fn:error($code as xs:QName?) as none
+// fn:error($code as xs:QName?, $description as xs:string) as none
+// fn:error($code as xs:QName?, $description as xs:string,
+//          $error-object as item()*) as none
+//
+// 動的エラーとする。エラーには誤りコード、説明、$error-objectを保持し、
+// Rust側で XmlError#user_error() として取り出せる。
+// xs:QName型は未実装なので、$codeは文字列 ("app:E01"、
+// "Q{http://example.com/}E01" など) で指定する。
+// $codeを省略する (あるいは空シーケンスとする) と err:FOER0000。
+//
+fn fn_error(args: &Vec<&XSequence>) -> Result<XSequence, Box<Error>> {
+    let code = if 1 <= args.len() && ! args[0].is_empty() {
+        args[0].get_singleton_item()?.get_as_raw_string()?
+    } else {
+        String::from("err:FOER0000")
+    };
+    let description = if 2 <= args.len() {
+        args[1].get_singleton_item()?.get_as_raw_string()?
+    } else {
+        String::from("Error signalled by fn:error()")
+    };
+    let value = if args.len() == 3 {
+        Some(new_sequence(args[2]))
+    } else {
+        None
+    };
+    return Err(user_error(&code, &description, value));
+}
+
+// ---------------------------------------------------------------------
+// 3.2 fn:trace
+// fn:trace($value as item()*) as item()*
+// fn:trace($value as item()*, $label as xs:string) as item()*
+//
+// $valueをそのまま返す。その際、ラベルと$valueを文字列化したものを、
+// 評価環境に登録された出力先 (既定は標準エラー出力) に渡す。
+//
+fn fn_trace(args: &Vec<&XSequence>, _context_xseq: &XSequence,
+               eval_env: &mut EvalEnv) -> Result<XSequence, Box<Error>> {
+    let label = if args.len() == 2 {
+        args[1].get_singleton_item()?.get_as_raw_string()?
+    } else {
+        String::new()
+    };
+    eval_env.trace(&label, &args[0].to_string());
+    return Ok(args[0].clone());
+}
+
 // ---------------------------------------------------------------------
 // 4 Functions and Operators on Numerics
 //
@@ -468,9 +870,14 @@ fn fn_floor(args: &Vec<&XSequence>) -> Result<XSequence, Box<Error>> {
 // ---------------------------------------------------------------------
 // 4.4.4 fn:round
 // fn:round($arg as numeric?) as numeric?
+// fn:round($arg as numeric?, $precision as xs:integer) as numeric?
 //      空シーケンス => 空シーケンス
+//      丁度半分の場合は正の無限大の方向に丸める。
 //
 fn fn_round(args: &Vec<&XSequence>) -> Result<XSequence, Box<Error>> {
+    if args.len() == 2 {
+        return fn_round_precision(args, false);
+    }
     return fn_numeric_unary(args,
                 |a| { a },
                 |a| { (a + 0.5).floor() },
@@ -479,6 +886,121 @@ fn fn_round(args: &Vec<&XSequence>) -> Result<XSequence, Box<Error>> {
                 |a| { round_x(a) });
 }
 
+// ---------------------------------------------------------------------
+// 4.4.5 fn:round-half-to-even
+// fn:round-half-to-even($arg as numeric?) as numeric?
+// fn:round-half-to-even($arg as numeric?,
+//                       $precision as xs:integer) as numeric?
+//      空シーケンス => 空シーケンス
+//      丁度半分の場合は偶数の方に丸める。
+//
+fn fn_round_half_to_even(args: &Vec<&XSequence>) -> Result<XSequence, Box<Error>> {
+    return fn_round_precision(args, true);
+}
+
+// ---------------------------------------------------------------------
+// 小数点以下 precision 桁に丸める (precision が負ならば整数部を丸める)。
+// precision を省略した場合は 0。
+//
+fn fn_round_precision(args: &Vec<&XSequence>,
+                half_even: bool) -> Result<XSequence, Box<Error>> {
+    let precision = if args.len() == 2 {
+            args[1].get_singleton_item()?.get_as_raw_integer()?
+        } else {
+            0
+        };
+    if args[0].is_empty() {
+        return Ok(new_xsequence());
+    }
+    match args[0].get_singleton_item()? {
+        XItem::XIInteger{value} => {
+            return Ok(new_singleton_integer(
+                    round_integer(value, precision, half_even)?));
+        },
+        XItem::XIDecimal{value} => {
+            return Ok(new_singleton_decimal(
+                    round_f64(value, precision, half_even, true)));
+        },
+        XItem::XIDouble{value} => {
+            let result = round_f64(value, precision, half_even, false);
+            if result == 0.0 && value.is_sign_negative() {
+                return Ok(new_singleton_double(1.0 / f64::NEG_INFINITY));
+                                                // 負のゼロ
+            }
+            return Ok(new_singleton_double(result));
+        },
+        _ => {
+            return Err(type_error!(
+                    "round: numeric value expected: {}", args[0].to_string()));
+        },
+    }
+}
+
+// ---------------------------------------------------------------------
+// 整数を 10^(-precision) の倍数に丸める。
+//
+fn round_integer(value: i64, precision: i64,
+                half_even: bool) -> Result<i64, Box<Error>> {
+    if 0 <= precision {
+        return Ok(value);
+    }
+    if 18 < -precision {
+        return Ok(0);
+    }
+    let scale = 10i64.pow((-precision) as u32);
+    let q = value.div_euclid(scale);
+    let r = value.rem_euclid(scale);
+    let q = if r * 2 < scale {
+            q
+        } else if scale < r * 2 {
+            q + 1
+        } else if half_even && q % 2 == 0 {
+            q
+        } else {
+            q + 1
+        };
+    match q.checked_mul(scale) {
+        Some(result) => return Ok(result),
+        None => return Err(dynamic_error!(
+                "round: integer overflow: {} [err:FOAR0002]", value)),
+    }
+}
+
+// ---------------------------------------------------------------------
+// 浮動小数点数を小数点以下 precision 桁に丸める。
+// decimal (exact_tie が真) の場合、2.675 のように10進表記で丁度半分と
+// なる値は、2進表現の誤差にかかわらず、丁度半分として扱う。
+//
+fn round_f64(num: f64, precision: i64, half_even: bool, exact_tie: bool) -> f64 {
+    if num.is_nan() || num.is_infinite() || num == 0.0 {
+        return num;
+    }
+    if 300 < precision.abs() {
+        return if 0 < precision { num } else { 0.0 };
+    }
+    let scale = 10f64.powi(precision.abs() as i32);
+    let scaled = if 0 <= precision { num * scale } else { num / scale };
+    if scaled.is_infinite() || (1u64 << 53) as f64 <= scaled.abs() {
+        return num;                     // 既に整数 (丸める桁がない)
+    }
+
+    let fl = scaled.floor();
+    let frac = scaled - fl;
+    let tolerance = if exact_tie {
+            scaled.abs().max(1.0) * f64::EPSILON * 8.0
+        } else {
+            0.0
+        };
+    let rounded = if (frac - 0.5).abs() <= tolerance {
+            if half_even && fl % 2.0 == 0.0 { fl } else { fl + 1.0 }
+        } else if frac < 0.5 {
+            fl
+        } else {
+            fl + 1.0
+        };
+    return if 0 <= precision { rounded / scale } else { rounded * scale };
+}
+
 // ---------------------------------------------------------------------
 // ceil_x/floor_x/round_x: 天井/床/四捨五入だが、負のゼロの扱いが特殊。
 //
@@ -553,6 +1075,290 @@ fn fn_number(args: &Vec<&XSequence>) -> Result<XSequence, Box<Error>> {
     return Ok(new_singleton_double(result));
 }
 
+// ---------------------------------------------------------------------
+// 4.8 Trigonometric and exponential functions
+//      引数、結果とも xs:double。空シーケンス => 空シーケンス
+//      (math:pi、math:atan2 を除く)。
+//      特殊な値 (NaN、INF、-0 など) の扱いは、f64 の演算のとおり。
+//
+// ---------------------------------------------------------------------
+// 4.8.1 math:pi
+// math:pi() as xs:double
+//
+fn math_pi(_args: &Vec<&XSequence>) -> Result<XSequence, Box<Error>> {
+    return Ok(new_singleton_double(f64::consts::PI));
+}
+
+// ---------------------------------------------------------------------
+// 4.8.2 math:exp
+// math:exp($arg as xs:double?) as xs:double?
+//
+fn math_exp(args: &Vec<&XSequence>) -> Result<XSequence, Box<Error>> {
+    return math_unary("math:exp", args, |a| { a.exp() });
+}
+
+// ---------------------------------------------------------------------
+// 4.8.3 math:exp10
+// math:exp10($arg as xs:double?) as xs:double?
+//
+fn math_exp10(args: &Vec<&XSequence>) -> Result<XSequence, Box<Error>> {
+    return math_unary("math:exp10", args, |a| { 10f64.powf(a) });
+}
+
+// ---------------------------------------------------------------------
+// 4.8.4 math:log
+// math:log($arg as xs:double?) as xs:double?
+//
+fn math_log(args: &Vec<&XSequence>) -> Result<XSequence, Box<Error>> {
+    return math_unary("math:log", args, |a| { a.ln() });
+}
+
+// ---------------------------------------------------------------------
+// 4.8.5 math:log10
+// math:log10($arg as xs:double?) as xs:double?
+//
+fn math_log10(args: &Vec<&XSequence>) -> Result<XSequence, Box<Error>> {
+    return math_unary("math:log10", args, |a| { a.log10() });
+}
+
+// ---------------------------------------------------------------------
+// 4.8.6 math:pow
+// math:pow($x as xs:double?, $y as numeric) as xs:double?
+//
+fn math_pow(args: &Vec<&XSequence>) -> Result<XSequence, Box<Error>> {
+    let x = match get_double_arg("math:pow", args[0])? {
+        Some(x) => x,
+        None => return Ok(new_xsequence()),
+    };
+    let y = match get_double_arg("math:pow", args[1])? {
+        Some(y) => y,
+        None => return Err(type_error!("math:pow(): Exponent is empty")),
+    };
+    return Ok(new_singleton_double(x.powf(y)));
+}
+
+// ---------------------------------------------------------------------
+// 4.8.7 math:sqrt
+// math:sqrt($arg as xs:double?) as xs:double?
+//
+fn math_sqrt(args: &Vec<&XSequence>) -> Result<XSequence, Box<Error>> {
+    return math_unary("math:sqrt", args, |a| { a.sqrt() });
+}
+
+// ---------------------------------------------------------------------
+// 4.8.8 math:sin
+// math:sin($θ as xs:double?) as xs:double?
+//
+fn math_sin(args: &Vec<&XSequence>) -> Result<XSequence, Box<Error>> {
+    return math_unary("math:sin", args, |a| { a.sin() });
+}
+
+// ---------------------------------------------------------------------
+// 4.8.9 math:cos
+// math:cos($θ as xs:double?) as xs:double?
+//
+fn math_cos(args: &Vec<&XSequence>) -> Result<XSequence, Box<Error>> {
+    return math_unary("math:cos", args, |a| { a.cos() });
+}
+
+// ---------------------------------------------------------------------
+// 4.8.10 math:tan
+// math:tan($θ as xs:double?) as xs:double?
+//
+fn math_tan(args: &Vec<&XSequence>) -> Result<XSequence, Box<Error>> {
+    return math_unary("math:tan", args, |a| { a.tan() });
+}
+
+// ---------------------------------------------------------------------
+// 4.8.11 math:asin
+// math:asin($arg as xs:double?) as xs:double?
+//
+fn math_asin(args: &Vec<&XSequence>) -> Result<XSequence, Box<Error>> {
+    return math_unary("math:asin", args, |a| { a.asin() });
+}
+
+// ---------------------------------------------------------------------
+// 4.8.12 math:acos
+// math:acos($arg as xs:double?) as xs:double?
+//
+fn math_acos(args: &Vec<&XSequence>) -> Result<XSequence, Box<Error>> {
+    return math_unary("math:acos", args, |a| { a.acos() });
+}
+
+// ---------------------------------------------------------------------
+// 4.8.13 math:atan
+// math:atan($arg as xs:double?) as xs:double?
+//
+fn math_atan(args: &Vec<&XSequence>) -> Result<XSequence, Box<Error>> {
+    return math_unary("math:atan", args, |a| { a.atan() });
+}
+
+// ---------------------------------------------------------------------
+// 4.8.14 math:atan2
+// math:atan2($y as xs:double, $x as xs:double) as xs:double
+//
+fn math_atan2(args: &Vec<&XSequence>) -> Result<XSequence, Box<Error>> {
+    let y = get_double_arg("math:atan2", args[0])?;
+    let x = get_double_arg("math:atan2", args[1])?;
+    match (y, x) {
+        (Some(y), Some(x)) => return Ok(new_singleton_double(y.atan2(x))),
+        _ => return Err(type_error!("math:atan2(): Argument is empty")),
+    }
+}
+
+// ---------------------------------------------------------------------
+// 引数が xs:double? である単項の函数。
+//
+fn math_unary<F>(func_name: &str, args: &Vec<&XSequence>,
+                mut op: F) -> Result<XSequence, Box<Error>>
+        where F: FnMut(f64) -> f64 {
+    match get_double_arg(func_name, args[0])? {
+        Some(arg) => return Ok(new_singleton_double(op(arg))),
+        None => return Ok(new_xsequence()),
+    }
+}
+
+// ---------------------------------------------------------------------
+// xs:double? である引数の値。空シーケンスならば None。
+// 数値型 (ノードは原子化済み) でなければエラー。
+//
+fn get_double_arg(func_name: &str, arg: &XSequence) -> Result<Option<f64>, Box<Error>> {
+    if arg.is_empty() {
+        return Ok(None);
+    }
+    let item = arg.get_singleton_item()?;
+    if ! item.is_numeric() {
+        return Err(type_error!("{}(): Item {} is not numeric",
+                    func_name, item.to_string()));
+    }
+    return Ok(Some(item.get_as_raw_double()?));
+}
+
+// ---------------------------------------------------------------------
+// 4.9.1 fn:random-number-generator
+// fn:random-number-generator() as map(xs:string, item())
+// fn:random-number-generator($seed as xs:anyAtomicType?)
+//                                           as map(xs:string, item())
+//
+// 次のエントリーを持つマップを返す。
+//   number: [0, 1) の xs:double。
+//   next: 次の乱数生成器 (同じ形のマップ) を返す、引数のない函数。
+//   permute: 引数のシーケンスを並べ替えて返す函数。
+// 擬似乱数は SplitMix64 で生成する。$seedが同じならば同じ乱数列になる。
+// $seedが整数ならばそのまま内部状態とし、他の型ならば文字列値の
+// ハッシュ値を内部状態とする。
+// next は、内部状態を整数リテラルとして渡す部分函数
+// fn:random-number-generator(state) として表す。
+// 引数がない (または空の) 場合は、評価を始めた時刻から内部状態を
+// 決める。同じ評価の中では同じ乱数列になる (EvalEnv#get_clock_seed())。
+//
+fn fn_random_number_generator(args: &Vec<&XSequence>, _context_xseq: &XSequence,
+                eval_env: &mut EvalEnv) -> Result<XSequence, Box<Error>> {
+    let state = if args.len() == 0 {
+        eval_env.get_clock_seed()
+    } else {
+        random_seed_state(args[0], eval_env)?
+    };
+    let (next_state, z) = splitmix64(state);
+    let number = (z >> 11) as f64 / (1u64 << 53) as f64;
+    let next = new_partial_function_xnode("fn:random-number-generator",
+                &vec!{Some(next_state)});
+    let permute = new_partial_function_xnode("am:random-permute",
+                &vec!{None, Some(next_state)});
+    let map = new_xitem_map(&vec!{
+        (new_xitem_string("number"), new_singleton_double(number)),
+        (new_xitem_string("next"), new_singleton_xnodeptr(&next)),
+        (new_xitem_string("permute"), new_singleton_xnodeptr(&permute)),
+    });
+    return Ok(new_singleton(&map));
+}
+
+// ---------------------------------------------------------------------
+// $seedから擬似乱数の内部状態を決める。
+//
+fn random_seed_state(seed: &XSequence, eval_env: &EvalEnv) -> Result<u64, Box<Error>> {
+    if seed.is_empty() {
+        return Ok(eval_env.get_clock_seed());
+    }
+    match seed.get_singleton_item()? {
+        XItem::XIInteger{value} => return Ok(value as u64),
+        item => return Ok(fnv1a_hash(&item.get_as_raw_string()?)),
+    }
+}
+
+// ---------------------------------------------------------------------
+// $seedを省略した場合の内部状態: 現在時刻から決める。
+// 評価を始めるときに1回だけ呼び出す (EvalEnv を参照)。
+// feature "no_std" では時計がないので、固定の値。
+//
+#[cfg(not(feature = "no_std"))]
+pub fn clock_seed() -> u64 {
+    match SystemTime::now().duration_since(UNIX_EPOCH) {
+        Ok(d) => return d.as_secs().wrapping_mul(1_000_000_000)
+                    .wrapping_add(d.subsec_nanos() as u64),
+        Err(_) => return 0,
+    }
+}
+
+#[cfg(feature = "no_std")]
+pub fn clock_seed() -> u64 {
+    return 0;
+}
+
+// ---------------------------------------------------------------------
+// SplitMix64: 内部状態を進め、(新しい内部状態, 乱数) を返す。
+//
+fn splitmix64(state: u64) -> (u64, u64) {
+    let state = state.wrapping_add(0x9E3779B97F4A7C15);
+    let mut z = state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+    return (state, z ^ (z >> 31));
+}
+
+// ---------------------------------------------------------------------
+// 文字列の FNV-1a ハッシュ値 (64ビット)。
+//
+fn fnv1a_hash(s: &str) -> u64 {
+    let mut h: u64 = 0xCBF29CE484222325;
+    for b in s.bytes() {
+        h ^= b as u64;
+        h = h.wrapping_mul(0x100000001B3);
+    }
+    return h;
+}
+
+// ---------------------------------------------------------------------
+// 静的函数 func_name の部分函数呼び出しを表すxnodeを作る。
+// args: 各引数。Some(n) は整数リテラル n (内部状態を u64 のまま
+//       i64 として表す)、None は「?」(ArgumentPlaceholder)。
+//
+// PartialFunctionCall --- ArgumentTop --- ArgumentPlaceholder ---...
+//    (func_name)               |
+//                        IntegerLiteral
+//
+fn new_partial_function_xnode(func_name: &str, args: &Vec<Option<u64>>) -> XNodePtr {
+    let mut rest = new_xnode(XNodeType::Nil, "");
+    for arg in args.iter().rev() {
+        rest = match *arg {
+            Some(n) => {
+                let literal = new_xnode(XNodeType::IntegerLiteral,
+                                &format!("{}", n as i64));
+                copy_xnode_with_children(
+                    &new_xnode(XNodeType::ArgumentTop, ""), &literal, &rest)
+            },
+            None => {
+                copy_xnode_with_children(
+                    &new_xnode(XNodeType::ArgumentPlaceholder, ""),
+                    &new_xnode(XNodeType::Nil, ""), &rest)
+            },
+        };
+    }
+    return copy_xnode_with_children(
+            &new_xnode(XNodeType::PartialFunctionCall, func_name),
+            &new_xnode(XNodeType::Nil, ""), &rest);
+}
+
 // ---------------------------------------------------------------------
 // 5 Functions on Strings
 //
@@ -630,13 +1436,12 @@ fn string_to_codepoints_sub(str: &String) -> Vec<u64> {
 //            $comparand2 as xs:string?,
 //            $collation as xs:string) as xs:integer?
 // いずれかの引数が空シーケンスの場合、空シーケンスを返す。
-// 第3引数 $collation がある場合の比較は未実装。
+// 第3引数 $collation がある場合は fn_compare_collation で処理する。
 //
 pub fn fn_compare(args: &Vec<&XSequence>) -> Result<XSequence, Box<Error>> {
 
     if args.len() != 2 {
         panic!("fn_compare: args.len() != 2.");
-            // 実際には第3引数 collation も指定できる。
     }
     if args[0].is_empty() || args[1].is_empty() {
         return Ok(new_xsequence());
@@ -653,27 +1458,93 @@ pub fn fn_compare(args: &Vec<&XSequence>) -> Result<XSequence, Box<Error>> {
     }
 }
 
-// ---------------------------------------------------------------------
-// 5.3.7 fn:codepoint-equal
-// fn:codepoint-equal($comparand1 as xs:string?,
-//                    $comparand2 as xs:string?) as xs:boolean?
-// いずれかの引数が空シーケンスの場合、空シーケンスを返す。
-//
-fn fn_codepoint_equal(args: &Vec<&XSequence>) -> Result<XSequence, Box<Error>> {
+fn fn_compare_collation(args: &Vec<&XSequence>, _context_xseq: &XSequence,
+                eval_env: &mut EvalEnv) -> Result<XSequence, Box<Error>> {
+    let collation = collation_arg(args, 2, eval_env)?;
     if args[0].is_empty() || args[1].is_empty() {
         return Ok(new_xsequence());
     }
 
-    let comparand1 = args[0].get_singleton_string()?;
-    let comparand2 = args[1].get_singleton_string()?;
-    let result = codepoint_equal_sub(&comparand1, &comparand2);
-    return Ok(new_singleton_boolean(result));
+    let lhs = args[0].get_singleton_string()?;
+    let rhs = args[1].get_singleton_string()?;
+    match collation(&lhs, &rhs) {
+        Ordering::Less => return Ok(new_singleton_integer(-1)),
+        Ordering::Equal => return Ok(new_singleton_integer(0)),
+        Ordering::Greater => return Ok(new_singleton_integer(1)),
+    }
 }
 
-fn codepoint_equal_sub(str1: &String, str2: &String) -> bool {
+// ---------------------------------------------------------------------
+// 引数 args[n] のURIで指定された照合順序。
+// 引数が省略されていれば既定の照合順序。
+//
+fn collation_arg(args: &Vec<&XSequence>, n: usize,
+                eval_env: &EvalEnv) -> Result<Collation, Box<Error>> {
+    if args.len() <= n {
+        return Ok(eval_env.default_collation());
+    }
+    let uri = args[n].get_singleton_string()?;
+    return eval_env.get_collation(&uri);
+}
 
-    let codepoints1 = string_to_codepoints_sub(&str1);
-    let codepoints2 = string_to_codepoints_sub(&str2);
+// ---------------------------------------------------------------------
+// 原子値どうしの比較。
+// どちらも文字列ならば照合順序にしたがって比較し、
+// そうでなければ値比較 (lt、eq) の結果による。
+//
+fn compare_with_collation(lhs: &XItem, rhs: &XItem,
+                collation: &Collation) -> Result<Ordering, Box<Error>> {
+    if let (XItem::XIString{value: lhs}, XItem::XIString{value: rhs}) =
+                                    (lhs.atomize(), rhs.atomize()) {
+        return Ok(collation(&lhs, &rhs));
+    }
+    let lhs = new_singleton(lhs);
+    let rhs = new_singleton(rhs);
+    if value_compare_lt(&lhs, &rhs)?.get_singleton_boolean()? {
+        return Ok(Ordering::Less);
+    }
+    if value_compare_eq(&lhs, &rhs)?.get_singleton_boolean()? {
+        return Ok(Ordering::Equal);
+    }
+    return Ok(Ordering::Greater);
+}
+
+// ---------------------------------------------------------------------
+// 原子値どうしが等しいか否か。
+// どちらも文字列ならば照合順序にしたがって比較し、
+// そうでなければ値比較 (eq) の結果による。
+//
+fn equal_with_collation(lhs: &XItem, rhs: &XItem,
+                collation: &Collation) -> Result<bool, Box<Error>> {
+    if let (XItem::XIString{value: lhs}, XItem::XIString{value: rhs}) =
+                                    (lhs.atomize(), rhs.atomize()) {
+        return Ok(collation(&lhs, &rhs) == Ordering::Equal);
+    }
+    let result = value_compare_eq(&new_singleton(lhs), &new_singleton(rhs))?;
+    return result.get_singleton_boolean();
+}
+
+// ---------------------------------------------------------------------
+// 5.3.7 fn:codepoint-equal
+// fn:codepoint-equal($comparand1 as xs:string?,
+//                    $comparand2 as xs:string?) as xs:boolean?
+// いずれかの引数が空シーケンスの場合、空シーケンスを返す。
+//
+fn fn_codepoint_equal(args: &Vec<&XSequence>) -> Result<XSequence, Box<Error>> {
+    if args[0].is_empty() || args[1].is_empty() {
+        return Ok(new_xsequence());
+    }
+
+    let comparand1 = args[0].get_singleton_string()?;
+    let comparand2 = args[1].get_singleton_string()?;
+    let result = codepoint_equal_sub(&comparand1, &comparand2);
+    return Ok(new_singleton_boolean(result));
+}
+
+fn codepoint_equal_sub(str1: &String, str2: &String) -> bool {
+
+    let codepoints1 = string_to_codepoints_sub(&str1);
+    let codepoints2 = string_to_codepoints_sub(&str2);
 
     if codepoints1.len() != codepoints2.len() {
         return false;
@@ -756,7 +1627,8 @@ fn fn_string_join(args: &Vec<&XSequence>) -> Result<XSequence, Box<Error>> {
 //  - starting_loc や length がNaNならば空文字列。
 //  - starting_loc = -∞ のとき: lengthが有限ならばe = -∞なので空文字列、
 //              length = ∞ならば - ∞ + ∞ = NaNなのでやはり空文字列。
-//  - starting_loc = ∞ のとき: lengthにかかわらずe = ∞なので空文字列、
+//              lengthを省略した場合は全体。
+//  - starting_loc = ∞ のとき: lengthにかかわらず空文字列、
 //
 fn fn_substring(args: &Vec<&XSequence>) -> Result<XSequence, Box<Error>> {
 
@@ -769,9 +1641,9 @@ fn fn_substring(args: &Vec<&XSequence>) -> Result<XSequence, Box<Error>> {
 
     let starting_loc = args[1].get_singleton_item()?.get_as_raw_double()?;
     let length = if args.len() == 2 {
-            f64::INFINITY
+            None
         } else {
-            args[2].get_singleton_item()?.get_as_raw_double()?
+            Some(args[2].get_singleton_item()?.get_as_raw_double()?)
         };
     let (b, e) = subcollection_index_sub(sv.len(), starting_loc, length);
 
@@ -786,6 +1658,8 @@ fn fn_substring(args: &Vec<&XSequence>) -> Result<XSequence, Box<Error>> {
 // 5.4.4 fn:string-length
 // fn:string-length() as xs:integer
 // fn:string-length($arg as xs:string?) as xs:integer
+//      文字 (コードポイント) の数。書記素クラスタの数は
+//      am:grapheme-length() で数える。
 //
 fn fn_string_length_0(_args: &Vec<&XSequence>, context_xseq: &XSequence,
                _eval_env: &mut EvalEnv) -> Result<XSequence, Box<Error>> {
@@ -805,8 +1679,10 @@ fn fn_string_length(args: &Vec<&XSequence>) -> Result<XSequence, Box<Error>> {
 
 // ---------------------------------------------------------------------
 // 5.4.5 fn:normalize-space
-// fn:normalize-space() as xs:integer
-// fn:normalize-space($arg as xs:string?) as xs:integer
+// fn:normalize-space() as xs:string
+// fn:normalize-space($arg as xs:string?) as xs:string
+//      前後の空白を除き、連続する空白をひとつの空白 (#x20) に置き換える。
+//      空白は XML の空白文字 (#x20、#x9、#xD、#xA) のみ。
 //
 fn fn_normalize_space_0(_args: &Vec<&XSequence>, context_xseq: &XSequence,
                _eval_env: &mut EvalEnv) -> Result<XSequence, Box<Error>> {
@@ -819,12 +1695,61 @@ fn fn_normalize_space(args: &Vec<&XSequence>) -> Result<XSequence, Box<Error>> {
     }
 
     let arg = args[0].get_singleton_string()?;
-    let v: Vec<&str> = arg.split_whitespace().collect();
-    let mut result = String::new();
-    for t in v.iter() {
-        result += t;
+    let v: Vec<&str> = arg.split(|c| c == ' ' || c == '\t' || c == '\r' || c == '\n')
+            .filter(|t| *t != "")
+            .collect();
+    return Ok(new_singleton_string(&v.join(" ")));
+}
+
+// ---------------------------------------------------------------------
+// 5.4.6 fn:normalize-unicode
+// fn:normalize-unicode($arg as xs:string?) as xs:string
+// fn:normalize-unicode($arg as xs:string?,
+//                      $normalizationForm as xs:string) as xs:string
+//
+// 正規化形式は、前後の空白を除き大文字に変換して判断する。
+// "" ならば正規化しない。NFC、NFD、NFKC、NFKD 以外は未対応 [err:FOCH0003]。
+// 正規化の実体は feature "unicode-normalization" 指定時のみ。
+// 指定しない場合は、正規化しても変わらないASCII文字列のみ扱える。
+//
+fn fn_normalize_unicode(args: &Vec<&XSequence>) -> Result<XSequence, Box<Error>> {
+    if args[0].is_empty() {
+        return Ok(new_singleton_string(&""));
     }
-    return Ok(new_singleton_string(&result));
+
+    let arg = args[0].get_singleton_string()?;
+    let form = if args.len() == 2 {
+        args[1].get_singleton_string()?.trim().to_uppercase()
+    } else {
+        String::from("NFC")
+    };
+    match form.as_str() {
+        "" => return Ok(new_singleton_string(&arg)),
+        "NFC" | "NFD" | "NFKC" | "NFKD" => {},
+        _ => return Err(dynamic_error!(
+                "Unsupported normalization form: {} [err:FOCH0003]", form)),
+    }
+    if arg.is_ascii() {
+        return Ok(new_singleton_string(&arg));
+    }
+    return Ok(new_singleton_string(&normalize_unicode(&arg, &form)?));
+}
+
+#[cfg(feature = "unicode-normalization")]
+fn normalize_unicode(s: &str, form: &str) -> Result<String, Box<Error>> {
+    use unicode_normalization::UnicodeNormalization;
+    match form {
+        "NFC" => return Ok(s.nfc().collect()),
+        "NFD" => return Ok(s.nfd().collect()),
+        "NFKC" => return Ok(s.nfkc().collect()),
+        _ => return Ok(s.nfkd().collect()),
+    }
+}
+
+#[cfg(not(feature = "unicode-normalization"))]
+fn normalize_unicode(_s: &str, form: &str) -> Result<String, Box<Error>> {
+    return Err(uninplemented!(
+        "fn:normalize-unicode ({}) of non-ASCII string requires feature \"unicode-normalization\"", form));
 }
 
 // ---------------------------------------------------------------------
@@ -1020,6 +1945,43 @@ fn fn_substring_after(args: &Vec<&XSequence>) -> Result<XSequence, Box<Error>> {
 // ---------------------------------------------------------------------
 // 6 Functions that manipulate URIs
 //
+// ---------------------------------------------------------------------
+// 6.1 fn:resolve-uri
+// fn:resolve-uri($relative as xs:string?) as xs:anyURI?
+// fn:resolve-uri($relative as xs:string?, $base as xs:string) as xs:anyURI?
+//
+// 第1形式は静的なベースURI (EvalOptions#base_uri()) に対して解決する。
+// ベースURIが絶対URIでなければエラー [err:FORG0002]。
+//
+fn fn_resolve_uri_1(args: &Vec<&XSequence>, _context_xseq: &XSequence,
+               eval_env: &mut EvalEnv) -> Result<XSequence, Box<Error>> {
+    if args[0].is_empty() {
+        return Ok(new_xsequence());
+    }
+    let relative = args[0].get_singleton_item()?.get_as_raw_string()?;
+    if uri_scheme(&relative).is_some() {
+        return Ok(new_singleton_string(&relative));
+    }
+    let base = match eval_env.static_base_uri() {
+        Some(base) => base,
+        None => return Err(dynamic_error!(
+                "resolve-uri(): Static base URI is absent [err:FONS0005]")),
+    };
+    return fn_resolve_uri(&vec!{args[0], &new_singleton_string(&base)});
+}
+
+fn fn_resolve_uri(args: &Vec<&XSequence>) -> Result<XSequence, Box<Error>> {
+    if args[0].is_empty() {
+        return Ok(new_xsequence());
+    }
+    let relative = args[0].get_singleton_item()?.get_as_raw_string()?;
+    let base = args[1].get_singleton_item()?.get_as_raw_string()?;
+    match resolve_uri(&relative, &base) {
+        Some(uri) => return Ok(new_singleton_string(&uri)),
+        None => return Err(dynamic_error!(
+                "resolve-uri(): Base URI is not absolute: {} [err:FORG0002]", base)),
+    }
+}
 
 // ---------------------------------------------------------------------
 // 7 Functions and Operators on Boolean Values
@@ -1071,6 +2033,20 @@ pub fn fn_not(args: &Vec<&XSequence>) -> Result<XSequence, Box<Error>> {
 // 8 Functions and Operators on Durations
 // ---------------------------------------------------------------------
 // 9 Functions and Operators on Dates and Times
+//
+// ---------------------------------------------------------------------
+// 9.8.5 fn:parse-ietf-date
+// fn:parse-ietf-date($value as xs:string?) as xs:dateTime?
+//
+fn fn_parse_ietf_date(args: &Vec<&XSequence>) -> Result<XSequence, Box<Error>> {
+    if args[0].is_empty() {
+        return Ok(new_xsequence());
+    }
+    let value = args[0].get_singleton_string()?;
+    let dt = parse_ietf_date(&value)?;
+    return Ok(new_singleton(&new_xitem_date_time(&dt)));
+}
+
 // ---------------------------------------------------------------------
 // 10 Functions Related to QNames
 // ---------------------------------------------------------------------
@@ -1161,14 +2137,10 @@ fn fn_lang(args: &Vec<&XSequence>) -> Result<XSequence, Box<Error>> {
     let testlang = args[0].get_singleton_string()?;
     let node = args[1].get_singleton_node()?;
 
-    let mut xmllang = String::new();
-    for n in array_ancestor_or_self(&node).iter() {
-                                // array_ancestor_or_self() !!!!!!!!!!!!
-        if let Some(val) = n.attribute_value("xml:lang") {
-            xmllang = val.to_lowercase();
-            break;
-        }
-    }
+    let xmllang = match node.effective_lang() {
+        Some(val) => val.to_lowercase(),
+        None => String::new(),
+    };
     let v: Vec<&str> = xmllang.splitn(2, "-").collect();
     let result = v[0] == testlang;
 
@@ -1199,6 +2171,66 @@ fn fn_root(args: &Vec<&XSequence>) -> Result<XSequence, Box<Error>> {
 
 }
 
+// ---------------------------------------------------------------------
+// 13.7 fn:has-children
+// fn:has-children() as xs:boolean
+// fn:has-children($node as node()?) as xs:boolean
+//
+// 子のベクターを作らずに判定する (NodePtr#has_children())。
+//
+fn fn_has_children_0(_args: &Vec<&XSequence>, context_xseq: &XSequence,
+               _eval_env: &mut EvalEnv) -> Result<XSequence, Box<Error>> {
+    return fn_has_children(&vec!{context_xseq});
+}
+
+fn fn_has_children(args: &Vec<&XSequence>) -> Result<XSequence, Box<Error>> {
+    if args[0].is_empty() {
+        return Ok(new_singleton_boolean(false));
+    }
+    let node = match args[0].get_singleton_node() {
+        Ok(node) => node,
+        Err(_) => return Err(type_error!("has-children(): Item is not a node [err:XPTY0004]")),
+    };
+    return Ok(new_singleton_boolean(node.has_children()));
+}
+
+// ---------------------------------------------------------------------
+// fn:siblings (XPath 4.0)
+// fn:siblings() as node()*
+// fn:siblings($node as node()?) as node()*
+//
+// ノード自身とその兄弟 (親の子であるノード) を文書順に返す。
+// 属性ノード、親のないノードは、そのノードのみを返す。
+//
+fn fn_siblings_0(_args: &Vec<&XSequence>, context_xseq: &XSequence,
+               _eval_env: &mut EvalEnv) -> Result<XSequence, Box<Error>> {
+    return fn_siblings(&vec!{context_xseq});
+}
+
+fn fn_siblings(args: &Vec<&XSequence>) -> Result<XSequence, Box<Error>> {
+    if args[0].is_empty() {
+        return Ok(new_xsequence());
+    }
+    let node = match args[0].get_singleton_node() {
+        Ok(node) => node,
+        Err(_) => return Err(type_error!("siblings(): Item is not a node [err:XPTY0004]")),
+    };
+    if node.node_type() == NodeType::Attribute {
+        return Ok(new_singleton_node(&node));
+    }
+    match node.parent() {
+        Some(parent) => {
+            let siblings: Vec<NodePtr> = parent.children().into_iter()
+                .filter(|ch| ch.node_type() != NodeType::XMLDecl &&
+                             ch.node_type() != NodeType::Directive &&
+                             ! ch.is_prolog_white_space())
+                .collect();
+            return Ok(new_xsequence_from_node_array(&siblings));
+        },
+        None => return Ok(new_singleton_node(&node)),
+    }
+}
+
 // ---------------------------------------------------------------------
 // 14 Functions and Operators on Sequences
 //
@@ -1323,9 +2355,9 @@ fn fn_subsequence(args: &Vec<&XSequence>) -> Result<XSequence, Box<Error>> {
 
     let starting_loc = args[1].get_singleton_item()?.get_as_raw_double()?;
     let length = if args.len() == 2 {
-            f64::INFINITY
+            None
         } else {
-            args[2].get_singleton_item()?.get_as_raw_double()?
+            Some(args[2].get_singleton_item()?.get_as_raw_double()?)
         };
     let (b, e) = subcollection_index_sub(
                     source_sequence.len(), starting_loc, length);
@@ -1340,6 +2372,44 @@ fn fn_subsequence(args: &Vec<&XSequence>) -> Result<XSequence, Box<Error>> {
 // ---------------------------------------------------------------------
 // 14.2 Functions That Compare Values in Sequences
 //
+// ---------------------------------------------------------------------
+// 14.2.1 fn:distinct-values
+// fn:distinct-values($arg as xs:anyAtomicType*) as xs:anyAtomicType*
+// fn:distinct-values($arg as xs:anyAtomicType*,
+//                    $collation as xs:string) as xs:anyAtomicType*
+//
+// 等しい値のうち最初に現れたものを残す。
+// 比較できない値どうしは異なるとみなす。NaN どうしは等しいとみなす。
+//
+fn fn_distinct_values(args: &Vec<&XSequence>, _context_xseq: &XSequence,
+                eval_env: &mut EvalEnv) -> Result<XSequence, Box<Error>> {
+    let collation = collation_arg(args, 1, eval_env)?;
+    let mut distinct: Vec<XItem> = vec!{};
+    for item in args[0].iter() {
+        let item = item.atomize();
+        let is_nan = item.is_numeric() &&
+                    item.get_as_raw_double().map(|d| d.is_nan()).unwrap_or(false);
+        let mut found = false;
+        for v in distinct.iter() {
+            let same_nan = is_nan && v.is_numeric() &&
+                    v.get_as_raw_double().map(|d| d.is_nan()).unwrap_or(false);
+            if same_nan || equal_with_collation(v, &item, &collation).unwrap_or(false) {
+                found = true;
+                break;
+            }
+        }
+        if ! found {
+            distinct.push(item);
+        }
+    }
+
+    let mut result = new_xsequence();
+    for item in distinct.iter() {
+        result.push(item);
+    }
+    return Ok(result);
+}
+
 // ---------------------------------------------------------------------
 // 14.2.2 fn:index-of
 // fn:index-of($seqParam as xs:anyAtomicType*,
@@ -1348,12 +2418,14 @@ fn fn_subsequence(args: &Vec<&XSequence>) -> Result<XSequence, Box<Error>> {
 //             $srchParam as xs:anyAtomicType,
 //             $collation as xs:string) as xs:integer*
 //
-fn fn_index_of(args: &Vec<&XSequence>) -> Result<XSequence, Box<Error>> {
+fn fn_index_of(args: &Vec<&XSequence>, _context_xseq: &XSequence,
+                eval_env: &mut EvalEnv) -> Result<XSequence, Box<Error>> {
+    let collation = collation_arg(args, 2, eval_env)?;
     let seq_param = args[0];
-    let srch_param = args[1];
+    let srch_param = args[1].get_singleton_item()?;
     let mut result = new_xsequence();
     for (i, v) in seq_param.iter().enumerate() {
-        if value_compare_eq(&new_singleton(v), srch_param)?.get_singleton_boolean()? == true {
+        if equal_with_collation(v, &srch_param, &collation)? {
             result.push(&new_xitem_integer(usize_to_i64(i + 1)));
         }
     }
@@ -1371,7 +2443,9 @@ fn fn_zero_or_one(args: &Vec<&XSequence>) -> Result<XSequence, Box<Error>> {
     if args[0].len() <= 1 {
         return Ok(args[0].clone());
     } else {
-        return Err(dynamic_error!("fn:zero-or-one called with a sequence containing more than one item."));
+        return Err(dynamic_error!(
+                "fn:zero-or-one called with a sequence containing {} items [err:FORG0003]",
+                args[0].len()));
     }
 }
 
@@ -1383,7 +2457,8 @@ fn fn_one_or_more(args: &Vec<&XSequence>) -> Result<XSequence, Box<Error>> {
     if 1 <= args[0].len() {
         return Ok(args[0].clone());
     } else {
-        return Err(dynamic_error!("fn:one-or-more called with a sequence containing no items."));
+        return Err(dynamic_error!(
+                "fn:one-or-more called with a sequence containing no items [err:FORG0004]"));
     }
 }
 
@@ -1395,7 +2470,9 @@ fn fn_exactly_one(args: &Vec<&XSequence>) -> Result<XSequence, Box<Error>> {
     if args[0].len() == 1 {
         return Ok(args[0].clone());
     } else {
-        return Err(dynamic_error!("fn:exactly-one called with a sequence containing zero or more than one item."));
+        return Err(dynamic_error!(
+                "fn:exactly-one called with a sequence containing {} items [err:FORG0005]",
+                args[0].len()));
     }
 }
 
@@ -1435,15 +2512,19 @@ fn fn_avg(args: &Vec<&XSequence>) -> Result<XSequence, Box<Error>> {
 // fn:max($arg as xs:anyAtomicType*) as xs:anyAtomicType?
 // fn:max($arg as xs:anyAtomicType*, $collation as string) as xs:anyAtomicType?
 //
-fn fn_max(args: &Vec<&XSequence>) -> Result<XSequence, Box<Error>> {
+fn fn_max(args: &Vec<&XSequence>, _context_xseq: &XSequence,
+                eval_env: &mut EvalEnv) -> Result<XSequence, Box<Error>> {
+    let collation = collation_arg(args, 1, eval_env)?;
     if args[0].is_empty() {
         return Ok(new_xsequence());
     }
+    if let Some(result) = numeric_extremum(args[0], Ordering::Greater) {
+        return Ok(new_singleton(&result?));
+    }
 
     let mut max_item = args[0].get_item(0).clone();
     for item in args[0].iter() {
-        let b = value_compare_lt(&new_singleton(&max_item), &new_singleton(item))?;
-        if b.effective_boolean_value()? == true {
+        if compare_with_collation(&max_item, item, &collation)? == Ordering::Less {
             max_item = item.clone();
         }
     }
@@ -1456,20 +2537,65 @@ fn fn_max(args: &Vec<&XSequence>) -> Result<XSequence, Box<Error>> {
 // fn:min($arg as xs:anyAtomicType*) as xs:anyAtomicType?
 // fn:min($arg as xs:anyAtomicType*, $collation as string) as xs:anyAtomicType?
 //
-fn fn_min(args: &Vec<&XSequence>) -> Result<XSequence, Box<Error>> {
+fn fn_min(args: &Vec<&XSequence>, _context_xseq: &XSequence,
+                eval_env: &mut EvalEnv) -> Result<XSequence, Box<Error>> {
+    let collation = collation_arg(args, 1, eval_env)?;
     if args[0].is_empty() {
         return Ok(new_xsequence());
     }
+    if let Some(result) = numeric_extremum(args[0], Ordering::Less) {
+        return Ok(new_singleton(&result?));
+    }
 
-    let mut max_item = args[0].get_item(0).clone();
+    let mut min_item = args[0].get_item(0).clone();
     for item in args[0].iter() {
-        let b = value_compare_gt(&new_singleton(&max_item), &new_singleton(item))?;
-        if b.effective_boolean_value()? == true {
-            max_item = item.clone();
+        if compare_with_collation(item, &min_item, &collation)? == Ordering::Less {
+            min_item = item.clone();
         }
     }
 
-    return Ok(new_singleton(&max_item));
+    return Ok(new_singleton(&min_item));
+}
+
+// ---------------------------------------------------------------------
+// fn:max、fn:min で、すべての項目が数値の場合。それ以外はNone。
+// - 項目はすべて共通の型 (integer < decimal < double の順で最も広い型)
+//   に昇格してから比較するので、結果もその型になる。
+// - NaN があれば、結果は NaN。
+//
+fn numeric_extremum(xseq: &XSequence, ordering: Ordering) -> Option<Result<XItem, Box<Error>>> {
+    let mut common_type = "xs:integer";
+    for item in xseq.iter() {
+        match item {
+            XItem::XIInteger{value: _} => {},
+            XItem::XIDecimal{value: _} => {
+                if common_type == "xs:integer" {
+                    common_type = "xs:decimal";
+                }
+            },
+            XItem::XIDouble{value} => {
+                if value.is_nan() {
+                    return Some(Ok(new_xitem_double(f64::NAN)));
+                }
+                common_type = "xs:double";
+            },
+            _ => return None,
+        }
+    }
+
+    let mut result = xseq.get_item(0).clone();
+    for item in xseq.iter() {
+        let replace = match ordering {
+            Ordering::Greater => xitem_numeric_greater_than(item, &result),
+            _ => xitem_numeric_less_than(item, &result),
+        };
+        match replace {
+            Ok(true) => result = item.clone(),
+            Ok(false) => {},
+            Err(e) => return Some(Err(e)),
+        }
+    }
+    return Some(result.cast_as(common_type));
 }
 
 // ---------------------------------------------------------------------
@@ -1505,9 +2631,137 @@ fn fn_sum(args: &Vec<&XSequence>) -> Result<XSequence, Box<Error>> {
 // ---------------------------------------------------------------------
 // 14.5 Functions on Node Identifiers
 //
+// ---------------------------------------------------------------------
+// 14.5.1 fn:id
+// fn:id($arg as xs:string*) as element()*
+// fn:id($arg as xs:string*, $node as node()) as element()*
+//
+// DTDもスキーマも扱わないので、ID型の属性は xml:id だけである。
+// 属性値の索引 (NodePtr#build_attr_index()) を作っておき、それを引く。
+// $nodeを含む木の根が文書ノードでなければエラー [err:FODC0001]。
+//
+fn fn_id_1(args: &Vec<&XSequence>, context_xseq: &XSequence,
+               _eval_env: &mut EvalEnv) -> Result<XSequence, Box<Error>> {
+    if context_xseq.get_singleton_node().is_err() {
+        return Err(type_error!("fn:id(): Context item is not a node [err:XPTY0004]"));
+    }
+    return fn_id(&vec!{args[0], context_xseq});
+}
+
+fn fn_id(args: &Vec<&XSequence>) -> Result<XSequence, Box<Error>> {
+    let node = args[1].get_singleton_node()?;
+    let doc = node.root();
+    if doc.node_type() != NodeType::DocumentRoot {
+        return Err(dynamic_error!("fn:id(): Root is not a document node [err:FODC0001]"));
+    }
+    if doc.attr_index_lookup("xml:id", "").is_none() {
+        doc.build_attr_index("xml:id");
+    }
+
+    let mut node_array: Vec<NodePtr> = vec!{};
+    for item in args[0].iter() {
+        let idrefs = item.get_as_raw_string()?;
+        for idref in idrefs.split_whitespace() {
+            if let Some(nodes) = doc.attr_index_lookup("xml:id", idref) {
+                node_array.extend(nodes.into_iter().take(1));
+            }
+        }
+    }
+    sort_document_order(&mut node_array);
+    return Ok(new_xsequence_from_node_array(&node_array));
+}
+
 // ---------------------------------------------------------------------
 // 14.6 Functions Giving Access to External Information
 //
+// 資源は、EvalOptions#uri_scheme() で登録した処理函数で取得する。
+// 相対URIは静的なベースURI (EvalOptions#base_uri()) に対して解決する。
+//
+// ---------------------------------------------------------------------
+// 14.6.1 fn:doc
+// fn:doc($uri as xs:string?) as document-node()?
+//
+// 評価中、同じURIに対しては同じ文書ノードを返す。
+//
+fn fn_doc(args: &Vec<&XSequence>, _context_xseq: &XSequence,
+                eval_env: &mut EvalEnv) -> Result<XSequence, Box<Error>> {
+    if args[0].is_empty() {
+        return Ok(new_xsequence());
+    }
+    let uri = args[0].get_singleton_item()?.get_as_raw_string()?;
+    let doc = eval_env.fetch_document(&uri)?;
+    return Ok(new_singleton_node(&doc));
+}
+
+// ---------------------------------------------------------------------
+// 14.6.2 fn:doc-available
+// fn:doc-available($uri as xs:string?) as xs:boolean
+//
+fn fn_doc_available(args: &Vec<&XSequence>, _context_xseq: &XSequence,
+                eval_env: &mut EvalEnv) -> Result<XSequence, Box<Error>> {
+    if args[0].is_empty() {
+        return Ok(new_singleton_boolean(false));
+    }
+    let uri = args[0].get_singleton_item()?.get_as_raw_string()?;
+    let available = eval_env.fetch_document(&uri).is_ok();
+    return Ok(new_singleton_boolean(available));
+}
+
+// ---------------------------------------------------------------------
+// 14.6.5 fn:unparsed-text
+// fn:unparsed-text($href as xs:string?) as xs:string?
+//
+// 取得できなければエラー [err:FOUT1170]。
+//
+fn fn_unparsed_text(args: &Vec<&XSequence>, _context_xseq: &XSequence,
+                eval_env: &mut EvalEnv) -> Result<XSequence, Box<Error>> {
+    if args[0].is_empty() {
+        return Ok(new_xsequence());
+    }
+    let uri = args[0].get_singleton_item()?.get_as_raw_string()?;
+    match eval_env.fetch_resource(&uri) {
+        Ok(text) => return Ok(new_singleton_string(&text)),
+        Err(e) => return Err(dynamic_error!(
+                "fn:unparsed-text(): {} [err:FOUT1170]", e)),
+    }
+}
+
+// ---------------------------------------------------------------------
+// 14.6.7 fn:unparsed-text-available
+// fn:unparsed-text-available($href as xs:string?) as xs:boolean
+//
+fn fn_unparsed_text_available(args: &Vec<&XSequence>, _context_xseq: &XSequence,
+                eval_env: &mut EvalEnv) -> Result<XSequence, Box<Error>> {
+    if args[0].is_empty() {
+        return Ok(new_singleton_boolean(false));
+    }
+    let uri = args[0].get_singleton_item()?.get_as_raw_string()?;
+    let available = eval_env.fetch_resource(&uri).is_ok();
+    return Ok(new_singleton_boolean(available));
+}
+
+// ---------------------------------------------------------------------
+// 14.6.8 fn:collection
+// fn:collection() as item()*
+// fn:collection($arg as xs:string?) as item()*
+//
+// EvalOptions#add_collection() で登録したノード列を返す。
+// 引数なし、または空シーケンスならば既定のコレクション。
+//
+fn fn_collection_0(_args: &Vec<&XSequence>, _context_xseq: &XSequence,
+                eval_env: &mut EvalEnv) -> Result<XSequence, Box<Error>> {
+    return eval_env.fetch_collection(None);
+}
+
+fn fn_collection(args: &Vec<&XSequence>, _context_xseq: &XSequence,
+                eval_env: &mut EvalEnv) -> Result<XSequence, Box<Error>> {
+    if args[0].is_empty() {
+        return eval_env.fetch_collection(None);
+    }
+    let uri = args[0].get_singleton_item()?.get_as_raw_string()?;
+    return eval_env.fetch_collection(Some(&uri));
+}
+
 // ---------------------------------------------------------------------
 // 14.7 Parsing and Serializing
 //
@@ -1532,9 +2786,128 @@ fn fn_last(_args: &Vec<&XSequence>, _xseq: &XSequence,
     return Ok(new_singleton_integer(usize_to_i64(eval_env.get_last())));
 }
 
+// ---------------------------------------------------------------------
+// 15.9 fn:static-base-uri
+// fn:static-base-uri() as xs:anyURI?
+//
+// EvalOptions#base_uri() で指定した値。指定がなければ空シーケンス。
+//
+fn fn_static_base_uri(_args: &Vec<&XSequence>, _xseq: &XSequence,
+                eval_env: &mut EvalEnv) -> Result<XSequence, Box<Error>> {
+    match eval_env.static_base_uri() {
+        Some(uri) => return Ok(new_singleton_string(&uri)),
+        None => return Ok(new_xsequence()),
+    }
+}
+
 // ---------------------------------------------------------------------
 // 16 Higher-Order Functions
 //
+// ---------------------------------------------------------------------
+// 16.1.1 fn:function-lookup
+// fn:function-lookup($name as xs:QName,
+//                    $arity as xs:integer) as function(*)?
+//
+// xs:QName型は未実装なので、$nameは文字列 ("fn:concat"、"concat"、
+// "Q{http://www.w3.org/2005/xpath-functions}concat" など) で指定する。
+// 接頭辞を省略した場合は "fn:" とみなす。
+// 組み込み函数表に該当する函数があれば、その名前付き函数参照を返す。
+//
+fn fn_function_lookup(args: &Vec<&XSequence>) -> Result<XSequence, Box<Error>> {
+    let mut func_name = resolve_eqname(&args[0].get_singleton_item()?.get_as_raw_string()?);
+    if ! func_name.contains(":") {
+        func_name = format!("fn:{}", func_name);
+    }
+    let arity = args[1].get_singleton_integer()?;
+    if arity < 0 || ! check_function_spec(&func_name, arity as usize) {
+        return Ok(new_xsequence());
+    }
+    let xnode = new_xnode(XNodeType::NamedFunctionRef,
+                    &format!("{}#{}", func_name, arity));
+    return Ok(new_singleton_xnodeptr(&xnode));
+}
+
+// ---------------------------------------------------------------------
+// 16.1.2 fn:function-name
+// fn:function-name($func as function(*)) as xs:QName?
+//
+// 名前付き函数参照ならばその函数名 (xs:QName型は未実装なので文字列)、
+// インライン函数、部分函数、マップ、配列ならば空シーケンスを返す。
+//
+fn fn_function_name(args: &Vec<&XSequence>) -> Result<XSequence, Box<Error>> {
+    if let Ok(func_xnode) = args[0].get_singleton_xnodeptr() {
+        if get_xnode_type(&func_xnode) == XNodeType::NamedFunctionRef {
+            let func_name = get_xnode_name(&func_xnode);
+            let v: Vec<&str> = func_name.split("#").collect();
+            return Ok(new_singleton_string(v[0]));
+        }
+        return Ok(new_xsequence());
+    }
+    function_item_arity(args[0])?;             // 函数であることの検査
+    return Ok(new_xsequence());
+}
+
+// ---------------------------------------------------------------------
+// 16.1.3 fn:function-arity
+// fn:function-arity($func as function(*)) as xs:integer
+//
+fn fn_function_arity(args: &Vec<&XSequence>) -> Result<XSequence, Box<Error>> {
+    let arity = function_item_arity(args[0])?;
+    return Ok(new_singleton_integer(usize_to_i64(arity)));
+}
+
+// ---------------------------------------------------------------------
+// 函数アイテム (インライン函数、名前付き函数参照、部分函数、マップ、配列)
+// の引数の個数。
+//
+fn function_item_arity(xseq: &XSequence) -> Result<usize, Box<Error>> {
+    if xseq.get_singleton_map().is_ok() || xseq.get_singleton_array().is_ok() {
+        return Ok(1);
+    }
+    let func_xnode = xseq.get_singleton_xnodeptr()?;
+    match function_xnode_arity(&func_xnode) {
+        Some(arity) => return Ok(arity),
+        None => return Err(type_error!("Not a function item: {}", xseq.to_string())),
+    }
+}
+
+// ---------------------------------------------------------------------
+// 函数アイテムを表す構文木 (インライン函数、名前付き函数参照、
+// 部分函数) の引数の個数。それ以外の構文木ならば None。
+//
+pub fn function_xnode_arity(func_xnode: &XNodePtr) -> Option<usize> {
+    match get_xnode_type(func_xnode) {
+        XNodeType::NamedFunctionRef => {
+            let func_name = get_xnode_name(func_xnode);
+            let v: Vec<&str> = func_name.split("#").collect();
+            return Some(v.get(1).and_then(|n| n.parse::<usize>().ok()).unwrap_or(0));
+        },
+        XNodeType::InlineFunction => {
+            // InlineFunction --- ReturnType --- Param --- Param ---...
+            let mut arity = 0;
+            let mut curr = get_right(&get_right(func_xnode));
+            while ! is_nil_xnode(&curr) {
+                arity += 1;
+                curr = get_right(&curr);
+            }
+            return Some(arity);
+        },
+        XNodeType::PartialFunctionCall => {
+            // PartialFunctionCall --- ArgumentTop --- ArgumentPlaceholder ---...
+            let mut arity = 0;
+            let mut curr = get_right(func_xnode);
+            while ! is_nil_xnode(&curr) {
+                if get_xnode_type(&curr) == XNodeType::ArgumentPlaceholder {
+                    arity += 1;
+                }
+                curr = get_right(&curr);
+            }
+            return Some(arity);
+        },
+        _ => return None,
+    }
+}
+
 // ---------------------------------------------------------------------
 // 16.2.1 fn:for-each
 // fn:for-each($seq as item()*,
@@ -1543,12 +2916,12 @@ fn fn_last(_args: &Vec<&XSequence>, _xseq: &XSequence,
 fn fn_for_each(args: &Vec<&XSequence>, context_xseq: &XSequence,
                 eval_env: &mut EvalEnv) -> Result<XSequence, Box<Error>> {
 
-    let action_xnode = args[1].get_singleton_xnodeptr()?;
+    let (action_xnode, captured) = args[1].get_singleton_function()?;
     let mut result = new_xsequence();
     for xitem in args[0].iter() {
         let argument_xseq = new_singleton(xitem);
-        let result_xseq = call_function(
-                &action_xnode, vec!{argument_xseq}, context_xseq, eval_env)?;
+        let result_xseq = call_function(&action_xnode, &captured,
+                vec!{argument_xseq}, context_xseq, eval_env)?;
         result.append(&result_xseq);
     }
     return Ok(result);
@@ -1562,12 +2935,12 @@ fn fn_for_each(args: &Vec<&XSequence>, context_xseq: &XSequence,
 fn fn_filter(args: &Vec<&XSequence>, context_xseq: &XSequence,
                 eval_env: &mut EvalEnv) -> Result<XSequence, Box<Error>> {
 
-    let func_xnode = args[1].get_singleton_xnodeptr()?;
+    let (func_xnode, captured) = args[1].get_singleton_function()?;
     let mut result = new_xsequence();
     for xitem in args[0].iter() {
         let argument_xseq = new_singleton(xitem);
-        let result_xseq = call_function(
-                &func_xnode, vec!{argument_xseq}, context_xseq, eval_env)?;
+        let result_xseq = call_function(&func_xnode, &captured,
+                vec!{argument_xseq}, context_xseq, eval_env)?;
         if result_xseq.effective_boolean_value()? == true {
             result.push(&xitem);
         }
@@ -1575,6 +2948,420 @@ fn fn_filter(args: &Vec<&XSequence>, context_xseq: &XSequence,
     return Ok(result);
 }
 
+// ---------------------------------------------------------------------
+// 16.2.7 fn:apply
+// fn:apply($function as function(*), $array as array(*)) as item()*
+//
+// $arrayの各メンバーを引数として$functionを呼び出す。
+// メンバーの個数が$functionの引数の個数と一致しなければエラー
+// [err:FOAP0001]。
+//
+fn fn_apply(args: &Vec<&XSequence>, context_xseq: &XSequence,
+                eval_env: &mut EvalEnv) -> Result<XSequence, Box<Error>> {
+
+    let array_item = args[1].get_singleton_array()?;
+    let mut argument_xseq: Vec<XSequence> = vec!{};
+    for i in 1 ..= array_item.array_size() {
+        if let Some(member) = array_item.array_get(&new_xitem_integer(usize_to_i64(i))) {
+            argument_xseq.push(member);
+        }
+    }
+
+    let arity = function_item_arity(args[0])?;
+    if arity != argument_xseq.len() {
+        return Err(dynamic_error!(
+            "fn:apply: the function expects {} argument(s), but the array has {} member(s) [err:FOAP0001]",
+            arity, argument_xseq.len()));
+    }
+
+    if let Ok(map_item) = args[0].get_singleton_map() {
+        let key = argument_xseq[0].get_singleton_item()?;
+        return Ok(map_item.map_get(&key).unwrap_or(new_xsequence()));
+    }
+    if let Ok(array_func) = args[0].get_singleton_array() {
+        let index = argument_xseq[0].get_singleton_item()?;
+        match array_func.array_get(&index) {
+            Some(v) => return Ok(v),
+            None => return Err(dynamic_error!(
+                    "Array index ({}) out of bounds.", index)),
+        }
+    }
+    let (func_xnode, captured) = args[0].get_singleton_function()?;
+    return call_function(&func_xnode, &captured, argument_xseq, context_xseq, eval_env);
+}
+
+// ---------------------------------------------------------------------
+// am: 拡張函数
+//
+// ---------------------------------------------------------------------
+// am:group-by
+// am:group-by($seq as item()*,
+//             $key as function(item()) as xs:anyAtomicType?) as map(*)
+//
+// $seqの各アイテムに$keyを適用して得たキーごとにアイテムをまとめ、
+// キーから (元の順序を保った) アイテム列へのマップを返す。
+// キーが空シーケンスになったアイテムはどのグループにも含めない。
+// マップのキーは、最初に出現した順に並ぶ。
+//
+fn am_group_by(args: &Vec<&XSequence>, context_xseq: &XSequence,
+                eval_env: &mut EvalEnv) -> Result<XSequence, Box<Error>> {
+
+    let (key_xnode, captured) = args[1].get_singleton_function()?;
+    let mut groups: Vec<(XItem, XSequence)> = vec!{};
+    for xitem in args[0].iter() {
+        let key = match group_key(&key_xnode, &captured, xitem, context_xseq, eval_env)? {
+            Some(key) => key,
+            None => continue,
+        };
+        let mut index = usize::MAX;
+        for (i, entry) in groups.iter().enumerate() {
+            if entry.0.op_same_key(&key) {
+                index = i;
+                break;
+            }
+        }
+        if index != usize::MAX {
+            groups[index].1.push(xitem);
+        } else {
+            groups.push((key, new_singleton(xitem)));
+        }
+    }
+    return Ok(new_singleton(&new_xitem_map(&groups)));
+}
+
+// ---------------------------------------------------------------------
+// am:group-adjacent
+// am:group-adjacent($seq as item()*,
+//             $key as function(item()) as xs:anyAtomicType?) as array(*)
+//
+// $seqの各アイテムに$keyを適用し、キーが等しいアイテムが隣接している
+// 範囲をひとつのグループとする。各グループ (シーケンス) を要素とする
+// 配列を返す。キーが空シーケンスになったアイテムは、それだけで
+// ひとつのグループとする。
+//
+fn am_group_adjacent(args: &Vec<&XSequence>, context_xseq: &XSequence,
+                eval_env: &mut EvalEnv) -> Result<XSequence, Box<Error>> {
+
+    let (key_xnode, captured) = args[1].get_singleton_function()?;
+    let mut groups: Vec<XSequence> = vec!{};
+    let mut prev_key: Option<XItem> = None;
+    for xitem in args[0].iter() {
+        let key = group_key(&key_xnode, &captured, xitem, context_xseq, eval_env)?;
+        let mut is_same_group = false;
+        if let (Some(ref k), Some(ref p)) = (&key, &prev_key) {
+            is_same_group = k.op_same_key(p);
+        }
+        if is_same_group {
+            if let Some(last) = groups.last_mut() {
+                last.push(xitem);
+            }
+        } else {
+            groups.push(new_singleton(xitem));
+        }
+        prev_key = key;
+    }
+    return Ok(new_singleton(&new_xitem_array(&groups)));
+}
+
+// ---------------------------------------------------------------------
+// am:group-by / am:group-adjacent の補助:
+// アイテムにキー函数を適用し、原子化したキーを返す。
+// キーが空シーケンスならばNone、2個以上のアイテムならばエラー。
+//
+fn group_key(key_xnode: &XNodePtr, captured: &Vec<(String, XSequence)>,
+                xitem: &XItem,
+                context_xseq: &XSequence,
+                eval_env: &mut EvalEnv) -> Result<Option<XItem>, Box<Error>> {
+    let key_xseq = call_function(
+            key_xnode, captured, vec!{new_singleton(xitem)}, context_xseq, eval_env)?;
+    let key_xseq = key_xseq.atomize();
+    if key_xseq.is_empty() {
+        return Ok(None);
+    }
+    if ! key_xseq.is_singleton() {
+        return Err(type_error!(
+                "グループ化のキーが単一の原子値でない: {}。", key_xseq));
+    }
+    return Ok(Some(key_xseq.get_singleton_item()?));
+}
+
+// ---------------------------------------------------------------------
+// am:random-permute
+// am:random-permute($seq as item()*, $seed as xs:anyAtomicType?) as item()*
+//
+// $seqを擬似乱数で並べ替えたシーケンス (Fisher-Yates法)。
+// fn:random-number-generator() の permute の実体でもある。
+// $seedの扱いは fn:random-number-generator() と同じ。
+//
+fn am_random_permute(args: &Vec<&XSequence>, _context_xseq: &XSequence,
+                eval_env: &mut EvalEnv) -> Result<XSequence, Box<Error>> {
+    let mut state = random_seed_state(args[1], eval_env)?;
+    let mut items: Vec<XItem> = args[0].iter().cloned().collect();
+    let mut i = items.len();
+    while i > 1 {
+        let (next_state, z) = splitmix64(state);
+        state = next_state;
+        let j = (z % (i as u64)) as usize;
+        items.swap(i - 1, j);
+        i -= 1;
+    }
+    let mut result = new_xsequence();
+    for item in items.iter() {
+        result.push(item);
+    }
+    return Ok(result);
+}
+
+// ---------------------------------------------------------------------
+// am:child-count
+// am:child-count($node as node()?) as xs:integer
+// am:child-element-count($node as node()?) as xs:integer
+//
+// 子ノード (child::node()) の数、子要素 (child::*) の数。
+// 要素の場合は、子のベクターを作らずに数える。
+//
+fn am_child_count(args: &Vec<&XSequence>) -> Result<XSequence, Box<Error>> {
+    if args[0].is_empty() {
+        return Ok(new_singleton_integer(0));
+    }
+    let node = match args[0].get_singleton_node() {
+        Ok(node) => node,
+        Err(_) => return Err(type_error!("am:child-count(): Item is not a node [err:XPTY0004]")),
+    };
+    let count = match node.node_type() {
+        NodeType::DocumentRoot => node.children().iter()
+                .filter(|ch| ch.node_type() != NodeType::XMLDecl &&
+                             ch.node_type() != NodeType::Directive &&
+                             ! ch.is_prolog_white_space())
+                .count(),
+        _ => node.child_count(),
+    };
+    return Ok(new_singleton_integer(count as i64));
+}
+
+fn am_child_element_count(args: &Vec<&XSequence>) -> Result<XSequence, Box<Error>> {
+    if args[0].is_empty() {
+        return Ok(new_singleton_integer(0));
+    }
+    let node = match args[0].get_singleton_node() {
+        Ok(node) => node,
+        Err(_) => return Err(type_error!("am:child-element-count(): Item is not a node [err:XPTY0004]")),
+    };
+    return Ok(new_singleton_integer(node.child_element_count() as i64));
+}
+
+// ---------------------------------------------------------------------
+// am:grapheme-length
+// am:grapheme-length($arg as xs:string?) as xs:integer
+//
+// 拡張書記素クラスタ (利用者が1文字と認識する単位) の数。
+// 結合文字や絵文字の並びも1個と数える (fn:string-length はコードポイント
+// の数)。
+// 数える実体は feature "unicode-segmentation" 指定時のみ。
+// 指定しない場合は、ASCII文字列のみ扱える (CR LF は1個と数える)。
+//
+fn am_grapheme_length(args: &Vec<&XSequence>) -> Result<XSequence, Box<Error>> {
+    if args[0].is_empty() {
+        return Ok(new_singleton_integer(0));
+    }
+
+    let arg = args[0].get_singleton_string()?;
+    let length = if arg.is_ascii() {
+        arg.len() - arg.matches("\r\n").count()
+    } else {
+        grapheme_length(&arg)?
+    };
+    return Ok(new_singleton_integer(usize_to_i64(length)));
+}
+
+#[cfg(feature = "unicode-segmentation")]
+fn grapheme_length(s: &str) -> Result<usize, Box<Error>> {
+    use unicode_segmentation::UnicodeSegmentation;
+    return Ok(s.graphemes(true).count());
+}
+
+#[cfg(not(feature = "unicode-segmentation"))]
+fn grapheme_length(_s: &str) -> Result<usize, Box<Error>> {
+    return Err(uninplemented!(
+        "am:grapheme-length of non-ASCII string requires feature \"unicode-segmentation\""));
+}
+
+// ---------------------------------------------------------------------
+// am:parse-rfc3339
+// am:parse-rfc3339($value as xs:string?) as xs:dateTime?
+//
+// RFC 3339 の日時を、xs:dateTime へのキャストより寛容に解析する
+// (日付と時刻の間の空白、秒の省略、コロンのない時差など)。
+//
+fn am_parse_rfc3339(args: &Vec<&XSequence>) -> Result<XSequence, Box<Error>> {
+    if args[0].is_empty() {
+        return Ok(new_xsequence());
+    }
+    let value = args[0].get_singleton_string()?;
+    let dt = parse_rfc3339(&value)?;
+    return Ok(new_singleton(&new_xitem_date_time(&dt)));
+}
+
+// ---------------------------------------------------------------------
+// am:parse-html
+// am:parse-html($html as xs:string) as document-node()
+//
+// HTML風の文字列を寛容モード (dom::new_html_document) で解析し、
+// 新しい文書の文書ノードを返す。解析は失敗しない。
+//
+fn am_parse_html(args: &Vec<&XSequence>) -> Result<XSequence, Box<Error>> {
+    let html = args[0].get_singleton_string()?;
+    return Ok(new_singleton_node(&new_html_document(&html)));
+}
+
+// ---------------------------------------------------------------------
+// am:put-in
+// am:put-in($target as item()*, $keys as array(*),
+//           $value as item()*) as item()*
+//
+// マップ/配列を入れ子にした$targetについて、$keysの各メンバーを順に
+// キー (配列ならば1始まりの位置) として辿った先の値を$valueに置き換えた
+// 新しいマップ/配列を返す。$targetは変更しない。
+// 途中のマップにキーがなければ空のマップを補う。配列の位置が範囲外の
+// 場合はエラー。$keysが空の配列ならば$valueをそのまま返す。
+//
+fn am_put_in(args: &Vec<&XSequence>) -> Result<XSequence, Box<Error>> {
+    let array = args[1].get_singleton_array()?;
+    let mut keys: Vec<XItem> = vec!{};
+    for i in 1 ..= array.array_size() {
+        let member = array.array_get(&new_xitem_integer(i as i64))
+                        .unwrap_or(new_xsequence());
+        keys.push(container_key(&member)?);
+    }
+    if keys.is_empty() {
+        return Ok(args[2].clone());
+    }
+
+    // -----------------------------------------------------------------
+    // 根から順に、辿った容器 (マップ/配列) を積んでおき、
+    // 葉から根に向かって値を置き換えた容器を作り直していく。
+    //
+    let mut containers: Vec<XItem> = vec!{};
+    let mut curr = args[0].clone();
+    for (i, key) in keys.iter().enumerate() {
+        let container = if curr.is_empty() && i != 0 {
+            new_xitem_map(&vec!{})
+        } else {
+            curr.get_singleton_item()?
+        };
+        curr = match container {
+            XItem::XIMap{ref value} => {
+                value.map_get(key).unwrap_or(new_xsequence())
+            },
+            XItem::XIArray{ref value} => {
+                match value.array_get(key) {
+                    Some(v) => v,
+                    None => return Err(dynamic_error!(
+                        "FOAY0001: 配列の位置が範囲外: {}。", key)),
+                }
+            },
+            _ => return Err(type_error!(
+                    "am:put-in: マップでも配列でもない: {}。", container)),
+        };
+        containers.push(container);
+    }
+
+    let mut value = args[2].clone();
+    while let Some(container) = containers.pop() {
+        let key = &keys[containers.len()];
+        value = new_singleton(&container_put(&container, key, &value)?);
+    }
+    return Ok(value);
+}
+
+// ---------------------------------------------------------------------
+// am:update
+// am:update($target as item(), $key as xs:anyAtomicType,
+//           $f as function(item()*) as item()*) as item()
+//
+// マップ/配列である$targetの、$keyに対応する値に$fを適用し、
+// その結果で値を置き換えた新しいマップ/配列を返す。
+// マップに$keyがなければ、空シーケンスに$fを適用した結果を追加する。
+// 配列の位置が範囲外の場合はエラー。
+//
+fn am_update(args: &Vec<&XSequence>, context_xseq: &XSequence,
+                eval_env: &mut EvalEnv) -> Result<XSequence, Box<Error>> {
+
+    let container = args[0].get_singleton_item()?;
+    let key = container_key(args[1])?;
+    let old_value = match container {
+        XItem::XIMap{ref value} => {
+            value.map_get(&key).unwrap_or(new_xsequence())
+        },
+        XItem::XIArray{ref value} => {
+            match value.array_get(&key) {
+                Some(v) => v,
+                None => return Err(dynamic_error!(
+                    "FOAY0001: 配列の位置が範囲外: {}。", key)),
+            }
+        },
+        _ => return Err(type_error!(
+                "am:update: マップでも配列でもない: {}。", container)),
+    };
+    let (func_xnode, captured) = args[2].get_singleton_function()?;
+    let new_value = call_function(&func_xnode, &captured,
+            vec!{old_value}, context_xseq, eval_env)?;
+    return Ok(new_singleton(&container_put(&container, &key, &new_value)?));
+}
+
+// ---------------------------------------------------------------------
+// am:evaluate
+// am:evaluate($xpath as xs:string) as item()*
+// am:evaluate($xpath as xs:string, $context as item()?) as item()*
+//
+// $xpathを式として構文解析し、評価した結果を返す。
+// 変数など評価環境は呼び出し側のものを引き継ぐ。
+// $contextを指定した場合は、それを文脈アイテムとする
+// (空シーケンスならば文脈アイテムなし)。
+// EvalOptions#allow_evaluate(true) を指定していなければエラー。
+//
+fn am_evaluate(args: &Vec<&XSequence>, context_xseq: &XSequence,
+                eval_env: &mut EvalEnv) -> Result<XSequence, Box<Error>> {
+    let xpath = args[0].get_singleton_string()?;
+    let new_context = if args.len() == 2 { Some(args[1]) } else { None };
+    return evaluate_dynamic_xpath(&xpath, context_xseq, new_context, eval_env);
+}
+
+// ---------------------------------------------------------------------
+// am:put-in / am:update の補助:
+// キーとするシーケンスを原子化し、単一の原子値であればそれを返す。
+//
+fn container_key(xseq: &XSequence) -> Result<XItem, Box<Error>> {
+    let key_xseq = xseq.atomize();
+    if ! key_xseq.is_singleton() {
+        return Err(type_error!(
+                "キーが単一の原子値でない: {}。", key_xseq));
+    }
+    return Ok(key_xseq.get_singleton_item()?);
+}
+
+// ---------------------------------------------------------------------
+// am:put-in / am:update の補助:
+// マップ/配列のkeyに対応する値をvalueに置き換えた、新しいマップ/配列を返す。
+//
+fn container_put(container: &XItem, key: &XItem,
+                value: &XSequence) -> Result<XItem, Box<Error>> {
+    match container {
+        XItem::XIMap{value: map} => {
+            return Ok(XItem::XIMap{ value: map.map_put(key, value) });
+        },
+        XItem::XIArray{value: array} => {
+            match array.array_put(key, value) {
+                Some(array) => return Ok(XItem::XIArray{ value: array }),
+                None => return Err(dynamic_error!(
+                    "FOAY0001: 配列の位置が範囲外: {}。", key)),
+            }
+        },
+        _ => return Err(type_error!(
+                "マップでも配列でもない: {}。", container)),
+    }
+}
+
 // ---------------------------------------------------------------------
 // 17 Maps and Arrays
 //
@@ -1689,6 +3476,7 @@ fn array_flatten(args: &Vec<&XSequence>) -> Result<XSequence, Box<Error>> {
 // ある長さの順序つき集合 (C; 文字列、シーケンスなど) の部分集合を
 // 取得するために、開始位置 b と終了位置 e を求める。
 // s (starting_loc、1起点の値) と l (length) はf64型で、NaNやInfにもなりうる。
+// lを省略した (2引数の) 場合はNoneとする。
 // Cの要素 (番号 i := [b, e)、0起点の値) から成る部分集合を
 // 取得すればよいよう、bとe (usize型) を求めて返す。
 // 空集合を取得するべき場合は、b = 0、e = 0 を返す。
@@ -1697,43 +3485,48 @@ fn array_flatten(args: &Vec<&XSequence>) -> Result<XSequence, Box<Error>> {
 //                  15.1.10 fn:subsequence
 //
 fn subcollection_index_sub(source_length: usize,
-                           starting_loc: f64, length: f64) -> (usize, usize) {
+                           starting_loc: f64, length: Option<f64>) -> (usize, usize) {
 
-    if starting_loc.is_nan() || starting_loc.is_infinite() {
+    // -----------------------------------------------------------------
+    // 位置 p (1起点) が round(s) <= p < round(s) + round(l) を満たす要素。
+    // lを省略した場合は round(s) <= p のみ。
+    // 比較はf64のまま行う (NaNとの比較は偽になるので空集合)。
+    //
+    let lo = round_x(starting_loc);
+    let hi = match length {
+        Some(l) => lo + round_x(l),
+        None => f64::INFINITY,
+    };
+    if lo.is_nan() || hi.is_nan() {
         return (0, 0);
     }
-    let beg_pos = round_x(starting_loc) as i64;     // 有限値
-    let mut b = beg_pos - 1;                        // 0起点の値に補正
-    if b < 0 {
-        b = 0;
-    }
-    if source_length as i64 <= b {
-        b = source_length as i64;
-    }
 
-    let mut e: i64;
-    if length.is_infinite() && length.is_sign_positive() {
-        e = source_length as i64;
-    } else {
-        if length.is_nan() || length.is_sign_negative() {
-            return (0, 0);
-        }
-        let len_str = if length.is_infinite() {
-                source_length as i64
-            } else {
-                round_x(length) as i64                 // 非負の有限値
-            };
-        e = beg_pos + len_str - 1;
-        if e < b {
-            e = b;
-        }
-        if source_length as i64 <= e {
-            e = source_length as i64;
-        }
+    let upper = (source_length as f64) + 1.0;
+    let b = if lo < 1.0 { 1.0 } else { lo };
+    let e = if upper < hi { upper } else { hi };
+    if ! (b < e) {
+        return (0, 0);
     }
+    return ((b as usize) - 1, (e as usize) - 1);
+}
 
-    return (b as usize, e as usize);
-
+// ---------------------------------------------------------------------
+// fn:subsequence($sourceSeq, s, l) で、$sourceSeq の先頭から何個の
+// アイテムがあれば足りるか。すなわち round(s) + round(l) - 1 (負ならば0)。
+// lを省略した場合や、上限がない場合はNone。
+//
+pub fn subsequence_item_limit(starting_loc: f64, length: Option<f64>) -> Option<usize> {
+    let hi = match length {
+        Some(l) => round_x(starting_loc) + round_x(l),
+        None => return None,
+    };
+    if hi.is_nan() || hi <= 1.0 {
+        return Some(0);
+    }
+    if hi.is_infinite() || (usize::MAX as f64) <= hi {
+        return None;
+    }
+    return Some((hi as usize) - 1);
 }
 
 // =====================================================================
@@ -1746,6 +3539,50 @@ mod test {
     use xpath_impl::helpers::subtest_xpath;
     use xpath_impl::helpers::subtest_eval_xpath;
 
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    #[cfg(feature = "no_std")]
+    use std::prelude::*;
+    use dom::*;
+    use xpath_impl::eval::*;
+    use xpath_impl::parser::*;
+
+    // -----------------------------------------------------------------
+    // 引数の変換 (ノードの原子化、数値型へのキャスト)
+    //
+    #[test]
+    fn test_function_argument_coercion() {
+        let xml = compress_spaces(r#"
+<a base="base">
+    <s>Hello</s>
+    <n>-2.5</n>
+    <p>ABC</p>
+</a>
+        "#);
+        subtest_eval_xpath("function_argument_coercion", &xml, &[
+            ( "upper-case(//s)", r#""HELLO""# ),
+            ( "lower-case(//s/text())", r#""hello""# ),
+            ( "contains(//s, 'ell')", "true" ),
+            ( "starts-with(//s, //s)", "true" ),
+            ( "substring(//s, 2, 3)", r#""ell""# ),
+            ( "string-length(//s)", "5" ),
+            ( "normalize-space(//s)", r#""Hello""# ),
+            ( "translate(//s, 'lo', 'LO')", r#""HeLLO""# ),
+            ( "compare(//s, 'Hello')", "0" ),
+            ( "substring-before(//s, 'l')", r#""He""# ),
+            ( "string-to-codepoints(//p)", "(65, 66, 67)" ),
+            ( "abs(//n)", "2.5e0" ),
+            ( "round(//n)", "-2e0" ),
+            ( "round(//n, 0)", "-2e0" ),
+            ( "concat(//s, '-', //p)", r#""Hello-ABC""# ),
+            ( "string-join(//p, '/')", r#""ABC""# ),
+            ( "abs(//s)", "Dynamic Error" ),
+            ( "upper-case(//*)", "Type Error" ),
+            ( "count(//s)", "1" ),
+            ( "name(//s)", r#""s""# ),
+        ]);
+    }
+
     // -----------------------------------------------------------------
     // 2.2 fn:nilled
     //
@@ -1784,19 +3621,133 @@ mod test {
     }
 
     // -----------------------------------------------------------------
-    // 2.4 fn:data
+    // 2.4 fn:data
+    //
+    #[test]
+    fn test_fn_data() {
+        let xml = compress_spaces(r#"
+<a base="base">
+    Data
+</a>
+        "#);
+        subtest_eval_xpath("fn_data", &xml, &[
+            ( r#"data((/a, 37))"#, r#"("Data", 37)"# ),
+            ( r#"data()"#, r#""Data""# ),
+        ]);
+    }
+
+    // -----------------------------------------------------------------
+    // 2.5 fn:base-uri
+    // 2.6 fn:document-uri
+    //
+    #[test]
+    fn test_fn_base_uri() {
+        let xml = compress_spaces(r#"
+<a xml:base="http://example.com/doc/">
+    <b xml:base="sub/x.xml" v="1">
+        <c xml:base="../img/"/>
+    </b>
+    <d xml:base="urn:isbn:0451450523"/>
+</a>
+        "#);
+        subtest_eval_xpath("fn_base_uri", &xml, &[
+            ( "base-uri(/a)", r#""http://example.com/doc/""# ),
+            ( "base-uri(//b)", r#""http://example.com/doc/sub/x.xml""# ),
+            ( "base-uri(//b/@v)", r#""http://example.com/doc/sub/x.xml""# ),
+            ( "base-uri(//c)", r#""http://example.com/doc/img/""# ),
+            ( "base-uri(//d)", r#""urn:isbn:0451450523""# ),
+            ( "//c/base-uri()", r#""http://example.com/doc/img/""# ),
+            ( "base-uri(/)", "()" ),
+            ( "base-uri(())", "()" ),
+            ( "base-uri(1)", "Type Error" ),
+            ( "document-uri(/)", "()" ),
+            ( "document-uri(/a)", "()" ),
+            ( "document-uri(())", "()" ),
+        ]);
+    }
+
+    // -----------------------------------------------------------------
+    // 3.1.1 fn:error
+    //
+    #[test]
+    fn test_fn_error() {
+        use xmlerror::*;
+
+        let doc = new_document("<root><p>1</p></root>").unwrap();
+        let specs = [
+            ( "error()", "err:FOER0000", "Error signalled by fn:error()", None ),
+            ( "error(())", "err:FOER0000", "Error signalled by fn:error()", None ),
+            ( "error('app:E01')", "app:E01", "Error signalled by fn:error()", None ),
+            ( "error('Q{http://e}E02', 'bad')", "Q{http://e}E02", "bad", None ),
+            ( "error((), 'bad', //p)", "err:FOER0000", "bad", Some("<p>") ),
+            ( "if (//p = '1') then error('app:E03', 'p is ' || //p, ()) else 0",
+                    "app:E03", "p is 1", Some("()") ),
+        ];
+        for &(xpath, code, description, value) in specs.iter() {
+            let err = doc.eval_xpath(xpath).err().unwrap();
+            let xml_err = err.downcast_ref::<XmlError>().unwrap();
+            assert_eq!(xml_err.error_type(), &XmlErrorType::DynamicError);
+            let user_error = xml_err.user_error().unwrap();
+            assert_eq!(user_error.code(), code, "xpath: {}", xpath);
+            assert_eq!(user_error.description(), description, "xpath: {}", xpath);
+            assert_eq!(user_error.value().map(|v| v.to_string()),
+                       value.map(String::from), "xpath: {}", xpath);
+        }
+
+        // 組み込みの動的エラーは、user_error() を持たない。
+        let err = doc.eval_xpath("exactly-one(())").err().unwrap();
+        assert!(err.downcast_ref::<XmlError>().unwrap().user_error().is_none());
+    }
+
+    // -----------------------------------------------------------------
+    // 14.3 基数の検査: 誤りコードと、シーケンスの長さを示す。
     //
     #[test]
-    fn test_fn_data() {
+    fn test_cardinality_error_codes() {
+        let doc = new_document("<root><p/><p/><p/></root>").unwrap();
+        let specs = [
+            ( "zero-or-one(//p)", "3 items [err:FORG0003]" ),
+            ( "one-or-more(//q)", "no items [err:FORG0004]" ),
+            ( "exactly-one(//q)", "0 items [err:FORG0005]" ),
+            ( "exactly-one(//p)", "3 items [err:FORG0005]" ),
+        ];
+        for &(xpath, expected) in specs.iter() {
+            let err = doc.eval_xpath(xpath).err().unwrap().to_string();
+            assert!(err.ends_with(expected), "xpath: {}: {}", xpath, err);
+        }
+    }
+
+    // -----------------------------------------------------------------
+    // 3.2 fn:trace
+    //
+    #[test]
+    fn test_fn_trace() {
         let xml = compress_spaces(r#"
 <a base="base">
-    Data
+    <b>x</b>
+    <b>y</b>
 </a>
         "#);
-        subtest_eval_xpath("fn_data", &xml, &[
-            ( r#"data((/a, 37))"#, r#"("Data", 37)"# ),
-            ( r#"data()"#, r#""Data""# ),
+        subtest_eval_xpath("fn_trace", &xml, &[
+            ( r#"trace(1 + 2, 'sum') * 2"#, r#"6"# ),
+            ( r#"count(trace(b))"#, r#"2"# ),
+            ( r#"trace((), 'empty')"#, r#"()"# ),
+            ( r#"trace(1, ('a', 'b'))"#, r#"Type Error"# ),
         ]);
+
+        let log = Rc::new(RefCell::new(vec!{}));
+        let log_clone = Rc::clone(&log);
+        let listener: TraceListener = Rc::new(move |label: &str, value: &str| {
+            log_clone.borrow_mut().push(format!("[{}] {}", label, value));
+        });
+        let doc = new_document(&xml).unwrap();
+        let xnode = compile_xpath(&String::from(
+                "for $b in //b return trace(string($b), 'b') || trace(1)")).unwrap();
+        let result = match_xpath_with_trace_listener(&doc, &xnode,
+                        Some(listener)).unwrap();
+        assert_eq!(result.to_string(), r#"("x1", "y1")"#);
+        assert_eq!(log.borrow().join("; "),
+            r#"[b] "x"; [] 1; [b] "y"; [] 1"#);
     }
 
     // -----------------------------------------------------------------
@@ -1872,6 +3823,138 @@ mod test {
         ]);
     }
 
+    // -----------------------------------------------------------------
+    // 4.4.4 fn:round#2
+    //
+    #[test]
+    fn test_fn_round_precision() {
+        let xml = compress_spaces(r#"
+<a base="base">
+</a>
+        "#);
+        subtest_eval_xpath("fn_round_precision", &xml, &[
+            ( "round(1.125, 2)", "1.13" ),
+            ( "round(-1.125, 2)", "-1.12" ),
+            ( "round(2.675, 2)", "2.68" ),
+            ( "round(8452, -2)", "8500" ),
+            ( "round(-8450, -2)", "-8400" ),
+            ( "round(3.1415e0, 2)", "3.14e0" ),
+            ( "round(35.425e0, 2)", "3.542e1" ),
+                            // 35.425e0 は 2進表現で 35.42499... のため。
+            ( "round(12.5, 0)", "13.0" ),
+            ( "round(1234.5, -2)", "1200.0" ),
+            ( "round(37, 2)", "37" ),
+            ( "round((), 2)", "()" ),
+            ( "round(-0.001e0, 2)", "-0e0" ),
+            ( "round('1.5', 0)", "Type Error" ),
+        ]);
+    }
+
+    // -----------------------------------------------------------------
+    // 4.4.5 fn:round-half-to-even
+    //
+    #[test]
+    fn test_fn_round_half_to_even() {
+        let xml = compress_spaces(r#"
+<a base="base">
+</a>
+        "#);
+        subtest_eval_xpath("fn_round_half_to_even", &xml, &[
+            ( "round-half-to-even(0.5)", "0.0" ),
+            ( "round-half-to-even(1.5)", "2.0" ),
+            ( "round-half-to-even(2.5)", "2.0" ),
+            ( "round-half-to-even(-2.5)", "-2.0" ),
+            ( "round-half-to-even(2.5e0)", "2e0" ),
+            ( "round-half-to-even(3.567812e+3, 2)", "3.56781e3" ),
+            ( "round-half-to-even(4.7564e-3, 2)", "0e0" ),
+            ( "round-half-to-even(35612.25, -2)", "35600.0" ),
+            ( "round-half-to-even(2.675, 2)", "2.68" ),
+            ( "round-half-to-even(2.665, 2)", "2.66" ),
+            ( "round-half-to-even(150, -2)", "200" ),
+            ( "round-half-to-even(250, -2)", "200" ),
+            ( "round-half-to-even(-250, -2)", "-200" ),
+            ( "round-half-to-even(7, -20)", "0" ),
+            ( "round-half-to-even(-0.5e0)", "-0e0" ),
+            ( "round-half-to-even(1 div 0e0, 2)", "+Infinity" ),
+            ( "round-half-to-even(())", "()" ),
+        ]);
+    }
+
+    // -----------------------------------------------------------------
+    // 4.8 math:*
+    //
+    #[test]
+    fn test_math_functions() {
+        let xml = compress_spaces(r#"
+<root>
+    <v>16</v>
+    <w>abc</w>
+</root>
+        "#);
+        subtest_eval_xpath("math_functions", &xml, &[
+            ( "math:pi()", "3.141592653589793e0" ),
+            ( "math:exp(0)", "1e0" ),
+            ( "math:exp(())", "()" ),
+            ( "math:exp10(2)", "1e2" ),
+            ( "math:log(1)", "0e0" ),
+            ( "math:log(0)", "-Infinity" ),
+            ( "math:log(-1)", "NaN" ),
+            ( "math:log10(1000)", "3e0" ),
+            ( "math:pow(2, 10)", "1.024e3" ),
+            ( "math:pow(-1, 1 div 0e0)", "1e0" ),
+            ( "math:pow((), 2)", "()" ),
+            ( "math:sqrt(2.25)", "1.5e0" ),
+            ( "math:sqrt(-0e0)", "-0e0" ),
+            ( "math:sqrt(/root/v)", "4e0" ),
+            ( "math:sqrt(/root/w)", "Dynamic Error" ),
+            ( "math:sqrt('4')", "Type Error" ),
+            ( "math:sin(0)", "0e0" ),
+            ( "math:cos(0)", "1e0" ),
+            ( "math:tan(0)", "0e0" ),
+            ( "math:asin(1) * 2 = math:pi()", "true" ),
+            ( "math:acos(2)", "NaN" ),
+            ( "math:atan(1) * 4 = math:pi()", "true" ),
+            ( "math:atan2(1, -1) * 4 = 3 * math:pi()", "true" ),
+            ( "math:atan2((), 1)", "Type Error" ),
+            ( "function-name(math:sqrt#1)", r#""math:sqrt""# ),
+        ]);
+    }
+
+    // -----------------------------------------------------------------
+    // 4.9.1 fn:random-number-generator
+    //
+    #[test]
+    fn test_fn_random_number_generator() {
+        let xml = compress_spaces(r#"
+<root>
+    <a>1</a><a>2</a><a>3</a><a>4</a><a>5</a>
+</root>
+        "#);
+        subtest_eval_xpath("random_number_generator", &xml, &[
+            ( "random-number-generator(42)?number", "7.415648787718233e-1" ),
+            ( "random-number-generator(42)?next()?number", "1.599103928769201e-1" ),
+            ( "random-number-generator(42)?number = random-number-generator(42)?number", "true" ),
+            ( "random-number-generator(42)?number = random-number-generator(43)?number", "false" ),
+            ( "random-number-generator('x')?number = random-number-generator('x')?number", "true" ),
+            ( "let $r := random-number-generator() return $r?number ge 0 and $r?number lt 1", "true" ),
+            ( "random-number-generator()?number = random-number-generator()?number", "true" ),
+            ( "random-number-generator(())?number = random-number-generator()?number", "true" ),
+            ( "random-number-generator()?next()?number = random-number-generator(())?next()?number", "true" ),
+            ( "string-join(am:random-permute(1 to 20, ()), ',') = string-join(am:random-permute(1 to 20, ()), ',')", "true" ),
+            ( "let $f := function() { random-number-generator()?number } return $f() = random-number-generator()?number", "true" ),
+            ( "every $n in for-each(1 to 50, function($s) { random-number-generator($s)?number }) satisfies ($n ge 0 and $n lt 1)", "true" ),
+            ( "function-arity(random-number-generator(1)?next)", "0" ),
+            ( "function-arity(random-number-generator(1)?permute)", "1" ),
+            ( "random-number-generator(42)?permute(1 to 10)", "(3, 4, 10, 8, 6, 9, 7, 5, 1, 2)" ),
+            ( "random-number-generator(42)?permute(())", "()" ),
+            ( "count(random-number-generator(1)?permute(/root/a))", "5" ),
+            ( "sum(random-number-generator(1)?permute(/root/a))", "1.5e1" ),
+            ( "count(distinct-values(random-number-generator(1)?permute(/root/a)))", "5" ),
+            ( "am:random-permute(1 to 5, 7)", "(5, 2, 4, 1, 3)" ),
+            ( "random-number-generator((1, 2))", "Type Error" ),
+        ]);
+    }
+
     // -----------------------------------------------------------------
     // 5.2.1 fn:codepoints-to-string
     //
@@ -1918,6 +4001,24 @@ mod test {
         ]);
     }
 
+    // -----------------------------------------------------------------
+    // 5.3.6 fn:compare#3
+    //
+    #[test]
+    fn test_fn_compare_collation() {
+        let xml = compress_spaces(r#"
+<root base="base">
+</root>
+        "#);
+        subtest_eval_xpath("fn_compare_collation", &xml, &[
+            ( r#"compare('abc', 'ABC', 'http://www.w3.org/2005/xpath-functions/collation/codepoint')"#, "1" ),
+            ( r#"compare('abc', 'ABC', 'http://www.w3.org/2005/xpath-functions/collation/html-ascii-case-insensitive')"#, "0" ),
+            ( r#"compare('abc', 'ABD', 'http://www.w3.org/2005/xpath-functions/collation/html-ascii-case-insensitive')"#, "-1" ),
+            ( r#"compare((), 'ABC', 'http://www.w3.org/2005/xpath-functions/collation/codepoint')"#, "()" ),
+            ( r#"compare('abc', 'ABC', 'http://example.com/unknown')"#, "Dynamic Error" ),
+        ]);
+    }
+
     // -----------------------------------------------------------------
     // 5.3.7 fn:codepoint-equal
     //
@@ -1996,6 +4097,8 @@ mod test {
 
             ( r#"substring("ABCDE", -42, 1 div 0e0)"#, r#""ABCDE""# ),
             ( r#"substring("ABCDE", -1 div 0e0, 1 div 0e0)"#, r#""""# ),
+            ( r#"substring("ABCDE", -1 div 0e0)"#, r#""ABCDE""# ),
+            ( r#"substring("ABCDE", 1 div 0e0)"#, r#""""# ),
 
             ( r#"substring("あいうえお", 2, 3)"#, r#""いうえ""# ),
             ( r#"substring("あいうえお", 2)"#, r#""いうえお""# ),
@@ -2030,7 +4133,43 @@ mod test {
         "#);
         subtest_eval_xpath("fn_normalize_space", &xml, &[
             ( r#"normalize-space('')"#, r#""""# ),
-            ( r#"normalize-space(' abc  def ')"#, r#""abcdef""# ),
+            ( r#"normalize-space(' abc  def ')"#, r#""abc def""# ),
+            ( "normalize-space('\ta\r\n\n b ')", r#""a b""# ),
+            ( "normalize-space('a\u{3000}b')", "\"a\u{3000}b\"" ),
+        ]);
+    }
+
+    // -----------------------------------------------------------------
+    // 5.4.6 fn:normalize-unicode
+    //
+    #[test]
+    fn test_fn_normalize_unicode() {
+        let xml = compress_spaces(r#"
+<a base="base">
+</a>
+        "#);
+        subtest_eval_xpath("fn_normalize_unicode", &xml, &[
+            ( r#"normalize-unicode(())"#, r#""""# ),
+            ( r#"normalize-unicode('abc')"#, r#""abc""# ),
+            ( r#"normalize-unicode('abc', ' nfkd ')"#, r#""abc""# ),
+            ( "normalize-unicode('e\u{301}', '')", "\"e\u{301}\"" ),
+            ( r#"normalize-unicode('abc', 'FULLY-NORMALIZED')"#, "Dynamic Error" ),
+        ]);
+    }
+
+    #[cfg(feature = "unicode-normalization")]
+    #[test]
+    fn test_fn_normalize_unicode_forms() {
+        let xml = compress_spaces(r#"
+<a base="base">
+</a>
+        "#);
+        subtest_eval_xpath("fn_normalize_unicode_forms", &xml, &[
+            ( "normalize-unicode('e\u{301}')", "\"\u{e9}\"" ),
+            ( "normalize-unicode('\u{e9}', 'NFD')", "\"e\u{301}\"" ),
+            ( "normalize-unicode('\u{fb01}', 'NFKC')", r#""fi""# ),
+            ( "normalize-unicode('\u{2460}', 'nfkd')", r#""1""# ),
+            ( "string-length(normalize-unicode('\u{e9}', 'NFD'))", "2" ),
         ]);
     }
 
@@ -2046,6 +4185,7 @@ mod test {
         subtest_eval_xpath("fn_upper_case", &xml, &[
             ( r#"upper-case('AbCdE')"#, r#""ABCDE""# ),
             ( r#"upper-case('ΣЯσя')"#, r#""ΣЯΣЯ""# ),
+            ( r#"upper-case('straße')"#, r#""STRASSE""# ),
         ]);
     }
 
@@ -2163,6 +4303,28 @@ mod test {
         ]);
     }
 
+    // -----------------------------------------------------------------
+    // 6.1 fn:resolve-uri
+    //
+    #[test]
+    fn test_fn_resolve_uri() {
+        let xml = compress_spaces(r#"
+<a base="base">
+</a>
+        "#);
+        subtest_eval_xpath("fn_resolve_uri", &xml, &[
+            ( r#"resolve-uri("c.xml", "http://example.com/a/b.xml")"#, r#""http://example.com/a/c.xml""# ),
+            ( r#"resolve-uri("../c.xml", "http://example.com/a/b/")"#, r#""http://example.com/a/c.xml""# ),
+            ( r##"resolve-uri("#f", "http://example.com/a")"##, r##""http://example.com/a#f""## ),
+            ( r#"resolve-uri("http://other.org/x", "http://example.com/")"#, r#""http://other.org/x""# ),
+            ( r#"resolve-uri((), "http://example.com/")"#, "()" ),
+            ( r#"resolve-uri("c.xml", "a/b")"#, "Dynamic Error" ),
+            ( r#"resolve-uri("http://other.org/x")"#, r#""http://other.org/x""# ),
+            ( r#"resolve-uri("c.xml")"#, "Dynamic Error" ),
+            ( r#"static-base-uri()"#, "()" ),
+        ]);
+    }
+
     // -----------------------------------------------------------------
     // 13.3 fn:namespace-uri
     //
@@ -2196,6 +4358,59 @@ mod test {
         ]);
     }
 
+    // -----------------------------------------------------------------
+    // 9.8.5 fn:parse-ietf-date
+    //
+    #[test]
+    fn test_fn_parse_ietf_date() {
+        let xml = compress_spaces(r#"
+<root>
+    <item date="Wed, 06 Jun 1994 07:29:35 GMT"/>
+    <item date="Wed Jun 06 11:54:45 EST 2013"/>
+    <item date="2013-06-06T16:54:45Z"/>
+    <item date="June 6, 2013"/>
+</root>
+        "#);
+
+        subtest_eval_xpath("fn_parse_ietf_date", &xml, &[
+            ( r#"parse-ietf-date(//item[1]/@date)"#, r#"xs:dateTime("1994-06-06T07:29:35Z")"# ),
+            ( r#"string(parse-ietf-date('Sunday, 06-Nov-94 08:49:37 GMT'))"#, r#""1994-11-06T08:49:37Z""# ),
+            ( r#"parse-ietf-date(())"#, "()" ),
+            ( r#"parse-ietf-date(//item[2]/@date) eq (//item[3]/@date cast as xs:dateTime)"#, "true" ),
+            ( r#"parse-ietf-date(//item[1]/@date) lt parse-ietf-date(//item[2]/@date)"#, "true" ),
+            ( r#"parse-ietf-date(//item[2]/@date) = //item[3]/@date"#, "true" ),
+            ( r#"parse-ietf-date(//item[1]/@date) instance of xs:dateTime"#, "true" ),
+            ( r#"parse-ietf-date(//item[4]/@date)"#, "Dynamic Error" ),
+            ( r#"am:parse-rfc3339('2013-06-06 16:54:45+0000') eq parse-ietf-date(//item[2]/@date)"#, "true" ),
+            ( r#"am:parse-rfc3339('2013/06/06')"#, "Dynamic Error" ),
+            ( r#"'2013-06-06 16:54:45Z' castable as xs:dateTime"#, "false" ),
+        ]);
+    }
+
+    // -----------------------------------------------------------------
+    // am:parse-html
+    //
+    #[test]
+    fn test_am_parse_html() {
+        let xml = compress_spaces(r#"
+<rss base="base">
+    <item><description><![CDATA[<P class=lead>Hello <b>World</b><br>bye<p>second]]></description></item>
+    <item><description>plain text</description></item>
+</rss>
+        "#);
+
+        subtest_eval_xpath("am_parse_html", &xml, &[
+            ( r#"am:parse-html(//item[1]/description)//b/string()"#, r#""World""# ),
+            ( r#"count(am:parse-html(//item[1]/description)/p)"#, "2" ),
+            ( r#"am:parse-html(//item[1]/description)/p[1]/@class/string()"#, r#""lead""# ),
+            ( r#"string(am:parse-html(//item[2]/description))"#, r#""plain text""# ),
+            ( r#"am:parse-html('<ul><li>a<li>b</ul>') instance of document-node()"#, "true" ),
+            ( r#"//item ! count(am:parse-html(description)//*)"#, "(4, 0)" ),
+            ( r#"am:parse-html('<p>x') is am:parse-html('<p>x')"#, "false" ),
+            ( r#"am:parse-html(())"#, "Type Error" ),
+        ]);
+    }
+
     // -----------------------------------------------------------------
     // 14.1 fn:name
     //
@@ -2228,6 +4443,11 @@ mod test {
     <para id="D" xml:lang="EN"/>
     <para id="E" xml:lang="en-us"/>
     <para id="F" />
+    <div id="G" xml:lang="ja">
+        <div xml:lang="en">
+            <para id="H"/>
+        </div>
+    </div>
     <a base="base">
         <sel img="z0" ans="0" />
         <sel img="z1" ans="1" />
@@ -2245,6 +4465,8 @@ mod test {
             ( "count(//para[@id='E'][lang('en')])", "1" ),
             ( "count(//para[@id='F'][lang('en')])", "0" ),
             ( "count(//para[@id='A'][lang('ja')])", "0" ),
+            ( "count(//para[@id='H'][lang('en')])", "1" ),
+            ( "count(//para[@id='H'][lang('ja')])", "0" ),
         ]);
     }
 
@@ -2267,6 +4489,99 @@ mod test {
         ]);
     }
 
+    // -----------------------------------------------------------------
+    // 13.7 fn:has-children
+    //
+    #[test]
+    fn test_fn_has_children() {
+        let xml = compress_spaces(r#"
+<?xml version='1.0' encoding='UTF-8'?>
+<root>
+    <a id="1"><b/></a><a id="2"/><a id="3"><!--c--></a><a id="4">t</a>
+</root>
+        "#);
+        subtest_eval_xpath("fn_has_children", &xml, &[
+            ( "has-children(/)", "true" ),
+            ( "has-children()", "true" ),
+            ( "//a[has-children()]/@id/string()", r#"("1", "3", "4")"# ),
+            ( "//a[not(has-children())]/@id/string()", r#""2""# ),
+            ( "has-children(//a[1]/@id)", "false" ),
+            ( "has-children(())", "false" ),
+            ( "has-children(1)", "Type Error" ),
+        ]);
+    }
+
+    // -----------------------------------------------------------------
+    // fn:siblings (XPath 4.0)
+    //
+    #[test]
+    fn test_fn_siblings() {
+        let xml = compress_spaces(r#"
+<?xml version='1.0' encoding='UTF-8'?>
+<root>
+    <a id="1"/>x<a id="2"/><a id="3"/>
+</root>
+        "#);
+        subtest_eval_xpath("fn_siblings", &xml, &[
+            ( "count(siblings(//a[@id='2']))", "4" ),
+            ( "siblings(//a[@id='3'])/@id/string()", r#"("1", "2", "3")"# ),
+            ( "//a[@id='1']/siblings()[3]/@id/string()", r#""2""# ),
+            ( "siblings(/root)", "<root>" ),
+            ( "siblings(//a[1]/@id)/string()", r#""1""# ),
+            ( "siblings(/)", "(DocumentRoot)" ),
+            ( "siblings(())", "()" ),
+            ( "siblings('a')", "Type Error" ),
+        ]);
+    }
+
+    // -----------------------------------------------------------------
+    // am:child-count, am:child-element-count
+    //
+    #[test]
+    fn test_am_child_count() {
+        let xml = compress_spaces(r#"
+<?xml version='1.0' encoding='UTF-8'?>
+<root>
+    <a><b/>t<c/><!--d--></a><a/>
+</root>
+        "#);
+        subtest_eval_xpath("am_child_count", &xml, &[
+            ( "am:child-count(//a[1])", "4" ),
+            ( "am:child-element-count(//a[1])", "2" ),
+            ( "am:child-count(//a[2])", "0" ),
+            ( "am:child-count(/)", "1" ),
+            ( "am:child-element-count(/)", "1" ),
+            ( "count(//a[am:child-element-count(.) = 0])", "1" ),
+            ( "am:child-count(())", "0" ),
+            ( "am:child-count(1)", "Type Error" ),
+        ]);
+    }
+
+    // -----------------------------------------------------------------
+    // am:grapheme-length
+    //
+    #[test]
+    fn test_am_grapheme_length() {
+        let xml = compress_spaces(r#"
+<root/>
+        "#);
+        subtest_eval_xpath("am_grapheme_length", &xml, &[
+            ( "am:grapheme-length('')", "0" ),
+            ( "am:grapheme-length(())", "0" ),
+            ( "am:grapheme-length('abc')", "3" ),
+            ( "am:grapheme-length('a\r\nb')", "3" ),
+            ( "string-length('a\r\nb')", "4" ),
+        ]);
+        if cfg!(feature = "unicode-segmentation") {
+            subtest_eval_xpath("am_grapheme_length", &xml, &[
+                ( "am:grapheme-length('ka\u{301}')", "2" ),
+                ( "string-length('ka\u{301}')", "3" ),
+                ( "am:grapheme-length('\u{1F468}\u{200D}\u{1F469}\u{200D}\u{1F467}')", "1" ),
+                ( "am:grapheme-length('\u{1F1EF}\u{1F1F5}かな')", "3" ),
+            ]);
+        }
+    }
+
     // -----------------------------------------------------------------
     // 14.1.1 fn:empty
     //
@@ -2397,6 +4712,40 @@ mod test {
             ( "subsequence((1, 2, 3, 4), 2, 2)", "(2, 3)" ),
             ( "subsequence((1, 2, 3, 4), -2, 5)", "(1, 2)" ),
             ( "subsequence((1, 2, 3, 4), -42, 1 div 0e0)", "(1, 2, 3, 4)" ),
+            ( "subsequence((1, 2, 3, 4, 5), 1.5, 2.5)", "(2, 3, 4)" ),
+            ( "subsequence((1, 2, 3, 4, 5), -1.5, 4)", "(1, 2)" ),
+            ( "subsequence((1, 2, 3, 4, 5), 2.5)", "(3, 4, 5)" ),
+            ( "subsequence((1, 2, 3, 4), 0 div 0e0)", "()" ),
+            ( "subsequence((1, 2, 3, 4), 1, 0 div 0e0)", "()" ),
+            ( "subsequence((1, 2, 3, 4), 2, -1)", "()" ),
+            ( "subsequence((1, 2, 3, 4), -1 div 0e0)", "(1, 2, 3, 4)" ),
+            ( "subsequence((1, 2, 3, 4), -1 div 0e0, 1 div 0e0)", "()" ),
+            ( "subsequence((1, 2, 3, 4), 1 div 0e0)", "()" ),
+            ( "subsequence((1, 2, 3, 4), 3, 1e300)", "(3, 4)" ),
+            ( "subsequence((1, 2, 3, 4), -1e300, 1e300)", "()" ),
+            ( "subsequence((1, 2, 3, 4), 5)", "()" ),
+        ]);
+    }
+
+    // -----------------------------------------------------------------
+    // 14.2.1 fn:distinct-values
+    //
+    #[test]
+    fn test_fn_distinct_values() {
+        let xml = compress_spaces(r#"
+<root base="base">
+    <a>x</a><a>y</a><a>x</a>
+</root>
+        "#);
+        subtest_eval_xpath("fn_distinct_values", &xml, &[
+            ( "distinct-values((1, 2.0, 3, 2))", "(1, 2.0, 3)" ),
+            ( "distinct-values(())", "()" ),
+            ( "distinct-values(('a', 'A', 'b', 'a'))", r#"("a", "A", "b")"# ),
+            ( "distinct-values(('a', 'A', 'b', 'a'), 'http://www.w3.org/2005/xpath-functions/collation/html-ascii-case-insensitive')", r#"("a", "b")"# ),
+            ( "distinct-values((1, '1', 1e0))", r#"(1, "1")"# ),
+            ( "distinct-values((0e0 div 0e0, 0e0 div 0e0))", "NaN" ),
+            ( "distinct-values(/root/a)", r#"("x", "y")"# ),
+            ( "distinct-values((1, 2), 'http://example.com/unknown')", "Dynamic Error" ),
         ]);
     }
 
@@ -2413,6 +4762,7 @@ mod test {
             ( "index-of((10, 20, 30, 40), 25)", "()" ),
             ( "index-of((10, 20, 30, 30, 20, 10), 20)", "(2, 5)" ),
             ( "index-of(('a', 'sport', 'and', 'a', 'pastime'), 'a')", "(1, 4)" ),
+            ( "index-of(('a', 'A', 'b'), 'a', 'http://www.w3.org/2005/xpath-functions/collation/html-ascii-case-insensitive')", "(1, 2)" ),
         ]);
     }
 
@@ -2493,8 +4843,14 @@ mod test {
         subtest_eval_xpath("fn_max", &xml, &[
             ( "max(())", "()" ),
             ( "max((3, 4, 5))", "5" ),
+            ( "max((1, 2.5, 3))", "3.0" ),
+            ( "max((1, 3e0, 2.5))", "3e0" ),
+            ( "max((1, 0e0 div 0e0, 3))", "NaN" ),
+            ( "max((1.5, -0e0 div 0e0))", "NaN" ),
             ( r#"max(("a", "b", "c"))"#, r#""c""# ),
             ( r#"max((3, 4, "zero"))"#, "Type Error" ),
+            ( r#"max(("a", "B", "c"))"#, r#""c""# ),
+            ( r#"max(("a", "B"), "http://www.w3.org/2005/xpath-functions/collation/html-ascii-case-insensitive")"#, r#""B""# ),
         ]);
     }
 
@@ -2510,8 +4866,13 @@ mod test {
         subtest_eval_xpath("fn_min", &xml, &[
             ( "min(())", "()" ),
             ( "min((3, 4, 5))", "3" ),
+            ( "min((3, 1, 2.5))", "1.0" ),
+            ( "min((2e0, 3))", "2e0" ),
+            ( "min((1, 0e0 div 0e0, 3))", "NaN" ),
             ( r#"min(("a", "b", "c"))"#, r#""a""# ),
             ( r#"min((3, 4, "zero"))"#, "Type Error" ),
+            ( r#"min(("a", "B"))"#, r#""B""# ),
+            ( r#"min(("a", "B"), "http://www.w3.org/2005/xpath-functions/collation/html-ascii-case-insensitive")"#, r#""a""# ),
         ]);
     }
 
@@ -2534,6 +4895,33 @@ mod test {
         ]);
     }
 
+    // -----------------------------------------------------------------
+    // 14.5.1 fn:id
+    //
+    #[test]
+    fn test_fn_id() {
+        let xml = compress_spaces(r#"
+<root>
+    <a xml:id="x" n="1"/>
+    <b>
+        <a xml:id="y" n="2"/>
+        <a xml:id="x" n="3"/>
+    </b>
+    <a id="z" n="4"/>
+</root>
+        "#);
+        subtest_eval_xpath("fn_id", &xml, &[
+            ( "id('x')/@n/string()", r#""1""# ),
+            ( "id('y x')/@n/string()", r#"("1", "2")"# ),
+            ( "id(('y', 'x', 'x'))/@n/string()", r#"("1", "2")"# ),
+            ( "id('z')", "()" ),
+            ( "id(())", "()" ),
+            ( "id('y', /root/b)/@n/string()", r#""2""# ),
+            ( "/root/b/id(' y ')/@n/string()", r#""2""# ),
+            ( "1 ! id('x')", "Type Error" ),
+        ]);
+    }
+
     // -----------------------------------------------------------------
     // 15.1 fn:position
     //
@@ -2629,6 +5017,168 @@ mod test {
         ]);
     }
 
+    // -----------------------------------------------------------------
+    // 16.1.1 fn:function-lookup
+    //
+    #[test]
+    fn test_fn_function_lookup() {
+        let xml = compress_spaces(r#"
+<root>
+</root>
+        "#);
+        subtest_eval_xpath("fn_function_lookup", &xml, &[
+            ( "function-lookup('fn:concat', 3)('a', 'b', 'c')", r#""abc""# ),
+            ( "function-lookup('upper-case', 1)('a')", r#""A""# ),
+            ( "function-lookup('map:size', 1)(map { 1: 2 })", "1" ),
+            ( "function-lookup('upper-case', 2)", "()" ),
+            ( "function-lookup('no-such-function', 1)", "()" ),
+            ( "function-name(function-lookup('upper-case', 1))", r#""fn:upper-case""# ),
+            ( "function-arity(function-lookup('concat', 4))", "4" ),
+        ]);
+    }
+
+    // -----------------------------------------------------------------
+    // 16.1.2 fn:function-name
+    //
+    #[test]
+    fn test_fn_function_name() {
+        let xml = compress_spaces(r#"
+<root>
+</root>
+        "#);
+        subtest_eval_xpath("fn_function_name", &xml, &[
+            ( "function-name(fn:substring#2)", r#""fn:substring""# ),
+            ( "function-name(array:size#1)", r#""array:size""# ),
+            ( "function-name(function($a) { $a })", "()" ),
+            ( "function-name(substring(?, 2))", "()" ),
+            ( "function-name(map { 1: 2 })", "()" ),
+            ( "function-name(1)", "Type Error" ),
+        ]);
+    }
+
+    // -----------------------------------------------------------------
+    // 16.1.3 fn:function-arity
+    //
+    #[test]
+    fn test_fn_function_arity() {
+        let xml = compress_spaces(r#"
+<root>
+</root>
+        "#);
+        subtest_eval_xpath("fn_function_arity", &xml, &[
+            ( "function-arity(fn:substring#2)", "2" ),
+            ( "function-arity(function($a, $b) { $a })", "2" ),
+            ( "function-arity(function() { 1 })", "0" ),
+            ( "function-arity(substring(?, 2, ?))", "2" ),
+            ( "function-arity([1, 2])", "1" ),
+            ( "function-arity('s')", "Type Error" ),
+        ]);
+    }
+
+    // -----------------------------------------------------------------
+    // 16.2.7 fn:apply
+    //
+    #[test]
+    fn test_fn_apply() {
+        let xml = compress_spaces(r#"
+<root>
+</root>
+        "#);
+        subtest_eval_xpath("fn_apply", &xml, &[
+            ( "apply(concat#3, ['a', 'b', 'c'])", r#""abc""# ),
+            ( "apply(function($a, $b) { $a + $b }, [1, 2])", "3" ),
+            ( "apply(substring(?, 2), ['abc'])", r#""bc""# ),
+            ( "apply(function() { 7 }, [])", "7" ),
+            ( "apply(map { 'k': 'v' }, ['k'])", r#""v""# ),
+            ( "apply([10, 20], [2])", "20" ),
+            ( "apply(sum#1, [(1, 2, 3)])", "6" ),
+            ( "apply(concat#3, ['a', 'b'])", "Dynamic Error" ),
+        ]);
+    }
+
+    // -----------------------------------------------------------------
+    // am:group-by
+    //
+    #[test]
+    fn test_am_group_by() {
+        let xml = compress_spaces(r#"
+<root base="base">
+    <a k="x" v="1"/>
+    <a k="y" v="2"/>
+    <a k="x" v="3"/>
+    <a v="4"/>
+</root>
+        "#);
+        subtest_eval_xpath("am_group_by", &xml, &[
+            ( "map:keys(am:group-by(1 to 7, function($n) { $n mod 3 }))", "(1, 2, 0)" ),
+            ( "am:group-by(1 to 7, function($n) { $n mod 3 })(1)", "(1, 4, 7)" ),
+            ( "map:keys(am:group-by(a, function($a) { $a/@k }))", r#"("x", "y")"# ),
+            ( "am:group-by(a, function($a) { $a/@k })('x') ! string(@v)", r#"("1", "3")"# ),
+            ( "map:size(am:group-by((), function($n) { $n }))", "0" ),
+            ( "am:group-by(1 to 3, function($n) { ($n, $n) })", "Type Error" ),
+        ]);
+    }
+
+    // -----------------------------------------------------------------
+    // am:group-adjacent
+    //
+    #[test]
+    fn test_am_group_adjacent() {
+        let xml = compress_spaces(r#"
+<root>
+</root>
+        "#);
+        subtest_eval_xpath("am_group_adjacent", &xml, &[
+            ( "array:size(am:group-adjacent((1, 1, 2, 1, 3, 3), function($n) { $n }))", "4" ),
+            ( "am:group-adjacent((1, 1, 2, 1, 3, 3), function($n) { $n })(1)", "(1, 1)" ),
+            ( "am:group-adjacent((1, 1, 2, 1, 3, 3), function($n) { $n })(4)", "(3, 3)" ),
+            ( "am:group-adjacent(('a', 'ab', 'b', 'bc'), function($s) { substring($s, 1, 1) })(2)", r#"("b", "bc")"# ),
+            ( "array:size(am:group-adjacent((), function($n) { $n }))", "0" ),
+        ]);
+    }
+
+    // -----------------------------------------------------------------
+    // am:put-in
+    //
+    #[test]
+    fn test_am_put_in() {
+        let xml = compress_spaces(r#"
+<root>
+</root>
+        "#);
+        subtest_eval_xpath("am_put_in", &xml, &[
+            ( "am:put-in(map{'a': map{'b': 1}}, ['a', 'b'], 2)?a?b", "2" ),
+            ( "am:put-in(map{'a': map{'b': 1, 'c': 3}}, ['a', 'b'], 2)?a?c", "3" ),
+            ( "am:put-in(map{'a': 1}, ['x', 'y'], 5)?x?y", "5" ),
+            ( "map:keys(am:put-in(map{'a': 1}, ['x', 'y'], 5))", r#"("a", "x")"# ),
+            ( "am:put-in(map{'a': [1, [2, 3]]}, ['a', 2, 1], 'z')?a?2?1", r#""z""# ),
+            ( "am:put-in(map{'a': [1, [2, 3]]}, ['a', 2, 1], 'z')?a?2?2", "3" ),
+            ( "let $m := map{'a': map{'b': 1}} return (am:put-in($m, ['a', 'b'], 2), $m)[2]?a?b", "1" ),
+            ( "am:put-in([1, 2], [3], 0)", "Dynamic Error" ),
+            ( "am:put-in(map{'a': 1}, ['a', 'b'], 0)", "Type Error" ),
+            ( "am:put-in(1, [], (4, 5))", "(4, 5)" ),
+        ]);
+    }
+
+    // -----------------------------------------------------------------
+    // am:update
+    //
+    #[test]
+    fn test_am_update() {
+        let xml = compress_spaces(r#"
+<root>
+</root>
+        "#);
+        subtest_eval_xpath("am_update", &xml, &[
+            ( "am:update(map{'n': 1}, 'n', function($v) { $v + 1 })?n", "2" ),
+            ( "am:update(map{'n': 1}, 'm', function($v) { count($v) })?m", "0" ),
+            ( "am:update([1, 2, 3], 2, function($v) { $v * 10 })?2", "20" ),
+            ( "am:update([1, 2, 3], 2, function($v) { $v * 10 })?3", "3" ),
+            ( "am:update([1, 2, 3], 4, function($v) { $v })", "Dynamic Error" ),
+            ( "am:update(1, 1, function($v) { $v })", "Type Error" ),
+        ]);
+    }
+
     // -----------------------------------------------------------------
     // 17.1.3 map:size
     //
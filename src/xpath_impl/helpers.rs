@@ -5,6 +5,9 @@
 // Copyright (C) 2018 KOYAMA Hiro <tac@amris.co.jp>
 //
 
+#[cfg(feature = "no_std")]
+use std::prelude::*;
+
 use dom::new_document;
 use xpath_impl::parser::compile_xpath;
 
@@ -7,8 +7,11 @@
 
 use std::collections::HashMap;
 use std::error::Error;
+#[cfg(feature = "no_std")]
+use std::prelude::*;
 
 use xmlerror::*;
+use xpath_impl::stack_guard::*;
 
 // =====================================================================
 //
@@ -127,12 +130,16 @@ pub enum TType {
 pub struct Token {
     t_type: TType,
     name: String,
+    offset: usize,          // XPath文字列中の開始位置 (文字単位)
+    length: usize,          // 文字数
 }
 
 fn new_token(t_type: TType, name: &str) -> Token {
     return Token {
         t_type: t_type,
         name: String::from(name),
+        offset: 0,
+        length: 0,
     };
 }
 
@@ -143,6 +150,32 @@ impl Token {
     pub fn get_name(&self) -> &str {
         return self.name.as_str();
     }
+    // 空白をはさまずに next が続いているか否か。
+    pub fn is_adjacent_to(&self, next: &Token) -> bool {
+        return self.offset + self.length == next.offset;
+    }
+}
+
+// ---------------------------------------------------------------------
+// 構文エラーの「期待したトークン」として示す、トークン種の表記。
+//
+pub fn ttype_symbol(t_type: &TType) -> String {
+    let s = match *t_type {
+        TType::EOF          => "end of xpath",
+        TType::Name         => "name",
+        TType::ColonColon   => "::",
+        TType::Colon        => ":",
+        TType::Bind         => ":=",
+        TType::Comma        => ",",
+        TType::LeftBracket  => "[",
+        TType::RightBracket => "]",
+        TType::LeftParen    => "(",
+        TType::RightParen   => ")",
+        TType::LeftCurly    => "{",
+        TType::RightCurly   => "}",
+        _ => return format!("{:?}", t_type),
+    };
+    return String::from(s);
 }
 
 // =====================================================================
@@ -154,6 +187,8 @@ pub struct Lexer {
     tokens: Vec<Token>,
     index: usize,
     mark_index: usize,
+    tok_start: usize,       // 切り出し中のトークンの開始位置
+    stack_guard: StackGuard,    // 構文解析の入れ子によるスタック消費量の監視
 }
 
 // =====================================================================
@@ -173,6 +208,13 @@ impl Lexer {
         return s;
     }
 
+    // -----------------------------------------------------------------
+    // 構文解析の入れ子が深すぎて、スタックを使い切りそうか否か。
+    //
+    pub fn stack_exhausted(&self) -> bool {
+        return self.stack_guard.exhausted();
+    }
+
     // -----------------------------------------------------------------
     //
     pub fn next_token(&self) -> Token {
@@ -212,12 +254,19 @@ impl Lexer {
     // -----------------------------------------------------------------
     //
     pub fn around_tokens(&self) -> String {
-        let min_index = if self.index <= 3 { 1 } else { self.index - 3 };
-        let max_index = (self.tokens.len() - 1).min(self.index + 3);
+        return self.tokens_around(self.index - 1);
+    }
+
+    // -----------------------------------------------------------------
+    // index 番目のトークンを ≪≫ で囲み、前後のトークンも並べた文字列。
+    //
+    fn tokens_around(&self, index: usize) -> String {
+        let min_index = if index <= 2 { 1 } else { index - 2 };
+        let max_index = (self.tokens.len() - 1).min(index + 4).max(index + 1);
 
         let mut s = String::new();
         for i in min_index .. max_index {
-            if i == self.index - 1 {
+            if i == index {
                 s += &format!("≪{}≫", self.tokens[i].name);
             } else {
                 s += &format!(" {} ", self.tokens[i].name);
@@ -226,12 +275,112 @@ impl Lexer {
         return s;
     }
 
+    // -----------------------------------------------------------------
+    // 括弧 ( [ { の外側 (入れ子の深さ0) に現れる、t_type 型のトークンの
+    // 位置 (offset, length) を列挙する。
+    //
+    #[cfg(not(feature = "no_std"))]
+    pub fn top_level_token_spans(&self, t_type: TType) -> Vec<(usize, usize)> {
+        let mut spans: Vec<(usize, usize)> = vec!{};
+        let mut depth: usize = 0;
+        for tok in self.tokens.iter() {
+            match tok.t_type {
+                TType::LeftParen | TType::LeftBracket | TType::LeftCurly => {
+                    depth += 1;
+                },
+                TType::RightParen | TType::RightBracket | TType::RightCurly => {
+                    if 0 < depth {
+                        depth -= 1;
+                    }
+                },
+                _ => {
+                    if depth == 0 && tok.t_type == t_type {
+                        spans.push((tok.offset, tok.length));
+                    }
+                },
+            }
+        }
+        return spans;
+    }
+
+    // -----------------------------------------------------------------
+    // 全トークン (番兵のEOFを除く) の種類と位置 (offset, length)。
+    //
+    pub fn token_spans(&self) -> Vec<(TType, usize, usize)> {
+        let mut spans: Vec<(TType, usize, usize)> = vec!{};
+        for tok in self.tokens.iter() {
+            if tok.t_type != TType::EOF {
+                spans.push((tok.t_type.clone(), tok.offset, tok.length));
+            }
+        }
+        return spans;
+    }
+
+    // -----------------------------------------------------------------
+    // 次のトークンの位置で検出した構文エラー。
+    // expectedの各トークンを期待していたのに、別のトークンが現れた。
+    // ja: 日本語の文言 (around_tokens() を含めた形)。
+    //
+    pub fn unexpected_token(&self, expected: &[&str], ja: &str) -> Box<XmlError> {
+        let tok = self.next_token();
+        let found = if tok.t_type == TType::EOF {
+                String::from("end of xpath")
+            } else {
+                format!("\"{}\"", tok.name)
+            };
+        let expected_list: Vec<String> =
+                expected.iter().map(|s| format!("\"{}\"", s)).collect();
+        let en = format!("expected {}, found {}",
+                expected_list.join(" or "), found);
+        return self.syntax_error(expected, &en, ja);
+    }
+
+    // -----------------------------------------------------------------
+    // 次のトークンの位置で検出した構文エラー。
+    // en: 英語の文言 (位置や前後のトークンはここで補う)。
+    // ja: 日本語の文言 (around_tokens() を含めた形)。
+    //
+    pub fn syntax_error(&self, expected: &[&str], en: &str, ja: &str) -> Box<XmlError> {
+        let tok = self.next_token();
+        return self.syntax_error_at(&tok, expected, en, ja);
+    }
+
+    // -----------------------------------------------------------------
+    // トークンtokの位置で検出した構文エラー。
+    // 前後のトークンは、現在位置ではなく tok の位置を中心に示す。
+    //
+    pub fn syntax_error_at(&self, tok: &Token,
+                expected: &[&str], en: &str, ja: &str) -> Box<XmlError> {
+        let index = match self.tokens.iter().skip(1)
+                        .position(|t| tok.offset <= t.offset) {
+            Some(i) => i + 1,
+            None => self.tokens.len() - 1,
+        };
+        let en = format!("{} at offset {}: {}",
+                en, tok.offset, self.tokens_around(index));
+        return xpath_syntax_error_at(tok.offset, tok.length, expected, &en, ja);
+    }
+
+    // -----------------------------------------------------------------
+    // 字句の切り出し中に検出した構文エラー。
+    // 位置は、切り出し中のトークンの開始位置から現在位置まで。
+    //
+    fn scan_error(&self, en: &str, ja: &str) -> Box<XmlError> {
+        let end = self.ch_index.min(self.char_vec.len());
+        let offset = self.tok_start.min(end);
+        let en = format!("{} at offset {}", en, offset);
+        return xpath_syntax_error_at(offset, end - offset, &[], &en, ja);
+    }
+
     // -----------------------------------------------------------------
     //
     fn push_token(&mut self, ttype: TType, name: &str) {
+        let offset = if self.tokens.len() == 0 { 0 } else { self.char_vec.len() };
         self.tokens.push(Token{
             t_type: ttype,
             name: String::from(name),
+            offset: offset,
+            length: 0,
         });
     }
 
@@ -244,6 +393,8 @@ impl Lexer {
             tokens: vec!{},
             index: 1,
             mark_index: 1,
+            tok_start: 0,
+            stack_guard: new_stack_guard(),
         };
 
         // -------------------------------------------------------------
@@ -254,7 +405,10 @@ impl Lexer {
         lexer.push_token(TType::EOF, "");
         loop {
             lexer.skip_spaces();
-            let tok = lexer.get_tok()?;
+            lexer.tok_start = lexer.ch_index;
+            let mut tok = lexer.get_tok()?;
+            tok.offset = lexer.tok_start;
+            tok.length = lexer.ch_index - lexer.tok_start;
             if tok.t_type == TType::EOF {
                 break;
             }
@@ -386,8 +540,9 @@ impl Lexer {
             }
 
         } else {
-            return Err(xpath_syntax_error!(
-                    "XPathを構成する字句として認識できない文字: {}", ch1));
+            return Err(self.scan_error(
+                    &format!("unexpected character \"{}\"", ch1),
+                    &format!("XPathを構成する字句として認識できない文字: {}", ch1)));
         }
     }
 
@@ -495,6 +650,8 @@ impl Lexer {
                     if self.tokens[i].name.as_str() == *str1 &&
                        self.tokens[i+1].name.as_str() == *str2 {
                         self.tokens[i].t_type = t_type.clone();
+                        self.tokens[i].length = self.tokens[i+1].offset +
+                                self.tokens[i+1].length - self.tokens[i].offset;
                         self.tokens[i+1].t_type = TType::Nop;
                     }
                 }
@@ -682,7 +839,8 @@ impl Lexer {
             } else if is_digit(ch1) {
                 self.unread_rune();
             } else {
-                return Err(xpath_syntax_error!(
+                return Err(self.scan_error(
+                        "digits expected after exponent [eE]",
                         "指数を表す [eE] の後には数字が必要。"));
             }
             numeric_after_e.push_str(&self.fetch_digits());
@@ -722,7 +880,9 @@ impl Lexer {
         loop {
             let ch1 = self.read_rune();
             if is_eof(ch1) {
-                return Err(xpath_syntax_error!("Unexpected EOF while scanning string literal."));
+                return Err(self.scan_error(
+                        "unexpected end of xpath in string literal",
+                        "文字列リテラルの途中でXPathが終わっている。"));
             } else if ch1 == delim {
                 let ch2 = self.read_rune();
                 if ch2 == delim {
@@ -746,7 +906,9 @@ impl Lexer {
         loop {
             let ch1 = self.read_rune();
             if is_eof(ch1) {
-                return Err(xpath_syntax_error!("Unexpected EOF while scanning."));
+                return Err(self.scan_error(
+                        "unexpected end of xpath in braced URI literal",
+                        "URIリテラルの途中でXPathが終わっている。"));
             } else if ch1 == delim {
                 literal.push(ch1);
                 return Ok(literal);
@@ -766,7 +928,9 @@ impl Lexer {
         while 0 < nest_level {
             let ch1 = self.read_rune();
             if is_eof(ch1) {
-                return Err(xpath_syntax_error!("Unexpected EOF while scanning comment."));
+                return Err(self.scan_error(
+                        "unexpected end of xpath in comment",
+                        "註釈の途中でXPathが終わっている。"));
             } else if ch1 == '(' {
                 let ch2 = self.read_rune();
                 if ch2 == ':' {
@@ -6,7 +6,10 @@
 //
 
 use std::cmp::Ordering;
+use std::collections::HashSet;
 use std::error::Error;
+#[cfg(feature = "no_std")]
+use std::prelude::*;
 
 use dom::*;
 use xpath_impl::eval::*;
@@ -89,6 +92,18 @@ pub fn op_numeric_greater_than(args: &Vec<&XSequence>) -> Result<XSequence, Box<
     return op_numeric_comparison(args, xitem_numeric_greater_than);
 }
 
+// ---------------------------------------------------------------------
+// 値比較 le、ge のための、op:numeric-less-than (greater-than) と
+// op:numeric-equal の論理和。
+//
+pub fn op_numeric_less_equal(args: &Vec<&XSequence>) -> Result<XSequence, Box<Error>> {
+    return op_numeric_comparison(args, xitem_numeric_less_equal);
+}
+
+pub fn op_numeric_greater_equal(args: &Vec<&XSequence>) -> Result<XSequence, Box<Error>> {
+    return op_numeric_comparison(args, xitem_numeric_greater_equal);
+}
+
 // ---------------------------------------------------------------------
 //
 fn op_numeric_comparison<F>(args: &Vec<&XSequence>, mut func_op: F) -> Result<XSequence, Box<Error>>
@@ -207,40 +222,98 @@ pub fn op_concatenate(args: &Vec<XSequence>) -> Result<XSequence, Box<Error>> {
 // Equals, Union, Intersection and Except
 //   (XPath 3.1 では演算子の項に載っていない)
 //
+// union は、両辺が文書順に整列済み (軸の評価結果など) であれば、
+// 併合するだけなので O(n+m) で済む。
+// intersect、except は、右辺のノードの集合 (HashSet) を作って左辺の
+// 各ノードを判定するので、O(n+m) で済む。結果は左辺の順になるので、
+// 左辺が整列済みでなければ整列する。
+//
 pub fn op_union(args: &Vec<XSequence>, eval_env: &EvalEnv) -> Result<XSequence, Box<Error>> {
-    let mut node_array: Vec<NodePtr> = vec!{};
-    for n in args[0].to_nodeset().iter() {          // lhs
-        node_array.push(n.rc_clone());
-    }
-    for n in args[1].to_nodeset().iter() {          // rhs
-        node_array.push(n.rc_clone());
+    let lhs = args[0].to_nodeset();
+    let rhs = args[1].to_nodeset();
+    if is_in_doc_order(&args[0], &lhs, eval_env) &&
+       is_in_doc_order(&args[1], &rhs, eval_env) {
+        let node_array = merge_doc_ordered(&lhs, &rhs, eval_env);
+        return Ok(new_xsequence_in_doc_order(&node_array));
     }
+
+    let mut node_array = lhs;
+    node_array.extend(rhs);
     eval_env.sort_by_doc_order(&mut node_array);
-    return Ok(new_xsequence_from_node_array(&node_array));
+    return Ok(new_xsequence_in_doc_order(&node_array));
 }
 
-pub fn op_intersect(args: &Vec<XSequence>, _eval_env: &EvalEnv) -> Result<XSequence, Box<Error>> {
+pub fn op_intersect(args: &Vec<XSequence>, eval_env: &EvalEnv) -> Result<XSequence, Box<Error>> {
+    let rhs: HashSet<NodePtr> = args[1].to_nodeset().into_iter().collect();
+    let lhs = args[0].to_nodeset();
     let mut node_array: Vec<NodePtr> = vec!{};
-    let rhs = args[1].to_nodeset();
-    for n in args[0].to_nodeset().iter() {          // lhs
-        if rhs.contains(&n) {
+    for n in lhs.iter() {
+        if rhs.contains(n) {
             node_array.push(n.rc_clone());
         }
     }
-    // eval_env.sort_by_doc_order(&mut node_array);
-    return Ok(new_xsequence_from_node_array(&node_array));
+    if ! is_in_doc_order(&args[0], &lhs, eval_env) {
+        eval_env.sort_by_doc_order(&mut node_array);
+    }
+    return Ok(new_xsequence_in_doc_order(&node_array));
 }
 
-pub fn op_except(args: &Vec<XSequence>, _eval_env: &EvalEnv) -> Result<XSequence, Box<Error>> {
+pub fn op_except(args: &Vec<XSequence>, eval_env: &EvalEnv) -> Result<XSequence, Box<Error>> {
+    let rhs: HashSet<NodePtr> = args[1].to_nodeset().into_iter().collect();
+    let lhs = args[0].to_nodeset();
     let mut node_array: Vec<NodePtr> = vec!{};
-    let rhs = args[1].to_nodeset();
-    for n in args[0].to_nodeset().iter() {          // lhs
-        if ! rhs.contains(&n) {
+    for n in lhs.iter() {
+        if ! rhs.contains(n) {
             node_array.push(n.rc_clone());
         }
     }
-    // eval_env.sort_by_doc_order(&mut node_array);
-    return Ok(new_xsequence_from_node_array(&node_array));
+    if ! is_in_doc_order(&args[0], &lhs, eval_env) {
+        eval_env.sort_by_doc_order(&mut node_array);
+    }
+    return Ok(new_xsequence_in_doc_order(&node_array));
+}
+
+// ---------------------------------------------------------------------
+// シーケンス (のノード nodes) が文書順に整列、重複排除済みか否か。
+// 整列済みと記録されていなければ、隣り合うノードを比較して確かめる。
+//
+fn is_in_doc_order(xseq: &XSequence, nodes: &Vec<NodePtr>, eval_env: &EvalEnv) -> bool {
+    if xseq.is_doc_ordered() {
+        return true;
+    }
+    return nodes.len() == xseq.len() &&
+           nodes.windows(2).all(|w|
+                eval_env.compare_by_doc_order(&w[0], &w[1]) == Ordering::Less);
+}
+
+// ---------------------------------------------------------------------
+// 文書順に整列済みの2つのノード列を、重複を除きながら併合する。
+//
+fn merge_doc_ordered(lhs: &Vec<NodePtr>, rhs: &Vec<NodePtr>,
+                eval_env: &EvalEnv) -> Vec<NodePtr> {
+    let mut node_array: Vec<NodePtr> = Vec::with_capacity(lhs.len() + rhs.len());
+    let mut i = 0;
+    let mut j = 0;
+    while i < lhs.len() && j < rhs.len() {
+        match eval_env.compare_by_doc_order(&lhs[i], &rhs[j]) {
+            Ordering::Less => {
+                node_array.push(lhs[i].rc_clone());
+                i += 1;
+            },
+            Ordering::Greater => {
+                node_array.push(rhs[j].rc_clone());
+                j += 1;
+            },
+            Ordering::Equal => {
+                node_array.push(lhs[i].rc_clone());
+                i += 1;
+                j += 1;
+            },
+        }
+    }
+    node_array.extend(lhs[i ..].iter().map(|n| n.rc_clone()));
+    node_array.extend(rhs[j ..].iter().map(|n| n.rc_clone()));
+    return node_array;
 }
 
 // ---------------------------------------------------------------------
@@ -319,6 +392,13 @@ mod test {
 
             ( "0.0e0 div 0.0e0", "NaN" ),
             ( "0 div 0", "Dynamic Error" ),
+
+            // 型の昇格で double 同士の除算になる場合は INF / NaN
+            ( "7e0 div 0", "+Infinity" ),
+            ( "-7e0 div 0.0", "-Infinity" ),
+            ( "7.0 div 0e0", "+Infinity" ),
+            ( "0e0 div 0", "NaN" ),
+            ( "7 div -0e0", "-Infinity" ),
         ]);
     }
 
@@ -345,6 +425,20 @@ mod test {
 
             ( "7 idiv 0", "Dynamic Error" ),
             ( "7.0 idiv 0", "Dynamic Error" ),
+            ( "7 idiv 0.0", "Dynamic Error" ),
+            ( "7 idiv 0e0", "Dynamic Error" ),      // double でもエラー
+            ( "7e0 idiv -0e0", "Dynamic Error" ),
+
+            // 除算してから切り捨てる (オペランドを先に丸めない)
+            ( "5 idiv 0.5", "10" ),
+            ( "7.5 idiv 0.5", "15" ),
+            ( "-7.5 idiv 2", "-3" ),
+            ( "1.0e1 idiv 2.5e0", "4" ),
+            ( "1e0 idiv 3", "0" ),
+            ( "-9223372036854775807 - 1", "-9223372036854775808" ),
+            ( "(-9223372036854775807 - 1) idiv -1", "Dynamic Error" ),
+            ( "1e300 idiv 1", "Dynamic Error" ),    // integer の範囲外
+            ( "'7' idiv 2", "Type Error" ),
 
             // XIDoubleの扱い: JavaやC++の実装とは違っている。
             ( "0.0e0 div 0.0e0", "NaN" ),
@@ -378,6 +472,10 @@ mod test {
             ( "-5 mod -2", "-1" ),
             ( "7 mod 0", "Dynamic Error" ),
             ( "7.0 mod 0", "Dynamic Error" ),
+            ( "7 mod 0.0", "Dynamic Error" ),
+            ( "7 mod 0e0", "NaN" ),                 // double ならば NaN
+            ( "7e0 mod 0", "NaN" ),
+            ( "(-9223372036854775807 - 1) mod -1", "0" ),
 
             ( "3.5 mod 1.5", "0.5" ),
             ( "3.5 mod -1.5", "0.5" ),
@@ -395,6 +493,7 @@ mod test {
             ( "5 mod (7.0e0 div 0.0e0)", "5e0" ),   // N mod +∞ = N
             ( "5 mod (-7.0e0 div 0.0e0)", "5e0" ),  // N mod -∞ = N
             ( "0.0e0 mod 5", "0e0" ),              // 0 mod N = 0
+            ( "(7.0e0 div 0.0e0) mod 5", "NaN" ),  // ∞ mod N = NaN
         ]);
     }
 
@@ -511,6 +610,9 @@ mod test {
             ( "(/a/right | /a/left)/p", "LALBRX" ),
             ( "(/a/left | /a/right)//p", "LALBRX" ),
             ( "(/a/right | /a/left)//p", "LALBRX" ),
+            // ---------------------------------------------
+            ( "/a/right/*[@img != 'RB'] | /a/left/p[2] | /a/right/q[2]", "LBRARBRCRX" ),
+            ( "(/a/right/q[3], /a/left/p[1]) | /a/right/q[1]", "LARARC" ),
         ]);
     }
 
@@ -536,6 +638,7 @@ mod test {
             ( r#"/a/p[@a="1"] intersect /a/p[@b="1"]"#, "x11" ),
             ( r#"/a/p[@a>="2"] intersect /a/p[@b>="2"]"#, "x22x23x32x33" ),
             ( r#"/a/p[@b>="2"] intersect /a/p[@a>="2"]"#, "x22x23x32x33" ),
+            ( r#"(/a/p[@a="3"], /a/p[@a="1"]) intersect /a/p[@b="1"]"#, "x11x31" ),
         ]);
     }
 
@@ -559,6 +662,24 @@ mod test {
         "#);
         subtest_xpath("op_except", &xml, false, &[
             ( r#"/a/p[@a="1"] except /a/p[@b="1"]"#, "x12x13" ),
+            ( r#"(/a/p[@a="3"], /a/p[@a="1"]) except /a/p[@b="1"]"#, "x12x13x32x33" ),
+        ]);
+    }
+
+    // -----------------------------------------------------------------
+    // 多数のノードの union / intersect / except
+    // (計算量が線形であることは benches/dom_xpath.rs で確かめる)。
+    //
+    #[test]
+    fn test_op_set_operations_large() {
+        let xml = format!("<a>{}</a>", "<p/>".repeat(400));
+        subtest_eval_xpath("op_set_operations_large", &xml, &[
+            ( "count(/a/p | /a/p[position() mod 2 = 0])", "400" ),
+            ( "count(/a/p[position() mod 2 = 0] | /a/p[position() mod 2 = 1])", "400" ),
+            ( "(/a/p[position() mod 2 = 0] | /a/p[position() mod 2 = 1])[2] is /a/p[2]", "true" ),
+            ( "count(/a/p intersect /a/p[position() mod 2 = 0])", "200" ),
+            ( "count(/a/p except /a/p[position() mod 2 = 0])", "200" ),
+            ( "(/a/p except /a/p[position() > 1]) is /a/p[1]", "true" ),
         ]);
     }
 
@@ -0,0 +1,475 @@
+//
+// xpath_impl/optimizer.rs
+//
+// amxml: XML processor with XPath.
+// Copyright (C) 2018 KOYAMA Hiro <tac@amris.co.jp>
+//
+
+#[cfg(feature = "no_std")]
+use std::prelude::*;
+
+use xpath_impl::parser::*;
+use xpath_impl::eval::*;
+use xpath_impl::func::*;
+use xpath_impl::stack_guard::*;
+use xpath_impl::xitem::*;
+
+// =====================================================================
+// 構文木の最適化。元の構文木は書き替えず、新しい構文木を返す
+// (変わらない部分木は元の構文木と共有する)。
+//
+// (1) 定数式の畳み込み:
+//     文脈アイテムにも変数にも依存しない部分式 (リテラル、演算子、
+//     引数のみで結果が決まる函数の呼び出しからなるもの) を評価し、
+//     結果が単一の数値、文字列、真偽値であればリテラルに置き換える。
+//     評価してエラーになる部分式は、そのまま残す (評価時にエラーになる)。
+//
+// (2) 「descendant-or-self::node()/child::x」(「//x」) を
+//     「descendant::x」に書き替える。
+//     child::x に述語があると意味が変わる (//x[1] と /descendant::x[1])
+//     ので、述語のない場合に限る。
+//
+// (3) 述語、および for / some / every の本体から、ループの中で値の
+//     変わらない部分式 (文脈アイテムにも変数にも依存しない、(1) で
+//     リテラルにできなかったもの。例えば「(1 to 10)」) を、式全体を
+//     囲む let に括り出す。
+//     括り出した部分式は、述語や本体が一度も評価されない場合にも
+//     評価することになるので、最適化の時点で評価してエラーにならない
+//     ものに限る。
+//
+// 入れ子が深すぎてスタックを使い切りそうな部分木は、最適化しない。
+//
+pub fn optimize_xpath(xnode: &XNodePtr) -> XNodePtr {
+    let guard = new_stack_guard();
+
+    let (folded, is_const) = fold_constants(xnode, &guard);
+    let folded = if is_const { materialize_constant(&folded, &guard) } else { folded };
+
+    let rewritten = rewrite_descendant_steps(&folded, &guard);
+
+    let mut hoisted: Vec<(String, XNodePtr)> = vec!{};
+    let mut body = hoist_invariants(&rewritten, false, &mut hoisted, &guard);
+
+    // -----------------------------------------------------------------
+    // let $#hoist1 := S1 return let $#hoist2 := S2 return ... body
+    //
+    while let Some((var_name, value)) = hoisted.pop() {
+        let var_bind = new_xnode(XNodeType::LetVarBind, &var_name);
+        let var_bind = copy_xnode_with_children(&var_bind, &value, &body);
+        let let_expr = new_xnode(XNodeType::LetExpr, "let");
+        body = copy_xnode_with_children(&let_expr, &new_xnode(XNodeType::Nil, ""), &var_bind);
+    }
+    return body;
+}
+
+// ---------------------------------------------------------------------
+// 括り出した部分式を束縛する変数の名前の接頭辞。
+// XPathの変数名としては書けない文字で始めて、利用者の変数と衝突しない
+// ようにする。
+//
+const HOIST_VAR_PREFIX: &str = "#hoist";
+
+// ---------------------------------------------------------------------
+// 子 (left, right) を函数 f で変換した xnode を返す。
+// 子がどちらも変わらなければ、xnode そのものを返す。
+//
+fn map_children<F>(xnode: &XNodePtr, mut f: F) -> XNodePtr
+        where F: FnMut(&XNodePtr) -> XNodePtr {
+    let left = get_left(xnode);
+    let right = get_right(xnode);
+    let new_left = if is_nil_xnode(&left) { left.clone() } else { f(&left) };
+    let new_right = if is_nil_xnode(&right) { right.clone() } else { f(&right) };
+    if new_left == left && new_right == right {
+        return xnode.clone();
+    }
+    return copy_xnode_with_children(xnode, &new_left, &new_right);
+}
+
+// ---------------------------------------------------------------------
+// 副作用がなく、オペランド (left, right) が定数ならば結果も定数である
+// 演算子など。
+//
+fn is_pure_operator(xnode_type: &XNodeType) -> bool {
+    match xnode_type {
+        XNodeType::OperatorOr |
+        XNodeType::OperatorAnd |
+        XNodeType::OperatorGeneralEQ |
+        XNodeType::OperatorGeneralNE |
+        XNodeType::OperatorGeneralLT |
+        XNodeType::OperatorGeneralLE |
+        XNodeType::OperatorGeneralGT |
+        XNodeType::OperatorGeneralGE |
+        XNodeType::OperatorValueEQ |
+        XNodeType::OperatorValueNE |
+        XNodeType::OperatorValueLT |
+        XNodeType::OperatorValueLE |
+        XNodeType::OperatorValueGT |
+        XNodeType::OperatorValueGE |
+        XNodeType::OperatorAdd |
+        XNodeType::OperatorSubtract |
+        XNodeType::OperatorMultiply |
+        XNodeType::OperatorDiv |
+        XNodeType::OperatorIDiv |
+        XNodeType::OperatorMod |
+        XNodeType::OperatorUnaryPlus |
+        XNodeType::OperatorUnaryMinus |
+        XNodeType::OperatorConcat |
+        XNodeType::OperatorConcatenate |
+        XNodeType::OperatorTo |
+        XNodeType::IfExpr |
+        XNodeType::IfThenElse |
+        XNodeType::ParenthesizedExpr |
+        XNodeType::ArgumentTop => return true,
+        _ => return false,
+    }
+}
+
+// ---------------------------------------------------------------------
+//
+fn is_literal(xnode_type: &XNodeType) -> bool {
+    match xnode_type {
+        XNodeType::StringLiteral |
+        XNodeType::IntegerLiteral |
+        XNodeType::DecimalLiteral |
+        XNodeType::DoubleLiteral => return true,
+        _ => return false,
+    }
+}
+
+// ---------------------------------------------------------------------
+// FunctionCallノードの引数の個数 (rightに連なるArgumentTopノードの数)。
+//
+fn num_arguments(xnode: &XNodePtr) -> usize {
+    let mut n = 0;
+    let mut curr = get_right(xnode);
+    while ! is_nil_xnode(&curr) {
+        n += 1;
+        curr = get_right(&curr);
+    }
+    return n;
+}
+
+// ---------------------------------------------------------------------
+// 引数のみで結果が決まる函数の呼び出しか否か。
+//
+fn is_pure_function_call(xnode: &XNodePtr) -> bool {
+    return get_xnode_type(xnode) == XNodeType::FunctionCall &&
+           is_context_independent_function(
+                &get_xnode_name(xnode), num_arguments(xnode));
+}
+
+// ---------------------------------------------------------------------
+// (1) 定数式の畳み込み。
+// 変換後の xnode と、それが定数式か否かを返す。
+// 定数式である部分木は、より大きな定数式の一部かも知れないので、
+// ここでは評価せず、定数式でない親ノードの側で materialize_constant()
+// を適用する。
+//
+fn fold_constants(xnode: &XNodePtr, guard: &StackGuard) -> (XNodePtr, bool) {
+    if guard.exhausted() {
+        return (xnode.clone(), false);
+    }
+
+    let xnode_type = get_xnode_type(xnode);
+    if is_literal(&xnode_type) {
+        return (xnode.clone(), true);
+    }
+    if xnode_type == XNodeType::InlineFunction {
+        return (xnode.clone(), false);
+    }
+
+    let left = get_left(xnode);
+    let right = get_right(xnode);
+    let (new_left, left_const) = if is_nil_xnode(&left) {
+            (left.clone(), true)
+        } else {
+            fold_constants(&left, guard)
+        };
+    let (new_right, right_const) = if is_nil_xnode(&right) {
+            (right.clone(), true)
+        } else {
+            fold_constants(&right, guard)
+        };
+
+    let is_const = left_const && right_const &&
+            (is_pure_operator(&xnode_type) || is_pure_function_call(xnode));
+
+    // -----------------------------------------------------------------
+    // 定数式でなければ、定数式である子をここで評価する。
+    //
+    let (new_left, new_right) = if is_const {
+            (new_left, new_right)
+        } else {
+            (if left_const { materialize_constant(&new_left, guard) } else { new_left },
+             if right_const { materialize_constant(&new_right, guard) } else { new_right })
+        };
+
+    if new_left == left && new_right == right {
+        return (xnode.clone(), is_const);
+    }
+    return (copy_xnode_with_children(xnode, &new_left, &new_right), is_const);
+}
+
+// ---------------------------------------------------------------------
+// 定数式 xnode を評価し、リテラル (あるいは fn:true() / fn:false()) に
+// 置き換える。結果がリテラルで表せない (シーケンスなど) 場合は、
+// 子の定数式について同様に置き換える。
+// 評価してエラーになる場合は、そのまま返す。
+//
+fn materialize_constant(xnode: &XNodePtr, guard: &StackGuard) -> XNodePtr {
+    if is_nil_xnode(xnode) || guard.exhausted() {
+        return xnode.clone();
+    }
+    let xnode_type = get_xnode_type(xnode);
+    if is_literal(&xnode_type) {
+        return xnode.clone();
+    }
+
+    if xnode_type != XNodeType::ArgumentTop && xnode_type != XNodeType::IfThenElse {
+        if let Ok(xseq) = evaluate_constant_xnode(xnode) {
+            if xseq.is_singleton() {
+                if let Ok(xitem) = xseq.get_singleton_item() {
+                    if let Some(literal) = literal_xnode(&xitem) {
+                        return literal;
+                    }
+                }
+            }
+        } else {
+            return xnode.clone();
+        }
+    }
+    return map_children(xnode, |ch| materialize_constant(ch, guard));
+}
+
+// ---------------------------------------------------------------------
+// アイテムを表すリテラルのxnode。表せなければNone。
+// 数値のリテラルには負号を含めてよい (評価時にそのまま解釈する)。
+//
+fn literal_xnode(xitem: &XItem) -> Option<XNodePtr> {
+    match xitem {
+        XItem::XIString{value} => {
+            return Some(new_xnode(XNodeType::StringLiteral, value));
+        },
+        XItem::XIInteger{value} => {
+            return Some(new_xnode(XNodeType::IntegerLiteral, &value.to_string()));
+        },
+        XItem::XIDecimal{value} if value.is_finite() => {
+            return Some(new_xnode(XNodeType::DecimalLiteral, &format!("{:?}", value)));
+        },
+        XItem::XIDouble{value} if value.is_finite() => {
+            return Some(new_xnode(XNodeType::DoubleLiteral, &format!("{:?}", value)));
+        },
+        XItem::XIBoolean{value} => {
+            let func_name = if *value { "fn:true" } else { "fn:false" };
+            return Some(new_xnode(XNodeType::FunctionCall, func_name));
+        },
+        _ => return None,
+    }
+}
+
+// ---------------------------------------------------------------------
+// (2) descendant-or-self::node()/child::x を descendant::x に書き替える。
+//
+//   OperatorPath
+//     L: AxisDescendantOrSelf "node()"
+//     R: AxisChild "x"                     => AxisDescendant "x"
+//   あるいは
+//     R: OperatorPath                      => OperatorPath
+//          L: AxisChild "x"                     L: AxisDescendant "x"
+//          R: (後続のステップ)                  R: (後続のステップ)
+//
+fn rewrite_descendant_steps(xnode: &XNodePtr, guard: &StackGuard) -> XNodePtr {
+    if guard.exhausted() {
+        return xnode.clone();
+    }
+    let xnode = map_children(xnode, |ch| rewrite_descendant_steps(ch, guard));
+
+    if get_xnode_type(&xnode) != XNodeType::OperatorPath {
+        return xnode;
+    }
+    let left = get_left(&xnode);
+    if get_xnode_type(&left) != XNodeType::AxisDescendantOrSelf ||
+       get_xnode_name(&left) != "node()" ||
+       ! is_nil_xnode(&get_left(&left)) || ! is_nil_xnode(&get_right(&left)) {
+        return xnode;
+    }
+
+    let right = get_right(&xnode);
+    if let Some(descendant) = descendant_step(&right) {
+        return descendant;
+    }
+    if get_xnode_type(&right) == XNodeType::OperatorPath {
+        if let Some(descendant) = descendant_step(&get_left(&right)) {
+            return copy_xnode_with_children(&xnode, &descendant, &get_right(&right));
+        }
+    }
+    return xnode;
+}
+
+// ---------------------------------------------------------------------
+// 述語のない AxisChild ならば、同じノードテストの AxisDescendant を返す。
+//
+fn descendant_step(xnode: &XNodePtr) -> Option<XNodePtr> {
+    if get_xnode_type(xnode) != XNodeType::AxisChild ||
+       ! is_nil_xnode(&get_right(xnode)) {
+        return None;
+    }
+    let descendant = new_xnode(XNodeType::AxisDescendant, &get_xnode_name(xnode));
+    return Some(copy_xnode_with_children(&descendant, &get_left(xnode), &get_right(xnode)));
+}
+
+// ---------------------------------------------------------------------
+// 文脈アイテムにも変数にも依存しない部分式か否か。
+//
+fn is_constant(xnode: &XNodePtr, guard: &StackGuard) -> bool {
+    if is_nil_xnode(xnode) {
+        return true;
+    }
+    if guard.exhausted() {
+        return false;
+    }
+    let xnode_type = get_xnode_type(xnode);
+    if is_literal(&xnode_type) {
+        return true;
+    }
+    if ! is_pure_operator(&xnode_type) && ! is_pure_function_call(xnode) {
+        return false;
+    }
+    return is_constant(&get_left(xnode), guard) &&
+           is_constant(&get_right(xnode), guard);
+}
+
+// ---------------------------------------------------------------------
+// (3) ループの中で値の変わらない部分式を括り出し、変数参照に置き換える。
+// in_loop: 述語、あるいは for / some / every の本体の中か否か。
+//
+fn hoist_invariants(xnode: &XNodePtr, in_loop: bool,
+                hoisted: &mut Vec<(String, XNodePtr)>,
+                guard: &StackGuard) -> XNodePtr {
+    if is_nil_xnode(xnode) || guard.exhausted() {
+        return xnode.clone();
+    }
+    let xnode_type = get_xnode_type(xnode);
+    if xnode_type == XNodeType::InlineFunction {
+        return xnode.clone();
+    }
+
+    // -----------------------------------------------------------------
+    // 括り出す価値のあるのは、リテラルそのもの以外の部分式。
+    // ArgumentTop などは部分式そのものではないので、括り出さない。
+    //
+    if in_loop && (is_pure_operator(&xnode_type) || is_pure_function_call(xnode)) &&
+       xnode_type != XNodeType::ArgumentTop &&
+       xnode_type != XNodeType::IfThenElse &&
+       is_constant(xnode, guard) &&
+       evaluate_constant_xnode(xnode).is_ok() {
+        let var_name = format!("{}{}", HOIST_VAR_PREFIX, hoisted.len() + 1);
+        hoisted.push((var_name.clone(), xnode.clone()));
+        return new_xnode(XNodeType::VarRef, &var_name);
+    }
+
+    let left = get_left(xnode);
+    let right = get_right(xnode);
+    let (left_in_loop, right_in_loop) = match xnode_type {
+        XNodeType::PredicateTop |
+        XNodeType::PredicateRevTop => (true, in_loop),
+        XNodeType::ForVarBind |
+        XNodeType::SomeVarBind |
+        XNodeType::EveryVarBind => (in_loop, true),
+        _ => (in_loop, in_loop),
+    };
+    let new_left = hoist_invariants(&left, left_in_loop, hoisted, guard);
+    let new_right = hoist_invariants(&right, right_in_loop, hoisted, guard);
+    if new_left == left && new_right == right {
+        return xnode.clone();
+    }
+    return copy_xnode_with_children(xnode, &new_left, &new_right);
+}
+
+// =====================================================================
+//
+#[cfg(test)]
+mod test {
+    #[cfg(feature = "no_std")]
+    use std::prelude::*;
+    use dom::*;
+    use xpath::*;
+
+    // -----------------------------------------------------------------
+    // 最適化した構文木のS式と、最適化の前後の評価結果を比べる。
+    //
+    fn subtest_optimize(xml: &str, cases: &[(&str, &str)]) {
+        let doc = new_document(xml).unwrap();
+        for (xpath, expected_sexpr) in cases.iter() {
+            let compiled = XPath::compile(xpath).unwrap();
+            let optimized = compiled.optimize();
+            assert_eq!(optimized.to_sexpr(), *expected_sexpr, "xpath = {}", xpath);
+            assert_eq!(eval_to_string(&optimized, &doc),
+                       eval_to_string(&compiled, &doc),
+                       "xpath = {}", xpath);
+        }
+    }
+
+    // -----------------------------------------------------------------
+    //
+    fn eval_to_string(xpath: &XPath, node: &NodePtr) -> String {
+        match xpath.eval(node) {
+            Ok(result) => return result.to_string(),
+            Err(e) => return e.to_string(),
+        }
+    }
+
+    // -----------------------------------------------------------------
+    //
+    #[test]
+    fn test_fold_constants() {
+        subtest_optimize(r#"<root><a v="6"/><a v="7"/></root>"#, &[
+            ( "1 + 2 * 3", r#"(IntegerLiteral "7")"# ),
+            ( "-(1.5 + 1)", r#"(DecimalLiteral "-2.5")"# ),
+            ( "'a' || 'b' || string(1 + 1)", r#"(StringLiteral "ab2")"# ),
+            ( "1 = 1", r#"(FunctionCall "fn:true")"# ),
+            ( "count(1 to 10)", r#"(IntegerLiteral "10")"# ),
+            ( "math:pow(2, 10)", r#"(DoubleLiteral "1024.0")"# ),
+            ( "/root/a[@v = 3 * 2]/@v",
+              r#"(OperatorPath "parse_path_expr Slash" (AxisRoot "node()") (OperatorPath "parse_relative_path_expr TOP" (AxisChild "root") (OperatorPath "parse_relative_path_expr Slash" (AxisChild "a" (PredicateTop "Predicate" (OperatorGeneralEQ "=" (AxisAttribute "v") (IntegerLiteral "6")))) (OperatorPath "parse_relative_path_expr Slash" (AxisAttribute "v")))))"# ),
+            ( "(1, 2 + 3)", r#"(ParenthesizedExpr (OperatorConcatenate "," (IntegerLiteral "1") (IntegerLiteral "5")))"# ),
+            ( "1 div 0 + count(/root)", r#"(OperatorAdd "+" (OperatorDiv "div" (IntegerLiteral "1") (IntegerLiteral "0")) (FunctionCall "fn:count" (ArgumentTop (OperatorPath "parse_path_expr Slash" (AxisRoot "node()") (AxisChild "root")))))"# ),
+        ]);
+    }
+
+    // -----------------------------------------------------------------
+    //
+    #[test]
+    fn test_rewrite_descendant_steps() {
+        subtest_optimize(r#"<root><a><b/></a><b><b/></b></root>"#, &[
+            ( "//b",
+              r#"(OperatorPath "parse_path_expr SlashSlash 1" (AxisRoot "/") (AxisDescendant "b"))"# ),
+            ( "a//b/..",
+              r#"(OperatorPath "parse_relative_path_expr TOP" (AxisChild "a") (OperatorPath "parse_relative_path_expr SlashSlash 1" (AxisDescendant "b") (OperatorPath "parse_relative_path_expr Slash" (AxisParent "node()"))))"# ),
+            ( "//b[1]",
+              r#"(OperatorPath "parse_path_expr SlashSlash 1" (AxisRoot "/") (OperatorPath "parse_path_expr SlashSlash 2" (AxisDescendantOrSelf "node()") (AxisChild "b" (PredicateTop "Predicate" (IntegerLiteral "1")))))"# ),
+            ( "//@v",
+              r#"(OperatorPath "parse_path_expr SlashSlash 1" (AxisRoot "/") (OperatorPath "parse_path_expr SlashSlash 2" (AxisDescendantOrSelf "node()") (AxisAttribute "v")))"# ),
+        ]);
+    }
+
+    // -----------------------------------------------------------------
+    //
+    #[test]
+    fn test_hoist_invariants() {
+        subtest_optimize(r#"<root><a v="2"/><a v="4"/></root>"#, &[
+            ( "/root/a[@v = (3 to 5)]",
+              r##"(LetExpr "let" (LetVarBind "#hoist1" (ParenthesizedExpr (OperatorTo "to" (IntegerLiteral "3") (IntegerLiteral "5"))) (OperatorPath "parse_path_expr Slash" (AxisRoot "node()") (OperatorPath "parse_relative_path_expr TOP" (AxisChild "root") (OperatorPath "parse_relative_path_expr Slash" (AxisChild "a" (PredicateTop "Predicate" (OperatorGeneralEQ "=" (AxisAttribute "v") (VarRef "#hoist1")))))))))"## ),
+            ( "for $x in (1, 2) return ($x, 1 to 2)",
+              r##"(LetExpr "let" (LetVarBind "#hoist1" (OperatorTo "to" (IntegerLiteral "1") (IntegerLiteral "2")) (ForExpr "for" (ForVarBind "x" (ParenthesizedExpr (OperatorConcatenate "," (IntegerLiteral "1") (IntegerLiteral "2"))) (ParenthesizedExpr (OperatorConcatenate "," (VarRef "x") (VarRef "#hoist1")))))))"## ),
+            ( "/root/a[position() = $k + 1]",
+              r#"(OperatorPath "parse_path_expr Slash" (AxisRoot "node()") (OperatorPath "parse_relative_path_expr TOP" (AxisChild "root") (OperatorPath "parse_relative_path_expr Slash" (AxisChild "a" (PredicateTop "Predicate" (OperatorGeneralEQ "=" (FunctionCall "fn:position") (OperatorAdd "+" (VarRef "k") (IntegerLiteral "1"))))))))"# ),
+            ( "/root/a[@v = (1 idiv 0, 2)]",
+              r#"(OperatorPath "parse_path_expr Slash" (AxisRoot "node()") (OperatorPath "parse_relative_path_expr TOP" (AxisChild "root") (OperatorPath "parse_relative_path_expr Slash" (AxisChild "a" (PredicateTop "Predicate" (OperatorGeneralEQ "=" (AxisAttribute "v") (ParenthesizedExpr (OperatorConcatenate "," (OperatorIDiv "idiv" (IntegerLiteral "1") (IntegerLiteral "0")) (IntegerLiteral "2")))))))))"# ),
+            ( "some $x in (1, 2) satisfies $x + 1 = 3",
+              r#"(SomeExpr "some" (SomeVarBind "x" (ParenthesizedExpr (OperatorConcatenate "," (IntegerLiteral "1") (IntegerLiteral "2"))) (OperatorGeneralEQ "=" (OperatorAdd "+" (VarRef "x") (IntegerLiteral "1")) (IntegerLiteral "3"))))"# ),
+            ( "for $x in (1, 2) return let $y := $x return ($y, 2 to 3)",
+              r##"(LetExpr "let" (LetVarBind "#hoist1" (OperatorTo "to" (IntegerLiteral "2") (IntegerLiteral "3")) (ForExpr "for" (ForVarBind "x" (ParenthesizedExpr (OperatorConcatenate "," (IntegerLiteral "1") (IntegerLiteral "2"))) (LetExpr "let" (LetVarBind "y" (VarRef "x") (ParenthesizedExpr (OperatorConcatenate "," (VarRef "y") (VarRef "#hoist1")))))))))"## ),
+        ]);
+    }
+}
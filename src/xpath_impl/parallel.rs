@@ -0,0 +1,225 @@
+//
+// xpath_impl/parallel.rs
+//
+// amxml: XML processor with XPath.
+// Copyright (C) 2018 KOYAMA Hiro <tac@amris.co.jp>
+//
+
+use std::error::Error;
+use std::sync::Arc;
+use std::thread;
+
+use dom::*;
+use xpath_impl::parser::*;
+use xpath_impl::eval::*;
+use xpath_impl::func::clock_seed;
+use xpath_impl::xsequence::*;
+
+// ---------------------------------------------------------------------
+// 文書根からノードに至る経路の1段。
+// NodePtr はスレッド間で共有できないので、複製から組み立てた木の
+// ノードと元の文書のノードとは、この経路によって対応づける。
+//
+#[derive(Debug, Clone, PartialEq)]
+enum Step {
+    Child(usize),
+    Attribute(usize),
+}
+
+// =====================================================================
+// 最上位の UnionExpr (a | b | c ...) の各オペランドを、別々のスレッドで
+// 評価し、結果を文書順に併合する。
+//
+// 文書の複製 (TreeSnapshot) を1回作ってスレッド間で共有し、各スレッド
+// はそれから組み立てた木で評価する。次の場合は Ok(None) を返すので、
+// 呼び出し側で通常の (逐次的な) 評価をやり直すこと。
+// - xpath が最上位の UnionExpr でない。
+// - 文書に、複製できない情報 (注釈、名前付きキー、属性値の索引) が
+//   付いている。Schema#validate() による型注釈もこれに含まれる。
+// - いずれかのオペランドがエラーになった、あるいはノード以外を返した
+//   (エラーの詳細は逐次評価の方で報告する)。
+//
+pub fn match_xpath_parallel(start_node: &NodePtr,
+                xpath: &String) -> Result<Option<XSequence>, Box<Error>> {
+
+    let branches = split_top_level_union(xpath)?;
+    if branches.len() < 2 {
+        return Ok(None);
+    }
+
+    let snapshot = match start_node.tree_snapshot() {
+        Some(snapshot) => Arc::new(snapshot),
+        None => return Ok(None),
+    };
+    let root = start_node.root();
+    let start_path = node_path(start_node);
+    let seed = clock_seed();    // 各オペランドの fn:random-number-generator() で共通
+
+    let mut handles = vec!{};
+    for branch in branches.into_iter() {
+        let snapshot = Arc::clone(&snapshot);
+        let start_path = start_path.clone();
+        handles.push(thread::spawn(move || {
+            return eval_branch(&snapshot, &start_path, &branch, seed);
+        }));
+    }
+
+    let mut node_array: Vec<NodePtr> = vec!{};
+    for handle in handles.into_iter() {
+        let paths = match handle.join() {
+            Ok(Some(paths)) => paths,
+            _ => return Ok(None),
+        };
+        for path in paths.iter() {
+            match resolve_path(&root, path) {
+                Some(node) => node_array.push(node),
+                None => return Ok(None),
+            }
+        }
+    }
+
+    sort_document_order(&mut node_array);
+    return Ok(Some(new_xsequence_from_node_array(&node_array)));
+}
+
+// ---------------------------------------------------------------------
+// 複製から木を組み立て、オペランド branch を評価して、結果のノード
+// それぞれの経路を返す。
+// clock_seed: $seedを省略した fn:random-number-generator() の内部状態。
+//
+fn eval_branch(snapshot: &TreeSnapshot, start_path: &Vec<Step>,
+                branch: &String, clock_seed: u64) -> Option<Vec<Vec<Step>>> {
+    let root = new_tree_from_snapshot(snapshot);
+    let start_node = resolve_path(&root, start_path)?;
+    let xnode = match compile_xpath(branch) {
+        Ok(xnode) => xnode,
+        Err(_) => return None,
+    };
+    let result = match match_xpath_with_clock_seed(&start_node, &xnode, clock_seed) {
+        Ok(result) => result,
+        Err(_) => return None,
+    };
+
+    let mut paths: Vec<Vec<Step>> = vec!{};
+    for xitem in result.iter() {
+        paths.push(node_path(&xitem.as_nodeptr()?));
+    }
+    return Some(paths);
+}
+
+// ---------------------------------------------------------------------
+// 文書根からノード node に至る経路。
+//
+fn node_path(node: &NodePtr) -> Vec<Step> {
+    let mut path: Vec<Step> = vec!{};
+    let mut curr = node.rc_clone();
+    while let Some(parent) = curr.parent() {
+        let step = if curr.node_type() == NodeType::Attribute {
+            let pos = parent.attribute_nodes().iter().position(|n| *n == curr);
+            Step::Attribute(pos.unwrap_or(usize::MAX))
+        } else {
+            let pos = curr.index_in_parent();
+            Step::Child(pos.unwrap_or(usize::MAX))
+        };
+        path.push(step);
+        curr = parent;
+    }
+    path.reverse();
+    return path;
+}
+
+// ---------------------------------------------------------------------
+// 文書根から経路 path をたどって得られるノード。
+//
+fn resolve_path(root: &NodePtr, path: &Vec<Step>) -> Option<NodePtr> {
+    let mut curr = root.rc_clone();
+    for step in path.iter() {
+        curr = match *step {
+            Step::Child(pos) => curr.children().get(pos)?.rc_clone(),
+            Step::Attribute(pos) => curr.attribute_nodes().get(pos)?.rc_clone(),
+        };
+    }
+    return Some(curr);
+}
+
+// =====================================================================
+//
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use xpath_impl::helpers::compress_spaces;
+
+    // -----------------------------------------------------------------
+    //
+    #[test]
+    fn test_match_xpath_parallel() {
+        let xml = compress_spaces(r#"
+<root>
+    <a img="a1"><c img="c1"/></a>
+    <b img="b1"/>
+    <a img="a2"/>
+    <c img="c2"/>
+</root>
+        "#);
+        let doc = new_document(&xml).unwrap();
+
+        let cases = [
+            ( "//a | //b | //c", "a1c1b1a2c2" ),
+            ( "//c | //a", "a1c1a2c2" ),
+            ( "/root/b | //*[@img = 'b1'] | //@img[. = 'a2']/..", "b1a2" ),
+        ];
+        for (xpath, expected) in cases.iter() {
+            let result = match_xpath_parallel(&doc,
+                            &String::from(*xpath)).unwrap().unwrap();
+            let mut img = String::new();
+            for node in result.to_nodeset().iter() {
+                img += &node.attribute_value("img").unwrap();
+            }
+            assert_eq!(&img, expected, "xpath: {}", xpath);
+        }
+
+        // 文脈ノードは元の文書と同じ位置になる。
+        let elem_a = doc.get_first_node("//a").unwrap();
+        let result = match_xpath_parallel(&elem_a,
+                        &String::from("c | @img")).unwrap().unwrap();
+        assert_eq!(result.to_string(), r#"(img="a1", <c img="c1">)"#);
+
+        // 最上位の UnionExpr でない場合、ノード以外を返す場合は None。
+        let not_applicable = [
+            "//a",
+            "(//a | //b)[1]",
+            "for $x in //a return $x/c | $x",
+            "//a | 1",
+        ];
+        for xpath in not_applicable.iter() {
+            let result = match_xpath_parallel(&doc, &String::from(*xpath));
+            assert!(result.unwrap().is_none(), "xpath: {}", xpath);
+        }
+    }
+
+    // -----------------------------------------------------------------
+    // 複製から組み立てた木は、文字列化では保てない構造も再現する。
+    // 複製できない情報が付いた文書では None (逐次評価にまかせる)。
+    //
+    #[test]
+    fn test_match_xpath_parallel_snapshot() {
+        let doc = new_document("<root><a/><b/>x<c/>y</root>").unwrap();
+        let root_elem = doc.root_element();
+        root_elem.delete_child(&doc.get_first_node("//c").unwrap());
+        assert_eq!(root_elem.children().len(), 4);
+        let result = match_xpath_parallel(&doc,
+                        &String::from("/root/text() | //b")).unwrap().unwrap();
+        assert_eq!(result.len(), 3);
+        assert_eq!(result.to_string(), "(<b>, x, y)");
+
+        root_elem.set_annotation("note", 1);
+        let result = match_xpath_parallel(&doc, &String::from("//a | //b"));
+        assert!(result.unwrap().is_none());
+        root_elem.remove_annotation("note");
+
+        doc.build_attr_index("id");
+        let result = match_xpath_parallel(&doc, &String::from("//a | //b"));
+        assert!(result.unwrap().is_none());
+    }
+}
@@ -10,6 +10,8 @@ use std::collections::HashMap;
 use std::fmt;
 use std::error::Error;
 use std::rc::Rc;
+#[cfg(feature = "no_std")]
+use std::prelude::*;
 
 use xmlerror::*;
 use xpath_impl::lexer::*;
@@ -158,6 +160,26 @@ struct XNode {
     right: Option<XNodePtr>,
 }
 
+// ---------------------------------------------------------------------
+// 長い演算子の連鎖 (1 + 1 + ...) などによる深い構文木を再帰的に解放
+// すると、スタックを使い切ることがあるので、部分木を作業スタックに
+// 移してから1個ずつ解放する。
+//
+impl Drop for XNode {
+    fn drop(&mut self) {
+        let mut stack: Vec<XNodePtr> = vec!{};
+        stack.extend(self.left.take());
+        stack.extend(self.right.take());
+        while let Some(xnode) = stack.pop() {
+            if let Ok(cell) = Rc::try_unwrap(xnode.xnode_ptr) {
+                let mut xnode = cell.into_inner();
+                stack.extend(xnode.left.take());
+                stack.extend(xnode.right.take());
+            }
+        }
+    }
+}
+
 // =====================================================================
 //
 impl fmt::Debug for XNodePtr {
@@ -209,8 +231,8 @@ fn xnode_dump_sub(xnode: &XNodePtr, indent: usize, step: usize, pref: &str) -> S
 macro_rules! error_if_not_ttype {
     ( $lex: expr, $ttype: expr, $msg: expr ) => {
         if $lex.next_token().get_type() != $ttype {
-            return Err(xpath_syntax_error!($msg,
-                $lex.around_tokens().as_str()));
+            return Err($lex.unexpected_token(&[&ttype_symbol(&$ttype)],
+                &format!($msg, $lex.around_tokens())));
         }
     }
 }
@@ -224,10 +246,10 @@ macro_rules! error_if_not_ttype {
 //
 macro_rules! error_if_not_name {
     ( $lex: expr, $name: expr, $msg: expr ) => {
-        if $lex.next_token().get_type() != TType::Name &&
+        if $lex.next_token().get_type() != TType::Name ||
            $lex.next_token().get_name() != $name {
-            return Err(xpath_syntax_error!($msg,
-                $lex.around_tokens().as_str()));
+            return Err($lex.unexpected_token(&[$name],
+                &format!($msg, $lex.around_tokens())));
         }
     }
 }
@@ -273,8 +295,8 @@ macro_rules! return_if_nil {
 macro_rules! error_if_nil {
     ( $lex: expr, $xnode: expr, $msg: expr ) => {
         if is_nil_xnode(&$xnode) {
-            return Err(xpath_syntax_error!($msg,
-                $lex.around_tokens().as_str()));
+            return Err($lex.syntax_error(&["expression"], "expression expected",
+                &format!($msg, $lex.around_tokens())));
         }
     }
 }
@@ -288,6 +310,234 @@ pub fn compile_xpath(xpath: &String) -> Result<XNodePtr, Box<Error>> {
     return parse_main(&mut lex);
 }
 
+// =====================================================================
+// [PARSE] 誤り回復
+// 構文エラーがあっても、できるだけ長い有効な接頭部分から構文木を作り、
+// 検出した構文エラーの一覧とともに返す。
+//
+// 末尾のトークンを1個ずつ除き、閉じていない括弧 ( [ { を補って
+// 構文解析を試みる。成功したら、そのとき補った括弧それぞれについても
+// 構文エラーとして一覧に加える。
+// 例: "/root/item[@" → "/root/item" として構文解析する。
+//     "count(/root/a" → "count(/root/a)" として構文解析する。
+// どの接頭部分も構文解析できなければ、構文木はNoneとする。
+//
+pub fn compile_xpath_recovering(xpath: &String) -> (Option<XNodePtr>, Vec<Box<Error>>) {
+    let first_err = match compile_xpath(xpath) {
+        Ok(xnode) => return (Some(xnode), vec!{}),
+        Err(e) => e,
+    };
+
+    // -----------------------------------------------------------------
+    // 字句解析の段階でエラーになった場合は、その位置より前の部分のみ
+    // 字句解析する。
+    //
+    let chars: Vec<char> = xpath.chars().collect();
+    let lex = match Lexer::new(xpath) {
+        Ok(lex) => lex,
+        Err(_) => {
+            let offset = match first_err.downcast_ref::<XmlError>()
+                            .and_then(|e| e.syntax_info()) {
+                Some(info) => info.offset().min(chars.len()),
+                None => return (None, vec!{first_err}),
+            };
+            let head: String = chars[.. offset].iter().collect();
+            match Lexer::new(&head) {
+                Ok(lex) => lex,
+                Err(_) => return (None, vec!{first_err}),
+            }
+        },
+    };
+
+    let spans = lex.token_spans();
+    let mut errors: Vec<Box<Error>> = vec!{first_err};
+    for n in (1 ..= spans.len()).rev() {
+        let end = spans[n - 1].1 + spans[n - 1].2;
+        let mut closers: Vec<&str> = vec!{};
+        for (t_type, _, _) in spans[.. n].iter() {
+            match *t_type {
+                TType::LeftParen => closers.push(")"),
+                TType::LeftBracket => closers.push("]"),
+                TType::LeftCurly => closers.push("}"),
+                TType::RightParen | TType::RightBracket | TType::RightCurly => {
+                    closers.pop();
+                },
+                _ => {},
+            }
+        }
+        closers.reverse();
+
+        let mut candidate: String = chars[.. end].iter().collect();
+        candidate += &closers.concat();
+        if let Ok(xnode) = compile_xpath(&candidate) {
+            for closer in closers.iter() {
+                errors.push(xpath_syntax_error_at(end, 0, &[closer],
+                    &format!("missing \"{}\" at offset {}", closer, end),
+                    &format!("{}: 閉じ括弧が必要。", closer)));
+            }
+            return (Some(xnode), errors);
+        }
+    }
+    return (None, errors);
+}
+
+// ---------------------------------------------------------------------
+// xpath 全体が最上位の UnionExpr (a | b | c ...) である場合、
+// 各オペランドのxpath文字列に分割して返す。
+// そうでない場合 (括弧内や for 式の return 節などに "|" がある場合を
+// 含む) は空の配列を返す。
+//
+#[cfg(not(feature = "no_std"))]
+pub fn split_top_level_union(xpath: &String) -> Result<Vec<String>, Box<Error>> {
+    let xnode = compile_xpath(xpath)?;
+    let mut union_count = 0;
+    let mut curr = xnode;
+    while get_xnode_type(&curr) == XNodeType::OperatorUnion {
+        union_count += 1;
+        curr = get_left(&curr);
+    }
+    if union_count == 0 {
+        return Ok(vec!{});
+    }
+
+    let lex = Lexer::new(xpath)?;
+    let spans = lex.top_level_token_spans(TType::Union);
+    if spans.len() != union_count {
+        return Ok(vec!{});
+    }
+
+    let char_vec: Vec<char> = xpath.chars().collect();
+    let mut branches: Vec<String> = vec!{};
+    let mut beg = 0;
+    for (offset, length) in spans.iter() {
+        branches.push(char_vec[beg .. *offset].iter().collect());
+        beg = offset + length;
+    }
+    branches.push(char_vec[beg ..].iter().collect());
+    return Ok(branches);
+}
+
+// ---------------------------------------------------------------------
+// XPath 1.0 の函数。
+//
+const XPATH10_FUNCTIONS: [&str; 27] = [
+    "fn:last", "fn:position", "fn:count", "fn:id", "fn:local-name",
+    "fn:namespace-uri", "fn:name",
+    "fn:string", "fn:concat", "fn:starts-with", "fn:contains",
+    "fn:substring-before", "fn:substring-after", "fn:substring",
+    "fn:string-length", "fn:normalize-space", "fn:translate",
+    "fn:boolean", "fn:not", "fn:true", "fn:false", "fn:lang",
+    "fn:number", "fn:sum", "fn:floor", "fn:ceiling", "fn:round",
+];
+
+// ---------------------------------------------------------------------
+// 構文木が XPath 1.0 の構文と函数のみからなることを検査する。
+// XPath 2.0 以降の構文を含んでいればエラー [err:XPST0003]、
+// XPath 1.0 にない函数を呼び出していればエラー [err:XPST0017]。
+//
+pub fn check_xpath10_syntax(xnode: &XNodePtr) -> Result<(), Box<Error>> {
+    let mut stack: Vec<XNodePtr> = vec!{ xnode.clone() };
+    while let Some(curr) = stack.pop() {
+        if is_nil_xnode(&curr) {
+            continue;
+        }
+        let n_type = get_xnode_type(&curr);
+        let allowed = match n_type {
+            XNodeType::OperatorPath => {
+                // 2番目以降のステップは軸ステップに限る ("a/string()" などは不可)
+                let mut step = get_right(&curr);
+                if get_xnode_type(&step) == XNodeType::OperatorPath {
+                    step = get_left(&step);
+                }
+                is_nil_xnode(&step) || is_axis_xnode(&step)
+            },
+            XNodeType::AxisRoot |
+            XNodeType::AxisAncestor |
+            XNodeType::AxisAncestorOrSelf |
+            XNodeType::AxisAttribute |
+            XNodeType::AxisChild |
+            XNodeType::AxisDescendant |
+            XNodeType::AxisDescendantOrSelf |
+            XNodeType::AxisFollowing |
+            XNodeType::AxisFollowingSibling |
+            XNodeType::AxisNamespace |
+            XNodeType::AxisParent |
+            XNodeType::AxisPreceding |
+            XNodeType::AxisPrecedingSibling |
+            XNodeType::AxisSelf |
+            XNodeType::PredicateTop |
+            XNodeType::PredicateRevTop |
+            XNodeType::OperatorOr |
+            XNodeType::OperatorAnd |
+            XNodeType::OperatorGeneralEQ |
+            XNodeType::OperatorGeneralNE |
+            XNodeType::OperatorGeneralLT |
+            XNodeType::OperatorGeneralGT |
+            XNodeType::OperatorGeneralLE |
+            XNodeType::OperatorGeneralGE |
+            XNodeType::OperatorAdd |
+            XNodeType::OperatorSubtract |
+            XNodeType::OperatorUnaryMinus |
+            XNodeType::OperatorMultiply |
+            XNodeType::OperatorDiv |
+            XNodeType::OperatorMod |
+            XNodeType::OperatorUnion |
+            XNodeType::StringLiteral |
+            XNodeType::IntegerLiteral |
+            XNodeType::DecimalLiteral |
+            XNodeType::ContextItem |
+            XNodeType::ArgumentTop |
+            XNodeType::VarRef |
+            XNodeType::ApplyPredicate |
+            XNodeType::KindTest |
+            XNodeType::PITest |
+            XNodeType::CommentTest |
+            XNodeType::TextTest |
+            XNodeType::AnyKindTest => true,
+            XNodeType::ParenthesizedExpr => ! is_nil_xnode(&get_left(&curr)),
+                                        // 空シーケンス () は不可
+            XNodeType::FunctionCall => {
+                let func_name = get_xnode_name(&curr);
+                if ! XPATH10_FUNCTIONS.contains(&func_name.as_str()) {
+                    return Err(static_error!(
+                        "{}: not a function of XPath 1.0 [err:XPST0017]",
+                        func_name));
+                }
+                true
+            },
+            _ => false,
+        };
+        if ! allowed {
+            return Err(xpath_syntax_error!(
+                "{:?}: not available in XPath 1.0 [err:XPST0003]", n_type));
+        }
+        stack.push(get_right(&curr));
+        stack.push(get_left(&curr));
+    }
+    return Ok(());
+}
+
+// ---------------------------------------------------------------------
+//
+fn is_axis_xnode(xnode: &XNodePtr) -> bool {
+    match get_xnode_type(xnode) {
+        XNodeType::AxisAncestor |
+        XNodeType::AxisAncestorOrSelf |
+        XNodeType::AxisAttribute |
+        XNodeType::AxisChild |
+        XNodeType::AxisDescendant |
+        XNodeType::AxisDescendantOrSelf |
+        XNodeType::AxisFollowing |
+        XNodeType::AxisFollowingSibling |
+        XNodeType::AxisNamespace |
+        XNodeType::AxisParent |
+        XNodeType::AxisPreceding |
+        XNodeType::AxisPrecedingSibling |
+        XNodeType::AxisSelf => return true,
+        _ => return false,
+    }
+}
+
 // ---------------------------------------------------------------------
 // [  1] XPath ::= Expr
 //
@@ -358,9 +608,9 @@ fn parse_axis_step(lex: &mut Lexer) -> Result<XNodePtr, Box<Error>> {
             let axis = match axis_tbl.get(tok.get_name()) {
                 Some(a) => a,
                 None => {
-                    return Err(xpath_syntax_error!(
-                        "{}: 軸名として不正。",
-                        lex.around_tokens().as_str()));
+                    return Err(lex.syntax_error(&["axis name"],
+                    "invalid axis name",
+                    &format!("{}: 軸名として不正。", lex.around_tokens())));
                 },
             };
             if *axis == XNodeType::AxisNamespace {
@@ -391,7 +641,7 @@ fn parse_axis_step(lex: &mut Lexer) -> Result<XNodePtr, Box<Error>> {
 fn parse_node_test(lex: &mut Lexer, axis_type: &XNodeType) -> Result<XNodePtr, Box<Error>> {
     let mut name = parse_wildcard(lex)?;
     if name == "" {
-        name = parse_eqname(lex, "")?;
+        name = parse_eqname_as_is(lex, "")?;
     }
 
     if name != "" {                             // NameTestがあった場合
@@ -532,7 +782,7 @@ fn parse_element_test(lex: &mut Lexer) -> Result<XNodePtr, Box<Error>> {
     error_if_not_ttype!(lex, TType::LeftParen, "{}: 開き括弧が必要。");
     lex.get_token();
 
-    let mut element_name_or_wildcard = parse_eqname(lex, "")?;
+    let mut element_name_or_wildcard = parse_eqname_as_is(lex, "")?;
     if element_name_or_wildcard.as_str() == "" {
         if lex.next_token().get_type() == TType::Asterisk {
             lex.get_token();
@@ -546,9 +796,9 @@ fn parse_element_test(lex: &mut Lexer) -> Result<XNodePtr, Box<Error>> {
             lex.get_token();
             type_name = parse_eqname(lex, "xs")?;
             if type_name == "" {
-                return Err(xpath_syntax_error!(
-                        "{}: カンマの後に型名がない。",
-                        lex.around_tokens().as_str()));
+                return Err(lex.syntax_error(&["type name"],
+                    "type name expected after comma",
+                    &format!("{}: カンマの後に型名がない。", lex.around_tokens())));
             }
             if lex.next_token().get_type() == TType::Question {
                 lex.get_token();
@@ -590,7 +840,7 @@ fn parse_attribute_test(lex: &mut Lexer) -> Result<XNodePtr, Box<Error>> {
     error_if_not_ttype!(lex, TType::LeftParen, "{}: 開き括弧が必要。");
     lex.get_token();
 
-    let mut attribute_name_or_wildcard = parse_eqname(lex, "")?;
+    let mut attribute_name_or_wildcard = parse_eqname_as_is(lex, "")?;
     if attribute_name_or_wildcard.as_str() == "" {
         if lex.next_token().get_type() == TType::Asterisk {
             lex.get_token();
@@ -604,9 +854,9 @@ fn parse_attribute_test(lex: &mut Lexer) -> Result<XNodePtr, Box<Error>> {
             lex.get_token();
             type_name = parse_eqname(lex, "xs")?;
             if type_name == "" {
-                return Err(xpath_syntax_error!(
-                        "{}: カンマの後に型名がない。",
-                        lex.around_tokens().as_str()));
+                return Err(lex.syntax_error(&["type name"],
+                    "type name expected after comma",
+                    &format!("{}: カンマの後に型名がない。", lex.around_tokens())));
             }
         }
     }
@@ -664,9 +914,9 @@ fn parse_kind_test_sub_one(lex: &mut Lexer,
 
     let eqname = parse_eqname(lex, "")?;
     if eqname == "" {
-        return Err(xpath_syntax_error!(
-                    "{}: 名前が必要。",
-                    lex.around_tokens().as_str()));
+        return Err(lex.syntax_error(&["name"],
+                    "name expected",
+                    &format!("{}: 名前が必要。", lex.around_tokens())));
     }
 
     error_if_not_ttype!(lex, TType::RightParen, "{}: 閉じ括弧が必要。");
@@ -693,9 +943,9 @@ fn parse_pi_test(lex: &mut Lexer) -> Result<XNodePtr, Box<Error>> {
         },
         TType::RightParen => {},
         _ => {
-            return Err(xpath_syntax_error!(
-                        "{}: 名前または文字列が必要。",
-                        lex.around_tokens().as_str()));
+            return Err(lex.syntax_error(&["name", "string literal"],
+                    "name or string literal expected",
+                    &format!("{}: 名前または文字列が必要。", lex.around_tokens())));
         },
     }
 
@@ -832,6 +1082,10 @@ fn parse_expr(lex: &mut Lexer) -> Result<XNodePtr, Box<Error>> {
 //                    | OrExpr
 //
 fn parse_expr_single(lex: &mut Lexer) -> Result<XNodePtr, Box<Error>> {
+    if lex.stack_exhausted() {
+        return Err(static_error!("Expression nested too deeply."));
+    }
+
     let xnode = parse_for_expr(lex)?;
     return_if_non_nil!(xnode);
 
@@ -955,9 +1209,9 @@ fn parse_simple_let_binding(lex: &mut Lexer) -> Result<XNodePtr, Box<Error>> {
 
     let var_name = parse_eqname(lex, "")?;
     if var_name == "" {
-        return Err(xpath_syntax_error!(
-                    "{}: $ の後には変数名が必要。",
-                    lex.around_tokens().as_str()));
+        return Err(lex.syntax_error(&["variable name"],
+                    "variable name expected after $",
+                    &format!("{}: $ の後には変数名が必要。", lex.around_tokens())));
     }
 
     error_if_not_ttype!(lex, TType::Bind, "{}: 変数名の後に := が必要。");
@@ -1045,9 +1299,9 @@ fn parse_simple_binding(lex: &mut Lexer, xnode_type: &XNodeType) -> Result<XNode
 
     let var_name = parse_eqname(lex, "")?;
     if var_name == "" {
-        return Err(xpath_syntax_error!(
-                    "{}: $ の後には変数名が必要。",
-                    lex.around_tokens().as_str()));
+        return Err(lex.syntax_error(&["variable name"],
+                    "variable name expected after $",
+                    &format!("{}: $ の後には変数名が必要。", lex.around_tokens())));
     }
 
     error_if_not_name!(lex, "in", "{}: 変数名の後に in が必要。");
@@ -1088,8 +1342,9 @@ fn parse_if_expr(lex: &mut Lexer) -> Result<XNodePtr, Box<Error>> {
 
     let xnode_then = parse_expr_single(lex)?;
     if is_nil_xnode(&xnode_then) {
-        return Err(xpath_syntax_error!(
-                "{}: if文のthen節が不正。", lex.around_tokens().as_str()));
+        return Err(lex.syntax_error(&["expression"],
+                    "expression expected in then clause",
+                    &format!("{}: if文のthen節が不正。", lex.around_tokens())));
     }
 
     error_if_not_name!(lex, "else", "{}: if に対応する else が必要。");
@@ -1248,9 +1503,9 @@ fn parse_instanceof_expr(lex: &mut Lexer) -> Result<XNodePtr, Box<Error>> {
         lex.get_token();
         let seq_type_xnode = parse_sequence_type(lex)?;
         if is_nil_xnode(&seq_type_xnode) {
-            return Err(xpath_syntax_error!(
-                "{}: 「instance of」の後にはSequenceTypeが必要。",
-                lex.around_tokens().as_str()));
+            return Err(lex.syntax_error(&["SequenceType"],
+                    "SequenceType expected after \"instance of\"",
+                    &format!("{}: 「instance of」の後にはSequenceTypeが必要。", lex.around_tokens())));
         }
         let instance_of_xnode = new_xnode(XNodeType::OperatorInstanceOf, "");
         assign_as_left(&instance_of_xnode, &xnode);
@@ -1271,9 +1526,9 @@ fn parse_treat_expr(lex: &mut Lexer) -> Result<XNodePtr, Box<Error>> {
         lex.get_token();
         let seq_type_xnode = parse_sequence_type(lex)?;
         if is_nil_xnode(&seq_type_xnode) {
-            return Err(xpath_syntax_error!(
-                "{}: 「treat of」の後にはSequenceTypeが必要。",
-                lex.around_tokens().as_str()));
+            return Err(lex.syntax_error(&["SequenceType"],
+                    "SequenceType expected after \"treat as\"",
+                    &format!("{}: 「treat of」の後にはSequenceTypeが必要。", lex.around_tokens())));
         }
         let treat_as_xnode = new_xnode(XNodeType::OperatorTreatAs, "");
         assign_as_left(&treat_as_xnode, &xnode);
@@ -1469,8 +1724,9 @@ fn parse_map_test(lex: &mut Lexer) -> Result<XNodePtr, Box<Error>> {
     } else {
         let atomic_or_union_name = parse_eqname(lex, "xs")?;
         if atomic_or_union_name == "" {
-            return Err(xpath_syntax_error!("{}: キーの型の指定がない。",
-                        lex.around_tokens().as_str()));
+            return Err(lex.syntax_error(&["type name"],
+                    "key type expected",
+                    &format!("{}: キーの型の指定がない。", lex.around_tokens())));
         }
 
         let key_type_xnode = new_xnode(
@@ -1561,9 +1817,9 @@ fn parse_castable_expr(lex: &mut Lexer) -> Result<XNodePtr, Box<Error>> {
         lex.get_token();
         let single_type_xnode = parse_single_type(lex)?;
         if is_nil_xnode(&single_type_xnode) {
-            return Err(xpath_syntax_error!(
-                    "{}: キャストする型の名前が必要。",
-                    lex.around_tokens().as_str()));
+            return Err(lex.syntax_error(&["type name"],
+                    "type name expected for cast",
+                    &format!("{}: キャストする型の名前が必要。", lex.around_tokens())));
         }
         let castable_xnode = new_xnode(XNodeType::OperatorCastableAs, "");
         assign_as_left(&castable_xnode, &xnode);
@@ -1590,9 +1846,9 @@ fn parse_cast_expr(lex: &mut Lexer) -> Result<XNodePtr, Box<Error>> {
         lex.get_token();
         let single_type_xnode = parse_single_type(lex)?;
         if is_nil_xnode(&single_type_xnode) {
-            return Err(xpath_syntax_error!(
-                    "{}: キャストする型の名前が必要。",
-                    lex.around_tokens().as_str()));
+            return Err(lex.syntax_error(&["type name"],
+                    "type name expected for cast",
+                    &format!("{}: キャストする型の名前が必要。", lex.around_tokens())));
         }
         let cast_xnode = new_xnode(XNodeType::OperatorCastAs, "");
         assign_as_left(&cast_xnode, &xnode);
@@ -1628,7 +1884,7 @@ fn parse_single_type(lex: &mut Lexer) -> Result<XNodePtr, Box<Error>> {
 // [ 29] ArrowExpr ::= UnaryExpr ( "=>" ArrowFunctionSpecifier ArgumentList)*
 // [ 55] ArrowFunctionSpecifier ::= EQName
 //                                | VarRef
-//                                | ParenthesizedExpr                  ☆
+//                                | ParenthesizedExpr
 //
 // UnaryExprを第1引数とすることを除き、FunctionCallと同じ構文木を生成する。
 //
@@ -1640,7 +1896,7 @@ fn parse_single_type(lex: &mut Lexer) -> Result<XNodePtr, Box<Error>> {
 //                      |
 //                 (UnaryExpr)
 //
-// (ArrowFunctionSpecifier ::= VarRef の場合)
+// (ArrowFunctionSpecifier ::= VarRef | ParenthesizedExpr の場合)
 //
 // ApplyArgument --- ArgumentListTop
 //      |                 |
@@ -1667,7 +1923,10 @@ fn parse_arrow_expr(lex: &mut Lexer) -> Result<XNodePtr, Box<Error>> {
             continue;
         }
 
-        let varref_xnode = parse_varref(lex)?;
+        let mut varref_xnode = parse_varref(lex)?;
+        if is_nil_xnode(&varref_xnode) {
+            varref_xnode = parse_parenthesized_expr(lex)?;
+        }
         if ! is_nil_xnode(&varref_xnode) {
             let apply_argment_xnode = new_xnode(XNodeType::ApplyArgument, "");
             assign_as_left(&apply_argment_xnode, &varref_xnode);
@@ -1684,38 +1943,38 @@ fn parse_arrow_expr(lex: &mut Lexer) -> Result<XNodePtr, Box<Error>> {
             continue;
         }
 
-        return Err(xpath_syntax_error!(
-                    "{}: アロー演算子: 函数名が必要。",
-                    lex.around_tokens().as_str()));
+        return Err(lex.syntax_error(&["function name"],
+                    "function name expected after =>",
+                    &format!("{}: アロー演算子: 函数名が必要。", lex.around_tokens())));
     }
 
     return Ok(curr_xnode.clone());
 }
 
 // ---------------------------------------------------------------------
-// [ 23] UnaryExpr ::= ( "-" | "+" )? ValueExpr
+// [ 23] UnaryExpr ::= ( "-" | "+" )* ValueExpr
 //
 fn parse_unary_expr(lex: &mut Lexer) -> Result<XNodePtr, Box<Error>> {
-    let tok = lex.next_token();
-    match tok.get_type() {
-        TType::Minus => {
-            lex.get_token();
-            let next_node = parse_value_expr(lex)?;
-            let xnode_op = new_xnode(XNodeType::OperatorUnaryMinus, "-");
-            assign_as_right(&xnode_op, &next_node);
-            return Ok(xnode_op);
-        },
-        TType::Plus => {
-            lex.get_token();
-            let next_node = parse_value_expr(lex)?;
-            let xnode_op = new_xnode(XNodeType::OperatorUnaryPlus, "+");
-            assign_as_right(&xnode_op, &next_node);
-            return Ok(xnode_op);
-        },
-        _ => {
-            return parse_value_expr(lex);
+    let mut signs: Vec<XNodeType> = vec!{};
+    loop {
+        let tok = lex.next_token();
+        match tok.get_type() {
+            TType::Minus => signs.push(XNodeType::OperatorUnaryMinus),
+            TType::Plus => signs.push(XNodeType::OperatorUnaryPlus),
+            _ => break,
         }
+        lex.get_token();
+    }
+
+    // 符号は再帰せずに、内側 (右) から順に組み立てる。
+    let mut xnode = parse_value_expr(lex)?;
+    while let Some(sign) = signs.pop() {
+        let name = if sign == XNodeType::OperatorUnaryMinus { "-" } else { "+" };
+        let xnode_op = new_xnode(sign, name);
+        assign_as_right(&xnode_op, &xnode);
+        xnode = xnode_op;
     }
+    return Ok(xnode);
 }
 
 // ---------------------------------------------------------------------
@@ -2072,9 +2331,9 @@ fn parse_varref(lex: &mut Lexer) -> Result<XNodePtr, Box<Error>> {
             if eqname != "" {
                 return Ok(new_xnode(XNodeType::VarRef, eqname.as_str()));
             } else {
-                return Err(xpath_syntax_error!(
-                        "{}: 変数参照の $ に続いて名前が必要。",
-                        lex.around_tokens().as_str()));
+                return Err(lex.syntax_error(&["variable name"],
+                    "variable name expected after $",
+                    &format!("{}: 変数参照の $ に続いて名前が必要。", lex.around_tokens())));
             }
         },
         _ => {
@@ -2253,8 +2512,9 @@ fn parse_param(lex: &mut Lexer) -> Result<XNodePtr, Box<Error>> {
 
     let param_name = parse_eqname(lex, "")?;
     if param_name == "" {
-        return Err(xpath_syntax_error!(
-                    "{}: 引数名が必要。", lex.around_tokens().as_str()));
+        return Err(lex.syntax_error(&["parameter name"],
+                    "parameter name expected",
+                    &format!("{}: 引数名が必要。", lex.around_tokens())));
     }
 
     let tok = lex.next_token();
@@ -2289,7 +2549,7 @@ fn parse_function_body(lex: &mut Lexer) -> Result<XNodePtr, Box<Error>> {
     error_if_not_ttype!(lex, TType::LeftCurly, "{}: 函数本体を始める左波括弧が必要。");
     lex.get_token();
 
-    let xnode = parse_expr(lex)?;
+    let xnode = empty_sequence_if_nil(&parse_expr(lex)?);
 
     error_if_not_ttype!(lex, TType::RightCurly, "{}: 函数本体を閉じる右波括弧が必要。");
     lex.get_token();
@@ -2297,6 +2557,17 @@ fn parse_function_body(lex: &mut Lexer) -> Result<XNodePtr, Box<Error>> {
     return Ok(xnode);
 }
 
+// ---------------------------------------------------------------------
+// EnclosedExprの Expr を省略した「{}」は、空シーケンス「()」と同じ
+// 構文木にする。
+//
+fn empty_sequence_if_nil(xnode: &XNodePtr) -> XNodePtr {
+    if is_nil_xnode(xnode) {
+        return new_xnode(XNodeType::ParenthesizedExpr, "");
+    }
+    return xnode.clone();
+}
+
 // ---------------------------------------------------------------------
 // [ 69] MapConstructor ::= "map" "{" (MapConstructorEntry ("," MapConstructorEntry)*)? "}"
 // これを次のように分解する。
@@ -2419,7 +2690,7 @@ fn parse_curly_array_constructor(lex: &mut Lexer) -> Result<XNodePtr, Box<Error>
     error_if_not_ttype!(lex, TType::LeftCurly, "{}: 配列を開く左波括弧が必要。");
     lex.get_token();
 
-    let array_top_xnode = parse_expr(lex)?;
+    let array_top_xnode = empty_sequence_if_nil(&parse_expr(lex)?);
 
     error_if_not_ttype!(lex, TType::RightCurly, "{}: 配列を閉じる右波括弧が必要。");
     lex.get_token();
@@ -2549,6 +2820,7 @@ fn parse_function_call(lex: &mut Lexer) -> Result<XNodePtr, Box<Error>> {
     // func_nameは、必要ならば "fn:" を補った形になっている。
     //
     lex.mark_token_index();
+    let func_tok = lex.next_token();
     let func_name = parse_static_func_name(lex)?;
     if func_name == "" {                            // 非該当
         lex.restore_marked_index();
@@ -2580,9 +2852,11 @@ fn parse_function_call(lex: &mut Lexer) -> Result<XNodePtr, Box<Error>> {
     // この時点で函数表と照合して、函数の存在や引数の数を検査する。
     //
     if func::check_function_spec(&func_name, arity) == false {
-        return Err(xpath_syntax_error!(
-            "{}: 函数が未実装、または引数の数 ({}) が不適切。",
-            func_name, arity));
+        return Err(lex.syntax_error_at(&func_tok, &[],
+            &format!("{}: unknown function, or wrong number of arguments ({})",
+                func_name, arity),
+            &format!("{}: 函数が未実装、または引数の数 ({}) が不適切。",
+                func_name, arity)));
     }
 
     // -------------------------------------------------------------
@@ -2684,6 +2958,61 @@ fn parse_static_func_name(lex: &mut Lexer) -> Result<String, Box<Error>> {
 // [118] BracedURILiteral ::= "Q" "{" [^{}]* "}"
 //
 fn parse_eqname(lex: &mut Lexer, default_prefix: &str) -> Result<String, Box<Error>> {
+    let eqname = parse_eqname_as_is(lex, default_prefix)?;
+    return Ok(resolve_eqname(&eqname));
+}
+
+// ---------------------------------------------------------------------
+// 組み込みの名前空間: (接頭辞, 名前空間URI)。
+//
+const KNOWN_NAMESPACES: [(&str, &str); 5] = [
+    ( "fn", "http://www.w3.org/2005/xpath-functions" ),
+    ( "math", "http://www.w3.org/2005/xpath-functions/math" ),
+    ( "map", "http://www.w3.org/2005/xpath-functions/map" ),
+    ( "array", "http://www.w3.org/2005/xpath-functions/array" ),
+    ( "xs", "http://www.w3.org/2001/XMLSchema" ),
+];
+
+// ---------------------------------------------------------------------
+// URIQualifiedName (Q{uri}local) を、静的文脈での名前 (函数名、型名、
+// 変数名) の形式に変換する。
+//   Q{}local: 名前空間のない名前 local。
+//   Q{組み込みの名前空間のURI}local: 接頭辞付きの名前 (fn:local など)。
+// それ以外 (QName、未知のURI) はそのまま返す。
+//
+pub fn resolve_eqname(eqname: &str) -> String {
+    if let Some((uri, local)) = split_uri_qualified_name(eqname) {
+        if uri == "" {
+            return String::from(local);
+        }
+        for (prefix, ns_uri) in KNOWN_NAMESPACES.iter() {
+            if *ns_uri == uri {
+                return format!("{}:{}", prefix, local);
+            }
+        }
+    }
+    return String::from(eqname);
+}
+
+// ---------------------------------------------------------------------
+// URIQualifiedName (Q{uri}local) を (uri, local) に分ける。
+// その形式でなければNone。
+//
+pub fn split_uri_qualified_name(eqname: &str) -> Option<(&str, &str)> {
+    if ! eqname.starts_with("Q{") {
+        return None;
+    }
+    match eqname.find('}') {
+        Some(pos) => return Some((&eqname[2 .. pos], &eqname[pos + 1 ..])),
+        None => return None,
+    }
+}
+
+// ---------------------------------------------------------------------
+// parse_eqname() と同様。ただし URIQualifiedName を変換せずに返す
+// (名前テストで、名前空間URIによって照合するため)。
+//
+fn parse_eqname_as_is(lex: &mut Lexer, default_prefix: &str) -> Result<String, Box<Error>> {
     let qname = parse_qname(lex, default_prefix)?;
     if qname != "" {
         return Ok(qname);
@@ -2722,29 +3051,34 @@ fn parse_eqname(lex: &mut Lexer, default_prefix: &str) -> Result<String, Box<Err
 // default_prefix: "" でなければ、UnprefixedNameであった場合に、
 //                 これをprefixとして補う。
 //
+// QNameの途中には空白をはさめないので、コロンの前後が空いていれば
+// UnprefixedNameとし、コロンは残しておく (「map{$k : 1}」のコロン)。
+//
 fn parse_qname(lex: &mut Lexer, default_prefix: &str) -> Result<String, Box<Error>> {
-    let tok = lex.next_token();
-    if tok.get_type() != TType::Name {
+    let name_tok = lex.next_token();
+    if name_tok.get_type() != TType::Name {
         return Ok(String::new());
     }
 
-    let mut qname = String::from(tok.get_name());
+    let mut qname = String::from(name_tok.get_name());
     lex.get_token();
 
     let tok = lex.next_token();
-    if tok.get_type() != TType::Colon {
+    if tok.get_type() != TType::Colon || ! name_tok.is_adjacent_to(&tok) {
         if default_prefix != "" {
             return Ok(String::from(default_prefix) + &":" + &qname);
         } else {
             return Ok(qname);
         }
     }
-    qname += tok.get_name();        // ":"
-    lex.get_token();
-
-    error_if_not_ttype!(lex, TType::Name, "{}: QName: コロンの後には名前が必要。");
-    let tok = lex.get_token();
-    qname += tok.get_name();
+    let colon_tok = lex.get_token();
+    let local_tok = lex.next_token();
+    if local_tok.get_type() != TType::Name || ! colon_tok.is_adjacent_to(&local_tok) {
+        lex.unget_token();
+        return Ok(qname);
+    }
+    qname += colon_tok.get_name();  // ":"
+    qname += lex.get_token().get_name();
 
     return Ok(qname);
 }
@@ -2814,7 +3148,7 @@ fn parse_wildcard(lex: &mut Lexer) -> Result<String, Box<Error>> {
 
 // ---------------------------------------------------------------------
 //
-fn new_xnode(n_type: XNodeType, name: &str) -> XNodePtr {
+pub fn new_xnode(n_type: XNodeType, name: &str) -> XNodePtr {
     return XNodePtr{
         xnode_ptr: Rc::new(RefCell::new(XNode{
             n_type: n_type,
@@ -2849,6 +3183,18 @@ fn assign_as_right(parent: &XNodePtr, right: &XNodePtr) {
     }
 }
 
+// ---------------------------------------------------------------------
+// xnodeと同じ型、名前で、子をleft、rightに替えた新しいxnodeを作る。
+// xnode自身は書き替えない (構文木の最適化で使う)。
+//
+pub fn copy_xnode_with_children(xnode: &XNodePtr,
+                left: &XNodePtr, right: &XNodePtr) -> XNodePtr {
+    let new = new_xnode(get_xnode_type(xnode), &get_xnode_name(xnode));
+    assign_as_left(&new, left);
+    assign_as_right(&new, right);
+    return new;
+}
+
 // =====================================================================
 // xnode関係の補助函数 (参照のみおこなうもの; 公開)
 //
@@ -2865,6 +3211,13 @@ pub fn get_xnode_type(xnode: &XNodePtr) -> XNodeType {
     return xnode.xnode_ptr.borrow().n_type.clone();
 }
 
+// ---------------------------------------------------------------------
+// 構文木のノードの識別子 (ノードが存在する間は一意)。
+//
+pub fn get_xnode_id(xnode: &XNodePtr) -> usize {
+    return &*xnode.xnode_ptr as *const RefCell<XNode> as usize;
+}
+
 // ---------------------------------------------------------------------
 //
 pub fn is_nil_xnode(xnode: &XNodePtr) -> bool {
@@ -2907,6 +3260,8 @@ pub fn get_right(parent: &XNodePtr) -> XNodePtr {
 mod test {
 //    use super::*;
 
+    #[cfg(feature = "no_std")]
+    use std::prelude::*;
     use xpath_impl::lexer::*;
     use xpath_impl::parser::compile_xpath;
 
@@ -2936,5 +3291,62 @@ mod test {
         }
 //        assert_eq!("A", "Z");
     }
+
+    // -----------------------------------------------------------------
+    // 構文エラーの位置情報
+    //
+    #[test]
+    fn test_syntax_error_info() {
+        use xmlerror::*;
+
+        let specs: &[(&str, usize, usize, &[&str], &str)] = &[
+            ( "count(/root", 11, 0, &[")"],
+                r#"expected ")", found end of xpath"# ),
+            ( "(1, 2]", 5, 1, &[")"],
+                r#"expected ")", found "]""# ),
+            ( "for $x in 1 to 3 retrun $x", 17, 6, &["return"],
+                r#"expected "return", found "retrun""# ),
+            ( "if (1) then 2 else ", 19, 0, &["expression"],
+                "expression expected" ),
+            ( "1 + nosuch(2)", 4, 6, &[],
+                "fn:nosuch: unknown function, or wrong number of arguments (1)" ),
+            ( "'abc", 0, 4, &[],
+                "unexpected end of xpath in string literal" ),
+            ( "1 + # 2", 4, 1, &["end of xpath"],
+                "expected \"end of xpath\", found \"#\"" ),
+            ( "(1) instance of 2", 16, 1, &["SequenceType"],
+                r#"SequenceType expected after "instance of""# ),
+        ];
+        for (xpath, offset, length, expected, message) in specs.iter() {
+            let err = compile_xpath(&String::from(*xpath)).err().unwrap();
+            if ! cfg!(feature = "ja-messages") {
+                let head = format!("Syntax Error in XPath: {} at offset {}",
+                                message, offset);
+                assert!(err.to_string().starts_with(&head),
+                        "xpath = {}: {}", xpath, err);
+            }
+            let xml_err = err.downcast_ref::<XmlError>().unwrap();
+            assert_eq!(xml_err.error_type(), &XmlErrorType::XPathSyntaxError);
+            let info = xml_err.syntax_info().unwrap();
+            assert_eq!(info.offset(), *offset, "xpath = {}", xpath);
+            assert_eq!(info.length(), *length, "xpath = {}", xpath);
+            let expected: Vec<String> = expected.iter().map(|s| String::from(*s)).collect();
+            assert_eq!(info.expected(), &expected, "xpath = {}", xpath);
+        }
+
+        // 前後のトークンは、エラーの位置のトークンを ≪≫ で囲んで示す。
+        if ! cfg!(feature = "ja-messages") {
+            let specs: &[(&str, &str)] = &[
+                ( "current-dateTime(1)", "≪current-dateTime≫ (  1  ) " ),
+                ( "1 + nosuch(2)", " 1  + ≪nosuch≫ (  2  ) " ),
+                ( "count(/root", " /  root ≪≫" ),
+            ];
+            for (xpath, excerpt) in specs.iter() {
+                let err = compile_xpath(&String::from(*xpath)).err().unwrap();
+                assert!(err.to_string().ends_with(excerpt),
+                        "xpath = {}: {}", xpath, err);
+            }
+        }
+    }
 }
 
@@ -0,0 +1,282 @@
+//
+// xpath_impl/resolver.rs
+//
+// amxml: XML processor with XPath.
+// Copyright (C) 2018 KOYAMA Hiro <tac@amris.co.jp>
+//
+
+use std::error::Error;
+use std::fmt;
+use std::rc::Rc;
+#[cfg(feature = "no_std")]
+use std::prelude::*;
+
+use catalog::*;
+use dom::*;
+use xmlerror::*;
+use xpath_impl::parser::*;
+
+// ---------------------------------------------------------------------
+// URIスキームの処理函数: URIを受け取り、その資源の内容 (文字列) を返す。
+//
+pub type UriSchemeHandler = Rc<Fn(&str) -> Result<String, Box<Error>>>;
+
+// ---------------------------------------------------------------------
+// 外部の資源を取得する組み込み函数 (第1引数がURI)。
+//
+#[cfg_attr(not(feature="async"), allow(dead_code))]
+pub const RESOURCE_FUNCTIONS: [&str; 4] = [
+    "fn:doc", "fn:doc-available", "fn:unparsed-text", "fn:unparsed-text-available",
+];
+
+// =====================================================================
+// URIスキームの登録簿: スキーム名 → 処理函数。
+// fn:doc()、fn:unparsed-text() などが資源を取得するのに使う。
+// 最初は何も登録していない (どのURIも取得できない)。
+// カタログを設定した場合は、URIをカタログで写像してから取得する。
+// fn:collection() が返すノード列 (コレクションURI → ノード列) も
+// ここに登録する。
+//
+#[derive(Clone, Default)]
+pub struct ResolverRegistry {
+    handlers: Vec<(String, UriSchemeHandler)>,
+    catalog: Option<Rc<Catalog>>,
+    collections: Vec<(String, Rc<Vec<NodePtr>>)>,
+}
+
+impl fmt::Debug for ResolverRegistry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let schemes: Vec<&str> = self.handlers.iter()
+                        .map(|&(ref scheme, _)| scheme.as_str()).collect();
+        write!(f, "ResolverRegistry {:?}", schemes)?;
+        if self.catalog.is_some() {
+            write!(f, " (with catalog)")?;
+        }
+        if ! self.collections.is_empty() {
+            let uris: Vec<&str> = self.collections.iter()
+                        .map(|&(ref uri, _)| uri.as_str()).collect();
+            write!(f, " (collections {:?})", uris)?;
+        }
+        return Ok(());
+    }
+}
+
+pub fn new_resolver_registry() -> ResolverRegistry {
+    return ResolverRegistry{ handlers: vec!{}, catalog: None, collections: vec!{} };
+}
+
+impl ResolverRegistry {
+    // -----------------------------------------------------------------
+    // 処理函数を登録する。同じスキームで登録済みならば置き換える。
+    // スキーム名は大文字・小文字を区別しない。
+    //
+    pub fn register(&mut self, scheme: &str, handler: UriSchemeHandler) {
+        let scheme = scheme.to_ascii_lowercase();
+        for entry in self.handlers.iter_mut() {
+            if entry.0 == scheme {
+                entry.1 = handler;
+                return;
+            }
+        }
+        self.handlers.push((scheme, handler));
+    }
+
+    // -----------------------------------------------------------------
+    // fetch() の前にURIを写像するカタログを設定する。
+    //
+    pub fn set_catalog(&mut self, catalog: Catalog) {
+        self.catalog = Some(Rc::new(catalog));
+    }
+
+    // -----------------------------------------------------------------
+    // コレクションを登録する。同じURIで登録済みならば置き換える。
+    // URIが空文字列ならば、既定のコレクション (引数なしの
+    // fn:collection() が返すもの) とする。
+    //
+    pub fn add_collection(&mut self, uri: &str, nodes: Vec<NodePtr>) {
+        let nodes = Rc::new(nodes);
+        for entry in self.collections.iter_mut() {
+            if entry.0 == uri {
+                entry.1 = nodes;
+                return;
+            }
+        }
+        self.collections.push((String::from(uri), nodes));
+    }
+
+    // -----------------------------------------------------------------
+    // 登録してあるコレクションのノード列。
+    //
+    pub fn collection(&self, uri: &str) -> Option<Rc<Vec<NodePtr>>> {
+        for &(ref t_uri, ref nodes) in self.collections.iter() {
+            if t_uri == uri {
+                return Some(Rc::clone(nodes));
+            }
+        }
+        return None;
+    }
+
+    // -----------------------------------------------------------------
+    // コレクションに含まれる文書ノードのうち、文書URIが uri であるもの。
+    //
+    pub fn collection_document(&self, uri: &str) -> Option<NodePtr> {
+        for &(_, ref nodes) in self.collections.iter() {
+            for node in nodes.iter() {
+                if node.node_type() == NodeType::DocumentRoot &&
+                   node.document_uri().as_ref().map(|s| s.as_str()) == Some(uri) {
+                    return Some(node.rc_clone());
+                }
+            }
+        }
+        return None;
+    }
+
+    // -----------------------------------------------------------------
+    // 処理函数 (またはコレクション) を登録してあるか否か。
+    //
+    #[cfg(any(test, not(feature = "no_std")))]
+    pub fn has_handlers(&self) -> bool {
+        return ! self.handlers.is_empty() || ! self.collections.is_empty();
+    }
+
+    // -----------------------------------------------------------------
+    // URIの資源を、スキームに対応する処理函数で取得する。
+    // スキームのない (相対) URIはエラー [err:FODC0005]。
+    // 処理函数が登録されていない場合、または処理函数が失敗した場合は
+    // エラー [err:FODC0002]。
+    // カタログで写像できるURIは、写像した先から取得する。
+    //
+    pub fn fetch(&self, uri: &str) -> Result<String, Box<Error>> {
+        let mapped = match self.catalog {
+            Some(ref catalog) => catalog.resolve_uri(uri),
+            None => None,
+        };
+        let uri = match mapped {
+            Some(ref mapped) => mapped.as_str(),
+            None => uri,
+        };
+        let scheme = match uri_scheme(uri) {
+            Some(scheme) => scheme,
+            None => return Err(dynamic_error!(
+                    "Invalid URI (no scheme): {} [err:FODC0005]", uri)),
+        };
+        for &(ref t_scheme, ref handler) in self.handlers.iter() {
+            if *t_scheme == scheme {
+                return match handler(uri) {
+                    Ok(s) => Ok(s),
+                    Err(e) => Err(dynamic_error!(
+                        "Error retrieving resource: {}: {} [err:FODC0002]",
+                        uri, e)),
+                };
+            }
+        }
+        return Err(dynamic_error!(
+                "No handler for URI scheme: {} [err:FODC0002]", uri));
+    }
+}
+
+// ---------------------------------------------------------------------
+// URIのスキーム名 (小文字にしたもの)。スキームがなければNone。
+// scheme = ALPHA *( ALPHA / DIGIT / "+" / "-" / "." )   (RFC 3986)
+//
+pub fn uri_scheme(uri: &str) -> Option<String> {
+    let colon = match uri.find(':') {
+        Some(pos) => pos,
+        None => return None,
+    };
+    let scheme = &uri[.. colon];
+    let mut chars = scheme.chars();
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() => {},
+        _ => return None,
+    }
+    if ! chars.all(|c| c.is_ascii_alphanumeric() || c == '+' || c == '-' || c == '.') {
+        return None;
+    }
+    return Some(scheme.to_ascii_lowercase());
+}
+
+// ---------------------------------------------------------------------
+// 構文木のうち、資源を取得する組み込み函数 (RESOURCE_FUNCTIONS) の
+// 呼び出しで、URIを文字列リテラルで指定しているものについて、そのURIの
+// 一覧を (重複を除き、出現順に) 返す。
+// 評価前に資源を取得しておくために使う。
+//
+// FunctionCall --- ArgumentTop
+//  (fn:doc)            |
+//                 StringLiteral
+//
+#[cfg_attr(not(feature="async"), allow(dead_code))]
+pub fn collect_literal_uris(xnode: &XNodePtr) -> Vec<String> {
+    let mut uris: Vec<String> = vec!{};
+    let mut stack: Vec<XNodePtr> = vec!{xnode.clone()};
+    while let Some(curr) = stack.pop() {
+        if is_nil_xnode(&curr) {
+            continue;
+        }
+        if get_xnode_type(&curr) == XNodeType::FunctionCall &&
+           RESOURCE_FUNCTIONS.contains(&get_xnode_name(&curr).as_str()) {
+            let arg = get_left(&get_right(&curr));
+            if get_xnode_type(&arg) == XNodeType::StringLiteral {
+                let uri = get_xnode_name(&arg);
+                if ! uris.contains(&uri) {
+                    uris.push(uri);
+                }
+            }
+        }
+        stack.push(get_right(&curr));
+        stack.push(get_left(&curr));
+    }
+    return uris;
+}
+
+// =====================================================================
+//
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // -----------------------------------------------------------------
+    //
+    #[test]
+    fn test_resolver_registry() {
+        let mut registry = new_resolver_registry();
+        assert!(! registry.has_handlers());
+        assert!(registry.fetch("mem:a").is_err());
+
+        registry.register("MEM", Rc::new(|uri: &str| {
+            if uri == "mem:a" {
+                return Ok(String::from("<a/>"));
+            }
+            return Err(From::from("not found"));
+        }));
+        assert!(registry.has_handlers());
+        assert_eq!(registry.fetch("mem:a").unwrap(), "<a/>");
+        assert_eq!(registry.fetch("MEM:b").unwrap_err().to_string(),
+            "Dynamic Error: Error retrieving resource: MEM:b: not found [err:FODC0002]");
+        assert!(registry.fetch("mem:b").unwrap_err().to_string().contains("FODC0002"));
+        assert!(registry.fetch("http://x/").unwrap_err().to_string().contains("FODC0002"));
+        assert!(registry.fetch("a.xml").unwrap_err().to_string().contains("FODC0005"));
+    }
+
+    // -----------------------------------------------------------------
+    //
+    #[test]
+    fn test_uri_scheme() {
+        assert_eq!(uri_scheme("http://example.com/a"), Some(String::from("http")));
+        assert_eq!(uri_scheme("X-Mem+1.2:a"), Some(String::from("x-mem+1.2")));
+        assert_eq!(uri_scheme("a.xml"), None);
+        assert_eq!(uri_scheme("1a:b"), None);
+        assert_eq!(uri_scheme("a/b:c"), None);
+    }
+
+    // -----------------------------------------------------------------
+    //
+    #[test]
+    fn test_collect_literal_uris() {
+        let xpath = r#"doc("mem:a")//x | doc(concat("mem:", "b"))//x,
+                unparsed-text("mem:c"), doc-available("mem:a"), string("mem:d")"#;
+        let xnode = compile_xpath(&String::from(xpath)).unwrap();
+        assert_eq!(collect_literal_uris(&xnode), vec!{"mem:a", "mem:c"});
+    }
+}
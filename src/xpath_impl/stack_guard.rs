@@ -0,0 +1,84 @@
+//
+// xpath_impl/stack_guard.rs
+//
+// amxml: XML processor with XPath.
+// Copyright (C) 2018 KOYAMA Hiro <tac@amris.co.jp>
+//
+
+use std::hint;
+
+// ---------------------------------------------------------------------
+// スタック消費量の監視。
+// 構文解析も評価も再帰的に実装してあるので、入れ子の深い式 (括弧、
+// 長い演算子の連鎖) や、函数の再帰呼び出しでスタックを使い切ると、
+// プロセスごと異常終了してしまう。
+// 開始時点からのスタック消費量が STACK_BUDGET を超えたら、呼び出し側で
+// エラーを返すようにする。
+//
+// 1段あたりのスタック消費量はビルド (debugかreleaseか) によって大きく
+// 異なるので、入れ子の段数ではなく消費量で制限する。
+// スレッドの既定のスタックサイズは2MiB (主スレッドは多くの場合8MiB)
+// なので、それに収まる値にしてある。
+//
+const STACK_BUDGET: usize = 1024 * 1024;
+
+#[derive(Debug, Clone, Copy)]
+pub struct StackGuard {
+    base: usize,                // 開始時点のスタックのアドレス
+}
+
+// ---------------------------------------------------------------------
+// 現在のスタックのアドレス (の近似値)。
+//
+#[inline(never)]
+fn stack_address() -> usize {
+    let marker = 0u8;
+    return hint::black_box(&marker) as *const u8 as usize;
+}
+
+// ---------------------------------------------------------------------
+// 呼び出した時点を開始時点とする。
+//
+pub fn new_stack_guard() -> StackGuard {
+    return StackGuard{
+        base: stack_address(),
+    };
+}
+
+impl StackGuard {
+    // -----------------------------------------------------------------
+    // 開始時点からのスタック消費量が上限を超えたか否か。
+    // スタックの伸びる方向は問わない。
+    //
+    pub fn exhausted(&self) -> bool {
+        let curr = stack_address();
+        let used = if curr < self.base { self.base - curr } else { curr - self.base };
+        return STACK_BUDGET < used;
+    }
+}
+
+// =====================================================================
+//
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // 1段あたり1KiB以上のスタックを使う再帰。上限に達した段数を返す。
+    fn recurse(guard: &StackGuard, depth: usize) -> usize {
+        let padding = hint::black_box([0u8; 1024]);
+        if guard.exhausted() {
+            return depth;
+        }
+        return recurse(guard, depth + 1) + padding[0] as usize;
+    }
+
+    // -----------------------------------------------------------------
+    //
+    #[test]
+    fn test_stack_guard() {
+        let guard = new_stack_guard();
+        assert!(! guard.exhausted());
+        let depth = recurse(&guard, 0);
+        assert!(100 < depth && depth < 1024 + 1);
+    }
+}
@@ -5,6 +5,7 @@
 // Copyright (C) 2018 KOYAMA Hiro <tac@amris.co.jp>
 //
 
+use std::cmp::Ordering;
 use std::error::Error;
 use std::fmt;
 use std::f64;
@@ -12,9 +13,16 @@ use std::i64;
 use std::ops::Neg;
 use std::ops::Rem;
 use std::str::FromStr;
+#[cfg(feature = "no_std")]
+use std::prelude::*;
 
 use dom::*;
 use xmlerror::*;
+use binary::*;
+use datetime::*;
+use json::json_string;
+use xpath_impl::cast::cast_xitem;
+use xpath_impl::func::function_xnode_arity;
 use xpath_impl::parser::*;
 use xpath_impl::xsequence::*;
 
@@ -59,6 +67,9 @@ pub enum XItem {
     XItemXNodePtr {
         value: XNodePtr,
             // (内部処理用) インライン函数をXItemとして扱う。
+        captured: Vec<(String, XSequence)>,
+            // 函数アイテムを作った時点で捕捉した変数 (名前、値)。
+            // 呼び出すときに、この変数を束縛した上で評価する。
     },
     XIMap {
         value: XSeqMap,
@@ -72,10 +83,17 @@ pub enum XItem {
     XIString {
         value: String,
     },
+    XIAnyURI {
+        value: String,
+    },
     // XINormalizedString,
     // XIToken,
-    // XIBase64Binary,
-    // XIHexBinary,
+    XIBase64Binary {
+        value: Vec<u8>,
+    },
+    XIHexBinary {
+        value: Vec<u8>,
+    },
     XIInteger {
         value: i64,
     },
@@ -102,7 +120,9 @@ pub enum XItem {
         value: bool,
     },
     // XIDuration,
-    // XIDateTime,
+    XIDateTime {
+        value: DateTime,
+    },
     // XIDate,
     // XITime,
     // XIGYear,
@@ -113,7 +133,6 @@ pub enum XItem {
     // XIName,
     // XIQName,
     // XINCName,
-    // XIAnyURI,
     // XILanguage,
     // XIID,
     // XIIDREF,
@@ -132,14 +151,18 @@ pub struct XSeqMap {
     v: Vec<(XItem, XSequence)>,
 }
 
+// ---------------------------------------------------------------------
+// 適応的直列化 (Serialization 3.1, 10) にならい、map{key: value, ...}
+// の形で表記する。
+//
 impl fmt::Display for XSeqMap {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        let mut s = String::from("{");
+        let mut s = String::from("map{");
         for (i, v) in self.v.iter().enumerate() {
             if i != 0 {
                 s += &", ";
             }
-            s += &format!("{} => {}", v.0, v.1);
+            s += &format!("{}: {}", v.0, v.1);
         }
         s += &"}";
         return write!(f, "{}", s);
@@ -147,6 +170,32 @@ impl fmt::Display for XSeqMap {
 }
 
 impl XSeqMap {
+    // -----------------------------------------------------------------
+    // JSONのオブジェクトとして直列化する (Serialization 3.1, 11)。
+    // キーは文字列値に変換する。変換後に重複するキーがあれば
+    // エラー [err:SERE0022]。
+    //
+    pub fn to_xdm_json(&self) -> Result<String, Box<Error>> {
+        let mut keys: Vec<String> = vec!{};
+        let mut s = String::from("{");
+        for (i, v) in self.v.iter().enumerate() {
+            let key = v.0.get_as_raw_string()?;
+            if keys.contains(&key) {
+                return Err(dynamic_error!(
+                    "Duplicate key in JSON object: {} [err:SERE0022]", key));
+            }
+            if i != 0 {
+                s += &",";
+            }
+            s += &json_string(&key);
+            s += &":";
+            s += &v.1.to_xdm_json()?;
+            keys.push(key);
+        }
+        s += &"}";
+        return Ok(s);
+    }
+
     pub fn map_size(&self) -> usize {
         return self.v.len();
     }
@@ -176,6 +225,22 @@ impl XSeqMap {
         }
         return None;
     }
+
+    // -----------------------------------------------------------------
+    // keyの値をvalueに置き換えた (keyがなければ末尾に追加した) マップを
+    // 新たに作って返す。selfは変更しない。
+    //
+    pub fn map_put(&self, key: &XItem, value: &XSequence) -> XSeqMap {
+        let mut v = self.v.clone();
+        for entry in v.iter_mut() {
+            if entry.0.op_same_key(key) {
+                entry.1 = value.clone();
+                return XSeqMap{ v: v };
+            }
+        }
+        v.push((key.clone(), value.clone()));
+        return XSeqMap{ v: v };
+    }
 }
 
 // =====================================================================
@@ -200,6 +265,21 @@ impl fmt::Display for XSeqArray {
 }
 
 impl XSeqArray {
+    // -----------------------------------------------------------------
+    // JSONの配列として直列化する (Serialization 3.1, 11)。
+    //
+    pub fn to_xdm_json(&self) -> Result<String, Box<Error>> {
+        let mut s = String::from("[");
+        for (i, v) in self.v.iter().enumerate() {
+            if i != 0 {
+                s += &",";
+            }
+            s += &v.to_xdm_json()?;
+        }
+        s += &"]";
+        return Ok(s);
+    }
+
     pub fn array_size(&self) -> usize {
         return self.v.len();
     }
@@ -217,6 +297,25 @@ impl XSeqArray {
         return None;
     }
 
+    // -----------------------------------------------------------------
+    // index番目 (1始まり) の値をvalueに置き換えた配列を新たに作って返す。
+    // selfは変更しない。indexが範囲外ならばNone。
+    //
+    pub fn array_put(&self, index: &XItem, value: &XSequence) -> Option<XSeqArray> {
+        let i = index.get_as_raw_integer();
+        match i {
+            Ok(i) => {
+                if 1 <= i && i <= self.v.len() as i64 {
+                    let mut v = self.v.clone();
+                    v[(i - 1) as usize] = value.clone();
+                    return Some(XSeqArray{ v: v });
+                }
+            },
+            _ => {},
+        }
+        return None;
+    }
+
     pub fn array_flatten(&self) -> XSequence {
         let mut result = new_xsequence();
         for xseq in self.v.iter() {
@@ -240,6 +339,14 @@ impl XSeqArray {
 pub fn new_xitem_xnodeptr(xnode: &XNodePtr) -> XItem {
     return XItem::XItemXNodePtr {
         value: xnode.clone(),
+        captured: vec!{},
+    }
+}
+
+pub fn new_xitem_closure(xnode: &XNodePtr, captured: &Vec<(String, XSequence)>) -> XItem {
+    return XItem::XItemXNodePtr {
+        value: xnode.clone(),
+        captured: captured.clone(),
     }
 }
 
@@ -269,6 +376,10 @@ pub fn new_xitem_string(value: &str) -> XItem {
     return XItem::XIString{value: value.to_string()};
 }
 
+pub fn new_xitem_any_uri(value: &str) -> XItem {
+    return XItem::XIAnyURI{value: value.to_string()};
+}
+
 pub fn new_xitem_integer(value: i64) -> XItem {
     return XItem::XIInteger{value};
 }
@@ -285,6 +396,18 @@ pub fn new_xitem_boolean(value: bool) -> XItem {
     return XItem::XIBoolean{value};
 }
 
+pub fn new_xitem_base64_binary(value: &[u8]) -> XItem {
+    return XItem::XIBase64Binary{value: value.to_vec()};
+}
+
+pub fn new_xitem_hex_binary(value: &[u8]) -> XItem {
+    return XItem::XIHexBinary{value: value.to_vec()};
+}
+
+pub fn new_xitem_date_time(value: &DateTime) -> XItem {
+    return XItem::XIDateTime{value: value.clone()};
+}
+
 // =====================================================================
 //
 impl NodePtr {
@@ -295,11 +418,7 @@ impl NodePtr {
     fn string_value(&self) -> String {
         match self.node_type() {
             NodeType::DocumentRoot | NodeType::Element => {
-                let mut s = String::new();
-                for ch in self.children().iter() {
-                    s += &ch.string_value();
-                }
-                return s;
+                return self.concat_descendants(NodePtr::string_value);
             },
             NodeType::Text | NodeType::Attribute | NodeType::Comment => {
                 return format!("{}", self.value());
@@ -341,11 +460,7 @@ impl NodePtr {
                 return format!("{}", self.value());
             },
             NodeType::DocumentRoot => {             // xs:untypedAtomic
-                let mut s = String::new();
-                for ch in self.children().iter() {
-                    s += &ch.typed_value();
-                }
-                return s;
+                return self.concat_descendants(NodePtr::typed_value);
             },
             NodeType::Comment => {                  // xs:string
                 return format!("{}", self.value());
@@ -357,20 +472,44 @@ impl NodePtr {
                 return format!("{}", self.value());
             },
             NodeType::Element => {                  // xs:untypedAtomic
-                let mut s = String::new();
-                for ch in self.children().iter() {
-                    s += &ch.typed_value();
-                }
-                return s;
+                return self.concat_descendants(NodePtr::typed_value);
             },
             _ => return String::new(),
         }
     }
 
+    // -----------------------------------------------------------------
+    // 子孫のうち、文書根、要素以外のノードそれぞれに leaf_value を適用し、
+    // 文書順に連結する。
+    // 深い木でもスタックを使い切らないよう、再帰せず作業スタックでたどる。
+    //
+    fn concat_descendants(&self, leaf_value: fn(&NodePtr) -> String) -> String {
+        let mut s = String::new();
+        let mut stack: Vec<NodePtr> = self.children();
+        stack.reverse();
+        while let Some(curr) = stack.pop() {
+            match curr.node_type() {
+                NodeType::DocumentRoot | NodeType::Element => {
+                    let mut children = curr.children();
+                    children.reverse();
+                    stack.append(&mut children);
+                },
+                NodeType::XMLDecl | NodeType::Directive => {},
+                _ if curr.is_prolog_white_space() => {},
+                _ => s += &leaf_value(&curr),
+            }
+        }
+        return s;
+    }
+
 }
 
 // =====================================================================
 // Trait std::fmt::Display
+// 適応的直列化 (Serialization 3.1, 10) にならう。ただし、原子値以外の
+// ノードは簡略に表記する (NodePtr の Display)。
+// 文字列中の「"」は二重にする。函数アイテムは「名前#引数の個数」、
+// 無名函数ならば「(anonymous-function)#引数の個数」。
 //
 impl fmt::Display for XItem {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
@@ -379,7 +518,10 @@ impl fmt::Display for XItem {
                 return write!(f, "{}", value);
             },
             XItem::XIString{value} => {
-                return write!(f, r#""{}""#, value);
+                return write!(f, r#""{}""#, value.replace('"', r#""""#));
+            },
+            XItem::XIAnyURI{value} => {
+                return write!(f, r#"xs:anyURI("{}")"#, value.replace('"', r#""""#));
             },
             XItem::XIInteger{value} => {
                 return write!(f, "{}", value);
@@ -413,8 +555,23 @@ impl fmt::Display for XItem {
                     return write!(f, "false");
                 }
             },
-            XItem::XItemXNodePtr{value} => {
-                return write!(f, "{}", value);
+            XItem::XIBase64Binary{value} => {
+                return write!(f, r#"xs:base64Binary("{}")"#, encode_base64(value));
+            },
+            XItem::XIHexBinary{value} => {
+                return write!(f, r#"xs:hexBinary("{}")"#, encode_hex(value));
+            },
+            XItem::XIDateTime{value} => {
+                return write!(f, r#"xs:dateTime("{}")"#, value);
+            },
+            XItem::XItemXNodePtr{value, ..} => {
+                let arity = function_xnode_arity(value).unwrap_or(0);
+                if get_xnode_type(value) == XNodeType::NamedFunctionRef {
+                    let name = get_xnode_name(value);
+                    let name = name.split("#").next().unwrap_or("");
+                    return write!(f, "{}#{}", name, arity);
+                }
+                return write!(f, "(anonymous-function)#{}", arity);
             },
             XItem::XIMap{value} => {
                 return write!(f, "{}", value);
@@ -430,21 +587,72 @@ impl fmt::Display for XItem {
 //
 impl XItem {
 
+    // -----------------------------------------------------------------
+    // JSONの値として直列化する (Serialization 3.1, 11)。
+    // - 数値と真偽値はそのまま、NaN と無限大はエラー [err:SERE0020]。
+    // - ノードはXMLとして直列化した文字列。属性はエラー [err:SENR0001]。
+    // - 函数アイテム (マップ、配列以外) はエラー [err:SERE0021]。
+    // - その他の原子値は文字列値。
+    //
+    pub fn to_xdm_json(&self) -> Result<String, Box<Error>> {
+        match self {
+            XItem::XIInteger{value} => return Ok(format!("{}", value)),
+            XItem::XIDecimal{value} | XItem::XIDouble{value} => {
+                if value.is_nan() || value.is_infinite() {
+                    return Err(dynamic_error!(
+                        "Can't serialize {} as JSON number [err:SERE0020]", self));
+                }
+                return Ok(format!("{}", value));
+            },
+            XItem::XIBoolean{value} => return Ok(format!("{}", value)),
+            XItem::XINode{value} => {
+                if value.node_type() == NodeType::Attribute {
+                    return Err(dynamic_error!(
+                        "Can't serialize attribute as JSON: {} [err:SENR0001]", value));
+                }
+                return Ok(json_string(&value.to_string()));
+            },
+            XItem::XIMap{value} => return value.to_xdm_json(),
+            XItem::XIArray{value} => return value.to_xdm_json(),
+            XItem::XItemXNodePtr{..} => {
+                return Err(dynamic_error!(
+                    "Can't serialize function item as JSON: {} [err:SERE0021]", self));
+            },
+            _ => return Ok(json_string(&self.get_as_raw_string()?)),
+        }
+    }
+
+    // -----------------------------------------------------------------
+    // 誤りメッセージ用の文字列化: ノードは、文書中の位置も示す。
+    //
+    pub fn describe(&self) -> String {
+        match self {
+            XItem::XINode{value} => {
+                return format!("{} (at {})", value, value.display_path());
+            },
+            _ => return self.to_string(),
+        }
+    }
+
     // -----------------------------------------------------------------
     //
     pub fn xs_type(&self) -> String {
         match self {
-            XItem::XItemXNodePtr{value: _} => return String::from("function(*)"),
+            XItem::XItemXNodePtr{..} => return String::from("function(*)"),
             XItem::XIMap{value: _} => return String::from("map(*)"),
             XItem::XIArray{value: _} => return String::from("array(*)"),
             XItem::XINode{value: _} => return String::from("node"),
 
 
             XItem::XIString{value: _} => return String::from("xs:string"),
+            XItem::XIAnyURI{value: _} => return String::from("xs:anyURI"),
             XItem::XIInteger{value: _} => return String::from("xs:integer"),
             XItem::XIDecimal{value: _} => return String::from("xs:decimal"),
             XItem::XIDouble{value: _} => return String::from("xs:double"),
             XItem::XIBoolean{value: _} => return String::from("xs:boolean"),
+            XItem::XIBase64Binary{value: _} => return String::from("xs:base64Binary"),
+            XItem::XIHexBinary{value: _} => return String::from("xs:hexBinary"),
+            XItem::XIDateTime{value: _} => return String::from("xs:dateTime"),
         }
     }
 
@@ -482,11 +690,39 @@ impl XItem {
     //
     pub fn is_item(&self) -> bool {
         match self {
-            XItem::XItemXNodePtr{value: _} => return false,
+            XItem::XItemXNodePtr{..} => return false,
             _ => return true,
         }
     }
 
+    // -----------------------------------------------------------------
+    //
+    pub fn is_node(&self) -> bool {
+        match self {
+            XItem::XINode{value: _} => return true,
+            _ => return false,
+        }
+    }
+
+    // -----------------------------------------------------------------
+    //
+    pub fn is_binary(&self) -> bool {
+        match self {
+            XItem::XIBase64Binary{value: _} => return true,
+            XItem::XIHexBinary{value: _} => return true,
+            _ => return false,
+        }
+    }
+
+    // -----------------------------------------------------------------
+    //
+    pub fn is_date_time(&self) -> bool {
+        match self {
+            XItem::XIDateTime{value: _} => return true,
+            _ => return false,
+        }
+    }
+
     // -----------------------------------------------------------------
     //
     pub fn is_numeric(&self) -> bool {
@@ -540,48 +776,19 @@ impl XItem {
     }
 
     // -----------------------------------------------------------------
-    // キャスト。
+    // キャスト (xpath_impl::cast の表による)。
     //     原子化を施してからキャストするので、XItem::XINodeの場合については
     //     考えなくてよい。
     //
     pub fn cast_as(&self, type_name: &str) -> Result<XItem, Box<Error>> {
-        match type_name {
-            "string" | "xs:string" => {
-                if let Ok(s) = self.get_as_raw_string() {
-                    return Ok(new_xitem_string(&s));
-                }
-            },
-            "double" | "xs:double" => {
-                if let Ok(d) = self.get_as_raw_double() {
-                    return Ok(new_xitem_double(d));
-                }
-            },
-            "decimal" | "xs:decimal" => {
-                if let Ok(d) = self.get_as_raw_decimal() {
-                    return Ok(new_xitem_decimal(d));
-                }
-            },
-            "integer" | "xs:integer" => {
-                if let Ok(i) = self.get_as_raw_integer() {
-                    return Ok(new_xitem_integer(i));
-                }
-            },
-            "boolean" | "xs:boolean" => {
-                if let Ok(b) = self.get_as_raw_boolean() {
-                    return Ok(new_xitem_boolean(b));
-                }
-            },
-            _ => {},
-        }
-        return Err(type_error!("Item {}: can't cast to {}",
-                                self.to_string(), type_name));
+        return cast_xitem(self, type_name);
     }
 
     // -----------------------------------------------------------------
     //
     pub fn get_as_raw_xnodeptr(&self) -> Result<XNodePtr, Box<Error>> {
         match self {
-            XItem::XItemXNodePtr{value} => {
+            XItem::XItemXNodePtr{value, ..} => {
                 return Ok(value.clone());
             },
             _ => {
@@ -626,6 +833,9 @@ impl XItem {
             XItem::XIString{value} => {
                 return Ok(value.clone());
             },
+            XItem::XIAnyURI{value} => {
+                return Ok(value.clone());
+            },
             XItem::XIInteger{value} => {
                 return Ok(String::from(format!("{}", value)));
             },
@@ -652,43 +862,51 @@ impl XItem {
                     return Ok(String::from("false"));
                 }
             },
+            XItem::XIBase64Binary{value} => {
+                return Ok(encode_base64(value));
+            },
+            XItem::XIHexBinary{value} => {
+                return Ok(encode_hex(value));
+            },
+            XItem::XIDateTime{value} => {
+                return Ok(value.to_string());
+            },
             _ => {},
         }
         return Err(type_error!(
-                "Item {}: can't cast to string", self.to_string()));
+                "Item {}: can't cast to string", self.describe()));
     }
 
     // -----------------------------------------------------------------
+    // 二進データ (xs:base64Binary、xs:hexBinary) のオクテット列。
+    // 文字列を字句表現としてデコードすることはしない
+    // (どちらの型の字句表現かは、呼び出し側で判断する)。
     //
-    pub fn get_as_raw_double(&self) -> Result<f64, Box<Error>> {
+    pub fn get_as_raw_binary(&self) -> Result<Vec<u8>, Box<Error>> {
         match self {
-            XItem::XINode{value} => {
-                return Ok(atof(&value.string_value()));
-            },
-            XItem::XIString{ref value} => {
-                return Ok(atof(value.as_str()));
-            },
-            XItem::XIInteger{ref value} => {
-                return Ok(i64_to_f64(*value));
-            },
-            XItem::XIDecimal{ref value} => {
-                return Ok(*value);
-            },
-            XItem::XIDouble{ref value} => {
-                return Ok(*value);
-            },
-            XItem::XIBoolean{value} => {
-                return Ok(if *value == true { 1.0 } else { 0.0 });
-            },
+            XItem::XIBase64Binary{value} => return Ok(value.clone()),
+            XItem::XIHexBinary{value} => return Ok(value.clone()),
             _ => {},
         }
         return Err(type_error!(
-                "Item {}: can't cast to double", self.to_string()));
+                "Item {}: can't cast to binary", self.describe()));
     }
 
     // -----------------------------------------------------------------
+    // xs:dateTime の値。文字列を字句表現として解析することはしない。
     //
-    pub fn get_as_raw_decimal(&self) -> Result<f64, Box<Error>> {
+    pub fn get_as_raw_date_time(&self) -> Result<DateTime, Box<Error>> {
+        match self {
+            XItem::XIDateTime{value} => return Ok(value.clone()),
+            _ => {},
+        }
+        return Err(type_error!(
+                "Item {}: can't cast to dateTime", self.describe()));
+    }
+
+    // -----------------------------------------------------------------
+    //
+    pub fn get_as_raw_double(&self) -> Result<f64, Box<Error>> {
         match self {
             XItem::XINode{value} => {
                 return Ok(atof(&value.string_value()));
@@ -711,7 +929,7 @@ impl XItem {
             _ => {},
         }
         return Err(type_error!(
-                "Item {}: can't cast to decimal", self.to_string()));
+                "Item {}: can't cast to double", self.describe()));
     }
 
     // -----------------------------------------------------------------
@@ -739,7 +957,7 @@ impl XItem {
             _ => {},
         }
         return Err(type_error!(
-                "Item {}: can't cast to integer", self.to_string()));
+                "Item {}: can't cast to integer", self.describe()));
     }
 
     // -----------------------------------------------------------------
@@ -781,7 +999,7 @@ impl XItem {
             _ => {},
         }
         return Err(type_error!(
-                "Item {}: can't cast to boolean", self.to_string()));
+                "Item {}: can't cast to boolean", self.describe()));
     }
 }
 
@@ -789,6 +1007,8 @@ impl XItem {
 // 文字列としての比較。
 //
 pub fn xitem_compare(lhs: &XItem, rhs: &XItem) -> Result<i64, Box<Error>> {
+    let lhs = &promote_any_uri(lhs);
+    let rhs = &promote_any_uri(rhs);
     match lhs {
         XItem::XIString{value: lhs} => {
             match rhs {
@@ -809,6 +1029,61 @@ pub fn xitem_compare(lhs: &XItem, rhs: &XItem) -> Result<i64, Box<Error>> {
     return Err(type_error!("xitem_compare: Not string"));
 }
 
+// ---------------------------------------------------------------------
+// xs:anyURI を xs:string に昇格する (B.1 Type Promotion)。
+//
+fn promote_any_uri(xitem: &XItem) -> XItem {
+    match xitem {
+        XItem::XIAnyURI{value} => return new_xitem_string(value),
+        _ => return xitem.clone(),
+    }
+}
+
+// ---------------------------------------------------------------------
+// 二進データ (xs:base64Binary、xs:hexBinary) としての比較。
+// オクテット列を辞書順に比較する。
+// 両辺が同じ型でなければエラー。ただし一方が文字列ならば、
+// 他方の型にキャストしてから比較する (型註釈がないので、文字列は
+// xs:untypedAtomic であるとみなす)。
+//
+pub fn xitem_binary_compare(lhs: &XItem, rhs: &XItem) -> Result<i64, Box<Error>> {
+    let (lhs, rhs) = match (lhs.is_binary(), rhs.is_binary()) {
+        (true, true) => (lhs.clone(), rhs.clone()),
+        (true, false) => (lhs.clone(), rhs.cast_as(&lhs.xs_type())?),
+        (false, true) => (lhs.cast_as(&rhs.xs_type())?, rhs.clone()),
+        (false, false) => return Err(type_error!("xitem_binary_compare: Not binary")),
+    };
+    if lhs.xs_type() != rhs.xs_type() {
+        return Err(type_error!(
+                "xitem_binary_compare: {} and {} can't compare",
+                lhs.xs_type(), rhs.xs_type()));
+    }
+    let lhs = lhs.get_as_raw_binary()?;
+    let rhs = rhs.get_as_raw_binary()?;
+    if lhs < rhs {
+        return Ok(-1);
+    } else if lhs == rhs {
+        return Ok(0);
+    } else {
+        return Ok(1);
+    }
+}
+
+// ---------------------------------------------------------------------
+// xs:dateTime としての比較。時点を比較する (時間帯のない値は UTC とみなす)。
+// 一方が文字列ならば、xs:dateTime にキャストしてから比較する
+// (xitem_binary_compare と同様、文字列は xs:untypedAtomic とみなす)。
+//
+pub fn xitem_date_time_compare(lhs: &XItem, rhs: &XItem) -> Result<i64, Box<Error>> {
+    let lhs = lhs.cast_as("xs:dateTime")?.get_as_raw_date_time()?;
+    let rhs = rhs.cast_as("xs:dateTime")?.get_as_raw_date_time()?;
+    match lhs.compare(&rhs) {
+        Ordering::Less => return Ok(-1),
+        Ordering::Equal => return Ok(0),
+        Ordering::Greater => return Ok(1),
+    }
+}
+
 // ---------------------------------------------------------------------
 //
 pub fn xitem_numeric_add(lhs: &XItem, rhs: &XItem) -> Result<XItem, Box<Error>> {
@@ -833,24 +1108,20 @@ pub fn xitem_numeric_multiply(lhs: &XItem, rhs: &XItem) -> Result<XItem, Box<Err
 }
 
 // ---------------------------------------------------------------------
+// 4.2.4 op:numeric-divide
+// - integer / decimal の除数がゼロならばエラー [err:FOAR0001]。
+// - 型の昇格によって double 同士の除算になる場合は、IEEE 754 に従い、
+//   INF、-INF、NaN になる。
+// - integer div integer の結果は decimal。
 //
 pub fn xitem_numeric_divide(lhs: &XItem, rhs: &XItem) -> Result<XItem, Box<Error>> {
+    if is_zero_divisor(lhs, rhs) {
+        return Err(dynamic_error!("Division by zero [err:FOAR0001]"));
+    }
     let rhs_a = match rhs {
-        XItem::XIInteger{value: rhs} => {
-            if *rhs == 0 {
-                return Err(dynamic_error!("Division by zero"));
-            }
-            new_xitem_decimal(i64_to_f64(*rhs))
+        XItem::XIInteger{value: rhs} => new_xitem_decimal(i64_to_f64(*rhs)),
                             // 例外: Integer div Integer => Decimal
-        },
-        XItem::XIDecimal{value: rhs} => {
-            if *rhs == 0.0 {
-                return Err(dynamic_error!("Division by zero"));
-            }
-            new_xitem_decimal(*rhs)
-        },
-        XItem::XIDouble{value: rhs} => new_xitem_double(*rhs),
-        _ => return Err(cant_occur!("xitem_numeric_divide: rhs_a")),
+        _ => rhs.clone(),
     };
     return xitem_numeric_operation(lhs, &rhs_a,
                 |a, b| { a / b },
@@ -859,68 +1130,85 @@ pub fn xitem_numeric_divide(lhs: &XItem, rhs: &XItem) -> Result<XItem, Box<Error
 }
 
 // ---------------------------------------------------------------------
+// 4.2.5 op:numeric-integer-divide
+// - 除数がゼロ (double の場合も) ならばエラー [err:FOAR0001]。
+// - いずれかが NaN、または被除数が INF / -INF ならばエラー [err:FOAR0002]。
+// - 除数が INF / -INF で被除数が有限ならば 0。
+// - それ以外は、(lhs div rhs) の小数部を (ゼロ方向に) 切り捨てた整数。
+//   結果が integer の範囲を超える場合もエラー [err:FOAR0002]。
 //
 pub fn xitem_numeric_integer_divide(lhs: &XItem, rhs: &XItem) -> Result<XItem, Box<Error>> {
-    match lhs {
-        XItem::XIDouble{value} => {
-            if value.is_nan() {
-                return Err(dynamic_error!("Numeric operation overflow/underflow."));
-            }
-            if ! value.is_finite() {
-                return Err(dynamic_error!("Numeric operation overflow/underflow."));
-            }
-        },
-        _ => {},
+    if ! lhs.is_numeric() || ! rhs.is_numeric() {
+        return Err(type_error!("xitem_numeric_operation: Not numeric"));
     }
-    match rhs {
-        XItem::XIDouble{value} => {
-            if value.is_nan() {
-                return Err(dynamic_error!("Numeric operation overflow/underflow."));
+    if let XItem::XIDouble{value} = lhs {
+        if value.is_nan() || value.is_infinite() {
+            return Err(dynamic_error!("Numeric operation overflow/underflow [err:FOAR0002]"));
+        }
+    }
+    if let XItem::XIDouble{value} = rhs {
+        if value.is_nan() {
+            return Err(dynamic_error!("Numeric operation overflow/underflow [err:FOAR0002]"));
+        }
+    }
+
+    if let XItem::XIInteger{value: lhs} = lhs {
+        if let XItem::XIInteger{value: rhs} = rhs {
+            if *rhs == 0 {
+                return Err(dynamic_error!("Division by zero [err:FOAR0001]"));
             }
-        },
-        _ => {},
+            match lhs.checked_div(*rhs) {
+                Some(q) => return Ok(new_xitem_integer(q)),
+                None => return Err(dynamic_error!(
+                        "Numeric operation overflow/underflow [err:FOAR0002]")),
+            }
+        }
     }
 
-    let lhs = match lhs {
-        XItem::XIInteger{value} => *value,
-        XItem::XIDecimal{value} => f64_to_i64(*value),
-        XItem::XIDouble{value} => f64_to_i64(*value),
-        _ => 0,
-    };
-    let rhs = match rhs {
-        XItem::XIInteger{value} => *value,
-        XItem::XIDecimal{value} => f64_to_i64(*value),
-        XItem::XIDouble{value} => f64_to_i64(*value),
-        _ => 0,
-    };
-    if rhs != 0 {
-        return Ok(new_xitem_integer(lhs / rhs));
-    } else {
-        return Err(dynamic_error!("Division by zero"));
+    let lhs = lhs.get_as_raw_double()?;
+    let rhs = rhs.get_as_raw_double()?;
+    if rhs == 0.0 {
+        return Err(dynamic_error!("Division by zero [err:FOAR0001]"));
     }
+    let q = (lhs / rhs).trunc();
+    if ! (i64_to_f64(i64::MIN) <= q && q < -i64_to_f64(i64::MIN)) {
+        return Err(dynamic_error!("Numeric operation overflow/underflow [err:FOAR0002]"));
+    }
+    return Ok(new_xitem_integer(f64_to_i64(q)));
 }
 
 // ---------------------------------------------------------------------
+// 4.2.6 op:numeric-mod
+// - integer / decimal の除数がゼロならばエラー [err:FOAR0001]。
+// - double の場合は IEEE 754 の剰余 (結果の符号は被除数と同じ):
+//   いずれかが NaN、被除数が INF / -INF、除数がゼロならば NaN。
+//   除数が INF / -INF で被除数が有限ならば被除数。
 //
 pub fn xitem_numeric_mod(lhs: &XItem, rhs: &XItem) -> Result<XItem, Box<Error>> {
-    match rhs {
-        XItem::XIInteger{value: rhs} => {
-            if *rhs == 0 {
-                return Err(dynamic_error!("Division by zero"));
-            }
-        },
-        XItem::XIDecimal{value: rhs} => {
-            if *rhs == 0.0 {
-                return Err(dynamic_error!("Division by zero"));
-            }
-        },
-        _ => {},
+    if is_zero_divisor(lhs, rhs) {
+        return Err(dynamic_error!("Division by zero [err:FOAR0001]"));
     }
 
     return xitem_numeric_operation(lhs, rhs,
-                |a, b| { a.rem(b) },
+                |a, b| { a.wrapping_rem(b) },
                 |a, b| { a.rem(b) },
                 |a, b| { a.rem(b) });
+                    // i64::MIN mod -1 はオーバーフローするが、結果は 0。
+}
+
+// ---------------------------------------------------------------------
+// 型の昇格後も double にならない (integer または decimal の) 除算で、
+// 除数がゼロであるか否か。
+//
+fn is_zero_divisor(lhs: &XItem, rhs: &XItem) -> bool {
+    if let XItem::XIDouble{value: _} = lhs {
+        return false;
+    }
+    match rhs {
+        XItem::XIInteger{value} => return *value == 0,
+        XItem::XIDecimal{value} => return *value == 0.0,
+        _ => return false,
+    }
 }
 
 // ---------------------------------------------------------------------
@@ -979,7 +1267,7 @@ fn xitem_numeric_operation<FINT, FDEC, FDBL>(lhs: &XItem, rhs: &XItem,
                     return Ok(new_xitem_decimal(dec_op(*lhs, *rhs)));
                 },
                 XItem::XIDouble{value: rhs} => {
-                    return Ok(new_xitem_double(dec_op(dec_to_dbl(*lhs), *rhs)));
+                    return Ok(new_xitem_double(dbl_op(dec_to_dbl(*lhs), *rhs)));
                 },
                 _ => {},
             }
@@ -1026,6 +1314,24 @@ pub fn xitem_numeric_greater_than(lhs: &XItem, rhs: &XItem) -> Result<bool, Box<
             |a, b| { a > b });
 }
 
+// ---------------------------------------------------------------------
+// le、ge は、lt (gt) または eq として判定する。
+// gt (lt) の否定とすると、NaN との比較が真になってしまう。
+//
+pub fn xitem_numeric_less_equal(lhs: &XItem, rhs: &XItem) -> Result<bool, Box<Error>> {
+    return xitem_numeric_comparison(lhs, rhs,
+            |a, b| { a <= b },
+            |a, b| { a <= b },
+            |a, b| { a <= b });
+}
+
+pub fn xitem_numeric_greater_equal(lhs: &XItem, rhs: &XItem) -> Result<bool, Box<Error>> {
+    return xitem_numeric_comparison(lhs, rhs,
+            |a, b| { a >= b },
+            |a, b| { a >= b },
+            |a, b| { a >= b });
+}
+
 // ---------------------------------------------------------------------
 // 数値と数値の比較: 必要に応じ、型を昇格する。
 //
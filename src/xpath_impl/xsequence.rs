@@ -5,9 +5,12 @@
 // Copyright (C) 2018 KOYAMA Hiro <tac@amris.co.jp>
 //
 
+use std::collections::HashSet;
 use std::error::Error;
 use std::fmt;
 use std::slice::Iter;
+#[cfg(feature = "no_std")]
+use std::prelude::*;
 
 use dom::*;
 use xmlerror::*;
@@ -22,20 +25,33 @@ use xpath_impl::parser::*;
 // An item is identical to a singleton sequence containing that item.
 // A sequence containing zero items is called an [empty sequence].
 //
-#[derive(Debug, PartialEq, Clone)]
+// doc_ordered: ノードのみからなり、文書順に整列、重複排除済みである
+//              ことがわかっているか否か (falseならば不明)。
+//              op_union() などは、整列済みの入力を併合するだけで済ませる。
+//
+#[derive(Debug, Clone)]
 pub struct XSequence {
     value: Vec<XItem>,
+    doc_ordered: bool,
+}
+
+impl PartialEq for XSequence {
+    fn eq(&self, other: &XSequence) -> bool {
+        return self.value == other.value;
+    }
 }
 
 pub fn new_xsequence() -> XSequence {
     return XSequence{
         value: vec!{},
+        doc_ordered: true,
     };
 }
 
 pub fn new_singleton(item: &XItem) -> XSequence {
     return XSequence{
         value: vec!{item.clone()},
+        doc_ordered: item.is_node(),
     };
 }
 
@@ -47,6 +63,17 @@ pub fn new_xsequence_from_node_array(node_array: &Vec<NodePtr>) -> XSequence {
     return xsequence;
 }
 
+// ---------------------------------------------------------------------
+// new_xsequence_from_node_array() と同様。ただし、node_array が文書順に
+// 整列、重複排除済みであること (sort_by_doc_order() の結果など) を
+// 呼び出し側が保証し、シーケンスにその旨を記録する。
+//
+pub fn new_xsequence_in_doc_order(node_array: &Vec<NodePtr>) -> XSequence {
+    let mut xsequence = new_xsequence_from_node_array(node_array);
+    xsequence.doc_ordered = true;
+    return xsequence;
+}
+
 pub fn new_singleton_xnodeptr(xnode: &XNodePtr) -> XSequence {
     return new_singleton(&new_xitem_xnodeptr(xnode));
 }
@@ -101,6 +128,20 @@ impl fmt::Display for XSequence {
 //
 impl XSequence {
 
+    // -----------------------------------------------------------------
+    // JSONとして直列化する (Serialization 3.1, 11)。
+    // 空シーケンスは null、シングルトンはそのアイテムの値。
+    // 2個以上のアイテムからなるシーケンスはエラー [err:SERE0023]。
+    //
+    pub fn to_xdm_json(&self) -> Result<String, Box<Error>> {
+        match self.value.len() {
+            0 => return Ok(String::from("null")),
+            1 => return self.value[0].to_xdm_json(),
+            n => return Err(dynamic_error!(
+                    "Can't serialize sequence of {} items as JSON [err:SERE0023]", n)),
+        }
+    }
+
     // -----------------------------------------------------------------
     // シングルトンである場合に、これと同一視するXItemを返す。
     //
@@ -118,7 +159,20 @@ impl XSequence {
     pub fn get_singleton_xnodeptr(&self) -> Result<XNodePtr, Box<Error>> {
         let item = self.get_singleton_item()?;
         match item {
-            XItem::XItemXNodePtr{value} => return Ok(value.clone()),
+            XItem::XItemXNodePtr{value, ..} => return Ok(value.clone()),
+            _ => {},
+        }
+
+        return Err(type_error!("This sequence must be singleton xnodeptr."));
+    }
+
+    // -----------------------------------------------------------------
+    // シングルトンかつXNodePtrであれば、そのノードと、捕捉した変数を返す。
+    //
+    pub fn get_singleton_function(&self) -> Result<(XNodePtr, Vec<(String, XSequence)>), Box<Error>> {
+        let item = self.get_singleton_item()?;
+        match item {
+            XItem::XItemXNodePtr{value, captured} => return Ok((value.clone(), captured.clone())),
             _ => {},
         }
 
@@ -171,6 +225,7 @@ impl XSequence {
         let item = self.get_singleton_item()?;
         match item {
             XItem::XIString{value} => return Ok(value),
+            XItem::XIAnyURI{value} => return Ok(value),
             _ => {},
         }
 
@@ -218,6 +273,7 @@ impl XSequence {
             match self.value[0] {
                 XItem::XIBoolean{value} => return Ok(value.clone()),
                 XItem::XIString{ref value} => return Ok(value != ""),
+                XItem::XIAnyURI{ref value} => return Ok(value != ""),
                 XItem::XIDouble{value} => {
                     return Ok(value != 0.0 && ! value.is_nan());
                 },
@@ -232,7 +288,7 @@ impl XSequence {
         }
         return Err(type_error!(
             "effective_boolean_value: Can't determin effective boolean value: {}",
-            self.to_string()));
+            self.describe()));
 
     }
 
@@ -253,6 +309,24 @@ impl XSequence {
         return self.value.len() == 0;
     }
 
+    // -----------------------------------------------------------------
+    // 誤りメッセージ用の文字列化: ノードは、文書中の位置も示す。
+    // 長いシーケンスは先頭の数個のみ示す。
+    //
+    pub fn describe(&self) -> String {
+        const MAX_ITEMS: usize = 5;
+        let items: Vec<String> = self.value.iter()
+                .take(MAX_ITEMS).map(|item| item.describe()).collect();
+        let mut s = items.join(", ");
+        if MAX_ITEMS < self.value.len() {
+            s += &format!(", ... ({} items)", self.value.len());
+        }
+        if ! self.is_singleton() {
+            s = format!("({})", s);
+        }
+        return s;
+    }
+
     // -----------------------------------------------------------------
     //
     pub fn is_singleton(&self) -> bool {
@@ -296,12 +370,17 @@ impl XSequence {
     // -----------------------------------------------------------------
     //
     pub fn push(&mut self, item: &XItem) {
+        self.doc_ordered = self.value.len() == 0 && item.is_node();
         self.value.push(item.clone());
     }
 
     // -----------------------------------------------------------------
     //
     pub fn append(&mut self, other: &XSequence) {
+        if other.value.len() == 0 {
+            return;
+        }
+        self.doc_ordered = self.value.len() == 0 && other.doc_ordered;
         for item in other.value.iter() {
             self.value.push(item.clone());
         }
@@ -310,9 +389,18 @@ impl XSequence {
     // -----------------------------------------------------------------
     //
     pub fn reverse(&mut self) {
+        self.doc_ordered = self.doc_ordered && self.value.len() <= 1;
         self.value.reverse();
     }
 
+    // -----------------------------------------------------------------
+    // ノードのみからなり、文書順に整列、重複排除済みであることが
+    // わかっているか否か。falseの場合は不明 (整列済みであることもある)。
+    //
+    pub fn is_doc_ordered(&self) -> bool {
+        return self.doc_ordered;
+    }
+
     // -----------------------------------------------------------------
     // キャスト可能か否か。
     //     キャスト指定の末尾が "?" であれば、空シーケンスもキャスト可能。
@@ -332,10 +420,12 @@ impl XSequence {
     // -----------------------------------------------------------------
     // キャスト。
     // 1. 原子化を施す。
-    // 2. 空シーケンスでもシングルトンでもなければエラー。
+    // 2. 空シーケンスでもシングルトンでもなければエラー [err:XPTY0004]。
     // 3. 空シーケンスのとき:
     //      キャスト指定の末尾が "?" であれば、空シーケンスを返す。
     //      キャスト指定の末尾が "?" でなければエラー。
+    // 4. シングルトンのとき: アイテムのキャストのエラー
+    //      ([err:FORG0001] など) はそのまま返す。
     //
     pub fn cast_as(&self, type_name: &str) -> Result<XSequence, Box<Error>> {
         if self.is_empty() {
@@ -345,13 +435,12 @@ impl XSequence {
         }
 
         if let Ok(xitem) = self.get_singleton_item() {
-            if let Ok(result) = xitem.atomize().cast_as(type_name.trim_right_matches('?')) {
-                return Ok(new_singleton(&result));
-            }
+            let result = xitem.atomize().cast_as(type_name.trim_right_matches('?'))?;
+            return Ok(new_singleton(&result));
         }
 
-        return Err(type_error!("{}: can't cast as {}",
-                    self.to_string(), type_name));
+        return Err(type_error!("{}: can't cast as {} [err:XPTY0004]",
+                    self.describe(), type_name));
     }
 
     // -----------------------------------------------------------------
@@ -381,8 +470,10 @@ pub fn value_compare_eq(lhs: &XSequence, rhs: &XSequence) -> Result<XSequence, B
 }
 
 pub fn value_compare_ne(lhs: &XSequence, rhs: &XSequence) -> Result<XSequence, Box<Error>> {
-    let result = value_compare_eq(lhs, rhs)?;
-    return fn_not(&vec!{&result});
+    return value_comparison(lhs, rhs,
+            |arg| { let b = op_numeric_equal(arg)?; return fn_not(&vec!{&b}); },
+            |arg| { arg != 0 },
+            |arg| { let b = op_boolean_equal(arg)?; return fn_not(&vec!{&b}); });
 }
 
 pub fn value_compare_lt(lhs: &XSequence, rhs: &XSequence) -> Result<XSequence, Box<Error>> {
@@ -393,8 +484,10 @@ pub fn value_compare_lt(lhs: &XSequence, rhs: &XSequence) -> Result<XSequence, B
 }
 
 pub fn value_compare_le(lhs: &XSequence, rhs: &XSequence) -> Result<XSequence, Box<Error>> {
-    let result = value_compare_gt(lhs, rhs)?;
-    return fn_not(&vec!{&result});
+    return value_comparison(lhs, rhs,
+            |arg| { op_numeric_less_equal(arg) },
+            |arg| { arg <= 0 },
+            |arg| { let b = op_boolean_greater_than(arg)?; return fn_not(&vec!{&b}); });
 }
 
 pub fn value_compare_gt(lhs: &XSequence, rhs: &XSequence) -> Result<XSequence, Box<Error>> {
@@ -405,8 +498,10 @@ pub fn value_compare_gt(lhs: &XSequence, rhs: &XSequence) -> Result<XSequence, B
 }
 
 pub fn value_compare_ge(lhs: &XSequence, rhs: &XSequence) -> Result<XSequence, Box<Error>> {
-    let result = value_compare_lt(lhs, rhs)?;
-    return fn_not(&vec!{&result});
+    return value_comparison(lhs, rhs,
+            |arg| { op_numeric_greater_equal(arg) },
+            |arg| { arg >= 0 },
+            |arg| { let b = op_boolean_less_than(arg)?; return fn_not(&vec!{&b}); });
 }
 
 // ---------------------------------------------------------------------
@@ -424,10 +519,20 @@ fn value_comparison<FNUM, FSTR, FBOOL>(lhs: &XSequence, rhs: &XSequence,
     if ! lhs.is_singleton() || ! rhs.is_singleton() {
         return Err(type_error!(
                     "value_compare: operand is not singleton: {} : {}",
-                    lhs.to_string(), rhs.to_string()));
+                    lhs.describe(), rhs.describe()));
     }
     let lhs = lhs.atomize();
     let rhs = rhs.atomize();
+    let lhs_item = lhs.get_singleton_item()?;
+    let rhs_item = rhs.get_singleton_item()?;
+    if lhs_item.is_binary() || rhs_item.is_binary() {
+        let n = xitem_binary_compare(&lhs_item, &rhs_item)?;
+        return Ok(new_singleton_boolean(str_cmp(n)));
+    }
+    if lhs_item.is_date_time() || rhs_item.is_date_time() {
+        let n = xitem_date_time_compare(&lhs_item, &rhs_item)?;
+        return Ok(new_singleton_boolean(str_cmp(n)));
+    }
     if let Ok(result) = num_op(&vec!{&lhs, &rhs}) {
         return Ok(result);
     }
@@ -453,15 +558,28 @@ fn value_comparison<FNUM, FSTR, FBOOL>(lhs: &XSequence, rhs: &XSequence,
 // XPath 1.0 非互換モードの場合、一方のオペランドが数値型であっても、
 // もう一方を数値型に変換することはない。
 //
+// "=" と "!=" は、両辺が文字列と数値だけならば、全組を比較せず、
+// 値の集合を使って O(n+m) で判定する (hashed_general_compare_eq など)。
+//
 pub fn general_compare_eq(lhs: &XSequence, rhs: &XSequence) -> Result<XSequence, Box<Error>> {
-    return general_comparison(lhs, rhs,
+    let lhs = lhs.atomize();
+    let rhs = rhs.atomize();
+    if let Some(b) = hashed_general_compare_eq(&lhs, &rhs) {
+        return Ok(new_singleton_boolean(b));
+    }
+    return general_comparison(&lhs, &rhs,
         |s, t| { xitem_numeric_equal(s, t) },
         |arg| { arg == 0 },
         |s, t| { xitem_boolean_equal(s, t) });
 }
 
 pub fn general_compare_ne(lhs: &XSequence, rhs: &XSequence) -> Result<XSequence, Box<Error>> {
-    return general_comparison(lhs, rhs,
+    let lhs = lhs.atomize();
+    let rhs = rhs.atomize();
+    if let Some(b) = hashed_general_compare_ne(&lhs, &rhs) {
+        return Ok(new_singleton_boolean(b));
+    }
+    return general_comparison(&lhs, &rhs,
         |s, t| { let b = xitem_numeric_equal(s, t)?; return Ok(! b); },
         |arg| { arg != 0 },
         |s, t| { let b = xitem_boolean_equal(s, t)?; return Ok(! b); });
@@ -476,7 +594,7 @@ pub fn general_compare_lt(lhs: &XSequence, rhs: &XSequence) -> Result<XSequence,
 
 pub fn general_compare_le(lhs: &XSequence, rhs: &XSequence) -> Result<XSequence, Box<Error>> {
     return general_comparison(lhs, rhs,
-        |s, t| { let b = xitem_numeric_greater_than(s, t)?; return Ok(! b); },
+        |s, t| { xitem_numeric_less_equal(s, t) },
         |arg| { arg <= 0 },
         |s, t| { let b = xitem_boolean_greater_than(s, t)?; return Ok(! b); });
 }
@@ -490,11 +608,72 @@ pub fn general_compare_gt(lhs: &XSequence, rhs: &XSequence) -> Result<XSequence,
 
 pub fn general_compare_ge(lhs: &XSequence, rhs: &XSequence) -> Result<XSequence, Box<Error>> {
     return general_comparison(lhs, rhs,
-        |s, t| { let b = xitem_numeric_less_than(s, t)?; return Ok(! b); },
+        |s, t| { xitem_numeric_greater_equal(s, t) },
         |arg| { arg >= 0 },
         |s, t| { let b = xitem_boolean_less_than(s, t)?; return Ok(! b); });
 }
 
+// ---------------------------------------------------------------------
+// XPath 1.0 互換モードの一般比較の両辺の変換 (XPath 1.0 3.4 Booleans)。
+// 変換した両辺を general_compare_xx() で比較すれば、XPath 1.0 と
+// 同じ結果になる。
+//
+// (1) "=" または "!=" で、一方が真偽値ならば、両辺を実効ブール値にする。
+// (2) "<" などの場合は、両辺の各アイテムを number() で数値にする。
+// (3) "=" または "!=" で、一方が数値を含めば、両辺の各アイテムを
+//     number() で数値にする。
+// (4) それ以外は、両辺の各アイテムを string() で文字列にする。
+//
+pub fn xpath10_comparison_operands(lhs: &XSequence, rhs: &XSequence,
+                relational: bool) -> Result<(XSequence, XSequence), Box<Error>> {
+    let is_boolean = |xseq: &XSequence| {
+        xseq.is_singleton() && xseq.get_item(0).xs_type() == "xs:boolean"
+    };
+    let has_numeric = |xseq: &XSequence| {
+        xseq.iter().any(|item| item.is_numeric())
+    };
+    if ! relational && (is_boolean(lhs) || is_boolean(rhs)) {
+        return Ok((new_singleton_boolean(lhs.effective_boolean_value()?),
+                   new_singleton_boolean(rhs.effective_boolean_value()?)));
+    }
+
+    let to_number = relational || has_numeric(lhs) || has_numeric(rhs);
+    let convert = |xseq: &XSequence| -> Result<XSequence, Box<Error>> {
+        let mut result = new_xsequence();
+        for item in xseq.iter() {
+            if to_number {
+                result.push(&new_xitem_double(item.get_as_raw_double()?));
+            } else {
+                result.push(&new_xitem_string(&item.get_as_raw_string()?));
+            }
+        }
+        return Ok(result);
+    };
+    return Ok((convert(lhs)?, convert(rhs)?));
+}
+
+// ---------------------------------------------------------------------
+// XPath 1.0 の number(): 先頭のアイテムを xs:double に変換する。
+// 空シーケンスならば NaN。
+//
+pub fn xpath10_number(xseq: &XSequence) -> Result<XSequence, Box<Error>> {
+    if xseq.is_empty() {
+        return Ok(new_singleton_double(f64::NAN));
+    }
+    return Ok(new_singleton_double(xseq.get_item(0).get_as_raw_double()?));
+}
+
+// ---------------------------------------------------------------------
+// XPath 1.0 の string(): 先頭のアイテムを xs:string に変換する。
+// 空シーケンスならば空文字列。
+//
+pub fn xpath10_string(xseq: &XSequence) -> Result<XSequence, Box<Error>> {
+    if xseq.is_empty() {
+        return Ok(new_singleton_string(""));
+    }
+    return Ok(new_singleton_string(&xseq.get_item(0).get_as_raw_string()?));
+}
+
 // ---------------------------------------------------------------------
 //
 fn general_comparison<FNUM, FSTR, FBOOL>(lhs: &XSequence, rhs: &XSequence,
@@ -504,8 +683,25 @@ fn general_comparison<FNUM, FSTR, FBOOL>(lhs: &XSequence, rhs: &XSequence,
           FSTR: FnMut(i64) -> bool,
           FBOOL: FnMut(&XItem, &XItem) -> Result<bool, Box<Error>> {
 
+    let rhs = rhs.atomize();
     for xitem_lhs in lhs.atomize().iter() {
-        for xitem_rhs in rhs.atomize().iter() {
+        for xitem_rhs in rhs.iter() {
+            if xitem_lhs.is_binary() || xitem_rhs.is_binary() {
+                if let Ok(n) = xitem_binary_compare(&xitem_lhs, &xitem_rhs) {
+                    if str_cmp(n) == true {
+                        return Ok(new_singleton_boolean(true));
+                    }
+                }
+                continue;
+            }
+            if xitem_lhs.is_date_time() || xitem_rhs.is_date_time() {
+                if let Ok(n) = xitem_date_time_compare(&xitem_lhs, &xitem_rhs) {
+                    if str_cmp(n) == true {
+                        return Ok(new_singleton_boolean(true));
+                    }
+                }
+                continue;
+            }
             if let Ok(b) = num_op(&xitem_lhs, &xitem_rhs) {
                 if b == true {
                     return Ok(new_singleton_boolean(true));
@@ -528,12 +724,128 @@ fn general_comparison<FNUM, FSTR, FBOOL>(lhs: &XSequence, rhs: &XSequence,
 
 }
 
+// ---------------------------------------------------------------------
+// 一般比較の高速化に使う、原子値の鍵。
+// 数値は型 (xs:integer、xs:decimal、xs:double) によらず、f64 の
+// ビット列で比較する (-0.0 は 0.0 とし、NaN は鍵にしない)。
+//
+#[derive(PartialEq, Eq, Hash)]
+enum CompareKey {
+    Str(String),
+    Num(u64),
+}
+
+// ---------------------------------------------------------------------
+// f64 で正確に表せる整数の上限 (2^53)。これを超える整数を含む場合は
+// 高速化しない (xs:double と比較する際に丸めが生じるため)。
+//
+const MAX_EXACT_INTEGER: i64 = 1 << 53;
+
+// ---------------------------------------------------------------------
+// 原子値の鍵。None: NaN。
+// Err: 文字列でも数値でもない (高速化の対象外)。
+//
+fn compare_key(xitem: &XItem) -> Result<Option<CompareKey>, ()> {
+    let f = match xitem {
+        XItem::XIString{value} | XItem::XIAnyURI{value} => {
+            return Ok(Some(CompareKey::Str(value.clone())));
+        },
+        XItem::XIInteger{value} => {
+            if MAX_EXACT_INTEGER < value.abs() {
+                return Err(());
+            }
+            *value as f64
+        },
+        XItem::XIDecimal{value} => *value,
+        XItem::XIDouble{value} => *value,
+        _ => return Err(()),
+    };
+    if f.is_nan() {
+        return Ok(None);
+    }
+    let f = if f == 0.0 { 0.0 } else { f };
+    return Ok(Some(CompareKey::Num(f.to_bits())));
+}
+
+// ---------------------------------------------------------------------
+// 各項目の鍵。None: 高速化の対象外の項目がある。
+//
+fn compare_keys(seq: &XSequence) -> Option<Vec<Option<CompareKey>>> {
+    let mut keys = vec!{};
+    for xitem in seq.iter() {
+        match compare_key(xitem) {
+            Ok(key) => keys.push(key),
+            Err(_) => return None,
+        }
+    }
+    return Some(keys);
+}
+
+// ---------------------------------------------------------------------
+// 原子化済みの両辺について、"=" の一般比較を、小さい方の鍵の集合を
+// 作り、大きい方の鍵を探すことにより判定する。
+// 文字列と数値は等しくならない (general_comparison と同じ)。
+// None: 高速化の対象外 (文字列、数値以外の項目がある)。
+//
+fn hashed_general_compare_eq(lhs: &XSequence, rhs: &XSequence) -> Option<bool> {
+    let lhs_keys = compare_keys(lhs)?;
+    let rhs_keys = compare_keys(rhs)?;
+    let (smaller, larger) = if lhs_keys.len() <= rhs_keys.len() {
+        (lhs_keys, rhs_keys)
+    } else {
+        (rhs_keys, lhs_keys)
+    };
+    let key_set: HashSet<CompareKey> = smaller.into_iter()
+            .filter_map(|key| key).collect();
+    return Some(larger.iter().any(|key| match key {
+        Some(key) => key_set.contains(key),
+        None => false,
+    }));
+}
+
+// ---------------------------------------------------------------------
+// 原子化済みの両辺について、"!=" の一般比較を判定する。
+// 文字列同士、数値同士の組で、等しくないものがあれば true。
+// 一方の辺の文字列 (数値) がすべて同じ値 v で、他方の辺の文字列
+// (数値) もすべて v である場合に限り、そのような組はない
+// (NaN は自身とも等しくない)。
+// None: 高速化の対象外 (文字列、数値以外の項目がある)。
+//
+fn hashed_general_compare_ne(lhs: &XSequence, rhs: &XSequence) -> Option<bool> {
+    let lhs_keys = compare_keys(lhs)?;
+    let rhs_keys = compare_keys(rhs)?;
+    let is_str = |key: &Option<CompareKey>| match key {
+        Some(CompareKey::Str(_)) => true,
+        _ => false,
+    };
+    for &want_str in [true, false].iter() {
+        let mut values: HashSet<&Option<CompareKey>> = HashSet::new();
+        let mut has_lhs = false;
+        let mut has_rhs = false;
+        for key in lhs_keys.iter().filter(|key| is_str(key) == want_str) {
+            values.insert(key);
+            has_lhs = true;
+        }
+        for key in rhs_keys.iter().filter(|key| is_str(key) == want_str) {
+            values.insert(key);
+            has_rhs = true;
+        }
+        if has_lhs && has_rhs &&
+                (1 < values.len() || values.contains(&None)) {
+            return Some(true);
+        }
+    }
+    return Some(false);
+}
+
 // =====================================================================
 //
 #[cfg(test)]
 mod test {
 //    use super::*;
 
+    #[cfg(feature = "no_std")]
+    use std::prelude::*;
     use xpath_impl::helpers::compress_spaces;
     use xpath_impl::helpers::subtest_xpath;
     use xpath_impl::helpers::subtest_eval_xpath;
@@ -595,6 +907,46 @@ mod test {
         ]);
     }
 
+    // -----------------------------------------------------------------
+    // 列どうしの一般比較 ("=" と "!=" は値の集合で判定)
+    //
+    #[test]
+    fn test_compare_general_sequences() {
+        let xml = compress_spaces(r#"
+<root>
+    <a id="x1"/><a id="x2"/><a id="x3"/>
+    <b ref="x3"/><b ref="y1"/>
+    <c ref="y2"/><c ref="y2"/>
+    <n v="1.0"/><n v="1"/>
+</root>
+        "#);
+        subtest_eval_xpath("compare_general_sequences", &xml, &[
+            ( "//a/@id = //b/@ref", "true" ),
+            ( "//b/@ref = //a/@id", "true" ),
+            ( "//a/@id = //c/@ref", "false" ),
+            ( "//a/@id = ()", "false" ),
+            ( "//a/@id != //c/@ref", "true" ),
+            ( "//c/@ref != //c/@ref", "false" ),
+            ( "//c/@ref != 'y2'", "false" ),
+            ( "//c/@ref != ('y2', 'y2')", "false" ),
+            ( "//c/@ref != ('y2', 3)", "false" ),
+            ( "//c/@ref != ('y2', 'y3')", "true" ),
+            ( "//n/@v = '1'", "true" ),
+            ( "//n/@v = 1", "false" ),
+            ( "(1, 2, 3) = (3.0, 4)", "true" ),
+            ( "(1, 2, 3) = (4e0, 5)", "false" ),
+            ( "(1, 2) = ('1', '2')", "false" ),
+            ( "(1, 1.0, 1e0) != 1", "false" ),
+            ( "(1, 2) != (1, 2)", "true" ),
+            ( "(0e0, -0e0) != 0", "false" ),
+            ( "(1, number('NaN')) = number('NaN')", "false" ),
+            ( "(1, number('NaN')) != 1", "true" ),
+            ( "(9007199254740993, 1) = 9007199254740992e0", "true" ),
+            ( "('a', true()) = true()", "true" ),
+            ( "('a', true()) != 'a'", "false" ),
+        ]);
+    }
+
     // -----------------------------------------------------------------
     // Infinity
     //
@@ -635,6 +987,57 @@ mod test {
         ]);
     }
 
+    // -----------------------------------------------------------------
+    // 数値の比較: integer、decimal、double の全組み合わせについて、
+    // 型を昇格した上で f64 の比較と一致すること (NaN、-0 を含む)。
+    //
+    #[test]
+    fn test_compare_numeric_matrix() {
+        let xml = compress_spaces(r#"
+<root base="base">
+</root>
+        "#);
+        let values = [
+            ( "1", 1.0 ), ( "-2", -2.0 ), ( "0", 0.0 ),
+            ( "1.0", 1.0 ), ( "0.5", 0.5 ), ( "-0.0", -0.0 ),
+            ( "1e0", 1.0 ), ( "-0e0", -0.0 ), ( "2.5e0", 2.5 ),
+            ( "(0e0 div 0e0)", f64::NAN ), ( "(1e0 div 0e0)", f64::INFINITY ),
+        ];
+        let ops: [(&str, &str, fn(f64, f64) -> bool); 6] = [
+            ( "eq", "=", |a, b| a == b ),
+            ( "ne", "!=", |a, b| a != b ),
+            ( "lt", "<", |a, b| a < b ),
+            ( "le", "<=", |a, b| a <= b ),
+            ( "gt", ">", |a, b| a > b ),
+            ( "ge", ">=", |a, b| a >= b ),
+        ];
+        let mut specs: Vec<(String, String)> = vec!{};
+        for &(lhs, x) in values.iter() {
+            for &(rhs, y) in values.iter() {
+                for &(value_op, general_op, f) in ops.iter() {
+                    let expected = format!("{}", f(x, y));
+                    specs.push((format!("{} {} {}", lhs, value_op, rhs), expected.clone()));
+                    specs.push((format!("{} {} {}", lhs, general_op, rhs), expected));
+                }
+            }
+        }
+        let specs: Vec<(&str, &str)> = specs.iter()
+                    .map(|(xpath, expected)| (xpath.as_str(), expected.as_str())).collect();
+        subtest_eval_xpath("compare_numeric_matrix", &xml, &specs);
+
+        subtest_eval_xpath("compare_numeric_empty", &xml, &[
+            ( "() eq 1", "()" ),
+            ( "() ne 1", "()" ),
+            ( "1 le ()", "()" ),
+            ( "() ge ()", "()" ),
+            ( "() != 1", "false" ),
+            ( "(0e0 div 0e0, 1) <= 0", "false" ),
+            ( "(0e0 div 0e0, 1) >= 1", "true" ),
+            ( "9007199254740993 gt 9007199254740992", "true" ),
+            ( "9007199254740993 eq 9007199254740992e0", "true" ),
+        ]);
+    }
+
     // -----------------------------------------------------------------
     // Value Compare
     //
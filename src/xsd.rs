@@ -0,0 +1,1389 @@
+//
+// xsd.rs
+//
+// amxml: XML processor with XPath.
+// Copyright (C) 2018 KOYAMA Hiro <tac@amris.co.jp>
+//
+//!
+//! Simple-type validation by XML Schema (XSD).
+//!
+//! <strong>Schema</strong> reads the parts of
+//! <a href="https://www.w3.org/TR/xmlschema11-2/">XML Schema</a>
+//! that define the values of elements and attributes:
+//!
+//! - <code>simpleType</code>: <code>restriction</code> of a built-in
+//!   or another simple type, <code>list</code> and <code>union</code>.
+//!   The facets checked are <code>enumeration</code>,
+//!   <code>pattern</code> (feature "regex"; ignored without it),
+//!   <code>minInclusive</code>, <code>maxInclusive</code>,
+//!   <code>minExclusive</code>, <code>maxExclusive</code>
+//!   (numeric and date/time types), <code>length</code>,
+//!   <code>minLength</code>, <code>maxLength</code>,
+//!   <code>totalDigits</code> and <code>fractionDigits</code>.
+//! - <code>element</code>: the type of the element, named or
+//!   anonymous, and the <code>attribute</code>s of its
+//!   <code>complexType</code> (including those of the base type
+//!   extended or restricted, and <code>use="required"</code>).
+//!
+//! Schema#validate() checks the values of the elements and attributes
+//! declared in the schema, and annotates them with their types.
+//! Then ElementTest and AttributeTest of XPath with TypeName,
+//! e.g. <code>element(*, xs:integer)</code>, match the nodes by the
+//! derivation of their types, instead of xs:untyped or
+//! xs:untypedAtomic of the nodes not validated.
+//! Types defined by the schema are matched by their local names,
+//! as unprefixed type names in XPath are in the namespace of xs.
+//!
+//! Content models (<code>sequence</code>, <code>choice</code>, etc.)
+//! are not checked. Declarations are looked up by the local name of
+//! the element (or attribute), regardless of its namespace and of the
+//! scope of the declaration; the first declaration of the name wins.
+//! <code>import</code>, <code>include</code>, <code>group</code> and
+//! <code>attributeGroup</code> are ignored.
+//!
+//! # Examples
+//!
+//! ```
+//! use amxml::dom::*;
+//! use amxml::xsd::*;
+//! let schema = new_schema(r#"
+//!     <xs:schema xmlns:xs="http://www.w3.org/2001/XMLSchema">
+//!         <xs:simpleType name="SizeType">
+//!             <xs:restriction base="xs:positiveInteger">
+//!                 <xs:maxInclusive value="100"/>
+//!             </xs:restriction>
+//!         </xs:simpleType>
+//!         <xs:element name="box">
+//!             <xs:complexType>
+//!                 <xs:attribute name="size" type="SizeType" use="required"/>
+//!             </xs:complexType>
+//!         </xs:element>
+//!         <xs:element name="price" type="xs:decimal"/>
+//!     </xs:schema>"#).unwrap();
+//!
+//! let doc = new_document(r#"<root><box size="10"/><price>9.99</price></root>"#).unwrap();
+//! assert!(schema.validate(&doc).is_ok());
+//! assert_eq!(doc.eval_xpath("count(//@*[. instance of attribute(*, SizeType)])").unwrap().to_string(), "1");
+//! assert_eq!(doc.eval_xpath("count(//element(*, xs:decimal))").unwrap().to_string(), "1");
+//! assert_eq!(doc.eval_xpath("count(//element(*, xs:integer))").unwrap().to_string(), "0");
+//!
+//! let doc = new_document(r#"<root><box size="200"/><box/><price>free</price></root>"#).unwrap();
+//! assert_eq!(schema.validate(&doc).unwrap_err().len(), 3);
+//! ```
+//!
+
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::error::Error;
+#[cfg(feature = "no_std")]
+use std::prelude::*;
+
+#[cfg(feature = "regex")]
+use regex::Regex;
+
+use binary::decode_base64;
+use datetime::*;
+use dom::*;
+use sax::{is_ncname, is_xml_name};
+use xmlerror::*;
+use xpath_impl::cast::{parse_xs_decimal, parse_xs_double};
+
+const XSD_NS_URI: &str = "http://www.w3.org/2001/XMLSchema";
+
+// 型註釈を保持する注釈のキー。
+const TYPE_ANNOTATION_KEY: &str = "amxml:xsd-type";
+
+// ---------------------------------------------------------------------
+// 組込み型と、その基底型。
+// 基底型を順にたどると xs:anyType に至る。
+//
+const BUILTIN_TYPES: &[(&str, &str)] = &[
+    ( "xs:anyType",             "" ),
+    ( "xs:anySimpleType",       "xs:anyType" ),
+    ( "xs:anyAtomicType",       "xs:anySimpleType" ),
+    ( "xs:string",              "xs:anyAtomicType" ),
+    ( "xs:normalizedString",    "xs:string" ),
+    ( "xs:token",               "xs:normalizedString" ),
+    ( "xs:language",            "xs:token" ),
+    ( "xs:NMTOKEN",             "xs:token" ),
+    ( "xs:Name",                "xs:token" ),
+    ( "xs:NCName",              "xs:Name" ),
+    ( "xs:ID",                  "xs:NCName" ),
+    ( "xs:IDREF",               "xs:NCName" ),
+    ( "xs:ENTITY",              "xs:NCName" ),
+    ( "xs:boolean",             "xs:anyAtomicType" ),
+    ( "xs:decimal",             "xs:anyAtomicType" ),
+    ( "xs:integer",             "xs:decimal" ),
+    ( "xs:nonPositiveInteger",  "xs:integer" ),
+    ( "xs:negativeInteger",     "xs:nonPositiveInteger" ),
+    ( "xs:long",                "xs:integer" ),
+    ( "xs:int",                 "xs:long" ),
+    ( "xs:short",               "xs:int" ),
+    ( "xs:byte",                "xs:short" ),
+    ( "xs:nonNegativeInteger",  "xs:integer" ),
+    ( "xs:unsignedLong",        "xs:nonNegativeInteger" ),
+    ( "xs:unsignedInt",         "xs:unsignedLong" ),
+    ( "xs:unsignedShort",       "xs:unsignedInt" ),
+    ( "xs:unsignedByte",        "xs:unsignedShort" ),
+    ( "xs:positiveInteger",     "xs:nonNegativeInteger" ),
+    ( "xs:float",               "xs:anyAtomicType" ),
+    ( "xs:double",              "xs:anyAtomicType" ),
+    ( "xs:dateTime",            "xs:anyAtomicType" ),
+    ( "xs:date",                "xs:anyAtomicType" ),
+    ( "xs:time",                "xs:anyAtomicType" ),
+    ( "xs:hexBinary",           "xs:anyAtomicType" ),
+    ( "xs:base64Binary",        "xs:anyAtomicType" ),
+    ( "xs:anyURI",              "xs:anyAtomicType" ),
+        // 以下は字句表現を検査しない。
+    ( "xs:duration",            "xs:anyAtomicType" ),
+    ( "xs:gYear",               "xs:anyAtomicType" ),
+    ( "xs:gYearMonth",          "xs:anyAtomicType" ),
+    ( "xs:gMonth",              "xs:anyAtomicType" ),
+    ( "xs:gMonthDay",           "xs:anyAtomicType" ),
+    ( "xs:gDay",                "xs:anyAtomicType" ),
+    ( "xs:QName",               "xs:anyAtomicType" ),
+    ( "xs:NOTATION",            "xs:anyAtomicType" ),
+    ( "xs:IDREFS",              "xs:anySimpleType" ),
+    ( "xs:ENTITIES",            "xs:anySimpleType" ),
+    ( "xs:NMTOKENS",            "xs:anySimpleType" ),
+];
+
+// ---------------------------------------------------------------------
+// 整数型の値の範囲 (下限、上限)。
+//
+const INTEGER_RANGES: &[(&str, i128, i128)] = &[
+    ( "xs:byte",                -128,           127 ),
+    ( "xs:short",               -32768,         32767 ),
+    ( "xs:int",                 -2147483648,    2147483647 ),
+    ( "xs:long",                -9223372036854775808, 9223372036854775807 ),
+    ( "xs:unsignedByte",        0,              255 ),
+    ( "xs:unsignedShort",       0,              65535 ),
+    ( "xs:unsignedInt",         0,              4294967295 ),
+    ( "xs:unsignedLong",        0,              18446744073709551615 ),
+];
+
+// ---------------------------------------------------------------------
+// pattern 制約面。
+// 同じ派生の段階にある複数の pattern は、いずれかに合致すればよい。
+//
+#[derive(Debug, Clone)]
+struct Pattern {
+    source: String,
+    #[cfg(feature = "regex")]
+    regex: Regex,
+}
+
+// ---------------------------------------------------------------------
+// 単純型の制約面。
+//
+#[derive(Debug, Clone, Default)]
+struct Facets {
+    enumeration: Vec<String>,
+    patterns: Vec<Pattern>,
+    min_inclusive: Option<String>,
+    max_inclusive: Option<String>,
+    min_exclusive: Option<String>,
+    max_exclusive: Option<String>,
+    length: Option<usize>,
+    min_length: Option<usize>,
+    max_length: Option<usize>,
+    total_digits: Option<usize>,
+    fraction_digits: Option<usize>,
+}
+
+// ---------------------------------------------------------------------
+// 単純型の種類。型は、組込み型の名前 ("xs:..."),
+// スキーマで定義した型の局所名、または無名型のキー ("#1" など)。
+//
+#[derive(Debug, Clone)]
+enum Variety {
+    Atomic{ base: String, facets: Facets },
+    List{ item: String, facets: Facets },
+    Union{ members: Vec<String> },
+}
+
+// ---------------------------------------------------------------------
+// 属性の宣言。型が空文字列の場合は、大域的な属性の参照 (ref)。
+//
+#[derive(Debug, Clone)]
+struct AttributeUse {
+    name: String,
+    type_key: String,
+    required: bool,
+}
+
+// ---------------------------------------------------------------------
+// スキーマで定義した型。
+// 複合型の base は、complexContent または simpleContent の
+// extension/restriction の基底型。
+//
+#[derive(Debug, Clone)]
+enum TypeDef {
+    Simple{ name: Option<String>, variety: Variety },
+    Complex{ name: Option<String>, base: Option<String>,
+             simple_content: bool, attributes: Vec<AttributeUse> },
+}
+
+// ---------------------------------------------------------------------
+// ノードの型註釈: 型の名前を、自身から xs:anyType まで派生の順に並べる。
+// 無名型は含まない。
+//
+#[derive(Debug)]
+struct TypeAnnotation {
+    derivation: Vec<String>,
+}
+
+// =====================================================================
+/// Simple types and declarations read from XML Schema:
+/// see the module document.
+///
+#[derive(Debug, Clone, Default)]
+pub struct Schema {
+    types: HashMap<String, TypeDef>,
+    elements: HashMap<String, String>,
+    attributes: HashMap<String, String>,
+    n_anonymous: usize,
+}
+
+// =====================================================================
+/// Parses the XML string as a schema.
+///
+/// # Errors
+///
+/// - When there is syntax error in XML.
+/// - When the root element is not <code>schema</code> in the
+///   namespace of XML Schema.
+/// - When a definition lacks a required attribute, has an invalid
+///   facet, or refers to an undefined type.
+///
+pub fn new_schema(xsd_string: &str) -> Result<Schema, Box<Error>> {
+    let doc = new_document(xsd_string)?;
+    return new_schema_from_document(&doc);
+}
+
+// =====================================================================
+/// Makes the schema from the document, e.g. read by
+/// new_document_from_file().
+///
+/// # Errors
+///
+/// - Same as new_schema().
+///
+pub fn new_schema_from_document(doc: &NodePtr) -> Result<Schema, Box<Error>> {
+    let root = doc.root().root_element();
+    if root.local_name() != "schema" || root.namespace_uri() != XSD_NS_URI {
+        return Err(xml_syntax_error!(
+            "Not an XML schema: root element is {}", root.name()));
+    }
+
+    let mut schema = Schema::default();
+    for elem in xsd_children(&root).iter() {
+        match elem.local_name().as_str() {
+            "simpleType" => {
+                let name = required_attribute(elem, "name")?;
+                schema.parse_simple_type(elem, Some(name))?;
+            },
+            "complexType" => {
+                let name = required_attribute(elem, "name")?;
+                schema.parse_complex_type(elem, Some(name))?;
+            },
+            "element" => {
+                schema.parse_element(elem)?;
+            },
+            "attribute" => {
+                let attr = schema.parse_attribute(elem)?;
+                if ! schema.attributes.contains_key(&attr.name) {
+                    schema.attributes.insert(attr.name, attr.type_key);
+                }
+            },
+            _ => {},
+        }
+    }
+    schema.check_references()?;
+    return Ok(schema);
+}
+
+// ---------------------------------------------------------------------
+// XML Schemaの名前空間にある子要素。
+//
+fn xsd_children(elem: &NodePtr) -> Vec<NodePtr> {
+    return elem.children().into_iter()
+        .filter(|ch| ch.node_type() == NodeType::Element &&
+                     ch.namespace_uri() == XSD_NS_URI)
+        .collect();
+}
+
+// ---------------------------------------------------------------------
+// 定義の必須の属性値。
+//
+fn required_attribute(elem: &NodePtr, name: &str) -> Result<String, Box<Error>> {
+    match elem.attribute_value(name) {
+        Some(value) => return Ok(value),
+        None => return Err(xml_syntax_error!(
+            "XML schema: {} lacks attribute {}", elem.local_name(), name)),
+    }
+}
+
+// ---------------------------------------------------------------------
+// 属性値のQNameを型のキーにする。XML Schemaの名前空間にあれば
+// "xs:" を前置し、そうでなければ局所名とする。
+//
+fn type_key_of_qname(elem: &NodePtr, qname: &str) -> String {
+    let (prefix, local) = match qname.find(':') {
+        Some(pos) => (&qname[.. pos], &qname[pos + 1 ..]),
+        None => ("", qname),
+    };
+    let xmlns_attr = if prefix == "" {
+        String::from("xmlns")
+    } else {
+        format!("xmlns:{}", prefix)
+    };
+    let mut curr = Some(elem.rc_clone());
+    while let Some(e) = curr {
+        if let Some(uri) = e.attribute_value(&xmlns_attr) {
+            if uri == XSD_NS_URI {
+                return format!("xs:{}", local);
+            }
+            break;
+        }
+        curr = e.parent();
+    }
+    return String::from(local);
+}
+
+// ---------------------------------------------------------------------
+// 属性値の非負整数。
+//
+fn parse_facet_usize(elem: &NodePtr, value: &str) -> Result<usize, Box<Error>> {
+    match value.trim().parse::<usize>() {
+        Ok(n) => return Ok(n),
+        Err(_) => return Err(xml_syntax_error!(
+            "XML schema: invalid value of {}: {}", elem.local_name(), value)),
+    }
+}
+
+impl Schema {
+    // -----------------------------------------------------------------
+    // 型を登録し、そのキーを返す。
+    //
+    fn register_type(&mut self, name: Option<String>, def: TypeDef) -> String {
+        let key = match name {
+            Some(name) => name,
+            None => {
+                self.n_anonymous += 1;
+                format!("#{}", self.n_anonymous)
+            },
+        };
+        self.types.insert(key.clone(), def);
+        return key;
+    }
+
+    // -----------------------------------------------------------------
+    // 属性 (base、itemTypeなど) に型名があればそれを、なければ
+    // 子要素の無名の simpleType を、型のキーとする。
+    //
+    fn simple_type_ref(&mut self, elem: &NodePtr, attr_name: &str) -> Result<String, Box<Error>> {
+        if let Some(qname) = elem.attribute_value(attr_name) {
+            return Ok(type_key_of_qname(elem, &qname));
+        }
+        for ch in xsd_children(elem).iter() {
+            if ch.local_name() == "simpleType" {
+                return self.parse_simple_type(ch, None);
+            }
+        }
+        return Err(xml_syntax_error!(
+            "XML schema: {} lacks attribute {}", elem.local_name(), attr_name));
+    }
+
+    // -----------------------------------------------------------------
+    // simpleType を解析して登録し、そのキーを返す。
+    //
+    fn parse_simple_type(&mut self, elem: &NodePtr, name: Option<String>) -> Result<String, Box<Error>> {
+        for ch in xsd_children(elem).iter() {
+            let variety = match ch.local_name().as_str() {
+                "restriction" => Variety::Atomic{
+                    base: self.simple_type_ref(ch, "base")?,
+                    facets: self.parse_facets(ch)?,
+                },
+                "list" => Variety::List{
+                    item: self.simple_type_ref(ch, "itemType")?,
+                    facets: Facets::default(),
+                },
+                "union" => {
+                    let mut members: Vec<String> = vec!{};
+                    if let Some(member_types) = ch.attribute_value("memberTypes") {
+                        for qname in member_types.split_whitespace() {
+                            members.push(type_key_of_qname(ch, qname));
+                        }
+                    }
+                    for st in xsd_children(ch).iter() {
+                        if st.local_name() == "simpleType" {
+                            members.push(self.parse_simple_type(st, None)?);
+                        }
+                    }
+                    Variety::Union{ members }
+                },
+                _ => continue,
+            };
+            return Ok(self.register_type(name.clone(),
+                    TypeDef::Simple{ name, variety }));
+        }
+        return Err(xml_syntax_error!(
+            "XML schema: simpleType lacks restriction, list or union"));
+    }
+
+    // -----------------------------------------------------------------
+    // restriction の子要素の制約面。
+    //
+    fn parse_facets(&mut self, elem: &NodePtr) -> Result<Facets, Box<Error>> {
+        let mut facets = Facets::default();
+        for ch in xsd_children(elem).iter() {
+            let local_name = ch.local_name();
+            if local_name == "simpleType" || local_name == "annotation" {
+                continue;
+            }
+            let value = required_attribute(ch, "value")?;
+            match local_name.as_str() {
+                "enumeration" => facets.enumeration.push(value),
+                "pattern" => facets.patterns.push(compile_pattern(&value)?),
+                "minInclusive" => facets.min_inclusive = Some(value),
+                "maxInclusive" => facets.max_inclusive = Some(value),
+                "minExclusive" => facets.min_exclusive = Some(value),
+                "maxExclusive" => facets.max_exclusive = Some(value),
+                "length" => facets.length = Some(parse_facet_usize(ch, &value)?),
+                "minLength" => facets.min_length = Some(parse_facet_usize(ch, &value)?),
+                "maxLength" => facets.max_length = Some(parse_facet_usize(ch, &value)?),
+                "totalDigits" => facets.total_digits = Some(parse_facet_usize(ch, &value)?),
+                "fractionDigits" => facets.fraction_digits = Some(parse_facet_usize(ch, &value)?),
+                _ => {},            // whiteSpace など
+            }
+        }
+        return Ok(facets);
+    }
+
+    // -----------------------------------------------------------------
+    // complexType を解析して登録し、そのキーを返す。
+    //
+    fn parse_complex_type(&mut self, elem: &NodePtr, name: Option<String>) -> Result<String, Box<Error>> {
+        let mut base: Option<String> = None;
+        let mut simple_content = false;
+        let mut attributes: Vec<AttributeUse> = vec!{};
+        self.parse_complex_content(elem, &mut base, &mut simple_content, &mut attributes)?;
+        return Ok(self.register_type(name.clone(),
+                TypeDef::Complex{ name, base, simple_content, attributes }));
+    }
+
+    // -----------------------------------------------------------------
+    // complexType の内容を再帰的にたどり、基底型、属性の宣言、
+    // 局所的な要素の宣言を得る。
+    //
+    fn parse_complex_content(&mut self, elem: &NodePtr,
+                base: &mut Option<String>, simple_content: &mut bool,
+                attributes: &mut Vec<AttributeUse>) -> Result<(), Box<Error>> {
+        for ch in xsd_children(elem).iter() {
+            match ch.local_name().as_str() {
+                "attribute" => attributes.push(self.parse_attribute(ch)?),
+                "element" => self.parse_element(ch)?,
+                "simpleContent" => {
+                    *simple_content = true;
+                    self.parse_complex_content(ch, base, simple_content, attributes)?;
+                },
+                "extension" | "restriction" => {
+                    *base = Some(type_key_of_qname(ch, &required_attribute(ch, "base")?));
+                    self.parse_complex_content(ch, base, simple_content, attributes)?;
+                },
+                "complexContent" | "sequence" | "choice" | "all" => {
+                    self.parse_complex_content(ch, base, simple_content, attributes)?;
+                },
+                _ => {},
+            }
+        }
+        return Ok(());
+    }
+
+    // -----------------------------------------------------------------
+    // element を解析して、名前と型を登録する (refは何もしない)。
+    //
+    fn parse_element(&mut self, elem: &NodePtr) -> Result<(), Box<Error>> {
+        let name = match elem.attribute_value("name") {
+            Some(name) => name,
+            None => return Ok(()),
+        };
+        let mut type_key = String::from("xs:anyType");
+        if let Some(qname) = elem.attribute_value("type") {
+            type_key = type_key_of_qname(elem, &qname);
+        } else {
+            for ch in xsd_children(elem).iter() {
+                match ch.local_name().as_str() {
+                    "simpleType" => type_key = self.parse_simple_type(ch, None)?,
+                    "complexType" => type_key = self.parse_complex_type(ch, None)?,
+                    _ => {},
+                }
+            }
+        }
+        if ! self.elements.contains_key(&name) {
+            self.elements.insert(name, type_key);
+        }
+        return Ok(());
+    }
+
+    // -----------------------------------------------------------------
+    // attribute を解析する。
+    //
+    fn parse_attribute(&mut self, elem: &NodePtr) -> Result<AttributeUse, Box<Error>> {
+        let required = elem.attribute_value("use") == Some(String::from("required"));
+        if let Some(qname) = elem.attribute_value("ref") {
+            let local = match qname.find(':') {
+                Some(pos) => String::from(&qname[pos + 1 ..]),
+                None => qname,
+            };
+            return Ok(AttributeUse{ name: local, type_key: String::new(), required });
+        }
+        let name = required_attribute(elem, "name")?;
+        let mut type_key = String::from("xs:anySimpleType");
+        if let Some(qname) = elem.attribute_value("type") {
+            type_key = type_key_of_qname(elem, &qname);
+        } else {
+            for ch in xsd_children(elem).iter() {
+                if ch.local_name() == "simpleType" {
+                    type_key = self.parse_simple_type(ch, None)?;
+                }
+            }
+        }
+        return Ok(AttributeUse{ name, type_key, required });
+    }
+
+    // -----------------------------------------------------------------
+    // 参照している型がすべて定義されていることを確かめる。
+    //
+    fn check_references(&self) -> Result<(), Box<Error>> {
+        let mut refs: Vec<&String> = vec!{};
+        refs.extend(self.elements.values());
+        refs.extend(self.attributes.values());
+        for def in self.types.values() {
+            match def {
+                TypeDef::Simple{ variety: Variety::Atomic{ base, .. }, .. } => refs.push(base),
+                TypeDef::Simple{ variety: Variety::List{ item, .. }, .. } => refs.push(item),
+                TypeDef::Simple{ variety: Variety::Union{ members }, .. } => refs.extend(members),
+                TypeDef::Complex{ base, attributes, .. } => {
+                    refs.extend(base);
+                    for at in attributes.iter() {
+                        if at.type_key == "" {
+                            if ! self.attributes.contains_key(&at.name) {
+                                return Err(xml_syntax_error!(
+                                    "XML schema: undefined attribute: {}", at.name));
+                            }
+                        } else {
+                            refs.push(&at.type_key);
+                        }
+                    }
+                },
+            }
+        }
+        for key in refs.iter() {
+            if ! self.types.contains_key(*key) && builtin_base(key).is_none() {
+                return Err(xml_syntax_error!(
+                    "XML schema: undefined type: {}", key));
+            }
+        }
+        return Ok(());
+    }
+
+    // =================================================================
+    /// Validates the values of the elements and attributes under the
+    /// node (usually the document) declared in the schema, and
+    /// annotates them with their types (see the module document).
+    /// The nodes not declared are left as they are, i.e. xs:untyped
+    /// (or xs:untypedAtomic for attributes).
+    ///
+    /// The annotations are not updated by later changes of the
+    /// document; validate it again if needed.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use amxml::dom::*;
+    /// use amxml::xsd::*;
+    /// let schema = new_schema(r#"
+    ///     <xs:schema xmlns:xs="http://www.w3.org/2001/XMLSchema">
+    ///         <xs:element name="color">
+    ///             <xs:simpleType>
+    ///                 <xs:restriction base="xs:token">
+    ///                     <xs:enumeration value="red"/>
+    ///                     <xs:enumeration value="green"/>
+    ///                 </xs:restriction>
+    ///             </xs:simpleType>
+    ///         </xs:element>
+    ///     </xs:schema>"#).unwrap();
+    /// let doc = new_document(r#"<root><color> red </color><color>blue</color></root>"#).unwrap();
+    /// let errs = schema.validate(&doc).unwrap_err();
+    /// assert_eq!(errs.len(), 1);
+    /// assert!(errs[0].to_string().contains("blue"));
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// - Vector of errors, one for each invalid value or missing
+    ///   required attribute, in document order.
+    ///
+    pub fn validate(&self, node: &NodePtr) -> Result<(), Vec<Box<Error>>> {
+        let mut errors: Vec<Box<Error>> = vec!{};
+        let mut stack: Vec<NodePtr> = vec!{node.rc_clone()};
+        while let Some(node) = stack.pop() {
+            if node.node_type() == NodeType::Element {
+                if let Some(type_key) = self.elements.get(&node.local_name()) {
+                    self.validate_element(&node, type_key, &mut errors);
+                }
+            }
+            let mut children = node.children();
+            children.reverse();
+            stack.extend(children);
+        }
+        if errors.is_empty() {
+            return Ok(());
+        } else {
+            return Err(errors);
+        }
+    }
+
+    // -----------------------------------------------------------------
+    // 宣言された要素の属性と内容を検証し、型註釈をつける。
+    //
+    fn validate_element(&self, elem: &NodePtr, type_key: &str, errors: &mut Vec<Box<Error>>) {
+        elem.set_annotation(TYPE_ANNOTATION_KEY,
+                TypeAnnotation{ derivation: self.derivation(type_key) });
+
+        for at_use in self.attribute_uses(type_key).iter() {
+            let at = elem.attribute_nodes().iter()
+                .find(|at| at.local_name() == at_use.name).map(|at| at.rc_clone());
+            let at = match at {
+                Some(at) => at,
+                None => {
+                    if at_use.required {
+                        errors.push(dynamic_error!(
+                            "Element {} lacks required attribute {}",
+                            elem.name(), at_use.name));
+                    }
+                    continue;
+                },
+            };
+            let at_type = if at_use.type_key == "" {
+                match self.attributes.get(&at_use.name) {
+                    Some(key) => key.as_str(),
+                    None => continue,
+                }
+            } else {
+                at_use.type_key.as_str()
+            };
+            at.set_annotation(TYPE_ANNOTATION_KEY,
+                    TypeAnnotation{ derivation: self.derivation(at_type) });
+            if let Err(reason) = self.check_value(at_type, &at.value()) {
+                errors.push(dynamic_error!(
+                    "Invalid value of attribute {} of element {}: \"{}\" ({})",
+                    at.name(), elem.name(), at.value(), reason));
+            }
+        }
+
+        let content_type = match self.content_type(type_key) {
+            Some(content_type) => content_type,
+            None => return,
+        };
+        if elem.attribute_value("xsi:nil") == Some(String::from("true")) {
+            return;
+        }
+        let mut value = String::new();
+        for ch in elem.children().iter() {
+            match ch.node_type() {
+                NodeType::Text => value += &ch.value(),
+                NodeType::Element => {
+                    errors.push(dynamic_error!(
+                        "Element {} of simple type has child element {}",
+                        elem.name(), ch.name()));
+                    return;
+                },
+                _ => {},
+            }
+        }
+        if let Err(reason) = self.check_value(&content_type, &value) {
+            errors.push(dynamic_error!(
+                "Invalid value of element {}: \"{}\" ({})",
+                elem.name(), value, reason));
+        }
+    }
+
+    // -----------------------------------------------------------------
+    // 型の名前を、派生の順に xs:anyType まで並べる。
+    //
+    fn derivation(&self, type_key: &str) -> Vec<String> {
+        let mut names: Vec<String> = vec!{};
+        let mut key = String::from(type_key);
+        loop {
+            if let Some(base) = builtin_base(&key) {
+                names.push(key);
+                if base == "" {
+                    return names;
+                }
+                key = String::from(base);
+                continue;
+            }
+            match self.types.get(&key) {
+                Some(TypeDef::Simple{ name, variety }) => {
+                    names.extend(name.clone());
+                    match variety {
+                        Variety::Atomic{ base, .. } => key = base.clone(),
+                        _ => key = String::from("xs:anySimpleType"),
+                    }
+                },
+                Some(TypeDef::Complex{ name, base, .. }) => {
+                    names.extend(name.clone());
+                    match base {
+                        Some(base) => key = base.clone(),
+                        None => key = String::from("xs:anyType"),
+                    }
+                },
+                None => return names,
+            }
+        }
+    }
+
+    // -----------------------------------------------------------------
+    // 複合型の属性の宣言 (基底型のものを含む)。
+    //
+    fn attribute_uses(&self, type_key: &str) -> Vec<AttributeUse> {
+        let mut uses: Vec<AttributeUse> = vec!{};
+        let mut key = String::from(type_key);
+        while let Some(TypeDef::Complex{ base, attributes, .. }) = self.types.get(&key) {
+            for at in attributes.iter() {
+                if ! uses.iter().any(|u| u.name == at.name) {
+                    uses.push(at.clone());
+                }
+            }
+            match base {
+                Some(base) => key = base.clone(),
+                None => break,
+            }
+        }
+        return uses;
+    }
+
+    // -----------------------------------------------------------------
+    // 要素の内容の単純型。内容が単純型でなければ None。
+    //
+    fn content_type(&self, type_key: &str) -> Option<String> {
+        let mut key = String::from(type_key);
+        loop {
+            match self.types.get(&key) {
+                Some(TypeDef::Complex{ base, simple_content, .. }) => {
+                    if ! simple_content {
+                        return None;
+                    }
+                    key = base.clone()?;
+                },
+                Some(TypeDef::Simple{ .. }) => return Some(key),
+                None => {
+                    if key == "xs:anyType" {
+                        return None;
+                    }
+                    return Some(key);
+                },
+            }
+        }
+    }
+
+    // =================================================================
+    /// Checks if the value is valid for the simple type: either a
+    /// built-in type such as <code>xs:integer</code>, or a type
+    /// defined in the schema by its local name.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use amxml::xsd::*;
+    /// let schema = new_schema(r#"
+    ///     <xs:schema xmlns:xs="http://www.w3.org/2001/XMLSchema">
+    ///         <xs:simpleType name="Sizes">
+    ///             <xs:list itemType="xs:unsignedByte"/>
+    ///         </xs:simpleType>
+    ///     </xs:schema>"#).unwrap();
+    /// assert!(schema.validate_value("Sizes", "1 2 255").is_ok());
+    /// assert!(schema.validate_value("Sizes", "1 256").is_err());
+    /// assert!(schema.validate_value("xs:date", "2018-10-01").is_ok());
+    /// assert!(schema.validate_value("xs:date", "2018-10-32").is_err());
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// - When the value is not valid.
+    /// - When the type is not defined.
+    ///
+    pub fn validate_value(&self, type_name: &str, value: &str) -> Result<(), Box<Error>> {
+        if ! self.types.contains_key(type_name) && builtin_base(type_name).is_none() {
+            return Err(dynamic_error!("Undefined type: {}", type_name));
+        }
+        match self.check_value(type_name, value) {
+            Ok(()) => return Ok(()),
+            Err(reason) => return Err(dynamic_error!(
+                "Invalid value of {}: \"{}\" ({})", type_name, value, reason)),
+        }
+    }
+
+    // -----------------------------------------------------------------
+    // 値を単純型に照らして検査する。不正であれば理由を返す。
+    //
+    fn check_value(&self, type_key: &str, value: &str) -> Result<(), String> {
+        match self.types.get(type_key) {
+            Some(TypeDef::Simple{ variety: Variety::Atomic{ base, facets }, .. }) => {
+                self.check_value(base, value)?;
+                let primitive = self.primitive_type(type_key);
+                let value = normalize_white_space(&primitive, value);
+                return check_facets(facets, &primitive, &value, value.chars().count());
+            },
+            Some(TypeDef::Simple{ variety: Variety::List{ item, facets }, .. }) => {
+                for v in value.split_whitespace() {
+                    self.check_value(item, v)?;
+                }
+                let value = normalize_white_space("", value);
+                let n_items = value.split_whitespace().count();
+                return check_facets(facets, "xs:anySimpleType", &value, n_items);
+            },
+            Some(TypeDef::Simple{ variety: Variety::Union{ members }, .. }) => {
+                if members.iter().any(|m| self.check_value(m, value).is_ok()) {
+                    return Ok(());
+                }
+                return Err(String::from("none of the member types matches"));
+            },
+            Some(TypeDef::Complex{ .. }) => {
+                return Err(format!("{} is not a simple type", type_key));
+            },
+            None => {
+                let value = normalize_white_space(type_key, value);
+                return check_builtin(type_key, &value);
+            },
+        }
+    }
+
+    // -----------------------------------------------------------------
+    // 原子型の派生元の組込み型。
+    //
+    fn primitive_type(&self, type_key: &str) -> String {
+        let mut key = String::from(type_key);
+        while let Some(TypeDef::Simple{ variety: Variety::Atomic{ base, .. }, .. }) = self.types.get(&key) {
+            key = base.clone();
+        }
+        return key;
+    }
+}
+
+// ---------------------------------------------------------------------
+// 組込み型の基底型 (xs:anyType については "")。組込み型でなければ None。
+//
+fn builtin_base(type_name: &str) -> Option<&'static str> {
+    return BUILTIN_TYPES.iter()
+        .find(|(name, _)| *name == type_name)
+        .map(|(_, base)| *base);
+}
+
+// ---------------------------------------------------------------------
+// 組込み型が、もう一方の組込み型から派生しているか。
+//
+fn builtin_derives_from(type_name: &str, base_name: &str) -> bool {
+    let mut t = type_name;
+    loop {
+        if t == base_name {
+            return true;
+        }
+        match builtin_base(t) {
+            Some(base) if base != "" => t = base,
+            _ => return false,
+        }
+    }
+}
+
+// ---------------------------------------------------------------------
+// 組込み型の whiteSpace 制約面に従って、空白を正規化する。
+// xs:string は preserve、xs:normalizedString は replace、他は collapse。
+//
+fn normalize_white_space(type_name: &str, value: &str) -> String {
+    if type_name == "xs:string" {
+        return String::from(value);
+    }
+    if type_name == "xs:normalizedString" {
+        return value.replace(|c| c == '\t' || c == '\n' || c == '\r', " ");
+    }
+    return value.split_whitespace().collect::<Vec<&str>>().join(" ");
+}
+
+// ---------------------------------------------------------------------
+// 組込み型の字句表現 (空白は正規化済み) を検査する。
+//
+fn check_builtin(type_name: &str, value: &str) -> Result<(), String> {
+    let valid = match type_name {
+        "xs:language" => {
+            value.split('-').enumerate().all(|(i, part)|
+                part.len() >= 1 && part.len() <= 8 &&
+                part.chars().all(|c| c.is_ascii_alphabetic() ||
+                                     (i > 0 && c.is_ascii_digit())))
+        },
+        "xs:NMTOKEN" => {
+            value != "" && value.chars().all(|c|
+                c.is_alphanumeric() || c == '.' || c == '-' || c == '_' || c == ':')
+        },
+        "xs:Name" => is_xml_name(value),
+        "xs:NCName" | "xs:ID" | "xs:IDREF" | "xs:ENTITY" => is_ncname(value),
+        "xs:boolean" => ["true", "false", "1", "0"].contains(&value),
+        "xs:decimal" => parse_xs_decimal(value).is_some(),
+        "xs:float" | "xs:double" => parse_xs_double(value).is_some(),
+        "xs:dateTime" => parse_date_time(value).is_ok(),
+        "xs:date" => parse_xs_date(value).is_some(),
+        "xs:time" => parse_date_time(&format!("1972-01-01T{}", value)).is_ok(),
+        "xs:hexBinary" => value.len() % 2 == 0 &&
+                          value.chars().all(|c| c.is_ascii_hexdigit()),
+        "xs:base64Binary" => decode_base64(value).is_ok(),
+        _ if builtin_derives_from(type_name, "xs:integer") => {
+            return check_integer(type_name, value);
+        },
+        _ => true,
+    };
+    if valid {
+        return Ok(());
+    } else {
+        return Err(format!("not a valid {}", type_name));
+    }
+}
+
+// ---------------------------------------------------------------------
+// 整数型の字句表現と値の範囲を検査する。
+//
+fn check_integer(type_name: &str, value: &str) -> Result<(), String> {
+    let digits = value.trim_start_matches(|c| c == '+' || c == '-');
+    if value.len() - digits.len() > 1 || digits == "" ||
+       ! digits.chars().all(|c| c.is_ascii_digit()) {
+        return Err(format!("not a valid {}", type_name));
+    }
+    let negative = value.starts_with('-') && digits.chars().any(|c| c != '0');
+    let mut t = type_name;
+    while t != "xs:integer" {
+        let sign_ok = match t {
+            "xs:nonPositiveInteger" => negative || digits.chars().all(|c| c == '0'),
+            "xs:negativeInteger" => negative,
+            "xs:nonNegativeInteger" => ! negative,
+            "xs:positiveInteger" => ! negative && digits.chars().any(|c| c != '0'),
+            _ => true,
+        };
+        if ! sign_ok {
+            return Err(format!("out of range of {}", t));
+        }
+        if let Some((_, min, max)) = INTEGER_RANGES.iter().find(|(name, _, _)| *name == t) {
+            match value.parse::<i128>() {
+                Ok(n) if *min <= n && n <= *max => {},
+                _ => return Err(format!("out of range of {}", t)),
+            }
+        }
+        t = builtin_base(t).unwrap_or("xs:integer");
+    }
+    return Ok(());
+}
+
+// ---------------------------------------------------------------------
+// xs:date の字句表現を、その日の0時の日時として解析する。
+//
+fn parse_xs_date(value: &str) -> Option<DateTime> {
+    let year_start = if value.starts_with('-') { 1 } else { 0 };
+    let month_pos = year_start + value[year_start ..].find('-')?;
+    let date_end = month_pos + 6;
+    if value.len() < date_end || ! value.is_char_boundary(date_end) {
+        return None;
+    }
+    let date_time = format!("{}T00:00:00{}", &value[.. date_end], &value[date_end ..]);
+    return parse_date_time(&date_time).ok();
+}
+
+// ---------------------------------------------------------------------
+// 制約面を検査する。length は、リスト型については項目数。
+//
+fn check_facets(facets: &Facets, primitive: &str, value: &str, length: usize) -> Result<(), String> {
+    if ! facets.enumeration.is_empty() &&
+       ! facets.enumeration.iter().any(|e| compare_values(primitive, value, e) == Some(Ordering::Equal)) {
+        return Err(format!("not one of {}", facets.enumeration.join(", ")));
+    }
+    if ! facets.patterns.is_empty() &&
+       ! facets.patterns.iter().any(|p| match_pattern(p, value)) {
+        let sources: Vec<&str> = facets.patterns.iter().map(|p| p.source.as_str()).collect();
+        return Err(format!("not matching pattern {}", sources.join(" | ")));
+    }
+
+    let bounds = [
+        ( &facets.min_inclusive, "minInclusive", [Ordering::Greater, Ordering::Equal] ),
+        ( &facets.max_inclusive, "maxInclusive", [Ordering::Less, Ordering::Equal] ),
+        ( &facets.min_exclusive, "minExclusive", [Ordering::Greater, Ordering::Greater] ),
+        ( &facets.max_exclusive, "maxExclusive", [Ordering::Less, Ordering::Less] ),
+    ];
+    for (bound, facet_name, accepted) in bounds.iter() {
+        if let Some(bound) = bound {
+            if let Some(ord) = compare_values(primitive, value, bound) {
+                if ! accepted.contains(&ord) {
+                    return Err(format!("violates {} {}", facet_name, bound));
+                }
+            }
+        }
+    }
+
+    let lengths = [
+        ( facets.length, "length", length != facets.length.unwrap_or(0) ),
+        ( facets.min_length, "minLength", length < facets.min_length.unwrap_or(0) ),
+        ( facets.max_length, "maxLength", length > facets.max_length.unwrap_or(0) ),
+    ];
+    for (facet, facet_name, violated) in lengths.iter() {
+        if let Some(n) = facet {
+            if *violated {
+                return Err(format!("violates {} {}", facet_name, n));
+            }
+        }
+    }
+
+    if facets.total_digits.is_some() || facets.fraction_digits.is_some() {
+        let digits = value.trim_start_matches(|c| c == '+' || c == '-');
+        let (int_part, frac_part) = match digits.find('.') {
+            Some(pos) => (&digits[.. pos], digits[pos + 1 ..].trim_end_matches('0')),
+            None => (digits, ""),
+        };
+        let int_part = int_part.trim_start_matches('0');
+        if let Some(n) = facets.total_digits {
+            if int_part.len() + frac_part.len() > n {
+                return Err(format!("violates totalDigits {}", n));
+            }
+        }
+        if let Some(n) = facets.fraction_digits {
+            if frac_part.len() > n {
+                return Err(format!("violates fractionDigits {}", n));
+            }
+        }
+    }
+    return Ok(());
+}
+
+// ---------------------------------------------------------------------
+// 値を比較する。数値型は数値として、日付型は日時として比較し、
+// それ以外は文字列として等しいかどうかだけを判定する。
+//
+fn compare_values(primitive: &str, a: &str, b: &str) -> Option<Ordering> {
+    if ["xs:decimal", "xs:float", "xs:double"].iter().any(|t| builtin_derives_from(primitive, t)) {
+        let a = parse_xs_double(a)?;
+        let b = parse_xs_double(b.trim())?;
+        return a.partial_cmp(&b);
+    }
+    match primitive {
+        "xs:dateTime" => {
+            return Some(parse_date_time(a).ok()?.compare(&parse_date_time(b).ok()?));
+        },
+        "xs:date" => {
+            return Some(parse_xs_date(a)?.compare(&parse_xs_date(b.trim())?));
+        },
+        _ => {
+            if a == b {
+                return Some(Ordering::Equal);
+            } else {
+                return None;
+            }
+        },
+    }
+}
+
+// ---------------------------------------------------------------------
+// pattern 制約面をコンパイルする。
+// XML Schemaの正規表現は全体に合致するので、^(?:...)$ で囲む。
+// 名前文字のクラス \i、\c は、おおよその文字クラスに置き換える。
+//
+#[cfg(feature = "regex")]
+fn compile_pattern(source: &str) -> Result<Pattern, Box<Error>> {
+    let mut translated = String::new();
+    let mut chars = source.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            translated.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('i') => translated += r"[\p{L}_:]",
+            Some('I') => translated += r"[^\p{L}_:]",
+            Some('c') => translated += r"[\p{L}\p{Nd}._:\-]",
+            Some('C') => translated += r"[^\p{L}\p{Nd}._:\-]",
+            Some(c) => { translated.push('\\'); translated.push(c); },
+            None => translated.push('\\'),
+        }
+    }
+    match Regex::new(&format!("^(?:{})$", translated)) {
+        Ok(regex) => return Ok(Pattern{ source: String::from(source), regex }),
+        Err(e) => return Err(xml_syntax_error!(
+            "XML schema: invalid pattern {}: {}", source, e)),
+    }
+}
+
+#[cfg(not(feature = "regex"))]
+fn compile_pattern(source: &str) -> Result<Pattern, Box<Error>> {
+    return Ok(Pattern{ source: String::from(source) });
+}
+
+#[cfg(feature = "regex")]
+fn match_pattern(pattern: &Pattern, value: &str) -> bool {
+    return pattern.regex.is_match(value);
+}
+
+#[cfg(not(feature = "regex"))]
+fn match_pattern(_pattern: &Pattern, _value: &str) -> bool {
+    return true;
+}
+
+// ---------------------------------------------------------------------
+// ノードの型註釈が TypeName に合致するか (XPathの ElementTest、
+// AttributeTest に使う)。型註釈がなければ None。
+// スキーマで定義した型は、局所名で照合する。
+//
+pub(crate) fn type_annotation_matches(node: &NodePtr, type_name: &str) -> Option<bool> {
+    let annotation = node.get_annotation::<TypeAnnotation>(TYPE_ANNOTATION_KEY)?;
+    let local_name = match type_name.find(':') {
+        Some(pos) if builtin_base(type_name).is_none() => &type_name[pos + 1 ..],
+        _ => type_name,
+    };
+    return Some(annotation.derivation.iter().any(|t|
+        t == type_name || (! t.starts_with("xs:") && t == local_name)));
+}
+
+impl NodePtr {
+    // =================================================================
+    /// Returns the name of the type annotated by Schema#validate(),
+    /// that is, the name of the type of the declaration
+    /// (its base type for anonymous types).
+    /// None if the node is not validated.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use amxml::dom::*;
+    /// use amxml::xsd::*;
+    /// let schema = new_schema(r#"
+    ///     <xs:schema xmlns:xs="http://www.w3.org/2001/XMLSchema">
+    ///         <xs:element name="a" type="xs:int"/>
+    ///     </xs:schema>"#).unwrap();
+    /// let doc = new_document(r#"<root><a>1</a></root>"#).unwrap();
+    /// schema.validate(&doc).unwrap();
+    /// assert_eq!(doc.get_first_node("//a").unwrap().schema_type_name().unwrap(), "xs:int");
+    /// assert_eq!(doc.root_element().schema_type_name(), None);
+    /// ```
+    ///
+    pub fn schema_type_name(&self) -> Option<String> {
+        let annotation = self.get_annotation::<TypeAnnotation>(TYPE_ANNOTATION_KEY)?;
+        return annotation.derivation.first().cloned();
+    }
+}
+
+// =====================================================================
+//
+#[cfg(test)]
+mod test {
+    use super::*;
+    use xpath::EvalOptions;
+
+    const SCHEMA: &str = r#"
+        <xs:schema xmlns:xs="http://www.w3.org/2001/XMLSchema"
+                   xmlns:t="http://example.com/t" targetNamespace="http://example.com/t">
+            <xs:simpleType name="Percent">
+                <xs:restriction base="xs:decimal">
+                    <xs:minInclusive value="0"/>
+                    <xs:maxExclusive value="100"/>
+                    <xs:fractionDigits value="1"/>
+                </xs:restriction>
+            </xs:simpleType>
+            <xs:simpleType name="Code">
+                <xs:restriction base="xs:string">
+                    <xs:length value="3"/>
+                </xs:restriction>
+            </xs:simpleType>
+            <xs:simpleType name="CodeOrNone">
+                <xs:union memberTypes="t:Code">
+                    <xs:simpleType>
+                        <xs:restriction base="xs:token">
+                            <xs:enumeration value="none"/>
+                        </xs:restriction>
+                    </xs:simpleType>
+                </xs:union>
+            </xs:simpleType>
+            <xs:complexType name="Item">
+                <xs:attribute name="code" type="t:CodeOrNone" use="required"/>
+                <xs:attribute ref="t:since"/>
+            </xs:complexType>
+            <xs:attribute name="since" type="xs:date"/>
+            <xs:element name="items">
+                <xs:complexType>
+                    <xs:sequence>
+                        <xs:element name="item" maxOccurs="unbounded">
+                            <xs:complexType>
+                                <xs:complexContent>
+                                    <xs:extension base="t:Item">
+                                        <xs:attribute name="n" type="xs:unsignedByte"/>
+                                    </xs:extension>
+                                </xs:complexContent>
+                            </xs:complexType>
+                        </xs:element>
+                        <xs:element name="rate">
+                            <xs:complexType>
+                                <xs:simpleContent>
+                                    <xs:extension base="t:Percent">
+                                        <xs:attribute name="unit" type="xs:string"/>
+                                    </xs:extension>
+                                </xs:simpleContent>
+                            </xs:complexType>
+                        </xs:element>
+                    </xs:sequence>
+                </xs:complexType>
+            </xs:element>
+        </xs:schema>"#;
+
+    // -----------------------------------------------------------------
+    // 値の検証。
+    //
+    #[test]
+    fn test_validate() {
+        let schema = new_schema(SCHEMA).unwrap();
+
+        let xml = r#"<items><item code="abc" n="1" since="2018-10-01"/><item code=" none "/><rate unit="%">12.5</rate></items>"#;
+        let doc = new_document(xml).unwrap();
+        assert!(schema.validate(&doc).is_ok());
+
+        let xml = r#"<items><item code="abcd" n="256"/><item since="2018-13-01"/><rate>100</rate><rate>1.25</rate><rate><b/></rate></items>"#;
+        let doc = new_document(xml).unwrap();
+        let errs: Vec<String> = schema.validate(&doc).unwrap_err()
+            .iter().map(|e| e.to_string()).collect();
+        assert_eq!(errs.len(), 7);
+        assert!(errs[0].contains("xs:unsignedByte"));
+        assert!(errs[1].contains("code") && errs[1].contains("abcd"));
+        assert!(errs[2].contains("lacks required attribute code"));
+        assert!(errs[3].contains("xs:date"));
+        assert!(errs[4].contains("maxExclusive"));
+        assert!(errs[5].contains("fractionDigits"));
+        assert!(errs[6].contains("child element b"));
+
+        assert!(schema.validate_value("xs:positiveInteger", "0").is_err());
+        assert!(schema.validate_value("xs:nonPositiveInteger", "-0").is_ok());
+        assert!(schema.validate_value("xs:long", "9223372036854775808").is_err());
+        assert!(schema.validate_value("xs:integer", "123456789012345678901234567890").is_ok());
+        assert!(schema.validate_value("xs:boolean", " true ").is_ok());
+        assert!(schema.validate_value("xs:time", "09:00:00Z").is_ok());
+        assert!(schema.validate_value("xs:language", "en-US").is_ok());
+        assert!(schema.validate_value("xs:language", "1en").is_err());
+        assert!(schema.validate_value("xs:hexBinary", "0aF").is_err());
+        assert!(schema.validate_value("Code", " ab").is_ok());
+        assert!(schema.validate_value("Unknown", "x").is_err());
+    }
+
+    // -----------------------------------------------------------------
+    // 型註釈と、XPathの ElementTest、AttributeTest。
+    //
+    #[test]
+    fn test_type_annotation() {
+        let schema = new_schema(SCHEMA).unwrap();
+        let xml = r#"<items><item code="abc" n="1" since="2018-10-01" x="y"/><rate>1</rate></items>"#;
+        let doc = new_document(xml).unwrap();
+        assert_eq!(doc.eval_xpath("count(//element(*, xs:untyped))").unwrap().to_string(), "3");
+        schema.validate(&doc).unwrap();
+
+        let cases = [
+            ( "count(//element(*, xs:untyped))", "0" ),
+            ( "count(//element(*, xs:anyType))", "3" ),
+            ( "name(//element(*, Item))", r#""item""# ),
+            ( "name(//element(*, xs:Item))", r#""item""# ),
+            ( "name(//element(rate, Percent))", r#""rate""# ),
+            ( "count(//element(*, xs:decimal))", "1" ),
+            ( "count(//element(*, xs:integer))", "0" ),
+            ( "count(//item/@attribute(*, CodeOrNone))", "1" ),
+            ( "count(//item/@attribute(*, xs:anySimpleType))", "4" ),
+            ( "count(//item/@attribute(*, xs:integer))", "1" ),
+            ( "count(//item/@attribute(*, xs:untypedAtomic))", "1" ),
+            ( "//item/@since instance of attribute(since, xs:date)", "true" ),
+            ( "//item/@n instance of attribute(n, xs:string)", "false" ),
+        ];
+        for (xpath, expected) in cases.iter() {
+            assert_eq!(doc.eval_xpath(xpath).unwrap().to_string(), *expected, "{}", xpath);
+        }
+        assert_eq!(doc.get_first_node("//rate").unwrap().schema_type_name(), Some(String::from("Percent")));
+        assert_eq!(doc.get_first_node("//@x").unwrap().schema_type_name(), None);
+    }
+
+    // -----------------------------------------------------------------
+    // 型註釈のある文書は、EvalOptions#parallel() でも逐次評価と同じ結果。
+    //
+    #[test]
+    fn test_type_annotation_parallel() {
+        let schema = new_schema(r#"
+            <xs:schema xmlns:xs="http://www.w3.org/2001/XMLSchema">
+                <xs:element name="order">
+                    <xs:complexType>
+                        <xs:sequence>
+                            <xs:element name="price" type="xs:decimal"/>
+                            <xs:element name="q" type="xs:integer"/>
+                            <xs:element name="note" type="xs:string"/>
+                        </xs:sequence>
+                    </xs:complexType>
+                </xs:element>
+            </xs:schema>"#).unwrap();
+        let doc = new_document("<order><price>1.5</price><q>2</q><note>x</note></order>").unwrap();
+        schema.validate(&doc).unwrap();
+
+        let xpath = "//element(*, xs:decimal) | //element(*, xs:integer)";
+        let expected = "(<price>, <q>)";
+        assert_eq!(doc.eval_xpath(xpath).unwrap().to_string(), expected);
+        let options = EvalOptions::new().parallel(true);
+        assert_eq!(doc.eval_xpath_with_options(xpath, &options).unwrap().to_string(), expected);
+    }
+
+    // -----------------------------------------------------------------
+    // pattern 制約面 (feature "regex")。
+    //
+    #[cfg(feature = "regex")]
+    #[test]
+    fn test_pattern() {
+        let schema = new_schema(r#"
+            <xs:schema xmlns:xs="http://www.w3.org/2001/XMLSchema">
+                <xs:simpleType name="Zip">
+                    <xs:restriction base="xs:token">
+                        <xs:pattern value="\d{3}-\d{4}"/>
+                        <xs:pattern value="\d{7}"/>
+                    </xs:restriction>
+                </xs:simpleType>
+                <xs:simpleType name="Ident">
+                    <xs:restriction base="xs:string">
+                        <xs:pattern value="\i\c*"/>
+                    </xs:restriction>
+                </xs:simpleType>
+            </xs:schema>"#).unwrap();
+        assert!(schema.validate_value("Zip", "100-0001").is_ok());
+        assert!(schema.validate_value("Zip", " 1000001 ").is_ok());
+        assert!(schema.validate_value("Zip", "100-00011").is_err());
+        assert!(schema.validate_value("Ident", "a-1").is_ok());
+        assert!(schema.validate_value("Ident", "1a").is_err());
+    }
+
+    // -----------------------------------------------------------------
+    // スキーマの誤り。
+    //
+    #[test]
+    fn test_schema_errors() {
+        assert!(new_schema(r#"<schema/>"#).is_err());
+        assert!(new_schema(r#"
+            <xs:schema xmlns:xs="http://www.w3.org/2001/XMLSchema">
+                <xs:element name="a" type="Undefined"/>
+            </xs:schema>"#).is_err());
+        assert!(new_schema(r#"
+            <xs:schema xmlns:xs="http://www.w3.org/2001/XMLSchema">
+                <xs:element name="a" type="xs:unknown"/>
+            </xs:schema>"#).is_err());
+        assert!(new_schema(r#"
+            <xs:schema xmlns:xs="http://www.w3.org/2001/XMLSchema">
+                <xs:simpleType name="A">
+                    <xs:restriction base="xs:string">
+                        <xs:maxLength value="-1"/>
+                    </xs:restriction>
+                </xs:simpleType>
+            </xs:schema>"#).is_err());
+    }
+}